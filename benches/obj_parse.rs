@@ -0,0 +1,61 @@
+//! Compares the OBJ fast path with tobj on a generated grid of a million
+//! vertices. Run with `cargo bench --bench obj_parse`.
+
+use std::{fmt::Write, io::Cursor};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// A 1000x1000 vertex grid with UVs and normals, as quads.
+fn grid_obj(side: u32) -> String {
+    let mut obj = String::new();
+    for y in 0..side {
+        for x in 0..side {
+            let (u, v) = (x as f32 / side as f32, y as f32 / side as f32);
+            writeln!(
+                obj,
+                "v {:.6} {:.6} {:.6}",
+                u * 10.0,
+                (u * v).sin(),
+                v * 10.0
+            )
+            .unwrap();
+            writeln!(obj, "vt {:.6} {:.6}", u, v).unwrap();
+        }
+    }
+    obj.push_str("vn 0.0 1.0 0.0\n");
+    for y in 0..side - 1 {
+        for x in 0..side - 1 {
+            let i = y * side + x + 1;
+            let (a, b, c, d) = (i, i + 1, i + side + 1, i + side);
+            writeln!(obj, "f {a}/{a}/1 {b}/{b}/1 {c}/{c}/1 {d}/{d}/1").unwrap();
+        }
+    }
+    obj
+}
+
+fn parse(c: &mut Criterion) {
+    let obj = grid_obj(1000);
+    let mut group = c.benchmark_group("obj_parse");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(obj.len() as u64));
+    group.bench_function("fast path", |b| {
+        b.iter(|| test2::obj::parse(obj.as_bytes()).unwrap().unwrap())
+    });
+    group.bench_function("tobj", |b| {
+        b.iter(|| {
+            tobj::load_obj_buf(
+                &mut Cursor::new(obj.as_bytes()),
+                &tobj::LoadOptions {
+                    triangulate: true,
+                    ..Default::default()
+                },
+                |_| Err(tobj::LoadError::OpenFileFailed),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+//! Feeds arbitrary bytes to the OBJ fast path, which has to return an error
+//! or fall back rather than panic. Run with `cargo fuzz run obj_parse`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = test2::obj::parse(data);
+});
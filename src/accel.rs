@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+
+use crate::bounds::Aabb;
+
+/// Identifies an object in a [`SceneBvh`], e.g. the index of a scene node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    /// The ray through the pixel at `cursor` in a `size` sized target,
+    /// starting at the near plane. `view_proj` maps to wgpu clip space.
+    /// `None` if `view_proj` can't be inverted.
+    pub fn from_screen(
+        view_proj: &Matrix4<f32>,
+        cursor: (f32, f32),
+        size: (u32, u32),
+    ) -> Option<Self> {
+        let inverse = view_proj.invert()?;
+        let x = 2.0 * cursor.0 / size.0.max(1) as f32 - 1.0;
+        let y = 1.0 - 2.0 * cursor.1 / size.1.max(1) as f32;
+        let near = inverse.transform_point(Point3::new(x, y, 0.0));
+        let far = inverse.transform_point(Point3::new(x, y, 1.0));
+        Some(Self::new(near, (far - near).normalize()))
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    /// Distance along the ray, in multiples of `direction`, to where it
+    /// hits the triangle from either side.
+    pub fn intersect_triangle(
+        &self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        c: Point3<f32>,
+    ) -> Option<f32> {
+        self.intersect_triangle_barycentric(a, b, c)
+            .map(|(t, _, _)| t)
+    }
+
+    /// Like [`Self::intersect_triangle`], also returning the weights of `b`
+    /// and `c` at the hit, for interpolating vertex attributes.
+    pub fn intersect_triangle_barycentric(
+        &self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        c: Point3<f32>,
+    ) -> Option<(f32, f32, f32)> {
+        // Möller-Trumbore.
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let s = self.origin - a;
+        let u = s.dot(p) / det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(ab);
+        let v = self.direction.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = ac.dot(q) / det;
+        (t >= 0.0).then_some((t, u, v))
+    }
+
+    /// Distance along the ray, in multiples of `direction`, to where it
+    /// enters `aabb`. This is 0 if the ray starts inside the box.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // min and max skip the NaNs from rays parallel to a slab edge.
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+/// Leaves are split until they hold at most this many objects.
+const MAX_LEAF_OBJECTS: usize = 4;
+/// Number of bins candidate splits are evaluated at along an axis.
+const BINS: usize = 16;
+/// Relative cost of visiting a node compared to testing an object.
+const TRAVERSAL_COST: f32 = 1.0;
+/// Refits rebuild the tree once its cost grows this much past a fresh build.
+const REBUILD_RATIO: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { left: usize, right: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// A bounding volume hierarchy over the world bounds of scene objects, for
+/// picking and other spatial queries.
+///
+/// The tree is built with a binned surface area heuristic. Moving objects
+/// with [`Self::update`] only refits the existing tree, which gets looser as
+/// objects move apart, so [`Self::refit`] rebuilds it once the estimated
+/// query cost has degraded by [`REBUILD_RATIO`]. Building is deterministic:
+/// the same objects in the same order give the same tree.
+#[derive(Default)]
+pub struct SceneBvh {
+    nodes: Vec<BvhNode>,
+    objects: Vec<(NodeId, Aabb)>,
+    slots: HashMap<NodeId, usize>,
+    built_cost: f32,
+    dirty: bool,
+}
+
+impl SceneBvh {
+    pub fn build<I: IntoIterator<Item = (NodeId, Aabb)>>(objects: I) -> Self {
+        let mut bvh = Self {
+            objects: objects.into_iter().collect(),
+            ..Default::default()
+        };
+        bvh.rebuild();
+        bvh
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Bounds of everything in the tree.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    /// Adds an object, or replaces the bounds of one already present, and
+    /// rebuilds the tree.
+    pub fn insert(&mut self, id: NodeId, aabb: Aabb) {
+        match self.slots.get(&id) {
+            Some(&slot) => self.objects[slot].1 = aabb,
+            None => self.objects.push((id, aabb)),
+        }
+        self.rebuild();
+    }
+
+    /// Removes an object and rebuilds the tree. Returns false if it wasn't
+    /// present.
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let Some(slot) = self.slots.get(&id).copied() else {
+            return false;
+        };
+        self.objects.remove(slot);
+        self.rebuild();
+        true
+    }
+
+    /// Sets the bounds of a moved object. Queries see the change after the
+    /// next [`Self::refit`]. Returns false if the object isn't in the tree.
+    pub fn update(&mut self, id: NodeId, aabb: Aabb) -> bool {
+        match self.slots.get(&id) {
+            Some(&slot) => {
+                self.objects[slot].1 = aabb;
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Refits the tree to the bounds set with [`Self::update`], rebuilding it
+    /// if that has made it too loose. Returns true if it was rebuilt.
+    pub fn refit(&mut self) -> bool {
+        if !self.dirty {
+            return false;
+        }
+        // Children always come after their parents.
+        for i in (0..self.nodes.len()).rev() {
+            self.nodes[i].bounds = match self.nodes[i].kind {
+                NodeKind::Leaf { first, count } => self.objects_bounds(first, count),
+                NodeKind::Interior { left, right } => {
+                    self.nodes[left].bounds.union(&self.nodes[right].bounds)
+                }
+            };
+        }
+        self.dirty = false;
+
+        let cost = self.cost();
+        if cost > self.built_cost * REBUILD_RATIO {
+            log::debug!(
+                "Rebuilding BVH, cost went from {} to {}",
+                self.built_cost,
+                cost
+            );
+            self.rebuild();
+            return true;
+        }
+        false
+    }
+
+    /// Rebuilds the tree from scratch.
+    pub fn rebuild(&mut self) {
+        self.nodes.clear();
+        if !self.objects.is_empty() {
+            self.build_node(0, self.objects.len());
+        }
+        self.slots = self
+            .objects
+            .iter()
+            .enumerate()
+            .map(|(slot, (id, _))| (*id, slot))
+            .collect();
+        self.built_cost = self.cost();
+        self.dirty = false;
+    }
+
+    /// The surface area heuristic estimate of the cost of a query, relative
+    /// to testing the root. Lower is better.
+    pub fn cost(&self) -> f32 {
+        let Some(root) = self.nodes.first() else {
+            return 0.0;
+        };
+        let root_area = root.bounds.surface_area().max(f32::EPSILON);
+        self.nodes
+            .iter()
+            .map(|node| {
+                let area = node.bounds.surface_area() / root_area;
+                match node.kind {
+                    NodeKind::Leaf { count, .. } => area * count as f32,
+                    NodeKind::Interior { .. } => area * TRAVERSAL_COST,
+                }
+            })
+            .sum()
+    }
+
+    /// Objects whose bounds the ray hits, nearest entry point first.
+    pub fn ray_query(&self, ray: &Ray) -> impl Iterator<Item = NodeId> {
+        let mut hits = Vec::new();
+        self.traverse(
+            |bounds| ray.intersect_aabb(bounds).is_some(),
+            |id, bounds| {
+                if let Some(t) = ray.intersect_aabb(bounds) {
+                    hits.push((t, id));
+                }
+            },
+        );
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        hits.into_iter().map(|(_, id)| id)
+    }
+
+    /// Objects whose bounds overlap `region`, e.g. for box selection.
+    pub fn aabb_query(&self, region: &Aabb) -> impl Iterator<Item = NodeId> {
+        let mut hits = Vec::new();
+        self.traverse(
+            |bounds| bounds.intersects(region),
+            |id, bounds| {
+                if bounds.intersects(region) {
+                    hits.push(id);
+                }
+            },
+        );
+        hits.into_iter()
+    }
+
+    fn traverse<F, G>(&self, mut visit: F, mut object: G)
+    where
+        F: FnMut(&Aabb) -> bool,
+        G: FnMut(NodeId, &Aabb),
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if !visit(&node.bounds) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for (id, bounds) in &self.objects[first..first + count] {
+                        object(*id, bounds);
+                    }
+                }
+                NodeKind::Interior { left, right } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+    }
+
+    fn objects_bounds(&self, first: usize, count: usize) -> Aabb {
+        let objects = &self.objects[first..first + count];
+        objects
+            .iter()
+            .skip(1)
+            .fold(objects[0].1, |bounds, (_, aabb)| bounds.union(aabb))
+    }
+
+    /// Builds the node for `objects[first..first + count]`, reordering them
+    /// so each leaf's objects are contiguous, and returns its index.
+    fn build_node(&mut self, first: usize, count: usize) -> usize {
+        let bounds = self.objects_bounds(first, count);
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds,
+            kind: NodeKind::Leaf { first, count },
+        });
+        if count <= MAX_LEAF_OBJECTS {
+            return index;
+        }
+
+        let mid = first + self.partition(first, count);
+        let left = self.build_node(first, mid - first);
+        let right = self.build_node(mid, first + count - mid);
+        self.nodes[index].kind = NodeKind::Interior { left, right };
+        index
+    }
+
+    /// Splits the objects in two at the cheapest binned split along the axis
+    /// their centers are most spread out on, returning the size of the first
+    /// half. Falls back to a median split when binning can't separate them.
+    fn partition(&mut self, first: usize, count: usize) -> usize {
+        let objects = &mut self.objects[first..first + count];
+        let centers = Aabb::from_points(objects.iter().map(|(_, aabb)| aabb.center())).unwrap();
+        let extent = centers.size();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            return count / 2;
+        }
+
+        let bin_of = |aabb: &Aabb| {
+            let t = (aabb.center()[axis] - centers.min[axis]) / extent[axis];
+            ((t * BINS as f32) as usize).min(BINS - 1)
+        };
+        let mut bins: [Option<Aabb>; BINS] = [None; BINS];
+        let mut counts = [0usize; BINS];
+        for (_, aabb) in objects.iter() {
+            let bin = bin_of(aabb);
+            counts[bin] += 1;
+            bins[bin] = Some(bins[bin].map_or(*aabb, |b| b.union(aabb)));
+        }
+
+        // Cost of splitting after each bin, from sweeps in both directions.
+        let sweep = |order: &mut dyn Iterator<Item = usize>| {
+            let mut costs = [0.0f32; BINS];
+            let mut bounds: Option<Aabb> = None;
+            let mut total = 0;
+            for bin in order {
+                if let Some(b) = bins[bin] {
+                    bounds = Some(bounds.map_or(b, |a| a.union(&b)));
+                }
+                total += counts[bin];
+                costs[bin] = bounds.map_or(0.0, |b| b.surface_area()) * total as f32;
+            }
+            costs
+        };
+        let left_costs = sweep(&mut (0..BINS));
+        let right_costs = sweep(&mut (0..BINS).rev());
+        let split = (0..BINS - 1)
+            .min_by(|&a, &b| {
+                let cost_a = left_costs[a] + right_costs[a + 1];
+                let cost_b = left_costs[b] + right_costs[b + 1];
+                cost_a.total_cmp(&cost_b)
+            })
+            .unwrap();
+
+        // A stable partition keeps construction deterministic.
+        let (left, right): (Vec<_>, Vec<_>) = objects
+            .iter()
+            .copied()
+            .partition(|(_, aabb)| bin_of(aabb) <= split);
+        let mid = left.len();
+        if mid == 0 || mid == count {
+            return count / 2;
+        }
+        for (slot, object) in objects.iter_mut().zip(left.into_iter().chain(right)) {
+            *slot = object;
+        }
+        mid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small deterministic generator, so failures reproduce.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+
+        fn point(&mut self, extent: f32) -> Point3<f32> {
+            Point3::new(
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+            )
+        }
+
+        fn aabb(&mut self, extent: f32) -> Aabb {
+            let center = self.point(extent);
+            let half = Vector3::new(
+                self.range(0.05, 2.0),
+                self.range(0.05, 2.0),
+                self.range(0.05, 2.0),
+            );
+            Aabb::new(center - half, center + half)
+        }
+
+        fn ray(&mut self) -> Ray {
+            let direction = Vector3::new(
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+            );
+            Ray::new(self.point(40.0), direction.normalize())
+        }
+    }
+
+    fn objects(rng: &mut Lcg, count: u32) -> Vec<(NodeId, Aabb)> {
+        (0..count).map(|i| (NodeId(i), rng.aabb(30.0))).collect()
+    }
+
+    fn brute_force_ray(objects: &[(NodeId, Aabb)], ray: &Ray) -> Vec<NodeId> {
+        let mut hits: Vec<_> = objects
+            .iter()
+            .filter_map(|(id, aabb)| ray.intersect_aabb(aabb).map(|t| (t, *id)))
+            .collect();
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        hits.into_iter().map(|(_, id)| id).collect()
+    }
+
+    fn brute_force_aabb(objects: &[(NodeId, Aabb)], region: &Aabb) -> Vec<NodeId> {
+        let mut hits: Vec<_> = objects
+            .iter()
+            .filter(|(_, aabb)| aabb.intersects(region))
+            .map(|(id, _)| *id)
+            .collect();
+        hits.sort();
+        hits
+    }
+
+    fn check_queries(bvh: &SceneBvh, objects: &[(NodeId, Aabb)], rng: &mut Lcg) {
+        for i in 0..200 {
+            let mut ray = rng.ray();
+            // Aim every other ray at an object, so most of them hit
+            // something even when the objects are spread out.
+            if i % 2 == 0 && !objects.is_empty() {
+                let target = objects[i % objects.len()].1.center();
+                ray.direction = (target - ray.origin).normalize();
+            }
+            let hits: Vec<_> = bvh.ray_query(&ray).collect();
+            assert_eq!(hits, brute_force_ray(objects, &ray), "{:?}", ray);
+        }
+        for _ in 0..100 {
+            let region = rng.aabb(30.0).expanded(Vector3::new(3.0, 3.0, 3.0));
+            let mut hits: Vec<_> = bvh.aabb_query(&region).collect();
+            hits.sort();
+            assert_eq!(hits, brute_force_aabb(objects, &region));
+        }
+    }
+
+    #[test]
+    fn ray_hits_box_from_outside_and_inside() {
+        let aabb = Aabb::new(Point3::new(1.0, -1.0, -1.0), Point3::new(3.0, 1.0, 1.0));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_aabb(&aabb), Some(1.0));
+        let inside = Ray::new(Point3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(inside.intersect_aabb(&aabb), Some(0.0));
+        let behind = Ray::new(Point3::new(4.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(behind.intersect_aabb(&aabb), None);
+        // Parallel to the x slabs but outside them.
+        let parallel = Ray::new(Point3::new(0.0, 2.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(parallel.intersect_aabb(&aabb), None);
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Lcg(7);
+        for count in [0, 1, 4, 5, 17, 300] {
+            let objects = objects(&mut rng, count);
+            let bvh = SceneBvh::build(objects.iter().copied());
+            assert_eq!(bvh.len(), objects.len());
+            check_queries(&bvh, &objects, &mut rng);
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force_after_edits() {
+        let mut rng = Lcg(11);
+        let mut objects = objects(&mut rng, 200);
+        let mut bvh = SceneBvh::build(objects.iter().copied());
+
+        // Small moves are refitted without a rebuild.
+        for (id, aabb) in objects.iter_mut().step_by(7) {
+            let offset = Vector3::new(rng.range(-0.5, 0.5), 0.0, rng.range(-0.5, 0.5));
+            *aabb = Aabb::new(aabb.min + offset, aabb.max + offset);
+            assert!(bvh.update(*id, *aabb));
+        }
+        assert!(!bvh.refit());
+        check_queries(&bvh, &objects, &mut rng);
+
+        // Scattering everything far apart makes the tree too loose.
+        for (id, aabb) in objects.iter_mut() {
+            *aabb = rng.aabb(300.0);
+            bvh.update(*id, *aabb);
+        }
+        assert!(bvh.refit());
+        check_queries(&bvh, &objects, &mut rng);
+
+        let (id, _) = objects.remove(42);
+        assert!(bvh.remove(id));
+        assert!(!bvh.remove(id));
+        assert!(!bvh.update(id, rng.aabb(1.0)));
+        let added = (NodeId(1000), rng.aabb(30.0));
+        objects.push(added);
+        bvh.insert(added.0, added.1);
+        check_queries(&bvh, &objects, &mut rng);
+    }
+
+    #[test]
+    fn building_is_deterministic() {
+        let objects = objects(&mut Lcg(3), 100);
+        let a = SceneBvh::build(objects.iter().copied());
+        let b = SceneBvh::build(objects.iter().copied());
+        assert_eq!(a.objects, b.objects);
+        assert_eq!(a.cost(), b.cost());
+        // A tree should beat testing every object.
+        assert!(a.cost() < objects.len() as f32 / 2.0);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector3};
+
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Rest pose transform relative to the parent.
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Joint {
+    pub fn local_transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// Joints are stored so that parents come before their children.
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    /// Rest pose transform of a joint in skeleton space.
+    pub fn rest_transform(&self, joint: usize) -> Matrix4<f32> {
+        let local = self.joints[joint].local_transform();
+        match self.joints[joint].parent {
+            Some(parent) => self.rest_transform(parent) * local,
+            None => local,
+        }
+    }
+
+    /// Length of the bone from a joint's parent to the joint, at rest.
+    pub fn bone_length(&self, joint: usize) -> f32 {
+        self.joints[joint].translation.magnitude()
+    }
+
+    /// Height of a joint above the skeleton origin at rest. For the root this
+    /// is the hip height that root motion is scaled by.
+    pub fn rest_height(&self, joint: usize) -> f32 {
+        self.rest_transform(joint).w.y
+    }
+}
+
+pub enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    Rotations(Vec<Quaternion<f32>>),
+    Scales(Vec<Vector3<f32>>),
+}
+
+/// Keyframes animating one property of a joint, addressed by name so clips
+/// can be shared between skeletons.
+pub struct Channel {
+    pub joint: String,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+/// Maps `clip`, authored on `source`, onto `target`, matching joints by name.
+/// See [`retarget_with_names`].
+pub fn retarget(clip: &Animation, source: &Skeleton, target: &Skeleton) -> Animation {
+    retarget_with_names(clip, source, target, &HashMap::new())
+}
+
+/// Maps `clip`, authored on `source`, onto `target`. A source joint maps to
+/// the target joint named `names[source_name]`, or the one with the same name
+/// if it isn't in `names` (use this for prefixes like "mixamorig:").
+///
+/// Rotations and scales are copied as is. Translations are scaled by the ratio
+/// of the target and source bone lengths, or by the ratio of rest heights for
+/// root joints. Channels for joints missing from either skeleton are dropped,
+/// which leaves the corresponding target joints at their rest pose.
+pub fn retarget_with_names(
+    clip: &Animation,
+    source: &Skeleton,
+    target: &Skeleton,
+    names: &HashMap<String, String>,
+) -> Animation {
+    let mut channels = Vec::new();
+    for channel in &clip.channels {
+        let target_name = names.get(&channel.joint).unwrap_or(&channel.joint);
+        let (source_joint, target_joint) =
+            match (source.find(&channel.joint), target.find(target_name)) {
+                (Some(s), Some(t)) => (s, t),
+                _ => {
+                    log::warn!("Dropping channel for unmatched joint {:?}", channel.joint);
+                    continue;
+                }
+            };
+
+        let values = match &channel.values {
+            ChannelValues::Translations(translations) => {
+                let ratio = translation_ratio(source, source_joint, target, target_joint);
+                ChannelValues::Translations(translations.iter().map(|t| *t * ratio).collect())
+            }
+            ChannelValues::Rotations(rotations) => ChannelValues::Rotations(rotations.clone()),
+            ChannelValues::Scales(scales) => ChannelValues::Scales(scales.clone()),
+        };
+
+        channels.push(Channel {
+            joint: target_name.clone(),
+            times: channel.times.clone(),
+            values,
+        });
+    }
+
+    Animation {
+        name: clip.name.clone(),
+        channels,
+    }
+}
+
+fn translation_ratio(
+    source: &Skeleton,
+    source_joint: usize,
+    target: &Skeleton,
+    target_joint: usize,
+) -> f32 {
+    let (from, to) = if source.joints[source_joint].parent.is_none() {
+        (
+            source.rest_height(source_joint),
+            target.rest_height(target_joint),
+        )
+    } else {
+        (
+            source.bone_length(source_joint),
+            target.bone_length(target_joint),
+        )
+    };
+    // A zero length bone has no proportions to match.
+    if from.abs() > f32::EPSILON {
+        to / from
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rotation3};
+
+    use super::*;
+
+    fn joint(name: &str, parent: Option<usize>, translation: [f32; 3]) -> Joint {
+        Joint {
+            name: name.to_owned(),
+            parent,
+            translation: translation.into(),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Hips at `hip_height`, with a spine and a thigh of the given lengths.
+    fn skeleton(prefix: &str, hip_height: f32, spine: f32, thigh: f32) -> Skeleton {
+        let name = |joint: &str| format!("{}{}", prefix, joint);
+        Skeleton {
+            joints: vec![
+                joint(&name("Hips"), None, [0.0, hip_height, 0.0]),
+                joint(&name("Spine"), Some(0), [0.0, spine, 0.0]),
+                joint(&name("LeftUpLeg"), Some(0), [0.1, -thigh, 0.0]),
+            ],
+        }
+    }
+
+    fn clip(channels: Vec<Channel>) -> Animation {
+        Animation {
+            name: "walk".to_owned(),
+            channels,
+        }
+    }
+
+    fn translations(joint: &str, values: &[[f32; 3]]) -> Channel {
+        Channel {
+            joint: joint.to_owned(),
+            times: (0..values.len()).map(|i| i as f32 / 30.0).collect(),
+            values: ChannelValues::Translations(values.iter().map(|&v| v.into()).collect()),
+        }
+    }
+
+    fn translation_values(channel: &Channel) -> &[Vector3<f32>] {
+        match &channel.values {
+            ChannelValues::Translations(values) => values,
+            _ => panic!("{:?} isn't a translation channel", channel.joint),
+        }
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn root_translation_scales_by_hip_height() {
+        let source = skeleton("", 1.0, 0.2, 0.5);
+        let target = skeleton("", 1.5, 0.2, 0.5);
+        let walk = clip(vec![translations(
+            "Hips",
+            &[[0.0, 1.0, 0.0], [0.4, 0.9, 2.0]],
+        )]);
+        let retargeted = retarget(&walk, &source, &target);
+        let values = translation_values(&retargeted.channels[0]);
+        assert_close(values[0], Vector3::new(0.0, 1.5, 0.0));
+        assert_close(values[1], Vector3::new(0.6, 1.35, 3.0));
+    }
+
+    #[test]
+    fn bone_translation_scales_by_bone_length() {
+        let source = skeleton("", 1.0, 0.2, 0.5);
+        let target = skeleton("", 2.0, 0.3, 0.5);
+        let walk = clip(vec![
+            translations("Spine", &[[0.0, 0.2, 0.0], [0.0, 0.25, 0.1]]),
+            translations("LeftUpLeg", &[[0.1, -0.5, 0.0]]),
+        ]);
+        let retargeted = retarget(&walk, &source, &target);
+        // The spine ratio ignores the hips being twice as high.
+        let spine = translation_values(&retargeted.channels[0]);
+        assert_close(spine[0], Vector3::new(0.0, 0.3, 0.0));
+        assert_close(spine[1], Vector3::new(0.0, 0.375, 0.15));
+        // Same thigh length, so the leg is untouched.
+        let leg = translation_values(&retargeted.channels[1]);
+        assert_close(leg[0], Vector3::new(0.1, -0.5, 0.0));
+    }
+
+    #[test]
+    fn zero_length_bones_keep_translations() {
+        let source = skeleton("", 0.0, 0.0, 0.5);
+        let target = skeleton("", 1.0, 0.3, 0.5);
+        let walk = clip(vec![
+            translations("Hips", &[[0.0, 0.1, 0.0]]),
+            translations("Spine", &[[0.0, 0.2, 0.0]]),
+        ]);
+        let retargeted = retarget(&walk, &source, &target);
+        for (channel, expected) in retargeted.channels.iter().zip([0.1, 0.2]) {
+            assert_close(
+                translation_values(channel)[0],
+                Vector3::new(0.0, expected, 0.0),
+            );
+        }
+    }
+
+    #[test]
+    fn rotations_copy_and_names_map() {
+        let source = skeleton("mixamorig:", 1.0, 0.2, 0.5);
+        let target = skeleton("", 1.8, 0.4, 0.9);
+        let rotation = Quaternion::from_angle_x(Deg(30.0));
+        let walk = clip(vec![
+            Channel {
+                joint: "mixamorig:Spine".to_owned(),
+                times: vec![0.0],
+                values: ChannelValues::Rotations(vec![rotation]),
+            },
+            translations("mixamorig:Hips", &[[0.0, 1.0, 0.0]]),
+            translations("mixamorig:Head", &[[0.0, 0.1, 0.0]]),
+        ]);
+        let names = ["Spine", "Hips", "Head"]
+            .iter()
+            .map(|name| (format!("mixamorig:{}", name), name.to_string()))
+            .collect();
+        let retargeted = retarget_with_names(&walk, &source, &target, &names);
+
+        // The head isn't in either skeleton, so its channel is dropped.
+        assert_eq!(retargeted.channels.len(), 2);
+        assert_eq!(retargeted.channels[0].joint, "Spine");
+        match &retargeted.channels[0].values {
+            ChannelValues::Rotations(values) => assert_eq!(values, &[rotation]),
+            _ => panic!("the spine channel should stay a rotation channel"),
+        }
+        assert_eq!(retargeted.channels[1].joint, "Hips");
+        assert_close(
+            translation_values(&retargeted.channels[1])[0],
+            Vector3::new(0.0, 1.8, 0.0),
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Ambient occlusion baked into vertices, for static props that don't get
+//! lightmaps. See [`MeshData::bake_vertex_ao`].
+
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::{
+    accel::{NodeId, Ray, SceneBvh},
+    bounds::Aabb,
+    model::MeshData,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AoOptions {
+    /// Rays per vertex.
+    pub samples: u32,
+    /// Geometry further than this from a vertex doesn't occlude it, in the
+    /// mesh's own units.
+    pub ray_distance: f32,
+    /// How far off the surface along its normal each ray starts, as a
+    /// fraction of `ray_distance`, so flat surfaces don't occlude
+    /// themselves in speckles.
+    pub bias: f32,
+    /// Sends more rays near the normal, so occluders in front of a vertex
+    /// count for more than those at grazing angles, as they do for diffuse
+    /// light.
+    pub cosine_weighted: bool,
+}
+
+impl Default for AoOptions {
+    fn default() -> Self {
+        Self {
+            samples: 64,
+            ray_distance: 1.0,
+            bias: 1e-3,
+            cosine_weighted: true,
+        }
+    }
+}
+
+pub(crate) fn bake(mesh: &mut MeshData, options: &AoOptions) {
+    let position = |i: u32| Point3::from(mesh.vertices[i as usize].position);
+    let triangles = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|t| [position(t[0]), position(t[1]), position(t[2])])
+        .collect::<Vec<_>>();
+    let bvh = SceneBvh::build(
+        triangles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| Some((NodeId(i as u32), Aabb::from_points(t.iter().copied())?))),
+    );
+    let directions = hemisphere(options.samples.max(1), options.cosine_weighted);
+    let bias = options.bias * options.ray_distance;
+
+    for vertex in &mut mesh.vertices {
+        let normal = Vector3::from(vertex.normal);
+        if normal.magnitude2() == 0.0 {
+            vertex.occlusion = 1.0;
+            continue;
+        }
+        let normal = normal.normalize();
+        let (tangent, bitangent) = basis(normal);
+        let origin = Point3::from(vertex.position) + normal * bias;
+        let hits = directions
+            .iter()
+            .filter(|d| {
+                let ray = Ray::new(origin, tangent * d.x + bitangent * d.y + normal * d.z);
+                bvh.ray_query(&ray).any(|NodeId(i)| {
+                    let [a, b, c] = triangles[i as usize];
+                    ray.intersect_triangle(a, b, c)
+                        .is_some_and(|t| t <= options.ray_distance)
+                })
+            })
+            .count();
+        vertex.occlusion = 1.0 - hits as f32 / directions.len() as f32;
+    }
+}
+
+/// `count` directions over the hemisphere around +Z, spread evenly by a
+/// Hammersley sequence so the same mesh always bakes the same.
+fn hemisphere(count: u32, cosine_weighted: bool) -> Vec<Vector3<f32>> {
+    (0..count)
+        .map(|i| {
+            let u = (i as f32 + 0.5) / count as f32;
+            let phi = 2.0 * PI * (i.reverse_bits() as f32 / 2f32.powi(32));
+            let z = if cosine_weighted {
+                (1.0 - u).sqrt()
+            } else {
+                1.0 - u
+            };
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            Vector3::new(r * phi.cos(), r * phi.sin(), z)
+        })
+        .collect()
+}
+
+/// Two unit vectors perpendicular to unit `n` and each other, without a
+/// branch on its direction (Duff et al. 2017).
+fn basis(n: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{CompressedVertex, ModelVertex},
+        model_data::{MeshRecord, ModelData},
+    };
+
+    /// The cube's bottom rests this high above the plane, so its bottom
+    /// corners aren't left to rounding in the ray's first step.
+    const BOTTOM: f32 = 0.01;
+
+    /// Appends a quad with `corners` counterclockwise seen from the front,
+    /// with vertices of its own.
+    fn push_quad(mesh: &mut MeshData, corners: [[f32; 3]; 4], normal: [f32; 3]) {
+        let first = mesh.vertices.len() as u32;
+        mesh.vertices.extend(corners.map(|position| ModelVertex {
+            position,
+            tex_coords: [0.0, 0.0],
+            normal,
+            occlusion: 1.0,
+        }));
+        mesh.indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+    }
+
+    /// A 6 unit plane at y = 0, then a unit cube without a bottom on it.
+    fn cube_on_plane() -> MeshData {
+        let mut mesh = MeshData::default();
+        push_quad(
+            &mut mesh,
+            [
+                [-3.0, 0.0, 3.0],
+                [3.0, 0.0, 3.0],
+                [3.0, 0.0, -3.0],
+                [-3.0, 0.0, -3.0],
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        let top = BOTTOM + 1.0;
+        let corner = |x: f32, y: f32, z: f32| [x * 0.5, y, z * 0.5];
+        push_quad(
+            &mut mesh,
+            [
+                corner(-1.0, top, 1.0),
+                corner(1.0, top, 1.0),
+                corner(1.0, top, -1.0),
+                corner(-1.0, top, -1.0),
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        for (x, z) in [(0.0, 1.0), (1.0, 0.0), (0.0, -1.0), (-1.0, 0.0)] {
+            // Counterclockwise seen from outside, along the side facing (x, z).
+            let (right_x, right_z) = (z, -x);
+            let side = |along: f32, y: f32| corner(x + right_x * along, y, z + right_z * along);
+            push_quad(
+                &mut mesh,
+                [
+                    side(-1.0, BOTTOM),
+                    side(1.0, BOTTOM),
+                    side(1.0, top),
+                    side(-1.0, top),
+                ],
+                [x, 0.0, z],
+            );
+        }
+        mesh
+    }
+
+    #[test]
+    fn a_cube_on_a_plane_is_darker_at_its_base() {
+        let mesh = cube_on_plane();
+        for cosine_weighted in [true, false] {
+            let mut baked = mesh.clone();
+            baked.bake_vertex_ao_with_options(&AoOptions {
+                samples: 128,
+                ray_distance: 0.5,
+                cosine_weighted,
+                ..Default::default()
+            });
+            let mean = |vertices: Vec<&ModelVertex>| {
+                vertices.iter().map(|v| v.occlusion).sum::<f32>() / vertices.len().max(1) as f32
+            };
+            let cube = &baked.vertices[4..];
+            let near_plane = mean(cube.iter().filter(|v| v.position[1] == BOTTOM).collect());
+            let on_top = mean(cube.iter().filter(|v| v.normal[1] == 1.0).collect());
+            assert!(
+                on_top > 0.99 && (0.3..0.7).contains(&near_plane),
+                "cosine weighted {}: occlusion {} on top and {} next to the plane",
+                cosine_weighted,
+                on_top,
+                near_plane
+            );
+            let far_corners = &baked.vertices[..4];
+            assert!(
+                far_corners.iter().all(|v| v.occlusion == 1.0),
+                "the plane occludes itself: {:?}",
+                far_corners.iter().map(|v| v.occlusion).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn baked_occlusion_survives_files_and_compression() {
+        let mut baked = cube_on_plane();
+        baked.bake_vertex_ao(64, 0.5);
+        let data = ModelData {
+            meshes: vec![MeshRecord {
+                name: "ao".to_string(),
+                material: 0,
+                data: baked.clone(),
+                has_uvs: false,
+            }],
+            materials: Vec::new(),
+        };
+        let loaded = ModelData::from_bytes(&data.to_bytes()).unwrap();
+        let aabb =
+            Aabb::from_points(baked.vertices.iter().map(|v| Point3::from(v.position))).unwrap();
+        for (v, read) in baked.vertices.iter().zip(&loaded.meshes[0].data.vertices) {
+            let decoded = CompressedVertex::encode(v, &aabb).decode(&aabb);
+            assert_eq!(read.occlusion, v.occlusion);
+            assert!(
+                (decoded.occlusion - v.occlusion).abs() < 1e-4,
+                "occlusion {} came back as {} compressed",
+                v.occlusion,
+                decoded.occlusion
+            );
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Typed handles to loaded assets. A handle is an index plus a generation,
+//! so one kept after its asset is released stops resolving instead of
+//! silently referring to whatever reused the slot. Paths are resolved to
+//! handles once, at load or [`Assets::lookup`] time, and never per frame.
+//!
+//! Resolving a handle marks its asset as used, so that when GPU memory runs
+//! out the least recently used assets can be released to make room, see
+//! [`Assets::evicting`].
+
+use std::{
+    collections::HashMap,
+    fmt, hash,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{gpu::AllocError, model, texture};
+
+pub type ModelHandle = Handle<model::Model>;
+pub type TextureHandle = Handle<texture::Texture>;
+pub type MaterialHandle = Handle<model::Material>;
+
+/// Refers to a `T` in an [`Assets<T>`].
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self {
+            index,
+            generation,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+// Implemented by hand, as deriving would require the same of `T`.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.index, self.generation) == (other.index, other.generation)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> hash::Hash for Handle<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (self.index, self.generation).hash(state);
+    }
+}
+
+impl<T> PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.index, self.generation).cmp(&(other.index, other.generation))
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+    path: Option<String>,
+    /// The [`Assets::clock`] tick the asset was last inserted or resolved
+    /// at. Atomic so resolving can stay `&self`.
+    used: AtomicU64,
+}
+
+/// Assets of one type, each with a [`Handle`] and optionally the path it
+/// was loaded from.
+pub struct Assets<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    by_path: HashMap<String, Handle<T>>,
+    clock: AtomicU64,
+}
+
+impl<T> Default for Assets<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Assets<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_path: HashMap::new(),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds an asset that wasn't loaded from a path, e.g. a generated one.
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.insert_slot(value, None)
+    }
+
+    /// Adds an asset loaded from `path`, which [`Assets::lookup`] then finds
+    /// it by. An asset already loaded from `path` keeps its handle, but the
+    /// path refers to the new one from now on.
+    pub fn insert_with_path(&mut self, path: &str, value: T) -> Handle<T> {
+        let handle = self.insert_slot(value, Some(path.to_string()));
+        self.by_path.insert(path.to_string(), handle);
+        handle
+    }
+
+    fn insert_slot(&mut self, value: T, path: Option<String>) -> Handle<T> {
+        let used = self.tick();
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                slot.path = path;
+                *slot.used.get_mut() = used;
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                    path,
+                    used: AtomicU64::new(used),
+                });
+                Handle::new(self.slots.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The asset `handle` refers to, or `None` if it has been released.
+    /// Marks it as used.
+    pub fn resolve(&self, handle: Handle<T>) -> Option<&T> {
+        let slot = self
+            .slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let value = slot.value.as_ref()?;
+        slot.used.store(self.tick(), Ordering::Relaxed);
+        Some(value)
+    }
+
+    pub fn resolve_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let used = self.tick();
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        *slot.used.get_mut() = used;
+        slot.value.as_mut()
+    }
+
+    /// Removes the asset, after which `handle` and every copy of it no
+    /// longer resolve, even once the slot is reused.
+    pub fn release(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        if let Some(path) = slot.path.take() {
+            if self.by_path.get(&path) == Some(&handle) {
+                self.by_path.remove(&path);
+            }
+        }
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    /// The live asset that was inserted or resolved longest ago.
+    pub fn least_recently_used(&self) -> Option<Handle<T>> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.value.is_some())
+            .min_by_key(|(_, slot)| slot.used.load(Ordering::Relaxed))
+            .map(|(i, slot)| Handle::new(i as u32, slot.generation))
+    }
+
+    /// Releases the least recently used asset if `error` is GPU memory
+    /// running out, for the caller to retry what failed. False if it is
+    /// another error or there is nothing left to release.
+    pub fn evict_for(&mut self, error: &anyhow::Error) -> bool {
+        let out_of_memory = error.chain().any(|cause| {
+            cause
+                .downcast_ref()
+                .is_some_and(AllocError::is_out_of_memory)
+        });
+        if !out_of_memory {
+            return false;
+        }
+        let Some(handle) = self.least_recently_used() else {
+            return false;
+        };
+        log::warn!(
+            "{}, releasing {}",
+            error,
+            self.path(handle).unwrap_or("an unnamed asset")
+        );
+        self.release(handle).is_some()
+    }
+
+    /// Calls `create` until it stops running out of GPU memory, releasing
+    /// the least recently used asset before each retry. Fails with the last
+    /// error once there is nothing left to release.
+    pub fn evicting<R>(
+        &mut self,
+        mut create: impl FnMut() -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        loop {
+            match create() {
+                Err(e) if self.evict_for(&e) => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// The handle of the asset most recently loaded from `path`.
+    pub fn lookup(&self, path: &str) -> Option<Handle<T>> {
+        self.by_path.get(path).copied()
+    }
+
+    /// The path the asset was loaded from, for logging.
+    pub fn path(&self, handle: Handle<T>) -> Option<&str> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.path.as_deref())
+    }
+
+    /// Number of live assets.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            let value = slot.value.as_ref()?;
+            Some((Handle::new(i as u32, slot.generation), value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releasing_bumps_the_generation() {
+        let mut assets = Assets::new();
+        let first = assets.insert("first");
+        assert_eq!(assets.release(first), Some("first"));
+        assert_eq!(assets.release(first), None);
+        assert!(assets.is_empty());
+
+        let second = assets.insert("second");
+        assert_eq!(second.index(), first.index());
+        assert_eq!(second.generation(), first.generation() + 1);
+        assert_ne!(second, first);
+    }
+
+    #[test]
+    fn stale_handles_dont_alias_reused_slots() {
+        let mut assets = Assets::new();
+        let kept = assets.insert_with_path("kept.png", 1);
+        let stale = assets.insert_with_path("stale.png", 2);
+        assets.release(stale);
+        let reused = assets.insert_with_path("reused.png", 3);
+        assert_eq!(reused.index(), stale.index());
+
+        assert_eq!(assets.resolve(stale), None);
+        assert_eq!(assets.resolve_mut(stale), None);
+        assert_eq!(assets.path(stale), None);
+        assert_eq!(assets.release(stale), None);
+        assert_eq!(assets.lookup("stale.png"), None);
+
+        assert_eq!(assets.resolve(reused), Some(&3));
+        assert_eq!(assets.resolve(kept), Some(&1));
+        assert_eq!(assets.lookup("reused.png"), Some(reused));
+        assert_eq!(assets.len(), 2);
+        let live = assets.iter().map(|(handle, _)| handle).collect::<Vec<_>>();
+        assert_eq!(live, vec![kept, reused]);
+    }
+
+    #[test]
+    fn resolving_keeps_assets_from_being_evicted() {
+        let mut assets = Assets::new();
+        let first = assets.insert("first");
+        let second = assets.insert("second");
+        let third = assets.insert("third");
+        assert_eq!(assets.least_recently_used(), Some(first));
+        assets.resolve(first);
+        assert_eq!(assets.least_recently_used(), Some(second));
+        assets.resolve_mut(second);
+        assert_eq!(assets.least_recently_used(), Some(third));
+
+        let out_of_memory = anyhow::Error::from(AllocError::OutOfMemory {
+            label: None,
+            category: crate::gpu::MemoryCategory::Texture,
+            bytes: 4,
+        })
+        .context("loading a texture");
+        assert!(!assets.evict_for(&anyhow::anyhow!("not a GPU error")));
+        assert!(assets.evict_for(&out_of_memory));
+        assert_eq!(assets.resolve(third), None);
+        assert!(assets.evict_for(&out_of_memory));
+        assert!(assets.evict_for(&out_of_memory));
+        assert!(!assets.evict_for(&out_of_memory));
+        assert!(assets.is_empty());
+    }
+
+    #[test]
+    fn reloading_a_path_keeps_the_old_handle() {
+        let mut assets = Assets::new();
+        let old = assets.insert_with_path("model.obj", "old");
+        let new = assets.insert_with_path("model.obj", "new");
+        assert_eq!(assets.lookup("model.obj"), Some(new));
+        assert_eq!(assets.resolve(old), Some(&"old"));
+
+        // Releasing the old one leaves the path with the new one.
+        assets.release(old);
+        assert_eq!(assets.lookup("model.obj"), Some(new));
+        assets.release(new);
+        assert_eq!(assets.lookup("model.obj"), None);
+    }
+}
@@ -0,0 +1,531 @@
+use anyhow::bail;
+use image::RgbaImage;
+
+use crate::{
+    model::MeshData,
+    model_data::{MaterialRecord, MeshRecord, ModelData},
+};
+
+/// How far UVs may stray outside `0..1` before a texture is treated as
+/// wrapping, to tolerate rounding in exported files.
+const UV_EPSILON: f32 = 1e-4;
+
+/// Where an image ended up in the atlas, in pixels, excluding padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasEntry {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasEntry {
+    /// Maps a UV in the source image to a UV in the atlas page.
+    pub fn remap_uv(&self, uv: [f32; 2], page_size: (u32, u32)) -> [f32; 2] {
+        [
+            (self.x as f32 + uv[0] * self.width as f32) / page_size.0 as f32,
+            (self.y as f32 + uv[1] * self.height as f32) / page_size.1 as f32,
+        ]
+    }
+}
+
+pub struct AtlasPage {
+    pub image: RgbaImage,
+}
+
+impl AtlasPage {
+    pub fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+/// Packs images into one or more atlas pages of at most `max_size` pixels
+/// square. Images are placed on shelves, tallest first, and start a new page
+/// once the current one is full.
+///
+/// Every image is surrounded by `padding` pixels copied from its edges, so
+/// filtering and lower mip levels don't bleed neighbouring images in. Each
+/// mip level halves the gutter, so `1 << (mip_levels - 1)` pixels keeps all
+/// levels clean.
+pub struct AtlasBuilder {
+    max_size: u32,
+    padding: u32,
+    images: Vec<RgbaImage>,
+}
+
+impl AtlasBuilder {
+    pub fn new(max_size: u32, padding: u32) -> Self {
+        Self {
+            max_size,
+            padding,
+            images: Vec::new(),
+        }
+    }
+
+    /// Adds an image, returning its index into the entries from [`Self::build`].
+    pub fn add(&mut self, image: RgbaImage) -> usize {
+        self.images.push(image);
+        self.images.len() - 1
+    }
+
+    pub fn build(self) -> anyhow::Result<(Vec<AtlasPage>, Vec<AtlasEntry>)> {
+        let padding = self.padding;
+        for (i, image) in self.images.iter().enumerate() {
+            let (w, h) = image.dimensions();
+            if w + padding * 2 > self.max_size || h + padding * 2 > self.max_size {
+                bail!(
+                    "Image {} ({}x{}) doesn't fit a {} pixel atlas page",
+                    i,
+                    w,
+                    h,
+                    self.max_size
+                );
+            }
+        }
+
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.images[i].height()));
+
+        // Pack first so pages can be sized to what they hold.
+        let mut entries = vec![
+            AtlasEntry {
+                page: 0,
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            };
+            self.images.len()
+        ];
+        let mut page_heights = vec![0];
+        let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+        for i in order {
+            let (w, h) = self.images[i].dimensions();
+            let (padded_w, padded_h) = (w + padding * 2, h + padding * 2);
+            if shelf_x + padded_w > self.max_size {
+                shelf_y += shelf_height;
+                shelf_x = 0;
+                shelf_height = 0;
+            }
+            if shelf_y + padded_h > self.max_size {
+                page_heights.push(0);
+                shelf_x = 0;
+                shelf_y = 0;
+                shelf_height = 0;
+            }
+            let page = page_heights.len() - 1;
+            entries[i] = AtlasEntry {
+                page,
+                x: shelf_x + padding,
+                y: shelf_y + padding,
+                width: w,
+                height: h,
+            };
+            shelf_x += padded_w;
+            shelf_height = shelf_height.max(padded_h);
+            page_heights[page] = page_heights[page].max(shelf_y + padded_h);
+        }
+
+        let mut pages: Vec<AtlasPage> = page_heights
+            .iter()
+            .map(|&height| AtlasPage {
+                image: RgbaImage::new(self.max_size, height.next_power_of_two().min(self.max_size)),
+            })
+            .collect();
+        for (image, entry) in self.images.iter().zip(&entries) {
+            blit_with_gutter(&mut pages[entry.page].image, image, entry, padding);
+        }
+
+        Ok((pages, entries))
+    }
+}
+
+fn blit_with_gutter(page: &mut RgbaImage, image: &RgbaImage, entry: &AtlasEntry, padding: u32) {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return;
+    }
+    let (px, py) = (entry.x - padding, entry.y - padding);
+    for y in 0..h + padding * 2 {
+        let sy = y.saturating_sub(padding).min(h - 1);
+        for x in 0..w + padding * 2 {
+            let sx = x.saturating_sub(padding).min(w - 1);
+            page.put_pixel(px + x, py + y, *image.get_pixel(sx, sy));
+        }
+    }
+}
+
+/// Whether every UV lies in `0..1`. Textures sampled outside that range rely
+/// on wrapping, which an atlas can't reproduce.
+pub fn uvs_in_unit_range<I: IntoIterator<Item = [f32; 2]>>(uvs: I) -> bool {
+    let range = -UV_EPSILON..=1.0 + UV_EPSILON;
+    uvs.into_iter()
+        .all(|uv| range.contains(&uv[0]) && range.contains(&uv[1]))
+}
+
+/// How [`merge`] atlases textures.
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    /// The largest atlas page, in pixels. Textures that don't fit on one
+    /// page spill over into another, which is a separate batch.
+    pub max_size: u32,
+    /// Gutter pixels around every texture. See [`AtlasBuilder`].
+    pub padding: u32,
+    /// The atlas pages are named `{page_prefix}{page}.png` in the merged
+    /// materials, for the caller to save the pages under.
+    pub page_prefix: String,
+}
+
+/// A model with its meshes merged, and the atlas pages its materials use.
+pub struct Merged {
+    pub data: ModelData,
+    pub pages: Vec<AtlasPage>,
+}
+
+/// Merges `data`'s meshes into one mesh per material, and the meshes of
+/// textured materials into one mesh per atlas page, so each page is a single
+/// draw. `textures` holds the decoded diffuse texture of each material.
+///
+/// Materials without a texture, with glass, triplanar or alpha masking, or
+/// with UVs outside `0..1` keep their own texture and mesh. Wrapping UVs are
+/// warned about, since they are the ones a caller may not expect.
+pub fn merge(
+    data: &ModelData,
+    textures: &[Option<RgbaImage>],
+    options: &MergeOptions,
+) -> anyhow::Result<Merged> {
+    let atlased: Vec<bool> = data
+        .materials
+        .iter()
+        .enumerate()
+        .map(|(i, material)| {
+            let plain = matches!(textures.get(i), Some(Some(_)))
+                && !material.alpha_mask
+                && material.refractive.is_none()
+                && material.triplanar.is_none();
+            let mut meshes = data.meshes.iter().filter(|m| m.material as usize == i);
+            let wraps = meshes.any(|m| {
+                m.has_uvs && !uvs_in_unit_range(m.data.vertices.iter().map(|v| v.tex_coords))
+            });
+            if plain && wraps {
+                log::warn!(
+                    "{} has UVs outside 0..1, so it isn't atlased",
+                    material.name
+                );
+            }
+            plain && !wraps
+        })
+        .collect();
+
+    let mut builder = AtlasBuilder::new(options.max_size, options.padding);
+    let slots: Vec<Option<usize>> = textures
+        .iter()
+        .zip(&atlased)
+        .map(|(texture, &atlased)| match texture {
+            Some(texture) if atlased => Some(builder.add(texture.clone())),
+            _ => None,
+        })
+        .collect();
+    let (pages, entries) = builder.build()?;
+
+    // The merged materials: one per page, then the ones left alone.
+    let mut materials: Vec<MaterialRecord> = (0..pages.len())
+        .map(|page| MaterialRecord {
+            name: format!("atlas{}", page),
+            diffuse_texture: format!("{}{}.png", options.page_prefix, page),
+            alpha_mask: false,
+            refractive: None,
+            triplanar: None,
+        })
+        .collect();
+    let mut merged_material = Vec::with_capacity(data.materials.len());
+    for (i, material) in data.materials.iter().enumerate() {
+        merged_material.push(match slots.get(i).copied().flatten() {
+            Some(slot) => entries[slot].page,
+            None => {
+                materials.push(material.clone());
+                materials.len() - 1
+            }
+        });
+    }
+
+    let mut meshes: Vec<MeshRecord> = materials
+        .iter()
+        .enumerate()
+        .map(|(i, material)| MeshRecord {
+            name: material.name.clone(),
+            material: i as u32,
+            data: MeshData::default(),
+            has_uvs: false,
+        })
+        .collect();
+    for mesh in &data.meshes {
+        let source = mesh.material as usize;
+        let merged = &mut meshes[merged_material[source]];
+        let entry = slots
+            .get(source)
+            .copied()
+            .flatten()
+            .map(|slot| (entries[slot], pages[entries[slot].page].size()));
+        let base = merged.data.vertices.len() as u32;
+        merged
+            .data
+            .vertices
+            .extend(mesh.data.vertices.iter().map(|v| {
+                let mut v = *v;
+                if let Some((entry, page_size)) = entry {
+                    v.tex_coords = entry.remap_uv(v.tex_coords, page_size);
+                }
+                v
+            }));
+        merged
+            .data
+            .indices
+            .extend(mesh.data.indices.iter().map(|i| i + base));
+        merged.has_uvs |= mesh.has_uvs;
+    }
+    meshes.retain(|mesh| !mesh.data.indices.is_empty());
+
+    Ok(Merged {
+        data: ModelData { meshes, materials },
+        pages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ModelVertex;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, image::Rgba(color))
+    }
+
+    /// The entry grown by its gutter.
+    fn padded(entry: &AtlasEntry, padding: u32) -> (u32, u32, u32, u32) {
+        (
+            entry.x - padding,
+            entry.y - padding,
+            entry.x + entry.width + padding,
+            entry.y + entry.height + padding,
+        )
+    }
+
+    #[test]
+    fn packed_images_stay_in_bounds_without_overlapping() {
+        let padding = 2;
+        let mut builder = AtlasBuilder::new(64, padding);
+        for i in 0..40u32 {
+            builder.add(solid(1 + i * 7 % 13, 1 + i * 5 % 11, [i as u8, 0, 0, 255]));
+        }
+        let (pages, entries) = builder.build().unwrap();
+        assert!(pages.len() > 1, "40 images should spill over 64x64 pages");
+
+        for (i, a) in entries.iter().enumerate() {
+            let (x0, y0, x1, y1) = padded(a, padding);
+            let (width, height) = pages[a.page].size();
+            assert!(width <= 64 && height <= 64);
+            assert!(x1 <= width && y1 <= height, "{:?} is outside its page", a);
+            for b in &entries[i + 1..] {
+                let (bx0, by0, bx1, by1) = padded(b, padding);
+                let apart = a.page != b.page || x1 <= bx0 || bx1 <= x0 || y1 <= by0 || by1 <= y0;
+                assert!(apart, "{:?} and {:?} overlap", a, b);
+            }
+        }
+        // Every image landed whole, so nothing overwrote it.
+        for (i, entry) in entries.iter().enumerate() {
+            let page = &pages[entry.page].image;
+            for y in entry.y..entry.y + entry.height {
+                for x in entry.x..entry.x + entry.width {
+                    assert_eq!(page.get_pixel(x, y).0, [i as u8, 0, 0, 255]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gutters_repeat_the_edges() {
+        let mut image = solid(2, 2, [0; 4]);
+        image.put_pixel(0, 0, image::Rgba([1, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([2, 0, 0, 255]));
+        image.put_pixel(0, 1, image::Rgba([3, 0, 0, 255]));
+        image.put_pixel(1, 1, image::Rgba([4, 0, 0, 255]));
+        let padding = 3;
+        let mut builder = AtlasBuilder::new(16, padding);
+        builder.add(image.clone());
+        let (pages, entries) = builder.build().unwrap();
+        let (page, entry) = (&pages[0].image, entries[0]);
+        assert_eq!((entry.x, entry.y), (padding, padding));
+
+        for y in 0..2 + padding * 2 {
+            for x in 0..2 + padding * 2 {
+                let source = (
+                    x.saturating_sub(padding).min(1),
+                    y.saturating_sub(padding).min(1),
+                );
+                assert_eq!(
+                    page.get_pixel(x, y),
+                    image.get_pixel(source.0, source.1),
+                    "at {}, {}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn images_larger_than_a_page_are_refused() {
+        let mut builder = AtlasBuilder::new(16, 1);
+        builder.add(solid(15, 4, [0; 4]));
+        assert!(builder.build().is_err());
+    }
+
+    fn quad(material: u32, uvs: [[f32; 2]; 4]) -> MeshRecord {
+        let vertices = uvs
+            .iter()
+            .map(|&uv| ModelVertex {
+                position: [uv[0], uv[1], material as f32],
+                tex_coords: uv,
+                normal: [0.0, 0.0, 1.0],
+                occlusion: 1.0,
+            })
+            .collect();
+        MeshRecord {
+            name: format!("quad{}", material),
+            material,
+            data: MeshData {
+                vertices,
+                indices: vec![0, 1, 2, 0, 2, 3],
+            },
+            has_uvs: true,
+        }
+    }
+
+    const UNIT_UVS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    const COLORS: [[u8; 4]; 3] = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+
+    fn three_quads(uvs: [[[f32; 2]; 4]; 3]) -> (ModelData, Vec<Option<RgbaImage>>) {
+        let data = ModelData {
+            meshes: (0..3).map(|i| quad(i, uvs[i as usize])).collect(),
+            materials: (0..3)
+                .map(|i| MaterialRecord {
+                    name: format!("material{}", i),
+                    diffuse_texture: format!("texture{}.png", i),
+                    alpha_mask: false,
+                    refractive: None,
+                    triplanar: None,
+                })
+                .collect(),
+        };
+        let textures = COLORS.iter().map(|&c| Some(solid(8, 8, c))).collect();
+        (data, textures)
+    }
+
+    fn options(max_size: u32) -> MergeOptions {
+        MergeOptions {
+            max_size,
+            padding: 2,
+            page_prefix: "atlas".to_string(),
+        }
+    }
+
+    /// The texel of `page` under `uv`.
+    fn texel(page: &AtlasPage, uv: [f32; 2]) -> [u8; 4] {
+        let (width, height) = page.size();
+        let x = ((uv[0] * width as f32) as u32).min(width - 1);
+        let y = ((uv[1] * height as f32) as u32).min(height - 1);
+        page.image.get_pixel(x, y).0
+    }
+
+    #[test]
+    fn textured_quads_merge_into_one_draw() {
+        let (data, textures) = three_quads([UNIT_UVS; 3]);
+        let merged = merge(&data, &textures, &options(64)).unwrap();
+
+        assert_eq!(merged.pages.len(), 1);
+        assert_eq!(merged.data.meshes.len(), 1);
+        assert_eq!(merged.data.materials.len(), 1);
+        assert_eq!(merged.data.materials[0].diffuse_texture, "atlas0.png");
+        let mesh = &merged.data.meshes[0];
+        assert_eq!(mesh.data.vertices.len(), 12);
+        assert_eq!(mesh.data.indices.len(), 18);
+
+        // Each quad's vertices follow the previous ones', and its UVs now
+        // land on its own texture in the atlas.
+        for (quad, color) in COLORS.iter().enumerate() {
+            let vertices = &mesh.data.vertices[quad * 4..quad * 4 + 4];
+            assert!(vertices.iter().all(|v| v.position[2] == quad as f32));
+            let [a, _, c, _] = [0, 1, 2, 3].map(|i| vertices[i].tex_coords);
+            let center = [(a[0] + c[0]) / 2.0, (a[1] + c[1]) / 2.0];
+            assert_eq!(texel(&merged.pages[0], center), *color);
+            // Corners are inside the texture, not on a neighbour.
+            let inset = |uv: [f32; 2], toward: [f32; 2]| {
+                [
+                    uv[0] + (toward[0] - uv[0]) * 0.01,
+                    uv[1] + (toward[1] - uv[1]) * 0.01,
+                ]
+            };
+            assert_eq!(texel(&merged.pages[0], inset(a, c)), *color);
+            assert_eq!(texel(&merged.pages[0], inset(c, a)), *color);
+        }
+        let indices = &mesh.data.indices;
+        assert_eq!(&indices[6..12], &[4, 5, 6, 4, 6, 7]);
+    }
+
+    #[test]
+    fn wrapping_materials_keep_their_texture() {
+        let mut uvs = [UNIT_UVS; 3];
+        uvs[1] = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]];
+        let (data, textures) = three_quads(uvs);
+        let merged = merge(&data, &textures, &options(64)).unwrap();
+
+        assert_eq!(merged.data.meshes.len(), 2);
+        let materials: Vec<_> = merged
+            .data
+            .materials
+            .iter()
+            .map(|m| m.diffuse_texture.as_str())
+            .collect();
+        assert_eq!(materials, ["atlas0.png", "texture1.png"]);
+        let wrapped = &merged.data.meshes[1];
+        assert_eq!(wrapped.material, 1);
+        let uvs: Vec<_> = wrapped.data.vertices.iter().map(|v| v.tex_coords).collect();
+        assert_eq!(
+            uvs,
+            data.meshes[1]
+                .data
+                .vertices
+                .iter()
+                .map(|v| v.tex_coords)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn textures_spill_over_into_another_batch() {
+        let (data, textures) = three_quads([UNIT_UVS; 3]);
+        // A padded 8x8 texture is 12 pixels, so a 20 pixel page holds one.
+        let merged = merge(&data, &textures, &options(20)).unwrap();
+
+        assert_eq!(merged.pages.len(), 3);
+        assert_eq!(merged.data.meshes.len(), 3);
+        let quads: Vec<_> = merged
+            .data
+            .meshes
+            .iter()
+            .map(|m| m.data.vertices.len() / 4)
+            .collect();
+        assert_eq!(quads, [1, 1, 1]);
+        for mesh in &merged.data.meshes {
+            let page = &merged.pages[mesh.material as usize];
+            for quad in mesh.data.vertices.chunks(4) {
+                let color = COLORS[quad[0].position[2] as usize];
+                let (a, c) = (quad[0].tex_coords, quad[2].tex_coords);
+                let center = [(a[0] + c[0]) / 2.0, (a[1] + c[1]) / 2.0];
+                assert_eq!(texel(page, center), color);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use cgmath::{Matrix, Matrix3, Quaternion, SquareMatrix, Vector3};
+
+use crate::animation::{Animation, ChannelValues, Skeleton};
+
+/// Which way is up and which way the third axis points in a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AxisConvention {
+    /// +Y up, +Z towards the viewer. What the crate uses, and what glTF
+    /// specifies.
+    #[default]
+    YUpRightHanded,
+    /// +Y up, +Z away from the viewer.
+    YUpLeftHanded,
+    /// +Z up, +Y away from the viewer, as Blender and most CAD tools export.
+    ZUpRightHanded,
+    /// +Z up, +Y towards the viewer.
+    ZUpLeftHanded,
+}
+
+/// The convention loaded assets are converted to.
+pub const TARGET_AXES: AxisConvention = AxisConvention::YUpRightHanded;
+
+impl AxisConvention {
+    pub fn is_right_handed(self) -> bool {
+        matches!(
+            self,
+            AxisConvention::YUpRightHanded | AxisConvention::ZUpRightHanded
+        )
+    }
+
+    /// Maps this convention's coordinates to Y up right handed ones.
+    fn to_y_up_right_handed(self) -> Matrix3<f32> {
+        // Columns are where the source X, Y and Z axes end up.
+        match self {
+            AxisConvention::YUpRightHanded => Matrix3::identity(),
+            AxisConvention::YUpLeftHanded => {
+                Matrix3::from_cols(Vector3::unit_x(), Vector3::unit_y(), -Vector3::unit_z())
+            }
+            AxisConvention::ZUpRightHanded => {
+                Matrix3::from_cols(Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y())
+            }
+            AxisConvention::ZUpLeftHanded => {
+                Matrix3::from_cols(Vector3::unit_x(), Vector3::unit_z(), Vector3::unit_y())
+            }
+        }
+    }
+
+    pub fn conversion_to(self, target: AxisConvention) -> AxisConversion {
+        // The matrices only permute and negate axes, so the transpose is the
+        // inverse.
+        let matrix = target.to_y_up_right_handed().transpose() * self.to_y_up_right_handed();
+        AxisConversion {
+            matrix,
+            flips_winding: self.is_right_handed() != target.is_right_handed(),
+        }
+    }
+}
+
+/// Converts positions, directions and transforms between two conventions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisConversion {
+    pub matrix: Matrix3<f32>,
+    /// Whether the handedness changes, which mirrors geometry and so turns
+    /// triangles around.
+    pub flips_winding: bool,
+}
+
+impl AxisConversion {
+    pub fn is_identity(&self) -> bool {
+        self.matrix == Matrix3::identity()
+    }
+
+    /// Converts a position or a direction such as a normal or tangent.
+    pub fn vector(&self, v: [f32; 3]) -> [f32; 3] {
+        (self.matrix * Vector3::from(v)).into()
+    }
+
+    pub fn rotation(&self, q: Quaternion<f32>) -> Quaternion<f32> {
+        Quaternion::from(self.matrix * Matrix3::from(q) * self.matrix.transpose())
+    }
+
+    /// Scales stay positive, they only move to the converted axes.
+    pub fn scale(&self, s: Vector3<f32>) -> Vector3<f32> {
+        let m = &self.matrix;
+        let abs = Matrix3::from_cols(m.x.map(f32::abs), m.y.map(f32::abs), m.z.map(f32::abs));
+        abs * s
+    }
+
+    /// Restores counter clockwise winding of a triangle list after a
+    /// handedness change.
+    pub fn fix_winding(&self, indices: &mut [u32]) {
+        if self.flips_winding {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    pub fn convert_skeleton(&self, skeleton: &mut Skeleton) {
+        for joint in &mut skeleton.joints {
+            joint.translation = self.matrix * joint.translation;
+            joint.rotation = self.rotation(joint.rotation);
+            joint.scale = self.scale(joint.scale);
+        }
+    }
+
+    pub fn convert_animation(&self, animation: &mut Animation) {
+        for channel in &mut animation.channels {
+            match &mut channel.values {
+                ChannelValues::Translations(translations) => {
+                    for t in translations {
+                        *t = self.matrix * *t;
+                    }
+                }
+                ChannelValues::Rotations(rotations) => {
+                    for q in rotations {
+                        *q = self.rotation(*q);
+                    }
+                }
+                ChannelValues::Scales(scales) => {
+                    for s in scales {
+                        *s = self.scale(*s);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Point3};
+
+    use super::*;
+    use crate::bounds::Aabb;
+
+    const CONVENTIONS: [AxisConvention; 4] = [
+        AxisConvention::YUpRightHanded,
+        AxisConvention::YUpLeftHanded,
+        AxisConvention::ZUpRightHanded,
+        AxisConvention::ZUpLeftHanded,
+    ];
+
+    /// `x` to the right, `up` and `toward` the viewer in `convention`'s
+    /// coordinates.
+    fn source_point(convention: AxisConvention, x: f32, up: f32, toward: f32) -> [f32; 3] {
+        match convention {
+            AxisConvention::YUpRightHanded => [x, up, toward],
+            AxisConvention::YUpLeftHanded => [x, up, -toward],
+            AxisConvention::ZUpRightHanded => [x, -toward, up],
+            AxisConvention::ZUpLeftHanded => [x, toward, up],
+        }
+    }
+
+    #[test]
+    fn a_box_from_every_convention_converts_to_the_same_bounds() {
+        // 1 wide, 3 tall and 2 deep, so swapped axes show.
+        let expected = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 3.0, 2.0));
+        for convention in CONVENTIONS {
+            let conversion = convention.conversion_to(TARGET_AXES);
+            let corners = (0..8).map(|i| {
+                let corner = source_point(
+                    convention,
+                    (i & 1) as f32,
+                    (i >> 1 & 1) as f32 * 3.0,
+                    (i >> 2 & 1) as f32 * 2.0,
+                );
+                Point3::from(conversion.vector(corner))
+            });
+            assert_eq!(
+                Aabb::from_points(corners),
+                Some(expected),
+                "{:?}",
+                convention
+            );
+            assert_eq!(conversion.is_identity(), convention == TARGET_AXES);
+        }
+    }
+
+    #[test]
+    fn winding_flips_with_handedness() {
+        for source in CONVENTIONS {
+            for target in CONVENTIONS {
+                let conversion = source.conversion_to(target);
+                assert_eq!(
+                    conversion.flips_winding,
+                    source.is_right_handed() != target.is_right_handed(),
+                    "{:?} to {:?}",
+                    source,
+                    target
+                );
+            }
+        }
+
+        // A triangle facing the viewer, wound counter clockwise as seen from
+        // them in a right handed source and clockwise in a left handed one,
+        // where the cross product of its edges points at the viewer too.
+        for source in CONVENTIONS {
+            let conversion = source.conversion_to(TARGET_AXES);
+            let triangle = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+                .map(|(x, up)| Vector3::from(conversion.vector(source_point(source, x, up, 0.0))));
+            let mut indices = if source.is_right_handed() {
+                vec![0, 1, 2]
+            } else {
+                vec![0, 2, 1]
+            };
+            conversion.fix_winding(&mut indices);
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[indices[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize();
+            // After conversion everything is right handed and faces +Z.
+            assert_eq!(normal, Vector3::unit_z(), "{:?}", source);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Converts a source asset tree into what loads fastest: OBJ and glTF models
+//! to model data files and images to KTX2 files with mips, shrunk to a
+//! maximum size, plus a JSON manifest of every output's hash and size.
+//! Prints the sizes before and after, and a breakdown of each converted
+//! model. See [`test2::pipeline`].
+//!
+//! Usage: `asset-pipeline [--force] [--max-dimension N] [--bake-ao]
+//! [--ao-samples N] [--ao-distance D] [--json] <source> <output>`
+//!
+//! `--bake-ao` bakes ambient occlusion into model vertices, with
+//! `--ao-samples` rays per vertex out to `--ao-distance` in the model's
+//! units. Either of those implies `--bake-ao`.
+//!
+//! With `--json`, the model breakdowns are printed as JSON by source path
+//! for other tools, and the rest of the report goes to stderr.
+//!
+//! Write to `res/processed` for the loaders to find the outputs with
+//! `LoadOptions::prefer_processed`.
+#![deny(warnings)]
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use test2::{
+    ao::AoOptions,
+    pipeline::{self, PipelineOptions},
+};
+
+const USAGE: &str = "Usage: asset-pipeline [--force] [--max-dimension N] [--bake-ao] \
+                     [--ao-samples N] [--ao-distance D] [--json] <source> <output>";
+
+struct Args {
+    source: PathBuf,
+    output: PathBuf,
+    options: PipelineOptions,
+    json: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut options = PipelineOptions::default();
+    let mut json = false;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--force" => options.force = true,
+            "--json" => json = true,
+            "--max-dimension" => {
+                let value = args.next().ok_or("--max-dimension needs a value")?;
+                let limit = value
+                    .parse()
+                    .map_err(|_| format!("Not a size: {:?}", value))?;
+                options.max_dimension = Some(limit);
+            }
+            "--bake-ao" => {
+                options.bake_ao.get_or_insert_with(AoOptions::default);
+            }
+            "--ao-samples" => {
+                let value = args.next().ok_or("--ao-samples needs a value")?;
+                let samples = value
+                    .parse()
+                    .map_err(|_| format!("Not a sample count: {:?}", value))?;
+                options
+                    .bake_ao
+                    .get_or_insert_with(AoOptions::default)
+                    .samples = samples;
+            }
+            "--ao-distance" => {
+                let value = args.next().ok_or("--ao-distance needs a value")?;
+                let distance = value
+                    .parse()
+                    .ok()
+                    .filter(|d: &f32| *d > 0.0)
+                    .ok_or_else(|| format!("Not a distance: {:?}", value))?;
+                options
+                    .bake_ao
+                    .get_or_insert_with(AoOptions::default)
+                    .ray_distance = distance;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([source, output]) => Ok(Args {
+            source,
+            output,
+            options,
+            json,
+        }),
+        Err(_) => Err("Expected a source and an output directory".to_string()),
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    env_logger::init();
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return std::process::ExitCode::FAILURE;
+        }
+    };
+    match pipeline::run(&args.source, &args.output, &args.options) {
+        Ok(report) => {
+            if args.json {
+                let models = report
+                    .files
+                    .iter()
+                    .filter_map(|file| {
+                        let source = file.source.to_string_lossy().into_owned();
+                        Some((source, file.model.as_ref()?))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                println!("{}", serde_json::to_string_pretty(&models).unwrap());
+                eprint!("{}", report);
+            } else {
+                print!("{}", report);
+            }
+            if report.failures() > 0 {
+                std::process::ExitCode::FAILURE
+            } else {
+                std::process::ExitCode::SUCCESS
+            }
+        }
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
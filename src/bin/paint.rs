@@ -0,0 +1,209 @@
+//! Paints onto the default model's texture with the mouse, showing partial
+//! texture updates. Painting starts once the model has been read back for
+//! picking, a few frames in. Native only.
+//!
+//! Controls:
+//! - Left drag: paint under the cursor
+//! - WASD / arrows, Space, LShift: move the camera
+//! - Escape: quit
+#![deny(warnings)]
+
+use std::sync::{mpsc, Arc};
+
+use test2::{gpu::ReadbackQueue, texture::Texture, tools::PickMesh, RenderError, State};
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+};
+
+/// Side of the painted texture, in texels.
+const CANVAS_SIZE: u32 = 512;
+/// Side of the square brush, in texels.
+const BRUSH_SIZE: u32 = 8;
+const BRUSH_COLOR: [u8; 4] = [200, 30, 30, 255];
+
+fn main() {
+    pollster::block_on(run());
+}
+
+/// Swaps every material's texture for one blank canvas that can be painted.
+fn attach_canvas(state: &mut State) -> anyhow::Result<()> {
+    let canvas = Texture::writable(
+        state.device(),
+        CANVAS_SIZE,
+        CANVAS_SIZE,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        false,
+        Some("canvas"),
+    )?;
+    let white = vec![255; (CANVAS_SIZE * CANVAS_SIZE * 4) as usize];
+    canvas.write_region(state.queue(), (0, 0), (CANVAS_SIZE, CANVAS_SIZE), &white)?;
+
+    let bind_group = |state: &State| {
+        state
+            .device()
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: state.texture_bind_group_layout(),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&canvas.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&canvas.sampler),
+                    },
+                ],
+                label: Some("canvas"),
+            })
+    };
+    let bind_groups = (0..state.model().materials.len())
+        .map(|_| bind_group(state))
+        .collect::<Vec<_>>();
+    for (material, bind_group) in state.model_mut().materials.iter_mut().zip(bind_groups) {
+        material.bind_group = bind_group;
+    }
+    // Keep the canvas alive with the first material; the rest share its view.
+    if let Some(material) = state.model_mut().materials.first_mut() {
+        material.diffuse_texture = Arc::new(canvas);
+    }
+    Ok(())
+}
+
+/// Paints a brush stroke centered on `uv`, clipped to the canvas.
+fn paint(state: &State, canvas: &Texture, uv: [f32; 2]) -> anyhow::Result<()> {
+    let size = canvas.texture.size();
+    let wrap = |t: f32| t - t.floor();
+    let center = (
+        (wrap(uv[0]) * size.width as f32) as i64,
+        (wrap(uv[1]) * size.height as f32) as i64,
+    );
+    let half = BRUSH_SIZE as i64 / 2;
+    let x0 = (center.0 - half).clamp(0, size.width as i64) as u32;
+    let y0 = (center.1 - half).clamp(0, size.height as i64) as u32;
+    let x1 = (center.0 + half).clamp(0, size.width as i64) as u32;
+    let y1 = (center.1 + half).clamp(0, size.height as i64) as u32;
+    let (width, height) = (x1 - x0, y1 - y0);
+    let data = BRUSH_COLOR.repeat((width * height) as usize);
+    canvas.write_region(state.queue(), (x0, y0), (width, height), &data)
+}
+
+async fn run() {
+    test2::init_logger();
+
+    let event_loop = EventLoop::new();
+    let window = test2::create_window(&event_loop, "paint");
+    let mut state = match State::new(window).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Couldn't set up rendering: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = attach_canvas(&mut state) {
+        log::error!("Couldn't create the canvas: {:?}", e);
+        return;
+    }
+    let mut readbacks = ReadbackQueue::new(1);
+    let (sender, picked) = mpsc::channel();
+    let mut encoder = state
+        .device()
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picking Readback Encoder"),
+        });
+    let recorded = PickMesh::read_model(
+        &mut readbacks,
+        state.device(),
+        &mut encoder,
+        state.model(),
+        move |picking| {
+            let _ = sender.send(picking);
+        },
+    );
+    if let Err(e) = recorded {
+        log::error!("Couldn't read back the model for picking: {}", e);
+        return;
+    }
+    state.queue().submit(Some(encoder.finish()));
+    readbacks.after_submit();
+    let mut picking = None;
+
+    let mut painting = false;
+    let mut cursor = winit::dpi::PhysicalPosition::new(0.0, 0.0);
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::MainEventsCleared => state.window().request_redraw(),
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == state.window().id() && !state.input(event) => match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::MouseInput {
+                    state: button_state,
+                    button: MouseButton::Left,
+                    ..
+                } => painting = *button_state == ElementState::Pressed,
+                WindowEvent::CursorMoved { position, .. } => cursor = *position,
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    state.resize(**new_inner_size);
+                }
+                _ => {}
+            },
+            Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+                readbacks.poll(state.device());
+                match picked.try_recv() {
+                    Ok(Ok(mesh)) => picking = Some(mesh),
+                    Ok(Err(e)) => log::error!("Couldn't read back the model for picking: {}", e),
+                    Err(_) => {}
+                }
+                if let (true, Some(picking)) = (painting, &picking) {
+                    let hit = state
+                        .cursor_ray(cursor)
+                        .and_then(|ray| picking.pick_uv(&ray));
+                    if let (Some((_, uv)), Some(material)) = (hit, state.model().materials.first())
+                    {
+                        if let Err(e) = paint(&state, &material.diffuse_texture, uv) {
+                            log::warn!("Couldn't paint: {:?}", e);
+                        }
+                    }
+                }
+                let rendered = state
+                    .update()
+                    .map_err(RenderError::from)
+                    .and_then(|()| state.render());
+                match rendered {
+                    Ok(_) => {}
+                    Err(RenderError::Surface(
+                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                    )) => state.resize(state.size()),
+                    Err(RenderError::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::Alloc(e)) => {
+                        log::error!("{}", e);
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::Surface(wgpu::SurfaceError::Timeout)) => {
+                        log::warn!("Surface timeout")
+                    }
+                }
+            }
+            _ => {}
+        }
+    });
+}
@@ -0,0 +1,23 @@
+//! Runs the validation scenes on every backend this machine has and prints
+//! the outcome of each. Exits with an error if any scene failed; backends
+//! without an adapter are skipped.
+//!
+//! Usage: `validate`
+#![deny(warnings)]
+
+use test2::{stats::CountingAllocator, validation};
+
+/// Lets the instancing scene check that batching doesn't allocate.
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() -> std::process::ExitCode {
+    env_logger::init();
+    let report = pollster::block_on(validation::run());
+    print!("{}", report);
+    if report.failures() > 0 {
+        std::process::ExitCode::FAILURE
+    } else {
+        std::process::ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,44 @@
+// Draws egui's meshes into the UI target. Vertex colors and textures hold
+// sRGB encoded values with premultiplied alpha, as the target does, so they
+// are multiplied as they are, without decoding.
+
+struct Screen {
+    // In egui's points.
+    size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+
+@group(1) @binding(0)
+var t_ui: texture_2d<f32>;
+@group(1) @binding(1)
+var s_ui: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = in.position / screen.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * textureSample(t_ui, s_ui, in.uv);
+}
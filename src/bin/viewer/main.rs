@@ -0,0 +1,1064 @@
+//! A small model viewer built only on the public API of the crate.
+//!
+//! Usage: `viewer [model.obj]`. Models can also be dropped onto the window.
+//! They are loaded a slice per frame, and files are read by futures the
+//! event loop polls, so the window stays responsive and nothing blocks on
+//! the web. PLY scans, given or dropped, are drawn as points alongside the
+//! model and framed, and measuring picks their points.
+//! Frames much slower than usual are logged with what happened in them.
+//!
+//! The settings panel holds the camera, shading, light, ambient and
+//! measuring settings, which the keys below also change. The stats overlay
+//! shows the frame rate, what the last frame drew and GPU memory.
+//!
+//! Controls:
+//! - F1: toggle the settings panel, F2: toggle the stats overlay
+//! - WASD / arrows, Space, LShift: move the camera
+//! - C: toggle between orbit and fly camera
+//! - L: toggle light gizmos
+//! - N / Backspace: add a light / remove the newest; they light only the
+//!   gizmos until the main shader has direct lighting
+//! - Y: turn the lights around the model
+//! - H: toggle between flat and sky/ground ambient light
+//! - [ / ]: dim / brighten the ambient light
+//! - M: cycle measuring distance, angle and dimensions; click to pick points
+//! - U: switch measurements between meters and centimeters
+//! - G: write the frame's passes as Graphviz next to the executable
+//! - P: save a screenshot next to the executable, where the surface allows
+//! - R: log a breakdown of the model's meshes and materials
+//! - T: toggle a glass sphere in front of the model
+//! - F11: borderless fullscreen, Alt+Enter: exclusive fullscreen
+//! - Escape: quit
+#![deny(warnings)]
+
+mod painter;
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::mpsc,
+    task::{Context, Poll, Waker},
+};
+
+use test2::{
+    bounds::Aabb,
+    compose::PassSlot,
+    cooperative::{self, CooperativeLoad},
+    gpu::{ContextOptions, MemoryStats, ReadbackError, ReadbackQueue},
+    light::{Ambient, LightUniform},
+    resources, stats,
+    tools::{Measure, MeasureMode, MeasureSettings, PickMesh, PickPoints, Units},
+    variant::ShadingTier,
+    window::WindowController,
+    CameraMode, RenderError, State,
+};
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+};
+
+use painter::Painter;
+
+/// How often the frame rate and the title bar are refreshed, in seconds.
+const STATS_INTERVAL: f64 = 0.5;
+
+/// The ambient light H switches to: a blue sky over a brown ground.
+const HEMISPHERE_AMBIENT: Ambient = Ambient::new([0.75, 0.85, 1.0], [0.35, 0.3, 0.25], 1.0);
+
+/// How much [ and ] change the ambient intensity by.
+const AMBIENT_STEP: f32 = 1.25;
+
+/// The colors N gives new lights, in turn.
+const LIGHT_COLORS: [[f32; 3]; 3] = [[1.0, 1.0, 1.0], [1.0, 0.8, 0.6], [0.6, 0.8, 1.0]];
+/// How far Y turns the lights, in degrees.
+const LIGHT_TURN: f32 = 15.0;
+
+/// The model T toggles, and where it's put.
+const GLASS_MODEL: &str = "glass/sphere.obj";
+const GLASS_POSITION: [f32; 3] = [0.0, 2.5, -4.0];
+
+/// How large scan points are drawn.
+const POINT_SIZE: test2::points::PointSize = test2::points::PointSize::Screen(2.0);
+/// How near the cursor a scan point must be, in pixels, to be picked with
+/// snapping turned off.
+const POINT_PICK_RADIUS: f32 = 8.0;
+
+fn main() {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(run());
+        } else {
+            pollster::block_on(run());
+        }
+    }
+}
+
+/// `file_name` in the executable's directory.
+#[cfg(not(target_arch = "wasm32"))]
+fn beside_executable(file_name: &str) -> Option<std::path::PathBuf> {
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(file_name)));
+    if path.is_none() {
+        log::error!("Couldn't find the executable's directory");
+    }
+    path
+}
+
+/// Writes `frame_graph.dot` next to the executable and logs where, along
+/// with the execution order.
+#[cfg(not(target_arch = "wasm32"))]
+fn dump_frame_graph(state: &State) {
+    let graph = state.frame_graph();
+    log::info!("{}", graph.explain());
+    let Some(path) = beside_executable("frame_graph.dot") else {
+        return;
+    };
+    match std::fs::write(&path, graph.dump_dot()) {
+        Ok(()) => log::info!("Wrote the frame graph to {}", path.display()),
+        Err(e) => log::error!("Couldn't write {}: {}", path.display(), e),
+    }
+}
+
+/// Saves `screenshot` next to the executable, named by when it was taken.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(screenshot: Result<image::RgbaImage, test2::gpu::ReadbackError>) {
+    let screenshot = match screenshot {
+        Ok(screenshot) => screenshot,
+        Err(e) => return log::error!("Couldn't take a screenshot: {}", e),
+    };
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs());
+    let Some(path) = beside_executable(&format!("screenshot-{}.png", seconds)) else {
+        return;
+    };
+    match screenshot.save(&path) {
+        Ok(()) => log::info!("Saved a screenshot to {}", path.display()),
+        Err(e) => log::error!("Couldn't write {}: {}", path.display(), e),
+    }
+}
+
+/// The light N adds as the `index`th: the default light's position turned
+/// a quarter further around the model for each light before it.
+fn new_light(index: usize) -> LightUniform {
+    let position = turn_around_y([2.0, 2.0, 2.0], 90.0 * index as f32);
+    LightUniform::new(position, LIGHT_COLORS[index % LIGHT_COLORS.len()])
+}
+
+fn turn_around_y(position: [f32; 3], degrees: f32) -> [f32; 3] {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let [x, y, z] = position;
+    [x * cos + z * sin, y, z * cos - x * sin]
+}
+
+fn toggle_camera_mode(state: &mut State) {
+    let mode = match state.camera_mode() {
+        CameraMode::Orbit => CameraMode::Fly,
+        CameraMode::Fly => CameraMode::Orbit,
+    };
+    state.set_camera_mode(mode);
+}
+
+fn is_hemisphere(ambient: &Ambient) -> bool {
+    ambient.sky_color == HEMISPHERE_AMBIENT.sky_color
+        && ambient.ground_color == HEMISPHERE_AMBIENT.ground_color
+}
+
+/// Switches between flat and sky/ground ambient light, keeping the
+/// intensity.
+fn toggle_hemisphere(state: &mut State) {
+    let current = state.ambient();
+    let mut ambient = if is_hemisphere(&current) {
+        Ambient::default()
+    } else {
+        HEMISPHERE_AMBIENT
+    };
+    ambient.intensity = current.intensity;
+    state.set_ambient(ambient);
+}
+
+fn add_light(state: &mut State) {
+    let lights = state.lights_mut();
+    lights.push(new_light(lights.len()));
+}
+
+fn turn_lights(state: &mut State) {
+    for light in state.lights_mut() {
+        light.position = turn_around_y(light.position, LIGHT_TURN);
+    }
+}
+
+/// Removes the glass sphere, or starts loading it.
+fn toggle_glass(state: &mut State, loads: &mut Loads) {
+    if state.has_glass() {
+        state.clear_glass();
+    } else if !loads.is_loading(Target::Glass) {
+        loads.open_model(GLASS_MODEL, Target::Glass);
+    }
+}
+
+fn set_measure_mode(
+    mode: MeasureMode,
+    measure: &mut Measure,
+    picking: &mut Picking,
+    state: &State,
+) {
+    measure.set_mode(mode);
+    if mode != MeasureMode::Off {
+        picking.request_mesh(state);
+    }
+    if let Some(aabb) = picking.aabb() {
+        measure.measure_bounds(&aabb);
+    }
+}
+
+/// Turns a path given on the command line or dropped onto the window into a
+/// name the resources module can load.
+#[cfg(not(target_arch = "wasm32"))]
+fn resolve_model_path(path: &std::path::Path) -> String {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn is_ply(file_name: &str) -> bool {
+    std::path::Path::new(file_name)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ply"))
+}
+
+/// Uploads a scan opened by [`Loads`] and starts reading its points back
+/// for measuring.
+fn show_points(
+    state: &mut State,
+    picking: &mut Picking,
+    file_name: &str,
+    file: resources::PlyFile,
+) {
+    picking.points = None;
+    let shown = resources::upload_ply(file_name, state.device(), state.queue(), file)
+        .and_then(|cloud| Ok(state.set_point_cloud(cloud, POINT_SIZE)?));
+    if let Err(e) = shown {
+        return log::error!("Couldn't load {}: {:?}", file_name, e);
+    }
+    picking.read_points(state);
+}
+
+/// What a model being loaded is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Model,
+    Glass,
+}
+
+enum Opened {
+    Model(Target, CooperativeLoad),
+    /// Scans are only opened on native.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Points(resources::PlyFile),
+}
+
+type Opening = Pin<Box<dyn Future<Output = anyhow::Result<Opened>>>>;
+
+/// The files being loaded, without blocking the event loop, which would
+/// freeze the page on the web. Files are read by futures polled once a
+/// frame, then models are parsed a slice a frame, one at a time.
+#[derive(Default)]
+struct Loads {
+    /// By file name, and what the model is for.
+    opening: Vec<(String, Option<Target>, Opening)>,
+    loading: VecDeque<(Target, CooperativeLoad)>,
+}
+
+impl Loads {
+    fn open_model(&mut self, file_name: &str, target: Target) {
+        let name = file_name.to_string();
+        let opening = Box::pin(async move {
+            let options = resources::LoadOptions::default();
+            Ok(Opened::Model(
+                target,
+                CooperativeLoad::open(&name, options).await?,
+            ))
+        });
+        self.opening
+            .push((file_name.to_string(), Some(target), opening));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_points(&mut self, file_name: &str) {
+        let name = file_name.to_string();
+        let opening =
+            Box::pin(async move { Ok(Opened::Points(resources::open_ply(&name).await?)) });
+        self.opening.push((file_name.to_string(), None, opening));
+    }
+
+    fn is_loading(&self, target: Target) -> bool {
+        self.opening.iter().any(|(_, t, _)| *t == Some(target))
+            || self.loading.iter().any(|(t, _)| *t == target)
+    }
+
+    /// Polls the files being opened, queueing the models that are, and
+    /// returns the scans that are.
+    fn poll(&mut self) -> Vec<(String, resources::PlyFile)> {
+        // The loop redraws continuously, so whatever a read waits on is
+        // polled again next frame without a wake.
+        let mut context = Context::from_waker(Waker::noop());
+        let loading = &mut self.loading;
+        let mut scans = Vec::new();
+        self.opening.retain_mut(|(file_name, _, opening)| {
+            let Poll::Ready(opened) = opening.as_mut().poll(&mut context) else {
+                return true;
+            };
+            match opened {
+                Ok(Opened::Model(target, load)) => loading.push_back((target, load)),
+                Ok(Opened::Points(file)) => scans.push((file_name.clone(), file)),
+                Err(e) => log::error!("Couldn't load {}: {:?}", file_name, e),
+            }
+            false
+        });
+        scans
+    }
+
+    /// Works on the first model in line for a frame's budget, and hands it
+    /// over once it is ready to upload.
+    fn tick(&mut self) -> Option<(Target, CooperativeLoad)> {
+        let (_, load) = self.loading.front_mut()?;
+        match load.tick_for(cooperative::DEFAULT_BUDGET) {
+            Ok(_) if load.is_ready() => self.loading.pop_front(),
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("Couldn't load {}: {:?}", load.file_name(), e);
+                self.loading.pop_front();
+                None
+            }
+        }
+    }
+
+    /// The model being worked on, and how far along it is.
+    fn progress(&self) -> Option<(&str, f32)> {
+        let (_, load) = self.loading.front()?;
+        Some((load.file_name(), load.progress()))
+    }
+}
+
+enum Picked {
+    /// Of the model read back in `generation`.
+    Mesh {
+        generation: u32,
+        mesh: PickMesh,
+    },
+    Points(PickPoints),
+}
+
+/// What measuring picks points on, read back from the GPU a few frames
+/// after it's asked for.
+struct Picking {
+    readbacks: ReadbackQueue,
+    sender: mpsc::Sender<Picked>,
+    receiver: mpsc::Receiver<Picked>,
+    /// Bumped when the model changes, so reads of the old one are ignored.
+    generation: u32,
+    mesh_requested: bool,
+    mesh: Option<PickMesh>,
+    /// Picked instead of the mesh while there are any.
+    points: Option<PickPoints>,
+}
+
+impl Picking {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            readbacks: ReadbackQueue::new(2),
+            sender,
+            receiver,
+            generation: 0,
+            mesh_requested: false,
+            mesh: None,
+            points: None,
+        }
+    }
+
+    /// Starts reading the model back, unless that's done or underway.
+    fn request_mesh(&mut self, state: &State) {
+        if self.mesh_requested {
+            return;
+        }
+        self.mesh_requested = true;
+        let sender = self.sender.clone();
+        let generation = self.generation;
+        self.record(state, |readbacks, encoder| {
+            PickMesh::read_model(
+                readbacks,
+                state.device(),
+                encoder,
+                state.model(),
+                move |mesh| match mesh {
+                    Ok(mesh) => {
+                        let _ = sender.send(Picked::Mesh { generation, mesh });
+                    }
+                    Err(e) => log::error!("Couldn't read back the model for measuring: {}", e),
+                },
+            )
+        });
+    }
+
+    /// Drops the model read back, for a new one.
+    fn model_changed(&mut self) {
+        self.generation += 1;
+        self.mesh_requested = false;
+        self.mesh = None;
+    }
+
+    /// Starts reading the loaded scan's points back.
+    fn read_points(&mut self, state: &State) {
+        let Some(cloud) = state.point_cloud() else {
+            return;
+        };
+        let sender = self.sender.clone();
+        self.record(state, |readbacks, encoder| {
+            PickPoints::read_cloud(readbacks, state.device(), encoder, cloud, move |points| {
+                match points {
+                    Ok(points) => {
+                        let _ = sender.send(Picked::Points(points));
+                    }
+                    Err(e) => log::error!("Couldn't read back the points for measuring: {}", e),
+                }
+            })
+        });
+    }
+
+    fn record<F>(&mut self, state: &State, record: F)
+    where
+        F: FnOnce(&mut ReadbackQueue, &mut wgpu::CommandEncoder) -> Result<(), ReadbackError>,
+    {
+        let mut encoder = state
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Picking Readback Encoder"),
+            });
+        if let Err(e) = record(&mut self.readbacks, &mut encoder) {
+            return log::error!("Couldn't read back geometry for measuring: {}", e);
+        }
+        state.queue().submit(Some(encoder.finish()));
+        self.readbacks.after_submit();
+    }
+
+    /// Takes the geometry read back since the last call. Returns whether
+    /// any arrived.
+    fn poll(&mut self, device: &wgpu::Device) -> bool {
+        self.readbacks.poll(device);
+        let mut arrived = false;
+        while let Ok(picked) = self.receiver.try_recv() {
+            match picked {
+                Picked::Mesh { generation, mesh } if generation == self.generation => {
+                    self.mesh = Some(mesh);
+                }
+                Picked::Mesh { .. } => continue,
+                Picked::Points(points) => self.points = Some(points),
+            }
+            arrived = true;
+        }
+        arrived
+    }
+
+    /// The bounds of what is picked on.
+    fn aabb(&self) -> Option<Aabb> {
+        match &self.points {
+            Some(points) => points.aabb,
+            None => self.mesh.as_ref()?.aabb,
+        }
+    }
+}
+
+/// The frame rate, averaged over [`STATS_INTERVAL`].
+struct FrameRate {
+    frames: u32,
+    since: f64,
+    fps: f64,
+}
+
+impl FrameRate {
+    fn new() -> Self {
+        Self {
+            frames: 0,
+            since: stats::now(),
+            fps: 0.0,
+        }
+    }
+
+    /// Counts a frame. Returns whether the rate was refreshed.
+    fn count(&mut self) -> bool {
+        self.frames += 1;
+        let elapsed = stats::now() - self.since;
+        if elapsed < STATS_INTERVAL {
+            return false;
+        }
+        self.fps = self.frames as f64 / elapsed;
+        self.frames = 0;
+        self.since = stats::now();
+        true
+    }
+}
+
+/// Which of the egui windows are shown.
+struct Panels {
+    settings: bool,
+    stats: bool,
+}
+
+fn settings_panel(
+    ctx: &egui::Context,
+    state: &mut State,
+    measure: &mut Measure,
+    picking: &mut Picking,
+    loads: &mut Loads,
+) {
+    egui::Window::new("Settings")
+        .default_pos([8.0, 8.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.heading("View");
+            let mut mode = state.camera_mode();
+            ui.horizontal(|ui| {
+                ui.label("Camera");
+                ui.radio_value(&mut mode, CameraMode::Orbit, "Orbit");
+                ui.radio_value(&mut mode, CameraMode::Fly, "Fly");
+            });
+            if mode != state.camera_mode() {
+                state.set_camera_mode(mode);
+            }
+            let mut tier = state.shading_tier();
+            ui.horizontal(|ui| {
+                ui.label("Shading");
+                ui.radio_value(&mut tier, ShadingTier::Full, "Per pixel");
+                ui.radio_value(&mut tier, ShadingTier::Fast, "Per vertex");
+            });
+            if tier != state.shading_tier() {
+                state.set_shading_tier(tier);
+            }
+            let mut glass = state.has_glass();
+            if ui.checkbox(&mut glass, "Glass sphere").changed() {
+                toggle_glass(state, loads);
+            }
+
+            ui.separator();
+            ui.heading("Lights");
+            let mut gizmos = state.light_gizmos_visible();
+            if ui.checkbox(&mut gizmos, "Show gizmos").changed() {
+                state.set_light_gizmos_visible(gizmos);
+            }
+            for (i, light) in state.lights_mut().iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Light {}", i + 1));
+                    ui.color_edit_button_rgb(&mut light.color);
+                });
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add").clicked() {
+                    add_light(state);
+                }
+                if ui.button("Remove").clicked() {
+                    state.lights_mut().pop();
+                }
+                if ui.button("Turn").clicked() {
+                    turn_lights(state);
+                }
+            });
+            let mut ambient = state.ambient();
+            let mut hemisphere = is_hemisphere(&ambient);
+            if ui
+                .checkbox(&mut hemisphere, "Sky and ground ambient")
+                .changed()
+            {
+                toggle_hemisphere(state);
+            } else if ui
+                .add(
+                    egui::Slider::new(&mut ambient.intensity, 0.01..=10.0)
+                        .logarithmic(true)
+                        .text("Ambient"),
+                )
+                .changed()
+            {
+                state.set_ambient(ambient);
+            }
+
+            ui.separator();
+            ui.heading("Measuring");
+            let mut mode = measure.mode();
+            egui::ComboBox::from_label("Mode")
+                .selected_text(format!("{:?}", mode))
+                .show_ui(ui, |ui| {
+                    for option in [
+                        MeasureMode::Off,
+                        MeasureMode::Distance,
+                        MeasureMode::Angle,
+                        MeasureMode::Dimensions,
+                    ] {
+                        ui.selectable_value(&mut mode, option, format!("{:?}", option));
+                    }
+                });
+            if mode != measure.mode() {
+                set_measure_mode(mode, measure, picking, state);
+            }
+            ui.horizontal(|ui| {
+                ui.label("Units");
+                ui.radio_value(&mut measure.settings.units, Units::Meters, "m");
+                ui.radio_value(&mut measure.settings.units, Units::Centimeters, "cm");
+            });
+            let mut snap = measure.settings.snap_radius.is_some();
+            if ui.checkbox(&mut snap, "Snap to vertices").changed() {
+                measure.settings.snap_radius = if snap {
+                    MeasureSettings::default().snap_radius
+                } else {
+                    None
+                };
+            }
+        });
+}
+
+fn stats_overlay(
+    ctx: &egui::Context,
+    state: &State,
+    rate: &FrameRate,
+    model_name: &str,
+    measure: &Measure,
+    loads: &Loads,
+) {
+    egui::Area::new("stats")
+        .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.strong(model_name);
+                ui.label(format!(
+                    "{:.0} fps ({:.2} ms)",
+                    rate.fps,
+                    1000.0 / rate.fps.max(1.0)
+                ));
+                if let Some(p99) = state.timeline().percentile(99.0) {
+                    ui.label(format!("p99 {:.1} ms", 1000.0 * p99));
+                }
+                let model = state.model();
+                let triangles: u32 = model.meshes.iter().map(|m| m.num_elements / 3).sum();
+                ui.label(format!(
+                    "{} meshes, {} triangles",
+                    model.meshes.len(),
+                    triangles
+                ));
+                let frame = state.frame_stats();
+                ui.label(format!(
+                    "{} passes, {} draws, {} triangles drawn",
+                    frame.passes, frame.draw_calls, frame.triangles
+                ));
+                ui.label(format!(
+                    "{:.1} MiB of GPU memory",
+                    MemoryStats::snapshot().total_live() as f64 / (1 << 20) as f64
+                ));
+                ui.label(format!(
+                    "{:?} camera, exposure {:.2}",
+                    state.camera_mode(),
+                    state.exposure()
+                ));
+                if measure.mode() != MeasureMode::Off {
+                    ui.label(measure.status());
+                }
+                if let Some((file_name, progress)) = loads.progress() {
+                    ui.add(egui::ProgressBar::new(progress).text(format!("Loading {}", file_name)));
+                }
+            });
+        });
+}
+
+async fn run() {
+    test2::init_logger();
+
+    let event_loop = EventLoop::new();
+    let window = test2::create_window(&event_loop, "viewer");
+    // Loaded scenes are lit anywhere from dim interiors to daylight.
+    let options = ContextOptions {
+        auto_exposure: Some(Default::default()),
+        ..ContextOptions::from_env()
+    };
+    let mut state = match State::with_options(window, options).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Couldn't set up rendering: {}", e);
+            return;
+        }
+    };
+    let mut window_controller = WindowController::new("viewer");
+    if let Ok(icon) = resources::load_icon("icon.png").await {
+        state.window().set_window_icon(Some(icon));
+    }
+
+    let egui_ctx = egui::Context::default();
+    let mut egui_state = egui_winit::State::new(&event_loop);
+    egui_state.set_pixels_per_point(state.window().scale_factor() as f32);
+    let painter = match Painter::new(state.device(), state.ui_format()) {
+        Ok(painter) => Rc::new(RefCell::new(painter)),
+        Err(e) => {
+            log::error!("Couldn't set up the UI: {}", e);
+            return;
+        }
+    };
+    let ui_painter = painter.clone();
+    state.hooks_mut().add_pass(PassSlot::Ui, move |context| {
+        ui_painter.borrow_mut().paint(context)
+    });
+    let mut panels = Panels {
+        settings: true,
+        stats: true,
+    };
+
+    let mut model_name = State::DEFAULT_MODEL.to_string();
+    let mut picking = Picking::new();
+    let mut loads = Loads::default();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(arg) = std::env::args().nth(1) {
+        let file_name = resolve_model_path(std::path::Path::new(&arg));
+        if is_ply(&file_name) {
+            loads.open_points(&file_name);
+        } else {
+            loads.open_model(&file_name, Target::Model);
+        }
+    }
+
+    let mut measure = Measure::default();
+    let mut cursor = winit::dpi::PhysicalPosition::new(0.0, 0.0);
+    let mut rate = FrameRate::new();
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::MainEventsCleared => state.window().request_redraw(),
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == state.window().id() => {
+                let handled = egui_state.on_event(&egui_ctx, event).consumed
+                    || state.input(event)
+                    || window_controller.process_events(state.window(), event);
+                if handled {
+                    return;
+                }
+                match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F1),
+                                ..
+                            },
+                        ..
+                    } => panels.settings = !panels.settings,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F2),
+                                ..
+                            },
+                        ..
+                    } => panels.stats = !panels.stats,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::C),
+                                ..
+                            },
+                        ..
+                    } => toggle_camera_mode(&mut state),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::L),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let visible = state.light_gizmos_visible();
+                        state.set_light_gizmos_visible(!visible);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::H),
+                                ..
+                            },
+                        ..
+                    } => toggle_hemisphere(&mut state),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } if matches!(key, VirtualKeyCode::LBracket | VirtualKeyCode::RBracket) => {
+                        let mut ambient = state.ambient();
+                        if *key == VirtualKeyCode::RBracket {
+                            ambient.intensity *= AMBIENT_STEP;
+                        } else {
+                            ambient.intensity /= AMBIENT_STEP;
+                        }
+                        state.set_ambient(ambient);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::N),
+                                ..
+                            },
+                        ..
+                    } => add_light(&mut state),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Back),
+                                ..
+                            },
+                        ..
+                    } => {
+                        state.lights_mut().pop();
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Y),
+                                ..
+                            },
+                        ..
+                    } => turn_lights(&mut state),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::M),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let mode = measure.mode().next();
+                        set_measure_mode(mode, &mut measure, &mut picking, &state);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::U),
+                                ..
+                            },
+                        ..
+                    } => {
+                        measure.settings.units = match measure.settings.units {
+                            Units::Meters => Units::Centimeters,
+                            Units::Centimeters => Units::Meters,
+                        };
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::G),
+                                ..
+                            },
+                        ..
+                    } => dump_frame_graph(&state),
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::P),
+                                ..
+                            },
+                        ..
+                    } => state.request_screenshot(save_screenshot),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::R),
+                                ..
+                            },
+                        ..
+                    } => log::info!("{}:\n{}", model_name, state.model().report()),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::T),
+                                ..
+                            },
+                        ..
+                    } => toggle_glass(&mut state, &mut loads),
+                    WindowEvent::CursorMoved { position, .. } => cursor = *position,
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        let hit = if let Some(points) = &picking.points {
+                            points.pick(
+                                &state.view_proj(),
+                                (cursor.x as f32, cursor.y as f32),
+                                (state.size().width, state.size().height),
+                                measure.settings.snap_radius.unwrap_or(POINT_PICK_RADIUS),
+                            )
+                        } else {
+                            picking.mesh.as_ref().and_then(|p| {
+                                let hit = p.pick(&state.cursor_ray(cursor)?)?;
+                                Some(match measure.settings.snap_radius {
+                                    Some(radius) => p.snap(
+                                        hit,
+                                        &state.view_proj(),
+                                        (cursor.x as f32, cursor.y as f32),
+                                        (state.size().width, state.size().height),
+                                        radius,
+                                    ),
+                                    None => hit,
+                                })
+                            })
+                        };
+                        if let Some(hit) = hit {
+                            if let Some(result) = measure.click(hit) {
+                                log::info!("{}", result.label(&measure.settings));
+                            }
+                        }
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::DroppedFile(path) => {
+                        let file_name = resolve_model_path(path);
+                        if is_ply(&file_name) {
+                            loads.open_points(&file_name);
+                        } else {
+                            loads.open_model(&file_name, Target::Model);
+                        }
+                    }
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        state.resize(**new_inner_size);
+                    }
+                    _ => {}
+                }
+            }
+            Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+                for (file_name, file) in loads.poll() {
+                    show_points(&mut state, &mut picking, &file_name, file);
+                }
+                if let Some((target, load)) = loads.tick() {
+                    let file_name = load.file_name().to_string();
+                    let model = load.upload(
+                        state.device(),
+                        state.queue(),
+                        state.texture_bind_group_layout(),
+                        None,
+                    );
+                    match (target, model) {
+                        (Target::Model, Ok(model)) => {
+                            *state.model_mut() = model;
+                            model_name = file_name;
+                            measure.cancel();
+                            picking.model_changed();
+                            if measure.mode() != MeasureMode::Off {
+                                picking.request_mesh(&state);
+                            }
+                        }
+                        (Target::Glass, Ok(model)) => {
+                            if let Err(e) = state.set_glass(model, GLASS_POSITION.into()) {
+                                log::error!("Couldn't load {}: {}", file_name, e);
+                            }
+                        }
+                        (_, Err(e)) => log::error!("Couldn't load {}: {:?}", file_name, e),
+                    }
+                }
+                if picking.poll(state.device()) {
+                    if let Some(aabb) = picking.aabb() {
+                        measure.measure_bounds(&aabb);
+                    }
+                }
+
+                let input = egui_state.take_egui_input(state.window());
+                let output = egui_ctx.run(input, |ctx| {
+                    if panels.settings {
+                        settings_panel(ctx, &mut state, &mut measure, &mut picking, &mut loads);
+                    }
+                    if panels.stats {
+                        stats_overlay(ctx, &state, &rate, &model_name, &measure, &loads);
+                    }
+                });
+                egui_state.handle_platform_output(
+                    state.window(),
+                    &egui_ctx,
+                    output.platform_output,
+                );
+                painter.borrow_mut().set_frame(
+                    egui_ctx.tessellate(output.shapes),
+                    output.textures_delta,
+                    egui_ctx.pixels_per_point(),
+                );
+
+                let rendered = state
+                    .update()
+                    .map_err(RenderError::from)
+                    .and_then(|()| state.render());
+                match rendered {
+                    Ok(_) => {}
+                    Err(RenderError::Surface(
+                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                    )) => state.resize(state.size()),
+                    Err(RenderError::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::Alloc(e)) => {
+                        log::error!("{}", e);
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::Surface(wgpu::SurfaceError::Timeout)) => {
+                        log::warn!("Surface timeout")
+                    }
+                }
+
+                if rate.count() {
+                    let info = match loads.progress() {
+                        Some((file_name, progress)) => format!(
+                            "{} - loading {} ({:.0}%)",
+                            model_name,
+                            file_name,
+                            100.0 * progress
+                        ),
+                        None => model_name.clone(),
+                    };
+                    window_controller.set_title_info(state.window(), &info);
+                }
+            }
+            _ => {}
+        }
+    });
+}
@@ -0,0 +1,442 @@
+//! Paints egui's output as a [`PassSlot::Ui`](test2::compose::PassSlot::Ui)
+//! pass. egui's colors are sRGB encoded with premultiplied alpha, as the
+//! UI target expects, so its textures are uploaded without an sRGB format
+//! and blended as they are.
+
+use std::collections::HashMap;
+
+use test2::{
+    compose::PassContext,
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken},
+    region::Viewport,
+};
+
+/// The screen size in points, then padding, as `egui.wgsl` reads it.
+type ScreenUniform = [f32; 4];
+
+struct Texture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    _memory: MemoryToken,
+}
+
+/// A vertex or index buffer, replaced by a larger one when a frame doesn't
+/// fit.
+struct Buffer {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    buffer: wgpu::Buffer,
+    _memory: MemoryToken,
+}
+
+impl Buffer {
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        size: wgpu::BufferAddress,
+    ) -> Result<Self, AllocError> {
+        let category = if usage.contains(wgpu::BufferUsages::INDEX) {
+            MemoryCategory::Index
+        } else {
+            MemoryCategory::Vertex
+        };
+        let (buffer, memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(wgpu::COPY_BUFFER_ALIGNMENT),
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            category,
+        )?;
+        Ok(Self {
+            label,
+            usage,
+            buffer,
+            _memory: memory,
+        })
+    }
+
+    /// Grows the buffer to the next power of two if `size` doesn't fit.
+    fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        size: wgpu::BufferAddress,
+    ) -> Result<(), AllocError> {
+        if size > self.buffer.size() {
+            *self = Self::new(device, self.label, self.usage, size.next_power_of_two())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Painter {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    _uniform_memory: MemoryToken,
+    texture_layout: wgpu::BindGroupLayout,
+    textures: HashMap<egui::TextureId, Texture>,
+    vertices: Buffer,
+    indices: Buffer,
+    /// What the next [`Self::paint`] draws, in points.
+    primitives: Vec<egui::ClippedPrimitive>,
+    pixels_per_point: f32,
+    /// Texture changes not painted yet. Frames that aren't drawn, e.g.
+    /// while the window is minimized, still hand theirs over.
+    textures_delta: egui::TexturesDelta,
+}
+
+impl Painter {
+    /// For a UI target of `format`, see
+    /// [`State::ui_format`](test2::State::ui_format).
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Self, AllocError> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("egui.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("egui.wgsl").into()),
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("egui_uniform_bind_group_layout"),
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("egui_texture_bind_group_layout"),
+        });
+        let (uniform_buffer, uniform_memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("egui Uniform Buffer"),
+                size: std::mem::size_of::<ScreenUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("egui_uniform_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("egui Pipeline Layout"),
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("egui Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<egui::epaint::Vertex>()
+                        as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                        2 => Unorm8x4,
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Not culled, as egui doesn't keep to one winding.
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            _uniform_memory: uniform_memory,
+            texture_layout,
+            textures: HashMap::new(),
+            vertices: Buffer::new(device, "egui Vertex Buffer", wgpu::BufferUsages::VERTEX, 0)?,
+            indices: Buffer::new(device, "egui Index Buffer", wgpu::BufferUsages::INDEX, 0)?,
+            primitives: Vec::new(),
+            pixels_per_point: 1.0,
+            textures_delta: Default::default(),
+        })
+    }
+
+    /// Hands over what egui produced for a frame, to be drawn by the next
+    /// [`Self::paint`].
+    pub fn set_frame(
+        &mut self,
+        primitives: Vec<egui::ClippedPrimitive>,
+        textures_delta: egui::TexturesDelta,
+        pixels_per_point: f32,
+    ) {
+        self.primitives = primitives;
+        self.textures_delta.append(textures_delta);
+        self.pixels_per_point = pixels_per_point;
+    }
+
+    /// Draws the last frame handed over into `context.color`.
+    pub fn paint(&mut self, context: &mut PassContext) {
+        if let Err(e) = self.try_paint(context) {
+            log::error!("Couldn't draw the UI: {}", e);
+        }
+    }
+
+    fn try_paint(&mut self, context: &mut PassContext) -> Result<(), AllocError> {
+        let delta = std::mem::take(&mut self.textures_delta);
+        for (id, image) in &delta.set {
+            self.set_texture(context.device, context.queue, *id, image)?;
+        }
+
+        let meshes = self
+            .primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                egui::epaint::Primitive::Mesh(mesh) => Some((primitive.clip_rect, mesh)),
+                egui::epaint::Primitive::Callback(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let vertex_size = std::mem::size_of::<egui::epaint::Vertex>() as wgpu::BufferAddress;
+        let index_size = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let (vertex_count, index_count) = meshes.iter().fold((0, 0), |(v, i), (_, mesh)| {
+            (v + mesh.vertices.len(), i + mesh.indices.len())
+        });
+        self.vertices.reserve(
+            context.device,
+            vertex_count as wgpu::BufferAddress * vertex_size,
+        )?;
+        self.indices.reserve(
+            context.device,
+            index_count as wgpu::BufferAddress * index_size,
+        )?;
+
+        let (width, height) = context.size;
+        context.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of::<ScreenUniform>(&[
+                width as f32 / self.pixels_per_point,
+                height as f32 / self.pixels_per_point,
+                0.0,
+                0.0,
+            ]),
+        );
+        // Where each mesh starts in the buffers.
+        let mut ranges = Vec::with_capacity(meshes.len());
+        let (mut vertex_start, mut index_start) = (0, 0);
+        for (_, mesh) in &meshes {
+            context.queue.write_buffer(
+                &self.vertices.buffer,
+                vertex_start as wgpu::BufferAddress * vertex_size,
+                bytemuck::cast_slice(&mesh.vertices),
+            );
+            context.queue.write_buffer(
+                &self.indices.buffer,
+                index_start as wgpu::BufferAddress * index_size,
+                bytemuck::cast_slice(&mesh.indices),
+            );
+            ranges.push((
+                vertex_start,
+                index_start..index_start + mesh.indices.len() as u32,
+            ));
+            vertex_start += mesh.vertices.len() as i32;
+            index_start += mesh.indices.len() as u32;
+        }
+
+        {
+            let mut render_pass = context
+                .encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("egui Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: context.color,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertices.buffer.slice(..));
+            render_pass.set_index_buffer(self.indices.buffer.slice(..), wgpu::IndexFormat::Uint32);
+            for ((clip_rect, mesh), (base_vertex, indices)) in meshes.iter().zip(ranges) {
+                let scissor = Viewport::from_logical(
+                    clip_rect.min.x,
+                    clip_rect.min.y,
+                    clip_rect.width(),
+                    clip_rect.height(),
+                    self.pixels_per_point,
+                )
+                .scissor()
+                .clamped(context.size);
+                let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                    continue;
+                };
+                if scissor.is_empty() || indices.is_empty() {
+                    continue;
+                }
+                render_pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+                render_pass.set_bind_group(1, &texture.bind_group, &[]);
+                render_pass.draw_indexed(indices, base_vertex, 0..1);
+            }
+        }
+
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+        Ok(())
+    }
+
+    /// Creates the texture `id` from `delta`, or updates a part of it.
+    fn set_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: egui::TextureId,
+        delta: &egui::epaint::ImageDelta,
+    ) -> Result<(), AllocError> {
+        let pixels = match &delta.image {
+            egui::ImageData::Color(image) => image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_array())
+                .collect::<Vec<_>>(),
+            egui::ImageData::Font(image) => image
+                .srgba_pixels(None)
+                .flat_map(|color| color.to_array())
+                .collect(),
+        };
+        let [width, height] = delta.image.size().map(|n| n as u32);
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let origin = match delta.pos {
+            Some([x, y]) => wgpu::Origin3d {
+                x: x as u32,
+                y: y as u32,
+                z: 0,
+            },
+            None => {
+                let texture = self.create_texture(device, size, delta.options)?;
+                self.textures.insert(id, texture);
+                wgpu::Origin3d::ZERO
+            }
+        };
+        let Some(texture) = self.textures.get(&id) else {
+            log::warn!("egui updated {:?} before creating it", id);
+            return Ok(());
+        };
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: None,
+            },
+            size,
+        );
+        Ok(())
+    }
+
+    fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        options: egui::TextureOptions,
+    ) -> Result<Texture, AllocError> {
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("egui Texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                // Not sRGB, so sampling keeps the encoded values.
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            MemoryCategory::Texture,
+        )?;
+        let filter = |filter| match filter {
+            egui::TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+            egui::TextureFilter::Linear => wgpu::FilterMode::Linear,
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("egui Sampler"),
+            mag_filter: filter(options.magnification),
+            min_filter: filter(options.minification),
+            ..Default::default()
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("egui_texture_bind_group"),
+        });
+        Ok(Texture {
+            texture,
+            bind_group,
+            _memory: memory,
+        })
+    }
+}
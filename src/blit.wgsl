@@ -0,0 +1,48 @@
+// Downsamples one mip level into the next.
+//
+// Both the source view and the render target use the texture's own format, so
+// for sRGB textures loads decode to linear before filtering and the store
+// encodes back to sRGB.
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle covering the whole target.
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+struct Blit {
+    target_size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> blit: Blit;
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let source_size = vec2<f32>(textureDimensions(t_source));
+
+    // A box filter over the source texels this texel covers. Halving an odd
+    // sized level covers partial texels at the edges, which are weighted by
+    // how much of them is covered, the same as mipmap::generate_cpu.
+    let scale = source_size / blit.target_size;
+    let low = floor(position.xy) * scale;
+    let high = low + scale;
+    let first = vec2<i32>(floor(low));
+    let last = min(vec2<i32>(ceil(high)), vec2<i32>(source_size)) - 1;
+    var color = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var y = first.y; y <= last.y; y += 1) {
+        let wy = min(high.y, f32(y + 1)) - max(low.y, f32(y));
+        for (var x = first.x; x <= last.x; x += 1) {
+            let wx = min(high.x, f32(x + 1)) - max(low.x, f32(x));
+            let weight = max(wx, 0.0) * max(wy, 0.0);
+            color += textureLoad(t_source, vec2<i32>(x, y), 0) * weight;
+            total += weight;
+        }
+    }
+    return color / total;
+}
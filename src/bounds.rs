@@ -0,0 +1,293 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3, Vector4};
+
+/// An axis aligned bounding box.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box containing all `points`, or `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Point3<f32>>,
+    {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, p| aabb.including(p)))
+    }
+
+    pub fn including(&self, p: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        self.including(other.min).including(other.max)
+    }
+
+    /// Grows the box by `amount` in both directions along each axis.
+    pub fn expanded(&self, amount: Vector3<f32>) -> Self {
+        Self {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min + self.size() * 0.5
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn volume(&self) -> f32 {
+        let size = self.size();
+        size.x * size.y * size.z
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.size();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Whether the boxes overlap, including just touching.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// Box containing this one after it has been transformed by `m`.
+    pub fn transformed(&self, m: &Matrix4<f32>) -> Self {
+        use cgmath::Transform;
+        let corners = self.corners();
+        Self::from_points(corners.iter().map(|c| m.transform_point(*c))).unwrap()
+    }
+}
+
+/// The planes of a view projection's visible volume, for culling.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Each plane as `(normal, distance)`, with the normal pointing inwards.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// `view_proj` maps to wgpu clip space, where depth goes from 0 to 1.
+    pub fn from_view_proj(view_proj: &Matrix4<f32>) -> Self {
+        let m = view_proj;
+        let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    /// Whether any of `aabb` may be visible. Boxes near the corners of the
+    /// frustum can pass without being visible, which only costs a draw.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal.
+            let corner = Vector3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// Per joint bounds of a skinned mesh, used to get a conservative box for any
+/// pose without touching the vertices.
+#[derive(Debug, Clone)]
+pub struct SkinBounds {
+    /// Bind pose bounds of the vertices each joint influences. `None` for
+    /// joints that don't move any vertex.
+    pub joint_bounds: Vec<Option<Aabb>>,
+}
+
+impl SkinBounds {
+    pub fn new(
+        positions: &[[f32; 3]],
+        joints: &[[u16; 4]],
+        weights: &[[f32; 4]],
+        joint_count: usize,
+    ) -> Self {
+        let mut joint_bounds: Vec<Option<Aabb>> = vec![None; joint_count];
+        for ((position, joints), weights) in positions.iter().zip(joints).zip(weights) {
+            let p = Point3::from(*position);
+            for (&joint, &weight) in joints.iter().zip(weights) {
+                if weight <= 0.0 {
+                    continue;
+                }
+                let bounds = &mut joint_bounds[joint as usize];
+                *bounds = Some(bounds.map_or(Aabb::new(p, p), |aabb| aabb.including(p)));
+            }
+        }
+        Self { joint_bounds }
+    }
+
+    /// Union of each joint's box transformed by its skinning matrix, grown by
+    /// `padding` first (e.g. for morph target displacement).
+    pub fn current(&self, palette: &[Matrix4<f32>], padding: Vector3<f32>) -> Option<Aabb> {
+        self.joint_bounds
+            .iter()
+            .zip(palette)
+            .filter_map(|(bounds, matrix)| {
+                Some(bounds.as_ref()?.expanded(padding).transformed(matrix))
+            })
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+/// Largest displacement each morph target applies to any vertex.
+#[derive(Debug, Clone)]
+pub struct MorphBounds {
+    pub max_displacements: Vec<Vector3<f32>>,
+}
+
+impl MorphBounds {
+    /// `targets` holds the position deltas of every target.
+    pub fn new(targets: &[Vec<[f32; 3]>]) -> Self {
+        let max_displacements = targets
+            .iter()
+            .map(|deltas| {
+                deltas
+                    .iter()
+                    .fold(Vector3::new(0.0f32, 0.0, 0.0), |max, d| {
+                        Vector3::new(
+                            max.x.max(d[0].abs()),
+                            max.y.max(d[1].abs()),
+                            max.z.max(d[2].abs()),
+                        )
+                    })
+            })
+            .collect();
+        Self { max_displacements }
+    }
+
+    /// How far the weighted targets can move any vertex along each axis.
+    pub fn padding(&self, weights: &[f32]) -> Vector3<f32> {
+        self.max_displacements
+            .iter()
+            .zip(weights)
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, (d, w)| {
+                sum + *d * w.abs()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, SquareMatrix};
+
+    use super::*;
+    use crate::math::projection::{orthographic, DepthRange};
+
+    /// A body on joint 0 and an arm along +x on joint 1, pivoting at the
+    /// origin.
+    fn arm() -> SkinBounds {
+        let positions = [
+            [-0.5, -0.5, -0.5],
+            [0.5, 0.5, 0.5],
+            [1.0, 0.0, 0.0],
+            [3.0, 0.0, 0.0],
+        ];
+        let joints = [[0, 0, 0, 0], [0, 0, 0, 0], [1, 0, 0, 0], [1, 0, 0, 0]];
+        let weights = [[1.0, 0.0, 0.0, 0.0]; 4];
+        SkinBounds::new(&positions, &joints, &weights, 2)
+    }
+
+    #[test]
+    fn raised_arm_grows_bounds() {
+        let rest = arm()
+            .current(&[Matrix4::identity(); 2], Vector3::new(0.0, 0.0, 0.0))
+            .unwrap();
+        assert!(rest.max.y <= 0.5 + 1e-5);
+
+        let raised = [Matrix4::identity(), Matrix4::from_angle_z(Deg(90.0))];
+        let posed = arm().current(&raised, Vector3::new(0.0, 0.0, 0.0)).unwrap();
+        assert!((posed.max.y - 3.0).abs() < 1e-5);
+        assert!(posed.max.x <= 0.5 + 1e-5);
+
+        // A frustum just above the body sees the raised arm only.
+        let above = Frustum::from_view_proj(&orthographic(
+            -1.0,
+            1.0,
+            2.0,
+            4.0,
+            -1.0,
+            1.0,
+            DepthRange::Standard,
+        ));
+        assert!(!above.intersects(&rest));
+        assert!(above.intersects(&posed));
+    }
+
+    #[test]
+    fn unused_joints_have_no_bounds() {
+        let bounds = SkinBounds::new(&[[0.0; 3]], &[[0, 0, 0, 0]], &[[1.0, 0.0, 0.0, 0.0]], 3);
+        assert!(bounds.joint_bounds[0].is_some());
+        assert!(bounds.joint_bounds[1].is_none());
+        assert!(bounds.joint_bounds[2].is_none());
+    }
+
+    #[test]
+    fn morph_padding_is_weighted_max_displacement() {
+        let morph = MorphBounds::new(&[
+            vec![[0.5, -2.0, 0.0], [-1.0, 0.0, 0.25]],
+            vec![[0.0, 0.0, 4.0]],
+        ]);
+        assert_eq!(morph.max_displacements[0], Vector3::new(1.0, 2.0, 0.25));
+        assert_eq!(morph.max_displacements[1], Vector3::new(0.0, 0.0, 4.0));
+        assert_eq!(morph.padding(&[0.0, 0.0]), Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(morph.padding(&[0.5, -0.5]), Vector3::new(0.5, 1.0, 2.125));
+    }
+}
@@ -0,0 +1,206 @@
+//! Assets compiled into the crate, so fallbacks work even with an empty
+//! `res` folder. The resource loaders look here for a file only after the
+//! user's own, so a file of the same name in `res` replaces a builtin.
+//!
+//! Together they add under 10 KB to the binary: under 200 bytes for the
+//! images, about 400 for the font, and the rest for the WGSL modules, most
+//! of which the pipelines using them embed anyway. Build without the
+//! `builtin-font` feature to leave the font out.
+
+/// A magenta and black checker, for textures that are missing or failed
+/// to load.
+pub const MISSING_TEXTURE: &str = "builtin/missing.png";
+/// A 1x1 normal map pointing straight out of the surface.
+pub const FLAT_NORMAL: &str = "builtin/flat_normal.png";
+
+const FILES: &[(&str, &[u8])] = &[
+    (MISSING_TEXTURE, include_bytes!("builtin/missing.png")),
+    (FLAT_NORMAL, include_bytes!("builtin/flat_normal.png")),
+    ("builtin/shader.wgsl", include_bytes!("shader.wgsl")),
+    ("builtin/light.wgsl", include_bytes!("light.wgsl")),
+    ("builtin/sprite.wgsl", include_bytes!("sprite.wgsl")),
+    ("builtin/blit.wgsl", include_bytes!("blit.wgsl")),
+    (
+        "builtin/projection.wgsl",
+        include_bytes!("math/projection.wgsl"),
+    ),
+];
+
+/// The builtin files, by name.
+pub struct BuiltinSource;
+
+impl BuiltinSource {
+    pub fn get(name: &str) -> Option<&'static [u8]> {
+        FILES
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, bytes)| *bytes)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        FILES.iter().map(|(name, _)| *name)
+    }
+}
+
+/// A 3x5 pixel font for debug text, covering digits, capital letters and
+/// common punctuation. Lowercase letters are drawn as capitals. Drawn for
+/// this crate and free to use for any purpose.
+#[cfg(feature = "builtin-font")]
+pub mod font {
+    pub const GLYPH_WIDTH: u32 = 3;
+    pub const GLYPH_HEIGHT: u32 = 5;
+    /// Glyphs per row of [`atlas`].
+    pub const ATLAS_COLUMNS: u32 = 16;
+
+    /// Rows from the top, with the leftmost pixel in the highest of the
+    /// three bits.
+    const GLYPHS: &[(char, [u8; 5])] = &[
+        (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+        ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+        ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+        ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+        ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+        (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+        ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+        (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+        ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+        ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+        ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+        ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+        ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+        ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+        ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+        ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+        ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+        ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+        ('7', [0b111, 0b001, 0b001, 0b010, 0b010]),
+        ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+        ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+        (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+        ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+        ('?', [0b111, 0b001, 0b010, 0b000, 0b010]),
+        ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+        ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+        ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+        ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+        ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+        ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+        ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+        ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+        ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+        ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+        ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+        ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+        ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+        ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+        ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+        ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+        ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+        ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+        ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+        ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+        ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+        ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+        ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+        ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+        ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+        ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+        ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ];
+
+    /// Where `c` is in `GLYPHS`, and so in the atlas.
+    fn index(c: char) -> Option<usize> {
+        let c = c.to_ascii_uppercase();
+        GLYPHS.iter().position(|(glyph, _)| *glyph == c)
+    }
+
+    /// The rows of `c` from the top, with the leftmost pixel in the highest
+    /// of the three bits.
+    pub fn glyph(c: char) -> Option<[u8; 5]> {
+        index(c).map(|i| GLYPHS[i].1)
+    }
+
+    /// The top left pixel of `c` in [`atlas`].
+    pub fn atlas_position(c: char) -> Option<(u32, u32)> {
+        let i = index(c)? as u32;
+        Some((
+            i % ATLAS_COLUMNS * GLYPH_WIDTH,
+            i / ATLAS_COLUMNS * GLYPH_HEIGHT,
+        ))
+    }
+
+    /// Every glyph, white on transparent, [`ATLAS_COLUMNS`] to a row.
+    pub fn atlas() -> image::RgbaImage {
+        let rows = (GLYPHS.len() as u32).div_ceil(ATLAS_COLUMNS);
+        let mut atlas = image::RgbaImage::new(ATLAS_COLUMNS * GLYPH_WIDTH, rows * GLYPH_HEIGHT);
+        for (c, glyph) in GLYPHS {
+            let (x0, y0) = atlas_position(*c).unwrap();
+            for (y, row) in glyph.iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if row & (0b100 >> x) != 0 {
+                        atlas.put_pixel(x0 + x, y0 + y as u32, image::Rgba([255; 4]));
+                    }
+                }
+            }
+        }
+        atlas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources;
+
+    #[test]
+    fn builtin_files_are_found_and_decode() {
+        for name in BuiltinSource::names() {
+            assert!(name.starts_with("builtin/"), "{}", name);
+            assert!(BuiltinSource::get(name).is_some(), "{}", name);
+        }
+        assert!(BuiltinSource::get("builtin/nothing.png").is_none());
+        assert!(BuiltinSource::get("missing.png").is_none());
+
+        for name in [MISSING_TEXTURE, FLAT_NORMAL] {
+            image::load_from_memory(BuiltinSource::get(name).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        }
+        let flat = image::load_from_memory(BuiltinSource::get(FLAT_NORMAL).unwrap()).unwrap();
+        assert_eq!((flat.width(), flat.height()), (1, 1));
+    }
+
+    // One test, since the resource directory is global.
+    #[test]
+    fn user_files_override_builtins_and_builtins_fill_the_gaps() {
+        let builtin_normal = BuiltinSource::get(FLAT_NORMAL).unwrap();
+        let shader =
+            std::str::from_utf8(BuiltinSource::get("builtin/shader.wgsl").unwrap()).unwrap();
+
+        // Nothing in `res` shadows the builtins.
+        let bytes = pollster::block_on(resources::load_binary(FLAT_NORMAL)).unwrap();
+        assert_eq!(bytes, builtin_normal);
+        let text = pollster::block_on(resources::load_string("builtin/shader.wgsl")).unwrap();
+        assert_eq!(text, shader);
+
+        let root = std::env::temp_dir().join("builtin-override-test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("builtin")).unwrap();
+        std::fs::write(root.join(FLAT_NORMAL), b"user normal").unwrap();
+        resources::set_resource_dir(Some(root.clone()));
+        let overridden = pollster::block_on(resources::load_binary(FLAT_NORMAL));
+        let fallback = pollster::block_on(resources::load_binary(MISSING_TEXTURE));
+        let missing = pollster::block_on(resources::load_binary("builtin/nothing.png"));
+        resources::set_resource_dir(None);
+        let _ = std::fs::remove_dir_all(&root);
+
+        // The user's file wins over the builtin of the same name...
+        assert_eq!(overridden.unwrap(), b"user normal");
+        // ...builtins still fill in for files the user doesn't have...
+        assert_eq!(
+            fallback.unwrap(),
+            BuiltinSource::get(MISSING_TEXTURE).unwrap()
+        );
+        // ...and names that aren't builtin still fail.
+        assert!(missing.is_err());
+    }
+}
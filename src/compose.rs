@@ -0,0 +1,164 @@
+use crate::gpu::UniformFrame;
+
+/// Where in a frame an application's pass is recorded, in frame order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PassSlot {
+    /// Before the scene pass, e.g. to draw a background it loads over.
+    BeforeScene,
+    AfterOpaque,
+    /// After the particle pass, which blends over the opaque scene.
+    AfterTransparent,
+    /// Before the sprite pass, e.g. for post effects the UI shouldn't get.
+    BeforeUi,
+    /// Into the UI target after the sprites, in [`crate::State::ui_format`]
+    /// with sRGB encoded, premultiplied colors, e.g. for an immediate mode
+    /// UI. The target is loaded, so these passes draw over the sprites.
+    Ui,
+    AfterUi,
+}
+
+/// What a pass callback records with and into.
+pub struct PassContext<'a, 'f> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    /// In [`crate::State::scene_format`], except in [`PassSlot::Ui`] and
+    /// [`PassSlot::AfterUi`].
+    pub color: &'a wgpu::TextureView,
+    pub depth: &'a wgpu::TextureView,
+    pub size: (u32, u32),
+    /// Uniform memory for per draw data, bound with dynamic offsets. Every
+    /// callback in a frame shares one [`UniformFrame`], and its offsets
+    /// can't outlive the frame.
+    pub uniforms: &'a mut UniformFrame<'f>,
+}
+
+/// How the scene pass starts off its targets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetLoads {
+    pub color: wgpu::LoadOp<wgpu::Color>,
+    pub depth: wgpu::LoadOp<f32>,
+}
+
+impl Default for TargetLoads {
+    fn default() -> Self {
+        Self {
+            color: wgpu::LoadOp::Clear(wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            }),
+            depth: wgpu::LoadOp::Clear(1.0),
+        }
+    }
+}
+
+/// Identifies a callback added with [`FrameHooks::add_pass`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassId(u64);
+
+type PassCallback = Box<dyn FnMut(&mut PassContext)>;
+
+/// Lets applications clear or load the scene's targets and record their own
+/// passes around it, without forking the renderer.
+///
+/// Callbacks are kept across frames, so they own what they use; state that
+/// is also used outside them can be shared with `Rc<RefCell<_>>`.
+#[derive(Default)]
+pub struct FrameHooks {
+    loads: TargetLoads,
+    passes: Vec<(PassId, PassSlot, PassCallback)>,
+    next_id: u64,
+}
+
+impl FrameHooks {
+    pub fn loads(&self) -> TargetLoads {
+        self.loads
+    }
+
+    pub fn set_loads(&mut self, loads: TargetLoads) {
+        self.loads = loads;
+    }
+
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.loads.color = wgpu::LoadOp::Clear(color);
+    }
+
+    /// Records `callback` at `slot` every frame. Callbacks at the same slot
+    /// run in the order they were added.
+    pub fn add_pass<F>(&mut self, slot: PassSlot, callback: F) -> PassId
+    where
+        F: FnMut(&mut PassContext) + 'static,
+    {
+        let id = PassId(self.next_id);
+        self.next_id += 1;
+        self.passes.push((id, slot, Box::new(callback)));
+        id
+    }
+
+    /// Returns whether the callback was there.
+    pub fn remove_pass(&mut self, id: PassId) -> bool {
+        let len = self.passes.len();
+        self.passes.retain(|(i, _, _)| *i != id);
+        self.passes.len() != len
+    }
+
+    /// How many callbacks there are at `slot`.
+    pub fn pass_count(&self, slot: PassSlot) -> usize {
+        self.passes.iter().filter(|(_, s, _)| *s == slot).count()
+    }
+
+    /// Runs the callbacks at `slot`, returning how many there were.
+    pub fn run(&mut self, slot: PassSlot, context: &mut PassContext) -> u32 {
+        let mut count = 0;
+        for (_, _, callback) in self.passes.iter_mut().filter(|(_, s, _)| *s == slot) {
+            callback(context);
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_are_counted_by_slot_and_removed_by_id() {
+        let mut hooks = FrameHooks::default();
+        let first = hooks.add_pass(PassSlot::AfterOpaque, |_| {});
+        let second = hooks.add_pass(PassSlot::AfterOpaque, |_| {});
+        let ui = hooks.add_pass(PassSlot::AfterUi, |_| {});
+        assert!(first != second && second != ui);
+        assert_eq!(hooks.pass_count(PassSlot::AfterOpaque), 2);
+        assert_eq!(hooks.pass_count(PassSlot::AfterUi), 1);
+        assert_eq!(hooks.pass_count(PassSlot::BeforeScene), 0);
+
+        assert!(hooks.remove_pass(first));
+        assert!(!hooks.remove_pass(first), "removed twice");
+        assert_eq!(hooks.pass_count(PassSlot::AfterOpaque), 1);
+        // Ids aren't reused after a removal.
+        let third = hooks.add_pass(PassSlot::AfterOpaque, |_| {});
+        assert!(third != first && third != second && third != ui);
+    }
+
+    #[test]
+    fn the_clear_color_leaves_the_depth_load_alone() {
+        let mut hooks = FrameHooks::default();
+        assert_eq!(hooks.loads(), TargetLoads::default());
+        hooks.set_loads(TargetLoads {
+            color: wgpu::LoadOp::Load,
+            depth: wgpu::LoadOp::Load,
+        });
+        let red = wgpu::Color::RED;
+        hooks.set_clear_color(red);
+        assert_eq!(
+            hooks.loads(),
+            TargetLoads {
+                color: wgpu::LoadOp::Clear(red),
+                depth: wgpu::LoadOp::Load,
+            }
+        );
+    }
+}
@@ -0,0 +1,226 @@
+/// Converts to IEEE half precision bits, rounding to nearest. Values too
+/// large for a half become infinity.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Too small for a normal half, so it becomes subnormal or zero.
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    let half = sign | ((half_exponent as u16) << 10) | (mantissa >> 13) as u16;
+    // A carry out of the mantissa correctly bumps the exponent.
+    half + ((mantissa >> 12) & 1) as u16
+}
+
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    let bits = match exponent {
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            return if sign != 0 { -magnitude } else { magnitude };
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Maps `value` from `min..=max` to the full u16 range.
+pub fn quantize_unorm16(value: f32, min: f32, max: f32) -> u16 {
+    let extent = max - min;
+    if extent <= 0.0 {
+        return 0;
+    }
+    (((value - min) / extent).clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+pub fn dequantize_unorm16(value: u16, min: f32, max: f32) -> f32 {
+    min + value as f32 / 65535.0 * (max - min)
+}
+
+fn snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * 32767.0).round() as i16
+}
+
+fn sign_not_zero(value: f32) -> f32 {
+    if value >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Packs a unit vector into two snorm16s by projecting it onto an octahedron
+/// and unfolding the lower half over the upper one.
+pub fn octahedral_encode(n: [f32; 3]) -> [i16; 2] {
+    let l1 = n[0].abs() + n[1].abs() + n[2].abs();
+    if l1 == 0.0 {
+        return [0, 0];
+    }
+    let (mut x, mut y) = (n[0] / l1, n[1] / l1);
+    if n[2] < 0.0 {
+        let (ox, oy) = (x, y);
+        x = (1.0 - oy.abs()) * sign_not_zero(ox);
+        y = (1.0 - ox.abs()) * sign_not_zero(oy);
+    }
+    [snorm16(x), snorm16(y)]
+}
+
+pub fn octahedral_decode(e: [i16; 2]) -> [f32; 3] {
+    let mut x = (e[0] as f32 / 32767.0).max(-1.0);
+    let mut y = (e[1] as f32 / 32767.0).max(-1.0);
+    let z = 1.0 - x.abs() - y.abs();
+    if z < 0.0 {
+        let (ox, oy) = (x, y);
+        x = (1.0 - oy.abs()) * sign_not_zero(ox);
+        y = (1.0 - ox.abs()) * sign_not_zero(oy);
+    }
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    /// The angle between two unit vectors, precise for small angles where
+    /// `acos` of the dot product isn't.
+    fn angle(a: [f32; 3], b: [f32; 3]) -> f32 {
+        let cross = [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ];
+        dot(cross, cross).sqrt().atan2(dot(a, b))
+    }
+
+    fn normalize(v: [f32; 3]) -> [f32; 3] {
+        let length = dot(v, v).sqrt();
+        [v[0] / length, v[1] / length, v[2] / length]
+    }
+
+    #[test]
+    fn every_half_round_trips() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                assert!(f32_to_f16(value) & 0x7c00 == 0x7c00 && f32_to_f16(value) & 0x3ff != 0);
+            } else {
+                assert_eq!(f32_to_f16(value), half, "{:#06x} became {}", half, value);
+            }
+        }
+    }
+
+    #[test]
+    fn f16_rounds_to_nearest() {
+        // Normal halves have 11 significant bits.
+        for i in 0..10_000 {
+            let value = (i as f32 * 0.731).sin() * 2f32.powi(i % 30 - 14);
+            let back = f16_to_f32(f32_to_f16(value));
+            let ulp = 2f32.powi(value.abs().log2().floor().max(-14.0) as i32 - 10);
+            assert!(
+                (back - value).abs() <= ulp / 2.0,
+                "{} became {}",
+                value,
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn f16_handles_out_of_range_values() {
+        assert_eq!(f16_to_f32(f32_to_f16(65504.0)), 65504.0);
+        assert_eq!(f16_to_f32(f32_to_f16(1e6)), f32::INFINITY);
+        assert_eq!(f16_to_f32(f32_to_f16(-1e6)), f32::NEG_INFINITY);
+        assert_eq!(f16_to_f32(f32_to_f16(f32::INFINITY)), f32::INFINITY);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+        // The smallest subnormal half, and values that flush to zero.
+        assert_eq!(f16_to_f32(f32_to_f16(2f32.powi(-24))), 2f32.powi(-24));
+        assert_eq!(f32_to_f16(1e-10), 0);
+        assert_eq!(f32_to_f16(-1e-10), 0x8000);
+    }
+
+    #[test]
+    fn unorm16_round_trips_within_a_step() {
+        let (min, max) = (-3.0, 5.0);
+        let step = (max - min) / 65535.0;
+        for i in 0..=1000 {
+            let value = min + (max - min) * i as f32 / 1000.0;
+            let back = dequantize_unorm16(quantize_unorm16(value, min, max), min, max);
+            assert!((back - value).abs() <= step / 2.0 + 1e-6);
+        }
+        assert_eq!(quantize_unorm16(-10.0, min, max), 0);
+        assert_eq!(quantize_unorm16(10.0, min, max), u16::MAX);
+        assert_eq!(quantize_unorm16(1.0, 2.0, 2.0), 0);
+    }
+
+    #[test]
+    fn octahedral_round_trips_over_the_sphere() {
+        // A snorm16 step is 3e-5 and the fold can stretch it; 1e-4 leaves
+        // some headroom over the worst angle seen.
+        let max_angle = 1e-4f32;
+        let (rings, segments) = (64, 128);
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..segments {
+                let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+                let n = [
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ];
+                let back = octahedral_decode(octahedral_encode(n));
+                assert!((dot(back, back) - 1.0).abs() < 1e-5);
+                assert!(angle(n, back) < max_angle, "{:?} became {:?}", n, back);
+            }
+        }
+    }
+
+    #[test]
+    fn octahedral_keeps_axes_and_folded_edges() {
+        let axes = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for n in axes {
+            assert_eq!(octahedral_decode(octahedral_encode(n)), n);
+        }
+        // The lower hemisphere folds over the edges of the square, where
+        // the sign of a zero component decides which way it unfolds.
+        for n in [[1.0, 0.0, -1.0], [0.0, -1.0, -1.0], [-1.0, 1.0, -0.01]] {
+            let n = normalize(n);
+            let back = octahedral_decode(octahedral_encode(n));
+            assert!(angle(n, back) < 1e-4, "{:?} became {:?}", n, back);
+        }
+        assert_eq!(octahedral_encode([0.0, 0.0, 0.0]), [0, 0]);
+    }
+}
@@ -0,0 +1,483 @@
+//! Loading OBJ models a slice at a time on the thread that renders, for
+//! platforms without threads such as wasm without cross-origin isolation.
+//!
+//! [`CooperativeLoad::tick`] is called once per frame with a deadline and
+//! parses, builds vertices, then reads the materials until the deadline
+//! passes. Once [`CooperativeLoad::is_ready`], [`CooperativeLoad::finish`]
+//! uploads everything in one go.
+//!
+//! tobj can't parse a slice at a time, so files the [`obj`] fast path
+//! doesn't handle are parsed by a [`obj::Parser::lenient`] one, which skips
+//! what the loader can't use rather than falling back to tobj.
+//!
+//! Material libraries and textures are read in the background, polled by
+//! each tick. On the web that is a fetch, which goes on between frames;
+//! elsewhere a read is done when first polled.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    gpu, model, obj,
+    resources::{
+        self, BuiltMesh, DecodedMaterial, LoadOptions, MaterialUploader, MeshUploader,
+        ResourceBytes, VertexBuilder,
+    },
+    stats, texture,
+};
+
+/// How long a [`CooperativeLoad::tick`] may take by default, in seconds.
+/// Leaves most of a 60 Hz frame for rendering.
+pub const DEFAULT_BUDGET: f64 = 0.004;
+
+/// Lines or face corners handled between checks of the clock by default.
+/// Small enough that a slice takes well under a millisecond.
+pub const DEFAULT_SLICE: usize = 4096;
+
+/// A model's vertices, built but not yet converted or uploaded.
+struct Built {
+    name: String,
+    material: Option<String>,
+    data: model::MeshData,
+    has_uvs: bool,
+}
+
+/// A read being polled by [`CooperativeLoad::tick`].
+type Read<T> = Pin<Box<dyn Future<Output = anyhow::Result<T>>>>;
+
+enum Stage {
+    Parsing(Box<obj::Parser>),
+    Building {
+        models: std::vec::IntoIter<(tobj::Model, Option<String>)>,
+        current: Option<Box<(tobj::Model, Option<String>, VertexBuilder)>>,
+        count: usize,
+        material_libs: Vec<String>,
+        built: Vec<Built>,
+    },
+    /// Reading and parsing the material libraries, one a step.
+    Libraries {
+        libs: std::vec::IntoIter<String>,
+        count: usize,
+        reading: Option<Read<String>>,
+        materials: Vec<tobj::Material>,
+        material_ids: HashMap<String, usize>,
+        built: Vec<Built>,
+    },
+    /// Reading and decoding the materials' textures, one a step.
+    Textures {
+        materials: std::vec::IntoIter<tobj::Material>,
+        count: usize,
+        reading: Option<Read<(tobj::Material, String, Vec<u8>)>>,
+        decoded: Vec<DecodedMaterial>,
+        material_ids: HashMap<String, usize>,
+        built: Vec<Built>,
+    },
+    Ready {
+        decoded: Vec<DecodedMaterial>,
+        material_ids: HashMap<String, usize>,
+        built: Vec<Built>,
+    },
+}
+
+/// An OBJ model being loaded a slice at a time. See the module docs.
+pub struct CooperativeLoad {
+    file_name: String,
+    options: LoadOptions,
+    /// Released once parsing is done.
+    bytes: Option<ResourceBytes>,
+    stage: Stage,
+    /// Lines, face corners or triangles handled between checks of the
+    /// clock.
+    pub slice: usize,
+}
+
+impl CooperativeLoad {
+    /// Reads `file_name` and starts loading it. Reading isn't sliced, but on
+    /// the web it is a fetch, which doesn't block.
+    pub async fn open(file_name: &str, options: LoadOptions) -> anyhow::Result<Self> {
+        let bytes = resources::load_resource(file_name).await?;
+        Ok(Self::new(file_name, bytes, options))
+    }
+
+    pub fn new(file_name: &str, bytes: ResourceBytes, options: LoadOptions) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            options,
+            bytes: Some(bytes),
+            stage: Stage::Parsing(Box::new(obj::Parser::lenient())),
+            slice: DEFAULT_SLICE,
+        }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// Whether there is nothing left for [`CooperativeLoad::tick`] to do.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.stage, Stage::Ready { .. })
+    }
+
+    /// Roughly how far along loading is, from 0 to 1. Parsing counts as the
+    /// first 60%, building vertices as the next 30%, and reading the
+    /// materials as the rest.
+    pub fn progress(&self) -> f32 {
+        const PARSE_SHARE: f32 = 0.6;
+        const BUILD_SHARE: f32 = 0.3;
+        const LIBRARY_SHARE: f32 = 0.03;
+        let materials_start = PARSE_SHARE + BUILD_SHARE;
+        let textures_start = materials_start + LIBRARY_SHARE;
+        let done = |left: usize, count: usize| 1.0 - left as f32 / count.max(1) as f32;
+        match &self.stage {
+            Stage::Parsing(parser) => {
+                let bytes = self.bytes.as_deref().unwrap_or_default();
+                PARSE_SHARE * parser.progress(bytes)
+            }
+            Stage::Building {
+                models,
+                current,
+                count,
+                ..
+            } => {
+                let left = models.len() as f32
+                    + current
+                        .as_deref()
+                        .map_or(0.0, |(m, _, builder)| 1.0 - builder.progress(&m.mesh));
+                let done = 1.0 - left / (*count).max(1) as f32;
+                PARSE_SHARE + BUILD_SHARE * done
+            }
+            Stage::Libraries { libs, count, .. } => {
+                materials_start + LIBRARY_SHARE * done(libs.len(), *count)
+            }
+            Stage::Textures {
+                materials, count, ..
+            } => textures_start + (1.0 - textures_start) * done(materials.len(), *count),
+            Stage::Ready { .. } => 1.0,
+        }
+    }
+
+    /// Works on the load until `deadline`, in [`stats::now`] seconds, and
+    /// returns the progress. At least one slice is done per call, so loading
+    /// moves on even if the frame ran over, unless it is waiting on a read.
+    pub fn tick(&mut self, deadline: f64) -> anyhow::Result<f32> {
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if self.is_ready() || self.step(&mut context)?.is_pending() {
+                break;
+            }
+            if stats::now() >= deadline {
+                break;
+            }
+        }
+        Ok(self.progress())
+    }
+
+    /// [`CooperativeLoad::tick`] for `budget` seconds from now.
+    pub fn tick_for(&mut self, budget: f64) -> anyhow::Result<f32> {
+        self.tick(stats::now() + budget)
+    }
+
+    /// Does a slice of the load, or polls the read it is waiting on with
+    /// `context`.
+    fn step(&mut self, context: &mut Context) -> Poll<anyhow::Result<()>> {
+        let slice = self.slice.max(1);
+        match &mut self.stage {
+            Stage::Parsing(parser) => {
+                let bytes = self.bytes.as_deref().unwrap_or_default();
+                match parser.step(bytes, slice)? {
+                    obj::Step::Pending => {}
+                    obj::Step::Unsupported => unreachable!("lenient parsers don't fall back"),
+                    obj::Step::Done(data) => {
+                        self.bytes = None;
+                        let count = data.models.len();
+                        let models = data
+                            .models
+                            .into_iter()
+                            .zip(data.material_names)
+                            .collect::<Vec<_>>();
+                        self.stage = Stage::Building {
+                            models: models.into_iter(),
+                            current: None,
+                            count,
+                            material_libs: data.material_libs,
+                            built: Vec::with_capacity(count),
+                        };
+                    }
+                }
+            }
+            Stage::Building {
+                models,
+                current,
+                material_libs,
+                built,
+                ..
+            } => {
+                let (model, _, builder) = match current {
+                    Some(current) => &mut **current,
+                    None => match models.next() {
+                        Some((model, material)) => {
+                            let builder = VertexBuilder::new(&model.mesh);
+                            &mut **current.insert(Box::new((model, material, builder)))
+                        }
+                        None => {
+                            let libs = std::mem::take(material_libs);
+                            self.stage = Stage::Libraries {
+                                count: libs.len(),
+                                libs: libs.into_iter(),
+                                reading: None,
+                                materials: Vec::new(),
+                                material_ids: HashMap::new(),
+                                built: std::mem::take(built),
+                            };
+                            return Poll::Ready(Ok(()));
+                        }
+                    },
+                };
+                if builder.step(&model.mesh, slice) {
+                    let (model, material, builder) = *current.take().unwrap();
+                    let (vertices, indices) = builder.finish();
+                    built.push(Built {
+                        name: model.name,
+                        material,
+                        data: model::MeshData { vertices, indices },
+                        has_uvs: !model.mesh.texcoords.is_empty(),
+                    });
+                }
+            }
+            Stage::Libraries {
+                libs,
+                reading,
+                materials,
+                material_ids,
+                built,
+                ..
+            } => {
+                let read = match reading {
+                    Some(read) => read,
+                    None => match libs.next() {
+                        Some(lib) => {
+                            let path = resources::sibling_path(&self.file_name, &lib);
+                            reading.insert(Box::pin(
+                                async move { resources::load_string(&path).await },
+                            ))
+                        }
+                        None => {
+                            let materials = std::mem::take(materials);
+                            self.stage = Stage::Textures {
+                                count: materials.len(),
+                                materials: materials.into_iter(),
+                                reading: None,
+                                decoded: Vec::new(),
+                                material_ids: std::mem::take(material_ids),
+                                built: std::mem::take(built),
+                            };
+                            return Poll::Ready(Ok(()));
+                        }
+                    },
+                };
+                let mat_text = std::task::ready!(read.as_mut().poll(context))?;
+                *reading = None;
+                resources::add_material_lib(materials, material_ids, mat_text)?;
+            }
+            Stage::Textures {
+                materials,
+                reading,
+                decoded,
+                material_ids,
+                built,
+                ..
+            } => {
+                let read = match reading {
+                    Some(read) => read,
+                    None => match materials.next() {
+                        Some(material) => {
+                            let file_name = self.file_name.clone();
+                            reading.insert(Box::pin(async move {
+                                let (path, bytes) =
+                                    resources::read_diffuse(&file_name, &material.diffuse_texture)
+                                        .await?;
+                                Ok((material, path, bytes))
+                            }))
+                        }
+                        None => {
+                            self.stage = Stage::Ready {
+                                decoded: std::mem::take(decoded),
+                                material_ids: std::mem::take(material_ids),
+                                built: std::mem::take(built),
+                            };
+                            return Poll::Ready(Ok(()));
+                        }
+                    },
+                };
+                let (material, diffuse_path, bytes) =
+                    std::task::ready!(read.as_mut().poll(context))?;
+                *reading = None;
+                decoded.push(DecodedMaterial {
+                    diffuse: texture::SourceImage::decode(&bytes)?,
+                    material,
+                    diffuse_path,
+                });
+            }
+            Stage::Ready { .. } => {}
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Uploads the materials and meshes. Call once
+    /// [`CooperativeLoad::is_ready`]; loading is finished here otherwise.
+    pub async fn finish(
+        mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        pool: Option<&mut gpu::GeometryPool>,
+    ) -> anyhow::Result<model::Model> {
+        while !self.is_ready() {
+            std::future::poll_fn(|context| self.step(context)).await?;
+        }
+        self.upload(device, queue, layout, pool)
+    }
+
+    /// Like [`CooperativeLoad::finish`] without waiting, for frame loops
+    /// that tick the load until it [`CooperativeLoad::is_ready`]. Fails if
+    /// it isn't.
+    pub fn upload(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        mut pool: Option<&mut gpu::GeometryPool>,
+    ) -> anyhow::Result<model::Model> {
+        let Stage::Ready {
+            decoded,
+            material_ids,
+            built,
+        } = self.stage
+        else {
+            anyhow::bail!("{} isn't ready to upload", self.file_name);
+        };
+
+        let mut materials = Vec::with_capacity(decoded.len());
+        let mut material_uploader = MaterialUploader::new(device);
+        for decoded in decoded {
+            materials.push(material_uploader.upload(
+                device,
+                queue,
+                layout,
+                decoded,
+                &self.options,
+            )?);
+        }
+        material_uploader.finish(queue);
+        let mut uploader = MeshUploader::new(device, &self.file_name, &self.options);
+        for b in built {
+            let material = b
+                .material
+                .and_then(|name| material_ids.get(&name).copied())
+                .unwrap_or(0);
+            let mesh = BuiltMesh::new(b.name, material, b.data, b.has_uvs, &self.options);
+            uploader.upload(device, queue, pool.as_deref_mut(), mesh)?;
+        }
+
+        Ok(model::Model {
+            meshes: uploader.finish(),
+            materials,
+            vertex_precision: self.options.vertex_precision,
+            unit_scale: self.options.unit_scale(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_OBJECTS: &str = "\
+mtllib a.mtl
+o First
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+o Second
+v 0 0 1
+v 1 0 1
+v 1 1 1
+usemtl blue
+f 5 6 7
+f 7 6 5
+";
+
+    /// Steps `load` until its vertices are built, returning how many steps
+    /// it took. The material library isn't there to read.
+    fn run(load: &mut CooperativeLoad) -> usize {
+        let mut context = Context::from_waker(Waker::noop());
+        let mut steps = 0;
+        while matches!(load.stage, Stage::Parsing(_) | Stage::Building { .. }) {
+            let before = load.progress();
+            assert!(load.step(&mut context).is_ready());
+            assert!(load.progress() >= before, "progress went backwards");
+            steps += 1;
+        }
+        steps
+    }
+
+    fn load(slice: usize) -> (CooperativeLoad, usize) {
+        let bytes = ResourceBytes::Owned(TWO_OBJECTS.as_bytes().to_vec());
+        let mut load = CooperativeLoad::new("two.obj", bytes, LoadOptions::default());
+        load.slice = slice;
+        let steps = run(&mut load);
+        (load, steps)
+    }
+
+    #[test]
+    fn slicing_gives_the_same_result_as_a_one_shot_parse() {
+        let (whole, whole_steps) = load(usize::MAX);
+        let (sliced, sliced_steps) = load(1);
+        assert!(sliced_steps > whole_steps);
+
+        let (
+            Stage::Libraries {
+                libs: whole_libs,
+                built: whole,
+                ..
+            },
+            Stage::Libraries {
+                libs: sliced_libs,
+                built: sliced,
+                ..
+            },
+        ) = (whole.stage, sliced.stage)
+        else {
+            panic!("both loads should be reading the material library");
+        };
+        assert_eq!(whole_libs.as_slice(), ["a.mtl"]);
+        assert_eq!(sliced_libs.as_slice(), whole_libs.as_slice());
+        assert_eq!(whole.len(), 2);
+        assert_eq!(sliced.len(), whole.len());
+        for (sliced, whole) in sliced.iter().zip(&whole) {
+            assert_eq!(sliced.name, whole.name);
+            assert_eq!(sliced.material, whole.material);
+            assert_eq!(sliced.has_uvs, whole.has_uvs);
+            assert_eq!(sliced.data.indices, whole.data.indices);
+            assert_eq!(
+                bytemuck::cast_slice::<_, u8>(&sliced.data.vertices),
+                bytemuck::cast_slice::<_, u8>(&whole.data.vertices)
+            );
+        }
+        assert_eq!(whole[0].data.indices.len(), 6);
+        assert_eq!(whole[1].data.indices.len(), 6);
+        assert!(whole[0].has_uvs && !whole[1].has_uvs);
+    }
+}
@@ -0,0 +1,843 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::Context;
+use gltf::json::{self, validation::Checked::Valid};
+
+use crate::{
+    bounds::Aabb,
+    gpu::{
+        ReadbackBatch, ReadbackData, ReadbackError, ReadbackFuture, ReadbackPolicy, ReadbackQueue,
+        ReadbackResult,
+    },
+    light::LightUniform,
+    model::{self, CompressedVertex, MeshGeometry, Model, ModelVertex, VertexPrecision},
+    variant::MaterialFeatures,
+};
+
+pub struct ExportMesh {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+}
+
+pub struct ExportMaterial {
+    pub name: String,
+    pub base_color: [f32; 4],
+    /// Written to the file as a PNG.
+    pub base_color_texture: Option<image::RgbaImage>,
+}
+
+/// A point light, written with `KHR_lights_punctual`.
+pub struct ExportLight {
+    pub name: String,
+    pub color: [f32; 3],
+    /// In candela, as the extension specifies.
+    pub intensity: f32,
+}
+
+pub struct ExportNode {
+    pub name: String,
+    pub mesh: Option<usize>,
+    pub light: Option<usize>,
+    pub translation: [f32; 3],
+    /// A quaternion as x, y, z, w.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    pub children: Vec<usize>,
+}
+
+impl ExportNode {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mesh: None,
+            light: None,
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+            children: Vec::new(),
+        }
+    }
+}
+
+/// CPU copies of everything to export. Indices into the vectors link the
+/// parts together, as in glTF itself.
+#[derive(Default)]
+pub struct ExportScene {
+    pub meshes: Vec<ExportMesh>,
+    pub materials: Vec<ExportMaterial>,
+    pub lights: Vec<ExportLight>,
+    pub nodes: Vec<ExportNode>,
+    /// The nodes at the top of the hierarchy.
+    pub roots: Vec<usize>,
+}
+
+impl ExportScene {
+    /// Reads a loaded model back from the GPU, since the loaders don't keep
+    /// CPU copies, recording the copies into `encoder`. Each mesh becomes a
+    /// root node. `on_read` gets the scene once `readbacks` has delivered
+    /// every copy. An `Err` means not all copies were recorded, and
+    /// `on_read` won't be called.
+    pub fn read_model(
+        readbacks: &mut ReadbackQueue,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        on_read: impl FnOnce(anyhow::Result<Self>) + Send + 'static,
+    ) -> Result<(), ReadbackError> {
+        let batch = ReadbackBatch::default();
+        let mut materials = Vec::new();
+        for material in &model.materials {
+            if material.features != MaterialFeatures::DIFFUSE_MAP {
+                log::warn!(
+                    "Dropping shader features {:?} of material {:?}, glTF can't represent them",
+                    material.features,
+                    material.name
+                );
+            }
+            let texture = &material.diffuse_texture.texture;
+            let read = read_rgba_texture(readbacks, &batch, device, encoder, texture)?;
+            materials.push((material.name.clone(), read));
+        }
+        let mut meshes = Vec::new();
+        for mesh in &model.meshes {
+            let readback = MeshReadback::record(
+                readbacks,
+                &batch,
+                device,
+                encoder,
+                mesh,
+                model.vertex_precision,
+            )?;
+            meshes.push((mesh.name.clone(), mesh.material, readback));
+        }
+
+        batch.finish(move |results| {
+            let mut results = results.into_iter();
+            let mut scene = Self::default();
+            let read = || -> anyhow::Result<()> {
+                for (name, read) in materials {
+                    let texture = if read {
+                        let data = results
+                            .next()
+                            .expect("a texture readback")
+                            .with_context(|| format!("Couldn't read back {:?}", name))?;
+                        data.into_rgba_image()
+                    } else {
+                        None
+                    };
+                    scene.materials.push(ExportMaterial {
+                        name,
+                        base_color: [1.0; 4],
+                        base_color_texture: texture,
+                    });
+                }
+                for (name, material, readback) in meshes {
+                    let (vertices, indices) = readback
+                        .decode(&mut results)
+                        .with_context(|| format!("Couldn't read back {:?}", name))?;
+                    let mut node = ExportNode::new(&name);
+                    node.mesh = Some(scene.meshes.len());
+                    scene.roots.push(scene.nodes.len());
+                    scene.nodes.push(node);
+                    scene.meshes.push(ExportMesh {
+                        name,
+                        positions: vertices.iter().map(|v| v.position).collect(),
+                        normals: vertices.iter().map(|v| v.normal).collect(),
+                        tex_coords: vertices.iter().map(|v| v.tex_coords).collect(),
+                        indices,
+                        material: (material < scene.materials.len()).then_some(material),
+                    });
+                }
+                Ok(())
+            };
+            on_read(read().map(|()| scene));
+        });
+        Ok(())
+    }
+
+    /// Adds a root node with a point light for each of `lights`.
+    pub fn add_lights(&mut self, lights: &[LightUniform], intensity: f32) {
+        for (i, light) in lights.iter().enumerate() {
+            let name = format!("Light {}", i);
+            let mut node = ExportNode::new(&name);
+            node.translation = light.position;
+            node.light = Some(self.lights.len());
+            self.lights.push(ExportLight {
+                name,
+                color: light.color,
+                intensity,
+            });
+            self.roots.push(self.nodes.len());
+            self.nodes.push(node);
+        }
+    }
+}
+
+pub(crate) fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+) -> anyhow::Result<Vec<u8>> {
+    let data = read_now(device, queue, |readbacks, encoder, callback| {
+        readbacks.read_buffer(
+            device,
+            encoder,
+            buffer,
+            offset..offset + size,
+            ReadbackPolicy::Guaranteed,
+            callback,
+        )
+    })?;
+    Ok(data.bytes)
+}
+
+/// Copies `extent` texels of one layer of `source` back, tightly packed.
+/// This blocks, so it only works on native.
+pub(crate) fn read_texture_region(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    source: wgpu::ImageCopyTexture,
+    extent: (u32, u32),
+) -> anyhow::Result<ReadbackData> {
+    read_now(device, queue, |readbacks, encoder, callback| {
+        readbacks.read_texture(
+            device,
+            encoder,
+            source,
+            extent,
+            ReadbackPolicy::Guaranteed,
+            callback,
+        )
+    })
+}
+
+/// Records one readback with `record`, submits it and waits for it.
+fn read_now(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    record: impl FnOnce(
+        &mut ReadbackQueue,
+        &mut wgpu::CommandEncoder,
+        Box<dyn FnOnce(ReadbackResult) + Send>,
+    ) -> Result<(), ReadbackError>,
+) -> anyhow::Result<ReadbackData> {
+    let mut readbacks = ReadbackQueue::new(1);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Export Encoder"),
+    });
+    let data = ReadbackFuture::default();
+    record(&mut readbacks, &mut encoder, Box::new(data.callback()))?;
+    queue.submit(std::iter::once(encoder.finish()));
+    readbacks.after_submit();
+    readbacks.flush(device);
+    Ok(data
+        .try_take()
+        .ok_or_else(|| anyhow::anyhow!("The readback didn't arrive"))??)
+}
+
+/// Reads back the top mip of an `Rgba8Unorm` or `Rgba8UnormSrgb` texture.
+/// Other formats are skipped with a warning.
+pub(crate) fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<Option<image::RgbaImage>> {
+    read_texture_level(device, queue, texture, 0)
+}
+
+/// [`read_texture`] for mip level `mip_level`.
+pub(crate) fn read_texture_level(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+) -> anyhow::Result<Option<image::RgbaImage>> {
+    if !is_rgba8(texture) {
+        return Ok(None);
+    }
+    let size = texture
+        .size()
+        .mip_level_size(mip_level, texture.dimension());
+    let source = wgpu::ImageCopyTexture {
+        aspect: wgpu::TextureAspect::All,
+        texture,
+        mip_level,
+        origin: wgpu::Origin3d::ZERO,
+    };
+    Ok(read_texture_region(device, queue, source, (size.width, size.height))?.into_rgba_image())
+}
+
+/// Whether `texture` is `Rgba8Unorm` or `Rgba8UnormSrgb`, warning if not.
+fn is_rgba8(texture: &wgpu::Texture) -> bool {
+    let rgba8 = matches!(
+        texture.format(),
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb
+    );
+    if !rgba8 {
+        log::warn!("Can't export {:?} textures", texture.format());
+    }
+    rgba8
+}
+
+/// What decoding a mesh's readbacks needs of the mesh.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MeshReadback {
+    num_elements: u32,
+    aabb: Aabb,
+    precision: VertexPrecision,
+}
+
+impl MeshReadback {
+    /// Records copies of the mesh's vertices and then its indices into
+    /// `encoder`, with callbacks from `batch`.
+    pub(crate) fn record(
+        readbacks: &mut ReadbackQueue,
+        batch: &ReadbackBatch,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        mesh: &model::Mesh,
+        precision: VertexPrecision,
+    ) -> Result<Self, ReadbackError> {
+        let stride = match precision {
+            VertexPrecision::Full => std::mem::size_of::<ModelVertex>(),
+            VertexPrecision::Compressed => std::mem::size_of::<CompressedVertex>(),
+        } as wgpu::BufferAddress;
+        let (vertices, vertex_range, indices, index_range) = match &mesh.geometry {
+            MeshGeometry::Buffers {
+                vertex_buffer,
+                index_buffer,
+            } => (
+                vertex_buffer,
+                0..vertex_buffer.size(),
+                index_buffer,
+                0..mesh.num_elements as u64 * 4,
+            ),
+            MeshGeometry::Pooled(allocation) => (
+                &*allocation.vertex_buffer,
+                allocation.vertex_range.start as u64 * stride
+                    ..allocation.vertex_range.end as u64 * stride,
+                &*allocation.index_buffer,
+                allocation.index_range.start as u64 * 4..allocation.index_range.end as u64 * 4,
+            ),
+        };
+        let policy = ReadbackPolicy::Guaranteed;
+        readbacks.read_buffer(
+            device,
+            encoder,
+            vertices,
+            vertex_range,
+            policy,
+            batch.callback(),
+        )?;
+        readbacks.read_buffer(
+            device,
+            encoder,
+            indices,
+            index_range,
+            policy,
+            batch.callback(),
+        )?;
+        Ok(Self {
+            num_elements: mesh.num_elements,
+            aabb: mesh.aabb,
+            precision,
+        })
+    }
+
+    /// The vertices and indices from the two results [`Self::record`] asked
+    /// for, taken from the front of `results`.
+    pub(crate) fn decode(
+        &self,
+        results: &mut impl Iterator<Item = ReadbackResult>,
+    ) -> Result<(Vec<ModelVertex>, Vec<u32>), ReadbackError> {
+        let vertex_bytes = results.next().expect("a vertex readback")?.bytes;
+        let index_bytes = results.next().expect("an index readback")?.bytes;
+        let mut indices = bytemuck::pod_collect_to_vec::<u8, u32>(&index_bytes);
+        // Pooled indices are relative to the allocation already; base_vertex
+        // is applied at draw time.
+        indices.truncate(self.num_elements as usize);
+        let vertices = match self.precision {
+            VertexPrecision::Full => bytemuck::pod_collect_to_vec::<u8, ModelVertex>(&vertex_bytes),
+            VertexPrecision::Compressed => {
+                bytemuck::pod_collect_to_vec::<u8, CompressedVertex>(&vertex_bytes)
+                    .iter()
+                    .map(|v| v.decode(&self.aabb))
+                    .collect()
+            }
+        };
+        Ok((vertices, indices))
+    }
+}
+
+/// Records a copy of the top mip of an `Rgba8Unorm` or `Rgba8UnormSrgb`
+/// texture into `encoder`, with a callback from `batch`. Other formats
+/// are skipped with a warning, which returns `false`.
+pub(crate) fn read_rgba_texture(
+    readbacks: &mut ReadbackQueue,
+    batch: &ReadbackBatch,
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+) -> Result<bool, ReadbackError> {
+    if !is_rgba8(texture) {
+        return Ok(false);
+    }
+    readbacks.read_texture(
+        device,
+        encoder,
+        texture.as_image_copy(),
+        (texture.width(), texture.height()),
+        ReadbackPolicy::Guaranteed,
+        batch.callback(),
+    )?;
+    Ok(true)
+}
+
+/// Packs buffer views into the GLB binary chunk.
+#[derive(Default)]
+struct BinBuilder {
+    data: Vec<u8>,
+    views: Vec<json::buffer::View>,
+    accessors: Vec<json::Accessor>,
+}
+
+impl BinBuilder {
+    fn push_view(&mut self, bytes: &[u8], target: Option<json::buffer::Target>) -> u32 {
+        // Accessors need their data aligned to the component size.
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(bytes);
+        self.views.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: bytes.len() as u32,
+            byte_offset: Some(offset),
+            byte_stride: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: target.map(Valid),
+        });
+        self.views.len() as u32 - 1
+    }
+
+    fn push_accessor<T: bytemuck::Pod>(
+        &mut self,
+        items: &[T],
+        component_type: json::accessor::ComponentType,
+        type_: json::accessor::Type,
+        target: json::buffer::Target,
+        bounds: Option<(json::Value, json::Value)>,
+    ) -> json::Index<json::Accessor> {
+        let view = self.push_view(bytemuck::cast_slice(items), Some(target));
+        let (min, max) = match bounds {
+            Some((min, max)) => (Some(min), Some(max)),
+            None => (None, None),
+        };
+        self.accessors.push(json::Accessor {
+            buffer_view: Some(json::Index::new(view)),
+            byte_offset: 0,
+            count: items.len() as u32,
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            extensions: Default::default(),
+            extras: Default::default(),
+            type_: Valid(type_),
+            min,
+            max,
+            name: None,
+            normalized: false,
+            sparse: None,
+        });
+        json::Index::new(self.accessors.len() as u32 - 1)
+    }
+}
+
+/// Writes `scene` to a binary glTF file at `path`.
+pub fn to_gltf<P: AsRef<Path>>(scene: &ExportScene, path: P) -> anyhow::Result<()> {
+    use json::accessor::{ComponentType, Type};
+    use json::buffer::Target;
+
+    let mut bin = BinBuilder::default();
+
+    let mut images = Vec::new();
+    let mut textures = Vec::new();
+    let mut materials = Vec::new();
+    for material in &scene.materials {
+        let base_color_texture = match &material.base_color_texture {
+            Some(texture) => {
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgba8(texture.clone())
+                    .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+                let view = bin.push_view(&png, None);
+                images.push(json::Image {
+                    buffer_view: Some(json::Index::new(view)),
+                    mime_type: Some(json::image::MimeType("image/png".to_string())),
+                    name: Some(material.name.clone()),
+                    uri: None,
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                });
+                textures.push(json::Texture {
+                    name: Some(material.name.clone()),
+                    sampler: None,
+                    source: json::Index::new(images.len() as u32 - 1),
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                });
+                Some(json::texture::Info {
+                    index: json::Index::new(textures.len() as u32 - 1),
+                    tex_coord: 0,
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                })
+            }
+            None => None,
+        };
+        materials.push(json::Material {
+            name: Some(material.name.clone()),
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_factor: json::material::PbrBaseColorFactor(material.base_color),
+                base_color_texture,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    let mut meshes = Vec::new();
+    for mesh in &scene.meshes {
+        let bounds = Aabb::from_points(mesh.positions.iter().map(|&p| p.into())).map(|aabb| {
+            let min: [f32; 3] = aabb.min.into();
+            let max: [f32; 3] = aabb.max.into();
+            (
+                json::Value::from(min.to_vec()),
+                json::Value::from(max.to_vec()),
+            )
+        });
+        let mut attributes = BTreeMap::new();
+        let positions = bin.push_accessor(
+            &mesh.positions,
+            ComponentType::F32,
+            Type::Vec3,
+            Target::ArrayBuffer,
+            bounds,
+        );
+        attributes.insert(Valid(json::mesh::Semantic::Positions), positions);
+        if !mesh.normals.is_empty() {
+            let normals = bin.push_accessor(
+                &mesh.normals,
+                ComponentType::F32,
+                Type::Vec3,
+                Target::ArrayBuffer,
+                None,
+            );
+            attributes.insert(Valid(json::mesh::Semantic::Normals), normals);
+        }
+        if !mesh.tex_coords.is_empty() {
+            let tex_coords = bin.push_accessor(
+                &mesh.tex_coords,
+                ComponentType::F32,
+                Type::Vec2,
+                Target::ArrayBuffer,
+                None,
+            );
+            attributes.insert(Valid(json::mesh::Semantic::TexCoords(0)), tex_coords);
+        }
+        let indices = bin.push_accessor(
+            &mesh.indices,
+            ComponentType::U32,
+            Type::Scalar,
+            Target::ElementArrayBuffer,
+            None,
+        );
+        meshes.push(json::Mesh {
+            name: Some(mesh.name.clone()),
+            primitives: vec![json::mesh::Primitive {
+                attributes,
+                extensions: Default::default(),
+                extras: Default::default(),
+                indices: Some(indices),
+                material: mesh.material.map(|m| json::Index::new(m as u32)),
+                mode: Valid(json::mesh::Mode::Triangles),
+                targets: None,
+            }],
+            weights: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+        });
+    }
+
+    let nodes = scene
+        .nodes
+        .iter()
+        .map(|node| json::Node {
+            name: Some(node.name.clone()),
+            mesh: node.mesh.map(|m| json::Index::new(m as u32)),
+            translation: Some(node.translation),
+            rotation: Some(json::scene::UnitQuaternion(node.rotation)),
+            scale: Some(node.scale),
+            children: (!node.children.is_empty()).then(|| {
+                node.children
+                    .iter()
+                    .map(|&c| json::Index::new(c as u32))
+                    .collect()
+            }),
+            extensions: node.light.map(|light| json::extensions::scene::Node {
+                khr_lights_punctual: Some(
+                    json::extensions::scene::khr_lights_punctual::KhrLightsPunctual {
+                        light: json::Index::new(light as u32),
+                    },
+                ),
+            }),
+            camera: None,
+            matrix: None,
+            skin: None,
+            weights: None,
+            extras: Default::default(),
+        })
+        .collect();
+
+    let lights = scene
+        .lights
+        .iter()
+        .map(
+            |light| json::extensions::scene::khr_lights_punctual::Light {
+                name: Some(light.name.clone()),
+                color: light.color,
+                intensity: light.intensity,
+                range: None,
+                spot: None,
+                type_: Valid(json::extensions::scene::khr_lights_punctual::Type::Point),
+                extensions: None,
+                extras: Default::default(),
+            },
+        )
+        .collect::<Vec<_>>();
+    let mut extensions_used = Vec::new();
+    let extensions = if lights.is_empty() {
+        None
+    } else {
+        extensions_used.push("KHR_lights_punctual".to_string());
+        Some(json::extensions::root::Root {
+            khr_lights_punctual: Some(json::extensions::root::KhrLightsPunctual { lights }),
+        })
+    };
+
+    while bin.data.len() % 4 != 0 {
+        bin.data.push(0);
+    }
+    let root = json::Root {
+        accessors: bin.accessors,
+        buffers: vec![json::Buffer {
+            byte_length: bin.data.len() as u32,
+            name: None,
+            uri: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+        }],
+        buffer_views: bin.views,
+        images,
+        materials,
+        meshes,
+        nodes,
+        scenes: vec![json::Scene {
+            name: None,
+            nodes: scene
+                .roots
+                .iter()
+                .map(|&n| json::Index::new(n as u32))
+                .collect(),
+            extensions: Default::default(),
+            extras: Default::default(),
+        }],
+        scene: Some(json::Index::new(0)),
+        textures,
+        extensions,
+        extensions_used,
+        ..Default::default()
+    };
+
+    let mut json = json::serialize::to_vec(&root)?;
+    // Chunks are padded to 4 bytes, with spaces for JSON.
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            // The header and two chunk headers are 12 and 8 bytes each.
+            length: (12 + 8 + json.len() + 8 + bin.data.len()) as u32,
+        },
+        json: Cow::Owned(json),
+        bin: Some(Cow::Owned(bin.data)),
+    };
+    let path = path.as_ref();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Couldn't create {}", path.display()))?;
+    glb.to_writer(file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A textured quad under a parent node, with a light beside it.
+    fn scene() -> ExportScene {
+        let texture = image::RgbaImage::from_fn(4, 2, |x, y| {
+            image::Rgba([x as u8 * 60, y as u8 * 200, 7, 255])
+        });
+        let mut parent = ExportNode::new("parent");
+        parent.translation = [1.0, 2.0, 3.0];
+        parent.children = vec![1, 2];
+        let mut quad = ExportNode::new("quad");
+        quad.mesh = Some(0);
+        quad.scale = [2.0; 3];
+        let mut lamp = ExportNode::new("lamp");
+        lamp.light = Some(0);
+        lamp.rotation = [0.0, 0.0, 1.0, 0.0];
+        ExportScene {
+            meshes: vec![ExportMesh {
+                name: "quad".to_string(),
+                positions: vec![
+                    [-1.0, 0.0, -2.0],
+                    [1.0, 0.0, -2.0],
+                    [1.0, 3.0, 2.0],
+                    [-1.0, 3.0, 2.0],
+                ],
+                normals: vec![[0.0, 0.0, 1.0]; 4],
+                tex_coords: vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+                indices: vec![0, 1, 2, 0, 2, 3],
+                material: Some(0),
+            }],
+            materials: vec![ExportMaterial {
+                name: "painted".to_string(),
+                base_color: [0.5, 0.25, 1.0, 1.0],
+                base_color_texture: Some(texture),
+            }],
+            lights: vec![ExportLight {
+                name: "lamp".to_string(),
+                color: [1.0, 0.5, 0.0],
+                intensity: 40.0,
+            }],
+            nodes: vec![parent, quad, lamp],
+            roots: vec![0],
+        }
+    }
+
+    #[test]
+    fn exported_scenes_import_unchanged() {
+        let path = std::env::temp_dir().join(format!("export-{}.glb", std::process::id()));
+        let scene = scene();
+        to_gltf(&scene, &path).unwrap();
+        let (document, buffers, images) = gltf::import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mesh = &scene.meshes[0];
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let bounds = primitive.bounding_box();
+        assert_eq!(
+            (bounds.min, bounds.max),
+            ([-1.0, 0.0, -2.0], [1.0, 3.0, 2.0])
+        );
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        assert_eq!(
+            reader.read_positions().unwrap().collect::<Vec<_>>(),
+            mesh.positions
+        );
+        assert_eq!(
+            reader.read_normals().unwrap().collect::<Vec<_>>(),
+            mesh.normals
+        );
+        assert_eq!(
+            reader
+                .read_tex_coords(0)
+                .unwrap()
+                .into_f32()
+                .collect::<Vec<_>>(),
+            mesh.tex_coords
+        );
+        assert_eq!(
+            reader
+                .read_indices()
+                .unwrap()
+                .into_u32()
+                .collect::<Vec<_>>(),
+            mesh.indices
+        );
+
+        let material = primitive.material();
+        let pbr = material.pbr_metallic_roughness();
+        assert_eq!(material.name(), Some("painted"));
+        assert_eq!(pbr.base_color_factor(), [0.5, 0.25, 1.0, 1.0]);
+        let image = &images[pbr.base_color_texture().unwrap().texture().source().index()];
+        let texture = scene.materials[0].base_color_texture.as_ref().unwrap();
+        assert_eq!((image.width, image.height), texture.dimensions());
+        assert_eq!(image.pixels, texture.as_raw().as_slice());
+
+        let root = document
+            .default_scene()
+            .unwrap()
+            .nodes()
+            .collect::<Vec<_>>();
+        assert_eq!(root.len(), 1);
+        let parent = &root[0];
+        assert_eq!(parent.transform().decomposed().0, [1.0, 2.0, 3.0]);
+        let children = parent.children().collect::<Vec<_>>();
+        assert_eq!(children[0].mesh().map(|m| m.index()), Some(0));
+        assert_eq!(children[0].transform().decomposed().2, [2.0; 3]);
+        assert_eq!(children[1].transform().decomposed().1, [0.0, 0.0, 1.0, 0.0]);
+        let light = children[1].light().unwrap();
+        assert_eq!(light.name(), Some("lamp"));
+        assert_eq!(light.color(), [1.0, 0.5, 0.0]);
+        assert_eq!(light.intensity(), 40.0);
+        assert!(matches!(
+            light.kind(),
+            gltf::khr_lights_punctual::Kind::Point
+        ));
+    }
+
+    #[test]
+    fn meshes_without_uvs_or_normals_leave_the_attributes_out() {
+        let mut scene = scene();
+        scene.meshes[0].normals.clear();
+        scene.meshes[0].tex_coords.clear();
+        scene.meshes[0].material = None;
+        scene.materials.clear();
+        scene.lights.clear();
+        scene.nodes[2].light = None;
+        let path = std::env::temp_dir().join(format!("export-bare-{}.glb", std::process::id()));
+        to_gltf(&scene, &path).unwrap();
+        let (document, _, images) = gltf::import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let semantics = primitive.attributes().map(|(s, _)| s).collect::<Vec<_>>();
+        assert_eq!(semantics, [gltf::Semantic::Positions]);
+        assert!(images.is_empty());
+        assert!(document.extensions_used().next().is_none());
+    }
+}
@@ -0,0 +1,733 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    compression,
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken, ReadbackPolicy, ReadbackQueue},
+    mipmap::{BlitSource, MipmapGenerator},
+    reflect, tonemap,
+};
+
+/// Bins in the luminance histogram, matching `metering.wgsl`.
+pub const BINS: usize = 64;
+/// The histograms in flight between the GPU and the CPU. Metering results
+/// arrive this many frames late at most; older ones are dropped.
+const READBACK_FRAMES: usize = 3;
+/// The texels [`ReadbackMeter`] filters the HDR target down to.
+const READBACK_SIZE: (u32, u32) = (32, 32);
+
+/// How the metered luminance is turned into an exposure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureSettings {
+    /// Added to the metered exposure value; positive values brighten.
+    pub ev_compensation: f32,
+    pub min_ev: f32,
+    pub max_ev: f32,
+    /// Adaptation rates, per second, for when the scene got brighter and
+    /// darker. Eyes adapt to brightness faster than to darkness.
+    pub brightening_speed: f32,
+    pub darkening_speed: f32,
+    /// The darkest and brightest fractions of pixels left out of the
+    /// average, so small lights and deep shadows don't swing the exposure.
+    pub ignore_darkest: f32,
+    pub ignore_brightest: f32,
+    /// The range of log2 luminance the histogram covers.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        Self {
+            ev_compensation: 0.0,
+            min_ev: -4.0,
+            max_ev: 16.0,
+            brightening_speed: 3.0,
+            darkening_speed: 1.0,
+            ignore_darkest: 0.1,
+            ignore_brightest: 0.1,
+            min_log_luminance: -10.0,
+            max_log_luminance: 16.0,
+        }
+    }
+}
+
+/// Pixel counts by log2 luminance, as `metering.wgsl` produces them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub bins: [u32; BINS],
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+}
+
+impl Histogram {
+    /// Bins `luminances` as `metering.wgsl` does.
+    pub fn from_luminances(
+        luminances: impl IntoIterator<Item = f32>,
+        min_log_luminance: f32,
+        max_log_luminance: f32,
+    ) -> Self {
+        let mut bins = [0; BINS];
+        let range = max_log_luminance - min_log_luminance;
+        for luminance in luminances {
+            let bin = if luminance < 0.00001 {
+                0
+            } else {
+                let t = ((luminance.log2() - min_log_luminance) / range).clamp(0.0, 1.0);
+                ((t * (BINS - 1) as f32) as usize + 1).min(BINS - 1)
+            };
+            bins[bin] += 1;
+        }
+        Self {
+            bins,
+            min_log_luminance,
+            max_log_luminance,
+        }
+    }
+
+    /// The log2 luminance at the middle of `bin`. Bin 0 holds black pixels
+    /// and counts as the bottom of the range.
+    fn bin_log_luminance(&self, bin: usize) -> f32 {
+        if bin == 0 {
+            return self.min_log_luminance;
+        }
+        let range = self.max_log_luminance - self.min_log_luminance;
+        self.min_log_luminance + (bin as f32 - 0.5) / (BINS - 1) as f32 * range
+    }
+
+    /// The average log2 luminance, leaving out the `low` darkest and `high`
+    /// brightest fractions of pixels. `None` if nothing is left.
+    pub fn average_log_luminance(&self, low: f32, high: f32) -> Option<f32> {
+        let total = self.bins.iter().map(|&c| c as u64).sum::<u64>() as f32;
+        let (start, end) = (total * low, total * (1.0 - high));
+        let mut seen = 0.0;
+        let mut sum = 0.0;
+        let mut weight = 0.0;
+        for (bin, &count) in self.bins.iter().enumerate() {
+            // The part of this bin's pixels inside [start, end).
+            let count = count as f32;
+            let included = (seen + count).min(end) - seen.max(start);
+            if included > 0.0 {
+                sum += self.bin_log_luminance(bin) * included;
+                weight += included;
+            }
+            seen += count;
+        }
+        (weight > 0.0).then(|| sum / weight)
+    }
+}
+
+/// Relative luminance of a linear Rec. 709 color, as `metering.wgsl`
+/// weighs it.
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// The luminances of tightly packed [`tonemap::HDR_FORMAT`] texels.
+fn texel_luminances(bytes: &[u8]) -> Vec<f32> {
+    bytemuck::pod_collect_to_vec::<u8, u16>(bytes)
+        .chunks_exact(4)
+        .map(|texel| luminance([0, 1, 2].map(|c| compression::f16_to_f32(texel[c]))))
+        .collect()
+}
+
+/// Exposure value at ISO 100 for an average luminance, with the usual 12.5
+/// reflected light meter constant.
+pub fn ev100(log_luminance: f32) -> f32 {
+    log_luminance + (100.0f32 / 12.5).log2()
+}
+
+/// The multiplier that maps a scene metered at `ev100` to mid grey.
+pub fn exposure_from_ev100(ev100: f32) -> f32 {
+    1.0 / (1.2 * 2.0f32.powf(ev100))
+}
+
+/// Smooths the metered exposure over time.
+#[derive(Debug, Clone)]
+pub struct AutoExposure {
+    pub settings: ExposureSettings,
+    ev100: Option<f32>,
+}
+
+impl AutoExposure {
+    pub fn new(settings: ExposureSettings) -> Self {
+        Self {
+            settings,
+            ev100: None,
+        }
+    }
+
+    /// The current exposure value, or `None` before anything was metered.
+    pub fn ev100(&self) -> Option<f32> {
+        self.ev100
+    }
+
+    /// The multiplier to feed the tonemapper. 1 before anything was
+    /// metered.
+    pub fn exposure(&self) -> f32 {
+        self.ev100.map_or(1.0, exposure_from_ev100)
+    }
+
+    /// Moves towards the exposure for `log_luminance`, `dt` seconds after
+    /// the last update. The first update jumps straight to it.
+    pub fn update_log_luminance(&mut self, log_luminance: f32, dt: f32) -> f32 {
+        let settings = &self.settings;
+        let target = (ev100(log_luminance) - settings.ev_compensation)
+            .clamp(settings.min_ev, settings.max_ev);
+        let ev = match self.ev100 {
+            Some(current) => {
+                let speed = if target > current {
+                    settings.brightening_speed
+                } else {
+                    settings.darkening_speed
+                };
+                // Exponential, so the result doesn't depend on the frame rate.
+                current + (target - current) * (1.0 - (-dt * speed).exp())
+            }
+            None => target,
+        };
+        self.ev100 = Some(ev);
+        self.exposure()
+    }
+
+    pub fn update(&mut self, histogram: &Histogram, dt: f32) -> f32 {
+        match histogram
+            .average_log_luminance(self.settings.ignore_darkest, self.settings.ignore_brightest)
+        {
+            Some(log_luminance) => self.update_log_luminance(log_luminance, dt),
+            None => self.exposure(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeteringParams {
+    min_log_luminance: f32,
+    inverse_log_luminance_range: f32,
+    _padding: [f32; 2],
+}
+
+/// Builds a luminance histogram of an HDR target on the GPU each frame and
+/// reads it back without stalling, a few frames late.
+///
+/// Needs compute shaders and storage buffers, which WebGL2 doesn't have;
+/// check [`Self::is_supported`] first, or use [`Self::try_new`].
+pub struct ExposureMeter {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params: wgpu::Buffer,
+    histogram: wgpu::Buffer,
+    _memory: [MemoryToken; 2],
+    /// The target set with [`Self::set_source`] and its size.
+    source: Option<(wgpu::BindGroup, (u32, u32))>,
+    readbacks: ReadbackQueue,
+    /// The newest histogram delivered since the last poll.
+    newest: Arc<Mutex<Option<[u32; BINS]>>>,
+    range: (f32, f32),
+}
+
+impl ExposureMeter {
+    /// The HDR target, the histogram and the parameters, group 0 of
+    /// `metering.wgsl`.
+    pub(crate) const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// Whether `device`, created from `adapter`, can meter. It needs compute
+    /// shaders and storage buffers, and [`crate::gpu::device_limits`] allows no
+    /// storage buffers on downlevel adapters even where they have compute.
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && device.limits().max_storage_buffers_per_shader_stage > 0
+    }
+
+    /// A meter, if `device` supports one. Without one, [`Meter::new`]
+    /// meters on the CPU instead.
+    pub fn try_new(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        settings: &ExposureSettings,
+    ) -> Result<Option<Self>, AllocError> {
+        if !Self::is_supported(adapter, device) {
+            log::info!("Exposure can't be metered with compute shaders on this device");
+            return Ok(None);
+        }
+        Self::new(device, settings).map(Some)
+    }
+
+    pub fn new(device: &wgpu::Device, settings: &ExposureSettings) -> Result<Self, AllocError> {
+        let source = include_str!("metering.wgsl");
+        reflect::debug_check("metering.wgsl", source, &[&Self::LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("metering.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::LAYOUT_ENTRIES,
+            label: Some("metering_bind_group_layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Metering Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Metering Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let range = (settings.min_log_luminance, settings.max_log_luminance);
+        let allocator = Allocator::new(device);
+        let (params, params_memory) = allocator.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Metering Params"),
+                contents: bytemuck::cast_slice(&[MeteringParams {
+                    min_log_luminance: range.0,
+                    inverse_log_luminance_range: 1.0 / (range.1 - range.0),
+                    _padding: [0.0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let size = (BINS * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+        let (histogram, histogram_memory) = allocator.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Luminance Histogram"),
+                size,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        Ok(Self {
+            pipeline,
+            bind_group_layout,
+            params,
+            histogram,
+            _memory: [params_memory, histogram_memory],
+            source: None,
+            readbacks: ReadbackQueue::new(READBACK_FRAMES),
+            newest: Arc::default(),
+            range,
+        })
+    }
+
+    /// Meters `hdr`, a view of a float target `size` across, from now on.
+    /// Set it again whenever the target is recreated.
+    pub fn set_source(&mut self, device: &wgpu::Device, hdr: &wgpu::TextureView, size: (u32, u32)) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+            ],
+            label: Some("metering_bind_group"),
+        });
+        self.source = Some((bind_group, size));
+    }
+
+    /// Records metering of the source. The oldest histogram in flight is
+    /// dropped if [`READBACK_FRAMES`] already are. Call
+    /// [`Self::after_submit`] once the encoder is submitted. Does nothing
+    /// before [`Self::set_source`].
+    pub fn meter(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let Some((bind_group, size)) = &self.source else {
+            return;
+        };
+        encoder.clear_buffer(&self.histogram, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Metering Pass"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(size.0.div_ceil(16), size.1.div_ceil(16), 1);
+        }
+        let newest = self.newest.clone();
+        let recorded = self.readbacks.read_buffer(
+            device,
+            encoder,
+            &self.histogram,
+            0..self.histogram.size(),
+            ReadbackPolicy::DropOldest,
+            move |result| match result {
+                Ok(data) => {
+                    let mut bins = [0u32; BINS];
+                    bins.copy_from_slice(&bytemuck::pod_collect_to_vec::<u8, u32>(&data.bytes));
+                    *newest.lock().unwrap() = Some(bins);
+                }
+                Err(e) => log::debug!("Lost a luminance histogram: {}", e),
+            },
+        );
+        if let Err(e) = recorded {
+            log::warn!("Couldn't read back the luminance histogram: {}", e);
+        }
+    }
+
+    /// Starts mapping the histograms recorded since the last call.
+    pub fn after_submit(&mut self) {
+        self.readbacks.after_submit();
+    }
+
+    /// The newest histogram the GPU has finished, if one arrived since the
+    /// last call.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Histogram> {
+        // Histograms are delivered oldest first, so the newest is left.
+        self.readbacks.poll(device);
+        let bins = self.newest.lock().unwrap().take()?;
+        Some(Histogram {
+            bins,
+            min_log_luminance: self.range.0,
+            max_log_luminance: self.range.1,
+        })
+    }
+}
+
+/// Meters on the CPU where [`ExposureMeter`] can't, e.g. on WebGL2. The HDR
+/// target is filtered down to [`READBACK_SIZE`] texels and read back, as
+/// late as the GPU histogram. Each texel is the average of a tile, so the
+/// darkest and brightest fractions left out are of tiles rather than
+/// pixels, and small lights count for less than they would on the GPU.
+pub struct ReadbackMeter {
+    mipmaps: MipmapGenerator,
+    /// The target set with [`Self::set_source`].
+    source: Option<BlitSource>,
+    target: wgpu::Texture,
+    view: wgpu::TextureView,
+    _memory: MemoryToken,
+    readbacks: ReadbackQueue,
+    /// The luminances of the newest readback delivered since the last poll.
+    newest: Arc<Mutex<Option<Vec<f32>>>>,
+    range: (f32, f32),
+}
+
+impl ReadbackMeter {
+    pub fn new(device: &wgpu::Device, settings: &ExposureSettings) -> Result<Self, AllocError> {
+        let (target, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("Metering Readback"),
+                size: wgpu::Extent3d {
+                    width: READBACK_SIZE.0,
+                    height: READBACK_SIZE.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: tonemap::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Self {
+            mipmaps: MipmapGenerator::new(device),
+            source: None,
+            target,
+            view,
+            _memory: memory,
+            readbacks: ReadbackQueue::new(READBACK_FRAMES),
+            newest: Arc::default(),
+            range: (settings.min_log_luminance, settings.max_log_luminance),
+        })
+    }
+
+    /// Meters `hdr`, a view of a [`tonemap::HDR_FORMAT`] target, from now
+    /// on. Set it again whenever the target is recreated.
+    pub fn set_source(
+        &mut self,
+        device: &wgpu::Device,
+        hdr: &wgpu::TextureView,
+    ) -> Result<(), AllocError> {
+        self.source = Some(self.mipmaps.bind_source(device, hdr, READBACK_SIZE)?);
+        Ok(())
+    }
+
+    /// Records filtering the source down and reading it back. See
+    /// [`ExposureMeter::meter`].
+    pub fn meter(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let Some(source) = &self.source else {
+            return;
+        };
+        self.mipmaps
+            .blit_bound(device, encoder, source, &self.view, tonemap::HDR_FORMAT);
+        let newest = self.newest.clone();
+        let recorded = self.readbacks.read_texture(
+            device,
+            encoder,
+            self.target.as_image_copy(),
+            READBACK_SIZE,
+            ReadbackPolicy::DropOldest,
+            move |result| match result {
+                Ok(data) => *newest.lock().unwrap() = Some(texel_luminances(&data.bytes)),
+                Err(e) => log::debug!("Lost a metering readback: {}", e),
+            },
+        );
+        if let Err(e) = recorded {
+            log::warn!("Couldn't read back the HDR target for metering: {}", e);
+        }
+    }
+
+    /// Starts mapping the readbacks recorded since the last call.
+    pub fn after_submit(&mut self) {
+        self.readbacks.after_submit();
+    }
+
+    /// A histogram of the newest readback the GPU has finished, if one
+    /// arrived since the last call.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Histogram> {
+        self.readbacks.poll(device);
+        let luminances = self.newest.lock().unwrap().take()?;
+        Some(Histogram::from_luminances(
+            luminances,
+            self.range.0,
+            self.range.1,
+        ))
+    }
+}
+
+/// Meters the HDR target for [`AutoExposure`], with compute shaders where
+/// the device has them and from a readback where it doesn't.
+pub enum Meter {
+    Compute(ExposureMeter),
+    Readback(ReadbackMeter),
+}
+
+impl Meter {
+    pub fn new(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        settings: &ExposureSettings,
+    ) -> Result<Self, AllocError> {
+        Ok(match ExposureMeter::try_new(adapter, device, settings)? {
+            Some(meter) => Meter::Compute(meter),
+            None => Meter::Readback(ReadbackMeter::new(device, settings)?),
+        })
+    }
+
+    /// Meters `hdr`, a view of a [`tonemap::HDR_FORMAT`] target `size`
+    /// across, from now on. Set it again whenever the target is recreated.
+    pub fn set_source(
+        &mut self,
+        device: &wgpu::Device,
+        hdr: &wgpu::TextureView,
+        size: (u32, u32),
+    ) -> Result<(), AllocError> {
+        match self {
+            Meter::Compute(meter) => {
+                meter.set_source(device, hdr, size);
+                Ok(())
+            }
+            Meter::Readback(meter) => meter.set_source(device, hdr),
+        }
+    }
+
+    /// Records metering of the source. Call [`Self::after_submit`] once the
+    /// encoder is submitted.
+    pub fn meter(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        match self {
+            Meter::Compute(meter) => meter.meter(device, encoder),
+            Meter::Readback(meter) => meter.meter(device, encoder),
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        match self {
+            Meter::Compute(meter) => meter.after_submit(),
+            Meter::Readback(meter) => meter.after_submit(),
+        }
+    }
+
+    /// The newest histogram, if one arrived since the last call.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Histogram> {
+        match self {
+            Meter::Compute(meter) => meter.poll(device),
+            Meter::Readback(meter) => meter.poll(device),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(
+        luminances: impl IntoIterator<Item = f32>,
+        settings: &ExposureSettings,
+    ) -> Histogram {
+        Histogram::from_luminances(
+            luminances,
+            settings.min_log_luminance,
+            settings.max_log_luminance,
+        )
+    }
+
+    /// Half a bin, the furthest a binned luminance is from its bin's middle.
+    fn tolerance(settings: &ExposureSettings) -> f32 {
+        0.5 * (settings.max_log_luminance - settings.min_log_luminance) / (BINS - 1) as f32
+    }
+
+    #[test]
+    fn a_steady_scene_converges_to_mid_grey() {
+        let settings = ExposureSettings::default();
+        for luminance in [0.01, 0.5, 3.0, 200.0] {
+            let field = histogram(std::iter::repeat_n(luminance, 1000), &settings);
+            let metered = field.average_log_luminance(0.1, 0.1).unwrap();
+            assert!((metered - luminance.log2()).abs() <= tolerance(&settings));
+
+            let mut auto = AutoExposure::new(settings);
+            assert_eq!(auto.exposure(), 1.0, "nothing metered yet");
+            for _ in 0..600 {
+                auto.update(&field, 1.0 / 60.0);
+            }
+            // Exposed, the average comes out at 1 / (1.2 * 100 / 12.5).
+            let grey = luminance * auto.exposure() * 9.6;
+            assert!(
+                grey.log2().abs() <= tolerance(&settings),
+                "{} exposes to {}",
+                luminance,
+                grey
+            );
+        }
+    }
+
+    #[test]
+    fn the_darkest_and_brightest_pixels_are_left_out() {
+        let settings = ExposureSettings::default();
+        // A lamp and a black sky around a room lit at 1.
+        let room = std::iter::repeat_n(1.0, 850);
+        let lamp = std::iter::repeat_n(10_000.0, 100);
+        let sky = std::iter::repeat_n(0.0, 50);
+        let field = histogram(room.chain(lamp).chain(sky), &settings);
+        let trimmed = field.average_log_luminance(0.1, 0.1).unwrap();
+        assert!(trimmed.abs() <= tolerance(&settings), "{}", trimmed);
+        let untrimmed = field.average_log_luminance(0.0, 0.0).unwrap();
+        assert!((untrimmed - trimmed).abs() > 0.5, "{}", untrimmed);
+
+        let empty = histogram([], &settings);
+        assert_eq!(empty.average_log_luminance(0.1, 0.1), None);
+        let mut auto = AutoExposure::new(settings);
+        assert_eq!(auto.update(&empty, 1.0), 1.0);
+        assert_eq!(auto.ev100(), None);
+    }
+
+    #[test]
+    fn readbacks_meter_like_the_histogram() {
+        let settings = ExposureSettings::default();
+        // A room lit at 3 and lit at 0.5, in red, green and blue.
+        let texels = [[3.0, 3.0, 3.0, 1.0], [0.5, 0.5, 0.5, 1.0]]
+            .into_iter()
+            .flat_map(|texel| std::iter::repeat_n(texel, 512))
+            .flatten()
+            .map(compression::f32_to_f16)
+            .collect::<Vec<_>>();
+        let luminances = texel_luminances(bytemuck::cast_slice(&texels));
+        assert_eq!(luminances.len(), 1024);
+        let metered = histogram(luminances, &settings)
+            .average_log_luminance(0.0, 0.0)
+            .unwrap();
+        let expected = (3.0f32.log2() + 0.5f32.log2()) / 2.0;
+        assert!(
+            (metered - expected).abs() <= tolerance(&settings),
+            "{}",
+            metered
+        );
+    }
+
+    #[test]
+    fn adaptation_takes_one_time_constant_per_e_fold() {
+        let settings = ExposureSettings::default();
+        let (dark, bright) = (-2.0, 6.0);
+        for (from, to, speed) in [
+            (dark, bright, settings.brightening_speed),
+            (bright, dark, settings.darkening_speed),
+        ] {
+            let mut auto = AutoExposure::new(settings);
+            auto.update_log_luminance(from, 0.1);
+            assert_eq!(auto.ev100(), Some(ev100(from)), "the first update jumps");
+
+            let mut stepped = auto.clone();
+            for _ in 0..60 {
+                stepped.update_log_luminance(to, 1.0 / speed / 60.0);
+            }
+            auto.update_log_luminance(to, 1.0 / speed);
+            let left = (ev100(to) - auto.ev100().unwrap()) / (ev100(to) - ev100(from));
+            assert!(
+                (left - (-1.0f32).exp()).abs() < 1e-4,
+                "{} of the way left",
+                left
+            );
+            assert!(
+                (stepped.ev100().unwrap() - auto.ev100().unwrap()).abs() < 1e-3,
+                "adaptation depends on the frame rate"
+            );
+        }
+    }
+
+    #[test]
+    fn compensation_and_clamps_apply_to_the_target() {
+        let settings = ExposureSettings {
+            ev_compensation: 1.0,
+            ..Default::default()
+        };
+        let mut compensated = AutoExposure::new(settings);
+        let mut plain = AutoExposure::new(ExposureSettings::default());
+        let ratio =
+            compensated.update_log_luminance(3.0, 0.0) / plain.update_log_luminance(3.0, 0.0);
+        assert!((ratio - 2.0).abs() < 1e-4, "one stop up is {}x", ratio);
+
+        let mut auto = AutoExposure::new(settings);
+        auto.update_log_luminance(-40.0, 0.0);
+        assert_eq!(auto.ev100(), Some(settings.min_ev));
+        auto.update_log_luminance(40.0, 1000.0);
+        assert_eq!(auto.ev100(), Some(settings.max_ev));
+    }
+}
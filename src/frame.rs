@@ -0,0 +1,780 @@
+pub mod arena;
+pub mod layers;
+
+use std::ops::Range;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3};
+
+pub use crate::assets::{MaterialHandle, ModelHandle};
+
+use crate::{
+    assets::Assets,
+    bounds::{Aabb, Frustum},
+    compose::{FrameHooks, PassContext, PassSlot, TargetLoads},
+    gpu::{self, UniformFrame},
+    instancing::{InstanceBuffer, PackedInstances},
+    light::{Ambient, LightUniform},
+    model::{DrawModel, Material, Model, VertexPrecision},
+    region::{DrawRegion, Viewport},
+    stats::FrameStats,
+    variant::InstanceLayout,
+    Instance,
+};
+
+pub use arena::{ArenaVec, FrameArena, Watermark};
+pub use layers::{LayerNames, Layers};
+
+/// Per draw changes to how a model looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MaterialOverrides {
+    /// Draws every mesh with this material instead of its own.
+    pub material: Option<MaterialHandle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DrawFlags(u32);
+
+impl DrawFlags {
+    /// Skipped entirely, e.g. for entities that are loaded but not spawned.
+    pub const HIDDEN: Self = Self(1 << 0);
+    /// Drawn even when outside the camera's frustum.
+    pub const NO_CULL: Self = Self(1 << 1);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for DrawFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// The animation state a skinned or morphing model is drawn in, which its
+/// culling bounds follow.
+#[derive(Debug, Clone, Default)]
+pub struct DrawPose {
+    /// Skinning matrices, one per joint.
+    pub palette: Vec<Matrix4<f32>>,
+    pub morph_weights: Vec<f32>,
+}
+
+pub struct ModelDraw {
+    pub model: ModelHandle,
+    pub transform: Instance,
+    pub overrides: MaterialOverrides,
+    pub flags: DrawFlags,
+    pub layers: Layers,
+    /// `None` culls with the bounds the model was loaded with.
+    pub pose: Option<DrawPose>,
+}
+
+pub struct FrameCamera {
+    /// Maps to wgpu clip space, as the camera uniform expects.
+    pub view_proj: Matrix4<f32>,
+    pub eye: Point3<f32>,
+    pub viewport: Viewport,
+    /// The layers of draws this camera shows.
+    pub cull_mask: Layers,
+}
+
+/// Everything to draw in a frame, as plain data copied out of the game's
+/// own state, so nothing the renderer holds borrows from it. Build a new
+/// one each frame, or [`clear`](Self::clear) and refill one to reuse its
+/// allocations.
+pub struct RenderFrame {
+    pub models: Vec<ModelDraw>,
+    pub lights: Vec<LightUniform>,
+    pub cameras: Vec<FrameCamera>,
+    pub ambient: Ambient,
+    /// The layers of draws that cast shadows, e.g. everything but editor
+    /// gizmos. There is no shadow pass yet; it is to cull with this.
+    pub shadow_cull_mask: Layers,
+}
+
+impl Default for RenderFrame {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            lights: Vec::new(),
+            cameras: Vec::new(),
+            ambient: Ambient::default(),
+            shadow_cull_mask: Layers::ALL,
+        }
+    }
+}
+
+/// Consecutive draws of one model with the same overrides, which become one
+/// instanced draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawBatch<'a> {
+    pub model: ModelHandle,
+    pub overrides: MaterialOverrides,
+    /// Indices into [`RenderFrame::models`], in draw order.
+    pub draws: &'a [usize],
+}
+
+impl RenderFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.models.clear();
+        self.lights.clear();
+        self.cameras.clear();
+    }
+
+    /// Draws `model` on [`Layers::DEFAULT`].
+    pub fn push_model(
+        &mut self,
+        model: ModelHandle,
+        transform: Instance,
+        overrides: MaterialOverrides,
+        flags: DrawFlags,
+    ) {
+        self.push_model_on(model, transform, overrides, flags, Layers::DEFAULT);
+    }
+
+    /// Draws `model` in the cameras whose cull mask has one of `layers`.
+    pub fn push_model_on(
+        &mut self,
+        model: ModelHandle,
+        transform: Instance,
+        overrides: MaterialOverrides,
+        flags: DrawFlags,
+        layers: Layers,
+    ) {
+        self.models.push(ModelDraw {
+            model,
+            transform,
+            overrides,
+            flags,
+            layers,
+            pose: None,
+        });
+    }
+
+    /// Draws an animated `model` on [`Layers::DEFAULT`], culled with the
+    /// bounds of its meshes in `pose` rather than at rest.
+    pub fn push_posed_model(
+        &mut self,
+        model: ModelHandle,
+        transform: Instance,
+        overrides: MaterialOverrides,
+        flags: DrawFlags,
+        pose: DrawPose,
+    ) {
+        self.push_model(model, transform, overrides, flags);
+        if let Some(draw) = self.models.last_mut() {
+            draw.pose = Some(pose);
+        }
+    }
+
+    /// Lights aren't drawn by [`Renderer`], since the model shader doesn't
+    /// light; pass them on to [`DebugLight::update`](crate::light::DebugLight::update).
+    pub fn push_light(&mut self, light: LightUniform) {
+        self.lights.push(light);
+    }
+
+    /// Each camera draws the frame's models into its viewport.
+    pub fn push_camera(&mut self, view_proj: Matrix4<f32>, eye: Point3<f32>, viewport: Viewport) {
+        self.push_masked_camera(view_proj, eye, viewport, Layers::ALL);
+    }
+
+    /// A camera drawing only the models on one of the `cull_mask` layers.
+    pub fn push_masked_camera(
+        &mut self,
+        view_proj: Matrix4<f32>,
+        eye: Point3<f32>,
+        viewport: Viewport,
+        cull_mask: Layers,
+    ) {
+        self.cameras.push(FrameCamera {
+            view_proj,
+            eye,
+            viewport,
+            cull_mask,
+        });
+    }
+
+    /// How [`Renderer`] packs the frame's instances: compactly unless one
+    /// of the models is scaled unevenly. The pipelines in
+    /// [`FrameResources`] have to be built for it.
+    pub fn instance_layout(&self) -> InstanceLayout {
+        InstanceLayout::for_instances(self.models.iter().map(|draw| &draw.transform))
+    }
+
+    /// The draws `camera` sees, culled by its mask and frustum and sorted into
+    /// batches: by pipeline, then model, then overrides, then front to back
+    /// within a batch. Handles that don't resolve in `models` are skipped.
+    /// Everything is allocated from `arena`, so this doesn't allocate once
+    /// it has grown.
+    pub fn batches<'a>(
+        &self,
+        camera: &FrameCamera,
+        models: &Assets<Model>,
+        arena: &'a FrameArena,
+    ) -> ArenaVec<'a, DrawBatch<'a>> {
+        self.batches_with_bounds(camera, models, arena, model_bounds)
+    }
+
+    /// [`Self::batches`], culling with the bounds `bounds` gives a model.
+    fn batches_with_bounds<'a, F>(
+        &self,
+        camera: &FrameCamera,
+        models: &Assets<Model>,
+        arena: &'a FrameArena,
+        bounds: F,
+    ) -> ArenaVec<'a, DrawBatch<'a>>
+    where
+        F: Fn(&Model, Option<&DrawPose>) -> Option<Aabb>,
+    {
+        let frustum = Frustum::from_view_proj(&camera.view_proj);
+        let mut visible = arena.vec_from_iter(
+            self.models
+                .iter()
+                .enumerate()
+                .filter(|(_, draw)| {
+                    !draw.flags.contains(DrawFlags::HIDDEN)
+                        && draw.layers.intersects(camera.cull_mask)
+                })
+                .filter_map(|(i, draw)| {
+                    let model = models.resolve(draw.model)?;
+                    let culled = !draw.flags.contains(DrawFlags::NO_CULL)
+                        && bounds(model, draw.pose.as_ref()).is_some_and(|aabb| {
+                            !frustum
+                                .intersects(&aabb.transformed(&transform_matrix(&draw.transform)))
+                        });
+                    let distance = (draw.transform.position - camera.eye.to_vec()).magnitude2();
+                    (!culled).then_some((i, model.vertex_precision, distance))
+                }),
+        );
+        // Ties are broken by index, so an unstable sort, which doesn't
+        // allocate, gives the same order as a stable one.
+        visible.sort_unstable_by(
+            |(a, a_precision, a_distance), (b, b_precision, b_distance)| {
+                let (a_draw, b_draw) = (&self.models[*a], &self.models[*b]);
+                precision_order(*a_precision)
+                    .cmp(&precision_order(*b_precision))
+                    .then(a_draw.model.cmp(&b_draw.model))
+                    .then(a_draw.overrides.cmp(&b_draw.overrides))
+                    .then(a_distance.total_cmp(b_distance))
+                    .then(a.cmp(b))
+            },
+        );
+
+        // Each batch is a run of the sorted draws.
+        let draws: &'a [usize] = arena
+            .vec_from_iter(visible.iter().map(|(i, _, _)| *i))
+            .into_slice();
+        let mut batches = arena.vec::<DrawBatch<'a>>();
+        let mut start = 0;
+        for (end, &i) in draws.iter().enumerate() {
+            let draw = &self.models[i];
+            match batches.last_mut() {
+                Some(batch) if batch.model == draw.model && batch.overrides == draw.overrides => {
+                    batch.draws = &draws[start..=end]
+                }
+                _ => {
+                    start = end;
+                    batches.push(DrawBatch {
+                        model: draw.model,
+                        overrides: draw.overrides,
+                        draws: &draws[end..=end],
+                    })
+                }
+            }
+        }
+        batches
+    }
+}
+
+fn precision_order(precision: VertexPrecision) -> u8 {
+    match precision {
+        VertexPrecision::Full => 0,
+        VertexPrecision::Compressed => 1,
+    }
+}
+
+fn transform_matrix(transform: &Instance) -> Matrix4<f32> {
+    transform.to_raw().model.into()
+}
+
+fn model_bounds(model: &Model, pose: Option<&DrawPose>) -> Option<Aabb> {
+    let (palette, weights) = match pose {
+        Some(pose) => (Some(pose.palette.as_slice()), pose.morph_weights.as_slice()),
+        None => (None, &[][..]),
+    };
+    model
+        .meshes
+        .iter()
+        .map(|mesh| mesh.current_bounds(palette, weights))
+        .reduce(|a, b| a.union(&b))
+}
+
+/// Where [`Renderer::render`] draws.
+pub struct RenderTarget<'a> {
+    pub color: &'a wgpu::TextureView,
+    pub depth: &'a wgpu::TextureView,
+    pub size: (u32, u32),
+}
+
+/// The GPU resources a [`RenderFrame`] refers to. The pipelines are the
+/// variants for the frame's [`RenderFrame::instance_layout`].
+pub struct FrameResources<'a> {
+    pub models: &'a Assets<Model>,
+    /// What [`MaterialOverrides::material`] resolves in.
+    pub materials: &'a Assets<Material>,
+    pub pipeline: &'a wgpu::RenderPipeline,
+    pub compressed_pipeline: &'a wgpu::RenderPipeline,
+    /// The `HAS_TRIPLANAR` variants of the two. Without them, and for draws
+    /// with a [`MaterialOverrides::material`], triplanar materials are drawn
+    /// with their UVs.
+    pub triplanar_pipelines: Option<(&'a wgpu::RenderPipeline, &'a wgpu::RenderPipeline)>,
+}
+
+/// Draws [`RenderFrame`]s, owning the per frame buffers so the frame itself
+/// stays plain data.
+pub struct Renderer {
+    cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    ambient_buffer: wgpu::Buffer,
+    instances: InstanceBuffer,
+    /// The ambient and camera buffers.
+    _memory: Vec<gpu::MemoryToken>,
+    instance_data: PackedInstances,
+    /// Culling output and batches, reset every frame.
+    arena: FrameArena,
+}
+
+impl Renderer {
+    const INITIAL_INSTANCES: usize = 256;
+
+    /// Frames can have up to `max_cameras` cameras, using `camera_layout`
+    /// as the model pipelines do.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        max_cameras: usize,
+    ) -> Result<Self, gpu::AllocError> {
+        let (ambient_buffer, ambient_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Frame Ambient Buffer"),
+                    contents: bytemuck::cast_slice(&[Ambient::default()]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let mut memory = vec![ambient_buffer_memory];
+        let cameras = (0..max_cameras)
+            .map(|i| {
+                let (buffer, buffer_memory) = gpu::Allocator::new(device).create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("Frame Camera Buffer {}", i)),
+                        contents: bytemuck::cast_slice(&[[[0.0f32; 4]; 4]]),
+                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    },
+                    gpu::MemoryCategory::Uniform,
+                )?;
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: camera_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: ambient_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some(&format!("Frame Camera Bind Group {}", i)),
+                });
+                memory.push(buffer_memory);
+                Ok((buffer, bind_group))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            cameras,
+            ambient_buffer,
+            instances: InstanceBuffer::new(
+                device,
+                "Frame Instance Buffer",
+                Self::INITIAL_INSTANCES,
+            )?,
+            _memory: memory,
+            instance_data: PackedInstances::default(),
+            arena: FrameArena::new(),
+        })
+    }
+
+    /// How much of the per frame arena the frames so far needed, for
+    /// pre-sizing it with [`Renderer::reserve_arena`].
+    pub fn arena_watermark(&self) -> Watermark {
+        self.arena.watermark()
+    }
+
+    /// Sizes the per frame arena to fit `bytes`, so that the first frames
+    /// don't grow it.
+    pub fn reserve_arena(&mut self, bytes: usize) {
+        self.arena.reset();
+        self.arena.reserve(bytes);
+    }
+
+    /// Culls, sorts and draws `frame` for each of its cameras in one pass,
+    /// starting off `target` as `hooks` says. Cameras past `max_cameras` are
+    /// skipped. There is no UI here, so the UI slots of `hooks` run right
+    /// after the scene, except [`PassSlot::Ui`], whose passes draw in the UI
+    /// format. The hooks allocate their uniforms from `uniforms`.
+    /// Fails if the instance buffer had to grow and couldn't.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &RenderTarget,
+        resources: &FrameResources,
+        frame: &RenderFrame,
+        hooks: &mut FrameHooks,
+        uniforms: &mut UniformFrame,
+    ) -> Result<FrameStats, gpu::AllocError> {
+        if frame.cameras.len() > self.cameras.len() {
+            log::warn!(
+                "Drawing {} of the frame's {} cameras",
+                self.cameras.len(),
+                frame.cameras.len()
+            );
+        }
+
+        queue.write_buffer(
+            &self.ambient_buffer,
+            0,
+            bytemuck::cast_slice(&[frame.ambient]),
+        );
+
+        // Instance data for every camera goes in one buffer, so the ranges
+        // are worked out before the pass starts.
+        self.instance_data.clear(frame.instance_layout());
+        self.arena.reset();
+        let arena = &self.arena;
+        let mut camera_batches = arena.vec();
+        for (camera, (buffer, _)) in frame.cameras.iter().zip(&self.cameras) {
+            let view_proj: [[f32; 4]; 4] = camera.view_proj.into();
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[view_proj]));
+            let batches = frame.batches(camera, resources.models, arena);
+            let mut instanced = ArenaVec::with_capacity_in(batches.len(), arena);
+            for batch in batches.iter() {
+                let start = self.instance_data.len() as u32;
+                for &i in batch.draws {
+                    self.instance_data.push(&frame.models[i].transform);
+                }
+                instanced.push((*batch, start));
+            }
+            camera_batches.push(&*instanced.into_slice());
+        }
+        self.instances.write(device, queue, &self.instance_data)?;
+
+        let mut stats = FrameStats {
+            passes: 1,
+            ..Default::default()
+        };
+        let loads = hooks.loads();
+        let mut run_hooks = |encoder: &mut wgpu::CommandEncoder, slot| {
+            hooks.run(
+                slot,
+                &mut PassContext {
+                    device,
+                    queue,
+                    encoder,
+                    color: target.color,
+                    depth: target.depth,
+                    size: target.size,
+                    uniforms,
+                },
+            )
+        };
+        stats.passes += run_hooks(encoder, PassSlot::BeforeScene);
+        self.draw_scene(
+            encoder,
+            target,
+            loads,
+            resources,
+            frame,
+            &camera_batches,
+            &mut stats,
+        );
+        for slot in [
+            PassSlot::AfterOpaque,
+            PassSlot::AfterTransparent,
+            PassSlot::BeforeUi,
+            PassSlot::AfterUi,
+        ] {
+            stats.passes += run_hooks(encoder, slot);
+        }
+        Ok(stats)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &RenderTarget,
+        loads: TargetLoads,
+        resources: &FrameResources,
+        frame: &RenderFrame,
+        camera_batches: &[&[(DrawBatch, u32)]],
+        stats: &mut FrameStats,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Frame Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: loads.color,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: loads.depth,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        self.instances.bind(&mut render_pass);
+        for ((camera, (_, bind_group)), batches) in
+            frame.cameras.iter().zip(&self.cameras).zip(camera_batches)
+        {
+            if !DrawRegion::new(camera.viewport, bind_group).apply(&mut render_pass, target.size) {
+                continue;
+            }
+            let mut precision = None;
+            for &(batch, start) in batches.iter() {
+                let instances = start..start + batch.draws.len() as u32;
+                // Batches only hold handles that resolved.
+                let Some(model) = resources.models.resolve(batch.model) else {
+                    continue;
+                };
+                let triplanar = resources
+                    .triplanar_pipelines
+                    .filter(|_| batch.overrides.material.is_none() && model.has_triplanar());
+                if let Some((full, compressed)) = triplanar {
+                    let pipelines = match model.vertex_precision {
+                        VertexPrecision::Full => (resources.pipeline, full),
+                        VertexPrecision::Compressed => (resources.compressed_pipeline, compressed),
+                    };
+                    render_pass.draw_model_instanced_triplanar(
+                        model,
+                        instances.clone(),
+                        bind_group,
+                        pipelines,
+                    );
+                    // Either pipeline may be set now.
+                    precision = None;
+                    stats.pipeline_sets += model.triplanar_pipeline_sets();
+                    stats.add_model(model, instances.len() as u32);
+                    continue;
+                }
+                if precision != Some(model.vertex_precision) {
+                    precision = Some(model.vertex_precision);
+                    stats.pipeline_sets += 1;
+                    render_pass.set_pipeline(match model.vertex_precision {
+                        VertexPrecision::Full => resources.pipeline,
+                        VertexPrecision::Compressed => resources.compressed_pipeline,
+                    });
+                }
+                draw_batch(
+                    &mut render_pass,
+                    model,
+                    &batch,
+                    resources.materials,
+                    instances.clone(),
+                    bind_group,
+                );
+                stats.add_model(model, instances.len() as u32);
+            }
+        }
+    }
+}
+
+fn draw_batch<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    batch: &DrawBatch,
+    materials: &'a Assets<Material>,
+    instances: Range<u32>,
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    match batch.overrides.material.and_then(|m| materials.resolve(m)) {
+        Some(material) => {
+            for mesh in &model.meshes {
+                render_pass.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    instances.clone(),
+                    camera_bind_group,
+                );
+            }
+        }
+        None => render_pass.draw_model_instanced(model, instances, camera_bind_group),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Vector3};
+
+    use super::*;
+    use crate::{
+        math::projection::{self, DepthRange},
+        units::UnitScale,
+    };
+
+    fn model(vertex_precision: VertexPrecision) -> Model {
+        Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            vertex_precision,
+            unit_scale: UnitScale(1.0),
+        }
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> Instance {
+        Instance {
+            position: Vector3::new(x, y, z),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Looking down -Z from the origin.
+    fn camera(cull_mask: Layers) -> FrameCamera {
+        FrameCamera {
+            view_proj: projection::perspective(
+                Deg(60.0).into(),
+                1.0,
+                0.1,
+                100.0,
+                DepthRange::Standard,
+            ),
+            eye: Point3::new(0.0, 0.0, 0.0),
+            viewport: Viewport::full((64, 64)),
+            cull_mask,
+        }
+    }
+
+    /// Every model is a unit cube around its origin.
+    fn unit_bounds(_: &Model, _: Option<&DrawPose>) -> Option<Aabb> {
+        Some(Aabb::new(
+            Point3::new(-0.5, -0.5, -0.5),
+            Point3::new(0.5, 0.5, 0.5),
+        ))
+    }
+
+    fn batch_draws(
+        frame: &RenderFrame,
+        camera: &FrameCamera,
+        models: &Assets<Model>,
+    ) -> Vec<(ModelHandle, Vec<usize>)> {
+        let arena = FrameArena::new();
+        frame
+            .batches_with_bounds(camera, models, &arena, unit_bounds)
+            .iter()
+            .map(|batch| (batch.model, batch.draws.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn batches_sort_by_precision_model_then_distance() {
+        let mut models = Assets::new();
+        let compressed = models.insert(model(VertexPrecision::Compressed));
+        let first = models.insert(model(VertexPrecision::Full));
+        let second = models.insert(model(VertexPrecision::Full));
+
+        let mut frame = RenderFrame::new();
+        let flags = DrawFlags::empty();
+        let overrides = MaterialOverrides::default();
+        for (model, z) in [
+            (second, -5.0),
+            (compressed, -3.0),
+            (first, -20.0),
+            (second, -2.0),
+            (first, -4.0),
+            (first, -4.0),
+        ] {
+            frame.push_model(model, at(0.0, 0.0, z), overrides, flags);
+        }
+
+        // Full precision comes first, then models in handle order, with each
+        // batch front to back and ties kept in push order.
+        assert_eq!(
+            batch_draws(&frame, &camera(Layers::ALL), &models),
+            [
+                (first, vec![4, 5, 2]),
+                (second, vec![3, 0]),
+                (compressed, vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_skip_culled_hidden_and_masked_draws() {
+        let mut models = Assets::new();
+        let cube = models.insert(model(VertexPrecision::Full));
+        let released = models.insert(model(VertexPrecision::Full));
+        models.release(released);
+
+        let mut frame = RenderFrame::new();
+        let overrides = MaterialOverrides::default();
+        let empty = DrawFlags::empty();
+        // 0: in view.
+        frame.push_model(cube, at(0.0, 0.0, -5.0), overrides, empty);
+        // 1: behind the camera.
+        frame.push_model(cube, at(0.0, 0.0, 5.0), overrides, empty);
+        // 2: behind the camera, but never culled.
+        frame.push_model(cube, at(0.0, 0.0, 6.0), overrides, DrawFlags::NO_CULL);
+        // 3: in view but hidden.
+        let hidden = DrawFlags::HIDDEN | DrawFlags::NO_CULL;
+        frame.push_model(cube, at(0.0, 0.0, -6.0), overrides, hidden);
+        // 4: only partly in view, off to the side.
+        frame.push_model(cube, at(3.3, 0.0, -5.0), overrides, empty);
+        // 5: on another layer.
+        let layer = Layers::layer(3);
+        frame.push_model_on(cube, at(0.0, 0.0, -7.0), overrides, empty, layer);
+        // 6: a model that has been released.
+        frame.push_model(released, at(0.0, 0.0, -5.0), overrides, empty);
+
+        let all = batch_draws(&frame, &camera(Layers::ALL), &models);
+        assert_eq!(all, [(cube, vec![0, 4, 2, 5])]);
+        let default = batch_draws(&frame, &camera(Layers::DEFAULT), &models);
+        assert_eq!(default, [(cube, vec![0, 4, 2])]);
+        let masked = batch_draws(&frame, &camera(layer), &models);
+        assert_eq!(masked, [(cube, vec![5])]);
+    }
+
+    #[test]
+    fn models_without_bounds_are_never_culled() {
+        let mut models = Assets::new();
+        let cube = models.insert(model(VertexPrecision::Full));
+        let mut frame = RenderFrame::new();
+        let overrides = MaterialOverrides::default();
+        frame.push_model(cube, at(0.0, 0.0, 50.0), overrides, DrawFlags::empty());
+        // The real bounds come from the meshes, and there are none.
+        let arena = FrameArena::new();
+        let batches = frame.batches(&camera(Layers::ALL), &models, &arena);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].draws, &[0]);
+    }
+}
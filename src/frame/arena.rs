@@ -0,0 +1,373 @@
+//! A bump allocator for data that only lives for one frame, such as culling
+//! output and sort keys. Everything is freed at once by
+//! [`FrameArena::reset`], and the memory is kept for the next frame, so once
+//! the arena has grown to fit a frame, recording one doesn't allocate.
+//!
+//! Allocations borrow the arena, and resetting takes it mutably, so nothing
+//! allocated in a frame can outlive it. Only `Copy` types can be stored,
+//! since nothing is dropped.
+
+use std::{
+    alloc::{self, Layout},
+    cell::Cell,
+    marker::PhantomData,
+    ptr::NonNull,
+};
+
+/// Chunks are aligned to this, which is the largest alignment a type stored
+/// in the arena can have.
+const ALIGN: usize = 16;
+
+/// The smallest chunk allocated, in bytes.
+const MIN_CHUNK: usize = 4096;
+
+/// How much of a [`FrameArena`] is used, for pre-sizing it with
+/// [`FrameArena::with_capacity`] or [`FrameArena::reserve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Watermark {
+    /// Bytes used since the last reset.
+    pub used: usize,
+    /// The most bytes used in any frame since the arena was made.
+    pub peak: usize,
+    /// Bytes allocated from the system.
+    pub capacity: usize,
+    /// Number of chunks the capacity is split into. After a reset there is
+    /// only one.
+    pub chunks: usize,
+}
+
+struct Chunk {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+impl Chunk {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, ALIGN).unwrap();
+        // SAFETY: `size` is never 0, as chunks are at least `MIN_CHUNK`.
+        let ptr = unsafe { alloc::alloc(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, size }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.size, ALIGN).unwrap();
+        // SAFETY: allocated in `Chunk::new` with the same layout.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) }
+    }
+}
+
+/// See the module docs.
+pub struct FrameArena {
+    /// Only the last chunk is allocated from. The earlier ones are full, or
+    /// were too small for an allocation, and stay until the next reset.
+    chunks: Cell<Vec<Chunk>>,
+    /// Bytes used in the last chunk.
+    offset: Cell<usize>,
+    /// Bytes used in the earlier chunks.
+    retired: Cell<usize>,
+    peak: usize,
+}
+
+// SAFETY: the chunks are owned by the arena, and moving it to another thread
+// needs it not to be borrowed, so nothing else can be using them.
+unsafe impl Send for FrameArena {}
+
+impl Default for FrameArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameArena {
+    /// An arena that allocates its first chunk when first used.
+    pub fn new() -> Self {
+        Self {
+            chunks: Cell::new(Vec::new()),
+            offset: Cell::new(0),
+            retired: Cell::new(0),
+            peak: 0,
+        }
+    }
+
+    /// An arena that fits `bytes` without growing, e.g. a [`Watermark::peak`]
+    /// from an earlier run.
+    pub fn with_capacity(bytes: usize) -> Self {
+        let mut arena = Self::new();
+        arena.reserve(bytes);
+        arena
+    }
+
+    /// Frees everything allocated since the last reset. If the frame needed
+    /// more than one chunk, they are replaced by one that fits it all.
+    pub fn reset(&mut self) {
+        let used = self.used();
+        self.peak = self.peak.max(used);
+        self.offset.set(0);
+        self.retired.set(0);
+        if self.chunks.get_mut().len() > 1 {
+            let capacity = self.capacity();
+            self.chunks.get_mut().clear();
+            self.reserve(capacity);
+        }
+    }
+
+    /// Makes sure the next frame fits `bytes` without growing. Only call
+    /// right after a reset or creating the arena, as anything allocated
+    /// before is freed.
+    pub fn reserve(&mut self, bytes: usize) {
+        if self.capacity() >= bytes {
+            return;
+        }
+        let chunks = self.chunks.get_mut();
+        chunks.clear();
+        chunks.push(Chunk::new(round_up(bytes.max(MIN_CHUNK), ALIGN)));
+        self.offset.set(0);
+        self.retired.set(0);
+    }
+
+    pub fn watermark(&self) -> Watermark {
+        let chunks = self.chunks.take();
+        let watermark = Watermark {
+            used: self.used(),
+            peak: self.peak.max(self.used()),
+            capacity: chunks.iter().map(|c| c.size).sum(),
+            chunks: chunks.len(),
+        };
+        self.chunks.set(chunks);
+        watermark
+    }
+
+    fn used(&self) -> usize {
+        self.retired.get() + self.offset.get()
+    }
+
+    fn capacity(&mut self) -> usize {
+        self.chunks.get_mut().iter().map(|c| c.size).sum()
+    }
+
+    /// Space for `layout`, valid until the next reset.
+    fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        assert!(
+            layout.align() <= ALIGN,
+            "FrameArena can't store types aligned to more than {} bytes",
+            ALIGN
+        );
+        if layout.size() == 0 {
+            // SAFETY: the alignment is never 0.
+            return unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+        }
+        // The chunks are taken out of the cell while they are looked at, so
+        // no reference to them exists while a caller writes through what
+        // was handed out.
+        let mut chunks = self.chunks.take();
+        let start = round_up(self.offset.get(), layout.align());
+        let fits = chunks
+            .last()
+            .is_some_and(|c| start.saturating_add(layout.size()) <= c.size);
+        let ptr = if fits {
+            self.offset.set(start + layout.size());
+            // SAFETY: `start + size` is within the last chunk.
+            unsafe { chunks.last().unwrap().ptr.as_ptr().add(start) }
+        } else {
+            let last = chunks.last().map_or(0, |c| c.size);
+            let size = round_up(layout.size().max(last * 2).max(MIN_CHUNK), ALIGN);
+            self.retired.set(self.retired.get() + self.offset.get());
+            self.offset.set(layout.size());
+            chunks.push(Chunk::new(size));
+            chunks.last().unwrap().ptr.as_ptr()
+        };
+        self.chunks.set(chunks);
+        // SAFETY: comes from a chunk, which is never null.
+        unsafe { NonNull::new_unchecked(ptr) }
+    }
+
+    /// Grows the allocation ending at `end` by `additional` bytes if it is
+    /// the last one made and the chunk has room.
+    fn try_grow_in_place(&self, end: *const u8, additional: usize) -> bool {
+        let chunks = self.chunks.take();
+        let grown = chunks.last().is_some_and(|c| {
+            let offset = self.offset.get();
+            // SAFETY: `offset` is within the chunk.
+            let top = unsafe { c.ptr.as_ptr().add(offset) } as *const u8;
+            top == end && offset.saturating_add(additional) <= c.size
+        });
+        if grown {
+            self.offset.set(self.offset.get() + additional);
+        }
+        self.chunks.set(chunks);
+        grown
+    }
+
+    // Each call hands out fresh space, so the mutable borrows never overlap.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()).cast::<T>();
+        // SAFETY: the space is fresh, aligned and big enough for a `T`.
+        unsafe {
+            ptr.as_ptr().write(value);
+            &mut *ptr.as_ptr()
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        let mut vec = ArenaVec::with_capacity_in(values.len(), self);
+        vec.extend_from_slice(values);
+        vec.into_slice()
+    }
+
+    /// An empty vector allocating from the arena as it grows.
+    pub fn vec<T: Copy>(&self) -> ArenaVec<'_, T> {
+        ArenaVec::new_in(self)
+    }
+
+    pub fn vec_from_iter<T: Copy>(&self, iter: impl IntoIterator<Item = T>) -> ArenaVec<'_, T> {
+        let mut vec = ArenaVec::new_in(self);
+        vec.extend(iter);
+        vec
+    }
+}
+
+fn round_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+/// A growable array in a [`FrameArena`]. Growing past the capacity moves it
+/// to a new allocation, leaving the old one unused until the reset, unless
+/// it was the last allocation and can grow in place.
+pub struct ArenaVec<'a, T: Copy> {
+    arena: &'a FrameArena,
+    ptr: NonNull<T>,
+    len: usize,
+    capacity: usize,
+    _marker: PhantomData<&'a mut [T]>,
+}
+
+impl<'a, T: Copy> ArenaVec<'a, T> {
+    pub fn new_in(arena: &'a FrameArena) -> Self {
+        Self {
+            arena,
+            ptr: NonNull::dangling(),
+            len: 0,
+            capacity: if std::mem::size_of::<T>() == 0 {
+                usize::MAX
+            } else {
+                0
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn with_capacity_in(capacity: usize, arena: &'a FrameArena) -> Self {
+        let mut vec = Self::new_in(arena);
+        vec.reserve(capacity);
+        vec
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Makes room for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed <= self.capacity {
+            return;
+        }
+        let capacity = needed.max(self.capacity * 2).max(4);
+        let size = std::mem::size_of::<T>();
+        // SAFETY: `capacity` elements starting at `ptr` are the allocation.
+        let end = unsafe { self.ptr.as_ptr().add(self.capacity) } as *const u8;
+        if self.capacity > 0
+            && self
+                .arena
+                .try_grow_in_place(end, (capacity - self.capacity) * size)
+        {
+            self.capacity = capacity;
+            return;
+        }
+        let layout = Layout::array::<T>(capacity).expect("capacity overflow");
+        let ptr = self.arena.alloc_layout(layout).cast::<T>();
+        // SAFETY: the new allocation is distinct and fits `len` elements.
+        unsafe { std::ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len) };
+        self.ptr = ptr;
+        self.capacity = capacity;
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len == self.capacity {
+            self.reserve(1);
+        }
+        // SAFETY: `len < capacity` after reserving.
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        self.reserve(values.len());
+        // SAFETY: there is room for `values` after `len`, and an `ArenaVec`
+        // can't overlap a slice borrowed while it is mutably borrowed.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                values.as_ptr(),
+                self.ptr.as_ptr().add(self.len),
+                values.len(),
+            )
+        };
+        self.len += values.len();
+    }
+
+    /// The elements, borrowed for as long as the arena is.
+    pub fn into_slice(self) -> &'a mut [T] {
+        // SAFETY: the first `len` elements are initialized, and the vector
+        // is consumed so nothing else refers to them.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T: Copy> std::ops::Deref for ArenaVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the first `len` elements are initialized.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T: Copy> std::ops::DerefMut for ArenaVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: the first `len` elements are initialized.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T: Copy> Extend<T> for ArenaVec<'a, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: Copy + std::fmt::Debug> std::fmt::Debug for ArenaVec<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,111 @@
+//! Visibility layers, for drawing some models only in some views, such as
+//! editor gizmos that the game camera shouldn't show. Each draw is on one or
+//! more of 32 layers, and each camera has a cull mask of the layers it
+//! draws. A draw shows in a camera when the two have a layer in common.
+
+/// A set of the 32 layers, used both for the layers a draw is on and for
+/// the layers a camera draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Layers(u32);
+
+impl Layers {
+    /// Layer 0, which draws are on unless put elsewhere.
+    pub const DEFAULT: Self = Self(1);
+    /// Every layer, which cameras draw unless masked.
+    pub const ALL: Self = Self(!0);
+    pub const NONE: Self = Self(0);
+
+    /// Just layer `index`, which must be under 32.
+    pub const fn layer(index: u32) -> Self {
+        assert!(index < 32, "there are only 32 layers");
+        Self(1 << index)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether the two share a layer, i.e. a draw on `self` shows in a
+    /// camera with `self` as its cull mask.
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl std::ops::BitOr for Layers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.with(other)
+    }
+}
+
+/// Names for layers, so code and tools can say `"gizmos"` rather than a
+/// layer number. Unnamed layers still work by number.
+#[derive(Debug, Clone, Default)]
+pub struct LayerNames {
+    names: [Option<String>; 32],
+}
+
+impl LayerNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names layer `index`, replacing its old name. A name already used by
+    /// another layer moves to this one.
+    pub fn set(&mut self, index: u32, name: &str) {
+        for existing in self.names.iter_mut() {
+            if existing.as_deref() == Some(name) {
+                *existing = None;
+            }
+        }
+        self.names[index as usize] = Some(name.to_string());
+    }
+
+    /// The layer called `name`.
+    pub fn get(&self, name: &str) -> Option<Layers> {
+        self.names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))
+            .map(|i| Layers::layer(i as u32))
+    }
+
+    /// The layers called `names`, or the first name that isn't known.
+    pub fn mask<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<Layers, &'a str> {
+        names.into_iter().try_fold(Layers::NONE, |mask, name| {
+            self.get(name).map(|layer| mask | layer).ok_or(name)
+        })
+    }
+
+    pub fn name(&self, index: u32) -> Option<&str> {
+        self.names.get(index as usize)?.as_deref()
+    }
+
+    /// The named layers in `layers`, for logging.
+    pub fn names(&self, layers: Layers) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| layers.intersects(Layers::layer(*i as u32)))
+            .filter_map(|(_, name)| name.as_deref())
+    }
+}
@@ -0,0 +1,925 @@
+pub mod memory;
+pub mod readback;
+pub mod uniform_ring;
+
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+pub use memory::{AllocError, Allocator, MemoryCategory, MemoryStats, MemoryToken};
+pub use readback::{
+    ReadbackBatch, ReadbackData, ReadbackError, ReadbackFuture, ReadbackPolicy, ReadbackQueue,
+    ReadbackResult,
+};
+pub use uniform_ring::{DynamicOffset, UniformFrame, UniformRing};
+
+/// Environment variable that overrides the adapter selection by name, e.g.
+/// `WGPU_ADAPTER_NAME=nvidia`. Matching is a case-insensitive substring test.
+pub const ADAPTER_NAME_ENV: &str = "WGPU_ADAPTER_NAME";
+
+/// How to pick the adapter when more than one is available.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterSelector {
+    /// Position in the list returned by [`available_adapters`].
+    Index(usize),
+    /// First adapter whose name contains this string, ignoring case.
+    NameContains(String),
+    /// Best adapter for the given preference. `HighPerformance` ranks
+    /// discrete > integrated > virtual > cpu, `LowPower` ranks integrated >
+    /// discrete > virtual > cpu. Ties go to the adapter enumerated first.
+    Power(wgpu::PowerPreference),
+}
+
+impl Default for AdapterSelector {
+    fn default() -> Self {
+        Self::Power(wgpu::PowerPreference::HighPerformance)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    pub backends: wgpu::Backends,
+    pub adapter_selector: AdapterSelector,
+    /// The output mode to try for the surface. See [`select_surface_format`].
+    pub output_mode: OutputMode,
+    /// How bright UI white shows on HDR surfaces, in nits.
+    pub paper_white_nits: f32,
+    pub render_settings: crate::variant::RenderSettings,
+    /// Draws the scene into an HDR target and exposes it for how bright it
+    /// is, see [`crate::tonemap`]. `None` draws it straight into the
+    /// surface, as it comes out.
+    pub auto_exposure: Option<crate::exposure::ExposureSettings>,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            adapter_selector: AdapterSelector::default(),
+            output_mode: OutputMode::default(),
+            paper_white_nits: DEFAULT_PAPER_WHITE_NITS,
+            render_settings: Default::default(),
+            auto_exposure: None,
+        }
+    }
+}
+
+impl ContextOptions {
+    /// Default options, with the selector replaced by [`ADAPTER_NAME_ENV`] if set.
+    pub fn from_env() -> Self {
+        let mut options = Self::default();
+        if let Some(name) = adapter_name_override() {
+            options.adapter_selector = AdapterSelector::NameContains(name);
+        }
+        options
+    }
+}
+
+/// How colors are presented to the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// An sRGB surface, the only mode available on most displays.
+    #[default]
+    Sdr,
+    /// A 10 bit surface with PQ encoded values, as HDR10 displays expect.
+    Hdr10,
+    /// A linear `Rgba16Float` surface where 1.0 is
+    /// [`SCRGB_REFERENCE_NITS`] and brighter values go above it.
+    ScRgbLinear,
+}
+
+/// Luminance of 1.0 in scRGB.
+pub const SCRGB_REFERENCE_NITS: f32 = 80.0;
+
+/// The reference white of ITU-R BT.2408, for SDR content like UI shown on
+/// an HDR display.
+pub const DEFAULT_PAPER_WHITE_NITS: f32 = 203.0;
+
+impl OutputMode {
+    /// The factor to scale linear colors by so SDR white, e.g. UI, shows at
+    /// `paper_white_nits` rather than at full HDR brightness.
+    pub fn paper_white_scale(self, paper_white_nits: f32) -> f32 {
+        match self {
+            OutputMode::Sdr => 1.0,
+            OutputMode::Hdr10 => paper_white_nits / PQ_MAX_NITS,
+            OutputMode::ScRgbLinear => paper_white_nits / SCRGB_REFERENCE_NITS,
+        }
+    }
+}
+
+/// The usages beyond `RENDER_ATTACHMENT` to configure a surface with, which
+/// is `COPY_SRC` where screenshots can copy from it. wgpu doesn't report
+/// what a surface supports, and configuring one with more is fatal, so this
+/// goes by backend: GL and Metal surfaces can't be copied from.
+pub fn surface_copy_usage(backend: wgpu::Backend) -> wgpu::TextureUsages {
+    match backend {
+        wgpu::Backend::Vulkan | wgpu::Backend::Dx12 | wgpu::Backend::BrowserWebGpu => {
+            wgpu::TextureUsages::COPY_SRC
+        }
+        _ => wgpu::TextureUsages::empty(),
+    }
+}
+
+/// Picks the surface format for `mode` from those the surface supports,
+/// falling back to SDR when the mode isn't available. Returns the format and
+/// the mode actually used.
+///
+/// HDR10 prefers `Rgb10a2Unorm` and takes `Rgba16Float` otherwise. wgpu
+/// doesn't expose the swapchain color space, so either relies on the
+/// platform presenting it as PQ; only the UI composite encodes PQ so far.
+pub fn select_surface_format(
+    formats: &[wgpu::TextureFormat],
+    mode: OutputMode,
+) -> (wgpu::TextureFormat, OutputMode) {
+    let hdr = match mode {
+        OutputMode::Sdr => None,
+        OutputMode::Hdr10 => {
+            let format = [
+                wgpu::TextureFormat::Rgb10a2Unorm,
+                wgpu::TextureFormat::Rgba16Float,
+            ]
+            .into_iter()
+            .find(|f| formats.contains(f));
+            if format.is_none() {
+                log::warn!("The surface has no 10 bit or float format, falling back to SDR");
+            }
+            format
+        }
+        OutputMode::ScRgbLinear => {
+            let format = formats
+                .iter()
+                .copied()
+                .find(|f| *f == wgpu::TextureFormat::Rgba16Float);
+            if format.is_none() {
+                log::warn!("The surface has no Rgba16Float format, falling back to SDR");
+            }
+            format
+        }
+    };
+    match hdr {
+        Some(format) => (format, mode),
+        None => {
+            // Shaders assume an sRGB surface, otherwise colors come out darker.
+            let format = formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb())
+                .unwrap_or(formats[0]);
+            (format, OutputMode::Sdr)
+        }
+    }
+}
+
+/// The peak luminance of the PQ curve.
+pub const PQ_MAX_NITS: f32 = 10000.0;
+
+/// Encodes a luminance with the SMPTE ST 2084 (PQ) curve used by HDR10.
+pub fn pq_encode(nits: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let y = (nits / PQ_MAX_NITS).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+fn adapter_name_override() -> Option<String> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            None
+        } else {
+            std::env::var(ADAPTER_NAME_ENV).ok().filter(|name| !name.is_empty())
+        }
+    }
+}
+
+fn device_type_rank(preference: wgpu::PowerPreference, device_type: wgpu::DeviceType) -> u32 {
+    use wgpu::DeviceType::*;
+    match (preference, device_type) {
+        (wgpu::PowerPreference::HighPerformance, DiscreteGpu) => 0,
+        (wgpu::PowerPreference::HighPerformance, IntegratedGpu) => 1,
+        (wgpu::PowerPreference::LowPower, IntegratedGpu) => 0,
+        (wgpu::PowerPreference::LowPower, DiscreteGpu) => 1,
+        (_, VirtualGpu) => 2,
+        (_, Cpu) => 3,
+        _ => 4,
+    }
+}
+
+/// Picks an adapter out of `adapters` and explains why. Returns `None` if
+/// the selector doesn't match anything.
+pub fn select_adapter(
+    adapters: &[wgpu::AdapterInfo],
+    selector: &AdapterSelector,
+) -> Option<(usize, String)> {
+    match selector {
+        AdapterSelector::Index(i) => adapters
+            .get(*i)
+            .map(|_| (*i, format!("adapter index {} was requested", i))),
+        AdapterSelector::NameContains(name) => {
+            let needle = name.to_lowercase();
+            adapters
+                .iter()
+                .position(|info| info.name.to_lowercase().contains(&needle))
+                .map(|i| (i, format!("name matches {:?}", name)))
+        }
+        AdapterSelector::Power(preference) => adapters
+            .iter()
+            .enumerate()
+            // min_by_key keeps the first of equal elements, which gives us the
+            // enumeration order tie-break.
+            .min_by_key(|(_, info)| device_type_rank(*preference, info.device_type))
+            .map(|(i, info)| {
+                (
+                    i,
+                    format!("{:?} is preferred for {:?}", info.device_type, preference),
+                )
+            }),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn compatible_adapters(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+    backends: wgpu::Backends,
+) -> Vec<wgpu::Adapter> {
+    instance
+        .enumerate_adapters(backends)
+        .filter(|adapter| adapter.is_surface_supported(surface))
+        .collect()
+}
+
+/// Info on every adapter that can present to `surface`, in the order
+/// [`AdapterSelector::Index`] refers to them. Always empty on the web.
+pub fn available_adapters(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+    backends: wgpu::Backends,
+) -> Vec<wgpu::AdapterInfo> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let _ = (instance, surface, backends);
+            Vec::new()
+        } else {
+            compatible_adapters(instance, surface, backends)
+                .iter()
+                .map(|adapter| adapter.get_info())
+                .collect()
+        }
+    }
+}
+
+/// Picks an adapter that can present to `surface` according to `options`.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+    options: &ContextOptions,
+) -> Option<wgpu::Adapter> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            // Adapters can't be enumerated on the web, so only the power
+            // preference is meaningful there.
+            let power_preference = match options.adapter_selector {
+                AdapterSelector::Power(preference) => preference,
+                _ => wgpu::PowerPreference::default(),
+            };
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference,
+                    compatible_surface: Some(surface),
+                    force_fallback_adapter: false,
+                })
+                .await
+        } else {
+            let mut adapters = compatible_adapters(instance, surface, options.backends);
+            let infos = adapters.iter().map(|a| a.get_info()).collect::<Vec<_>>();
+
+            let (index, reason) = match select_adapter(&infos, &options.adapter_selector) {
+                Some(selected) => selected,
+                None => {
+                    log::warn!(
+                        "No adapter matches {:?}, falling back to the default",
+                        options.adapter_selector
+                    );
+                    select_adapter(&infos, &AdapterSelector::default())?
+                }
+            };
+            log::warn!(
+                "Using adapter {:?} ({:?}, {:?}): {}",
+                infos[index].name,
+                infos[index].device_type,
+                infos[index].backend,
+                reason
+            );
+            Some(adapters.swap_remove(index))
+        }
+    }
+}
+
+/// The limits to request from `adapter`: wgpu's defaults where the adapter
+/// meets WebGPU, otherwise the WebGL2 set, e.g. on GL or older mobile GPUs,
+/// raised to the texture sizes the adapter actually supports.
+pub fn device_limits(adapter: &wgpu::Adapter) -> wgpu::Limits {
+    if adapter.get_downlevel_capabilities().is_webgpu_compliant() {
+        wgpu::Limits::default()
+    } else {
+        wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+    }
+}
+
+/// First fit allocator over `0..size` that merges neighbouring free ranges.
+#[derive(Debug)]
+struct RangeAllocator {
+    size: u32,
+    /// Sorted, non-overlapping and non-adjacent.
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    fn new(size: u32) -> Self {
+        Self {
+            size,
+            // One free range covering everything, not a list of offsets.
+            #[allow(clippy::single_range_in_vec_init)]
+            free: vec![0..size],
+        }
+    }
+
+    fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        if count == 0 {
+            return Some(0..0);
+        }
+        let i = self.free.iter().position(|r| r.end - r.start >= count)?;
+        let start = self.free[i].start;
+        self.free[i].start += count;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+        Some(start..start + count)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let i = self.free.partition_point(|r| r.start < range.start);
+        self.free.insert(i, range);
+        // Merge with the next range, then with the previous one.
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            self.free[i].end = self.free.remove(i + 1).end;
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            self.free[i - 1].end = self.free.remove(i).end;
+        }
+    }
+
+    fn free_count(&self) -> u32 {
+        self.free.iter().map(|r| r.end - r.start).sum()
+    }
+
+    fn largest_free(&self) -> u32 {
+        self.free.iter().map(|r| r.end - r.start).max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+struct PageAllocator {
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+}
+
+impl PageAllocator {
+    fn new(vertices: u32, indices: u32) -> Self {
+        Self {
+            vertices: RangeAllocator::new(vertices),
+            indices: RangeAllocator::new(indices),
+        }
+    }
+
+    /// Reserves space on the first page with room for both ranges.
+    fn reserve_existing(
+        pages: &mut [PageAllocator],
+        vertex_count: u32,
+        index_count: u32,
+    ) -> Option<(usize, Range<u32>, Range<u32>)> {
+        pages.iter_mut().enumerate().find_map(|(page, allocator)| {
+            if allocator.vertices.largest_free() < vertex_count
+                || allocator.indices.largest_free() < index_count
+            {
+                return None;
+            }
+            let vertex_range = allocator.vertices.allocate(vertex_count).unwrap();
+            let index_range = allocator.indices.allocate(index_count).unwrap();
+            Some((page, vertex_range, index_range))
+        })
+    }
+}
+
+type SharedPageAllocators = Arc<Mutex<Vec<PageAllocator>>>;
+
+/// One pair of shared buffers in a [`GeometryPool`].
+pub struct GeometryPage {
+    pub vertex_buffer: Arc<wgpu::Buffer>,
+    pub index_buffer: Arc<wgpu::Buffer>,
+    _memory: [MemoryToken; 2],
+}
+
+/// A mesh's share of a [`GeometryPool`]. The ranges are returned to the pool
+/// when this is dropped.
+pub struct GeometryAllocation {
+    pub page: usize,
+    pub vertex_buffer: Arc<wgpu::Buffer>,
+    pub index_buffer: Arc<wgpu::Buffer>,
+    /// In vertices, not bytes.
+    pub vertex_range: Range<u32>,
+    /// In indices, not bytes. Pass this straight to `draw_indexed`.
+    pub index_range: Range<u32>,
+    /// Indices are relative to the allocation, so this is added to them.
+    pub base_vertex: i32,
+    allocators: SharedPageAllocators,
+}
+
+impl Drop for GeometryAllocation {
+    fn drop(&mut self) {
+        let mut allocators = self.allocators.lock().unwrap();
+        if let Some(page) = allocators.get_mut(self.page) {
+            page.vertices.free(self.vertex_range.clone());
+            page.indices.free(self.index_range.clone());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeometryPoolStats {
+    pub pages: usize,
+    pub vertex_capacity: u32,
+    pub vertices_used: u32,
+    pub index_capacity: u32,
+    pub indices_used: u32,
+    /// 0 when all free vertex space is contiguous within each page, towards 1
+    /// as it gets split into small holes. A good hint for [`GeometryPool::compact`].
+    pub fragmentation: f32,
+}
+
+/// Large vertex and index buffers shared by many small meshes, so loading
+/// lots of props doesn't create lots of tiny buffers. All meshes in a pool
+/// use the same vertex layout (`vertex_stride`) and 32 bit indices.
+pub struct GeometryPool {
+    label: String,
+    vertex_stride: wgpu::BufferAddress,
+    page_vertices: u32,
+    page_indices: u32,
+    pages: Vec<GeometryPage>,
+    allocators: SharedPageAllocators,
+}
+
+impl GeometryPool {
+    pub fn new(
+        label: &str,
+        vertex_stride: wgpu::BufferAddress,
+        page_vertices: u32,
+        page_indices: u32,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            vertex_stride,
+            page_vertices,
+            page_indices,
+            pages: Vec::new(),
+            allocators: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn pages(&self) -> &[GeometryPage] {
+        &self.pages
+    }
+
+    /// The size of a new page that has room for at least the given counts.
+    fn page_size(&self, vertex_count: u32, index_count: u32) -> (u32, u32) {
+        (
+            self.page_vertices.max(vertex_count),
+            self.page_indices.max(index_count),
+        )
+    }
+
+    fn create_page(
+        &self,
+        device: &wgpu::Device,
+        vertices: u32,
+        indices: u32,
+    ) -> Result<GeometryPage, AllocError> {
+        let allocator = Allocator::new(device);
+        let (vertex_buffer, vertex_memory) = allocator.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(&format!("{} Vertex Page", self.label)),
+                size: vertices as wgpu::BufferAddress * self.vertex_stride,
+                usage: wgpu::BufferUsages::VERTEX
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Vertex,
+        )?;
+        let (index_buffer, index_memory) = allocator.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(&format!("{} Index Page", self.label)),
+                size: indices as wgpu::BufferAddress * 4,
+                usage: wgpu::BufferUsages::INDEX
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Index,
+        )?;
+        Ok(GeometryPage {
+            vertex_buffer: Arc::new(vertex_buffer),
+            index_buffer: Arc::new(index_buffer),
+            _memory: [vertex_memory, index_memory],
+        })
+    }
+
+    /// Reserves space on the first page with room, adding a page if none has
+    /// any. Pages are at least as big as the sizes given to [`GeometryPool::new`].
+    fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        vertex_count: u32,
+        index_count: u32,
+    ) -> Result<(usize, Range<u32>, Range<u32>), AllocError> {
+        let mut allocators = self.allocators.lock().unwrap();
+        if let Some(found) =
+            PageAllocator::reserve_existing(&mut allocators, vertex_count, index_count)
+        {
+            return Ok(found);
+        }
+
+        let (vertices, indices) = self.page_size(vertex_count, index_count);
+        log::info!(
+            "{}: adding page {} ({} vertices, {} indices)",
+            self.label,
+            self.pages.len(),
+            vertices,
+            indices
+        );
+        let page = self.create_page(device, vertices, indices)?;
+        self.pages.push(page);
+        let mut allocator = PageAllocator::new(vertices, indices);
+        let vertex_range = allocator.vertices.allocate(vertex_count).unwrap();
+        let index_range = allocator.indices.allocate(index_count).unwrap();
+        allocators.push(allocator);
+        Ok((allocators.len() - 1, vertex_range, index_range))
+    }
+
+    /// Uploads a mesh into the pool. `V` must match the pool's vertex stride.
+    /// Fails if a page had to be added and there was no memory for it.
+    pub fn allocate<V: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<GeometryAllocation, AllocError> {
+        assert_eq!(
+            std::mem::size_of::<V>() as wgpu::BufferAddress,
+            self.vertex_stride
+        );
+        let (page, vertex_range, index_range) =
+            self.reserve(device, vertices.len() as u32, indices.len() as u32)?;
+        let allocation = GeometryAllocation {
+            page,
+            vertex_buffer: self.pages[page].vertex_buffer.clone(),
+            index_buffer: self.pages[page].index_buffer.clone(),
+            base_vertex: vertex_range.start as i32,
+            vertex_range,
+            index_range,
+            allocators: self.allocators.clone(),
+        };
+        queue.write_buffer(
+            &allocation.vertex_buffer,
+            allocation.vertex_range.start as wgpu::BufferAddress * self.vertex_stride,
+            bytemuck::cast_slice(vertices),
+        );
+        queue.write_buffer(
+            &allocation.index_buffer,
+            allocation.index_range.start as wgpu::BufferAddress * 4,
+            bytemuck::cast_slice(indices),
+        );
+        Ok(allocation)
+    }
+
+    pub fn stats(&self) -> GeometryPoolStats {
+        let allocators = self.allocators.lock().unwrap();
+        let mut stats = GeometryPoolStats {
+            pages: allocators.len(),
+            vertex_capacity: 0,
+            vertices_used: 0,
+            index_capacity: 0,
+            indices_used: 0,
+            fragmentation: 0.0,
+        };
+        let mut free = 0;
+        let mut largest_free = 0;
+        for allocator in allocators.iter() {
+            stats.vertex_capacity += allocator.vertices.size;
+            stats.vertices_used += allocator.vertices.size - allocator.vertices.free_count();
+            stats.index_capacity += allocator.indices.size;
+            stats.indices_used += allocator.indices.size - allocator.indices.free_count();
+            free += allocator.vertices.free_count();
+            largest_free += allocator.vertices.largest_free();
+        }
+        if free > 0 {
+            stats.fragmentation = 1.0 - largest_free as f32 / free as f32;
+        }
+        stats
+    }
+
+    /// Moves every allocation into freshly packed pages, updating them in
+    /// place. `allocations` must be every live allocation of this pool, since
+    /// the old pages are released afterwards. If the new pages can't all be
+    /// created, nothing moves.
+    pub fn compact(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        allocations: &mut [&mut GeometryAllocation],
+    ) -> Result<(), AllocError> {
+        let live = {
+            let stats = self.stats();
+            (stats.vertices_used, stats.indices_used)
+        };
+        let passed = allocations.iter().fold((0, 0), |(v, i), a| {
+            (
+                v + a.vertex_range.len() as u32,
+                i + a.index_range.len() as u32,
+            )
+        });
+        assert_eq!(
+            live, passed,
+            "compact needs every live allocation of the pool"
+        );
+
+        // Old pages stay alive until the copies below have been recorded; the
+        // encoder keeps them alive on the GPU side after that.
+        let old_pages = std::mem::take(&mut self.pages);
+        let old_allocators = std::mem::take(&mut *self.allocators.lock().unwrap());
+        let mut reserved = Vec::with_capacity(allocations.len());
+        for allocation in allocations.iter() {
+            let vertex_count = allocation.vertex_range.len() as u32;
+            let index_count = allocation.index_range.len() as u32;
+            match self.reserve(device, vertex_count, index_count) {
+                Ok(reservation) => reserved.push(reservation),
+                Err(e) => {
+                    self.pages = old_pages;
+                    *self.allocators.lock().unwrap() = old_allocators;
+                    return Err(e);
+                }
+            }
+        }
+        for (allocation, (page, vertex_range, index_range)) in allocations.iter_mut().zip(reserved)
+        {
+            let vertex_count = vertex_range.len() as u32;
+            let index_count = index_range.len() as u32;
+            let (old, new) = (&old_pages[allocation.page], &self.pages[page]);
+            encoder.copy_buffer_to_buffer(
+                &old.vertex_buffer,
+                allocation.vertex_range.start as wgpu::BufferAddress * self.vertex_stride,
+                &new.vertex_buffer,
+                vertex_range.start as wgpu::BufferAddress * self.vertex_stride,
+                vertex_count as wgpu::BufferAddress * self.vertex_stride,
+            );
+            encoder.copy_buffer_to_buffer(
+                &old.index_buffer,
+                allocation.index_range.start as wgpu::BufferAddress * 4,
+                &new.index_buffer,
+                index_range.start as wgpu::BufferAddress * 4,
+                index_count as wgpu::BufferAddress * 4,
+            );
+
+            allocation.page = page;
+            allocation.vertex_buffer = new.vertex_buffer.clone();
+            allocation.index_buffer = new.index_buffer.clone();
+            allocation.base_vertex = vertex_range.start as i32;
+            allocation.vertex_range = vertex_range;
+            allocation.index_range = index_range;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, device_type: wgpu::DeviceType) -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: name.to_owned(),
+            vendor: 0,
+            device: 0,
+            device_type,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        }
+    }
+
+    fn adapters() -> Vec<wgpu::AdapterInfo> {
+        vec![
+            info("llvmpipe", wgpu::DeviceType::Cpu),
+            info("Intel UHD 630", wgpu::DeviceType::IntegratedGpu),
+            info("NVIDIA RTX 3070", wgpu::DeviceType::DiscreteGpu),
+            info("AMD Radeon RX 6800", wgpu::DeviceType::DiscreteGpu),
+        ]
+    }
+
+    fn selected(adapters: &[wgpu::AdapterInfo], selector: &AdapterSelector) -> Option<usize> {
+        select_adapter(adapters, selector).map(|(i, _)| i)
+    }
+
+    #[test]
+    fn power_preference_ranks_device_types() {
+        let adapters = adapters();
+        let high = AdapterSelector::Power(wgpu::PowerPreference::HighPerformance);
+        let low = AdapterSelector::Power(wgpu::PowerPreference::LowPower);
+        assert_eq!(selected(&adapters, &high), Some(2));
+        assert_eq!(selected(&adapters, &low), Some(1));
+    }
+
+    #[test]
+    fn power_preference_falls_back_past_missing_types() {
+        let adapters = vec![
+            info("llvmpipe", wgpu::DeviceType::Cpu),
+            info("virtio", wgpu::DeviceType::VirtualGpu),
+            info("unknown", wgpu::DeviceType::Other),
+        ];
+        let high = AdapterSelector::Power(wgpu::PowerPreference::HighPerformance);
+        assert_eq!(selected(&adapters, &high), Some(1));
+        assert_eq!(selected(&adapters[..1], &high), Some(0));
+        assert_eq!(selected(&adapters[2..], &high), Some(0));
+        assert_eq!(selected(&[], &high), None);
+    }
+
+    #[test]
+    fn equal_ranks_keep_enumeration_order() {
+        let mut adapters = adapters();
+        let high = AdapterSelector::Power(wgpu::PowerPreference::HighPerformance);
+        adapters.swap(2, 3);
+        assert_eq!(adapters[2].name, "AMD Radeon RX 6800");
+        assert_eq!(selected(&adapters, &high), Some(2));
+    }
+
+    #[test]
+    fn only_copyable_surfaces_get_copy_src() {
+        assert_eq!(
+            surface_copy_usage(wgpu::Backend::Vulkan),
+            wgpu::TextureUsages::COPY_SRC
+        );
+        assert!(surface_copy_usage(wgpu::Backend::Gl).is_empty());
+        assert!(surface_copy_usage(wgpu::Backend::Metal).is_empty());
+    }
+
+    #[test]
+    fn pq_matches_the_reference_points() {
+        // Values from ITU-R BT.2100 and BT.2408.
+        for (nits, expected) in [
+            (0.0, 7.3e-7),
+            (100.0, 0.5081),
+            (203.0, 0.5807),
+            (1000.0, 0.7518),
+            (10000.0, 1.0),
+        ] {
+            let encoded = pq_encode(nits);
+            assert!(
+                (encoded - expected).abs() < 5e-4,
+                "{} nits encoded to {}, expected {}",
+                nits,
+                encoded,
+                expected
+            );
+        }
+        assert_eq!(pq_encode(20000.0), pq_encode(PQ_MAX_NITS));
+        assert_eq!(pq_encode(-1.0), pq_encode(0.0));
+    }
+
+    #[test]
+    fn hdr_modes_pick_hdr_formats_or_fall_back() {
+        use wgpu::TextureFormat::*;
+        let sdr = [Bgra8Unorm, Bgra8UnormSrgb];
+        assert_eq!(
+            select_surface_format(
+                &[Bgra8UnormSrgb, Rgba16Float, Rgb10a2Unorm],
+                OutputMode::Hdr10
+            ),
+            (Rgb10a2Unorm, OutputMode::Hdr10)
+        );
+        assert_eq!(
+            select_surface_format(&[Bgra8UnormSrgb, Rgba16Float], OutputMode::Hdr10),
+            (Rgba16Float, OutputMode::Hdr10)
+        );
+        assert_eq!(
+            select_surface_format(&[Rgb10a2Unorm, Rgba16Float], OutputMode::ScRgbLinear),
+            (Rgba16Float, OutputMode::ScRgbLinear)
+        );
+        for mode in [OutputMode::Sdr, OutputMode::Hdr10, OutputMode::ScRgbLinear] {
+            assert_eq!(
+                select_surface_format(&sdr, mode),
+                (Bgra8UnormSrgb, OutputMode::Sdr)
+            );
+        }
+        assert_eq!(
+            select_surface_format(&[Rgba16Float, Bgra8UnormSrgb], OutputMode::Sdr),
+            (Bgra8UnormSrgb, OutputMode::Sdr)
+        );
+    }
+
+    #[test]
+    fn index_and_name_override_power_ranking() {
+        let adapters = adapters();
+        assert_eq!(selected(&adapters, &AdapterSelector::Index(0)), Some(0));
+        assert_eq!(selected(&adapters, &AdapterSelector::Index(4)), None);
+        let name = |s: &str| AdapterSelector::NameContains(s.to_owned());
+        assert_eq!(selected(&adapters, &name("intel")), Some(1));
+        assert_eq!(selected(&adapters, &name("RADEON")), Some(3));
+        assert_eq!(selected(&adapters, &name("apple")), None);
+    }
+
+    #[test]
+    fn ranges_are_allocated_first_fit() {
+        let mut ranges = RangeAllocator::new(10);
+        assert_eq!(ranges.allocate(0), Some(0..0));
+        assert_eq!(ranges.allocate(4), Some(0..4));
+        assert_eq!(ranges.allocate(4), Some(4..8));
+        assert_eq!(ranges.allocate(3), None);
+        assert_eq!(ranges.allocate(2), Some(8..10));
+        assert_eq!(ranges.allocate(1), None);
+        assert_eq!(ranges.free_count(), 0);
+        assert_eq!(ranges.largest_free(), 0);
+
+        // A hole that's too small is skipped for a later one that fits.
+        ranges.free(0..2);
+        ranges.free(4..8);
+        assert_eq!(ranges.allocate(3), Some(4..7));
+        assert_eq!(ranges.allocate(2), Some(0..2));
+    }
+
+    #[test]
+    fn freed_neighbours_merge() {
+        let mut ranges = RangeAllocator::new(12);
+        let parts: Vec<_> = (0..4).map(|_| ranges.allocate(3).unwrap()).collect();
+
+        // Next, then previous, then both.
+        ranges.free(parts[1].clone());
+        ranges.free(parts[0].clone());
+        assert_eq!(ranges.free, vec![0..6]);
+        ranges.free(parts[3].clone());
+        assert_eq!(ranges.free, vec![0..6, 9..12]);
+        assert_eq!(ranges.largest_free(), 6);
+        ranges.free(parts[2].clone());
+        assert_eq!(ranges.free, vec![0..12]);
+        assert_eq!(ranges.free_count(), 12);
+
+        // Empty ranges don't leave zero length entries behind.
+        ranges.free(5..5);
+        assert_eq!(ranges.free, vec![0..12]);
+    }
+
+    #[test]
+    fn pages_grow_when_nothing_fits() {
+        let pool = GeometryPool::new("Test", 4, 100, 300);
+        let mut pages = Vec::new();
+        assert!(PageAllocator::reserve_existing(&mut pages, 10, 30).is_none());
+
+        let (vertices, indices) = pool.page_size(10, 30);
+        assert_eq!((vertices, indices), (100, 300));
+        pages.push(PageAllocator::new(vertices, indices));
+        assert_eq!(
+            PageAllocator::reserve_existing(&mut pages, 60, 30),
+            Some((0, 0..60, 0..30))
+        );
+        // Enough indices but not enough vertices left on the first page.
+        assert!(PageAllocator::reserve_existing(&mut pages, 60, 30).is_none());
+        assert_eq!(
+            PageAllocator::reserve_existing(&mut pages, 40, 270),
+            Some((0, 60..100, 30..300))
+        );
+
+        // Meshes bigger than a page get a page of their own size.
+        assert_eq!(pool.page_size(500, 30), (500, 300));
+        pages.push(PageAllocator::new(500, 300));
+        assert_eq!(
+            PageAllocator::reserve_existing(&mut pages, 500, 30),
+            Some((1, 0..500, 0..30))
+        );
+
+        // Freed space on an earlier page is used again before later pages.
+        pages[0].vertices.free(0..60);
+        pages[0].indices.free(0..30);
+        pages.push(PageAllocator::new(100, 300));
+        assert_eq!(
+            PageAllocator::reserve_existing(&mut pages, 50, 20),
+            Some((0, 0..50, 0..20))
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! GPU memory accounting, and creation of buffers and textures that fails
+//! with an [`AllocError`] instead of a device error when memory runs out.
+//!
+//! Sizes are what the resources hold, not what the driver allocates, which
+//! adds alignment and padding. The counts cover every device, as
+//! [`CountingAllocator`](crate::stats::CountingAllocator) covers every
+//! thread.
+//!
+//! Every buffer and texture the renderer and loaders create goes through
+//! [`Allocator`], with three exceptions that are created directly and not
+//! counted: the mappable staging buffers of
+//! [`ReadbackQueue`](super::ReadbackQueue), which live in host memory; the
+//! buffers of the unfinished glTF loader, which is never reached; and the
+//! targets and inputs the `validate` binary makes to check the GPU output.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MemoryCategory {
+    Vertex,
+    Index,
+    /// Sampled textures, such as material maps.
+    Texture,
+    /// Textures rendered to, such as depth buffers.
+    Target,
+    Uniform,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 5] = [
+        MemoryCategory::Vertex,
+        MemoryCategory::Index,
+        MemoryCategory::Texture,
+        MemoryCategory::Target,
+        MemoryCategory::Uniform,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+static LIVE: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static PEAK: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static BUDGET: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Limits the memory [`Allocator`] hands out to `bytes` in total, failing
+/// allocations past it as if the device had run out. For trying fallbacks
+/// without filling a real GPU, or holding a small device's budget on a big
+/// one. `None` removes the limit.
+pub fn set_budget(bytes: Option<u64>) {
+    BUDGET.store(bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
+/// Accounts for `bytes` of `category` until dropped. Keep it next to the
+/// resource it was created with.
+#[derive(Debug)]
+pub struct MemoryToken {
+    category: MemoryCategory,
+    bytes: u64,
+}
+
+impl MemoryToken {
+    fn new(category: MemoryCategory, bytes: u64) -> Self {
+        let live = LIVE[category.index()].fetch_add(bytes, Ordering::Relaxed) + bytes;
+        PEAK[category.index()].fetch_max(live, Ordering::Relaxed);
+        Self { category, bytes }
+    }
+
+    pub fn category(&self) -> MemoryCategory {
+        self.category
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryToken {
+    fn drop(&mut self) {
+        LIVE[self.category.index()].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Bytes held now and at most since the start, or since
+/// [`MemoryStats::reset_peaks`], per category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    live: [u64; 5],
+    peak: [u64; 5],
+}
+
+impl MemoryStats {
+    pub fn snapshot() -> Self {
+        Self {
+            live: LIVE.each_ref().map(|n| n.load(Ordering::Relaxed)),
+            peak: PEAK.each_ref().map(|n| n.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Lowers the high water marks to what is held now.
+    pub fn reset_peaks() {
+        for (peak, live) in PEAK.iter().zip(&LIVE) {
+            peak.store(live.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    pub fn live(&self, category: MemoryCategory) -> u64 {
+        self.live[category.index()]
+    }
+
+    pub fn peak(&self, category: MemoryCategory) -> u64 {
+        self.peak[category.index()]
+    }
+
+    pub fn total_live(&self) -> u64 {
+        self.live.iter().sum()
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, category) in MemoryCategory::ALL.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{:?} {} KiB (peak {} KiB)",
+                category,
+                self.live(category) / 1024,
+                self.peak(category) / 1024
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllocError {
+    /// The device, or the [`set_budget`] limit, had no room left. Loaders
+    /// can retry with less, e.g. a smaller texture.
+    OutOfMemory {
+        label: Option<String>,
+        category: MemoryCategory,
+        bytes: u64,
+    },
+    /// Any other error creating the resource, e.g. a size over the limits.
+    Invalid(String),
+}
+
+impl AllocError {
+    pub fn is_out_of_memory(&self) -> bool {
+        matches!(self, AllocError::OutOfMemory { .. })
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::OutOfMemory {
+                label,
+                category,
+                bytes,
+            } => write!(
+                f,
+                "out of GPU memory for {} ({:?}, {} bytes)",
+                label.as_deref().unwrap_or("an unnamed resource"),
+                category,
+                bytes
+            ),
+            AllocError::Invalid(message) => {
+                write!(f, "couldn't create a GPU resource: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+/// Creates buffers and textures on a device, accounting for their memory
+/// and catching their errors in error scopes. Popping a scope is waited on,
+/// which returns at once with the native and WebGL backends.
+pub struct Allocator<'a> {
+    device: &'a wgpu::Device,
+}
+
+impl<'a> Allocator<'a> {
+    pub fn new(device: &'a wgpu::Device) -> Self {
+        Self { device }
+    }
+
+    pub fn create_buffer(
+        &self,
+        desc: &wgpu::BufferDescriptor,
+        category: MemoryCategory,
+    ) -> Result<(wgpu::Buffer, MemoryToken), AllocError> {
+        self.create(desc.label, category, desc.size, || {
+            self.device.create_buffer(desc)
+        })
+    }
+
+    pub fn create_buffer_init(
+        &self,
+        desc: &wgpu::util::BufferInitDescriptor,
+        category: MemoryCategory,
+    ) -> Result<(wgpu::Buffer, MemoryToken), AllocError> {
+        self.create(desc.label, category, desc.contents.len() as u64, || {
+            self.device.create_buffer_init(desc)
+        })
+    }
+
+    pub fn create_texture(
+        &self,
+        desc: &wgpu::TextureDescriptor,
+        category: MemoryCategory,
+    ) -> Result<(wgpu::Texture, MemoryToken), AllocError> {
+        self.create(desc.label, category, texture_bytes(desc), || {
+            self.device.create_texture(desc)
+        })
+    }
+
+    fn create<T>(
+        &self,
+        label: Option<&str>,
+        category: MemoryCategory,
+        bytes: u64,
+        create: impl FnOnce() -> T,
+    ) -> Result<(T, MemoryToken), AllocError> {
+        let out_of_memory = || AllocError::OutOfMemory {
+            label: label.map(str::to_string),
+            category,
+            bytes,
+        };
+        let live = MemoryStats::snapshot().total_live();
+        if live.saturating_add(bytes) > BUDGET.load(Ordering::Relaxed) {
+            return Err(out_of_memory());
+        }
+
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let resource = create();
+        let invalid = pollster::block_on(self.device.pop_error_scope());
+        let oom = pollster::block_on(self.device.pop_error_scope());
+        if oom.is_some() {
+            return Err(out_of_memory());
+        }
+        if let Some(error) = invalid {
+            return Err(AllocError::Invalid(error.to_string()));
+        }
+        Ok((resource, MemoryToken::new(category, bytes)))
+    }
+}
+
+/// The bytes a texture's texels take, over every mip, layer and sample.
+pub fn texture_bytes(desc: &wgpu::TextureDescriptor) -> u64 {
+    let (block_width, block_height) = desc.format.block_dimensions();
+    let block_size = desc.format.block_size(None).unwrap_or(4) as u64;
+    let layers = match desc.dimension {
+        wgpu::TextureDimension::D3 => 1,
+        _ => desc.size.depth_or_array_layers as u64,
+    };
+    (0..desc.mip_level_count)
+        .map(|level| {
+            let size = desc.size.mip_level_size(level, desc.dimension);
+            let blocks_wide = size.width.div_ceil(block_width) as u64;
+            let blocks_high = size.height.div_ceil(block_height) as u64;
+            let depth = match desc.dimension {
+                wgpu::TextureDimension::D3 => size.depth_or_array_layers as u64,
+                _ => 1,
+            };
+            blocks_wide * blocks_high * depth * block_size
+        })
+        .sum::<u64>()
+        * layers
+        * desc.sample_count as u64
+}
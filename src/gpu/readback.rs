@@ -0,0 +1,637 @@
+//! Copies from GPU buffers and textures back to the CPU without stalling.
+//!
+//! A copy is recorded into the frame's encoder, mapped once the frame is
+//! submitted, and handed to its callback from [`ReadbackQueue::poll`] a few
+//! frames later, on whichever thread polls. Callbacks run in the order
+//! their copies were recorded, so a result never arrives before an older
+//! one, even when the GPU finishes them the other way round.
+//!
+//! The queue caps how many copies are in flight. Consumers that only want
+//! the latest result, like exposure metering, ask for
+//! [`ReadbackPolicy::DropOldest`] and lose their oldest copy when the cap is
+//! hit; screenshots ask for [`ReadbackPolicy::Guaranteed`] and always get
+//! theirs.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// What happens to a readback when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackPolicy {
+    /// The oldest readback of this policy is dropped to make room, and a new
+    /// one is refused if only guaranteed readbacks are in flight.
+    DropOldest,
+    /// Never dropped or refused. May go over the cap, but drops the oldest
+    /// [`Self::DropOldest`] readback first if there is one.
+    Guaranteed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadbackError {
+    /// Refused, as only guaranteed readbacks are in flight.
+    Full,
+    /// Dropped to make room for a newer readback.
+    Dropped,
+    /// The source lacks `COPY_SRC` usage.
+    NotCopySource,
+    /// Compressed formats can't be read back a texel at a time, and some
+    /// readers take only a few formats.
+    UnsupportedFormat(wgpu::TextureFormat),
+    Map(wgpu::BufferAsyncError),
+}
+
+impl std::fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "Too many guaranteed readbacks are in flight"),
+            Self::Dropped => write!(f, "The readback was dropped for a newer one"),
+            Self::NotCopySource => write!(f, "The source can't be copied from"),
+            Self::UnsupportedFormat(format) => write!(f, "Can't read back {:?} textures", format),
+            Self::Map(e) => write!(f, "Couldn't map the readback: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReadbackError {}
+
+/// The bytes of a finished readback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadbackData {
+    /// Tightly packed; texture rows have their copy padding removed.
+    pub bytes: Vec<u8>,
+    /// Width and height in texels, for texture readbacks.
+    pub extent: Option<(u32, u32)>,
+}
+
+impl ReadbackData {
+    /// The texels as an image, for texture readbacks of 8 bit RGBA formats.
+    pub fn into_rgba_image(self) -> Option<image::RgbaImage> {
+        let (width, height) = self.extent?;
+        image::RgbaImage::from_raw(width, height, self.bytes)
+    }
+}
+
+pub type ReadbackResult = Result<ReadbackData, ReadbackError>;
+
+type Callback = Box<dyn FnOnce(ReadbackResult) + Send>;
+type MapResult = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+/// How a texture readback's rows are laid out in the staging buffer.
+#[derive(Debug, Clone, Copy)]
+struct Rows {
+    row_bytes: u32,
+    padded_row_bytes: u32,
+    extent: (u32, u32),
+}
+
+struct Request {
+    staging: wgpu::Buffer,
+    size: u64,
+    rows: Option<Rows>,
+    callback: Callback,
+    /// Set once [`ReadbackQueue::after_submit`] starts mapping, and filled
+    /// by the map callback.
+    mapped: Option<MapResult>,
+}
+
+impl Request {
+    fn is_ready(&self) -> bool {
+        self.mapped
+            .as_ref()
+            .is_some_and(|mapped| mapped.lock().unwrap().is_some())
+    }
+
+    fn deliver(self) -> Option<wgpu::Buffer> {
+        let mapped = self.mapped.expect("a ready readback is mapping");
+        let result = mapped
+            .lock()
+            .unwrap()
+            .take()
+            .expect("a ready readback is mapped");
+        if let Err(e) = result {
+            (self.callback)(Err(ReadbackError::Map(e)));
+            return None;
+        }
+        let slice = self.staging.slice(..self.size);
+        let bytes = {
+            let view = slice.get_mapped_range();
+            match self.rows {
+                Some(rows) => view
+                    .chunks(rows.padded_row_bytes as usize)
+                    .flat_map(|row| &row[..rows.row_bytes as usize])
+                    .copied()
+                    .collect(),
+                None => view.to_vec(),
+            }
+        };
+        self.staging.unmap();
+        (self.callback)(Ok(ReadbackData {
+            bytes,
+            extent: self.rows.map(|rows| rows.extent),
+        }));
+        Some(self.staging)
+    }
+}
+
+/// The readbacks in flight in recording order, and which may be dropped.
+/// Knows nothing of the GPU, so the policies can be tested without one.
+struct InFlight<T> {
+    entries: VecDeque<(ReadbackPolicy, T)>,
+    max: usize,
+}
+
+impl<T> InFlight<T> {
+    fn new(max: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max: max.max(1),
+        }
+    }
+
+    /// Makes room for a readback with `policy`, returning the one dropped
+    /// for it, if any.
+    fn admit(&mut self, policy: ReadbackPolicy) -> Result<Option<T>, ReadbackError> {
+        if self.entries.len() < self.max {
+            return Ok(None);
+        }
+        let oldest = self
+            .entries
+            .iter()
+            .position(|(policy, _)| *policy == ReadbackPolicy::DropOldest);
+        match (oldest, policy) {
+            (Some(i), _) => Ok(self.entries.remove(i).map(|(_, entry)| entry)),
+            (None, ReadbackPolicy::Guaranteed) => Ok(None),
+            (None, ReadbackPolicy::DropOldest) => Err(ReadbackError::Full),
+        }
+    }
+
+    fn push(&mut self, policy: ReadbackPolicy, entry: T) {
+        self.entries.push_back((policy, entry));
+    }
+
+    /// The oldest readback, if `is_ready`. Newer ones wait behind it even
+    /// when ready, to keep them in order.
+    fn pop_ready(&mut self, is_ready: impl Fn(&T) -> bool) -> Option<T> {
+        let (_, front) = self.entries.front()?;
+        if !is_ready(front) {
+            return None;
+        }
+        self.entries.pop_front().map(|(_, entry)| entry)
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.entries.iter_mut().map(|(_, entry)| entry)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Readbacks in flight and the staging buffers they copy into.
+///
+/// Each frame: record copies with [`Self::read_buffer`] or
+/// [`Self::read_texture`], submit the encoder, call [`Self::after_submit`],
+/// then call [`Self::poll`] to run the callbacks of those that are done.
+pub struct ReadbackQueue {
+    in_flight: InFlight<Request>,
+    /// Unmapped staging buffers to reuse.
+    free: Vec<wgpu::Buffer>,
+}
+
+impl ReadbackQueue {
+    /// A queue with at most `max_in_flight` readbacks in flight, except
+    /// guaranteed ones, which can go over.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            in_flight: InFlight::new(max_in_flight),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.in_flight.max
+    }
+
+    /// Takes effect from the next readback; none in flight are dropped.
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.in_flight.max = max_in_flight.max(1);
+    }
+
+    /// Readbacks recorded but not delivered yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Records a copy of `range` of `buffer`, which needs `COPY_SRC` usage,
+    /// into `encoder`. `callback` gets the bytes once they arrive, or why
+    /// they didn't. An `Err` means the copy wasn't recorded, and `callback`
+    /// won't be called.
+    pub fn read_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+        range: Range<wgpu::BufferAddress>,
+        policy: ReadbackPolicy,
+        callback: impl FnOnce(ReadbackResult) + Send + 'static,
+    ) -> Result<(), ReadbackError> {
+        if !buffer.usage().contains(wgpu::BufferUsages::COPY_SRC) {
+            return Err(ReadbackError::NotCopySource);
+        }
+        self.admit(policy)?;
+        let size = range.end - range.start;
+        let staging = self.staging(device, size);
+        encoder.copy_buffer_to_buffer(buffer, range.start, &staging, 0, size);
+        self.push(policy, staging, size, None, Box::new(callback));
+        Ok(())
+    }
+
+    /// Records a copy of the `extent` region of one layer of `source`, whose
+    /// texture needs `COPY_SRC` usage, into `encoder`. The data is tightly
+    /// packed by row. See [`Self::read_buffer`] for the rest.
+    pub fn read_texture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: wgpu::ImageCopyTexture,
+        extent: (u32, u32),
+        policy: ReadbackPolicy,
+        callback: impl FnOnce(ReadbackResult) + Send + 'static,
+    ) -> Result<(), ReadbackError> {
+        let format = source.texture.format();
+        let texel_bytes = match format.block_size(Some(source.aspect)) {
+            Some(bytes) if format.block_dimensions() == (1, 1) => bytes,
+            _ => return Err(ReadbackError::UnsupportedFormat(format)),
+        };
+        if !source
+            .texture
+            .usage()
+            .contains(wgpu::TextureUsages::COPY_SRC)
+        {
+            return Err(ReadbackError::NotCopySource);
+        }
+        self.admit(policy)?;
+        let row_bytes = texel_bytes * extent.0;
+        let padded_row_bytes = wgpu::util::align_to(row_bytes, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let size = padded_row_bytes as u64 * extent.1 as u64;
+        let staging = self.staging(device, size);
+        encoder.copy_texture_to_buffer(
+            source,
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(extent.1),
+                },
+            },
+            wgpu::Extent3d {
+                width: extent.0,
+                height: extent.1,
+                depth_or_array_layers: 1,
+            },
+        );
+        let rows = Rows {
+            row_bytes,
+            padded_row_bytes,
+            extent,
+        };
+        self.push(policy, staging, size, Some(rows), Box::new(callback));
+        Ok(())
+    }
+
+    fn admit(&mut self, policy: ReadbackPolicy) -> Result<(), ReadbackError> {
+        if let Some(dropped) = self.in_flight.admit(policy)? {
+            // Its staging buffer may still be mapping, so it isn't reused.
+            (dropped.callback)(Err(ReadbackError::Dropped));
+        }
+        Ok(())
+    }
+
+    fn push(
+        &mut self,
+        policy: ReadbackPolicy,
+        staging: wgpu::Buffer,
+        size: u64,
+        rows: Option<Rows>,
+        callback: Callback,
+    ) {
+        self.in_flight.push(
+            policy,
+            Request {
+                staging,
+                size,
+                rows,
+                callback,
+                mapped: None,
+            },
+        );
+    }
+
+    /// The smallest free staging buffer of at least `size` bytes, or a new
+    /// one rounded up to a power of two so it can be reused.
+    fn staging(&mut self, device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+        let best = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.size() >= size)
+            .min_by_key(|(_, buffer)| buffer.size())
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => self.free.swap_remove(i),
+            None => device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Readback Staging Buffer"),
+                size: size
+                    .next_power_of_two()
+                    .max(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+
+    /// Starts mapping the readbacks recorded since the last call. Call once
+    /// the encoder they were recorded into is submitted.
+    pub fn after_submit(&mut self) {
+        for request in self.in_flight.iter_mut() {
+            if request.mapped.is_some() {
+                continue;
+            }
+            let mapped = MapResult::default();
+            let result = mapped.clone();
+            request
+                .staging
+                .slice(..request.size)
+                .map_async(wgpu::MapMode::Read, move |r| {
+                    *result.lock().unwrap() = Some(r);
+                });
+            request.mapped = Some(mapped);
+        }
+    }
+
+    /// Runs the callbacks of the readbacks that have arrived, oldest first,
+    /// without waiting for the rest. Returns how many ran.
+    pub fn poll(&mut self, device: &wgpu::Device) -> usize {
+        device.poll(wgpu::Maintain::Poll);
+        let mut delivered = 0;
+        while let Some(request) = self.in_flight.pop_ready(Request::is_ready) {
+            delivered += 1;
+            if let Some(staging) = request.deliver() {
+                if self.free.len() < self.in_flight.max {
+                    self.free.push(staging);
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Waits for every submitted readback and runs its callback. This
+    /// blocks, so it only works on native.
+    pub fn flush(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Wait);
+        self.poll(device);
+        if self.in_flight() > 0 {
+            log::warn!(
+                "{} readbacks are still in flight after a flush; was after_submit called?",
+                self.in_flight()
+            );
+        }
+    }
+}
+
+/// A readback's result as a future, or to check on each frame. Pass
+/// [`Self::callback`] as a readback's callback; the future resolves once
+/// [`ReadbackQueue::poll`] delivers it, so something has to keep polling.
+#[derive(Clone, Default)]
+pub struct ReadbackFuture {
+    slot: Arc<Mutex<(Option<ReadbackResult>, Option<Waker>)>>,
+}
+
+impl ReadbackFuture {
+    pub fn callback(&self) -> impl FnOnce(ReadbackResult) + Send + 'static {
+        let slot = self.slot.clone();
+        move |result| {
+            let mut slot = slot.lock().unwrap();
+            slot.0 = Some(result);
+            if let Some(waker) = slot.1.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// The result, if it has arrived and wasn't taken yet.
+    pub fn try_take(&self) -> Option<ReadbackResult> {
+        self.slot.lock().unwrap().0.take()
+    }
+}
+
+impl Future for ReadbackFuture {
+    type Output = ReadbackResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ReadbackResult> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.0.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type BatchCallback = Box<dyn FnOnce(Vec<ReadbackResult>) + Send>;
+
+#[derive(Default)]
+struct BatchState {
+    results: Vec<Option<ReadbackResult>>,
+    remaining: usize,
+    on_done: Option<BatchCallback>,
+}
+
+impl BatchState {
+    /// The results and the callback to run them with, once both are in.
+    fn take_done(&mut self) -> Option<(BatchCallback, Vec<ReadbackResult>)> {
+        if self.remaining > 0 {
+            return None;
+        }
+        let on_done = self.on_done.take()?;
+        let results = self.results.drain(..).map(Option::unwrap).collect();
+        Some((on_done, results))
+    }
+}
+
+/// Several readbacks whose results are wanted together, such as the vertex
+/// and index buffers of every mesh of a model. Pass [`Self::callback`] as
+/// each readback's callback, then [`Self::finish`] with what to do once all
+/// of them arrived.
+#[derive(Default)]
+pub struct ReadbackBatch {
+    state: Arc<Mutex<BatchState>>,
+}
+
+impl ReadbackBatch {
+    /// The callback of the next readback in the batch, whose result is at
+    /// the next index of those [`Self::finish`] hands over.
+    pub fn callback(&self) -> impl FnOnce(ReadbackResult) + Send + 'static {
+        let index = {
+            let mut state = self.state.lock().unwrap();
+            state.results.push(None);
+            state.remaining += 1;
+            state.results.len() - 1
+        };
+        let state = self.state.clone();
+        move |result| {
+            let done = {
+                let mut state = state.lock().unwrap();
+                state.results[index] = Some(result);
+                state.remaining -= 1;
+                state.take_done()
+            };
+            if let Some((on_done, results)) = done {
+                on_done(results);
+            }
+        }
+    }
+
+    /// Runs `on_done` with the batch's results, in the order their callbacks
+    /// were made, once the last arrives; right away for an empty batch.
+    pub fn finish(self, on_done: impl FnOnce(Vec<ReadbackResult>) + Send + 'static) {
+        let done = {
+            let mut state = self.state.lock().unwrap();
+            state.on_done = Some(Box::new(on_done));
+            state.take_done()
+        };
+        if let Some((on_done, results)) = done {
+            on_done(results);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A readback that arrives on frame `ready_at`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Simulated {
+        id: usize,
+        ready_at: usize,
+    }
+
+    /// The ids delivered on `frame`.
+    fn poll(in_flight: &mut InFlight<Simulated>, frame: usize) -> Vec<usize> {
+        std::iter::from_fn(|| in_flight.pop_ready(|r| r.ready_at <= frame))
+            .map(|r| r.id)
+            .collect()
+    }
+
+    #[test]
+    fn delivers_in_recording_order() {
+        let mut in_flight = InFlight::new(4);
+        for (id, ready_at) in [(0, 3), (1, 1), (2, 2)] {
+            in_flight.push(ReadbackPolicy::Guaranteed, Simulated { id, ready_at });
+        }
+        assert!(poll(&mut in_flight, 1).is_empty());
+        assert!(poll(&mut in_flight, 2).is_empty());
+        assert_eq!(poll(&mut in_flight, 3), vec![0, 1, 2]);
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[test]
+    fn polling_never_waits_for_latency() {
+        const LATENCY: usize = 2;
+        let mut in_flight = InFlight::new(LATENCY + 1);
+        let mut delivered = Vec::new();
+        for frame in 0..10 {
+            delivered.push(poll(&mut in_flight, frame));
+            let admitted = in_flight.admit(ReadbackPolicy::DropOldest);
+            assert_eq!(admitted, Ok(None), "frame {} dropped a readback", frame);
+            let ready_at = frame + LATENCY;
+            in_flight.push(
+                ReadbackPolicy::DropOldest,
+                Simulated {
+                    id: frame,
+                    ready_at,
+                },
+            );
+        }
+        // Nothing arrives early, then one readback a frame, each from
+        // `LATENCY` frames before.
+        assert!(delivered[..LATENCY].iter().all(Vec::is_empty));
+        for (frame, ids) in delivered.iter().enumerate().skip(LATENCY) {
+            assert_eq!(ids, &vec![frame - LATENCY]);
+        }
+    }
+
+    #[test]
+    fn drop_oldest_makes_room_and_guaranteed_never_drops() {
+        let mut in_flight = InFlight::new(2);
+        let at = |id| Simulated { id, ready_at: 100 };
+        in_flight.push(ReadbackPolicy::Guaranteed, at(0));
+        in_flight.push(ReadbackPolicy::DropOldest, at(1));
+        assert_eq!(in_flight.admit(ReadbackPolicy::DropOldest), Ok(Some(at(1))));
+        in_flight.push(ReadbackPolicy::DropOldest, at(2));
+        // A guaranteed readback drops the droppable one too.
+        assert_eq!(in_flight.admit(ReadbackPolicy::Guaranteed), Ok(Some(at(2))));
+        in_flight.push(ReadbackPolicy::Guaranteed, at(3));
+        // With only guaranteed readbacks left, droppable ones are refused
+        // and guaranteed ones go over the cap.
+        assert_eq!(
+            in_flight.admit(ReadbackPolicy::DropOldest),
+            Err(ReadbackError::Full)
+        );
+        assert_eq!(in_flight.admit(ReadbackPolicy::Guaranteed), Ok(None));
+        in_flight.push(ReadbackPolicy::Guaranteed, at(4));
+        assert_eq!(poll(&mut in_flight, 100), vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn batch_waits_for_every_result() {
+        let data = |byte| {
+            Ok(ReadbackData {
+                bytes: vec![byte],
+                extent: None,
+            })
+        };
+        let batch = ReadbackBatch::default();
+        let first = batch.callback();
+        let second = batch.callback();
+        let done = ReadbackFuture::default();
+        let on_done = done.callback();
+        batch.finish(move |results| {
+            assert_eq!(results, vec![data(1), Err(ReadbackError::Dropped)]);
+            on_done(data(0));
+        });
+        first(data(1));
+        assert_eq!(done.try_take(), None);
+        second(Err(ReadbackError::Dropped));
+        assert_eq!(done.try_take(), Some(data(0)));
+
+        let empty = ReadbackBatch::default();
+        let done = ReadbackFuture::default();
+        let on_done = done.callback();
+        empty.finish(move |results| {
+            assert!(results.is_empty());
+            on_done(data(0));
+        });
+        assert_eq!(done.try_take(), Some(data(0)));
+    }
+
+    #[test]
+    fn future_resolves_from_the_callback() {
+        let future = ReadbackFuture::default();
+        assert_eq!(future.try_take(), None);
+        (future.callback())(Err(ReadbackError::Dropped));
+        assert_eq!(future.try_take(), Some(Err(ReadbackError::Dropped)));
+        assert_eq!(future.try_take(), None);
+    }
+}
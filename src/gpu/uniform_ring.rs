@@ -0,0 +1,264 @@
+//! Uniform memory written every frame and bound with dynamic offsets, for
+//! per draw data such as transforms or material parameters.
+//!
+//! The buffer is split into one segment per frame in flight. Each frame
+//! writes only its own segment, and [`UniformRing::begin_frame`] waits for
+//! the GPU to finish the frame that last used the next segment before
+//! handing it out, so data still being read is never overwritten.
+//!
+//! [`crate::State`] begins a frame of its own ring every frame and hands
+//! it to the frame hooks through [`crate::compose::PassContext`].
+
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::memory::{AllocError, Allocator, MemoryCategory, MemoryToken};
+
+/// The offset of a value in a [`UniformRing`], to pass to `set_bind_group`.
+/// Only valid during the frame it was allocated in, which the borrow
+/// enforces: the ring can't begin another frame while this is alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynamicOffset<'frame> {
+    offset: u32,
+    size: u32,
+    _frame: PhantomData<&'frame ()>,
+}
+
+impl DynamicOffset<'_> {
+    pub fn get(self) -> wgpu::DynamicOffset {
+        self.offset
+    }
+
+    /// The size of the value, which bindings reading it shouldn't exceed.
+    pub fn size(self) -> u32 {
+        self.size
+    }
+}
+
+pub struct UniformRing {
+    buffer: wgpu::Buffer,
+    memory: MemoryToken,
+    alignment: u64,
+    segment_size: u64,
+    /// Whether the GPU is done with the frame that last used each segment.
+    done: Vec<Arc<AtomicBool>>,
+    /// The segment of the current frame, `None` before the first.
+    current: Option<usize>,
+    /// Bytes the current frame has allocated, including what didn't fit.
+    used: u64,
+    /// The most any frame has wanted, which the next frame grows to.
+    wanted: u64,
+    generation: u64,
+}
+
+impl UniformRing {
+    /// A ring of `frames_in_flight` segments of `bytes_per_frame` each,
+    /// rounded up to the device's offset alignment.
+    pub fn new(
+        device: &wgpu::Device,
+        bytes_per_frame: u64,
+        frames_in_flight: usize,
+    ) -> Result<Self, AllocError> {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let segment_size = wgpu::util::align_to(bytes_per_frame.max(1), alignment);
+        let frames_in_flight = frames_in_flight.max(1);
+        let (buffer, memory) = Self::create_buffer(device, segment_size, frames_in_flight)?;
+        Ok(Self {
+            buffer,
+            memory,
+            alignment,
+            segment_size,
+            done: Self::fences(frames_in_flight),
+            current: None,
+            used: 0,
+            wanted: segment_size,
+            generation: 0,
+        })
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        segment_size: u64,
+        frames_in_flight: usize,
+    ) -> Result<(wgpu::Buffer, MemoryToken), AllocError> {
+        Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Uniform Ring"),
+                size: segment_size * frames_in_flight as u64,
+                // COPY_SRC lets the contents be read back to check them.
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )
+    }
+
+    fn fences(count: usize) -> Vec<Arc<AtomicBool>> {
+        (0..count)
+            .map(|_| Arc::new(AtomicBool::new(true)))
+            .collect()
+    }
+
+    /// Starts a frame on the next segment, first growing the ring if the
+    /// last frame ran out of room. Call after submitting the previous
+    /// frame's work, as that is what the fence waits for.
+    ///
+    /// Waiting polls the device, which blocks on native. On the web it
+    /// can't block, and a segment still in use is logged and reused.
+    pub fn begin_frame<'a>(
+        &'a mut self,
+        device: &wgpu::Device,
+        queue: &'a wgpu::Queue,
+    ) -> UniformFrame<'a> {
+        if let Some(previous) = self.current {
+            let done = self.done[previous].clone();
+            queue.on_submitted_work_done(move || done.store(true, Ordering::Release));
+        }
+        if self.wanted > self.segment_size {
+            self.grow(device);
+        }
+
+        let next = self.current.map_or(0, |i| (i + 1) % self.done.len());
+        let done = &self.done[next];
+        if !done.load(Ordering::Acquire) {
+            device.poll(wgpu::Maintain::Wait);
+            if !done.load(Ordering::Acquire) {
+                log::warn!("Reusing a uniform ring segment the GPU may still be reading");
+            }
+        }
+        done.store(false, Ordering::Release);
+        self.current = Some(next);
+        self.used = 0;
+        UniformFrame { ring: self, queue }
+    }
+
+    fn grow(&mut self, device: &wgpu::Device) {
+        let segment_size = wgpu::util::align_to(self.wanted.next_power_of_two(), self.alignment);
+        match Self::create_buffer(device, segment_size, self.done.len()) {
+            Ok((buffer, memory)) => {
+                log::info!(
+                    "Grew the uniform ring from {} to {} bytes per frame",
+                    self.segment_size,
+                    segment_size
+                );
+                // The old buffer lives on until the GPU is done with it, and
+                // the new one hasn't been used yet.
+                self.buffer = buffer;
+                self.memory = memory;
+                self.segment_size = segment_size;
+                self.done = Self::fences(self.done.len());
+                self.current = None;
+                self.generation += 1;
+            }
+            Err(e) => {
+                log::warn!("Couldn't grow the uniform ring: {}", e);
+                self.wanted = self.segment_size;
+            }
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// A binding of `size` bytes, for a bind group whose layout entry has a
+    /// dynamic offset. Rebuild bind groups when
+    /// [`UniformRing::generation`] changes, as growing replaces the buffer.
+    pub fn binding(&self, size: u64) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(size),
+        })
+    }
+
+    /// Changes whenever the buffer is replaced.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Bytes between the start of two allocations, at the least.
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// The bytes each frame can allocate.
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn memory(&self) -> &MemoryToken {
+        &self.memory
+    }
+}
+
+/// One frame's use of a [`UniformRing`], from
+/// [`UniformRing::begin_frame`] until dropped.
+pub struct UniformFrame<'a> {
+    ring: &'a mut UniformRing,
+    queue: &'a wgpu::Queue,
+}
+
+impl<'a> UniformFrame<'a> {
+    /// Writes `value` into this frame's segment at the next aligned offset.
+    ///
+    /// A value that doesn't fit overwrites the last one that did, so draws
+    /// using either read the wrong data this frame, and one larger than a
+    /// whole segment isn't written at all. The ring warns and grows at the
+    /// next [`UniformRing::begin_frame`].
+    pub fn allocate<T: bytemuck::Pod>(&mut self, value: &T) -> DynamicOffset<'a> {
+        let ring = &mut *self.ring;
+        let bytes = bytemuck::bytes_of(value);
+        let size = bytes.len() as u64;
+        let start = wgpu::util::align_to(ring.used, ring.alignment);
+        ring.used = start + size;
+
+        let offset = if start + size <= ring.segment_size {
+            start
+        } else {
+            if ring.used > ring.wanted {
+                if ring.wanted <= ring.segment_size {
+                    log::warn!(
+                        "The uniform ring's {} bytes per frame ran out, growing next frame",
+                        ring.segment_size
+                    );
+                }
+                ring.wanted = ring.used;
+            }
+            // The last aligned offset the value fits at.
+            (ring.segment_size.saturating_sub(size) / ring.alignment) * ring.alignment
+        };
+        let segment = ring.current.expect("a frame has begun") as u64 * ring.segment_size;
+        if size <= ring.segment_size {
+            self.queue
+                .write_buffer(&ring.buffer, segment + offset, bytes);
+        }
+        DynamicOffset {
+            offset: (segment + offset) as u32,
+            size: size as u32,
+            _frame: PhantomData,
+        }
+    }
+
+    /// Bytes allocated so far this frame, with padding, including any that
+    /// didn't fit.
+    pub fn used(&self) -> u64 {
+        self.ring.used
+    }
+
+    /// The ring, for its buffer and bindings.
+    pub fn ring(&self) -> &UniformRing {
+        self.ring
+    }
+}
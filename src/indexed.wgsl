@@ -0,0 +1,13 @@
+// Two step lookup for paletted textures: the index texel under `uv`, with
+// nearest filtering and repeating, then its color from the 256x1 palette.
+
+fn sample_indexed(
+    indices: texture_2d<u32>,
+    palette: texture_2d<f32>,
+    uv: vec2<f32>,
+) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(indices));
+    let texel = clamp(vec2<i32>(fract(uv) * vec2<f32>(size)), vec2<i32>(0), size - 1);
+    let index = textureLoad(indices, texel, 0).r;
+    return textureLoad(palette, vec2<i32>(i32(index), 0), 0);
+}
@@ -0,0 +1,274 @@
+use crate::{
+    gpu::{self, MemoryCategory},
+    variant::InstanceLayout,
+    Instance, InstanceRaw, NormalMatrixRaw,
+};
+
+/// Instance transforms packed on the CPU in an [`InstanceLayout`], reused
+/// from frame to frame so packing doesn't allocate once it has grown.
+#[derive(Default)]
+pub struct PackedInstances {
+    layout: InstanceLayout,
+    matrices: Vec<InstanceRaw>,
+    normals: Vec<NormalMatrixRaw>,
+}
+
+impl PackedInstances {
+    /// Empties the set, to be packed in `layout` from now on.
+    pub fn clear(&mut self, layout: InstanceLayout) {
+        self.layout = layout;
+        self.matrices.clear();
+        self.normals.clear();
+    }
+
+    /// Adds `instance`. The compact layout draws non-uniformly scaled
+    /// instances with skewed normals, so such sets should be packed in the
+    /// layout [`InstanceLayout::for_instances`] picks.
+    pub fn push(&mut self, instance: &Instance) {
+        self.matrices.push(instance.to_raw());
+        if self.layout == InstanceLayout::Full {
+            self.normals.push(instance.to_normal_raw());
+        }
+    }
+
+    pub fn layout(&self) -> InstanceLayout {
+        self.layout
+    }
+
+    pub fn len(&self) -> usize {
+        self.matrices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matrices.is_empty()
+    }
+
+    /// What the vertex shader fetches per instance.
+    pub fn bytes_per_instance(&self) -> usize {
+        match self.layout {
+            InstanceLayout::Compact => std::mem::size_of::<InstanceRaw>(),
+            InstanceLayout::Full => {
+                std::mem::size_of::<InstanceRaw>() + std::mem::size_of::<NormalMatrixRaw>()
+            }
+        }
+    }
+
+    /// The model matrices, then the normal matrices if there are any, as
+    /// [`InstanceBuffer`] lays them out.
+    fn parts(&self) -> [&[u8]; 2] {
+        [
+            bytemuck::cast_slice(&self.matrices),
+            bytemuck::cast_slice(&self.normals),
+        ]
+    }
+}
+
+/// Instances on the GPU, tagged with the [`InstanceLayout`] they were
+/// packed in. The model matrices start the buffer, so passes that only
+/// position instances can bind [`InstanceBuffer::buffer`] whole; the normal
+/// matrices of the full layout follow them.
+pub struct InstanceBuffer {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    memory: gpu::MemoryToken,
+    layout: InstanceLayout,
+    len: u32,
+}
+
+impl InstanceBuffer {
+    /// An empty buffer with room for `capacity` instances in the full
+    /// layout, grown by [`InstanceBuffer::write`] as needed.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        capacity: usize,
+    ) -> Result<Self, gpu::AllocError> {
+        let (buffer, memory) = Self::create(
+            device,
+            label,
+            (capacity.max(1) * Self::FULL_SIZE) as wgpu::BufferAddress,
+        )?;
+        Ok(Self {
+            label,
+            buffer,
+            memory,
+            layout: InstanceLayout::Compact,
+            len: 0,
+        })
+    }
+
+    /// A buffer holding `instances`, in the layout they need.
+    pub fn from_instances(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &'static str,
+        instances: &[Instance],
+    ) -> Result<Self, gpu::AllocError> {
+        let mut packed = PackedInstances::default();
+        packed.clear(InstanceLayout::for_instances(instances));
+        for instance in instances {
+            packed.push(instance);
+        }
+        let mut buffer = Self::new(device, label, 0)?;
+        buffer.write(device, queue, &packed)?;
+        Ok(buffer)
+    }
+
+    const FULL_SIZE: usize =
+        std::mem::size_of::<InstanceRaw>() + std::mem::size_of::<NormalMatrixRaw>();
+
+    fn create(
+        device: &wgpu::Device,
+        label: &str,
+        size: wgpu::BufferAddress,
+    ) -> Result<(wgpu::Buffer, gpu::MemoryToken), gpu::AllocError> {
+        gpu::Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Vertex,
+        )
+    }
+
+    /// Replaces the contents with `packed`, taking on its layout. The
+    /// buffer is only replaced when it has to grow, to the next power of
+    /// two. If it can't grow, it is left empty.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        packed: &PackedInstances,
+    ) -> Result<(), gpu::AllocError> {
+        let [matrices, normals] = packed.parts();
+        let size = (matrices.len() + normals.len()) as wgpu::BufferAddress;
+        if size > self.buffer.size() {
+            self.len = 0;
+            (self.buffer, self.memory) =
+                Self::create(device, self.label, size.next_power_of_two())?;
+        }
+        queue.write_buffer(&self.buffer, 0, matrices);
+        if !normals.is_empty() {
+            queue.write_buffer(&self.buffer, matrices.len() as wgpu::BufferAddress, normals);
+        }
+        self.layout = packed.layout();
+        self.len = packed.len() as u32;
+        Ok(())
+    }
+
+    pub fn layout(&self) -> InstanceLayout {
+        self.layout
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Binds the model matrices to vertex buffer 1 and, in the full layout,
+    /// the normal matrices to vertex buffer 2, as the model pipelines
+    /// expect. Empty buffers can't be bound, so nothing is.
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.is_empty() {
+            return;
+        }
+        let matrices = self.len as wgpu::BufferAddress
+            * std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        render_pass.set_vertex_buffer(1, self.buffer.slice(..matrices));
+        if self.layout == InstanceLayout::Full {
+            let normals = self.len as wgpu::BufferAddress
+                * std::mem::size_of::<NormalMatrixRaw>() as wgpu::BufferAddress;
+            render_pass.set_vertex_buffer(2, self.buffer.slice(matrices..matrices + normals));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Matrix3, Matrix4, Quaternion, Rotation3, Vector3};
+
+    use super::*;
+
+    fn scaled(scale: Vector3<f32>) -> Instance {
+        Instance {
+            position: Vector3::new(1.0, 2.0, 3.0),
+            rotation: Quaternion::from_axis_angle(
+                Vector3::new(1.0, 1.0, 0.0).normalize(),
+                cgmath::Deg(30.0),
+            ),
+            scale,
+        }
+    }
+
+    #[test]
+    fn only_uneven_scales_need_the_full_layout() {
+        let even = scaled(Vector3::new(2.0, 2.0, 2.0));
+        let rounded = scaled(Vector3::new(0.1 * 3.0, 0.3, 0.3));
+        let stretched = scaled(Vector3::new(1.0, 2.5, 1.0));
+        assert!(rounded.is_uniformly_scaled());
+        assert_eq!(InstanceLayout::for_instances([]), InstanceLayout::Compact);
+        assert_eq!(
+            InstanceLayout::for_instances([&even, &rounded]),
+            InstanceLayout::Compact
+        );
+        assert_eq!(
+            InstanceLayout::for_instances([&even, &stretched]),
+            InstanceLayout::Full
+        );
+    }
+
+    #[test]
+    fn normal_matrices_keep_normals_perpendicular() {
+        let instance = scaled(Vector3::new(1.0, 2.5, 0.5));
+        let model = Matrix4::from(instance.to_raw().model);
+        let model = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal_matrix = Matrix3::from(instance.to_normal_raw().normal);
+        // A surface leaning across the stretched axes.
+        let normal = Vector3::new(1.0, 1.0, 1.0).normalize();
+        let tangent = Vector3::new(1.0, -1.0, 0.0);
+        assert!(
+            (normal_matrix * normal)
+                .normalize()
+                .dot((model * tangent).normalize())
+                .abs()
+                < 1e-5
+        );
+        // What the compact layout would draw.
+        assert!(
+            (model * normal)
+                .normalize()
+                .dot((model * tangent).normalize())
+                .abs()
+                > 0.1
+        );
+    }
+
+    #[test]
+    fn compact_instances_take_64_percent_of_the_memory() {
+        let mut packed = PackedInstances::default();
+        let mut bytes = Vec::new();
+        for layout in [InstanceLayout::Compact, InstanceLayout::Full] {
+            packed.clear(layout);
+            for _ in 0..100_000 {
+                packed.push(&scaled(Vector3::new(1.0, 1.0, 1.0)));
+            }
+            let [matrices, normals] = packed.parts();
+            assert_eq!(
+                matrices.len() + normals.len(),
+                packed.len() * packed.bytes_per_instance()
+            );
+            bytes.push(matrices.len() + normals.len());
+        }
+        assert_eq!(bytes, [6_400_000, 10_000_000]);
+        assert_eq!(100 * bytes[0] / bytes[1], 64);
+    }
+}
@@ -0,0 +1,218 @@
+//! Uncompressed sRGB RGBA8 [KTX2](https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html)
+//! files with a full mip chain, which the `asset-pipeline` bin writes for
+//! images so the runtime uploads every level as it is instead of generating
+//! them. Only this one format is read back; anything else, including
+//! supercompressed files, fails to load.
+
+use anyhow::{bail, ensure, Context};
+
+use crate::mipmap;
+
+/// The extension of KTX2 files.
+pub const EXTENSION: &str = "ktx2";
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+/// `VK_FORMAT_R8G8B8A8_SRGB`.
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+/// The identifier, the header and the index before the level index.
+const HEADER_BYTES: usize = 80;
+/// Each level's offset, length and uncompressed length.
+const LEVEL_BYTES: usize = 24;
+
+/// Whether `bytes` start like a KTX2 file.
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+/// `rgba` with the rest of its mip chain, as [`mipmap::generate_cpu`] makes
+/// it, as a KTX2 file.
+pub fn encode(rgba: &image::RgbaImage) -> Vec<u8> {
+    let mut levels = vec![rgba.clone()];
+    levels.extend(mipmap::generate_cpu(rgba, true));
+    encode_levels(&levels)
+}
+
+/// `levels`, each half the size of the one before, as a KTX2 file.
+pub fn encode_levels(levels: &[image::RgbaImage]) -> Vec<u8> {
+    let dfd = data_format_descriptor();
+    let dfd_offset = HEADER_BYTES + LEVEL_BYTES * levels.len();
+    let data_offset = dfd_offset + dfd.len();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&IDENTIFIER);
+    for value in [
+        VK_FORMAT_R8G8B8A8_SRGB,
+        1,
+        levels[0].width(),
+        levels[0].height(),
+        0,
+        0,
+        1,
+        levels.len() as u32,
+        0,
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0,
+        0,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // No supercompression global data.
+    out.extend_from_slice(&[0; 16]);
+
+    // The smallest level comes first in the file, but the index starts
+    // with the largest. Every level is a whole number of 4 byte texels, so
+    // they stay aligned.
+    let mut offsets = vec![0; levels.len()];
+    let mut offset = data_offset;
+    for (i, level) in levels.iter().enumerate().rev() {
+        offsets[i] = offset;
+        offset += level.as_raw().len();
+    }
+    for (level, offset) in levels.iter().zip(offsets) {
+        let length = level.as_raw().len() as u64;
+        for value in [offset as u64, length, length] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&dfd);
+    for level in levels.iter().rev() {
+        out.extend_from_slice(level.as_raw());
+    }
+    out
+}
+
+/// The basic descriptor block of sRGB RGBA8, with straight alpha.
+fn data_format_descriptor() -> Vec<u8> {
+    const SAMPLES: u32 = 4;
+    let block_size = 24 + 16 * SAMPLES;
+    let mut words = vec![
+        4 + block_size,
+        // Khronos, basic descriptor.
+        0,
+        // Version 1.3, then the size.
+        2 | block_size << 16,
+        // RGBSDA, BT.709 primaries, the sRGB transfer function, straight alpha.
+        1 | 1 << 8 | 2 << 16,
+        // One texel per block.
+        0,
+        // Four bytes in the one plane.
+        4,
+        0,
+    ];
+    for (channel, id) in [0, 1, 2, 15].into_iter().enumerate() {
+        // Alpha is linear even in sRGB formats.
+        let linear = if id == 15 { 1 << 4 } else { 0 };
+        words.extend([
+            (channel as u32 * 8) | 7 << 16 | (id | linear) << 24,
+            0,
+            0,
+            255,
+        ]);
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Reads the mip chain of a KTX2 file written by [`encode`], largest level
+/// first.
+pub fn decode(bytes: &[u8]) -> anyhow::Result<Vec<image::RgbaImage>> {
+    ensure!(is_ktx2(bytes), "not a KTX2 file");
+    ensure!(bytes.len() >= HEADER_BYTES, "the KTX2 header is cut off");
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let [format, _type_size, width, height, depth, layers, faces, level_count, supercompression] =
+        std::array::from_fn(|i| u32_at(12 + 4 * i));
+    if format != VK_FORMAT_R8G8B8A8_SRGB {
+        bail!("KTX2 format {} isn't supported, only sRGB RGBA8", format);
+    }
+    ensure!(
+        supercompression == 0,
+        "supercompressed KTX2 files aren't supported"
+    );
+    ensure!(
+        depth == 0 && layers <= 1 && faces == 1,
+        "only 2D KTX2 textures are supported"
+    );
+    ensure!(width > 0 && height > 0, "the KTX2 texture is empty");
+    // Zero asks the loader to generate the chain, which then starts with
+    // the one level there is.
+    let level_count = level_count.max(1) as usize;
+    ensure!(
+        level_count as u32 <= mipmap::mip_level_count(width, height),
+        "the KTX2 file has {} levels, more than a {}x{} texture has",
+        level_count,
+        width,
+        height
+    );
+
+    (0..level_count)
+        .map(|i| {
+            let index = HEADER_BYTES + LEVEL_BYTES * i;
+            let entry = bytes
+                .get(index..index + 16)
+                .context("the KTX2 level index is cut off")?;
+            let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+            let length = u64::from_le_bytes(entry[8..].try_into().unwrap()) as usize;
+            let (level_width, level_height) = ((width >> i).max(1), (height >> i).max(1));
+            let data = offset
+                .checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .with_context(|| format!("KTX2 level {} is cut off", i))?;
+            image::RgbaImage::from_raw(level_width, level_height, data.to_vec()).with_context(
+                || {
+                    format!(
+                        "KTX2 level {} has {} bytes, not {}x{} texels",
+                        i, length, level_width, level_height
+                    )
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> image::RgbaImage {
+        image::RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([(x * 40) as u8, (y * 60) as u8, 90, 200])
+        })
+    }
+
+    #[test]
+    fn files_read_back_with_every_level() {
+        let rgba = gradient(6, 3);
+        let levels = decode(&encode(&rgba)).unwrap();
+        let sizes = levels.iter().map(|l| l.dimensions()).collect::<Vec<_>>();
+        assert_eq!(sizes, [(6, 3), (3, 1), (1, 1)]);
+        assert_eq!(levels[0], rgba);
+        assert_eq!(levels[1..], mipmap::generate_cpu(&rgba, true));
+    }
+
+    #[test]
+    fn the_header_describes_srgb_rgba8() {
+        let bytes = encode(&gradient(4, 4));
+        assert!(is_ktx2(&bytes));
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u32_at(12), VK_FORMAT_R8G8B8A8_SRGB);
+        assert_eq!((u32_at(20), u32_at(24)), (4, 4));
+        assert_eq!(u32_at(40), 3);
+        // The descriptor follows the level index and says how long it is.
+        let (dfd_offset, dfd_length) = (u32_at(48) as usize, u32_at(52));
+        assert_eq!(dfd_offset, HEADER_BYTES + 3 * LEVEL_BYTES);
+        assert_eq!(u32_at(dfd_offset), dfd_length);
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        assert!(decode(b"\x89PNG\r\n\x1a\n").is_err());
+        let mut bytes = encode(&gradient(2, 2));
+        bytes[12] = 37;
+        assert!(decode(&bytes).is_err());
+        let bytes = encode(&gradient(2, 2));
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
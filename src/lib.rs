@@ -1,7 +1,6 @@
-use std::iter;
+use std::{iter, sync::Arc};
 
 use cgmath::prelude::*;
-use wgpu::util::DeviceExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
@@ -11,12 +10,63 @@ use winit::{
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-mod model;
-mod resources;
-mod texture;
+pub mod accel;
+pub mod animation;
+pub mod ao;
+pub mod assets;
+pub mod atlas;
+pub mod axes;
+pub mod bounds;
+pub mod builtin;
+pub mod compose;
+pub mod compression;
+pub mod cooperative;
+pub mod export;
+pub mod exposure;
+pub mod frame;
+pub mod gpu;
+pub mod instancing;
+pub mod ktx2;
+pub mod light;
+pub mod math;
+pub mod mipmap;
+pub mod model;
+pub mod model_data;
+pub mod obj;
+pub mod particle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeline;
+pub mod ply;
+pub mod pointer;
+pub mod points;
+pub mod primitives;
+pub mod reflect;
+pub mod refraction;
+pub mod region;
+pub mod render;
+pub mod resources;
+pub mod scatter;
+pub mod shadow;
+pub mod skinning;
+pub mod spatial;
+pub mod split;
+pub mod sprite;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
+pub mod tools;
+pub mod triplanar;
+pub mod ui;
+pub mod units;
+pub mod uv;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod validation;
+pub mod variant;
+pub mod window;
 
 use model::{DrawModel, Vertex};
 
+#[deprecated(note = "build projections with math::projection, or convert with from_opengl")]
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -40,9 +90,63 @@ struct Camera {
 impl Camera {
     fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = math::projection::perspective(
+            cgmath::Deg(self.fovy).into(),
+            self.aspect,
+            self.znear,
+            self.zfar,
+            math::projection::DepthRange::Standard,
+        );
         proj * view
     }
+
+    /// Looks at the middle of `aabb` from far enough back along the current
+    /// view direction for all of it to be in view, pushing the far plane
+    /// out if it wouldn't reach the far side.
+    fn frame(&mut self, aabb: &bounds::Aabb) {
+        let radius = (aabb.size().magnitude() / 2.0).max(self.znear);
+        // The narrower of the vertical and horizontal fields of view.
+        let half_fovy = cgmath::Rad::from(cgmath::Deg(self.fovy / 2.0)).0;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        let distance = radius / half_fovy.min(half_fovx).sin();
+        let direction = match (self.target - self.eye).normalize() {
+            d if d.x.is_finite() => d,
+            _ => -cgmath::Vector3::unit_z(),
+        };
+        self.target = aabb.center();
+        self.eye = self.target - direction * distance;
+        self.zfar = self.zfar.max((distance + radius) * 1.01);
+    }
+
+    fn particle_camera(&self) -> particle::ParticleCamera {
+        particle::ParticleCamera {
+            view: cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up),
+            projection: math::projection::perspective(
+                cgmath::Deg(self.fovy).into(),
+                self.aspect,
+                self.znear,
+                self.zfar,
+                math::projection::DepthRange::Standard,
+            ),
+            near: self.znear,
+            far: self.zfar,
+            depth: math::projection::DepthRange::Standard,
+        }
+    }
+
+    fn refraction_camera(&self) -> refraction::RefractionCamera {
+        refraction::RefractionCamera {
+            view: cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up),
+            projection: math::projection::perspective(
+                cgmath::Deg(self.fovy).into(),
+                self.aspect,
+                self.znear,
+                self.zfar,
+                math::projection::DepthRange::Standard,
+            ),
+            eye: self.eye,
+        }
+    }
 }
 
 #[repr(C)]
@@ -59,12 +163,22 @@ impl CameraUniform {
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
+        self.view_proj = camera.build_view_projection_matrix().into();
     }
 }
 
+/// How [`CameraController`] moves the eye in response to the movement keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Circle around the target, keeping it centered.
+    Orbit,
+    /// Move the eye and the target together.
+    Fly,
+}
+
 struct CameraController {
     speed: f32,
+    mode: CameraMode,
     is_up_pressed: bool,
     is_down_pressed: bool,
     is_forward_pressed: bool,
@@ -77,6 +191,7 @@ impl CameraController {
     fn new(speed: f32) -> Self {
         Self {
             speed,
+            mode: CameraMode::Orbit,
             is_up_pressed: false,
             is_down_pressed: false,
             is_forward_pressed: false,
@@ -131,6 +246,11 @@ impl CameraController {
     }
 
     fn update_camera(&self, camera: &mut Camera) {
+        if self.mode == CameraMode::Fly {
+            self.fly_camera(camera);
+            return;
+        }
+
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
@@ -160,23 +280,105 @@ impl CameraController {
             camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
         }
     }
+
+    /// Turns the camera by `delta` pixels of mouse movement: around the
+    /// target when orbiting, and in place when flying.
+    fn look(&self, camera: &mut Camera, delta: (f32, f32)) {
+        const RADIANS_PER_PIXEL: f32 = 0.005;
+        let forward = camera.target - camera.eye;
+        let right = forward.cross(camera.up).normalize();
+        let yaw = cgmath::Quaternion::from_axis_angle(
+            camera.up.normalize(),
+            cgmath::Rad(-delta.0 * RADIANS_PER_PIXEL),
+        );
+        let pitch =
+            cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(-delta.1 * RADIANS_PER_PIXEL));
+        let turned = yaw * pitch * forward;
+        // Stop short of straight up or down, where right is undefined.
+        let turned = if turned.normalize().dot(camera.up.normalize()).abs() > 0.99 {
+            yaw * forward
+        } else {
+            turned
+        };
+        match self.mode {
+            CameraMode::Orbit => camera.eye = camera.target - turned,
+            CameraMode::Fly => camera.target = camera.eye + turned,
+        }
+    }
+
+    fn fly_camera(&self, camera: &mut Camera) {
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+
+        let mut delta = cgmath::Vector3::zero();
+        if self.is_forward_pressed {
+            delta += forward;
+        }
+        if self.is_backward_pressed {
+            delta -= forward;
+        }
+        if self.is_right_pressed {
+            delta += right;
+        }
+        if self.is_left_pressed {
+            delta -= right;
+        }
+        if self.is_up_pressed {
+            delta += camera.up;
+        }
+        if self.is_down_pressed {
+            delta -= camera.up;
+        }
+
+        // Moving both points keeps the view direction unchanged.
+        camera.eye += delta * self.speed;
+        camera.target += delta * self.speed;
+    }
 }
 
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
+pub struct Instance {
+    pub position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    /// Along each axis. Sets of instances that are all scaled uniformly are
+    /// drawn with the compact [`variant::InstanceLayout`].
+    pub scale: cgmath::Vector3<f32>,
 }
 
 impl Instance {
+    /// Whether the scale is the same along every axis, within rounding.
+    pub fn is_uniformly_scaled(&self) -> bool {
+        let s = self.scale;
+        let tolerance = 1e-6 * s.x.abs().max(s.y.abs()).max(s.z.abs());
+        (s.x - s.y).abs() <= tolerance && (s.x - s.z).abs() <= tolerance
+    }
+
     fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
+            .into(),
+        }
+    }
+
+    /// The inverse transpose of the rotation and scale, which is the
+    /// rotation with each axis divided by its scale.
+    fn to_normal_raw(&self) -> NormalMatrixRaw {
+        let rotation = cgmath::Matrix3::from(self.rotation);
+        NormalMatrixRaw {
+            normal: cgmath::Matrix3::from_cols(
+                rotation.x / self.scale.x,
+                rotation.y / self.scale.y,
+                rotation.z / self.scale.z,
+            )
             .into(),
         }
     }
 }
 
+/// Per instance data as the shader sees it. In the compact
+/// [`variant::InstanceLayout`] this is all there is, and the shader turns
+/// normals with the upper 3x3 of `model` and renormalizes.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
@@ -223,35 +425,659 @@ impl InstanceRaw {
     }
 }
 
-struct State {
+/// The normal matrix the full [`variant::InstanceLayout`] adds, in a
+/// vertex buffer of its own after the model matrices.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct NormalMatrixRaw {
+    #[allow(dead_code)]
+    normal: [[f32; 3]; 3],
+}
+
+impl NormalMatrixRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<NormalMatrixRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Group 0 of `shader.wgsl`: a material's diffuse map and its sampler.
+pub(crate) const TEXTURE_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+];
+
+/// Group 0 of `shader.wgsl` with `HAS_TRIPLANAR`: the diffuse map, a
+/// repeating sampler and the [`triplanar::Triplanar`] parameters, which the
+/// vertex stage needs to place the projections.
+pub(crate) const TRIPLANAR_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+    TEXTURE_LAYOUT_ENTRIES[0],
+    TEXTURE_LAYOUT_ENTRIES[1],
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+/// Group 1 of `shader.wgsl`. Binding 0 is the camera's view projection and
+/// 1 the scene's [`light::Ambient`].
+pub(crate) const CAMERA_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        // The fast shading tier lights vertices.
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+fn create_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &TEXTURE_LAYOUT_ENTRIES,
+        label: Some("texture_bind_group_layout"),
+    })
+}
+
+pub(crate) fn create_triplanar_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &TRIPLANAR_LAYOUT_ENTRIES,
+        label: Some("triplanar_bind_group_layout"),
+    })
+}
+
+fn create_camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &CAMERA_LAYOUT_ENTRIES,
+        label: Some("camera_bind_group_layout"),
+    })
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    vertex_entry_point: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: vertex_entry_point,
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
+            // or Features::POLYGON_MODE_POINT
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        // If the pipeline will be used with a multiview render pass, this
+        // indicates how many array layers the attachments will have.
+        multiview: None,
+    })
+}
+
+/// The name of a model pipeline variant, for its
+/// [`stats::FrameEvent::PipelineBuilt`] event.
+fn model_pipeline_label(key: &variant::PipelineKey) -> String {
+    let defines = key
+        .features
+        .defines()
+        .chain(key.instance_layout.define())
+        .collect::<Vec<_>>();
+    if defines.is_empty() {
+        format!("shader.wgsl ({:?})", key.shading)
+    } else {
+        format!("shader.wgsl ({:?}, {})", key.shading, defines.join(", "))
+    }
+}
+
+/// Checks a preprocessed `shader.wgsl` against the layouts its pipelines
+/// are built with, in debug builds.
+fn check_model_shader(label: &str, source: &str, features: variant::MaterialFeatures) {
+    let material_entries: &[wgpu::BindGroupLayoutEntry] =
+        if features.contains(variant::MaterialFeatures::TRIPLANAR) {
+            &TRIPLANAR_LAYOUT_ENTRIES
+        } else {
+            &TEXTURE_LAYOUT_ENTRIES
+        };
+    reflect::debug_check(
+        label,
+        source,
+        &[
+            material_entries,
+            &CAMERA_LAYOUT_ENTRIES,
+            &model::MESH_LAYOUT_ENTRIES,
+        ],
+    );
+}
+
+/// One model pipeline from a preprocessed `shader.wgsl`, for vertices of
+/// `precision` and instances packed in `instance_layout`. `layouts` are the
+/// material and camera layouts, groups 0 and 1; compressed vertices add the
+/// mesh layout as group 2.
+fn create_model_pipeline(
+    device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
+    layouts: (&wgpu::BindGroupLayout, &wgpu::BindGroupLayout),
+    precision: model::VertexPrecision,
+    instance_layout: variant::InstanceLayout,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let (texture_bind_group_layout, camera_bind_group_layout) = layouts;
+    let vertex_buffers = |vertex| match instance_layout {
+        variant::InstanceLayout::Compact => vec![vertex, InstanceRaw::desc()],
+        variant::InstanceLayout::Full => vec![vertex, InstanceRaw::desc(), NormalMatrixRaw::desc()],
+    };
+    match precision {
+        model::VertexPrecision::Full => {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[texture_bind_group_layout, camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            create_render_pipeline(
+                device,
+                &layout,
+                color_format,
+                &vertex_buffers(model::ModelVertex::desc()),
+                shader,
+                "vs_main",
+            )
+        }
+        model::VertexPrecision::Compressed => {
+            let mesh_bind_group_layout = model::create_mesh_bind_group_layout(device);
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compressed Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    texture_bind_group_layout,
+                    camera_bind_group_layout,
+                    &mesh_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+            create_render_pipeline(
+                device,
+                &layout,
+                color_format,
+                &vertex_buffers(model::CompressedVertex::desc()),
+                shader,
+                "vs_compressed",
+            )
+        }
+    }
+}
+
+/// Builds [`State`]'s model pipeline variants for its
+/// [`variant::PipelineCache`], choosing the layouts and vertex buffers by
+/// the variant's key.
+#[derive(Clone)]
+struct ModelPipelineBuilder {
+    device: Arc<wgpu::Device>,
+    color_format: wgpu::TextureFormat,
+    texture_layout: Arc<wgpu::BindGroupLayout>,
+    triplanar_layout: Arc<wgpu::BindGroupLayout>,
+    camera_layout: Arc<wgpu::BindGroupLayout>,
+}
+
+impl ModelPipelineBuilder {
+    fn build(
+        &self,
+        key: &variant::PipelineKey,
+        shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        let label = model_pipeline_label(key);
+        if cfg!(debug_assertions) {
+            match variant::preprocess(include_str!("shader.wgsl"), &key.defines()) {
+                Ok(source) => check_model_shader(&label, &source, key.features),
+                Err(e) => log::error!("{}: {}", label, e),
+            }
+        }
+        let texture_layout = if key.features.contains(variant::MaterialFeatures::TRIPLANAR) {
+            &self.triplanar_layout
+        } else {
+            &self.texture_layout
+        };
+        create_model_pipeline(
+            &self.device,
+            self.color_format,
+            (texture_layout, &self.camera_layout),
+            key.vertex_precision,
+            key.instance_layout,
+            shader,
+        )
+    }
+}
+
+/// [`State`]'s model pipelines by variant, in its current shading tier.
+/// The plain variants for both vertex precisions are built up front. The
+/// `HAS_TRIPLANAR` ones are built in the background once a frame has
+/// triplanar materials to draw; until they are ready those materials are
+/// drawn with the plain variants, through their UV mapped bind group.
+/// Variants of another tier stay cached after switching. Only the compact
+/// [`variant::InstanceLayout`] is built until another is used.
+struct ModelPipelines {
+    cache: variant::PipelineCache,
+    builder: ModelPipelineBuilder,
+    shading: variant::ShadingTier,
+    /// The instance layouts used so far, whose variants are kept built.
+    layouts: Vec<variant::InstanceLayout>,
+}
+
+impl ModelPipelines {
+    fn new(builder: ModelPipelineBuilder, shading: variant::ShadingTier) -> Self {
+        let mut pipelines = Self {
+            cache: variant::PipelineCache::new(include_str!("shader.wgsl")),
+            builder,
+            shading,
+            layouts: vec![variant::InstanceLayout::Compact],
+        };
+        pipelines.build(variant::MaterialFeatures::empty());
+        pipelines
+    }
+
+    /// Switches to `shading`, building its plain variants if they aren't
+    /// built yet.
+    fn set_shading(&mut self, shading: variant::ShadingTier) {
+        self.shading = shading;
+        self.build(variant::MaterialFeatures::empty());
+    }
+
+    /// Builds the plain variants for instances packed in `layout`, if they
+    /// aren't built yet. Variants built in the background from then on
+    /// include it.
+    fn use_layout(&mut self, layout: variant::InstanceLayout) {
+        if !self.layouts.contains(&layout) {
+            self.layouts.push(layout);
+            self.build(variant::MaterialFeatures::empty());
+        }
+    }
+
+    fn key(
+        &self,
+        precision: model::VertexPrecision,
+        features: variant::MaterialFeatures,
+        layout: variant::InstanceLayout,
+    ) -> variant::PipelineKey {
+        variant::PipelineKey::new(precision, features, variant::PassType::Forward, 1)
+            .with_shading(self.shading)
+            .with_instance_layout(layout)
+    }
+
+    /// The keys for `features`, both vertex precisions and every layout in
+    /// use.
+    fn keys(
+        &self,
+        features: variant::MaterialFeatures,
+    ) -> impl Iterator<Item = variant::PipelineKey> + '_ {
+        self.layouts.iter().flat_map(move |&layout| {
+            [
+                model::VertexPrecision::Full,
+                model::VertexPrecision::Compressed,
+            ]
+            .map(|precision| self.key(precision, features, layout))
+        })
+    }
+
+    /// Builds the variants for `features`, both vertex precisions and every
+    /// layout in use, if they aren't built yet. Building is noted as one
+    /// [`stats::FrameEvent::PipelineBuilt`].
+    fn build(&mut self, features: variant::MaterialFeatures) {
+        let mut built = None;
+        for key in self.keys(features).collect::<Vec<_>>() {
+            if self.cache.get(&key).is_some() {
+                continue;
+            }
+            let builder = &self.builder;
+            self.cache
+                .get_or_create(&builder.device, key, |key, shader| {
+                    builder.build(key, shader)
+                })
+                .expect("shader.wgsl has invalid directives");
+            built = Some(key);
+        }
+        if let Some(key) = built {
+            stats::note_event(stats::FrameEvent::PipelineBuilt(model_pipeline_label(&key)));
+        }
+    }
+
+    /// Starts building the variants for `features`, both vertex precisions
+    /// and every layout in use in the background, if they aren't built or
+    /// building.
+    fn spawn(&mut self, features: variant::MaterialFeatures) {
+        for key in self.keys(features).collect::<Vec<_>>() {
+            let builder = self.builder.clone();
+            if let Err(e) =
+                self.cache
+                    .get_or_spawn(&self.builder.device, key, move |key, shader| {
+                        builder.build(key, shader)
+                    })
+            {
+                log::error!("{:#}", e);
+            }
+        }
+    }
+
+    /// Picks up variants built in the background, to call once a frame.
+    fn poll(&mut self) {
+        self.cache.poll();
+    }
+
+    fn get(
+        &self,
+        precision: model::VertexPrecision,
+        features: variant::MaterialFeatures,
+        layout: variant::InstanceLayout,
+    ) -> Option<&wgpu::RenderPipeline> {
+        self.cache.get(&self.key(precision, features, layout))
+    }
+
+    /// The plain variant for `precision` and `layout`, which is built once
+    /// the layout is used.
+    fn plain(
+        &self,
+        precision: model::VertexPrecision,
+        layout: variant::InstanceLayout,
+    ) -> &wgpu::RenderPipeline {
+        self.get(precision, variant::MaterialFeatures::empty(), layout)
+            .expect("the plain model pipelines are built as their layout is used")
+    }
+
+    /// The triplanar variants for full and compressed vertices, if built.
+    fn triplanar(
+        &self,
+        layout: variant::InstanceLayout,
+    ) -> Option<(&wgpu::RenderPipeline, &wgpu::RenderPipeline)> {
+        let triplanar = variant::MaterialFeatures::TRIPLANAR;
+        Some((
+            self.get(model::VertexPrecision::Full, triplanar, layout)?,
+            self.get(model::VertexPrecision::Compressed, triplanar, layout)?,
+        ))
+    }
+
+    /// The pipeline for `model` with instances in `layout`, and the
+    /// triplanar variant if it has triplanar materials and the variant is
+    /// built.
+    fn for_model(
+        &self,
+        model: &model::Model,
+        layout: variant::InstanceLayout,
+    ) -> (&wgpu::RenderPipeline, Option<&wgpu::RenderPipeline>) {
+        let triplanar = self.triplanar(layout).filter(|_| model.has_triplanar());
+        let triplanar = match model.vertex_precision {
+            model::VertexPrecision::Full => triplanar.map(|p| p.0),
+            model::VertexPrecision::Compressed => triplanar.map(|p| p.1),
+        };
+        (self.plain(model.vertex_precision, layout), triplanar)
+    }
+
+    /// Draws the opaque meshes of `model` at each of `instances`, with the
+    /// variants for their layout and its triplanar materials projected if
+    /// their variant is ready.
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        instances: &'a instancing::InstanceBuffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        instances.bind(render_pass);
+        let (pipelines, instances) = (
+            self.for_model(model, instances.layout()),
+            0..instances.len(),
+        );
+        match pipelines {
+            (pipeline, Some(triplanar)) => render_pass.draw_model_instanced_triplanar(
+                model,
+                instances,
+                camera_bind_group,
+                (pipeline, triplanar),
+            ),
+            (pipeline, None) => {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw_model_instanced(model, instances, camera_bind_group);
+            }
+        }
+    }
+}
+
+/// Auto exposure, when [`gpu::ContextOptions::auto_exposure`] asks for it.
+struct Exposure {
+    tonemap: tonemap::Tonemap,
+    meter: exposure::Meter,
+    auto: exposure::AutoExposure,
+    /// When the last histogram arrived.
+    metered_at: Option<f64>,
+}
+
+impl Exposure {
+    /// Adapts to the newest histogram, if one arrived.
+    fn adapt(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, now: f64) {
+        let Some(histogram) = self.meter.poll(device) else {
+            return;
+        };
+        let dt = self.metered_at.map_or(0.0, |at| (now - at) as f32);
+        self.metered_at = Some(now);
+        let exposure = self.auto.update(&histogram, dt);
+        self.tonemap.set_exposure(queue, exposure);
+    }
+
+    /// Recreates the HDR target for a surface `size` across.
+    fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) -> Result<(), gpu::AllocError> {
+        self.tonemap.resize(device, size)?;
+        self.meter.set_source(device, self.tonemap.view(), size)
+    }
+
+    /// Records metering the HDR target and exposing it onto `surface`.
+    fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        surface: &wgpu::TextureView,
+        frame_stats: &mut stats::FrameStats,
+    ) {
+        self.meter.meter(device, encoder);
+        self.tonemap.add_stats(frame_stats);
+        self.tonemap.draw(encoder, surface);
+    }
+}
+
+type ScreenshotCallback = Box<dyn FnOnce(Result<image::RgbaImage, gpu::ReadbackError>) + Send>;
+
+/// Why [`State::render`] couldn't draw a frame.
+#[derive(Debug)]
+pub enum RenderError {
+    Surface(wgpu::SurfaceError),
+    /// A target or buffer couldn't be created or grown for the frame.
+    Alloc(gpu::AllocError),
+}
+
+impl From<wgpu::SurfaceError> for RenderError {
+    fn from(e: wgpu::SurfaceError) -> Self {
+        Self::Surface(e)
+    }
+}
+
+impl From<gpu::AllocError> for RenderError {
+    fn from(e: gpu::AllocError) -> Self {
+        Self::Alloc(e)
+    }
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Surface(e) => write!(f, "{}", e),
+            Self::Alloc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+pub struct State {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    context_options: gpu::ContextOptions,
     surface: wgpu::Surface,
-    device: wgpu::Device,
+    device: Arc<wgpu::Device>,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    output_mode: gpu::OutputMode,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
+    pipelines: ModelPipelines,
+    texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    camera_bind_group_layout: Arc<wgpu::BindGroupLayout>,
     obj_model: model::Model,
+    /// A model set with [`Self::load_glass`], drawn once alongside the
+    /// instances of `obj_model`.
+    glass: Option<(model::Model, instancing::InstanceBuffer)>,
+    /// Set with [`Self::load_point_cloud`], drawn with the opaque scene.
+    point_cloud: Option<(model::PointCloud, points::PointPipeline)>,
     camera: Camera,
     camera_controller: CameraController,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     instances: Vec<Instance>,
-    #[allow(dead_code)]
-    instance_buffer: wgpu::Buffer,
+    instance_buffer: instancing::InstanceBuffer,
+    lights: Vec<light::LightUniform>,
+    ambient: light::Ambient,
+    ambient_buffer: wgpu::Buffer,
+    /// The camera and ambient buffers'.
+    _memory: [gpu::MemoryToken; 2],
+    debug_light: light::DebugLight,
+    sprites: sprite::SpriteBatch,
+    ui: ui::UiCompositor,
+    particles: particle::ParticleBatch,
+    /// `None` where the surface can't be copied from.
+    refraction: Option<refraction::RefractionPass>,
+    exposure: Option<Exposure>,
+    frame_stats: stats::FrameStats,
+    timeline: stats::FrameTimeline,
+    /// The last [`Self::update`]'s duration, until a frame takes it.
+    update_seconds: Option<f64>,
+    last_frame_end: Option<f64>,
+    frame_renderer: frame::Renderer,
+    hooks: compose::FrameHooks,
+    /// Per draw uniforms for the hooks, begun once a frame.
+    uniform_ring: gpu::UniformRing,
+    /// Copies back from the GPU, delivered by [`Self::update`].
+    readbacks: gpu::ReadbackQueue,
+    /// Set by [`Self::request_screenshot`], taken by the next frame.
+    screenshot: Option<ScreenshotCallback>,
     depth_texture: texture::Texture,
+    resize_debounce: window::ResizeDebounce,
     window: Window,
 }
 
 impl State {
-    async fn new(window: Window) -> Self {
+    /// The model loaded when the application doesn't ask for a specific one.
+    pub const DEFAULT_MODEL: &'static str = "cube/cube.obj";
+
+    pub async fn new(window: Window) -> Result<Self, gpu::AllocError> {
+        Self::with_options(window, gpu::ContextOptions::from_env()).await
+    }
+
+    pub async fn with_options(
+        window: Window,
+        context_options: gpu::ContextOptions,
+    ) -> Result<Self, gpu::AllocError> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         log::warn!("WGPU setup");
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends: context_options.backends,
             dx12_shader_compiler: Default::default(),
         });
 
@@ -261,12 +1087,7 @@ impl State {
         // State owns the window so this should be safe.
         let surface = unsafe { instance.create_surface(&window) }.unwrap();
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
+        let adapter = gpu::request_adapter(&instance, &surface, &context_options)
             .await
             .unwrap();
         log::warn!("device and queue");
@@ -275,65 +1096,43 @@ impl State {
                 &wgpu::DeviceDescriptor {
                     label: None,
                     features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
+                    limits: gpu::device_limits(&adapter),
                 },
                 // Some(&std::path::Path::new("trace")), // Trace path
                 None, // Trace path
             )
             .await
             .unwrap();
+        let device = Arc::new(device);
 
         log::warn!("Surface");
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let (surface_format, output_mode) =
+            gpu::select_surface_format(&surface_caps.formats, context_options.output_mode);
+        let ui_path = ui::UiPath::select(
+            surface_format,
+            output_mode,
+            adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS),
+            context_options.paper_white_nits,
+        );
+        log::info!("UI path {:?}", ui_path);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | gpu::surface_copy_usage(adapter.get_info().backend),
             format: surface_format,
             width: size.width,
             height: size.height,
             present_mode: surface_caps.present_modes[0],
             alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
+            view_formats: ui_path.surface_view_formats(surface_format),
         };
 
         surface.configure(&device, &config);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = Arc::new(create_texture_bind_group_layout(&device));
 
         let camera = Camera {
             eye: (0.0, 5.0, -10.0).into(),
@@ -349,11 +1148,15 @@ impl State {
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
 
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let (camera_buffer, camera_buffer_memory) = gpu::Allocator::new(&device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Camera Buffer"),
+                    contents: bytemuck::cast_slice(&[camera_uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
 
         const SPACE_BETWEEN: f32 = 3.0;
         let instances = (0..NUM_INSTANCES_PER_ROW)
@@ -373,124 +1176,138 @@ impl State {
                         cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
                     };
 
-                    Instance { position, rotation }
+                    Instance {
+                        position,
+                        rotation,
+                        scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                    }
                 })
             })
             .collect::<Vec<_>>();
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let instance_buffer = instancing::InstanceBuffer::from_instances(
+            &device,
+            &queue,
+            "Instance Buffer",
+            &instances,
+        )?;
+
+        let camera_bind_group_layout = Arc::new(create_camera_bind_group_layout(&device));
+
+        let ambient = light::Ambient::default();
+        let (ambient_buffer, ambient_buffer_memory) = gpu::Allocator::new(&device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Ambient Buffer"),
+                    contents: bytemuck::cast_slice(&[ambient]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: ambient_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("camera_bind_group"),
         });
 
         log::warn!("Load model");
-        // let obj_model =
-        //     resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-        //         .await
-        //         .unwrap();
-
-        let gltf_data = resources::load_gltf("DamagedHelmet.gltf").await.unwrap();
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shader.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        let obj_model = resources::load_model(
+            Self::DEFAULT_MODEL,
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        )
+        .await
+        .unwrap();
 
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+            texture::Texture::create_depth_texture(&device, &config, "depth_texture")?;
+
+        let exposure = match context_options.auto_exposure {
+            Some(settings) if tonemap::Tonemap::is_supported(&adapter) => {
+                let size = (config.width, config.height);
+                let tonemap = tonemap::Tonemap::new(&device, config.format, output_mode, size)?;
+                let mut meter = exposure::Meter::new(&adapter, &device, &settings)?;
+                meter.set_source(&device, tonemap.view(), size)?;
+                Some(Exposure {
+                    tonemap,
+                    meter,
+                    auto: exposure::AutoExposure::new(settings),
+                    metered_at: None,
+                })
+            }
+            Some(_) => {
+                log::warn!(
+                    "{:?} targets can't be drawn into here, so exposure stays fixed",
+                    tonemap::HDR_FORMAT
+                );
+                None
+            }
+            None => None,
+        };
+        let scene_format = exposure
+            .as_ref()
+            .map_or(config.format, |_| tonemap::HDR_FORMAT);
+
+        let shading_tier = context_options.render_settings.shading_tier(&adapter);
+        log::info!("Shading tier {:?}", shading_tier);
+        let mut pipelines = ModelPipelines::new(
+            ModelPipelineBuilder {
+                device: device.clone(),
+                color_format: scene_format,
+                texture_layout: texture_bind_group_layout.clone(),
+                triplanar_layout: Arc::new(create_triplanar_bind_group_layout(&device)),
+                camera_layout: camera_bind_group_layout.clone(),
             },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-        });
-
-        Self {
+            shading_tier,
+        );
+        pipelines.use_layout(instance_buffer.layout());
+
+        let lights = vec![light::LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0])];
+        let debug_light = light::DebugLight::new(&device, scene_format, &camera_bind_group_layout)?;
+        let ui = ui::UiCompositor::new(
+            &device,
+            ui_path,
+            config.format,
+            (config.width, config.height),
+        )?;
+        let sprites = sprite::SpriteBatch::new(&device, ui.format(), &texture_bind_group_layout)?;
+        let mut particles = particle::ParticleBatch::new(&device, scene_format, 1)?;
+        particles.set_depth(&device, &depth_texture.view);
+        let refraction =
+            refraction::RefractionPass::new(&device, scene_format, (config.width, config.height))
+                .map_err(|e| log::warn!("Glass isn't drawn: {}", e))
+                .ok();
+        let frame_renderer = frame::Renderer::new(&device, &camera_bind_group_layout, 4)?;
+        // Grows when the hooks need more.
+        let uniform_ring = gpu::UniformRing::new(&device, 16 * 1024, 3)?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            context_options,
             surface,
             device,
             queue,
             config,
+            output_mode,
             size,
-            render_pipeline,
-            meshes,
-
+            pipelines,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
+            obj_model,
+            glass: None,
+            point_cloud: None,
             camera,
             camera_controller,
             camera_buffer,
@@ -498,31 +1315,626 @@ impl State {
             camera_uniform,
             instances,
             instance_buffer,
+            lights,
+            ambient,
+            ambient_buffer,
+            _memory: [camera_buffer_memory, ambient_buffer_memory],
+            debug_light,
+            sprites,
+            ui,
+            particles,
+            refraction,
+            exposure,
+            frame_stats: stats::FrameStats::default(),
+            timeline: stats::FrameTimeline::default(),
+            update_seconds: None,
+            last_frame_end: None,
+            frame_renderer,
+            hooks: compose::FrameHooks::default(),
+            uniform_ring,
+            readbacks: gpu::ReadbackQueue::new(3),
+            screenshot: None,
             depth_texture,
+            resize_debounce: window::ResizeDebounce::new(
+                (size.width, size.height),
+                window::ResizeDebounce::DEFAULT_SETTLE,
+            ),
             window,
-        }
+        })
     }
 
     pub fn window(&self) -> &Window {
         &self.window
     }
 
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    /// The output mode the surface was configured for, which is SDR if the
+    /// requested mode wasn't available.
+    pub fn output_mode(&self) -> gpu::OutputMode {
+        self.output_mode
+    }
+
+    /// The format everything before the UI draws in, frame hooks included:
+    /// [`tonemap::HDR_FORMAT`] with auto exposure on, the surface's
+    /// otherwise.
+    pub fn scene_format(&self) -> wgpu::TextureFormat {
+        match &self.exposure {
+            Some(_) => tonemap::HDR_FORMAT,
+            None => self.config.format,
+        }
+    }
+
+    /// The format sprites and [`compose::PassSlot::Ui`] passes draw in, see
+    /// [`ui::UiCompositor::format`].
+    pub fn ui_format(&self) -> wgpu::TextureFormat {
+        self.ui.format()
+    }
+
+    /// The exposure the scene is scaled by, 1 without auto exposure.
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+            .as_ref()
+            .map_or(1.0, |exposure| exposure.auto.exposure())
+    }
+
+    /// The adapter the device was created on.
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    /// Every adapter that [`gpu::AdapterSelector::Index`] can refer to. Switching
+    /// to another one means creating a new `State` with [`State::with_options`].
+    pub fn available_adapters(&self) -> Vec<wgpu::AdapterInfo> {
+        gpu::available_adapters(&self.instance, &self.surface, self.context_options.backends)
+    }
+
+    pub fn shading_tier(&self) -> variant::ShadingTier {
+        self.pipelines.shading
+    }
+
+    /// Switches the model pipelines to `tier`, e.g. from a quality menu.
+    /// Its plain variants are built now if they weren't before; the
+    /// triplanar ones in the background when next needed.
+    pub fn set_shading_tier(&mut self, tier: variant::ShadingTier) {
+        self.pipelines.set_shading(tier);
+    }
+
+    /// Starts building the model pipelines for materials with each of
+    /// `features` in the background, e.g. while a loading screen shows, so
+    /// they are ready by the time they are drawn. The plain variants are
+    /// always built. Frames pick up finished builds; a loading screen can
+    /// wait for [`Self::pipelines_building`] to reach zero.
+    pub fn prewarm_pipelines(&mut self, features: &[variant::MaterialFeatures]) {
+        for features in features {
+            self.pipelines.spawn(*features);
+        }
+    }
+
+    /// The number of model pipelines still building in the background.
+    pub fn pipelines_building(&self) -> usize {
+        self.pipelines.cache.building()
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Layout of the material bind group (group 0) used by the main pipeline.
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// Layout of the camera bind group (group 1) used by the main pipeline.
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    pub fn model(&self) -> &model::Model {
+        &self.obj_model
+    }
+
+    /// For swapping materials or textures of the displayed model in place.
+    pub fn model_mut(&mut self) -> &mut model::Model {
+        &mut self.obj_model
+    }
+
+    /// Replaces the displayed model. On failure the current model is kept.
+    pub async fn load_model(&mut self, file_name: &str) -> anyhow::Result<()> {
+        self.load_model_with_options(file_name, &resources::LoadOptions::default())
+            .await
+    }
+
+    pub async fn load_model_with_options(
+        &mut self,
+        file_name: &str,
+        options: &resources::LoadOptions,
+    ) -> anyhow::Result<()> {
+        self.obj_model = resources::load_model_with_options(
+            file_name,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            options,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Loads `file_name` to draw once at `position`, in front of the
+    /// instances of the main model. Its refractive materials, such as MTL
+    /// materials with `illum 7` and an `Ni` above 1, bend the scene behind
+    /// it.
+    pub async fn load_glass(
+        &mut self,
+        file_name: &str,
+        position: cgmath::Vector3<f32>,
+    ) -> anyhow::Result<()> {
+        let model = resources::load_model(
+            file_name,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+        )
+        .await?;
+        self.set_glass(model, position)?;
+        Ok(())
+    }
+
+    /// Like [`Self::load_glass`], for a model loaded some other way, e.g.
+    /// with a [`cooperative::CooperativeLoad`].
+    pub fn set_glass(
+        &mut self,
+        model: model::Model,
+        position: cgmath::Vector3<f32>,
+    ) -> Result<(), gpu::AllocError> {
+        let instance = Instance {
+            position,
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        let instance_buffer = instancing::InstanceBuffer::from_instances(
+            &self.device,
+            &self.queue,
+            "Glass Instance Buffer",
+            &[instance],
+        )?;
+        self.glass = Some((model, instance_buffer));
+        Ok(())
+    }
+
+    pub fn clear_glass(&mut self) {
+        self.glass = None;
+    }
+
+    pub fn has_glass(&self) -> bool {
+        self.glass.is_some()
+    }
+
+    /// Loads a PLY scan to draw with points of `size`, see
+    /// [`resources::load_ply`], and frames the camera on it.
+    pub async fn load_point_cloud(
+        &mut self,
+        file_name: &str,
+        size: points::PointSize,
+    ) -> anyhow::Result<()> {
+        let cloud = resources::load_ply(file_name, &self.device, &self.queue).await?;
+        self.set_point_cloud(cloud, size)?;
+        Ok(())
+    }
+
+    /// Like [`Self::load_point_cloud`], for points uploaded some other way,
+    /// e.g. with [`resources::upload_ply`].
+    pub fn set_point_cloud(
+        &mut self,
+        cloud: model::PointCloud,
+        size: points::PointSize,
+    ) -> Result<(), gpu::AllocError> {
+        if let Some(aabb) = &cloud.aabb {
+            self.frame_bounds(aabb);
+        }
+        let pipeline = points::PointPipeline::new(&self.device, self.scene_format(), size)?;
+        self.point_cloud = Some((cloud, pipeline));
+        Ok(())
+    }
+
+    pub fn point_cloud(&self) -> Option<&model::PointCloud> {
+        self.point_cloud.as_ref().map(|(cloud, _)| cloud)
+    }
+
+    pub fn clear_point_cloud(&mut self) {
+        self.point_cloud = None;
+    }
+
+    /// Moves the camera to look at all of `aabb`, keeping its direction.
+    /// Takes effect from the next [`Self::update`].
+    pub fn frame_bounds(&mut self, aabb: &bounds::Aabb) {
+        self.camera.frame(aabb);
+    }
+
+    pub fn lights(&self) -> &[light::LightUniform] {
+        &self.lights
+    }
+
+    /// Changes are picked up by the next [`State::update`].
+    pub fn lights_mut(&mut self) -> &mut Vec<light::LightUniform> {
+        &mut self.lights
+    }
+
+    pub fn ambient(&self) -> light::Ambient {
+        self.ambient
+    }
+
+    /// Used by [`State::render`]; [`State::render_frame`] takes the frame's.
+    pub fn set_ambient(&mut self, ambient: light::Ambient) {
+        self.ambient = ambient;
+        self.queue
+            .write_buffer(&self.ambient_buffer, 0, bytemuck::cast_slice(&[ambient]));
+    }
+
+    /// Reads back the next frame [`Self::render`] or [`Self::render_frame`]
+    /// draws, replacing a screenshot requested before that wasn't taken.
+    /// `on_read` runs from an [`Self::update`] a few frames later, or as
+    /// the frame is drawn if the surface can't be read back.
+    pub fn request_screenshot(
+        &mut self,
+        on_read: impl FnOnce(Result<image::RgbaImage, gpu::ReadbackError>) + Send + 'static,
+    ) {
+        self.screenshot = Some(Box::new(on_read));
+    }
+
+    /// Records the copy of a requested screenshot of `texture`, the
+    /// surface texture being drawn.
+    fn record_screenshot(&mut self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        let Some(on_read) = self.screenshot.take() else {
+            return;
+        };
+        let format = texture.format();
+        let swap_red_blue = match format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            _ => return on_read(Err(gpu::ReadbackError::UnsupportedFormat(format))),
+        };
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return on_read(Err(gpu::ReadbackError::NotCopySource));
+        }
+        let recorded = self.readbacks.read_texture(
+            &self.device,
+            encoder,
+            texture.as_image_copy(),
+            (texture.width(), texture.height()),
+            gpu::ReadbackPolicy::Guaranteed,
+            move |result| {
+                on_read(result.map(|mut data| {
+                    if swap_red_blue {
+                        for texel in data.bytes.chunks_exact_mut(4) {
+                            texel.swap(0, 2);
+                        }
+                    }
+                    data.into_rgba_image().expect("a whole frame is read back")
+                }))
+            },
+        );
+        if let Err(e) = recorded {
+            log::error!("Couldn't take a screenshot: {}", e);
+        }
+    }
+
+    pub fn light_gizmos_visible(&self) -> bool {
+        self.debug_light.enabled
+    }
+
+    pub fn set_light_gizmos_visible(&mut self, visible: bool) {
+        self.debug_light.enabled = visible;
+    }
+
+    /// Clear color, load ops and application passes for both
+    /// [`State::render`] and [`State::render_frame`].
+    pub fn hooks_mut(&mut self) -> &mut compose::FrameHooks {
+        &mut self.hooks
+    }
+
+    /// What the last [`Self::render`] recorded.
+    pub fn frame_stats(&self) -> stats::FrameStats {
+        self.frame_stats
+    }
+
+    /// The timings of the last frames drawn, and which were janks.
+    pub fn timeline(&self) -> &stats::FrameTimeline {
+        &self.timeline
+    }
+
+    pub fn timeline_mut(&mut self) -> &mut stats::FrameTimeline {
+        &mut self.timeline
+    }
+
+    /// Adds the frame that started recording at `record_started` to the
+    /// timeline, logging it if it was a jank. Neither the present latency
+    /// nor the GPU time can be measured yet.
+    fn push_frame_timing(&mut self, record_started: f64) {
+        let now = stats::now();
+        let update = self.update_seconds.take().unwrap_or(0.0);
+        let record = now - record_started;
+        let frame = match self.last_frame_end {
+            Some(end) => now - end,
+            None => update + record,
+        };
+        self.last_frame_end = Some(now);
+        let timing = stats::FrameTiming {
+            frame,
+            update,
+            record,
+            present_latency: None,
+            gpu: None,
+        };
+        if let Some(jank) = self.timeline.push(timing) {
+            log::warn!("{}", jank);
+        }
+    }
+
+    /// The passes [`Self::render`] records with the current settings, for
+    /// debugging. The refraction, particle and sprite passes are enabled if
+    /// the last frame had any glass, particles or sprites.
+    pub fn frame_graph(&self) -> render::Graph {
+        let size = (self.config.width, self.config.height);
+        let mut graph = render::Graph::new();
+        let surface = graph.add_resource("Surface", self.config.format, size, false);
+        // With auto exposure, everything before the UI is drawn into the
+        // HDR target, which the tonemap pass exposes onto the surface.
+        let color = match &self.exposure {
+            Some(_) => graph.add_resource("HDR Scene", tonemap::HDR_FORMAT, size, false),
+            None => surface,
+        };
+        let depth = graph.add_resource("Depth", texture::Texture::DEPTH_FORMAT, size, false);
+        let add_hooks = |graph: &mut render::Graph, slot, color| {
+            render::add_hooks(graph, &self.hooks, slot, color, depth)
+        };
+
+        // With glass, the opaque scene is drawn offscreen for the refraction
+        // pass to sample.
+        let glass = self.refraction.as_ref().is_some_and(|r| !r.is_empty());
+        let scene = if glass {
+            graph.add_resource("Scene Color", self.scene_format(), size, false)
+        } else {
+            color
+        };
+        add_hooks(&mut graph, compose::PassSlot::BeforeScene, scene);
+        let loads = self.hooks.loads();
+        graph.add_pass(
+            "Render Pass",
+            &[],
+            &[
+                render::Attachment {
+                    resource: scene,
+                    load: render::Load::from_op(loads.color),
+                    store: true,
+                },
+                render::Attachment {
+                    resource: depth,
+                    load: render::Load::from_op(loads.depth),
+                    store: true,
+                },
+            ],
+        );
+        add_hooks(&mut graph, compose::PassSlot::AfterOpaque, scene);
+        let refraction = graph.add_pass(
+            "Refraction Pass",
+            &[scene],
+            &[
+                render::Attachment {
+                    resource: color,
+                    load: render::Load::Clear,
+                    store: true,
+                },
+                render::Attachment::load(depth),
+            ],
+        );
+        graph.set_enabled(refraction, glass);
+        let particles = graph.add_pass(
+            "Particle Pass",
+            &[depth],
+            &[render::Attachment::load(color)],
+        );
+        graph.set_enabled(particles, !self.particles.is_empty());
+        for slot in [
+            compose::PassSlot::AfterTransparent,
+            compose::PassSlot::BeforeUi,
+        ] {
+            add_hooks(&mut graph, slot, color);
+        }
+        if self.exposure.is_some() {
+            graph.add_pass(
+                "Tonemap Pass",
+                &[color],
+                &[render::Attachment {
+                    resource: surface,
+                    load: render::Load::Clear,
+                    store: true,
+                }],
+            );
+        }
+        let ui_hooks = self.hooks.pass_count(compose::PassSlot::Ui) > 0;
+        if self.ui.has_layer() {
+            let layer = graph.add_resource("UI Layer", self.ui.format(), size, false);
+            let cleared = render::Attachment {
+                resource: layer,
+                load: render::Load::Clear,
+                store: true,
+            };
+            let sprites = graph.add_pass("Sprite Pass", &[], &[cleared]);
+            graph.set_enabled(sprites, !self.sprites.is_empty());
+            let clear = graph.add_pass("UI Clear Pass", &[], &[cleared]);
+            graph.set_enabled(clear, self.sprites.is_empty() && ui_hooks);
+            add_hooks(&mut graph, compose::PassSlot::Ui, layer);
+            let composite = graph.add_pass(
+                "UI Composite Pass",
+                &[layer],
+                &[render::Attachment::load(surface)],
+            );
+            graph.set_enabled(composite, !self.sprites.is_empty() || ui_hooks);
+        } else {
+            let sprites = graph.add_pass("Sprite Pass", &[], &[render::Attachment::load(surface)]);
+            graph.set_enabled(sprites, !self.sprites.is_empty());
+            add_hooks(&mut graph, compose::PassSlot::Ui, surface);
+        }
+        add_hooks(&mut graph, compose::PassSlot::AfterUi, surface);
+        graph
+    }
+
+    /// Replaces the instances the model is drawn with, e.g. with the output
+    /// of [`scatter::scatter_on_mesh`]. If they don't fit in GPU memory,
+    /// the old ones are kept.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) -> Result<(), gpu::AllocError> {
+        self.instance_buffer = instancing::InstanceBuffer::from_instances(
+            &self.device,
+            &self.queue,
+            "Instance Buffer",
+            &instances,
+        )?;
+        self.pipelines.use_layout(self.instance_buffer.layout());
+        self.instances = instances;
+        Ok(())
+    }
+
+    /// Sprites pushed here are drawn over the scene in the next frame.
+    pub fn sprites_mut(&mut self) -> &mut sprite::SpriteBatch {
+        &mut self.sprites
+    }
+
+    /// Particles pushed here are blended into the scene in the next frame.
+    pub fn particles_mut(&mut self) -> &mut particle::ParticleBatch {
+        &mut self.particles
+    }
+
+    /// The camera's view projection as of the last [`Self::update`], mapping
+    /// to wgpu clip space.
+    pub fn view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.camera_uniform.view_proj.into()
+    }
+
+    /// The ray from the camera through `position`, e.g. the cursor.
+    pub fn cursor_ray(&self, position: winit::dpi::PhysicalPosition<f64>) -> Option<accel::Ray> {
+        accel::Ray::from_screen(
+            &self.view_proj(),
+            (position.x as f32, position.y as f32),
+            (self.size.width, self.size.height),
+        )
+    }
+
+    /// Turns the camera by mouse movement, e.g. from
+    /// [`pointer::PointerLook::take_delta`].
+    pub fn look(&mut self, delta: (f32, f32)) {
+        if delta != (0.0, 0.0) {
+            self.camera_controller.look(&mut self.camera, delta);
+        }
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera_controller.mode
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_controller.mode = mode;
+    }
+
+    /// Reconfigures the surface. The depth target is rebuilt once the size
+    /// settles or a frame is drawn, so dragging the window doesn't rebuild
+    /// it for every event. A zero size pauses rendering.
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        let size = (new_size.width, new_size.height);
+        if self.resize_debounce.resize(size, stats::now()) {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
+            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
             self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
         }
     }
-    fn input(&mut self, event: &WindowEvent) -> bool {
+
+    /// Recreates the depth target to match the current surface size.
+    pub fn rebuild_depth_texture(&mut self) -> Result<(), gpu::AllocError> {
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture")?;
+        self.particles
+            .set_depth(&self.device, &self.depth_texture.view);
+        let size = (self.config.width, self.config.height);
+        let failed = match &mut self.refraction {
+            Some(refraction) => refraction.resize(&self.device, size).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            log::warn!("Glass isn't drawn: {}", e);
+            self.refraction = None;
+        }
+        self.ui.resize(&self.device, size)?;
+        if let Some(exposure) = &mut self.exposure {
+            exposure.resize(&self.device, size)?;
+        }
+        self.resize_debounce
+            .rebuilt((self.config.width, self.config.height));
+        stats::note_event(stats::FrameEvent::TargetsRebuilt(
+            self.config.width,
+            self.config.height,
+        ));
+        Ok(())
+    }
+
+    /// Rebuilds the depth target if it's due, right away if `needed` for a
+    /// frame.
+    fn prepare_targets(&mut self, needed: bool) -> Result<(), gpu::AllocError> {
+        if self.resize_debounce.due(stats::now(), needed).is_some() {
+            self.rebuild_depth_texture()?;
+        }
+        Ok(())
+    }
+
+    /// Whether rendering is paused for a zero sized window, e.g. while
+    /// minimized. [`Self::render`] then does nothing.
+    pub fn is_paused(&self) -> bool {
+        self.resize_debounce.is_paused()
+    }
+
+    /// How many times the depth target was rebuilt since startup.
+    pub fn target_rebuilds(&self) -> u64 {
+        self.resize_debounce.rebuilds()
+    }
+
+    /// Acquires the surface texture for a frame, or `None` if the frame
+    /// should be skipped: while paused, or if the texture doesn't match the
+    /// depth target, so no pass is recorded with mismatched extents.
+    fn begin_frame(&mut self) -> Result<Option<wgpu::SurfaceTexture>, RenderError> {
+        if self.is_paused() {
+            return Ok(None);
+        }
+        self.prepare_targets(true)?;
+        let output = self.surface.get_current_texture()?;
+        let size = (output.texture.width(), output.texture.height());
+        if !self.resize_debounce.matches(size) {
+            log::warn!("Skipping a {}x{} frame during a resize", size.0, size.1);
+            return Ok(None);
+        }
+        Ok(Some(output))
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
         self.camera_controller.process_events(event)
     }
 
-    fn update(&mut self) {
+    /// Moves the camera and uploads what the next frame needs. Fails if a
+    /// target that was due to be rebuilt, or the light buffer, couldn't be.
+    pub fn update(&mut self) -> Result<(), gpu::AllocError> {
+        let started = stats::now();
+        self.readbacks.poll(&self.device);
+        if let Some(exposure) = &mut self.exposure {
+            exposure.adapt(&self.device, &self.queue, started);
+        }
+        self.prepare_targets(false)?;
         self.camera_controller.update_camera(&mut self.camera);
         self.camera_uniform.update_view_proj(&self.camera);
         self.queue.write_buffer(
@@ -530,10 +1942,129 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+        if let Some((_, pipeline)) = &self.point_cloud {
+            pipeline.prepare(
+                &self.queue,
+                self.view_proj(),
+                (self.config.width, self.config.height),
+            );
+        }
+        self.debug_light
+            .update(&self.device, &self.queue, &self.lights)?;
+        self.update_seconds = Some(stats::now() - started);
+        Ok(())
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+    /// Draws `frame`, with its handles resolved in `models` and
+    /// `materials`, instead of the built in scene. With auto exposure on,
+    /// every hook slot draws into the HDR target, as there is no UI pass to
+    /// expose the scene before.
+    pub fn render_frame(
+        &mut self,
+        frame: &frame::RenderFrame,
+        models: &assets::Assets<model::Model>,
+        materials: &assets::Assets<model::Material>,
+    ) -> Result<(), RenderError> {
+        let record_started = stats::now();
+        let Some(output) = self.begin_frame()? else {
+            return Ok(());
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.pipelines.poll();
+        let instance_layout = frame.instance_layout();
+        self.pipelines.use_layout(instance_layout);
+        if models.iter().any(|(_, model)| model.has_triplanar()) {
+            self.pipelines.spawn(variant::MaterialFeatures::TRIPLANAR);
+        }
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Frame Encoder"),
+            });
+
+        let mut uniforms = self.uniform_ring.begin_frame(&self.device, &self.queue);
+
+        let allocations = stats::CountingAllocator::allocations();
+        let mut frame_stats = self.frame_renderer.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &frame::RenderTarget {
+                color: self
+                    .exposure
+                    .as_ref()
+                    .map_or(&view, |exposure| exposure.tonemap.view()),
+                depth: &self.depth_texture.view,
+                size: (self.config.width, self.config.height),
+            },
+            &frame::FrameResources {
+                models,
+                materials,
+                pipeline: self
+                    .pipelines
+                    .plain(model::VertexPrecision::Full, instance_layout),
+                compressed_pipeline: self
+                    .pipelines
+                    .plain(model::VertexPrecision::Compressed, instance_layout),
+                triplanar_pipelines: self.pipelines.triplanar(instance_layout),
+            },
+            frame,
+            &mut self.hooks,
+            &mut uniforms,
+        )?;
+        if let Some(exposure) = &mut self.exposure {
+            exposure.record(&self.device, &mut encoder, &view, &mut frame_stats);
+        }
+        // The UI passes draw in the UI format, so they wait for the scene to
+        // be exposed, as in `render`.
+        if self.hooks.pass_count(compose::PassSlot::Ui) > 0 {
+            let ui_view = self.ui.target(&output.texture);
+            frame_stats.passes += self.ui.clear(&mut encoder, &ui_view) as u32;
+            frame_stats.passes += self.hooks.run(
+                compose::PassSlot::Ui,
+                &mut compose::PassContext {
+                    device: &self.device,
+                    queue: &self.queue,
+                    encoder: &mut encoder,
+                    color: &ui_view,
+                    depth: &self.depth_texture.view,
+                    size: (self.config.width, self.config.height),
+                    uniforms: &mut uniforms,
+                },
+            );
+            if self.ui.has_layer() {
+                frame_stats.passes += 1;
+                self.ui.add_stats(&mut frame_stats);
+                self.ui.composite(&mut encoder, &view);
+            }
+        }
+        frame_stats.allocations = stats::CountingAllocator::allocations() - allocations;
+        self.frame_stats = frame_stats;
+
+        self.record_screenshot(&mut encoder, &output.texture);
+        self.queue.submit(iter::once(encoder.finish()));
+        self.readbacks.after_submit();
+        if let Some(exposure) = &mut self.exposure {
+            exposure.meter.after_submit();
+        }
+        output.present();
+        self.push_frame_timing(record_started);
+
+        Ok(())
+    }
+
+    /// Draws the built in scene: the instances of the loaded model, glass,
+    /// the point cloud, the light gizmos, particles and sprites. The
+    /// instances are neither culled nor batched; scenes kept in game state
+    /// go through [`Self::render_frame`] instead, which does both for a
+    /// [`frame::RenderFrame`].
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        let record_started = stats::now();
+        let Some(output) = self.begin_frame()? else {
+            return Ok(());
+        };
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -544,50 +2075,291 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        self.pipelines.poll();
+        if self.obj_model.has_triplanar()
+            || self
+                .glass
+                .as_ref()
+                .is_some_and(|(model, ..)| model.has_triplanar())
+        {
+            self.pipelines.spawn(variant::MaterialFeatures::TRIPLANAR);
+        }
+
+        let allocations = stats::CountingAllocator::allocations();
+        let mut frame_stats = stats::FrameStats {
+            passes: 1,
+            ..Default::default()
+        };
+        if !self.instances.is_empty() {
+            frame_stats.pipeline_sets += match self
+                .pipelines
+                .for_model(&self.obj_model, self.instance_buffer.layout())
+                .1
+            {
+                Some(_) => self.obj_model.triplanar_pipeline_sets(),
+                None => 1,
+            };
+            frame_stats.add_model(&self.obj_model, self.instances.len() as u32);
+        }
+        if let Some((model, instances)) = &self.glass {
+            frame_stats.pipeline_sets += match self.pipelines.for_model(model, instances.layout()).1
+            {
+                Some(_) => model.triplanar_pipeline_sets(),
+                None => 1,
+            };
+            frame_stats.add_model(model, 1);
+        }
+        if let Some((cloud, pipeline)) = &self.point_cloud {
+            pipeline.add_stats(&mut frame_stats, cloud);
+        }
+        self.debug_light.add_stats(&mut frame_stats);
+
+        // Empty, which doesn't allocate, in frames without glass.
+        let refraction_draws = match &mut self.refraction {
+            Some(refraction) => {
+                let instanced = (!self.instances.is_empty()).then(|| refraction::RefractionDraw {
+                    model: &self.obj_model,
+                    instance_buffer: self.instance_buffer.buffer(),
+                    instances: 0..self.instances.len() as u32,
+                });
+                let glass = self.glass.as_ref().map(|(model, instance_buffer)| {
+                    refraction::RefractionDraw {
+                        model,
+                        instance_buffer: instance_buffer.buffer(),
+                        instances: 0..1,
+                    }
+                });
+                let draws = instanced
+                    .into_iter()
+                    .chain(glass)
+                    .filter(|draw| refraction::has_refractive(draw.model))
+                    .collect::<Vec<_>>();
+                refraction.prepare(
+                    &self.queue,
+                    &self.camera.refraction_camera(),
+                    &self.ambient,
+                    &draws,
+                );
+                draws
+            }
+            None => Vec::new(),
+        };
+        // With auto exposure, everything before the UI draws into the HDR
+        // target, which is exposed onto the surface before the sprites.
+        let color = self
+            .exposure
+            .as_ref()
+            .map_or(&view, |exposure| exposure.tonemap.view());
+        // Frames with glass draw the opaque scene offscreen, for the
+        // refraction pass to sample and then draw onto the target.
+        let refraction = self.refraction.as_ref().filter(|refraction| {
+            !refraction.is_empty() && refraction.scene_texture().size() == output.texture.size()
+        });
+        let scene_view = refraction.map_or(color, |refraction| refraction.scene_view());
+
+        let size = (self.config.width, self.config.height);
+        let mut uniforms = self.uniform_ring.begin_frame(&self.device, &self.queue);
+        let mut run_hooks = |hooks: &mut compose::FrameHooks,
+                             encoder: &mut wgpu::CommandEncoder,
+                             color: &wgpu::TextureView,
+                             slot| {
+            hooks.run(
+                slot,
+                &mut compose::PassContext {
+                    device: &self.device,
+                    queue: &self.queue,
+                    encoder,
+                    color,
+                    depth: &self.depth_texture.view,
+                    size,
+                    uniforms: &mut uniforms,
+                },
+            )
+        };
+        frame_stats.passes += run_hooks(
+            &mut self.hooks,
+            &mut encoder,
+            scene_view,
+            compose::PassSlot::BeforeScene,
+        );
         {
+            let loads = self.hooks.loads();
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: scene_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: loads.color,
                         store: true,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: loads.depth,
                         store: true,
                     }),
                     stencil_ops: None,
                 }),
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
+            // An empty instance buffer can't be bound, e.g. after scattering
+            // found no room.
+            if !self.instances.is_empty() {
+                self.pipelines.draw(
+                    &mut render_pass,
+                    &self.obj_model,
+                    &self.instance_buffer,
+                    &self.camera_bind_group,
+                );
+            }
+            if let Some((model, instance_buffer)) = &self.glass {
+                self.pipelines.draw(
+                    &mut render_pass,
+                    model,
+                    instance_buffer,
+                    &self.camera_bind_group,
+                );
+            }
+            if let Some((cloud, pipeline)) = &self.point_cloud {
+                pipeline.draw(&mut render_pass, cloud);
+            }
+            self.debug_light
+                .draw(&mut render_pass, &self.camera_bind_group);
+        }
+        frame_stats.passes += run_hooks(
+            &mut self.hooks,
+            &mut encoder,
+            scene_view,
+            compose::PassSlot::AfterOpaque,
+        );
+
+        if let Some(refraction) = refraction {
+            frame_stats.passes += 1;
+            refraction.add_stats(&mut frame_stats, &refraction_draws);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Refraction Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color,
+                    resolve_target: None,
+                    // The whole surface is drawn from the scene.
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            refraction.draw(&mut render_pass, &refraction_draws);
+        }
+
+        self.particles
+            .prepare(&self.device, &self.queue, &self.camera.particle_camera())?;
+        if !self.particles.is_empty() {
+            frame_stats.passes += 1;
+            self.particles.add_stats(&mut frame_stats);
+            // No depth attachment, as the depth texture is bound for reading.
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Particle Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.particles.draw(&mut render_pass);
+        }
+
+        for slot in [
+            compose::PassSlot::AfterTransparent,
+            compose::PassSlot::BeforeUi,
+        ] {
+            frame_stats.passes += run_hooks(&mut self.hooks, &mut encoder, color, slot);
+        }
+        if let Some(exposure) = &mut self.exposure {
+            exposure.record(&self.device, &mut encoder, &view, &mut frame_stats);
+        }
+
+        let camera = self.camera.particle_camera();
+        self.sprites.set_camera(sprite::SpriteCamera {
+            view: camera.view,
+            projection: camera.projection,
+        });
+        self.sprites.prepare(
+            &self.device,
+            &self.queue,
+            self.size,
+            self.window.scale_factor(),
+        )?;
+        if !self.sprites.is_empty() || self.hooks.pass_count(compose::PassSlot::Ui) > 0 {
+            let ui_view = self.ui.target(&output.texture);
+            if !self.sprites.is_empty() {
+                frame_stats.passes += 1;
+                self.sprites.add_stats(&mut frame_stats);
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Sprite Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &ui_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: self.ui.load_op(),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                self.sprites.draw(&mut render_pass);
+            } else if self.ui.clear(&mut encoder, &ui_view) {
+                frame_stats.passes += 1;
+            }
+            frame_stats.passes += run_hooks(
+                &mut self.hooks,
+                &mut encoder,
+                &ui_view,
+                compose::PassSlot::Ui,
             );
+            if self.ui.has_layer() {
+                frame_stats.passes += 1;
+                self.ui.add_stats(&mut frame_stats);
+                self.ui.composite(&mut encoder, &view);
+            }
         }
+        frame_stats.passes += run_hooks(
+            &mut self.hooks,
+            &mut encoder,
+            &view,
+            compose::PassSlot::AfterUi,
+        );
+        frame_stats.allocations = stats::CountingAllocator::allocations() - allocations;
+        self.frame_stats = frame_stats;
 
+        self.record_screenshot(&mut encoder, &output.texture);
         self.queue.submit(iter::once(encoder.finish()));
+        self.readbacks.after_submit();
+        if let Some(exposure) = &mut self.exposure {
+            exposure.meter.after_submit();
+        }
         output.present();
+        self.push_frame_timing(record_started);
 
         Ok(())
     }
 }
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
+/// Sets up logging (and the panic hook on web). Call once at startup.
+pub fn init_logger() {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -596,12 +2368,13 @@ pub async fn run() {
             env_logger::init();
         }
     }
+}
 
-    let event_loop = EventLoop::new();
-    let title = env!("CARGO_PKG_NAME");
+/// Builds the window, attaching its canvas to the `wasm-example` element on web.
+pub fn create_window(event_loop: &EventLoop<()>, title: &str) -> Window {
     let window = winit::window::WindowBuilder::new()
         .with_title(title)
-        .build(&event_loop)
+        .build(event_loop)
         .unwrap();
 
     #[cfg(target_arch = "wasm32")]
@@ -623,8 +2396,31 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
+    window
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
+pub async fn run() {
+    init_logger();
+
+    let event_loop = EventLoop::new();
+    let window = create_window(&event_loop, env!("CARGO_PKG_NAME"));
+
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(window).await;
+    let mut state = match State::new(window).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Couldn't set up rendering: {}", e);
+            return;
+        }
+    };
+    let mut window_controller = window::WindowController::new(env!("CARGO_PKG_NAME"));
+    match resources::load_icon("icon.png").await {
+        Ok(icon) => state.window().set_window_icon(Some(icon)),
+        Err(e) => log::warn!("Couldn't load the window icon: {}", e),
+    }
+    let mut pointer_look = pointer::PointerLook::default();
+    let mut shown_hint = None;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -634,7 +2430,8 @@ pub async fn run() {
                 ref event,
                 window_id,
             } if window_id == state.window().id() => {
-                if !state.input(event) {
+                pointer_look.process_window_event(state.window(), event);
+                if !state.input(event) && !window_controller.process_events(state.window(), event) {
                     match event {
                         WindowEvent::CloseRequested
                         | WindowEvent::KeyboardInput {
@@ -656,18 +2453,38 @@ pub async fn run() {
                     }
                 }
             }
+            Event::DeviceEvent { ref event, .. } => pointer_look.process_device_event(event),
             Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-                state.update();
-                match state.render() {
+                pointer_look.update(state.window());
+                // There is no UI layer to draw text with, so the title bar
+                // carries the hint.
+                if pointer_look.hint() != shown_hint {
+                    shown_hint = pointer_look.hint();
+                    window_controller.set_title_info(state.window(), shown_hint.unwrap_or(""));
+                }
+                state.look(pointer_look.take_delta());
+                let rendered = state
+                    .update()
+                    .map_err(RenderError::from)
+                    .and_then(|()| state.render());
+                match rendered {
                     Ok(_) => {}
                     // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
-                    }
+                    Err(RenderError::Surface(
+                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                    )) => state.resize(state.size),
                     // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    Err(RenderError::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::Alloc(e)) => {
+                        log::error!("{}", e);
+                        *control_flow = ControlFlow::Exit
+                    }
                     // We're ignoring timeouts
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+                    Err(RenderError::Surface(wgpu::SurfaceError::Timeout)) => {
+                        log::warn!("Surface timeout")
+                    }
                 }
             }
             _ => {}
@@ -0,0 +1,265 @@
+use crate::{
+    gpu,
+    model::{PrimitiveVertex, Vertex},
+    primitives,
+    stats::FrameStats,
+    texture,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    pub position: [f32; 3],
+    // Uniforms require 16 byte (4 float) spacing, so we need to use padding fields
+    pub _padding: u32,
+    pub color: [f32; 3],
+    pub _padding2: u32,
+    /// The light's tile in the shadow atlas as `(x, y, width, height)` in
+    /// UVs, from [`ShadowAtlas::uv_rect`](crate::shadow::ShadowAtlas::uv_rect),
+    /// or all zero without a shadow.
+    pub shadow_rect: [f32; 4],
+}
+
+impl LightUniform {
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position,
+            _padding: 0,
+            color,
+            _padding2: 0,
+            shadow_rect: [0.0; 4],
+        }
+    }
+
+    /// Layout for using lights as per instance data, as [`DebugLight`] does.
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LightUniform>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Light arriving from everywhere, so surfaces facing away from every light
+/// aren't black. It blends from `ground_color` for surfaces facing down to
+/// `sky_color` for surfaces facing up, by the world normal's Y.
+///
+/// This is the only indirect term for now. Once there is an SH probe grid
+/// or an IBL irradiance map they should replace it where they cover the
+/// scene, probes first, then IBL, then this.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Ambient {
+    pub sky_color: [f32; 3],
+    pub intensity: f32,
+    pub ground_color: [f32; 3],
+    _padding: u32,
+}
+
+impl Ambient {
+    pub const fn new(sky_color: [f32; 3], ground_color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            sky_color,
+            intensity,
+            ground_color,
+            _padding: 0,
+        }
+    }
+
+    /// The ambient light reaching a surface with the unit world `normal`, as
+    /// the shader works it out.
+    pub fn at(&self, normal: [f32; 3]) -> [f32; 3] {
+        let t = normal[1] * 0.5 + 0.5;
+        let mut color = [0.0; 3];
+        for (i, c) in color.iter_mut().enumerate() {
+            *c = (self.ground_color[i] + (self.sky_color[i] - self.ground_color[i]) * t)
+                * self.intensity;
+        }
+        color
+    }
+}
+
+impl Default for Ambient {
+    /// White from every direction, which leaves textures as they are, since
+    /// there is no direct lighting in the main shader yet.
+    fn default() -> Self {
+        Self::new([1.0; 3], [1.0; 3], 1.0)
+    }
+}
+
+/// Draws a small emissive sphere at the position of every light, all in a
+/// single instanced draw.
+pub struct DebugLight {
+    pub enabled: bool,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_elements: u32,
+    light_buffer: wgpu::Buffer,
+    /// The sphere buffers, then the light buffer, which is replaced as it grows.
+    _memory: [gpu::MemoryToken; 3],
+    light_capacity: usize,
+    num_lights: u32,
+}
+
+impl DebugLight {
+    /// Radius of the gizmo spheres in world units.
+    pub const RADIUS: f32 = 0.25;
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self, gpu::AllocError> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("light.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Light Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Light Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[PrimitiveVertex::desc(), LightUniform::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let (vertices, indices) = primitives::uv_sphere(Self::RADIUS, 12, 8);
+        let (vertex_buffer, vertex_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Debug Light Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+                gpu::MemoryCategory::Vertex,
+            )?;
+        let (index_buffer, index_buffer_memory) = gpu::Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Debug Light Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+            gpu::MemoryCategory::Index,
+        )?;
+
+        let light_capacity = 1;
+        let (light_buffer, light_memory) = Self::create_light_buffer(device, light_capacity)?;
+        Ok(Self {
+            enabled: true,
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            light_buffer,
+            _memory: [vertex_buffer_memory, index_buffer_memory, light_memory],
+            light_capacity,
+            num_lights: 0,
+        })
+    }
+
+    fn create_light_buffer(
+        device: &wgpu::Device,
+        capacity: usize,
+    ) -> Result<(wgpu::Buffer, gpu::MemoryToken), gpu::AllocError> {
+        gpu::Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Debug Light Instance Buffer"),
+                size: (capacity * std::mem::size_of::<LightUniform>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            gpu::MemoryCategory::Vertex,
+        )
+    }
+
+    /// Uploads the lights to draw, growing the instance buffer if needed.
+    /// If it can't grow, no lights are drawn.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[LightUniform],
+    ) -> Result<(), gpu::AllocError> {
+        if lights.len() > self.light_capacity {
+            self.num_lights = 0;
+            let capacity = lights.len().next_power_of_two();
+            (self.light_buffer, self._memory[2]) = Self::create_light_buffer(device, capacity)?;
+            self.light_capacity = capacity;
+        }
+        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
+        self.num_lights = lights.len() as u32;
+        Ok(())
+    }
+
+    /// Adds the commands [`Self::draw`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats) {
+        if !self.enabled || self.num_lights == 0 {
+            return;
+        }
+        stats.pipeline_sets += 1;
+        stats.bind_group_sets += 1;
+        stats.draw_calls += 1;
+        stats.instances += self.num_lights;
+        stats.triangles += (self.num_elements / 3) as u64 * self.num_lights as u64;
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.enabled || self.num_lights == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.light_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.draw_indexed(0..self.num_elements, 0, 0..self.num_lights);
+    }
+}
@@ -0,0 +1,363 @@
+//! Projection matrices in wgpu's conventions: right handed view space
+//! looking down -Z, and clip space depth in `0..1` rather than OpenGL's
+//! `-1..1`. Everything in the crate builds its projections here instead of
+//! with `cgmath::perspective` or `cgmath::ortho`, which use OpenGL's range.
+
+use cgmath::{Matrix4, Rad, Vector4};
+
+/// How view distance maps to the depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DepthRange {
+    /// Near maps to 0 and far to 1. Pairs with `CompareFunction::Less`.
+    #[default]
+    Standard,
+    /// Near maps to 1 and far to 0, which spreads float precision far more
+    /// evenly. Pairs with `CompareFunction::Greater` and clearing to 0.
+    Reversed,
+}
+
+/// WGSL versions of [`linearize_depth`] and [`delinearize_depth`], to be
+/// prepended to shaders using them. The reversed-Z variants have a
+/// `_reversed` suffix.
+pub const WGSL: &str = include_str!("projection.wgsl");
+
+/// A perspective projection with a vertical field of view of `fovy`.
+/// `near` and `far` are positive distances in front of the camera.
+pub fn perspective(
+    fovy: Rad<f32>,
+    aspect: f32,
+    near: f32,
+    far: f32,
+    depth: DepthRange,
+) -> Matrix4<f32> {
+    let f = 1.0 / (fovy.0 / 2.0).tan();
+    // z_clip = a * z_view + b and w_clip = -z_view.
+    let (a, b) = match depth {
+        DepthRange::Standard => (far / (near - far), near * far / (near - far)),
+        DepthRange::Reversed => (near / (far - near), near * far / (far - near)),
+    };
+    Matrix4::from_cols(
+        Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+        Vector4::new(0.0, f, 0.0, 0.0),
+        Vector4::new(0.0, 0.0, a, -1.0),
+        Vector4::new(0.0, 0.0, b, 0.0),
+    )
+}
+
+/// A [`perspective`] projection with no far plane, so nothing is clipped
+/// for being too far away. Depth approaches the far value without reaching
+/// it; reversed-Z keeps far more precision out there.
+pub fn perspective_infinite(
+    fovy: Rad<f32>,
+    aspect: f32,
+    near: f32,
+    depth: DepthRange,
+) -> Matrix4<f32> {
+    let f = 1.0 / (fovy.0 / 2.0).tan();
+    let (a, b) = match depth {
+        DepthRange::Standard => (-1.0, -near),
+        DepthRange::Reversed => (0.0, near),
+    };
+    Matrix4::from_cols(
+        Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+        Vector4::new(0.0, f, 0.0, 0.0),
+        Vector4::new(0.0, 0.0, a, -1.0),
+        Vector4::new(0.0, 0.0, b, 0.0),
+    )
+}
+
+/// An orthographic projection of the given view space box. `near` and `far`
+/// are distances in front of the camera and may be negative.
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+    depth: DepthRange,
+) -> Matrix4<f32> {
+    let (a, b) = match depth {
+        DepthRange::Standard => (-1.0 / (far - near), -near / (far - near)),
+        DepthRange::Reversed => (1.0 / (far - near), far / (far - near)),
+    };
+    Matrix4::from_cols(
+        Vector4::new(2.0 / (right - left), 0.0, 0.0, 0.0),
+        Vector4::new(0.0, 2.0 / (top - bottom), 0.0, 0.0),
+        Vector4::new(0.0, 0.0, a, 0.0),
+        Vector4::new(
+            -(right + left) / (right - left),
+            -(top + bottom) / (top - bottom),
+            b,
+            1.0,
+        ),
+    )
+}
+
+/// Converts a projection made for OpenGL's `-1..1` depth, e.g. by another
+/// library, to [`DepthRange::Standard`].
+pub fn from_opengl(projection: Matrix4<f32>) -> Matrix4<f32> {
+    // Maps z from -1..1 to 0..1, leaving x, y and w alone.
+    Matrix4::from_cols(
+        Vector4::unit_x(),
+        Vector4::unit_y(),
+        Vector4::new(0.0, 0.0, 0.5, 0.0),
+        Vector4::new(0.0, 0.0, 0.5, 1.0),
+    ) * projection
+}
+
+/// The view distance of a depth buffer value written with a [`perspective`]
+/// projection using the same `near`, `far` and `depth`. A `far` of
+/// infinity matches [`perspective_infinite`].
+pub fn linearize_depth(d: f32, near: f32, far: f32, depth: DepthRange) -> f32 {
+    match (depth, far.is_infinite()) {
+        (DepthRange::Standard, false) => near * far / (far - d * (far - near)),
+        (DepthRange::Reversed, false) => near * far / (near + d * (far - near)),
+        (DepthRange::Standard, true) => near / (1.0 - d),
+        (DepthRange::Reversed, true) => near / d,
+    }
+}
+
+/// The depth buffer value a [`perspective`] projection writes for a point
+/// `distance` in front of the camera. The inverse of [`linearize_depth`].
+pub fn delinearize_depth(distance: f32, near: f32, far: f32, depth: DepthRange) -> f32 {
+    match (depth, far.is_infinite()) {
+        (DepthRange::Standard, false) => far * (distance - near) / (distance * (far - near)),
+        (DepthRange::Reversed, false) => near * (far - distance) / (distance * (far - near)),
+        (DepthRange::Standard, true) => 1.0 - near / distance,
+        (DepthRange::Reversed, true) => near / distance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Point3, SquareMatrix, Transform, Vector3};
+
+    use super::*;
+
+    const DEPTHS: [DepthRange; 2] = [DepthRange::Standard, DepthRange::Reversed];
+    /// `(near, far)` pairs from tight to very deep.
+    const PLANES: [(f32, f32); 4] = [(0.1, 100.0), (0.5, 30.0), (1.0, 2.0), (0.01, 10_000.0)];
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!(
+            (a - b).abs() <= tolerance * b.abs().max(1.0),
+            "{} isn't close to {}",
+            a,
+            b
+        );
+    }
+
+    /// The relative error to allow in a view distance recovered from depth.
+    /// Standard depth spends most of its float precision near the camera,
+    /// so far points come back only roughly, which is why reversed-Z exists.
+    fn distance_tolerance(depth: DepthRange, distance: f32, near: f32) -> f32 {
+        match depth {
+            DepthRange::Standard => 1e-4 + 4.0 * f32::EPSILON * distance / near,
+            DepthRange::Reversed => 1e-4,
+        }
+    }
+
+    /// Where `projection` puts a view space point, after the divide.
+    fn ndc(projection: &Matrix4<f32>, point: Point3<f32>) -> Point3<f32> {
+        projection.transform_point(point)
+    }
+
+    fn near_depth(depth: DepthRange) -> f32 {
+        match depth {
+            DepthRange::Standard => 0.0,
+            DepthRange::Reversed => 1.0,
+        }
+    }
+
+    #[test]
+    fn perspective_maps_near_and_far_to_depth_range() {
+        for depth in DEPTHS {
+            for (near, far) in PLANES {
+                let projection = perspective(Deg(60.0).into(), 1.5, near, far, depth);
+                let at_near = ndc(&projection, Point3::new(0.0, 0.0, -near));
+                let at_far = ndc(&projection, Point3::new(0.0, 0.0, -far));
+                assert_close(at_near.z, near_depth(depth), 1e-5);
+                assert_close(at_far.z, 1.0 - near_depth(depth), 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn perspective_frustum_corners_reach_ndc_corners() {
+        let (fovy, aspect) = (Deg(90.0), 2.0);
+        for depth in DEPTHS {
+            for (near, far) in PLANES {
+                let projection = perspective(fovy.into(), aspect, near, far, depth);
+                // At a 90 degree field of view, the frustum is as tall as it
+                // is deep.
+                for distance in [near, far] {
+                    for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                        let corner = Point3::new(x * aspect * distance, y * distance, -distance);
+                        let p = ndc(&projection, corner);
+                        assert_close(p.x, x, 1e-4);
+                        assert_close(p.y, y, 1e-4);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reversed_z_swaps_depth_order() {
+        for (near, far) in PLANES {
+            let standard = perspective(Deg(45.0).into(), 1.0, near, far, DepthRange::Standard);
+            let reversed = perspective(Deg(45.0).into(), 1.0, near, far, DepthRange::Reversed);
+            let mid = Point3::new(0.0, 0.0, -(near + far) / 2.0);
+            let (a, b) = (ndc(&standard, mid).z, ndc(&reversed, mid).z);
+            assert!(a > 0.0 && a < 1.0 && b > 0.0 && b < 1.0);
+            // Closer points get smaller standard and larger reversed depths.
+            let closer = Point3::new(0.0, 0.0, mid.z / 2.0);
+            assert!(ndc(&standard, closer).z < a);
+            assert!(ndc(&reversed, closer).z > b);
+        }
+    }
+
+    #[test]
+    fn infinite_far_plane_never_reaches_far_depth() {
+        for depth in DEPTHS {
+            for (near, _) in PLANES {
+                let projection = perspective_infinite(Deg(60.0).into(), 1.0, near, depth);
+                let at_near = ndc(&projection, Point3::new(0.0, 0.0, -near));
+                assert_close(at_near.z, near_depth(depth), 1e-5);
+                let far = 1.0 - near_depth(depth);
+                let mut previous = at_near.z;
+                for distance in [10.0, 1e3, 1e5, 1e7] {
+                    let z = ndc(&projection, Point3::new(0.0, 0.0, -distance)).z;
+                    assert!((z - far).abs() <= (previous - far).abs());
+                    assert!((0.0..=1.0).contains(&z));
+                    // Standard depth can't tell the farthest of these apart.
+                    let tolerance = distance_tolerance(depth, distance, near);
+                    if tolerance < 0.1 {
+                        assert_close(
+                            linearize_depth(z, near, f32::INFINITY, depth),
+                            distance,
+                            tolerance,
+                        );
+                    }
+                    previous = z;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orthographic_matches_cgmath_with_depth_remapped() {
+        let boxes = [
+            (-1.0, 1.0, -1.0, 1.0, 0.1, 100.0),
+            (-4.0, 2.0, -3.0, 5.0, -10.0, 10.0),
+            (0.0, 1920.0, 1080.0, 0.0, -1.0, 1.0),
+        ];
+        for (left, right, bottom, top, near, far) in boxes {
+            let ours = orthographic(left, right, bottom, top, near, far, DepthRange::Standard);
+            let reference = from_opengl(cgmath::ortho(left, right, bottom, top, near, far));
+            for (a, b) in [ours.x, ours.y, ours.z, ours.w]
+                .iter()
+                .zip([reference.x, reference.y, reference.z, reference.w].iter())
+            {
+                for i in 0..4 {
+                    assert_close(a[i], b[i], 1e-5);
+                }
+            }
+            let reversed = orthographic(left, right, bottom, top, near, far, DepthRange::Reversed);
+            let at_near = ndc(&reversed, Point3::new(0.0, 0.0, -near));
+            let at_far = ndc(&reversed, Point3::new(0.0, 0.0, -far));
+            assert_close(at_near.z, 1.0, 1e-5);
+            assert_close(at_far.z, 0.0, 1e-5);
+        }
+    }
+
+    #[test]
+    fn perspective_matches_cgmath_with_depth_remapped() {
+        for (near, far) in PLANES {
+            let ours = perspective(Deg(70.0).into(), 1.25, near, far, DepthRange::Standard);
+            let reference = from_opengl(cgmath::perspective(Deg(70.0), 1.25, near, far));
+            for p in [
+                Point3::new(0.3, -0.2, -near * 2.0),
+                Point3::new(-5.0, 4.0, -(near + far) / 2.0),
+            ] {
+                let (a, b) = (ndc(&ours, p), ndc(&reference, p));
+                assert_close(a.x, b.x, 1e-4);
+                assert_close(a.y, b.y, 1e-4);
+                assert_close(a.z, b.z, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_unprojects_ndc_back_to_view_space() {
+        for depth in DEPTHS {
+            for (near, far) in PLANES {
+                // Orthographic depth is linear, so its f32 error is a fixed
+                // fraction of the whole range rather than of the distance.
+                let projections = [
+                    (perspective(Deg(50.0).into(), 1.6, near, far, depth), false),
+                    (orthographic(-3.0, 3.0, -2.0, 2.0, near, far, depth), true),
+                ];
+                for (projection, linear) in projections {
+                    // Inverted in f64, as inverting the deepest projections
+                    // in f32 loses more than the projections themselves do.
+                    let inverse = projection
+                        .cast::<f64>()
+                        .and_then(|p| p.invert())
+                        .expect("projections are invertible");
+                    for point in [
+                        Point3::new(0.0, 0.0, -near),
+                        Point3::new(0.5, -0.25, -(near + far) / 2.0),
+                        Point3::new(-1.0, 1.0, -far),
+                    ] {
+                        let projected = ndc(&projection, point).cast::<f64>().unwrap();
+                        let back = inverse.transform_point(projected).cast::<f32>().unwrap();
+                        let error = (back - point).magnitude() / point.z.abs();
+                        let tolerance = if linear {
+                            1e-4 + 4.0 * f32::EPSILON * far / -point.z
+                        } else {
+                            distance_tolerance(depth, -point.z, near)
+                        };
+                        assert!(error < tolerance, "{:?} came back as {:?}", point, back);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn linearize_round_trips() {
+        for depth in DEPTHS {
+            for (near, far) in PLANES {
+                for t in [0.0, 0.01, 0.25, 0.5, 0.9, 1.0] {
+                    let distance = near + (far - near) * t;
+                    let d = delinearize_depth(distance, near, far, depth);
+                    assert!((0.0..=1.0).contains(&d), "{} is out of range", d);
+                    assert_close(
+                        linearize_depth(d, near, far, depth),
+                        distance,
+                        distance_tolerance(depth, distance, near),
+                    );
+                    // The projection writes the same depth.
+                    let projection = perspective(Deg(60.0).into(), 1.0, near, far, depth);
+                    let z = ndc(&projection, Point3::new(0.0, 0.0, -distance)).z;
+                    assert_close(z, d, 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn from_opengl_maps_clip_range() {
+        let projection = from_opengl(Matrix4::identity());
+        for z in [-1.0f32, 0.0, 1.0] {
+            let p = projection.transform_vector(Vector3::new(0.0, 0.0, z));
+            assert_close(p.z, z * 0.5, 1e-6);
+        }
+        let p = projection.transform_point(Point3::new(0.0, 0.0, -1.0));
+        assert_close(p.z, 0.0, 1e-6);
+        let p = projection.transform_point(Point3::new(0.0, 0.0, 1.0));
+        assert_close(p.z, 1.0, 1e-6);
+    }
+}
@@ -0,0 +1,20 @@
+// Depth helpers matching math::projection, for projections built by it.
+// Depth buffer values are 0..1 as in wgpu; view distances are positive.
+
+// Standard depth: near maps to 0 and far to 1.
+fn linearize_depth(depth: f32, near: f32, far: f32) -> f32 {
+    return near * far / (far - depth * (far - near));
+}
+
+fn delinearize_depth(distance: f32, near: f32, far: f32) -> f32 {
+    return far * (distance - near) / (distance * (far - near));
+}
+
+// Reversed-Z: near maps to 1 and far to 0.
+fn linearize_depth_reversed(depth: f32, near: f32, far: f32) -> f32 {
+    return near * far / (near + depth * (far - near));
+}
+
+fn delinearize_depth_reversed(distance: f32, near: f32, far: f32) -> f32 {
+    return near * (far - distance) / (distance * (far - near));
+}
@@ -0,0 +1,57 @@
+// Builds a histogram of log2 luminance over an HDR target, for auto exposure.
+//
+// Bin 0 counts pixels too dark to have a useful log, the rest split
+// [min_log_luminance, min_log_luminance + log_luminance_range] evenly.
+
+struct Params {
+    min_log_luminance: f32,
+    inverse_log_luminance_range: f32,
+    _padding: vec2<f32>,
+}
+
+const BINS: u32 = 64u;
+
+@group(0) @binding(0)
+var hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 64>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// Counted per workgroup first, so most atomics stay in fast shared memory.
+var<workgroup> local_histogram: array<atomic<u32>, 64>;
+
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < 0.00001 {
+        return 0u;
+    }
+    let t = clamp(
+        (log2(luminance) - params.min_log_luminance) * params.inverse_log_luminance_range,
+        0.0,
+        1.0,
+    );
+    return min(u32(t * f32(BINS - 1u)) + 1u, BINS - 1u);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if local_index < BINS {
+        atomicStore(&local_histogram[local_index], 0u);
+    }
+    workgroupBarrier();
+
+    let size = textureDimensions(hdr);
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_histogram[bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    if local_index < BINS {
+        atomicAdd(&histogram[local_index], atomicLoad(&local_histogram[local_index]));
+    }
+}
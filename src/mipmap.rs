@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken},
+    reflect, stats,
+};
+
+/// Number of mip levels in a full chain for a texture of the given size.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Whether mips of `format` can be generated on the GPU with [`MipmapGenerator`].
+pub fn supports_gpu_generation(device: &wgpu::Device, format: wgpu::TextureFormat) -> bool {
+    let features = format.guaranteed_format_features(device.features());
+    features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        && matches!(
+            format.sample_type(None),
+            Some(wgpu::TextureSampleType::Float { .. })
+        )
+}
+
+/// The size of the level a blit renders, `blit.wgsl`'s `Blit`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlitUniform {
+    target_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// Fills in mip levels on the GPU by rendering each level from the previous
+/// one. Generation for any number of textures can be recorded into a single
+/// encoder, so a whole model's textures go out in one submission.
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    /// The source level and the size of the target, group 0 of
+    /// `blit.wgsl`. Texels are loaded and weighted in the shader, so there
+    /// is no sampler and the format doesn't need to be filterable.
+    pub(crate) const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let source = include_str!("blit.wgsl");
+        reflect::debug_check("blit.wgsl", source, &[&Self::LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("blit.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::LAYOUT_ENTRIES,
+            label: Some("mipmap_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn prepare_pipeline(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        let Self {
+            shader,
+            pipeline_layout,
+            pipelines,
+            ..
+        } = self;
+        pipelines.entry(format).or_insert_with(|| {
+            stats::note_event(stats::FrameEvent::PipelineBuilt(format!(
+                "Mipmap Pipeline ({:?})",
+                format
+            )));
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mipmap Pipeline"),
+                layout: Some(pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        });
+    }
+
+    /// Records passes that fill mip levels `1..mip_level_count` of `texture`
+    /// from level 0. The texture needs `TEXTURE_BINDING | RENDER_ATTACHMENT`
+    /// usage and a format accepted by [`supports_gpu_generation`].
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+    ) -> Result<(), AllocError> {
+        let views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("mip"),
+                    format: Some(format),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    base_array_layer: 0,
+                    array_layer_count: None,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for target_mip in 1..mip_level_count as usize {
+            self.blit(
+                device,
+                encoder,
+                &views[target_mip - 1],
+                &views[target_mip],
+                format,
+                (
+                    (texture.width() >> target_mip).max(1),
+                    (texture.height() >> target_mip).max(1),
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Records a pass that filters `source` down into `target`, which has the
+    /// given `format` and is `target_size` across. Each target texel is the
+    /// area weighted average of the source texels it covers, so any smaller
+    /// target is filtered correctly, but one much smaller than half of
+    /// `source` loads many texels per pixel.
+    pub fn blit(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        format: wgpu::TextureFormat,
+        target_size: (u32, u32),
+    ) -> Result<(), AllocError> {
+        let source = self.bind_source(device, source, target_size)?;
+        self.blit_bound(device, encoder, &source, target, format);
+        Ok(())
+    }
+
+    /// Binds `source` for blits into targets `target_size` across, to keep
+    /// for [`Self::blit_bound`] where the same view is filtered every frame.
+    pub fn bind_source(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        target_size: (u32, u32),
+    ) -> Result<BlitSource, AllocError> {
+        let (uniform, memory) = Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Mipmap Blit Buffer"),
+                contents: bytemuck::bytes_of(&BlitUniform {
+                    target_size: [target_size.0 as f32, target_size.1 as f32],
+                    _padding: [0.0; 2],
+                }),
+                usage: wgpu::BufferUsages::UNIFORM,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+        Ok(BlitSource {
+            bind_group,
+            _memory: memory,
+        })
+    }
+
+    /// Like [`Self::blit`], for a source bound with [`Self::bind_source`]
+    /// and a target of the size it was bound for.
+    pub fn blit_bound(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &BlitSource,
+        target: &wgpu::TextureView,
+        format: wgpu::TextureFormat,
+    ) {
+        self.prepare_pipeline(device, format);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipelines[&format]);
+        render_pass.set_bind_group(0, &source.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// A view bound with [`MipmapGenerator::bind_source`].
+pub struct BlitSource {
+    bind_group: wgpu::BindGroup,
+    _memory: MemoryToken,
+}
+
+pub(crate) fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// CPU fallback for formats the GPU path can't render to. Returns levels
+/// `1..` of the chain. Color channels are averaged in linear space when
+/// `srgb` is set; alpha is always linear.
+pub fn generate_cpu(base: &image::RgbaImage, srgb: bool) -> Vec<image::RgbaImage> {
+    let decode = |c: u8, channel: usize| {
+        if srgb && channel < 3 {
+            srgb_to_linear(c)
+        } else {
+            c as f32 / 255.0
+        }
+    };
+    let encode = |c: f32, channel: usize| {
+        if srgb && channel < 3 {
+            linear_to_srgb(c)
+        } else {
+            (c * 255.0).round().clamp(0.0, 255.0) as u8
+        }
+    };
+
+    let mut levels: Vec<image::RgbaImage> = Vec::new();
+    let count = mip_level_count(base.width(), base.height());
+    for _ in 1..count {
+        let source = levels.last().unwrap_or(base);
+        let (sw, sh) = source.dimensions();
+        let (dw, dh) = ((sw / 2).max(1), (sh / 2).max(1));
+        let level = image::RgbaImage::from_fn(dw, dh, |x, y| {
+            // Box filter over the source texels this texel covers, weighting
+            // the partially covered ones at the edges of odd sized levels.
+            let (x0, x1) = (
+                x as f32 * sw as f32 / dw as f32,
+                (x + 1) as f32 * sw as f32 / dw as f32,
+            );
+            let (y0, y1) = (
+                y as f32 * sh as f32 / dh as f32,
+                (y + 1) as f32 * sh as f32 / dh as f32,
+            );
+            let mut sum = [0.0f32; 4];
+            let mut total = 0.0;
+            for sy in y0.floor() as u32..(y1.ceil() as u32).min(sh) {
+                let wy = (y1.min(sy as f32 + 1.0) - y0.max(sy as f32)).max(0.0);
+                for sx in x0.floor() as u32..(x1.ceil() as u32).min(sw) {
+                    let wx = (x1.min(sx as f32 + 1.0) - x0.max(sx as f32)).max(0.0);
+                    let texel = source.get_pixel(sx, sy);
+                    for (channel, s) in sum.iter_mut().enumerate() {
+                        *s += decode(texel[channel], channel) * wx * wy;
+                    }
+                    total += wx * wy;
+                }
+            }
+            image::Rgba([
+                encode(sum[0] / total, 0),
+                encode(sum[1] / total, 1),
+                encode(sum[2] / total, 2),
+                encode(sum[3] / total, 3),
+            ])
+        });
+        levels.push(level);
+    }
+    levels
+}
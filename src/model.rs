@@ -1,8 +1,23 @@
-use std::ops::Range;
+pub mod report;
+
+use std::{ops::Range, sync::Arc};
 
 use cgmath::{Matrix4, Quaternion, Vector3};
 
-use crate::texture;
+use crate::{
+    ao,
+    bounds::{Aabb, MorphBounds, SkinBounds},
+    compression, gpu,
+    refraction::Refractive,
+    region::{self, DrawRegion},
+    texture,
+    triplanar::{Triplanar, TriplanarBinding},
+    units::UnitScale,
+    uv,
+    variant::MaterialFeatures,
+};
+
+pub use report::{MaterialReport, MeshReport, ModelReport, ReportTotals, TextureReport};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -14,6 +29,9 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    /// How much ambient light reaches the vertex, from 0 to 1, as baked by
+    /// [`MeshData::bake_vertex_ao`]. 1 when not baked.
+    pub occlusion: f32,
 }
 
 impl Vertex for ModelVertex {
@@ -38,11 +56,185 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
 
+/// Which vertex layout the loaders produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VertexPrecision {
+    /// [`ModelVertex`], 36 bytes per vertex.
+    #[default]
+    Full,
+    /// [`CompressedVertex`], 16 bytes per vertex. Needs the `vs_compressed`
+    /// pipeline and the per mesh dequantization uniform.
+    Compressed,
+}
+
+/// Half the size of [`ModelVertex`], at the cost of some precision.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CompressedVertex {
+    /// Position within the mesh bounds: 0 maps to `aabb.min` and 65535 to
+    /// `aabb.max`. The fourth component is the occlusion, 65535 for none.
+    pub position: [u16; 4],
+    /// Half precision floats.
+    pub tex_coords: [u16; 2],
+    /// Octahedral encoded unit normal.
+    pub normal: [i16; 2],
+}
+
+impl CompressedVertex {
+    pub fn encode(vertex: &ModelVertex, aabb: &Aabb) -> Self {
+        let p = vertex.position;
+        Self {
+            position: [
+                compression::quantize_unorm16(p[0], aabb.min.x, aabb.max.x),
+                compression::quantize_unorm16(p[1], aabb.min.y, aabb.max.y),
+                compression::quantize_unorm16(p[2], aabb.min.z, aabb.max.z),
+                compression::quantize_unorm16(vertex.occlusion, 0.0, 1.0),
+            ],
+            tex_coords: [
+                compression::f32_to_f16(vertex.tex_coords[0]),
+                compression::f32_to_f16(vertex.tex_coords[1]),
+            ],
+            normal: compression::octahedral_encode(vertex.normal),
+        }
+    }
+
+    pub fn decode(&self, aabb: &Aabb) -> ModelVertex {
+        let p = self.position;
+        ModelVertex {
+            position: [
+                compression::dequantize_unorm16(p[0], aabb.min.x, aabb.max.x),
+                compression::dequantize_unorm16(p[1], aabb.min.y, aabb.max.y),
+                compression::dequantize_unorm16(p[2], aabb.min.z, aabb.max.z),
+            ],
+            tex_coords: [
+                compression::f16_to_f32(self.tex_coords[0]),
+                compression::f16_to_f32(self.tex_coords[1]),
+            ],
+            normal: compression::octahedral_decode(self.normal),
+            occlusion: compression::dequantize_unorm16(p[3], 0.0, 1.0),
+        }
+    }
+}
+
+impl Vertex for CompressedVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<CompressedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Unorm16x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[u16; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float16x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[u16; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Snorm16x2,
+                },
+            ],
+        }
+    }
+}
+
+/// A point of a [`PointCloud`], 16 bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointVertex {
+    pub position: [f32; 3],
+    /// sRGB encoded, with linear alpha.
+    pub color: [u8; 4],
+}
+
+impl Vertex for PointVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PointVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Unorm8x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Points without faces, such as a LiDAR scan loaded with
+/// [`resources::load_ply`](crate::resources::load_ply). Drawn by
+/// [`points::PointPipeline`](crate::points::PointPipeline).
+pub struct PointCloud {
+    /// [`PointVertex`]es.
+    pub vertex_buffer: wgpu::Buffer,
+    pub num_points: u32,
+    /// `None` without any points.
+    pub aabb: Option<Aabb>,
+    pub memory: gpu::MemoryToken,
+}
+
+/// Per mesh data for the vertex shader, bound at group 2.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshUniform {
+    /// `position = offset + quantized * scale` for [`CompressedVertex`].
+    pub dequantize_offset: [f32; 4],
+    pub dequantize_scale: [f32; 4],
+}
+
+impl MeshUniform {
+    pub fn dequantize(aabb: &Aabb) -> Self {
+        let size = aabb.size();
+        Self {
+            dequantize_offset: [aabb.min.x, aabb.min.y, aabb.min.z, 0.0],
+            dequantize_scale: [size.x, size.y, size.z, 0.0],
+        }
+    }
+}
+
+/// Group 2 of `shader.wgsl`: the [`MeshUniform`] of compressed meshes.
+pub(crate) const MESH_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+    [wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+
+pub fn create_mesh_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &MESH_LAYOUT_ENTRIES,
+        label: Some("mesh_bind_group_layout"),
+    })
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct PrimitiveVertex {
@@ -78,18 +270,134 @@ impl Vertex for PrimitiveVertex {
     }
 }
 
+/// What a material uses a texture for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub enum TextureRole {
+    Diffuse,
+    Normal,
+    MetallicRoughness,
+    Emissive,
+    Occlusion,
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    pub diffuse_texture: Arc<texture::Texture>,
     pub bind_group: wgpu::BindGroup,
+    /// The maps the material has, which select its shader variant.
+    pub features: MaterialFeatures,
+    /// Meshes with glass materials are left out of the opaque pass and
+    /// drawn by [`RefractionPass`](crate::refraction::RefractionPass).
+    pub refractive: Option<Refractive>,
+    /// Set with [`Self::set_triplanar`]. Pipelines without
+    /// [`MaterialFeatures::TRIPLANAR`] draw the material with its UVs.
+    pub triplanar: Option<TriplanarBinding>,
+}
+
+impl Material {
+    /// Projects the diffuse map along the axes instead of reading the UVs,
+    /// or goes back to the UVs with `None`.
+    pub fn set_triplanar(
+        &mut self,
+        device: &wgpu::Device,
+        triplanar: Option<Triplanar>,
+    ) -> Result<(), gpu::AllocError> {
+        self.triplanar = triplanar
+            .map(|t| TriplanarBinding::new(device, &self.diffuse_texture, t))
+            .transpose()?;
+        if self.triplanar.is_some() {
+            self.features.insert(MaterialFeatures::TRIPLANAR);
+        } else {
+            self.features.remove(MaterialFeatures::TRIPLANAR);
+        }
+        Ok(())
+    }
+
+    /// Every texture the material uses, e.g. for listing them in a UI. The
+    /// loaders only produce diffuse maps so far, so that's all there is.
+    pub fn textures(&self) -> impl Iterator<Item = (TextureRole, &Arc<texture::Texture>)> {
+        std::iter::once((TextureRole::Diffuse, &self.diffuse_texture))
+    }
+}
+
+/// A mesh's vertices and indices on the CPU, before they are uploaded.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    /// A triangle list.
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Replaces the texture coordinates with ones projected from the
+    /// positions. For planar and box mapping, `scale` is texture repeats per
+    /// unit of distance; for cylindrical and spherical mapping, repeats
+    /// around and along the mesh. Vertices are duplicated where triangles
+    /// sharing them need different coordinates, such as along box edges and
+    /// the wrap-around seam.
+    pub fn generate_uvs(&mut self, projection: uv::UvProjection, scale: f32) {
+        uv::generate(self, projection, scale);
+    }
+
+    /// Bakes ambient occlusion into each vertex's
+    /// [`occlusion`](ModelVertex::occlusion) from `samples` rays over its
+    /// hemisphere, against the mesh's own triangles within `ray_distance`.
+    /// Too slow to run while loading large meshes, so the asset pipeline
+    /// does it; see [`ao::AoOptions`] for the other settings.
+    pub fn bake_vertex_ao(&mut self, samples: u32, ray_distance: f32) {
+        self.bake_vertex_ao_with_options(&ao::AoOptions {
+            samples,
+            ray_distance,
+            ..Default::default()
+        });
+    }
+
+    pub fn bake_vertex_ao_with_options(&mut self, options: &ao::AoOptions) {
+        ao::bake(self, options);
+    }
+}
+
+/// Where a mesh's vertices and indices live on the GPU.
+pub enum MeshGeometry {
+    /// Buffers owned by the mesh.
+    Buffers {
+        vertex_buffer: wgpu::Buffer,
+        index_buffer: wgpu::Buffer,
+    },
+    /// A range of a [`gpu::GeometryPool`] shared with other meshes.
+    Pooled(gpu::GeometryAllocation),
 }
 
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub geometry: MeshGeometry,
     pub num_elements: u32,
     pub material: usize,
+    /// Bounds of the mesh as loaded, before skinning or morphing.
+    pub aabb: Aabb,
+    pub skin_bounds: Option<SkinBounds>,
+    pub morph_bounds: Option<MorphBounds>,
+    /// [`MeshUniform`] bind group, for meshes that need one.
+    pub bind_group: Option<wgpu::BindGroup>,
+    /// The GPU memory the mesh's own buffers hold, released with it.
+    pub memory: Vec<gpu::MemoryToken>,
+}
+
+impl Mesh {
+    /// Conservative bounds for the given skinning palette and morph weights.
+    /// Falls back to the load time bounds for meshes that aren't animated.
+    pub fn current_bounds(&self, palette: Option<&[Matrix4<f32>]>, weights: &[f32]) -> Aabb {
+        let padding = match &self.morph_bounds {
+            Some(morph_bounds) => morph_bounds.padding(weights),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        };
+        match (&self.skin_bounds, palette) {
+            (Some(skin_bounds), Some(palette)) => skin_bounds
+                .current(palette, padding)
+                .unwrap_or_else(|| self.aabb.expanded(padding)),
+            _ => self.aabb.expanded(padding),
+        }
+    }
 }
 
 pub struct GLTFMesh {
@@ -107,12 +415,63 @@ pub struct GLTFPrimitive {
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub vertex_precision: VertexPrecision,
+    /// What the source's lengths were multiplied by to get to
+    /// [`units::TARGET_UNIT`](crate::units::TARGET_UNIT).
+    pub unit_scale: UnitScale,
+}
+
+impl Model {
+    /// The size of every mesh and material, with totals.
+    pub fn report(&self) -> ModelReport {
+        ModelReport::from_meshes(&self.meshes, &self.materials, self.vertex_precision)
+    }
+
+    /// Whether any mesh the opaque pass draws has a triplanar material.
+    pub fn has_triplanar(&self) -> bool {
+        self.opaque_meshes()
+            .any(|(_, material)| material.triplanar.is_some())
+    }
+
+    /// The pipeline sets [`DrawModel::draw_model_instanced_triplanar`]
+    /// records: one per run of meshes with the same kind of material.
+    pub fn triplanar_pipeline_sets(&self) -> u32 {
+        let mut triplanar = None;
+        let mut sets = 0;
+        for (_, material) in self.opaque_meshes() {
+            if triplanar != Some(material.triplanar.is_some()) {
+                triplanar = Some(material.triplanar.is_some());
+                sets += 1;
+            }
+        }
+        sets
+    }
+
+    fn opaque_meshes(&self) -> impl Iterator<Item = (&Mesh, &Material)> {
+        self.meshes
+            .iter()
+            .map(|mesh| (mesh, &self.materials[mesh.material]))
+            .filter(|(_, material)| material.refractive.is_none())
+    }
 }
 
 pub struct GLTFModel {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
     pub nodes: Vec<Node>,
+    /// Features of the file that were left out when importing it.
+    pub warnings: Vec<String>,
+}
+
+impl GLTFModel {
+    /// Like [`Model::report`], with the node count and import warnings.
+    pub fn report(&self) -> ModelReport {
+        ModelReport {
+            nodes: Some(self.nodes.len()),
+            warnings: self.warnings.clone(),
+            ..ModelReport::from_meshes(&self.meshes, &self.materials, VertexPrecision::Full)
+        }
+    }
 }
 
 pub struct Node {
@@ -149,12 +508,63 @@ pub trait DrawModel<'a> {
     );
 
     fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
+    /// Draws every mesh of `model` but those with refractive materials.
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
     );
+
+    /// Like [`Self::draw_model_instanced`], with `pipelines.0` for meshes
+    /// with UV mapped materials and `pipelines.1`, the `HAS_TRIPLANAR`
+    /// variant, for triplanar ones. The pipeline is only set where it
+    /// changes from one mesh to the next.
+    fn draw_model_instanced_triplanar(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        pipelines: (&'a wgpu::RenderPipeline, &'a wgpu::RenderPipeline),
+    );
+
+    /// Draws `model` into `region` only, then restores the viewport and
+    /// scissor to cover the whole target. Regions with no area are skipped,
+    /// and so are refractive meshes.
+    fn draw_model_in_region(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        region: &DrawRegion<'a>,
+        target_size: (u32, u32),
+    );
+}
+
+pub(crate) fn draw_geometry<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    mesh: &'a Mesh,
+    instances: Range<u32>,
+) {
+    match &mesh.geometry {
+        MeshGeometry::Buffers {
+            vertex_buffer,
+            index_buffer,
+        } => {
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, instances);
+        }
+        MeshGeometry::Pooled(allocation) => {
+            render_pass.set_vertex_buffer(0, allocation.vertex_buffer.slice(..));
+            render_pass
+                .set_index_buffer(allocation.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(
+                allocation.index_range.clone(),
+                allocation.base_vertex,
+                instances,
+            );
+        }
+    }
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -177,11 +587,12 @@ where
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
+        if let Some(bind_group) = &mesh.bind_group {
+            self.set_bind_group(2, bind_group, &[]);
+        }
+        draw_geometry(self, mesh, instances);
     }
 
     fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
@@ -197,7 +608,66 @@ where
         for mesh in &model.meshes {
             log::warn!("materials: {}", model.materials.len());
             let material = &model.materials[mesh.material];
+            if material.refractive.is_some() {
+                continue;
+            }
             self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
         }
     }
+
+    fn draw_model_instanced_triplanar(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        pipelines: (&'b wgpu::RenderPipeline, &'b wgpu::RenderPipeline),
+    ) {
+        let mut triplanar = None;
+        for (mesh, material) in model.opaque_meshes() {
+            let binding = material.triplanar.as_ref();
+            if triplanar != Some(binding.is_some()) {
+                triplanar = Some(binding.is_some());
+                self.set_pipeline(match binding {
+                    Some(_) => pipelines.1,
+                    None => pipelines.0,
+                });
+            }
+            let bind_group = binding.map_or(&material.bind_group, |b| &b.bind_group);
+            self.set_bind_group(0, bind_group, &[]);
+            self.set_bind_group(1, camera_bind_group, &[]);
+            if let Some(bind_group) = &mesh.bind_group {
+                self.set_bind_group(2, bind_group, &[]);
+            }
+            draw_geometry(self, mesh, instances.clone());
+        }
+    }
+
+    fn draw_model_in_region(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        region: &DrawRegion<'b>,
+        target_size: (u32, u32),
+    ) {
+        if !region.apply(self, target_size) {
+            return;
+        }
+        let offsets: &[u32] = match &region.camera_offset {
+            Some(offset) => std::slice::from_ref(offset),
+            None => &[],
+        };
+        self.set_bind_group(1, region.camera_bind_group, offsets);
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            if material.refractive.is_some() {
+                continue;
+            }
+            self.set_bind_group(0, &material.bind_group, &[]);
+            if let Some(bind_group) = &mesh.bind_group {
+                self.set_bind_group(2, bind_group, &[]);
+            }
+            draw_geometry(self, mesh, instances.clone());
+        }
+        region::reset_region(self, target_size);
+    }
 }
@@ -0,0 +1,298 @@
+//! A breakdown of a model's meshes and materials, for finding what makes a
+//! scene heavy. [`Model::report`](super::Model::report) reports a loaded
+//! model from its GPU resources, and [`ModelReport::from_data`] reports
+//! parsed data the way it would load.
+
+use std::fmt;
+
+use cgmath::Point3;
+use serde::Serialize;
+
+use super::{
+    CompressedVertex, Material, Mesh, MeshGeometry, ModelVertex, TextureRole, VertexPrecision,
+};
+use crate::{bounds::Aabb, gpu, mipmap, model_data::ModelData};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MeshReport {
+    pub name: String,
+    pub vertices: u32,
+    pub indices: u32,
+    /// The mesh's share of its vertex buffer, which may be pooled.
+    pub vertex_bytes: u64,
+    pub index_bytes: u64,
+    /// Of the bounds as loaded.
+    pub aabb_volume: f32,
+    /// An index into [`ModelReport::materials`].
+    pub material: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextureReport {
+    pub role: TextureRole,
+    pub width: u32,
+    pub height: u32,
+    /// GPU memory over every mip.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaterialReport {
+    pub name: String,
+    pub textures: Vec<TextureReport>,
+    /// The sum of the textures' bytes. A texture shared by several
+    /// materials counts for each of them.
+    pub bytes: u64,
+}
+
+impl MaterialReport {
+    pub fn new(name: String, textures: Vec<TextureReport>) -> Self {
+        let bytes = textures.iter().map(|t| t.bytes).sum();
+        Self {
+            name,
+            textures,
+            bytes,
+        }
+    }
+}
+
+/// The sums over a report's meshes and materials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ReportTotals {
+    pub vertices: u64,
+    pub indices: u64,
+    /// Vertex and index bytes.
+    pub buffer_bytes: u64,
+    pub texture_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelReport {
+    pub meshes: Vec<MeshReport>,
+    pub materials: Vec<MaterialReport>,
+    /// For formats with a node hierarchy.
+    pub nodes: Option<usize>,
+    /// Features of the source that the loader left out.
+    pub warnings: Vec<String>,
+    pub totals: ReportTotals,
+}
+
+impl ModelReport {
+    /// Sums the totals from `meshes` and `materials`.
+    pub fn new(meshes: Vec<MeshReport>, materials: Vec<MaterialReport>) -> Self {
+        let totals = ReportTotals {
+            vertices: meshes.iter().map(|m| m.vertices as u64).sum(),
+            indices: meshes.iter().map(|m| m.indices as u64).sum(),
+            buffer_bytes: meshes.iter().map(|m| m.vertex_bytes + m.index_bytes).sum(),
+            texture_bytes: materials.iter().map(|m| m.bytes).sum(),
+        };
+        Self {
+            meshes,
+            materials,
+            nodes: None,
+            warnings: Vec::new(),
+            totals,
+        }
+    }
+
+    pub(crate) fn from_meshes(
+        meshes: &[Mesh],
+        materials: &[Material],
+        precision: VertexPrecision,
+    ) -> Self {
+        let stride = vertex_size(precision);
+        let meshes = meshes
+            .iter()
+            .map(|mesh| {
+                let (vertices, vertex_bytes, index_bytes) = match &mesh.geometry {
+                    MeshGeometry::Buffers {
+                        vertex_buffer,
+                        index_buffer,
+                    } => (
+                        (vertex_buffer.size() / stride) as u32,
+                        vertex_buffer.size(),
+                        index_buffer.size(),
+                    ),
+                    MeshGeometry::Pooled(allocation) => {
+                        let vertices = allocation.vertex_range.len() as u32;
+                        let indices = allocation.index_range.len() as u64;
+                        (vertices, vertices as u64 * stride, indices * INDEX_SIZE)
+                    }
+                };
+                MeshReport {
+                    name: mesh.name.clone(),
+                    vertices,
+                    indices: mesh.num_elements,
+                    vertex_bytes,
+                    index_bytes,
+                    aabb_volume: mesh.aabb.volume(),
+                    material: mesh.material,
+                }
+            })
+            .collect();
+        let materials = materials
+            .iter()
+            .map(|material| {
+                let textures = material
+                    .textures()
+                    .map(|(role, texture)| {
+                        let size = texture.texture.size();
+                        TextureReport {
+                            role,
+                            width: size.width,
+                            height: size.height,
+                            bytes: texture.bytes(),
+                        }
+                    })
+                    .collect();
+                MaterialReport::new(material.name.clone(), textures)
+            })
+            .collect();
+        Self::new(meshes, materials)
+    }
+
+    /// Reports `data` as it would load with the default options, before
+    /// any downscaling. `texture_size` gives the size of a texture by its
+    /// path in `data`, or `None` if it can't be read, which leaves it out.
+    pub fn from_data(data: &ModelData, texture_size: impl Fn(&str) -> Option<(u32, u32)>) -> Self {
+        let stride = vertex_size(VertexPrecision::Full);
+        let meshes = data
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let positions = mesh.data.vertices.iter().map(|v| Point3::from(v.position));
+                MeshReport {
+                    name: mesh.name.clone(),
+                    vertices: mesh.data.vertices.len() as u32,
+                    indices: mesh.data.indices.len() as u32,
+                    vertex_bytes: mesh.data.vertices.len() as u64 * stride,
+                    index_bytes: mesh.data.indices.len() as u64 * INDEX_SIZE,
+                    aabb_volume: Aabb::from_points(positions).map_or(0.0, |aabb| aabb.volume()),
+                    material: mesh.material as usize,
+                }
+            })
+            .collect();
+        let materials = data
+            .materials
+            .iter()
+            .map(|material| {
+                let textures = (!material.diffuse_texture.is_empty())
+                    .then(|| texture_size(&material.diffuse_texture))
+                    .flatten()
+                    .map(|(width, height)| TextureReport {
+                        role: TextureRole::Diffuse,
+                        width,
+                        height,
+                        bytes: mipmapped_bytes(width, height),
+                    });
+                MaterialReport::new(material.name.clone(), textures.into_iter().collect())
+            })
+            .collect();
+        Self::new(meshes, materials)
+    }
+
+    /// The report as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report is always valid JSON")
+    }
+}
+
+const INDEX_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+
+fn vertex_size(precision: VertexPrecision) -> u64 {
+    (match precision {
+        VertexPrecision::Full => std::mem::size_of::<ModelVertex>(),
+        VertexPrecision::Compressed => std::mem::size_of::<CompressedVertex>(),
+    }) as u64
+}
+
+/// The bytes of a material texture of this size, which loads as sRGB RGBA
+/// with a full mip chain.
+fn mipmapped_bytes(width: u32, height: u32) -> u64 {
+    gpu::memory::texture_bytes(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: mipmap::mip_level_count(width, height),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn kib(bytes: u64) -> String {
+    format!("{:.1}", bytes as f64 / 1024.0)
+}
+
+impl fmt::Display for ModelReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .meshes
+            .iter()
+            .map(|m| m.name.len())
+            .chain(self.materials.iter().map(|m| m.name.len()))
+            .fold("Material".len(), usize::max);
+        writeln!(
+            f,
+            "{:<width$}  {:>8}  {:>8}  {:>10}  {:>10}  {:>11}  Material",
+            "Mesh", "Vertices", "Indices", "Vertex KiB", "Index KiB", "AABB volume",
+        )?;
+        for mesh in &self.meshes {
+            writeln!(
+                f,
+                "{:<width$}  {:>8}  {:>8}  {:>10}  {:>10}  {:>11.3}  {}",
+                mesh.name,
+                mesh.vertices,
+                mesh.indices,
+                kib(mesh.vertex_bytes),
+                kib(mesh.index_bytes),
+                mesh.aabb_volume,
+                mesh.material,
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<width$}  {:>10}  Textures", "Material", "GPU KiB")?;
+        for material in &self.materials {
+            let textures = material
+                .textures
+                .iter()
+                .map(|t| format!("{:?} {}x{}", t.role, t.width, t.height))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "{:<width$}  {:>10}  {}",
+                material.name,
+                kib(material.bytes),
+                if textures.is_empty() {
+                    "none".to_string()
+                } else {
+                    textures.join(", ")
+                },
+            )?;
+        }
+        writeln!(f)?;
+        write!(
+            f,
+            "Total: {} meshes, {} materials, {} vertices, {} indices, {} KiB in buffers, {} KiB in textures",
+            self.meshes.len(),
+            self.materials.len(),
+            self.totals.vertices,
+            self.totals.indices,
+            kib(self.totals.buffer_bytes),
+            kib(self.totals.texture_bytes),
+        )?;
+        if let Some(nodes) = self.nodes {
+            write!(f, ", {} nodes", nodes)?;
+        }
+        writeln!(f)?;
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! A binary form of a parsed model, which loads without parsing text. The
+//! `asset-pipeline` bin writes one per OBJ or glTF, next to the model's
+//! textures, and [`resources`](crate::resources) reads them instead of the
+//! source when [`LoadOptions::prefer_processed`](crate::resources::LoadOptions)
+//! is set.
+//!
+//! Vertices are stored in the file's own axes and units, so load options
+//! such as [`LoadOptions::source_axes`](crate::resources::LoadOptions) and
+//! `assumed_unit` apply the same as to the OBJ. Every number is little
+//! endian.
+
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    model,
+    refraction::Refractive,
+    triplanar::{Triplanar, TriplanarSpace},
+};
+
+/// The extension of model data files.
+pub const EXTENSION: &str = "modeldata";
+
+const MAGIC: &[u8; 4] = b"MDAT";
+/// Bumped whenever the layout changes. Files of other versions don't load,
+/// and the pipeline has to be run again.
+const VERSION: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialRecord {
+    pub name: String,
+    /// Relative to the model data file, or empty for none.
+    pub diffuse_texture: String,
+    pub alpha_mask: bool,
+    pub refractive: Option<Refractive>,
+    pub triplanar: Option<Triplanar>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshRecord {
+    pub name: String,
+    /// An index into [`ModelData::materials`].
+    pub material: u32,
+    pub data: model::MeshData,
+    /// Whether the source had texture coordinates, rather than all (0, 0).
+    pub has_uvs: bool,
+}
+
+/// A model's meshes and materials on the CPU, as parsed from its source.
+#[derive(Debug, Clone, Default)]
+pub struct ModelData {
+    pub meshes: Vec<MeshRecord>,
+    pub materials: Vec<MaterialRecord>,
+}
+
+impl ModelData {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, VERSION);
+
+        put_u32(&mut out, self.materials.len() as u32);
+        for material in &self.materials {
+            put_str(&mut out, &material.name);
+            put_str(&mut out, &material.diffuse_texture);
+            out.push(material.alpha_mask as u8);
+            out.push(material.refractive.is_some() as u8);
+            if let Some(refractive) = material.refractive {
+                out.extend_from_slice(&refractive.ior.to_le_bytes());
+                out.extend_from_slice(&refractive.thickness.to_le_bytes());
+            }
+            out.push(material.triplanar.is_some() as u8);
+            if let Some(triplanar) = material.triplanar {
+                out.extend_from_slice(&triplanar.scale.to_le_bytes());
+                out.extend_from_slice(&triplanar.blend_sharpness.to_le_bytes());
+                out.push(match triplanar.space {
+                    TriplanarSpace::World => 0,
+                    TriplanarSpace::Object => 1,
+                });
+            }
+        }
+
+        put_u32(&mut out, self.meshes.len() as u32);
+        for mesh in &self.meshes {
+            put_str(&mut out, &mesh.name);
+            put_u32(&mut out, mesh.material);
+            out.push(mesh.has_uvs as u8);
+            put_u32(&mut out, mesh.data.vertices.len() as u32);
+            for v in &mesh.data.vertices {
+                let occlusion = [v.occlusion];
+                let floats = v.position.iter().chain(&v.tex_coords).chain(&v.normal);
+                for x in floats.chain(&occlusion) {
+                    out.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            put_u32(&mut out, mesh.data.indices.len() as u32);
+            for i in &mesh.data.indices {
+                put_u32(&mut out, *i);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Reader { bytes, at: 0 };
+        ensure!(r.take(4)? == MAGIC, "not a model data file");
+        let version = r.u32()?;
+        if version != VERSION {
+            bail!(
+                "model data version {} isn't supported, only {}",
+                version,
+                VERSION
+            );
+        }
+
+        let material_count = r.u32()?;
+        let mut materials = Vec::new();
+        for _ in 0..material_count {
+            materials.push(MaterialRecord {
+                name: r.string()?,
+                diffuse_texture: r.string()?,
+                alpha_mask: r.u8()? != 0,
+                refractive: match r.u8()? {
+                    0 => None,
+                    _ => Some(Refractive {
+                        ior: f32::from_le_bytes(r.array()?),
+                        thickness: f32::from_le_bytes(r.array()?),
+                    }),
+                },
+                triplanar: match r.u8()? {
+                    0 => None,
+                    _ => Some(Triplanar {
+                        scale: f32::from_le_bytes(r.array()?),
+                        blend_sharpness: f32::from_le_bytes(r.array()?),
+                        space: match r.u8()? {
+                            0 => TriplanarSpace::World,
+                            1 => TriplanarSpace::Object,
+                            space => bail!("unknown triplanar space {}", space),
+                        },
+                    }),
+                },
+            });
+        }
+
+        let mesh_count = r.u32()?;
+        let mut meshes = Vec::new();
+        for _ in 0..mesh_count {
+            let name = r.string()?;
+            let material = r.u32()?;
+            let has_uvs = r.u8()? != 0;
+            let vertex_count = r.u32()? as usize;
+            // Checked up front so a corrupt count fails rather than
+            // reserving gigabytes.
+            ensure!(
+                r.remaining() >= vertex_count * 36,
+                "{:?} has more vertices than the file holds",
+                name
+            );
+            let mut vertices = Vec::with_capacity(vertex_count);
+            for _ in 0..vertex_count {
+                let mut v = [0.0; 9];
+                for x in &mut v {
+                    *x = f32::from_le_bytes(r.array()?);
+                }
+                vertices.push(model::ModelVertex {
+                    position: [v[0], v[1], v[2]],
+                    tex_coords: [v[3], v[4]],
+                    normal: [v[5], v[6], v[7]],
+                    occlusion: v[8],
+                });
+            }
+            let index_count = r.u32()? as usize;
+            ensure!(
+                r.remaining() >= index_count * 4,
+                "{:?} has more indices than the file holds",
+                name
+            );
+            let indices = (0..index_count)
+                .map(|_| r.u32())
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            ensure!(
+                indices.iter().all(|&i| (i as usize) < vertex_count),
+                "{:?} has an index past its vertices",
+                name
+            );
+            meshes.push(MeshRecord {
+                name,
+                material,
+                data: model::MeshData { vertices, indices },
+                has_uvs,
+            });
+        }
+        ensure!(r.remaining() == 0, "trailing bytes after the model data");
+
+        Ok(Self { meshes, materials })
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.meshes.iter().map(|m| m.data.vertices.len()).sum()
+    }
+
+    pub fn index_count(&self) -> usize {
+        self.meshes.iter().map(|m| m.data.indices.len()).sum()
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.at..self.at.saturating_add(len))
+            .context("model data ends early")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_string())
+    }
+}
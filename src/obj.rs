@@ -0,0 +1,700 @@
+//! A fast path for the common subset of OBJ: `v`, `vt`, `vn` and `f`, with
+//! the grouping and material statements around them. Files using anything
+//! else, such as free-form curves or vertex colors, are left to tobj.
+//!
+//! Floats must use a dot as the decimal separator whatever the locale, and
+//! files written with localized commas are rejected rather than misread.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context};
+
+/// Meshes parsed by [`parse`], in tobj's types so loading continues the same
+/// way whichever parser ran.
+pub struct ObjData {
+    /// Triangulated, with separate position, UV and normal indices local to
+    /// each mesh. `material_id` is unset, see [`ObjData::material_names`].
+    pub models: Vec<tobj::Model>,
+    /// The `usemtl` name of each model, for resolving against the materials.
+    pub material_names: Vec<Option<String>>,
+    /// Files named by `mtllib`, in order.
+    pub material_libs: Vec<String>,
+}
+
+/// A face corner as absolute, zero based indices into the file's arrays.
+type Corner = (u32, Option<u32>, Option<u32>);
+
+#[derive(Default)]
+struct Group {
+    name: String,
+    material: Option<String>,
+    corners: Vec<Corner>,
+    /// How many of the corners have a UV, and how many a normal.
+    texcoord_corners: usize,
+    normal_corners: usize,
+}
+
+/// Parses `bytes` if it only uses the supported subset, or returns `None`
+/// so the caller can fall back to tobj.
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Option<ObjData>> {
+    let mut parser = Parser::new();
+    loop {
+        match parser.step(bytes, usize::MAX)? {
+            Step::Pending => {}
+            Step::Unsupported => return Ok(None),
+            Step::Done(data) => return Ok(Some(data)),
+        }
+    }
+}
+
+/// What a [`Parser::step`] got to.
+pub enum Step {
+    /// There is more to do.
+    Pending,
+    /// The file uses something outside the fast path; fall back to tobj.
+    Unsupported,
+    Done(ObjData),
+}
+
+/// [`parse`] in slices, for parsing large files a little at a time on a
+/// thread that can't block for long. Lines are parsed first, then the
+/// groups are turned into meshes, both resumable anywhere.
+///
+/// A [`Parser::lenient`] one never falls back to tobj, as tobj can't be
+/// sliced. It makes do with what the fast path handles instead:
+/// - statements other than the ones above are skipped, as tobj skips most
+///   of them, and lines and points can't be drawn anyway;
+/// - a vertex's w and colors are dropped, as the loader doesn't use them;
+/// - lines continued with a backslash are joined;
+/// - a group where only some corners have UVs or normals loses them, and
+///   gets generated normals.
+pub struct Parser {
+    /// Byte offset of the next line to parse.
+    offset: usize,
+    /// Number of the next line, from 1.
+    line: usize,
+    positions: Vec<f32>,
+    texcoords: Vec<f32>,
+    normals: Vec<f32>,
+    material_libs: Vec<String>,
+    groups: Vec<Group>,
+    group: Group,
+    /// Set once every line is parsed, for the group being built.
+    building: Option<MeshBuilder>,
+    models: Vec<tobj::Model>,
+    material_names: Vec<Option<String>>,
+    lenient: bool,
+    /// A line continued with a backslash, joined up so far.
+    continued: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            positions: Vec::new(),
+            texcoords: Vec::new(),
+            normals: Vec::new(),
+            material_libs: Vec::new(),
+            groups: Vec::new(),
+            group: Group {
+                name: "unnamed_object".to_string(),
+                ..Default::default()
+            },
+            building: None,
+            models: Vec::new(),
+            material_names: Vec::new(),
+            lenient: false,
+            continued: Vec::new(),
+        }
+    }
+
+    /// A parser that never returns [`Step::Unsupported`]. See [`Parser`].
+    pub fn lenient() -> Self {
+        Self {
+            lenient: true,
+            ..Self::new()
+        }
+    }
+
+    /// Roughly how far along parsing `bytes` is, from 0 to 1. Building the
+    /// meshes counts as the last 20%.
+    pub fn progress(&self, bytes: &[u8]) -> f32 {
+        const PARSE_SHARE: f32 = 0.8;
+        if self.offset < bytes.len() {
+            return PARSE_SHARE * self.offset as f32 / bytes.len() as f32;
+        }
+        let total = self.models.len() + self.groups.len() + self.building.is_some() as usize;
+        let built = match &self.building {
+            Some(builder) => self.models.len() as f32 + builder.progress(),
+            None => self.models.len() as f32,
+        };
+        PARSE_SHARE + (1.0 - PARSE_SHARE) * built / total.max(1) as f32
+    }
+
+    /// Does about `work` lines or face corners of parsing `bytes`, which
+    /// must be the same for every call.
+    pub fn step(&mut self, bytes: &[u8], work: usize) -> anyhow::Result<Step> {
+        if self.offset < bytes.len() {
+            return self.parse_lines(bytes, work);
+        }
+        if !self.continued.is_empty() {
+            // The file ended on a continued line.
+            let line = std::mem::take(&mut self.continued);
+            self.parse_line(&line, self.line - 1)?;
+        }
+        if self.building.is_none() {
+            finish_group(&mut self.groups, &mut self.group);
+            // Built in order from the front.
+            self.groups.reverse();
+            let Some(group) = self.groups.pop() else {
+                return Ok(Step::Done(self.take_data()));
+            };
+            let Some(builder) = MeshBuilder::new(group, self.counts(), self.lenient) else {
+                return Ok(Step::Unsupported);
+            };
+            self.building = Some(builder);
+        }
+
+        let builder = self.building.as_mut().unwrap();
+        if !builder.step(&self.positions, &self.texcoords, &self.normals, work) {
+            return Ok(Step::Pending);
+        }
+        let builder = self.building.take().unwrap();
+        self.models
+            .push(tobj::Model::new(builder.mesh, builder.group.name));
+        self.material_names.push(builder.group.material);
+        match self.groups.pop() {
+            Some(group) => match MeshBuilder::new(group, self.counts(), self.lenient) {
+                Some(builder) => self.building = Some(builder),
+                None => return Ok(Step::Unsupported),
+            },
+            None => return Ok(Step::Done(self.take_data())),
+        }
+        Ok(Step::Pending)
+    }
+
+    /// How many positions, UVs and normals have been parsed.
+    fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.positions.len() / 3,
+            self.texcoords.len() / 2,
+            self.normals.len() / 3,
+        )
+    }
+
+    fn take_data(&mut self) -> ObjData {
+        ObjData {
+            models: std::mem::take(&mut self.models),
+            material_names: std::mem::take(&mut self.material_names),
+            material_libs: std::mem::take(&mut self.material_libs),
+        }
+    }
+
+    fn parse_lines(&mut self, bytes: &[u8], lines: usize) -> anyhow::Result<Step> {
+        for _ in 0..lines {
+            if self.offset >= bytes.len() {
+                break;
+            }
+            let rest = &bytes[self.offset..];
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            let number = self.line;
+            self.offset += end + 1;
+            self.line += 1;
+            let line = &rest[..end];
+            if self.lenient {
+                if let Some(start) = line.trim_ascii_end().strip_suffix(b"\\") {
+                    self.continued.extend_from_slice(start);
+                    self.continued.push(b' ');
+                    continue;
+                }
+                if !self.continued.is_empty() {
+                    let mut joined = std::mem::take(&mut self.continued);
+                    joined.extend_from_slice(line);
+                    self.parse_line(&joined, number)?;
+                    continue;
+                }
+            }
+            if !self.parse_line(line, number)? {
+                return Ok(Step::Unsupported);
+            }
+        }
+        Ok(Step::Pending)
+    }
+
+    /// Returns `false` for lines outside the fast path.
+    fn parse_line(&mut self, line: &[u8], number: usize) -> anyhow::Result<bool> {
+        let line = std::str::from_utf8(line)
+            .with_context(|| format!("line {} of the OBJ isn't valid UTF-8", number))?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.ends_with('\\') && !self.lenient {
+            // Continued lines are rare enough to leave to tobj.
+            return Ok(false);
+        }
+        let mut tokens = line.split_ascii_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(true);
+        };
+        let group = &mut self.group;
+        match keyword {
+            "v" => {
+                let positions = &mut self.positions;
+                let start = positions.len();
+                for token in tokens {
+                    positions.push(parse_float(token, number)?);
+                }
+                match positions.len() - start {
+                    3 => {}
+                    // w, or vertex colors, which the loader doesn't use.
+                    4 | 6 | 7 if self.lenient => positions.truncate(start + 3),
+                    4 | 6 | 7 => return Ok(false),
+                    n => bail!("line {}: a vertex needs 3 coordinates, got {}", number, n),
+                }
+            }
+            "vt" => {
+                let mut uv = tokens.map(|t| parse_float(t, number));
+                let (Some(u), v) = (uv.next(), uv.next()) else {
+                    bail!("line {}: a texture coordinate needs at least u", number);
+                };
+                self.texcoords.push(u?);
+                self.texcoords.push(v.transpose()?.unwrap_or(0.0));
+                // An optional w is ignored, as tobj does.
+                uv.next().transpose()?;
+            }
+            "vn" => {
+                let normals = &mut self.normals;
+                let start = normals.len();
+                for token in tokens {
+                    normals.push(parse_float(token, number)?);
+                }
+                if normals.len() - start != 3 {
+                    bail!("line {}: a normal needs 3 coordinates", number);
+                }
+            }
+            "f" => {
+                let counts = (
+                    self.positions.len() / 3,
+                    self.texcoords.len() / 2,
+                    self.normals.len() / 3,
+                );
+                let face = tokens
+                    .map(|t| parse_corner(t, counts, number))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if face.len() < 3 {
+                    bail!("line {}: a face needs at least 3 vertices", number);
+                }
+                // Fan triangulation, as tobj does.
+                let start = group.corners.len();
+                for i in 1..face.len() - 1 {
+                    group.corners.extend([face[0], face[i], face[i + 1]]);
+                }
+                for corner in &group.corners[start..] {
+                    group.texcoord_corners += corner.1.is_some() as usize;
+                    group.normal_corners += corner.2.is_some() as usize;
+                }
+            }
+            "o" | "g" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let material = group.material.clone();
+                finish_group(&mut self.groups, group);
+                group.name = name;
+                group.material = material;
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if group.material.as_deref() != Some(&name) {
+                    let group_name = group.name.clone();
+                    finish_group(&mut self.groups, group);
+                    group.name = group_name;
+                }
+                group.material = Some(name);
+            }
+            "mtllib" => self.material_libs.extend(tokens.map(str::to_string)),
+            // Smoothing groups don't change the data.
+            "s" => {}
+            _ => return Ok(self.lenient),
+        }
+        Ok(true)
+    }
+}
+
+fn finish_group(groups: &mut Vec<Group>, group: &mut Group) {
+    let group = std::mem::take(group);
+    if !group.corners.is_empty() {
+        groups.push(group);
+    }
+}
+
+fn parse_float(token: &str, line: usize) -> anyhow::Result<f32> {
+    match fast_float::parse(token) {
+        Ok(value) => Ok(value),
+        Err(_) if token.contains(',') => bail!(
+            "line {}: {:?} uses a comma as the decimal separator, OBJ needs a dot",
+            line,
+            token
+        ),
+        Err(_) => bail!("line {}: {:?} isn't a number", line, token),
+    }
+}
+
+/// Parses `p`, `p/t`, `p//n` or `p/t/n`, resolving negative indices against
+/// the `counts` of positions, UVs and normals so far.
+fn parse_corner(token: &str, counts: (usize, usize, usize), line: usize) -> anyhow::Result<Corner> {
+    let resolve = |index: &str, count: usize, what: &str| -> anyhow::Result<u32> {
+        let index: i64 = index
+            .parse()
+            .with_context(|| format!("line {}: {:?} isn't a {} index", line, index, what))?;
+        let resolved = match index {
+            i if i > 0 => i - 1,
+            i if i < 0 => count as i64 + i,
+            _ => bail!("line {}: OBJ indices start at 1, got 0", line),
+        };
+        if !(0..count as i64).contains(&resolved) {
+            bail!(
+                "line {}: {} index {} is out of range, there are {}",
+                line,
+                what,
+                index,
+                count
+            );
+        }
+        Ok(resolved as u32)
+    };
+
+    let mut parts = token.split('/');
+    let p = resolve(parts.next().unwrap_or_default(), counts.0, "position")?;
+    let t = match parts.next() {
+        Some("") | None => None,
+        Some(t) => Some(resolve(t, counts.1, "texture coordinate")?),
+    };
+    let n = match parts.next() {
+        Some("") | None => None,
+        Some(n) => Some(resolve(n, counts.2, "normal")?),
+    };
+    if parts.next().is_some() {
+        bail!("line {}: {:?} has too many indices", line, token);
+    }
+    Ok((p, t, n))
+}
+
+/// Copies what a group's corners use into a mesh of its own, renumbering
+/// the file's indices in order of first use.
+struct MeshBuilder {
+    group: Group,
+    /// Whether the corners' UVs and normals are copied.
+    texcoords: bool,
+    normals: bool,
+    mesh: tobj::Mesh,
+    p_remap: HashMap<u32, u32>,
+    t_remap: HashMap<u32, u32>,
+    n_remap: HashMap<u32, u32>,
+    next: usize,
+}
+
+impl MeshBuilder {
+    /// `None` when only some corners have UVs or normals, which tobj's mesh
+    /// can't represent, unless `lenient`, when they are left out instead.
+    /// `counts` are the file's positions, UVs and normals.
+    ///
+    /// Everything is allocated for the most the group can use up front, so
+    /// stepping never stalls on a large map growing.
+    fn new(group: Group, counts: (usize, usize, usize), lenient: bool) -> Option<Self> {
+        let count = group.corners.len();
+        let texcoords = group.texcoord_corners == count;
+        let normals = group.normal_corners == count;
+        let mixed = |with: usize| with != 0 && with != count;
+        if (mixed(group.texcoord_corners) || mixed(group.normal_corners)) && !lenient {
+            return None;
+        }
+        let used = (
+            count.min(counts.0),
+            if texcoords { count.min(counts.1) } else { 0 },
+            if normals { count.min(counts.2) } else { 0 },
+        );
+        let mesh = tobj::Mesh {
+            positions: Vec::with_capacity(used.0 * 3),
+            texcoords: Vec::with_capacity(used.1 * 2),
+            normals: Vec::with_capacity(used.2 * 3),
+            indices: Vec::with_capacity(count),
+            texcoord_indices: Vec::with_capacity(if texcoords { count } else { 0 }),
+            normal_indices: Vec::with_capacity(if normals { count } else { 0 }),
+            ..Default::default()
+        };
+        Some(Self {
+            texcoords,
+            normals,
+            group,
+            mesh,
+            p_remap: HashMap::with_capacity(used.0),
+            t_remap: HashMap::with_capacity(used.1),
+            n_remap: HashMap::with_capacity(used.2),
+            next: 0,
+        })
+    }
+
+    fn progress(&self) -> f32 {
+        self.next as f32 / self.group.corners.len() as f32
+    }
+
+    /// Copies up to `count` more corners. Returns `true` once all are done.
+    fn step(
+        &mut self,
+        positions: &[f32],
+        texcoords: &[f32],
+        normals: &[f32],
+        count: usize,
+    ) -> bool {
+        fn local(
+            remap: &mut HashMap<u32, u32>,
+            data: &mut Vec<f32>,
+            source: &[f32],
+            size: usize,
+            index: u32,
+        ) -> u32 {
+            *remap.entry(index).or_insert_with(|| {
+                let start = index as usize * size;
+                data.extend_from_slice(&source[start..start + size]);
+                (data.len() / size) as u32 - 1
+            })
+        }
+
+        let end = self
+            .group
+            .corners
+            .len()
+            .min(self.next.saturating_add(count));
+        let mesh = &mut self.mesh;
+        for &(p, t, n) in &self.group.corners[self.next..end] {
+            mesh.indices.push(local(
+                &mut self.p_remap,
+                &mut mesh.positions,
+                positions,
+                3,
+                p,
+            ));
+            if let (true, Some(t)) = (self.texcoords, t) {
+                let t = local(&mut self.t_remap, &mut mesh.texcoords, texcoords, 2, t);
+                mesh.texcoord_indices.push(t);
+            }
+            if let (true, Some(n)) = (self.normals, n) {
+                let n = local(&mut self.n_remap, &mut mesh.normals, normals, 3, n);
+                mesh.normal_indices.push(n);
+            }
+        }
+        self.next = end;
+        self.next == self.group.corners.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(source: &str) -> ObjData {
+        parse(source.as_bytes())
+            .expect("the OBJ should parse")
+            .expect("the OBJ should be on the fast path")
+    }
+
+    /// The positions each triangle corner refers to.
+    fn corner_positions(mesh: &tobj::Mesh) -> Vec<[f32; 3]> {
+        mesh.indices
+            .iter()
+            .map(|&i| {
+                let i = i as usize * 3;
+                [
+                    mesh.positions[i],
+                    mesh.positions[i + 1],
+                    mesh.positions[i + 2],
+                ]
+            })
+            .collect()
+    }
+
+    const SQUARE: &str = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+";
+
+    #[test]
+    fn negative_indices_count_back_from_the_latest() {
+        let data = parsed(&format!("{}f -4/-4/-1 -3/-3/-1 -2/-2/-1\n", SQUARE));
+        let mesh = &data.models[0].mesh;
+        assert_eq!(
+            corner_positions(mesh),
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]
+        );
+        assert_eq!(mesh.texcoords, [0.0, 0.0, 1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(mesh.normals, [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.normal_indices, [0, 0, 0]);
+
+        // Relative to what has been read so far, not the whole file.
+        let data = parsed("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\nf -4 -3 -1\n");
+        assert_eq!(
+            corner_positions(&data.models[0].mesh)[3..],
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [5.0, 5.0, 5.0]]
+        );
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        for face in [
+            "f 1 2 5",
+            "f 0 1 2",
+            "f -5 1 2",
+            "f 1/5 2/1 3/1",
+            "f 1//2 2//1 3//1",
+        ] {
+            let source = format!("{}{}\n", SQUARE, face);
+            assert!(parse(source.as_bytes()).is_err(), "{:?} parsed", face);
+        }
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let data = parsed(&format!("{}v 0.5 1.5 0\nf 1 2 3 5 4\n", SQUARE));
+        let mesh = &data.models[0].mesh;
+        assert_eq!(mesh.indices.len(), 9);
+        let corners = corner_positions(mesh);
+        let first = [0.0, 0.0, 0.0];
+        // Every triangle starts at the first corner and they wind the same way.
+        for triangle in corners.chunks(3) {
+            assert_eq!(triangle[0], first);
+        }
+        assert_eq!(corners[1..3], [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]);
+        assert_eq!(corners[4..6], [[1.0, 1.0, 0.0], [0.5, 1.5, 0.0]]);
+        assert_eq!(corners[7..9], [[0.5, 1.5, 0.0], [0.0, 1.0, 0.0]]);
+
+        let source = format!("{}f 1 2\n", SQUARE);
+        assert!(parse(source.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn missing_normals_leave_normal_indices_empty() {
+        let data = parsed(&format!("{}f 1/1 2/2 3/3\n", SQUARE));
+        let mesh = &data.models[0].mesh;
+        assert!(mesh.normals.is_empty() && mesh.normal_indices.is_empty());
+        assert_eq!(mesh.texcoord_indices, [0, 1, 2]);
+
+        let data = parsed(&format!("{}f 1 2 3\n", SQUARE));
+        let mesh = &data.models[0].mesh;
+        assert!(mesh.texcoords.is_empty() && mesh.normals.is_empty());
+
+        // Only some corners with normals can't be represented, so tobj gets
+        // to decide what to do with it.
+        let source = format!("{}f 1//1 2 3//1\n", SQUARE);
+        assert!(parse(source.as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn groups_and_materials_split_meshes() {
+        let source = format!(
+            "mtllib a.mtl b.mtl\n{}o quad\nusemtl red\nf 1 2 3\nusemtl blue\nf 1 3 4\ng tail\nf 2 3 4\n",
+            SQUARE
+        );
+        let data = parsed(&source);
+        assert_eq!(data.material_libs, ["a.mtl", "b.mtl"]);
+        let names: Vec<_> = data.models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["quad", "quad", "tail"]);
+        assert_eq!(
+            data.material_names,
+            [
+                Some("red".to_owned()),
+                Some("blue".to_owned()),
+                Some("blue".to_owned())
+            ]
+        );
+        // Each mesh only holds the positions it uses.
+        assert!(data.models.iter().all(|m| m.mesh.positions.len() == 9));
+    }
+
+    #[test]
+    fn unsupported_and_malformed_lines() {
+        // Curves are left to tobj.
+        assert!(parse(b"v 0 0 0\ncurv 0 1 1\n").unwrap().is_none());
+        // A localized decimal comma is an error rather than a misread.
+        let error = parse(b"v 0,5 0 0\n").err().unwrap();
+        assert!(error.to_string().contains("comma"), "{}", error);
+        assert!(parse(b"v 0 0\n").is_err());
+    }
+
+    #[test]
+    fn lenient_parsers_make_do_without_tobj() {
+        let source = format!(
+            concat!(
+                "{}v 2 0 0 1\n",
+                "v 2 1 0 0.5 0.5 0.5\n",
+                "l 1 2\n",
+                "curv 0 1 1\n",
+                "f 1//1 2 \\\n",
+                "  5\n",
+                "f 1/1 2/2 6/3\n",
+            ),
+            SQUARE
+        );
+        assert!(parse(source.as_bytes()).unwrap().is_none());
+        let bytes = source.as_bytes();
+        let mut parser = Parser::lenient();
+        let data = loop {
+            match parser.step(bytes, 1).unwrap() {
+                Step::Pending => {}
+                Step::Unsupported => panic!("lenient parsers don't fall back"),
+                Step::Done(data) => break data,
+            }
+        };
+        let mesh = &data.models[0].mesh;
+        // The continued face is joined, and the extra components dropped.
+        assert_eq!(
+            corner_positions(mesh)[..3],
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]]
+        );
+        assert_eq!(corner_positions(mesh)[5], [2.0, 1.0, 0.0]);
+        // Only one corner had a normal and only three a UV, so none do.
+        assert!(mesh.normals.is_empty() && mesh.normal_indices.is_empty());
+        assert!(mesh.texcoords.is_empty() && mesh.texcoord_indices.is_empty());
+        assert_eq!(mesh.indices.len(), 6);
+    }
+
+    #[test]
+    fn stepping_matches_parsing_at_once() {
+        let source = format!(
+            "{}o a\nf 1/1/1 2/2/1 3/3/1 4/4/1\no b\nf -1 -2 -3\n",
+            SQUARE
+        );
+        let whole = parsed(&source);
+        let bytes = source.as_bytes();
+        let mut parser = Parser::new();
+        let mut last_progress = 0.0;
+        let stepped = loop {
+            match parser.step(bytes, 1).unwrap() {
+                Step::Pending => {}
+                Step::Unsupported => panic!("the OBJ should be on the fast path"),
+                Step::Done(data) => break data,
+            }
+            let progress = parser.progress(bytes);
+            assert!(progress >= last_progress && progress <= 1.0);
+            last_progress = progress;
+        };
+        assert_eq!(stepped.models.len(), whole.models.len());
+        for (a, b) in stepped.models.iter().zip(&whole.models) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.mesh.positions, b.mesh.positions);
+            assert_eq!(a.mesh.indices, b.mesh.indices);
+            assert_eq!(a.mesh.texcoord_indices, b.mesh.texcoord_indices);
+        }
+    }
+}
@@ -0,0 +1,443 @@
+//! Blended quads in the scene, such as smoke, sparks or light shafts, drawn
+//! after the opaque geometry.
+//!
+//! Quads fade out over [`Particle::fade_distance`] as they near the
+//! geometry behind them ("soft particles"), so they don't show a hard line
+//! where they cut through it. The scene's depth is bound as a texture and
+//! both it and the quad's own depth are turned back into view distances,
+//! which needs the camera's near and far planes and its [`DepthRange`].
+//! The depth is read with `textureLoad`, so no sampler is needed, and a
+//! multisampled depth target is read at its first sample. It is bound as an
+//! unfilterable float texture rather than a depth one: GLSL has no load
+//! from depth textures, but a depth target reads as float just the same.
+
+use bytemuck::Zeroable;
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+
+use crate::{
+    frame::{FrameArena, Watermark},
+    gpu,
+    math::projection::{self, DepthRange},
+    model::Vertex,
+    reflect,
+    stats::FrameStats,
+    variant,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub fade_distance: f32,
+}
+
+impl Vertex for ParticleVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ParticleVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// A flat colored quad in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Particle {
+    /// The center of the quad.
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+    /// The world space distance in front of the scene over which the quad
+    /// fades in. 0 gives hard intersections.
+    pub fade_distance: f32,
+    /// The directions of the quad's width and height, or `None` to face
+    /// the camera.
+    pub axes: Option<[[f32; 3]; 2]>,
+}
+
+impl Particle {
+    pub const DEFAULT_FADE_DISTANCE: f32 = 0.5;
+
+    /// A quad facing the camera with the default fade distance.
+    pub fn new(position: [f32; 3], size: [f32; 2], color: [f32; 4]) -> Self {
+        Self {
+            position,
+            size,
+            color,
+            fade_distance: Self::DEFAULT_FADE_DISTANCE,
+            axes: None,
+        }
+    }
+
+    /// The quad's corners, in the order top left, top right, bottom right,
+    /// bottom left.
+    fn corners(&self, camera_axes: [Vector3<f32>; 2]) -> [Vector3<f32>; 4] {
+        let [right, up] = self.axes.map_or(camera_axes, |a| a.map(Vector3::from));
+        let right = right * (self.size[0] / 2.0);
+        let up = up * (self.size[1] / 2.0);
+        let center = Vector3::from(self.position);
+        [
+            center - right + up,
+            center + right + up,
+            center + right - up,
+            center - right - up,
+        ]
+    }
+}
+
+/// What [`ParticleBatch::prepare`] needs to know about the camera. The
+/// projection must be a perspective one from [`projection::perspective`]
+/// with the same `near`, `far` and `depth`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParticleCamera {
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub near: f32,
+    pub far: f32,
+    pub depth: DepthRange,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    near: f32,
+    far: f32,
+    reversed: u32,
+    _padding: u32,
+}
+
+/// Queues particles each frame and draws them back to front with alpha
+/// blending, in a pass after the opaque scene that binds its depth.
+pub struct ParticleBatch {
+    pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    _camera_memory: gpu::MemoryToken,
+    camera_bind_group: wgpu::BindGroup,
+    depth_layout: wgpu::BindGroupLayout,
+    depth_bind_group: Option<wgpu::BindGroup>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    _buffer_memory: [gpu::MemoryToken; 2],
+    quad_capacity: usize,
+    particles: Vec<Particle>,
+    /// Quads written by the last [`Self::prepare`].
+    quads: u32,
+    /// Sort keys and vertices, reset every [`Self::prepare`].
+    arena: FrameArena,
+}
+
+impl ParticleBatch {
+    pub(crate) const CAMERA_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+
+    pub(crate) const fn depth_layout_entries(
+        multisampled: bool,
+    ) -> [wgpu::BindGroupLayoutEntry; 1] {
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled,
+            },
+            count: None,
+        }]
+    }
+
+    /// Draws into targets of `color_format` with `sample_count` samples,
+    /// which the depth passed to [`Self::set_depth`] must also have.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Result<Self, gpu::AllocError> {
+        let multisampled = sample_count > 1;
+        let defines: &[&str] = if multisampled { &["MULTISAMPLED"] } else { &[] };
+        let source = format!(
+            "{}\n{}",
+            projection::WGSL,
+            variant::preprocess(include_str!("particle.wgsl"), defines)
+                .expect("particle.wgsl has invalid directives")
+        );
+        let depth_entries = Self::depth_layout_entries(multisampled);
+        reflect::debug_check(
+            "particle.wgsl",
+            &source,
+            &[&Self::CAMERA_LAYOUT_ENTRIES, &depth_entries],
+        );
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("particle.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::CAMERA_LAYOUT_ENTRIES,
+            label: Some("particle_camera_bind_group_layout"),
+        });
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &depth_entries,
+            label: Some("particle_depth_bind_group_layout"),
+        });
+        let (camera_buffer, camera_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Particle Camera Buffer"),
+                    contents: bytemuck::cast_slice(&[CameraUniform::zeroed()]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("particle_camera_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[&camera_layout, &depth_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ParticleVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                // Fixed quads are seen from both sides.
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let quad_capacity = 64;
+        let (vertex_buffer, index_buffer, buffer_memory) =
+            Self::create_buffers(device, quad_capacity)?;
+        Ok(Self {
+            pipeline,
+            camera_buffer,
+            _camera_memory: camera_buffer_memory,
+            camera_bind_group,
+            depth_layout,
+            depth_bind_group: None,
+            vertex_buffer,
+            index_buffer,
+            _buffer_memory: buffer_memory,
+            quad_capacity,
+            particles: Vec::new(),
+            quads: 0,
+            arena: FrameArena::new(),
+        })
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        quads: usize,
+    ) -> Result<(wgpu::Buffer, wgpu::Buffer, [gpu::MemoryToken; 2]), gpu::AllocError> {
+        let (vertex_buffer, vertex_buffer_memory) = gpu::Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Particle Vertex Buffer"),
+                size: (quads * 4 * std::mem::size_of::<ParticleVertex>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            gpu::MemoryCategory::Vertex,
+        )?;
+        let indices = (0..quads as u32)
+            .flat_map(|q| {
+                let base = q * 4;
+                [base, base + 1, base + 2, base, base + 2, base + 3]
+            })
+            .collect::<Vec<_>>();
+        let (index_buffer, index_buffer_memory) = gpu::Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Particle Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+            gpu::MemoryCategory::Index,
+        )?;
+        Ok((
+            vertex_buffer,
+            index_buffer,
+            [vertex_buffer_memory, index_buffer_memory],
+        ))
+    }
+
+    /// Binds the scene's depth target. Call again whenever it's rebuilt,
+    /// e.g. on resize. It can't also be the depth attachment of the pass
+    /// the particles are drawn in.
+    pub fn set_depth(&mut self, device: &wgpu::Device, depth: &wgpu::TextureView) {
+        self.depth_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.depth_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth),
+            }],
+            label: Some("particle_depth_bind_group"),
+        }));
+    }
+
+    /// Queues a particle for the next [`Self::prepare`].
+    pub fn push(&mut self, particle: Particle) {
+        self.particles.push(particle);
+    }
+
+    /// Builds this frame's vertices from the queued particles, farthest
+    /// first, and clears the queue. If the buffers had to grow and couldn't,
+    /// nothing is drawn.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &ParticleCamera,
+    ) -> Result<(), gpu::AllocError> {
+        let uniform = CameraUniform {
+            view_proj: (camera.projection * camera.view).into(),
+            near: camera.near,
+            far: camera.far,
+            reversed: (camera.depth == DepthRange::Reversed) as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        // The camera's own axes and position, for facing and sorting.
+        let to_world = camera.view.invert().unwrap_or_else(Matrix4::identity);
+        let camera_axes = [to_world.x.truncate(), to_world.y.truncate()];
+        let eye = Point3::from_homogeneous(to_world.w);
+
+        self.arena.reset();
+        let arena = &self.arena;
+        let mut keys = arena.vec_from_iter(self.particles.iter().enumerate().map(|(i, p)| {
+            let offset = Point3::from(p.position) - eye;
+            (offset.magnitude2(), i)
+        }));
+        keys.sort_unstable_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        let mut vertices = arena.vec();
+        for &(_, i) in keys.iter() {
+            let particle = &self.particles[i];
+            for corner in particle.corners(camera_axes) {
+                vertices.push(ParticleVertex {
+                    position: corner.into(),
+                    color: particle.color,
+                    fade_distance: particle.fade_distance,
+                });
+            }
+        }
+        self.particles.clear();
+
+        let quads = vertices.len() / 4;
+        if quads > self.quad_capacity {
+            self.quads = 0;
+            let capacity = quads.next_power_of_two();
+            let (vertex_buffer, index_buffer, buffer_memory) =
+                Self::create_buffers(device, capacity)?;
+            self.vertex_buffer = vertex_buffer;
+            self.index_buffer = index_buffer;
+            self._buffer_memory = buffer_memory;
+            self.quad_capacity = capacity;
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.quads = quads as u32;
+        Ok(())
+    }
+
+    /// How much of the arena [`Self::prepare`] uses, for pre-sizing it with
+    /// [`Self::reserve_arena`].
+    pub fn arena_watermark(&self) -> Watermark {
+        self.arena.watermark()
+    }
+
+    pub fn reserve_arena(&mut self, bytes: usize) {
+        self.arena.reset();
+        self.arena.reserve(bytes);
+    }
+
+    /// Whether the last [`Self::prepare`] produced anything to draw.
+    pub fn is_empty(&self) -> bool {
+        self.quads == 0
+    }
+
+    /// Adds the commands [`Self::draw`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats) {
+        if self.is_empty() {
+            return;
+        }
+        stats.pipeline_sets += 1;
+        stats.bind_group_sets += 2;
+        stats.draw_calls += 1;
+        stats.instances += 1;
+        stats.triangles += self.quads as u64 * 2;
+    }
+
+    /// Draws the prepared particles. Call in a pass after the opaque scene
+    /// without a depth attachment, as the depth is bound for reading.
+    /// Nothing is drawn before [`Self::set_depth`].
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let Some(depth_bind_group) = &self.depth_bind_group else {
+            return;
+        };
+        if self.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, depth_bind_group, &[]);
+        render_pass.draw_indexed(0..self.quads * 6, 0, 0..1);
+    }
+}
@@ -0,0 +1,68 @@
+// Quads blended over the scene, fading out as they near the geometry behind
+// them so they don't cut hard lines through it. Prepended with
+// math/projection.wgsl.
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+    near: f32,
+    far: f32,
+    // 1 for reversed-Z.
+    reversed: u32,
+    _padding: u32,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// The scene's depth target, as an unfilterable float texture, since GLSL
+// can't load from depth textures.
+//!ifdef MULTISAMPLED
+@group(1) @binding(0)
+var t_depth: texture_multisampled_2d<f32>;
+//!else
+@group(1) @binding(0)
+var t_depth: texture_2d<f32>;
+//!endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) fade_distance: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) fade_distance: f32,
+}
+
+fn view_distance(depth: f32) -> f32 {
+    if camera.reversed != 0u {
+        return linearize_depth_reversed(depth, camera.near, camera.far);
+    }
+    return linearize_depth(depth, camera.near, camera.far);
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.color = model.color;
+    out.fade_distance = model.fade_distance;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // With MSAA, the first sample stands in for the pixel.
+    let scene_depth = textureLoad(t_depth, vec2<i32>(in.clip_position.xy), 0).x;
+    let gap = view_distance(scene_depth) - view_distance(in.clip_position.z);
+    // The depth is bound for reading, so the pass can't depth test.
+    if gap <= 0.0 {
+        discard;
+    }
+    var fade = 1.0;
+    if in.fade_distance > 0.0 {
+        fade = clamp(gap / in.fade_distance, 0.0, 1.0);
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * fade);
+}
@@ -0,0 +1,611 @@
+//! Batch conversion of a source asset tree into what loads fastest, for the
+//! `asset-pipeline` bin. OBJ and glTF models become
+//! [`ModelData`](model_data::ModelData) files, images become [`ktx2`] files
+//! with their mip chain, shrunk to a maximum size first, and a JSON manifest
+//! lists every output with its hash and size. Files are converted in
+//! parallel.
+//!
+//! Converted models refer to their textures' KTX2 outputs. Images stored
+//! inside glTF files are written next to the model's output, see
+//! [`resources::parse_gltf`].
+//!
+//! Conversion is incremental: an output newer than its source is kept. Only
+//! the model file itself is compared, so run with
+//! [`PipelineOptions::force`] after editing just a material library or a
+//! glTF file's separate buffers.
+//!
+//! Written with the output under `res/`[`PROCESSED_DIR`], the outputs are
+//! what [`LoadOptions::prefer_processed`] loads.
+//!
+//! [`PROCESSED_DIR`]: resources::PROCESSED_DIR
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ao::AoOptions,
+    ktx2,
+    model::ModelReport,
+    model_data,
+    resources::{self, LoadOptions},
+    texture,
+};
+
+/// The manifest's name in the output directory.
+pub const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOptions {
+    /// Convert every file, even those with an up to date output.
+    pub force: bool,
+    /// Shrink images larger than this on either side to fit, keeping their
+    /// aspect ratio. `None` copies them as they are.
+    pub max_dimension: Option<u32>,
+    /// Bake ambient occlusion into the vertices of every model. The ray
+    /// distance is in each model's own units.
+    pub bake_ao: Option<AoOptions>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AssetKind {
+    Model,
+    Image,
+}
+
+impl AssetKind {
+    pub const ALL: [AssetKind; 2] = [AssetKind::Model, AssetKind::Image];
+
+    fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" | "gltf" | "glb" => Some(AssetKind::Model),
+            "png" | "jpg" | "jpeg" => Some(AssetKind::Image),
+            _ => None,
+        }
+    }
+
+    /// Where the output of `source`, relative to the source directory, goes
+    /// relative to the output directory.
+    fn output(self, source: &Path) -> PathBuf {
+        match self {
+            AssetKind::Model => source.with_extension(model_data::EXTENSION),
+            AssetKind::Image => source.with_extension(ktx2::EXTENSION),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    Converted,
+    /// The output was newer than the source, so it was kept.
+    UpToDate,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    /// Relative to the source directory.
+    pub source: PathBuf,
+    /// Relative to the output directory.
+    pub output: PathBuf,
+    pub kind: AssetKind,
+    pub status: FileStatus,
+    pub source_bytes: u64,
+    /// Of the output and the images written beside it. Zero if the
+    /// conversion failed.
+    pub output_bytes: u64,
+    /// The images stored inside a glTF model, written as KTX2 files next to
+    /// its output. Relative to the output directory.
+    pub images: Vec<PathBuf>,
+    /// What a converted model holds.
+    pub model: Option<ModelReport>,
+}
+
+/// What [`run`] did with each file.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// In the order of their source paths.
+    pub files: Vec<FileReport>,
+}
+
+impl Report {
+    pub fn count(&self, status: &FileStatus) -> usize {
+        self.files.iter().filter(|f| f.status == *status).count()
+    }
+
+    pub fn failures(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| matches!(f.status, FileStatus::Failed(_)))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for kind in AssetKind::ALL {
+            let files = self.files.iter().filter(|file| file.kind == kind);
+            let (count, before, after) = files.fold((0, 0, 0), |(n, before, after), file| {
+                (n + 1, before + file.source_bytes, after + file.output_bytes)
+            });
+            if count == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "{:?}s: {} files, {} KiB -> {} KiB ({:+.0}%)",
+                kind,
+                count,
+                before / 1024,
+                after / 1024,
+                100.0 * (after as f64 / before.max(1) as f64 - 1.0)
+            )?;
+        }
+        writeln!(
+            f,
+            "{} converted, {} up to date, {} failed",
+            self.count(&FileStatus::Converted),
+            self.count(&FileStatus::UpToDate),
+            self.failures()
+        )?;
+        for file in &self.files {
+            if let FileStatus::Failed(message) = &file.status {
+                writeln!(f, "  {}: {}", file.source.display(), message)?;
+            }
+        }
+        for file in &self.files {
+            if let Some(model) = &file.model {
+                writeln!(f, "\n{}:\n{}", file.source.display(), model)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts every model and image under `source` into `output`, then writes
+/// the [`MANIFEST`] of everything converted. A file that fails to convert
+/// is reported and left out of the manifest rather than stopping the rest.
+pub fn run(source: &Path, output: &Path, options: &PipelineOptions) -> anyhow::Result<Report> {
+    let source =
+        fs::canonicalize(source).with_context(|| format!("Couldn't open {}", source.display()))?;
+    fs::create_dir_all(output)?;
+    let output = fs::canonicalize(output)?;
+
+    let mut sources = Vec::new();
+    find_sources(&source, &source, &output, &mut sources)?;
+    sources.sort();
+
+    // Sources that only differ in extension, e.g. `a.png` and `a.jpg`, would
+    // write over each other's output, so only the first is converted.
+    let mut outputs = HashMap::new();
+    let files = sources
+        .into_iter()
+        .map(|(relative, kind)| {
+            let first = outputs
+                .entry(kind.output(&relative))
+                .or_insert_with(|| relative.clone())
+                .clone();
+            (relative, kind, first)
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(relative, kind, first)| {
+            if first != relative {
+                let message = format!("{} has the same output", first.display());
+                return FileReport::failed(relative, kind, message);
+            }
+            convert(&source, &output, relative, kind, options)
+        })
+        .collect::<Vec<_>>();
+    let report = Report { files };
+    write_manifest(&output, &report)?;
+    Ok(report)
+}
+
+/// The models and images under `dir`, relative to `root`. `skip` is left
+/// out, so the output can be inside the source tree.
+fn find_sources(
+    root: &Path,
+    dir: &Path,
+    skip: &Path,
+    sources: &mut Vec<(PathBuf, AssetKind)>,
+) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path == skip {
+            continue;
+        }
+        if path.is_dir() {
+            find_sources(root, &path, skip, sources)?;
+        } else if let Some(kind) = AssetKind::of(&path) {
+            sources.push((path.strip_prefix(root)?.to_path_buf(), kind));
+        }
+    }
+    Ok(())
+}
+
+impl FileReport {
+    fn failed(source: PathBuf, kind: AssetKind, message: String) -> Self {
+        Self {
+            output: kind.output(&source),
+            source,
+            kind,
+            status: FileStatus::Failed(message),
+            source_bytes: 0,
+            output_bytes: 0,
+            images: Vec::new(),
+            model: None,
+        }
+    }
+}
+
+fn convert(
+    source_dir: &Path,
+    output_dir: &Path,
+    relative: PathBuf,
+    kind: AssetKind,
+    options: &PipelineOptions,
+) -> FileReport {
+    let source = source_dir.join(&relative);
+    let output_relative = kind.output(&relative);
+    let output = output_dir.join(&output_relative);
+    let size = |path: &Path| fs::metadata(path).map_or(0, |m| m.len());
+
+    let mut model = None;
+    let mut images = Vec::new();
+    let status = if !options.force && is_up_to_date(&source, &output) {
+        // The images a glTF file holds are named after it, so they are
+        // found without converting it again.
+        if resources::is_gltf(&source.to_string_lossy()) {
+            images = embedded_image_outputs(&source, &output_relative).unwrap_or_default();
+        }
+        FileStatus::UpToDate
+    } else {
+        let result = fs::create_dir_all(output.parent().unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|()| match kind {
+                AssetKind::Model => {
+                    convert_model(&source, &output, options).map(|(report, written)| {
+                        model = Some(report);
+                        images = written
+                            .iter()
+                            .map(|image| output_relative.with_file_name(image))
+                            .collect();
+                    })
+                }
+                AssetKind::Image => convert_image(&source, &output, options.max_dimension),
+            });
+        match result {
+            Ok(()) => FileStatus::Converted,
+            Err(e) => FileStatus::Failed(format!("{:#}", e)),
+        }
+    };
+    FileReport {
+        output_bytes: match status {
+            FileStatus::Failed(_) => 0,
+            _ => {
+                size(&output)
+                    + images
+                        .iter()
+                        .map(|image| size(&output_dir.join(image)))
+                        .sum::<u64>()
+            }
+        },
+        source_bytes: size(&source),
+        source: relative,
+        output: output_relative,
+        kind,
+        status,
+        images,
+        model,
+    }
+}
+
+fn is_up_to_date(source: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(source), modified(output)) {
+        (Ok(source), Ok(output)) => output >= source,
+        _ => false,
+    }
+}
+
+/// Parses with the same loader as the runtime. An absolute path is a valid
+/// resource name, and material libraries resolve next to it. Returns the
+/// names of the images written next to `output`.
+fn convert_model(
+    source: &Path,
+    output: &Path,
+    options: &PipelineOptions,
+) -> anyhow::Result<(ModelReport, Vec<String>)> {
+    let file_name = source.to_string_lossy();
+    let (mut data, embedded) = if resources::is_gltf(&file_name) {
+        pollster::block_on(resources::parse_gltf(&file_name))?
+    } else {
+        let data = pollster::block_on(resources::parse_model_data(
+            &file_name,
+            &LoadOptions::default(),
+        ))?;
+        (data, Vec::new())
+    };
+    if let Some(ao) = options.bake_ao {
+        for mesh in &mut data.meshes {
+            mesh.data.bake_vertex_ao_with_options(&ao);
+        }
+    }
+
+    let dir = source.parent().unwrap_or(Path::new(""));
+    let mut sizes = HashMap::new();
+    let mut written = Vec::new();
+    for image in &embedded {
+        let decoded = image::load_from_memory(&image.bytes)
+            .with_context(|| format!("Couldn't decode {}", image.name))?;
+        sizes.insert(image.name.as_str(), (decoded.width(), decoded.height()));
+        let name = ktx2_name(&image.name);
+        write_ktx2(
+            &decoded,
+            &output.with_file_name(&name),
+            options.max_dimension,
+        )?;
+        written.push(name);
+    }
+    let report = ModelReport::from_data(&data, |texture| {
+        sizes
+            .get(texture)
+            .copied()
+            .or_else(|| image::image_dimensions(dir.join(texture)).ok())
+    });
+
+    for material in &mut data.materials {
+        if AssetKind::of(Path::new(&material.diffuse_texture)) == Some(AssetKind::Image) {
+            material.diffuse_texture = ktx2_name(&material.diffuse_texture);
+        }
+    }
+    fs::write(output, data.to_bytes())?;
+    Ok((report, written))
+}
+
+/// The KTX2 output the image `name`, a path relative to a model, is
+/// converted to, relative to the model's output.
+fn ktx2_name(name: &str) -> String {
+    let stem = match name.rfind('.') {
+        Some(i) if !name[i..].contains(['/', '\\']) => &name[..i],
+        _ => name,
+    };
+    format!("{}.{}", stem, ktx2::EXTENSION)
+}
+
+/// The outputs of the images stored in the glTF file `source`, whose own
+/// output is `output`, as [`convert_model`] writes them.
+fn embedded_image_outputs(source: &Path, output: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let gltf = gltf::Gltf::from_slice(&fs::read(source)?)?;
+    let file_name = source.to_string_lossy();
+    Ok(gltf
+        .images()
+        .filter_map(|image| resources::embedded_image_name(&file_name, &image))
+        .map(|name| output.with_file_name(ktx2_name(&name)))
+        .collect())
+}
+
+fn convert_image(source: &Path, output: &Path, max_dimension: Option<u32>) -> anyhow::Result<()> {
+    let image = image::open(source)?;
+    write_ktx2(&image, output, max_dimension)
+}
+
+/// Writes `image`, shrunk to fit `max_dimension` first if it doesn't, with
+/// its mip chain as a KTX2 file.
+fn write_ktx2(
+    image: &image::DynamicImage,
+    output: &Path,
+    max_dimension: Option<u32>,
+) -> anyhow::Result<()> {
+    let (width, height) = (image.width(), image.height());
+    let (fit_width, fit_height) = match max_dimension {
+        Some(limit) => texture::fit_size(width, height, limit),
+        None => (width, height),
+    };
+    let rgba = if (fit_width, fit_height) == (width, height) {
+        image.to_rgba8()
+    } else {
+        image::imageops::resize(
+            &image.to_rgba8(),
+            fit_width,
+            fit_height,
+            image::imageops::FilterType::Triangle,
+        )
+    };
+    fs::write(output, ktx2::encode(&rgba))?;
+    Ok(())
+}
+
+/// Writes the [`Manifest`] of every output that converted or was up to
+/// date.
+fn write_manifest(output_dir: &Path, report: &Report) -> anyhow::Result<()> {
+    let files = report
+        .files
+        .iter()
+        .filter(|file| !matches!(file.status, FileStatus::Failed(_)))
+        .flat_map(|file| std::iter::once(&file.output).chain(&file.images))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|output| {
+            let bytes = fs::read(output_dir.join(output))?;
+            let path = output
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            Ok(ManifestEntry {
+                hash: fnv1a(&bytes),
+                bytes: bytes.len() as u64,
+                path,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let manifest = serde_json::to_string_pretty(&Manifest { files })?;
+    fs::write(output_dir.join(MANIFEST), manifest)?;
+    Ok(())
+}
+
+/// What [`run`] writes to [`MANIFEST`], as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A manifest entry: an output's hash, size and path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The [`fnv1a`] hash, written as 16 hex digits, as JSON readers
+    /// commonly lose integers past 53 bits.
+    #[serde(with = "hex_hash")]
+    pub hash: u64,
+    pub bytes: u64,
+    /// Relative to the output directory, with `/` separators.
+    pub path: String,
+}
+
+mod hex_hash {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:016x}", hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        u64::from_str_radix(&hex, 16).map_err(D::Error::custom)
+    }
+}
+
+/// Reads a manifest written by [`run`].
+pub fn read_manifest(text: &str) -> anyhow::Result<Vec<ManifestEntry>> {
+    Ok(serde_json::from_str::<Manifest>(text)?.files)
+}
+
+/// The 64 bit FNV-1a hash of `bytes`, which the manifest records for each
+/// output. Fast and stable, but not for telling apart files made to
+/// collide.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{self, ExportMaterial, ExportMesh, ExportNode, ExportScene};
+
+    /// A textured glTF quad and a separate image, under `root`.
+    fn fixture(root: &Path) {
+        let mut quad = ExportNode::new("quad");
+        quad.mesh = Some(0);
+        let scene = ExportScene {
+            meshes: vec![ExportMesh {
+                name: "quad".to_string(),
+                positions: vec![
+                    [0.0, 0.0, 0.0],
+                    [1.0, 0.0, 0.0],
+                    [1.0, 1.0, 0.0],
+                    [0.0, 1.0, 0.0],
+                ],
+                normals: vec![[0.0, 0.0, 1.0]; 4],
+                tex_coords: vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+                indices: vec![0, 1, 2, 0, 2, 3],
+                material: Some(0),
+            }],
+            materials: vec![ExportMaterial {
+                name: "painted".to_string(),
+                base_color: [1.0; 4],
+                base_color_texture: Some(image::RgbaImage::new(32, 8)),
+            }],
+            nodes: vec![quad],
+            roots: vec![0],
+            ..Default::default()
+        };
+        fs::create_dir_all(root.join("models")).unwrap();
+        export::to_gltf(&scene, root.join("models/quad.glb")).unwrap();
+        image::RgbaImage::new(20, 10)
+            .save(root.join("models/decal.png"))
+            .unwrap();
+    }
+
+    #[test]
+    fn gltf_models_and_images_convert_to_what_resources_loads() {
+        let root = std::env::temp_dir().join(format!("pipeline-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fixture(&root);
+        let output = root.join(resources::PROCESSED_DIR);
+        let mut options = PipelineOptions {
+            max_dimension: Some(16),
+            ..Default::default()
+        };
+
+        let report = run(&root, &output, &options).unwrap();
+        assert_eq!(report.count(&FileStatus::Converted), 2, "{}", report);
+        let manifest = read_manifest(&fs::read_to_string(output.join(MANIFEST)).unwrap()).unwrap();
+        let paths = manifest.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "models/decal.ktx2",
+                "models/quad.modeldata",
+                "models/quad.0.ktx2"
+            ]
+        );
+        for entry in &manifest {
+            let bytes = fs::read(output.join(&entry.path)).unwrap();
+            assert_eq!(
+                (fnv1a(&bytes), bytes.len() as u64),
+                (entry.hash, entry.bytes)
+            );
+        }
+
+        let model = output.join("models/quad.modeldata");
+        let data = model_data::ModelData::from_bytes(&fs::read(&model).unwrap()).unwrap();
+        assert_eq!(data.meshes[0].data.indices.len(), 6);
+        assert_eq!(data.materials[0].diffuse_texture, "quad.0.ktx2");
+        let bytes = fs::read(model.with_file_name("quad.0.ktx2")).unwrap();
+        let texture::SourceImage::MipChain(levels) = texture::SourceImage::decode(&bytes).unwrap()
+        else {
+            panic!("quad.0.ktx2 isn't a KTX2 file");
+        };
+        let sizes = levels.iter().map(|l| l.dimensions()).collect::<Vec<_>>();
+        assert_eq!(sizes, [(16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+
+        // Nothing changed, so the outputs, embedded images included, are
+        // kept and listed again.
+        let report = run(&root, &output, &options).unwrap();
+        assert_eq!(report.count(&FileStatus::UpToDate), 2, "{}", report);
+        let again = read_manifest(&fs::read_to_string(output.join(MANIFEST)).unwrap()).unwrap();
+        assert_eq!(again, manifest);
+        options.force = true;
+        let report = run(&root, &output, &options).unwrap();
+        assert_eq!(report.count(&FileStatus::Converted), 2, "{}", report);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn sources_with_the_same_output_convert_once() {
+        let root = std::env::temp_dir().join(format!("pipeline-clash-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        image::RgbaImage::new(2, 2)
+            .save(root.join("a.png"))
+            .unwrap();
+        image::RgbaImage::new(2, 2)
+            .save(root.join("a.jpg"))
+            .unwrap();
+        let report = run(&root, &root.join("out"), &PipelineOptions::default()).unwrap();
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(report.count(&FileStatus::Converted), 1, "{}", report);
+        assert_eq!(report.failures(), 1, "{}", report);
+    }
+}
@@ -0,0 +1,389 @@
+//! PLY point clouds, such as LiDAR scans: the positions and colors of the
+//! `vertex` element, from ASCII or binary little endian files. Normals are
+//! skipped, and an `intensity` stands in for the color where there is none.
+//! Other elements, like faces, are skipped too.
+//!
+//! [`PlyReader`] reads points a chunk at a time, so a file never has to fit
+//! in memory.
+
+use std::io::{BufRead, Read};
+
+use anyhow::{bail, Context};
+
+use crate::model::PointVertex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Both the old names, like `uchar`, and the sized ones, like `uint8`.
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// What full intensity is stored as, for normalizing colors. Float
+    /// colors go from 0 to 1.
+    fn full_scale(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f64 {
+        match self {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            ScalarType::U32 => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            ScalarType::F32 => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    Scalar(ScalarType),
+    /// A count of type `count`, then that many `item`s.
+    List {
+        count: ScalarType,
+        item: ScalarType,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlyProperty {
+    pub name: String,
+    pub ty: PropertyType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlyElement {
+    pub name: String,
+    pub count: u64,
+    pub properties: Vec<PlyProperty>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlyHeader {
+    pub format: PlyFormat,
+    pub elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    /// Reads the header, leaving `reader` at the first element's data.
+    pub fn read(reader: &mut impl BufRead) -> anyhow::Result<Self> {
+        let mut line = String::new();
+        let mut next_line = |line: &mut String| -> anyhow::Result<()> {
+            line.clear();
+            if reader.read_line(line)? == 0 {
+                bail!("the header has no end_header");
+            }
+            Ok(())
+        };
+
+        next_line(&mut line)?;
+        if line.trim_end() != "ply" {
+            bail!("not a PLY file");
+        }
+        let mut format = None;
+        let mut elements = Vec::<PlyElement>::new();
+        loop {
+            next_line(&mut line)?;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["end_header"] => break,
+                [] | ["comment", ..] | ["obj_info", ..] => {}
+                ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+                ["format", "binary_little_endian", _] => {
+                    format = Some(PlyFormat::BinaryLittleEndian)
+                }
+                ["format", other, _] => bail!("{} PLY files aren't supported", other),
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count
+                        .parse()
+                        .with_context(|| format!("bad count for element {}", name))?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, item, name] => {
+                    let scalar = |name: &str| {
+                        ScalarType::parse(name)
+                            .with_context(|| format!("unknown property type {}", name))
+                    };
+                    let ty = PropertyType::List {
+                        count: scalar(count)?,
+                        item: scalar(item)?,
+                    };
+                    add_property(&mut elements, name, ty)?;
+                }
+                ["property", ty, name] => {
+                    let ty = ScalarType::parse(ty)
+                        .with_context(|| format!("unknown property type {}", ty))?;
+                    add_property(&mut elements, name, PropertyType::Scalar(ty))?;
+                }
+                _ => bail!("unexpected header line {:?}", line.trim_end()),
+            }
+        }
+        Ok(Self {
+            format: format.context("the header has no format")?,
+            elements,
+        })
+    }
+
+    /// How many points the `vertex` element has.
+    pub fn point_count(&self) -> u64 {
+        self.elements
+            .iter()
+            .find(|e| e.name == "vertex")
+            .map_or(0, |e| e.count)
+    }
+}
+
+fn add_property(elements: &mut [PlyElement], name: &str, ty: PropertyType) -> anyhow::Result<()> {
+    let element = elements
+        .last_mut()
+        .with_context(|| format!("property {} comes before any element", name))?;
+    element.properties.push(PlyProperty {
+        name: name.to_string(),
+        ty,
+    });
+    Ok(())
+}
+
+/// Which of the vertex element's properties make up a [`PointVertex`].
+#[derive(Debug, Clone, Copy)]
+struct PointLayout {
+    position: [usize; 3],
+    /// Red, green, blue and alpha, with what full intensity is stored as.
+    color: [Option<(usize, f64)>; 4],
+    intensity: Option<(usize, f64)>,
+}
+
+impl PointLayout {
+    fn new(element: &PlyElement) -> anyhow::Result<Self> {
+        let find = |names: &[&str]| {
+            element.properties.iter().position(|p| {
+                names.contains(&p.name.as_str()) && matches!(p.ty, PropertyType::Scalar(_))
+            })
+        };
+        let scaled = |names: &[&str]| {
+            let i = find(names)?;
+            match element.properties[i].ty {
+                PropertyType::Scalar(ty) => Some((i, ty.full_scale())),
+                PropertyType::List { .. } => None,
+            }
+        };
+        let axis = |name: &str| {
+            find(&[name]).with_context(|| format!("the vertex element has no {}", name))
+        };
+        Ok(Self {
+            position: [axis("x")?, axis("y")?, axis("z")?],
+            color: [
+                scaled(&["red", "r", "diffuse_red"]),
+                scaled(&["green", "g", "diffuse_green"]),
+                scaled(&["blue", "b", "diffuse_blue"]),
+                scaled(&["alpha", "a", "diffuse_alpha"]),
+            ],
+            intensity: scaled(&["intensity", "scalar_intensity"]),
+        })
+    }
+
+    fn point(&self, values: &[f64]) -> PointVertex {
+        let unorm = |(i, full_scale): (usize, f64)| {
+            (values[i] / full_scale * 255.0).round().clamp(0.0, 255.0) as u8
+        };
+        let gray = self.intensity.map_or(255, unorm);
+        let [r, g, b, a] = self.color;
+        PointVertex {
+            position: self.position.map(|i| values[i] as f32),
+            color: [
+                r.map_or(gray, unorm),
+                g.map_or(gray, unorm),
+                b.map_or(gray, unorm),
+                a.map_or(255, unorm),
+            ],
+        }
+    }
+}
+
+/// Reads the points of a PLY file in chunks. Elements before `vertex` are
+/// skipped when the reader is made, and those after it aren't read at all.
+pub struct PlyReader<R> {
+    records: Records<R>,
+    header: PlyHeader,
+    vertex: PlyElement,
+    layout: PointLayout,
+    remaining: u64,
+}
+
+impl<R: BufRead> PlyReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let header = PlyHeader::read(&mut reader)?;
+        let mut records = Records {
+            reader,
+            format: header.format,
+            line: String::new(),
+            values: Vec::new(),
+        };
+        let mut vertex = None;
+        for element in &header.elements {
+            if element.name == "vertex" {
+                vertex = Some(element.clone());
+                break;
+            }
+            for _ in 0..element.count {
+                records
+                    .read(element)
+                    .with_context(|| format!("in element {}", element.name))?;
+            }
+        }
+        let vertex = vertex.context("the file has no vertex element")?;
+        Ok(Self {
+            records,
+            layout: PointLayout::new(&vertex)?,
+            remaining: vertex.count,
+            vertex,
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &PlyHeader {
+        &self.header
+    }
+
+    /// Points not read yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Appends up to `max` points to `out`, and returns how many. 0 means
+    /// every point has been read.
+    pub fn read_points(&mut self, max: usize, out: &mut Vec<PointVertex>) -> anyhow::Result<usize> {
+        let count = self.remaining.min(max as u64) as usize;
+        // The header's count isn't trusted with an allocation of its own.
+        out.reserve(count.min(1 << 20));
+        for i in 0..count {
+            self.records.read(&self.vertex).with_context(|| {
+                format!("at point {}", self.vertex.count - self.remaining + i as u64)
+            })?;
+            out.push(self.layout.point(&self.records.values));
+        }
+        self.remaining -= count as u64;
+        Ok(count)
+    }
+}
+
+/// Reads one record of an element at a time.
+struct Records<R> {
+    reader: R,
+    format: PlyFormat,
+    line: String,
+    /// The last record's values, one for each scalar property and 0 for
+    /// each list.
+    values: Vec<f64>,
+}
+
+impl<R: BufRead> Records<R> {
+    fn read(&mut self, element: &PlyElement) -> anyhow::Result<()> {
+        self.values.clear();
+        match self.format {
+            PlyFormat::Ascii => {
+                self.line.clear();
+                if self.reader.read_line(&mut self.line)? == 0 {
+                    bail!("the file ends early");
+                }
+                let mut words = self.line.split_whitespace();
+                let mut next = || -> anyhow::Result<f64> {
+                    let word = words.next().context("a record is missing values")?;
+                    word.parse()
+                        .with_context(|| format!("{:?} isn't a number", word))
+                };
+                for property in &element.properties {
+                    match property.ty {
+                        PropertyType::Scalar(_) => self.values.push(next()?),
+                        PropertyType::List { .. } => {
+                            for _ in 0..next()? as u64 {
+                                next()?;
+                            }
+                            self.values.push(0.0);
+                        }
+                    }
+                }
+            }
+            PlyFormat::BinaryLittleEndian => {
+                let mut bytes = [0; 8];
+                for property in &element.properties {
+                    match property.ty {
+                        PropertyType::Scalar(ty) => {
+                            let bytes = &mut bytes[..ty.size()];
+                            self.reader.read_exact(bytes)?;
+                            self.values.push(ty.decode(bytes));
+                        }
+                        PropertyType::List { count, item } => {
+                            let count_bytes = &mut bytes[..count.size()];
+                            self.reader.read_exact(count_bytes)?;
+                            let skip = count.decode(count_bytes) as u64 * item.size() as u64;
+                            let mut items = (&mut self.reader).take(skip);
+                            if std::io::copy(&mut items, &mut std::io::sink())? != skip {
+                                bail!("the file ends early");
+                            }
+                            self.values.push(0.0);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every point of a PLY file in memory. See [`PlyReader`] for large files.
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Vec<PointVertex>> {
+    let mut reader = PlyReader::new(bytes)?;
+    let mut points = Vec::new();
+    reader.read_points(usize::MAX, &mut points)?;
+    Ok(points)
+}
@@ -0,0 +1,306 @@
+use winit::{
+    event::{DeviceEvent, ElementState, MouseButton, WindowEvent},
+    window::{CursorGrabMode, Window},
+};
+
+/// How mouse movement reaches the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookMode {
+    /// Nothing requested yet. Browsers only grant pointer lock from a user
+    /// gesture, so it is requested on the first click.
+    Idle,
+    /// Lock was requested and hasn't taken effect yet. On the web the
+    /// request is answered asynchronously, so this waits a few frames.
+    Requested { frames: u32 },
+    /// Raw motion while the cursor is hidden and locked.
+    Locked,
+    /// Pointer lock was denied or released: the camera turns while a button
+    /// is held, from the change in cursor position.
+    Drag,
+}
+
+/// Mouse look with pointer lock, falling back to dragging when lock isn't
+/// available, e.g. in iframes that don't allow it, or after the user
+/// pressed Escape to release it.
+#[derive(Debug, Clone)]
+pub struct PointerLook {
+    mode: LookMode,
+    /// Frames a request may take before it counts as denied.
+    timeout_frames: u32,
+    dragging: bool,
+    last_cursor: Option<(f64, f64)>,
+    delta: (f32, f32),
+}
+
+impl Default for PointerLook {
+    fn default() -> Self {
+        Self::new(30)
+    }
+}
+
+impl PointerLook {
+    pub fn new(timeout_frames: u32) -> Self {
+        Self {
+            mode: LookMode::Idle,
+            timeout_frames,
+            dragging: false,
+            last_cursor: None,
+            delta: (0.0, 0.0),
+        }
+    }
+
+    pub fn mode(&self) -> LookMode {
+        self.mode
+    }
+
+    /// Explains the fallback while it is in use, for showing to the user.
+    pub fn hint(&self) -> Option<&'static str> {
+        (self.mode == LookMode::Drag)
+            .then_some("Pointer lock unavailable: drag with the left button to look around")
+    }
+
+    /// A request for lock was made.
+    pub fn lock_requested(&mut self) {
+        if self.mode == LookMode::Idle {
+            self.mode = LookMode::Requested { frames: 0 };
+        }
+    }
+
+    /// The request failed outright.
+    pub fn lock_failed(&mut self) {
+        self.fall_back();
+    }
+
+    /// Advances a frame, given whether the pointer is locked now. A request
+    /// that isn't granted in time counts as denied, and losing a granted
+    /// lock falls back to dragging.
+    pub fn observe_lock(&mut self, locked: bool) {
+        self.mode = match (self.mode, locked) {
+            (LookMode::Requested { .. }, true) => LookMode::Locked,
+            (LookMode::Requested { frames }, false) if frames + 1 >= self.timeout_frames => {
+                log::info!("Pointer lock wasn't granted, dragging to look instead");
+                self.fall_back_mode()
+            }
+            (LookMode::Requested { frames }, false) => LookMode::Requested { frames: frames + 1 },
+            (LookMode::Locked, false) => {
+                log::info!("Pointer lock was released, dragging to look instead");
+                self.fall_back_mode()
+            }
+            (mode, _) => mode,
+        };
+    }
+
+    fn fall_back_mode(&mut self) -> LookMode {
+        self.dragging = false;
+        LookMode::Drag
+    }
+
+    fn fall_back(&mut self) {
+        self.mode = self.fall_back_mode();
+    }
+
+    /// Requests lock on the first click, and tracks dragging.
+    pub fn process_window_event(&mut self, window: &Window, event: &WindowEvent) {
+        if self.track_window_event(event) {
+            self.request_lock(window);
+        }
+    }
+
+    /// [`Self::process_window_event`] without the window. Returns whether
+    /// lock should be requested.
+    fn track_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                self.dragging = pressed && self.mode == LookMode::Drag;
+                return pressed && self.mode == LookMode::Idle;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = (position.x, position.y);
+                if let (true, Some(last)) = (self.dragging, self.last_cursor) {
+                    self.delta.0 += (position.0 - last.0) as f32;
+                    self.delta.1 += (position.1 - last.1) as f32;
+                }
+                self.last_cursor = Some(position);
+            }
+            WindowEvent::CursorLeft { .. } | WindowEvent::Focused(false) => {
+                self.dragging = false;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    pub fn process_device_event(&mut self, event: &DeviceEvent) {
+        if let (LookMode::Locked, DeviceEvent::MouseMotion { delta }) = (self.mode, event) {
+            self.delta.0 += delta.0 as f32;
+            self.delta.1 += delta.1 as f32;
+        }
+    }
+
+    fn request_lock(&mut self, window: &Window) {
+        match window.set_cursor_grab(CursorGrabMode::Locked) {
+            Ok(()) => {
+                window.set_cursor_visible(false);
+                self.lock_requested();
+            }
+            Err(e) => {
+                log::info!("Pointer lock unavailable ({}), dragging to look instead", e);
+                self.lock_failed();
+            }
+        }
+    }
+
+    /// Call once a frame to follow the lock's state.
+    pub fn update(&mut self, window: &Window) {
+        if !matches!(self.mode, LookMode::Requested { .. } | LookMode::Locked) {
+            return;
+        }
+        let locked = is_pointer_locked();
+        self.observe_lock(locked);
+        if self.mode == LookMode::Drag {
+            window.set_cursor_visible(true);
+        }
+    }
+
+    /// The movement since the last call, in pixels.
+    pub fn take_delta(&mut self) -> (f32, f32) {
+        std::mem::take(&mut self.delta)
+    }
+}
+
+/// Whether the page holds pointer lock. Native grabs are granted or
+/// refused synchronously, so they count as held once requested.
+fn is_pointer_locked() -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            web_sys::window()
+                .and_then(|w| w.document())
+                .and_then(|d| d.pointer_lock_element())
+                .is_some()
+        } else {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::{dpi::PhysicalPosition, event::DeviceId};
+
+    use super::*;
+
+    fn device() -> DeviceId {
+        // Only compared against other ids, which these tests never do.
+        unsafe { DeviceId::dummy() }
+    }
+
+    fn button(look: &mut PointerLook, state: ElementState) -> bool {
+        #[allow(deprecated)]
+        look.track_window_event(&WindowEvent::MouseInput {
+            device_id: device(),
+            state,
+            button: MouseButton::Left,
+            modifiers: Default::default(),
+        })
+    }
+
+    fn cursor(look: &mut PointerLook, x: f64, y: f64) {
+        #[allow(deprecated)]
+        look.track_window_event(&WindowEvent::CursorMoved {
+            device_id: device(),
+            position: PhysicalPosition::new(x, y),
+            modifiers: Default::default(),
+        });
+    }
+
+    fn motion(look: &mut PointerLook, x: f64, y: f64) {
+        look.process_device_event(&DeviceEvent::MouseMotion { delta: (x, y) });
+    }
+
+    #[test]
+    fn granted_lock_turns_with_raw_motion() {
+        let mut look = PointerLook::new(3);
+        motion(&mut look, 5.0, 5.0);
+        assert_eq!(look.take_delta(), (0.0, 0.0), "nothing moves while idle");
+
+        assert!(
+            button(&mut look, ElementState::Pressed),
+            "the first click asks"
+        );
+        look.lock_requested();
+        // The web answers a few frames later.
+        look.observe_lock(false);
+        assert_eq!(look.mode(), LookMode::Requested { frames: 1 });
+        look.observe_lock(true);
+        assert_eq!(look.mode(), LookMode::Locked);
+        assert_eq!(look.hint(), None);
+
+        motion(&mut look, 3.0, -1.0);
+        motion(&mut look, 2.0, 4.0);
+        cursor(&mut look, 100.0, 100.0);
+        assert_eq!(look.take_delta(), (5.0, 3.0));
+        assert_eq!(look.take_delta(), (0.0, 0.0));
+        assert!(!button(&mut look, ElementState::Pressed), "asks only once");
+    }
+
+    #[test]
+    fn denied_lock_falls_back_to_dragging() {
+        let mut look = PointerLook::new(3);
+        look.lock_requested();
+        look.observe_lock(false);
+        look.observe_lock(false);
+        assert!(matches!(look.mode(), LookMode::Requested { .. }));
+        look.observe_lock(false);
+        assert_eq!(look.mode(), LookMode::Drag);
+        assert!(look.hint().is_some());
+
+        // Moving without a button held doesn't turn.
+        cursor(&mut look, 10.0, 10.0);
+        cursor(&mut look, 20.0, 10.0);
+        assert_eq!(look.take_delta(), (0.0, 0.0));
+        assert!(!button(&mut look, ElementState::Pressed));
+        cursor(&mut look, 25.0, 7.0);
+        cursor(&mut look, 30.0, 12.0);
+        button(&mut look, ElementState::Released);
+        cursor(&mut look, 50.0, 50.0);
+        assert_eq!(look.take_delta(), (10.0, 2.0));
+        // Raw motion is only for locked pointers.
+        motion(&mut look, 5.0, 5.0);
+        assert_eq!(look.take_delta(), (0.0, 0.0));
+
+        let mut refused = PointerLook::new(3);
+        refused.lock_failed();
+        assert_eq!(refused.mode(), LookMode::Drag);
+    }
+
+    #[test]
+    fn escape_mid_session_falls_back_to_dragging() {
+        let mut look = PointerLook::new(3);
+        look.lock_requested();
+        look.observe_lock(true);
+        motion(&mut look, 1.0, 1.0);
+        // Escape releases the lock; the next frame sees it gone.
+        look.observe_lock(false);
+        assert_eq!(look.mode(), LookMode::Drag);
+        assert_eq!(look.take_delta(), (1.0, 1.0), "motion before is kept");
+        motion(&mut look, 1.0, 1.0);
+        assert_eq!(look.take_delta(), (0.0, 0.0));
+
+        // Dragging works from there, and stays the mode.
+        cursor(&mut look, 0.0, 0.0);
+        button(&mut look, ElementState::Pressed);
+        cursor(&mut look, -4.0, 3.0);
+        look.observe_lock(true);
+        assert_eq!(look.mode(), LookMode::Drag);
+        assert_eq!(look.take_delta(), (-4.0, 3.0));
+        // Leaving the window ends the drag.
+        look.track_window_event(&WindowEvent::Focused(false));
+        cursor(&mut look, 10.0, 10.0);
+        assert_eq!(look.take_delta(), (0.0, 0.0));
+    }
+}
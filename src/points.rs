@@ -0,0 +1,209 @@
+//! Draws [`PointCloud`]s in the opaque scene, depth tested and written like
+//! meshes. WebGPU only rasterizes points a pixel across, so a larger size
+//! expands each point into a quad of its own, facing the screen.
+
+use bytemuck::Zeroable;
+
+use crate::{
+    gpu,
+    model::{PointCloud, PointVertex, Vertex},
+    reflect,
+    stats::FrameStats,
+    texture, variant,
+};
+
+/// How large points are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointSize {
+    /// A single pixel, with the point list topology.
+    Pixel,
+    /// A square this many pixels across, from two triangles per point.
+    Screen(f32),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointUniform {
+    view_proj: [[f32; 4]; 4],
+    viewport: [f32; 2],
+    size: f32,
+    _padding: u32,
+}
+
+/// Draws point clouds into targets of one format, with the scene's depth.
+pub struct PointPipeline {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    _memory: gpu::MemoryToken,
+    bind_group: wgpu::BindGroup,
+    size: PointSize,
+}
+
+impl PointPipeline {
+    pub(crate) const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+
+    /// Draws into targets of `color_format` with a
+    /// [`texture::Texture::DEPTH_FORMAT`] depth attachment.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        size: PointSize,
+    ) -> Result<Self, gpu::AllocError> {
+        let quads = matches!(size, PointSize::Screen(_));
+        let defines: &[&str] = if quads { &["QUADS"] } else { &[] };
+        let source = variant::preprocess(include_str!("points.wgsl"), defines)
+            .expect("points.wgsl has invalid directives");
+        reflect::debug_check("points.wgsl", &source, &[&Self::LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("points.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::LAYOUT_ENTRIES,
+            label: Some("point_bind_group_layout"),
+        });
+        let (uniform_buffer, uniform_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Point Buffer"),
+                    contents: bytemuck::cast_slice(&[PointUniform::zeroed()]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("point_bind_group"),
+        });
+
+        // Quads read each point once for all six of their vertices.
+        let vertex_layout = if quads {
+            wgpu::VertexBufferLayout {
+                step_mode: wgpu::VertexStepMode::Instance,
+                ..PointVertex::desc()
+            }
+        } else {
+            PointVertex::desc()
+        };
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Point Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[vertex_layout],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: if quads {
+                    wgpu::PrimitiveTopology::TriangleList
+                } else {
+                    wgpu::PrimitiveTopology::PointList
+                },
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            pipeline,
+            uniform_buffer,
+            _memory: uniform_buffer_memory,
+            bind_group,
+            size,
+        })
+    }
+
+    pub fn size(&self) -> PointSize {
+        self.size
+    }
+
+    /// Sets the camera and the size of the target in pixels for the next
+    /// draws.
+    pub fn prepare(
+        &self,
+        queue: &wgpu::Queue,
+        view_proj: cgmath::Matrix4<f32>,
+        viewport: (u32, u32),
+    ) {
+        let size = match self.size {
+            PointSize::Pixel => 1.0,
+            PointSize::Screen(size) => size,
+        };
+        let uniform = PointUniform {
+            view_proj: view_proj.into(),
+            viewport: [viewport.0.max(1) as f32, viewport.1.max(1) as f32],
+            size,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Adds the commands [`Self::draw`] records for `cloud`.
+    pub fn add_stats(&self, stats: &mut FrameStats, cloud: &PointCloud) {
+        if cloud.num_points == 0 {
+            return;
+        }
+        stats.pipeline_sets += 1;
+        stats.bind_group_sets += 1;
+        stats.draw_calls += 1;
+        match self.size {
+            PointSize::Pixel => stats.instances += 1,
+            PointSize::Screen(_) => {
+                stats.instances += cloud.num_points;
+                stats.triangles += cloud.num_points as u64 * 2;
+            }
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, cloud: &'a PointCloud) {
+        // An empty buffer can't be bound.
+        if cloud.num_points == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, cloud.vertex_buffer.slice(..));
+        match self.size {
+            PointSize::Pixel => render_pass.draw(0..cloud.num_points, 0..1),
+            PointSize::Screen(_) => render_pass.draw(0..6, 0..cloud.num_points),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// Point clouds, a pixel per point or, with QUADS, a square a fixed number of
+// pixels across for each instance.
+
+struct Points {
+    view_proj: mat4x4<f32>,
+    // The target's size in pixels.
+    viewport: vec2<f32>,
+    size: f32,
+    _padding: u32,
+}
+@group(0) @binding(0)
+var<uniform> points: Points;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+fn decode(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, point: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = points.view_proj * vec4<f32>(point.position, 1.0);
+//!ifdef QUADS
+    // Two triangles around the point. The offset is scaled by w so it comes
+    // out the same number of pixels after the perspective divide.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let offset = corners[vertex_index] * points.size / points.viewport;
+    out.clip_position += vec4<f32>(offset * out.clip_position.w, 0.0, 0.0);
+//!endif
+    // Colors are stored sRGB encoded.
+    out.color = vec4<f32>(decode(point.color.rgb), point.color.a);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,77 @@
+use std::f32::consts::PI;
+
+use crate::model::PrimitiveVertex;
+
+/// An axis aligned cube centered on the origin, with flat normals.
+pub fn cube(size: f32) -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    let h = size * 0.5;
+    // (normal, tangent u, tangent v) for each face.
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let base = vertices.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = [
+                (normal[0] + u[0] * su + v[0] * sv) * h,
+                (normal[1] + u[1] * su + v[1] * sv) * h,
+                (normal[2] + u[2] * su + v[2] * sv) * h,
+            ];
+            vertices.push(PrimitiveVertex {
+                position,
+                tex_coords: [(su + 1.0) * 0.5, (1.0 - sv) * 0.5],
+                normal,
+            });
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+/// A UV sphere centered on the origin. `sectors` splits it around the Y axis
+/// and `stacks` from pole to pole.
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+
+    let mut vertices = Vec::with_capacity(((sectors + 1) * (stacks + 1)) as usize);
+    for stack in 0..=stacks {
+        let v = stack as f32 / stacks as f32;
+        let phi = v * PI;
+        for sector in 0..=sectors {
+            // The seam column is duplicated so texture coordinates don't wrap.
+            let u = sector as f32 / sectors as f32;
+            let theta = u * 2.0 * PI;
+            let normal = [phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin()];
+            vertices.push(PrimitiveVertex {
+                position: [normal[0] * radius, normal[1] * radius, normal[2] * radius],
+                tex_coords: [u, v],
+                normal,
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((sectors * stacks * 6) as usize);
+    let row = sectors + 1;
+    for stack in 0..stacks {
+        for sector in 0..sectors {
+            let a = stack * row + sector;
+            let b = a + row;
+            if stack != 0 {
+                indices.extend_from_slice(&[a, a + 1, b]);
+            }
+            if stack != stacks - 1 {
+                indices.extend_from_slice(&[a + 1, b + 1, b]);
+            }
+        }
+    }
+    (vertices, indices)
+}
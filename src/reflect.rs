@@ -0,0 +1,611 @@
+//! Bind group layouts read from WGSL with naga, so they can't drift from
+//! the shaders. [`ShaderReflection`] lists a shader's bindings with the
+//! stages using them, builds layout entries from them, and checks
+//! hand-written entries against them.
+//!
+//! A few things a shader doesn't say are filled in the way the crate's own
+//! layouts have them: float textures are filterable if the shader samples
+//! them, samplers filter unless they compare, buffers have no dynamic
+//! offset and no minimum size. Binding arrays and pipeline overridable
+//! constants aren't supported and fail with a [`ReflectError`].
+
+use std::{collections::HashSet, fmt};
+
+/// One resource a shader declares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedBinding {
+    /// The variable's name in the shader, or empty if it has none.
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    /// The stages whose entry points use it, possibly through functions
+    /// they call. Empty if nothing uses it.
+    pub visibility: wgpu::ShaderStages,
+    pub ty: wgpu::BindingType,
+    /// For buffers, the size of the shader's type, which a binding must at
+    /// least hold.
+    pub min_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReflectError {
+    Parse(String),
+    Invalid(String),
+    /// `binding_array`s, e.g. arrays of textures.
+    BindingArray {
+        name: String,
+        group: u32,
+        binding: u32,
+    },
+    /// Pipeline overridable constants, which naga 0.12 doesn't parse.
+    OverrideConstant(String),
+    /// A type without a binding type, e.g. a 3D texture array.
+    Unsupported {
+        name: String,
+        what: String,
+    },
+    /// Two reflections given to [`ShaderReflection::merge`] disagree.
+    Conflict {
+        name: String,
+        group: u32,
+        binding: u32,
+    },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReflectError::Parse(message) => write!(f, "couldn't parse the shader: {}", message),
+            ReflectError::Invalid(message) => write!(f, "invalid shader: {}", message),
+            ReflectError::BindingArray {
+                name,
+                group,
+                binding,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) is a binding array, which isn't supported",
+                name, group, binding
+            ),
+            ReflectError::OverrideConstant(name) => write!(
+                f,
+                "override constant {} isn't supported, use a const or a define",
+                name
+            ),
+            ReflectError::Unsupported { name, what } => {
+                write!(f, "{} is a {}, which isn't supported", name, what)
+            }
+            ReflectError::Conflict {
+                name,
+                group,
+                binding,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) has different types in the variants",
+                name, group, binding
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+/// How a hand-written layout entry disagrees with the shader.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutMismatch {
+    /// The shader declares a binding the layout doesn't have.
+    Missing {
+        name: String,
+        group: u32,
+        binding: u32,
+    },
+    /// The layout's type can't be bound to the shader's.
+    Type {
+        name: String,
+        group: u32,
+        binding: u32,
+        shader: wgpu::BindingType,
+        layout: wgpu::BindingType,
+    },
+    /// The layout hides the binding from a stage that uses it.
+    Visibility {
+        name: String,
+        group: u32,
+        binding: u32,
+        shader: wgpu::ShaderStages,
+        layout: wgpu::ShaderStages,
+    },
+    /// The layout's `min_binding_size` is under the size of the shader's
+    /// type.
+    TooSmall {
+        name: String,
+        group: u32,
+        binding: u32,
+        shader: u64,
+        layout: u64,
+    },
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutMismatch::Missing {
+                name,
+                group,
+                binding,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) isn't in the layout",
+                name, group, binding
+            ),
+            LayoutMismatch::Type {
+                name,
+                group,
+                binding,
+                shader,
+                layout,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) is {:?} in the shader but {:?} in the layout",
+                name, group, binding, shader, layout
+            ),
+            LayoutMismatch::Visibility {
+                name,
+                group,
+                binding,
+                shader,
+                layout,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) is used in {:?} but only visible to {:?}",
+                name, group, binding, shader, layout
+            ),
+            LayoutMismatch::TooSmall {
+                name,
+                group,
+                binding,
+                shader,
+                layout,
+            } => write!(
+                f,
+                "{} (group {}, binding {}) takes {} bytes but the layout only requires {}",
+                name, group, binding, shader, layout
+            ),
+        }
+    }
+}
+
+/// The bindings of a shader, by group and then binding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderReflection {
+    bindings: Vec<ReflectedBinding>,
+}
+
+impl ShaderReflection {
+    pub fn from_wgsl(source: &str) -> Result<Self, ReflectError> {
+        if let Some(name) = find_override(source) {
+            return Err(ReflectError::OverrideConstant(name));
+        }
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|e| ReflectError::Parse(e.emit_to_string(source)))?;
+        Self::from_module(&module)
+    }
+
+    /// Validates `module`, which reflection needs to know which entry
+    /// points use what.
+    pub fn from_module(module: &naga::Module) -> Result<Self, ReflectError> {
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(module)
+        .map_err(|e| ReflectError::Invalid(e.into_inner().to_string()))?;
+        let sampled = sampled_images(module);
+
+        let mut bindings = Vec::new();
+        for (handle, var) in module.global_variables.iter() {
+            let Some(resource) = &var.binding else {
+                continue;
+            };
+            let name = var.name.clone().unwrap_or_default();
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (i, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(i)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+            let ty = binding_type(module, var, &name, resource, sampled.contains(&handle))?;
+            let min_size = match ty {
+                wgpu::BindingType::Buffer { .. } => {
+                    Some(module.types[var.ty].inner.size(&module.constants) as u64)
+                }
+                _ => None,
+            };
+            bindings.push(ReflectedBinding {
+                name,
+                group: resource.group,
+                binding: resource.binding,
+                visibility,
+                ty,
+                min_size,
+            });
+        }
+        bindings.sort_by_key(|b| (b.group, b.binding));
+        Ok(Self { bindings })
+    }
+
+    pub fn bindings(&self) -> &[ReflectedBinding] {
+        &self.bindings
+    }
+
+    /// Adds the bindings of another variant of the same shader, e.g. one
+    /// preprocessed with other defines. Bindings in both become visible to
+    /// the stages of either, and must have the same type.
+    pub fn merge(&mut self, other: &ShaderReflection) -> Result<(), ReflectError> {
+        for b in &other.bindings {
+            match self.get_mut(b.group, b.binding) {
+                Some(existing) if existing.ty != b.ty => {
+                    return Err(ReflectError::Conflict {
+                        name: b.name.clone(),
+                        group: b.group,
+                        binding: b.binding,
+                    });
+                }
+                Some(existing) => {
+                    existing.visibility |= b.visibility;
+                    existing.min_size = existing.min_size.max(b.min_size);
+                }
+                None => self.bindings.push(b.clone()),
+            }
+        }
+        self.bindings.sort_by_key(|b| (b.group, b.binding));
+        Ok(())
+    }
+
+    pub fn get(&self, group: u32, binding: u32) -> Option<&ReflectedBinding> {
+        self.bindings
+            .iter()
+            .find(|b| (b.group, b.binding) == (group, binding))
+    }
+
+    fn get_mut(&mut self, group: u32, binding: u32) -> Option<&mut ReflectedBinding> {
+        self.bindings
+            .iter_mut()
+            .find(|b| (b.group, b.binding) == (group, binding))
+    }
+
+    /// One more than the highest group, i.e. how many layouts a pipeline
+    /// using the shader needs.
+    pub fn group_count(&self) -> u32 {
+        self.bindings.last().map_or(0, |b| b.group + 1)
+    }
+
+    /// The layout entries of `group`, by binding.
+    pub fn layout_entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        self.bindings
+            .iter()
+            .filter(|b| b.group == group)
+            .map(|b| wgpu::BindGroupLayoutEntry {
+                binding: b.binding,
+                visibility: b.visibility,
+                ty: b.ty,
+                count: None,
+            })
+            .collect()
+    }
+
+    /// A layout per group, labelled `"{label} group {i}"`. Groups the
+    /// shader skips get empty layouts.
+    pub fn create_layouts(&self, device: &wgpu::Device, label: &str) -> Vec<wgpu::BindGroupLayout> {
+        (0..self.group_count())
+            .map(|group| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(&format!("{} group {}", label, group)),
+                    entries: &self.layout_entries(group),
+                })
+            })
+            .collect()
+    }
+
+    /// How `entries`, a hand-written layout for `group`, disagrees with the
+    /// shader. Entries the shader doesn't declare are fine, as are wider
+    /// visibility, dynamic offsets and choices the shader can't make, such
+    /// as whether a float texture is filterable.
+    pub fn check(&self, group: u32, entries: &[wgpu::BindGroupLayoutEntry]) -> Vec<LayoutMismatch> {
+        let mut mismatches = Vec::new();
+        for b in self.bindings.iter().filter(|b| b.group == group) {
+            let (name, binding) = (b.name.clone(), b.binding);
+            let Some(entry) = entries.iter().find(|e| e.binding == binding) else {
+                mismatches.push(LayoutMismatch::Missing {
+                    name,
+                    group,
+                    binding,
+                });
+                continue;
+            };
+            if !compatible(&b.ty, &entry.ty) {
+                mismatches.push(LayoutMismatch::Type {
+                    name: name.clone(),
+                    group,
+                    binding,
+                    shader: b.ty,
+                    layout: entry.ty,
+                });
+            }
+            if !entry.visibility.contains(b.visibility) {
+                mismatches.push(LayoutMismatch::Visibility {
+                    name: name.clone(),
+                    group,
+                    binding,
+                    shader: b.visibility,
+                    layout: entry.visibility,
+                });
+            }
+            if let (
+                Some(shader),
+                wgpu::BindingType::Buffer {
+                    min_binding_size: Some(layout),
+                    ..
+                },
+            ) = (b.min_size, entry.ty)
+            {
+                if layout.get() < shader {
+                    mismatches.push(LayoutMismatch::TooSmall {
+                        name,
+                        group,
+                        binding,
+                        shader,
+                        layout: layout.get(),
+                    });
+                }
+            }
+        }
+        mismatches
+    }
+
+    /// [`ShaderReflection::check`] for each group, `groups[i]` being the
+    /// entries of group `i`.
+    pub fn check_groups(&self, groups: &[&[wgpu::BindGroupLayoutEntry]]) -> Vec<LayoutMismatch> {
+        groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, entries)| self.check(group as u32, entries))
+            .collect()
+    }
+}
+
+/// A layout per bind group of `module`, derived from its bindings.
+pub fn layouts_from_shader(
+    device: &wgpu::Device,
+    module: &naga::Module,
+) -> Result<Vec<wgpu::BindGroupLayout>, ReflectError> {
+    Ok(ShaderReflection::from_module(module)?.create_layouts(device, "Reflected Layout"))
+}
+
+/// In debug builds, logs an error for each way `groups` disagree with the
+/// shader `source`, or for the shader failing to reflect. Does nothing in
+/// release builds.
+pub(crate) fn debug_check(label: &str, source: &str, groups: &[&[wgpu::BindGroupLayoutEntry]]) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    match ShaderReflection::from_wgsl(source) {
+        Ok(reflection) => {
+            for mismatch in reflection.check_groups(groups) {
+                log::error!("{}: {}", label, mismatch);
+            }
+        }
+        Err(e) => log::error!("{}: couldn't check the layouts: {}", label, e),
+    }
+}
+
+/// Whether a layout entry of type `layout` can be bound where the shader
+/// declares `shader`, ignoring what the shader can't know.
+fn compatible(shader: &wgpu::BindingType, layout: &wgpu::BindingType) -> bool {
+    use wgpu::{BindingType as B, TextureSampleType as S};
+    match (shader, layout) {
+        (B::Buffer { ty: a, .. }, B::Buffer { ty: b, .. }) => a == b,
+        (B::Sampler(a), B::Sampler(b)) => {
+            let compares =
+                |s: &wgpu::SamplerBindingType| *s == wgpu::SamplerBindingType::Comparison;
+            compares(a) == compares(b)
+        }
+        (
+            B::Texture {
+                sample_type: a,
+                view_dimension: a_dimension,
+                multisampled: a_multisampled,
+            },
+            B::Texture {
+                sample_type: b,
+                view_dimension: b_dimension,
+                multisampled: b_multisampled,
+            },
+        ) => {
+            let same_type = matches!(
+                (a, b),
+                (S::Float { .. }, S::Float { .. })
+                    | (S::Depth, S::Depth)
+                    | (S::Sint, S::Sint)
+                    | (S::Uint, S::Uint)
+            );
+            same_type && a_dimension == b_dimension && a_multisampled == b_multisampled
+        }
+        (a @ B::StorageTexture { .. }, b @ B::StorageTexture { .. }) => a == b,
+        _ => false,
+    }
+}
+
+/// The first `override` declaration in `source`, outside comments.
+fn find_override(source: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let code = line.split("//").next().unwrap_or_default();
+        let mut words = code
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|w| !w.is_empty());
+        words.find(|w| *w == "override")?;
+        Some(words.next().unwrap_or("?").to_string())
+    })
+}
+
+/// The images sampled with a sampler anywhere, which must be filterable.
+fn sampled_images(module: &naga::Module) -> HashSet<naga::Handle<naga::GlobalVariable>> {
+    let functions = module
+        .functions
+        .iter()
+        .map(|(_, f)| f)
+        .chain(module.entry_points.iter().map(|e| &e.function));
+    let mut sampled = HashSet::new();
+    for function in functions {
+        for (_, expression) in function.expressions.iter() {
+            if let naga::Expression::ImageSample { image, .. } = expression {
+                if let naga::Expression::GlobalVariable(handle) = function.expressions[*image] {
+                    sampled.insert(handle);
+                }
+            }
+        }
+    }
+    sampled
+}
+
+fn binding_type(
+    module: &naga::Module,
+    var: &naga::GlobalVariable,
+    name: &str,
+    resource: &naga::ResourceBinding,
+    sampled: bool,
+) -> Result<wgpu::BindingType, ReflectError> {
+    let unsupported = |what: &str| ReflectError::Unsupported {
+        name: name.to_string(),
+        what: what.to_string(),
+    };
+    let buffer = |ty| wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    };
+    match var.space {
+        naga::AddressSpace::Uniform => return Ok(buffer(wgpu::BufferBindingType::Uniform)),
+        naga::AddressSpace::Storage { access } => {
+            return Ok(buffer(wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            }))
+        }
+        naga::AddressSpace::Handle => {}
+        space => return Err(unsupported(&format!("binding in {:?}", space))),
+    }
+
+    match module.types[var.ty].inner {
+        naga::TypeInner::Sampler { comparison } => Ok(wgpu::BindingType::Sampler(if comparison {
+            wgpu::SamplerBindingType::Comparison
+        } else {
+            wgpu::SamplerBindingType::Filtering
+        })),
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            use naga::ImageDimension as D;
+            use wgpu::TextureViewDimension as V;
+            let view_dimension = match (dim, arrayed) {
+                (D::D1, false) => V::D1,
+                (D::D2, false) => V::D2,
+                (D::D2, true) => V::D2Array,
+                (D::D3, false) => V::D3,
+                (D::Cube, false) => V::Cube,
+                (D::Cube, true) => V::CubeArray,
+                (dim, _) => return Err(unsupported(&format!("{:?} texture array", dim))),
+            };
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => {
+                    let sample_type = match kind {
+                        naga::ScalarKind::Float => wgpu::TextureSampleType::Float {
+                            filterable: sampled && !multi,
+                        },
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        naga::ScalarKind::Bool => return Err(unsupported("bool texture")),
+                    };
+                    Ok(wgpu::BindingType::Texture {
+                        sample_type,
+                        view_dimension,
+                        multisampled: multi,
+                    })
+                }
+                naga::ImageClass::Depth { multi } => Ok(wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                naga::ImageClass::Storage { format, access } => {
+                    let load = access.contains(naga::StorageAccess::LOAD);
+                    let store = access.contains(naga::StorageAccess::STORE);
+                    Ok(wgpu::BindingType::StorageTexture {
+                        access: match (load, store) {
+                            (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                            (false, true) => wgpu::StorageTextureAccess::WriteOnly,
+                            _ => wgpu::StorageTextureAccess::ReadOnly,
+                        },
+                        format: storage_format(format),
+                        view_dimension,
+                    })
+                }
+            }
+        }
+        naga::TypeInner::BindingArray { .. } => Err(ReflectError::BindingArray {
+            name: name.to_string(),
+            group: resource.group,
+            binding: resource.binding,
+        }),
+        ref other => Err(unsupported(&format!("{:?}", other))),
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as N;
+    use wgpu::TextureFormat as W;
+    match format {
+        N::R8Unorm => W::R8Unorm,
+        N::R8Snorm => W::R8Snorm,
+        N::R8Uint => W::R8Uint,
+        N::R8Sint => W::R8Sint,
+        N::R16Uint => W::R16Uint,
+        N::R16Sint => W::R16Sint,
+        N::R16Float => W::R16Float,
+        N::Rg8Unorm => W::Rg8Unorm,
+        N::Rg8Snorm => W::Rg8Snorm,
+        N::Rg8Uint => W::Rg8Uint,
+        N::Rg8Sint => W::Rg8Sint,
+        N::R32Uint => W::R32Uint,
+        N::R32Sint => W::R32Sint,
+        N::R32Float => W::R32Float,
+        N::Rg16Uint => W::Rg16Uint,
+        N::Rg16Sint => W::Rg16Sint,
+        N::Rg16Float => W::Rg16Float,
+        N::Rgba8Unorm => W::Rgba8Unorm,
+        N::Rgba8Snorm => W::Rgba8Snorm,
+        N::Rgba8Uint => W::Rgba8Uint,
+        N::Rgba8Sint => W::Rgba8Sint,
+        N::Rgb10a2Unorm => W::Rgb10a2Unorm,
+        N::Rg11b10Float => W::Rg11b10Float,
+        N::Rg32Uint => W::Rg32Uint,
+        N::Rg32Sint => W::Rg32Sint,
+        N::Rg32Float => W::Rg32Float,
+        N::Rgba16Uint => W::Rgba16Uint,
+        N::Rgba16Sint => W::Rgba16Sint,
+        N::Rgba16Float => W::Rgba16Float,
+        N::Rgba32Uint => W::Rgba32Uint,
+        N::Rgba32Sint => W::Rgba32Sint,
+        N::Rgba32Float => W::Rgba32Float,
+        N::R16Unorm => W::R16Unorm,
+        N::R16Snorm => W::R16Snorm,
+        N::Rg16Unorm => W::Rg16Unorm,
+        N::Rg16Snorm => W::Rg16Snorm,
+        N::Rgba16Unorm => W::Rgba16Unorm,
+        N::Rgba16Snorm => W::Rgba16Snorm,
+    }
+}
@@ -0,0 +1,547 @@
+//! Glass drawn over the opaque scene. Frames with glass draw the opaque
+//! scene into [`RefractionPass::scene_view`] rather than the target, and the
+//! refraction pass draws it onto the target before the glass. Each
+//! refractive surface samples the scene at an offset along its view space
+//! normal, so whatever is behind it looks bent. Fresnel
+//! blends that with a reflection of the sky and ground from the
+//! [`Ambient`] light, the only environment there is.
+//!
+//! Glass is drawn last and doesn't write depth, so glass behind other glass
+//! only shows through it if it was opaque, and blended particles behind it
+//! are drawn over it.
+
+use std::ops::Range;
+
+use cgmath::{Matrix4, Point3};
+
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken},
+    light::Ambient,
+    model::{self, Mesh, Model, Vertex},
+    reflect,
+    stats::FrameStats,
+    texture, InstanceRaw,
+};
+
+/// A material that bends what's behind it, like glass or water.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Refractive {
+    /// The index of refraction. 1 doesn't bend at all, glass is about 1.5.
+    pub ior: f32,
+    /// How thick the surface looks, in world units. The offset grows with
+    /// it, so a solid ball wants its radius here.
+    pub thickness: f32,
+}
+
+impl Refractive {
+    pub const GLASS: Refractive = Refractive {
+        ior: 1.5,
+        thickness: 1.0,
+    };
+
+    /// Glass from an MTL material's `illum` model and optical density
+    /// (`Ni`). Models 4, 6 and 7 are the ones with refraction, and an
+    /// optical density of 1 or less doesn't bend anything.
+    pub fn from_mtl(illumination_model: Option<u8>, optical_density: f32) -> Option<Self> {
+        match illumination_model? {
+            4 | 6 | 7 if optical_density > 1.0 => Some(Refractive {
+                ior: optical_density,
+                ..Self::GLASS
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What [`RefractionPass::prepare`] needs to know about the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RefractionCamera {
+    pub view: Matrix4<f32>,
+    /// Maps to wgpu clip space.
+    pub projection: Matrix4<f32>,
+    pub eye: Point3<f32>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    eye: [f32; 4],
+    uv_scale: [f32; 2],
+    _padding: [f32; 2],
+    environment: Ambient,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    ior: f32,
+    thickness: f32,
+    _padding: [f32; 2],
+}
+
+/// Instances of a model whose refractive meshes [`RefractionPass`] draws.
+pub struct RefractionDraw<'a> {
+    pub model: &'a Model,
+    pub instance_buffer: &'a wgpu::Buffer,
+    pub instances: Range<u32>,
+}
+
+impl<'a> RefractionDraw<'a> {
+    /// The meshes drawn, and their material's refraction. Only models with
+    /// [`model::VertexPrecision::Full`] are drawn.
+    fn meshes(&self) -> impl Iterator<Item = (&'a Mesh, Refractive)> + 'a {
+        let model = self.model;
+        let full = model.vertex_precision == model::VertexPrecision::Full;
+        model
+            .meshes
+            .iter()
+            .filter(move |_| full)
+            .filter_map(move |mesh| Some((mesh, model.materials[mesh.material].refractive?)))
+    }
+}
+
+/// Whether any of `model`'s meshes are drawn by [`RefractionPass`] rather
+/// than the opaque pass.
+pub fn has_refractive(model: &Model) -> bool {
+    model.materials.iter().any(|m| m.refractive.is_some())
+}
+
+/// Holds the opaque scene each frame and draws refractive meshes over it.
+pub struct RefractionPass {
+    pipeline: wgpu::RenderPipeline,
+    scene_pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    scene_layout: wgpu::BindGroupLayout,
+    scene_sampler: wgpu::Sampler,
+    scene: SceneTarget,
+    material_buffer: wgpu::Buffer,
+    material_bind_group: wgpu::BindGroup,
+    material_stride: u32,
+    _memory: [MemoryToken; 2],
+    /// Meshes written by the last [`Self::prepare`].
+    meshes: u32,
+}
+
+/// What the opaque scene is drawn into, the size of the target.
+struct SceneTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    _memory: MemoryToken,
+}
+
+impl RefractionPass {
+    /// Meshes past this many in a frame are left out.
+    pub const MAX_MESHES: usize = 256;
+
+    pub(crate) const CAMERA_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+
+    pub(crate) const SCENE_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+
+    pub(crate) const MATERIAL_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(
+                    std::mem::size_of::<MaterialUniform>() as u64
+                ),
+            },
+            count: None,
+        }];
+
+    /// Draws into targets of `color_format`, `size` across, with a depth
+    /// target of [`texture::Texture::DEPTH_FORMAT`]. The scene is drawn in
+    /// the same format.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let source = include_str!("refraction.wgsl");
+        reflect::debug_check(
+            "refraction.wgsl",
+            source,
+            &[
+                &Self::CAMERA_LAYOUT_ENTRIES,
+                &Self::SCENE_LAYOUT_ENTRIES,
+                &Self::MATERIAL_LAYOUT_ENTRIES,
+            ],
+        );
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("refraction.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::CAMERA_LAYOUT_ENTRIES,
+            label: Some("refraction_camera_bind_group_layout"),
+        });
+        let scene_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::SCENE_LAYOUT_ENTRIES,
+            label: Some("refraction_scene_bind_group_layout"),
+        });
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::MATERIAL_LAYOUT_ENTRIES,
+            label: Some("refraction_material_bind_group_layout"),
+        });
+
+        let (camera_buffer, camera_buffer_memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Refraction Camera Buffer"),
+                size: std::mem::size_of::<CameraUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("refraction_camera_bind_group"),
+        });
+
+        let material_stride = wgpu::util::align_to(
+            std::mem::size_of::<MaterialUniform>() as u32,
+            device.limits().min_uniform_buffer_offset_alignment,
+        );
+        let (material_buffer, material_buffer_memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Refraction Material Buffer"),
+                size: material_stride as u64 * Self::MAX_MESHES as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let material_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &material_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &material_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<MaterialUniform>() as u64),
+                }),
+            }],
+            label: Some("refraction_material_bind_group"),
+        });
+
+        // Clamped, so the bilinear footprint at the edges stays on screen.
+        let scene_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Refraction Scene Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let scene = SceneTarget::new(device, &scene_layout, &scene_sampler, color_format, size)?;
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Refraction Pipeline Layout"),
+            bind_group_layouts: &[&camera_layout, &scene_layout, &material_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Refraction Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // Not blended: the refracted scene already is what's behind.
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        // Fills the whole target from the scene, under everything drawn
+        // after it in the pass.
+        let scene_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Refraction Scene Pipeline Layout"),
+                bind_group_layouts: &[&camera_layout, &scene_layout],
+                push_constant_ranges: &[],
+            });
+        let scene_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Refraction Scene Pipeline"),
+            layout: Some(&scene_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_scene",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_scene",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            pipeline,
+            scene_pipeline,
+            camera_buffer,
+            camera_bind_group,
+            scene_layout,
+            scene_sampler,
+            scene,
+            material_buffer,
+            material_bind_group,
+            material_stride,
+            _memory: [camera_buffer_memory, material_buffer_memory],
+            meshes: 0,
+        })
+    }
+
+    /// Recreates the scene target for a target `size` across.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) -> Result<(), AllocError> {
+        let format = self.scene.texture.format();
+        self.scene = SceneTarget::new(
+            device,
+            &self.scene_layout,
+            &self.scene_sampler,
+            format,
+            size,
+        )?;
+        Ok(())
+    }
+
+    pub fn scene_texture(&self) -> &wgpu::Texture {
+        &self.scene.texture
+    }
+
+    /// Where to draw the opaque scene, with the depth target, in frames
+    /// with glass. [`Self::draw`] then draws it onto the target.
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+
+    /// Writes the camera and each refractive mesh of `draws`' material, in
+    /// the order [`Self::draw`] draws them. Pass it the same `draws`.
+    pub fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &RefractionCamera,
+        environment: &Ambient,
+        draws: &[RefractionDraw],
+    ) {
+        let projection = camera.projection;
+        let uniform = CameraUniform {
+            view_proj: (projection * camera.view).into(),
+            view: camera.view.into(),
+            eye: camera.eye.to_homogeneous().into(),
+            uv_scale: [projection.x.x * 0.5, projection.y.y * 0.5],
+            _padding: [0.0; 2],
+            environment: *environment,
+        };
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let mut data = Vec::new();
+        self.meshes = 0;
+        for (_, refractive) in draws.iter().flat_map(|draw| draw.meshes()) {
+            if self.meshes as usize == Self::MAX_MESHES {
+                log::warn!(
+                    "Only the first {} refractive meshes are drawn",
+                    Self::MAX_MESHES
+                );
+                break;
+            }
+            data.resize(self.meshes as usize * self.material_stride as usize, 0);
+            let uniform = MaterialUniform {
+                ior: refractive.ior,
+                thickness: refractive.thickness,
+                _padding: [0.0; 2],
+            };
+            data.extend_from_slice(bytemuck::bytes_of(&uniform));
+            self.meshes += 1;
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.material_buffer, 0, &data);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes == 0
+    }
+
+    /// Adds the commands [`Self::draw`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats, draws: &[RefractionDraw]) {
+        if self.is_empty() {
+            return;
+        }
+        // The scene, then the glass.
+        stats.pipeline_sets += 2;
+        stats.bind_group_sets += 4;
+        stats.draw_calls += 1;
+        let meshes = draws
+            .iter()
+            .flat_map(|draw| draw.meshes().map(move |(mesh, _)| (draw, mesh)))
+            .take(self.meshes as usize);
+        for (draw, mesh) in meshes {
+            let instances = draw.instances.len() as u32;
+            stats.bind_group_sets += 1;
+            stats.draw_calls += 1;
+            stats.instances += instances;
+            stats.triangles += (mesh.num_elements / 3) as u64 * instances as u64;
+        }
+    }
+
+    /// Draws the opaque scene from [`Self::scene_view`] over the whole
+    /// target, then the refractive meshes of `draws`, as prepared. Call in a
+    /// pass with the target's color and the scene's depth attached.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        draws: &[RefractionDraw<'a>],
+    ) {
+        if self.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.scene_pipeline);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.scene.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.scene.bind_group, &[]);
+        let mut index = 0;
+        for draw in draws {
+            render_pass.set_vertex_buffer(1, draw.instance_buffer.slice(..));
+            for (mesh, _) in draw.meshes() {
+                if index == self.meshes {
+                    return;
+                }
+                render_pass.set_bind_group(
+                    2,
+                    &self.material_bind_group,
+                    &[index * self.material_stride],
+                );
+                model::draw_geometry(render_pass, mesh, draw.instances.clone());
+                index += 1;
+            }
+        }
+    }
+}
+
+impl SceneTarget {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("Refraction Scene"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("refraction_scene_bind_group"),
+        });
+        Ok(Self {
+            texture,
+            view,
+            bind_group,
+            _memory: memory,
+        })
+    }
+}
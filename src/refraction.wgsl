@@ -0,0 +1,106 @@
+// Glass: the opaque scene behind a surface, sampled from where it was drawn
+// at an offset along the view space normal, blended by Fresnel with the sky and
+// ground the surface reflects.
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    eye: vec4<f32>,
+    // The projection's x and y scale, halved, to turn view space offsets
+    // into UVs.
+    uv_scale: vec2<f32>,
+    _padding: vec2<f32>,
+    sky_color: vec3<f32>,
+    intensity: f32,
+    ground_color: vec3<f32>,
+    _padding2: u32,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(1) @binding(1)
+var s_scene: sampler;
+
+struct Refractive {
+    ior: f32,
+    thickness: f32,
+    _padding: vec2<f32>,
+}
+// At a dynamic offset, one per mesh drawn.
+@group(2) @binding(0)
+var<uniform> material: Refractive;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) view_depth: f32,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    let rotation = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.world_normal = rotation * model.normal;
+    out.view_depth = -(camera.view * world_position).z;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = normalize(in.world_normal);
+    let v = normalize(camera.eye.xyz - in.world_position);
+
+    // Denser and thicker glass bends the view further. The offset shrinks
+    // with distance as the projection would shrink it.
+    let view_normal = (camera.view * vec4<f32>(n, 0.0)).xyz;
+    let bend = (1.0 - 1.0 / material.ior) * material.thickness / max(in.view_depth, 1e-3);
+    let offset = -view_normal.xy * camera.uv_scale * bend;
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let uv = in.clip_position.xy / size + vec2<f32>(offset.x, -offset.y);
+    // Kept a half texel inside the scene, so offsets near the edges repeat
+    // the border rather than read off screen.
+    let half_texel = 0.5 / size;
+    let refracted = textureSampleLevel(t_scene, s_scene, clamp(uv, half_texel, 1.0 - half_texel), 0.0).rgb;
+
+    let r = reflect(-v, n);
+    let reflected = mix(camera.ground_color, camera.sky_color, r.y * 0.5 + 0.5) * camera.intensity;
+    let r0 = (material.ior - 1.0) / (material.ior + 1.0);
+    let f0 = r0 * r0;
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(n, v), 0.0), 5.0);
+    return vec4<f32>(mix(refracted, reflected, fresnel), 1.0);
+}
+
+// The opaque scene, drawn onto the target under the glass.
+@vertex
+fn vs_scene(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_scene(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(t_scene, vec2<i32>(position.xy), 0);
+}
@@ -0,0 +1,362 @@
+use crate::{gpu, light::Ambient};
+
+/// A viewport in physical pixels, with the depth range it maps to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    /// A viewport using the full `0.0..1.0` depth range.
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    /// The whole of a render target.
+    pub fn full(target_size: (u32, u32)) -> Self {
+        Self::new(0.0, 0.0, target_size.0 as f32, target_size.1 as f32)
+    }
+
+    /// Converts a rect in logical points, e.g. from egui, to physical pixels.
+    pub fn from_logical(x: f32, y: f32, width: f32, height: f32, scale_factor: f32) -> Self {
+        Self::new(
+            x * scale_factor,
+            y * scale_factor,
+            width * scale_factor,
+            height * scale_factor,
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.width > 0.0 && self.height > 0.0)
+    }
+
+    /// The pixels covered by the viewport, for use as a scissor rect.
+    pub fn scissor(&self) -> ScissorRect {
+        let x = self.x.max(0.0).floor();
+        let y = self.y.max(0.0).floor();
+        let right = (self.x + self.width).max(0.0).ceil();
+        let bottom = (self.y + self.height).max(0.0).ceil();
+        ScissorRect {
+            x: x as u32,
+            y: y as u32,
+            width: (right - x).max(0.0) as u32,
+            height: (bottom - y).max(0.0) as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    pub fn full(target_size: (u32, u32)) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: target_size.0,
+            height: target_size.1,
+        }
+    }
+
+    /// Clips the rect to a render target. wgpu rejects scissor rects that
+    /// extend past the target.
+    pub fn clamped(&self, target_size: (u32, u32)) -> Self {
+        let x = self.x.min(target_size.0);
+        let y = self.y.min(target_size.1);
+        Self {
+            x,
+            y,
+            width: self.width.min(target_size.0 - x),
+            height: self.height.min(target_size.1 - y),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// A sub-rectangle of the render target drawn with its own camera, e.g. a
+/// preview inside a UI panel.
+pub struct DrawRegion<'a> {
+    pub viewport: Viewport,
+    pub scissor: ScissorRect,
+    pub camera_bind_group: &'a wgpu::BindGroup,
+    /// The dynamic offset into `camera_bind_group` if it was created from
+    /// [`RegionCameras`], `None` for a regular camera bind group.
+    pub camera_offset: Option<u32>,
+}
+
+impl<'a> DrawRegion<'a> {
+    /// A region scissored to its viewport.
+    pub fn new(viewport: Viewport, camera_bind_group: &'a wgpu::BindGroup) -> Self {
+        Self {
+            viewport,
+            scissor: viewport.scissor(),
+            camera_bind_group,
+            camera_offset: None,
+        }
+    }
+
+    /// A region using one of the matrices in `cameras`.
+    pub fn with_cameras(viewport: Viewport, cameras: &'a RegionCameras, index: usize) -> Self {
+        Self {
+            viewport,
+            scissor: viewport.scissor(),
+            camera_bind_group: &cameras.bind_group,
+            camera_offset: Some(cameras.offset(index)),
+        }
+    }
+
+    /// Sets the region's viewport and scissor on `render_pass`, returning
+    /// false if nothing inside the target would be drawn.
+    pub fn apply(&self, render_pass: &mut wgpu::RenderPass, target_size: (u32, u32)) -> bool {
+        let scissor = self.scissor.clamped(target_size);
+        if self.viewport.is_empty() || scissor.is_empty() {
+            return false;
+        }
+        let v = &self.viewport;
+        render_pass.set_viewport(v.x, v.y, v.width, v.height, v.min_depth, v.max_depth);
+        render_pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+        true
+    }
+}
+
+/// Restores the viewport and scissor to cover the whole target.
+pub fn reset_region(render_pass: &mut wgpu::RenderPass, target_size: (u32, u32)) {
+    let v = Viewport::full(target_size);
+    render_pass.set_viewport(v.x, v.y, v.width, v.height, v.min_depth, v.max_depth);
+    render_pass.set_scissor_rect(0, 0, target_size.0, target_size.1);
+}
+
+/// One view projection matrix per region in a single uniform buffer, bound
+/// with a dynamic offset so many regions can be drawn in one pass. Like the
+/// regular camera layout, binding 1 is the scene's [`Ambient`], which every
+/// region shares and [`RegionCameras::write_ambient`] sets. The matrix
+/// binding uses a dynamic offset though, so pipelines drawing regions must
+/// be created with [`RegionCameras::bind_group_layout`].
+pub struct RegionCameras {
+    buffer: wgpu::Buffer,
+    ambient_buffer: wgpu::Buffer,
+    _memory: [gpu::MemoryToken; 2],
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    stride: u64,
+    capacity: usize,
+}
+
+impl RegionCameras {
+    const MATRIX_SIZE: u64 = std::mem::size_of::<[[f32; 4]; 4]>() as u64;
+
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Result<Self, gpu::AllocError> {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = wgpu::util::align_to(Self::MATRIX_SIZE, alignment);
+        let capacity = capacity.max(1);
+        let (buffer, buffer_memory) = gpu::Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Region Camera Buffer"),
+                contents: &vec![0u8; stride as usize * capacity],
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+            gpu::MemoryCategory::Uniform,
+        )?;
+        let (ambient_buffer, ambient_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Region Ambient Buffer"),
+                    contents: bytemuck::cast_slice(&[Ambient::default()]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(Self::MATRIX_SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("region_camera_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(Self::MATRIX_SIZE),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: ambient_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("region_camera_bind_group"),
+        });
+        Ok(Self {
+            buffer,
+            ambient_buffer,
+            _memory: [buffer_memory, ambient_buffer_memory],
+            bind_group_layout,
+            bind_group,
+            stride,
+            capacity,
+        })
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The dynamic offset of the matrix at `index`.
+    pub fn offset(&self, index: usize) -> u32 {
+        assert!(index < self.capacity, "region camera index out of range");
+        (index as u64 * self.stride) as u32
+    }
+
+    /// Sets the ambient light every region is drawn with.
+    pub fn write_ambient(&self, queue: &wgpu::Queue, ambient: Ambient) {
+        queue.write_buffer(&self.ambient_buffer, 0, bytemuck::cast_slice(&[ambient]));
+    }
+
+    /// Uploads the view projection matrices, starting at the first region.
+    pub fn write(&self, queue: &wgpu::Queue, view_projs: &[[[f32; 4]; 4]]) {
+        if view_projs.len() > self.capacity {
+            log::warn!(
+                "Only {} of {} region cameras fit",
+                self.capacity,
+                view_projs.len()
+            );
+        }
+        for (i, view_proj) in view_projs.iter().take(self.capacity).enumerate() {
+            queue.write_buffer(
+                &self.buffer,
+                i as u64 * self.stride,
+                bytemuck::cast_slice(view_proj),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logical_viewports_scale_to_pixels_with_the_full_depth_range() {
+        let viewport = Viewport::from_logical(10.0, 20.0, 100.0, 50.0, 1.5);
+        assert_eq!(viewport, Viewport::new(15.0, 30.0, 150.0, 75.0));
+        assert_eq!((viewport.min_depth, viewport.max_depth), (0.0, 1.0));
+        assert_eq!(
+            Viewport::full((640, 480)),
+            Viewport::new(0.0, 0.0, 640.0, 480.0)
+        );
+    }
+
+    #[test]
+    fn scissors_cover_every_pixel_the_viewport_touches() {
+        let scissor = Viewport::new(10.5, 20.25, 100.0, 50.5).scissor();
+        assert_eq!(
+            scissor,
+            ScissorRect {
+                x: 10,
+                y: 20,
+                width: 101,
+                height: 51,
+            }
+        );
+        // Parts left of or above the target are cut off.
+        let scissor = Viewport::new(-30.0, -10.0, 100.0, 50.0).scissor();
+        assert_eq!(
+            scissor,
+            ScissorRect {
+                x: 0,
+                y: 0,
+                width: 70,
+                height: 40,
+            }
+        );
+    }
+
+    #[test]
+    fn scissors_are_clamped_to_the_target() {
+        let target = (100, 80);
+        let rect = ScissorRect {
+            x: 90,
+            y: 70,
+            width: 40,
+            height: 40,
+        };
+        assert_eq!(
+            rect.clamped(target),
+            ScissorRect {
+                x: 90,
+                y: 70,
+                width: 10,
+                height: 10,
+            }
+        );
+        let outside = ScissorRect {
+            x: 200,
+            y: 10,
+            width: 5,
+            height: 5,
+        };
+        assert!(outside.clamped(target).is_empty());
+        assert_eq!(
+            ScissorRect::full(target).clamped(target),
+            ScissorRect::full(target)
+        );
+    }
+
+    #[test]
+    fn regions_without_area_are_empty() {
+        assert!(Viewport::new(0.0, 0.0, 0.0, 10.0).is_empty());
+        assert!(Viewport::new(0.0, 0.0, 10.0, -1.0).is_empty());
+        assert!(Viewport::new(0.0, 0.0, f32::NAN, 10.0).is_empty());
+        assert!(!Viewport::new(5.0, 5.0, 0.5, 0.5).is_empty());
+        // A sliver still covers the pixel it's in.
+        assert_eq!(Viewport::new(0.0, 0.0, 0.4, 0.4).scissor().width, 1);
+    }
+}
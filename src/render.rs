@@ -0,0 +1,581 @@
+//! A description of the passes a frame records and the targets they use,
+//! for debugging how passes are wired. The renderer records a fixed list of
+//! passes, so a [`Graph`] doesn't schedule anything: it says what
+//! [`State::render`](crate::State::render) will do with the current
+//! settings, as Graphviz with [`Graph::dump_dot`] or as text with
+//! [`Graph::explain`].
+//!
+//! Transient targets, which only live within a frame, can share memory with
+//! another of the same format and size when their uses don't overlap.
+//! [`Graph::aliases`] works out which would.
+
+use std::fmt::Write;
+
+use crate::compose::{FrameHooks, PassSlot};
+
+/// An index into [`Graph::resources`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(usize);
+
+/// An index into [`Graph::passes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PassIndex(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub name: String,
+    pub format: wgpu::TextureFormat,
+    pub size: (u32, u32),
+    /// Whether it only lives within the frame, rather than being presented
+    /// or kept for the next.
+    pub transient: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Load {
+    Clear,
+    Load,
+}
+
+impl Load {
+    pub fn from_op<V>(op: wgpu::LoadOp<V>) -> Self {
+        match op {
+            wgpu::LoadOp::Clear(_) => Load::Clear,
+            wgpu::LoadOp::Load => Load::Load,
+        }
+    }
+}
+
+/// A target a pass renders into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attachment {
+    pub resource: ResourceId,
+    pub load: Load,
+    pub store: bool,
+}
+
+impl Attachment {
+    /// Loaded and stored, as passes drawing over a target are.
+    pub fn load(resource: ResourceId) -> Self {
+        Self {
+            resource,
+            load: Load::Load,
+            store: true,
+        }
+    }
+
+    pub fn clear(resource: ResourceId) -> Self {
+        Self {
+            resource,
+            load: Load::Clear,
+            store: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pass {
+    pub name: String,
+    /// Resources sampled or copied from, rather than rendered into.
+    pub reads: Vec<ResourceId>,
+    pub attachments: Vec<Attachment>,
+    /// Disabled passes are shown but not recorded, e.g. the sprite pass
+    /// without sprites.
+    pub enabled: bool,
+}
+
+impl Pass {
+    /// Whether the pass needs what `resource` held before it, either by
+    /// reading it or by loading it as an attachment.
+    pub fn reads(&self, resource: ResourceId) -> bool {
+        self.reads.contains(&resource)
+            || self
+                .attachments
+                .iter()
+                .any(|a| a.resource == resource && a.load == Load::Load)
+    }
+
+    pub fn writes(&self, resource: ResourceId) -> bool {
+        self.attachments
+            .iter()
+            .any(|a| a.resource == resource && a.store)
+    }
+
+    fn uses(&self, resource: ResourceId) -> bool {
+        self.reads.contains(&resource) || self.attachments.iter().any(|a| a.resource == resource)
+    }
+}
+
+/// A resource a pass needs from an earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: PassIndex,
+    pub to: PassIndex,
+    pub resource: ResourceId,
+}
+
+/// The passes of a frame in recording order, and the targets they use.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_resource(
+        &mut self,
+        name: &str,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+        transient: bool,
+    ) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_string(),
+            format,
+            size,
+            transient,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Adds an enabled pass, recorded after those added before it.
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[ResourceId],
+        attachments: &[Attachment],
+    ) -> PassIndex {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            attachments: attachments.to_vec(),
+            enabled: true,
+        });
+        PassIndex(self.passes.len() - 1)
+    }
+
+    pub fn set_enabled(&mut self, pass: PassIndex, enabled: bool) {
+        self.passes[pass.0].enabled = enabled;
+    }
+
+    /// Enables or disables every pass named `name`, returning whether there
+    /// were any.
+    pub fn set_enabled_by_name(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for pass in self.passes.iter_mut().filter(|p| p.name == name) {
+            pass.enabled = enabled;
+            found = true;
+        }
+        found
+    }
+
+    pub fn resource(&self, id: ResourceId) -> &Resource {
+        &self.resources[id.0]
+    }
+
+    pub fn resources(&self) -> impl Iterator<Item = (ResourceId, &Resource)> {
+        self.resources
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (ResourceId(i), r))
+    }
+
+    pub fn pass(&self, index: PassIndex) -> &Pass {
+        &self.passes[index.0]
+    }
+
+    /// Every pass, enabled or not, in recording order.
+    pub fn passes(&self) -> impl Iterator<Item = (PassIndex, &Pass)> {
+        self.passes
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (PassIndex(i), p))
+    }
+
+    /// The enabled passes in the order they run.
+    pub fn order(&self) -> Vec<PassIndex> {
+        self.passes()
+            .filter(|(_, p)| p.enabled)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// For each enabled pass reading a resource, the last enabled pass
+    /// before it that wrote it.
+    pub fn edges(&self) -> Vec<Edge> {
+        let mut last_writer = vec![None; self.resources.len()];
+        let mut edges = Vec::new();
+        for to in self.order() {
+            let pass = self.pass(to);
+            for (resource, writer) in last_writer.iter().enumerate() {
+                if let Some(from) = *writer {
+                    if pass.reads(ResourceId(resource)) {
+                        edges.push(Edge {
+                            from,
+                            to,
+                            resource: ResourceId(resource),
+                        });
+                    }
+                }
+            }
+            for (resource, writer) in last_writer.iter_mut().enumerate() {
+                if pass.writes(ResourceId(resource)) {
+                    *writer = Some(to);
+                }
+            }
+        }
+        edges
+    }
+
+    /// Resources an enabled pass reads before any pass wrote them this
+    /// frame. Only a problem for transient ones, which start out undefined.
+    pub fn undefined_reads(&self) -> Vec<(PassIndex, ResourceId)> {
+        let mut written = vec![false; self.resources.len()];
+        let mut undefined = Vec::new();
+        for index in self.order() {
+            let pass = self.pass(index);
+            for (id, resource) in self.resources() {
+                if resource.transient && !written[id.0] && pass.reads(id) {
+                    undefined.push((index, id));
+                }
+            }
+            for (id, _) in self.resources() {
+                written[id.0] |= pass.writes(id);
+            }
+        }
+        undefined
+    }
+
+    /// The positions in [`Graph::order`] of the first and last pass using
+    /// each resource, or `None` for those no enabled pass uses.
+    fn lifetimes(&self) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None; self.resources.len()];
+        for (position, index) in self.order().into_iter().enumerate() {
+            for (id, _) in self.resources() {
+                if self.pass(index).uses(id) {
+                    let lifetime = lifetimes[id.0].get_or_insert((position, position));
+                    lifetime.1 = position;
+                }
+            }
+        }
+        lifetimes
+    }
+
+    /// For each resource, the earlier transient resource whose memory it
+    /// could reuse. Resources are taken in order of first use, and each
+    /// reuses the first one of the same format and size whose last use is
+    /// before its first. Persistent and unused resources never alias.
+    pub fn aliases(&self) -> Vec<Option<ResourceId>> {
+        let lifetimes = self.lifetimes();
+        let mut candidates = self
+            .resources()
+            .filter(|(_, r)| r.transient)
+            .filter_map(|(id, _)| Some((lifetimes[id.0]?, id)))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|&((first, _), id)| (first, id));
+
+        let mut aliases = vec![None; self.resources.len()];
+        // Each slot is a piece of memory: the resource that owns it and the
+        // last use of whatever holds it now.
+        let mut slots: Vec<(ResourceId, usize)> = Vec::new();
+        for ((first, last), id) in candidates {
+            let resource = self.resource(id);
+            let free = slots.iter_mut().find(|(owner, end)| {
+                let owner = self.resource(*owner);
+                *end < first && owner.format == resource.format && owner.size == resource.size
+            });
+            match free {
+                Some((owner, end)) => {
+                    aliases[id.0] = Some(*owner);
+                    *end = last;
+                }
+                None => slots.push((id, last)),
+            }
+        }
+        aliases
+    }
+
+    /// The graph in Graphviz's dot language. Passes are boxes numbered in
+    /// the order they run, resources are ellipses with their format and
+    /// size, and edges are labeled with load and store ops. Disabled
+    /// passes are dashed and grey.
+    pub fn dump_dot(&self) -> String {
+        let mut out = String::new();
+        let order = self.order();
+        writeln!(out, "digraph frame {{").unwrap();
+        writeln!(out, "    rankdir=LR;").unwrap();
+        for (index, pass) in self.passes() {
+            let label = match order.iter().position(|&i| i == index) {
+                Some(position) => format!("{}. {}", position + 1, pass.name),
+                None => format!("{} (disabled)", pass.name),
+            };
+            let style = if pass.enabled {
+                ""
+            } else {
+                ", style=dashed, color=grey, fontcolor=grey"
+            };
+            writeln!(
+                out,
+                "    pass{} [shape=box, label={:?}{}];",
+                index.0, label, style
+            )
+            .unwrap();
+        }
+        let aliases = self.aliases();
+        for (id, resource) in self.resources() {
+            let mut label = format!(
+                "{}\\n{:?} {}x{}",
+                resource.name, resource.format, resource.size.0, resource.size.1
+            );
+            if resource.transient {
+                label.push_str("\\ntransient");
+            }
+            if let Some(owner) = aliases[id.0] {
+                write!(label, "\\naliases {}", self.resource(owner).name).unwrap();
+            }
+            writeln!(out, "    res{} [shape=ellipse, label=\"{}\"];", id.0, label).unwrap();
+        }
+        for (index, pass) in self.passes() {
+            let style = if pass.enabled { "" } else { ", style=dashed" };
+            for read in &pass.reads {
+                writeln!(
+                    out,
+                    "    res{} -> pass{} [label=\"read\"{}];",
+                    read.0, index.0, style
+                )
+                .unwrap();
+            }
+            for attachment in &pass.attachments {
+                let ops = format!(
+                    "{:?}/{}",
+                    attachment.load,
+                    if attachment.store { "Store" } else { "Discard" }
+                );
+                if attachment.load == Load::Load {
+                    writeln!(
+                        out,
+                        "    res{} -> pass{} [label=\"{}\"{}];",
+                        attachment.resource.0, index.0, ops, style
+                    )
+                    .unwrap();
+                }
+                if attachment.store {
+                    writeln!(
+                        out,
+                        "    pass{} -> res{} [label=\"{}\"{}];",
+                        index.0, attachment.resource.0, ops, style
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        // Keeps the passes in order from left to right.
+        for pair in order.windows(2) {
+            writeln!(
+                out,
+                "    pass{} -> pass{} [style=dotted, arrowhead=none];",
+                pair[0].0, pair[1].0
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// The passes in the order they run with what each loads and stores,
+    /// the passes that are disabled, and which transient resources would
+    /// share memory.
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Execution order:").unwrap();
+        for (position, index) in self.order().into_iter().enumerate() {
+            let pass = self.pass(index);
+            let mut uses = pass
+                .reads
+                .iter()
+                .map(|r| format!("reads {}", self.resource(*r).name))
+                .collect::<Vec<_>>();
+            uses.extend(pass.attachments.iter().map(|a| {
+                format!(
+                    "{:?}/{} {}",
+                    a.load,
+                    if a.store { "Store" } else { "Discard" },
+                    self.resource(a.resource).name
+                )
+            }));
+            writeln!(
+                out,
+                "  {}. {}: {}",
+                position + 1,
+                pass.name,
+                uses.join(", ")
+            )
+            .unwrap();
+        }
+        let disabled = self
+            .passes()
+            .filter(|(_, p)| !p.enabled)
+            .map(|(_, p)| p.name.as_str())
+            .collect::<Vec<_>>();
+        if !disabled.is_empty() {
+            writeln!(out, "Disabled: {}", disabled.join(", ")).unwrap();
+        }
+
+        writeln!(out, "Transient aliasing:").unwrap();
+        let aliases = self.aliases();
+        let mut any = false;
+        for (id, resource) in self.resources().filter(|(_, r)| r.transient) {
+            any = true;
+            match aliases[id.0] {
+                Some(owner) => writeln!(
+                    out,
+                    "  {} reuses the memory of {}",
+                    resource.name,
+                    self.resource(owner).name
+                ),
+                None => writeln!(out, "  {} gets its own memory", resource.name),
+            }
+            .unwrap();
+        }
+        if !any {
+            writeln!(out, "  no transient resources").unwrap();
+        }
+
+        for (index, resource) in self.undefined_reads() {
+            writeln!(
+                out,
+                "Warning: {} reads {} before anything writes it",
+                self.pass(index).name,
+                self.resource(resource).name
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Adds a pass for the hooks at `slot`, which may draw over both targets,
+/// disabled when there are none.
+pub(crate) fn add_hooks(
+    graph: &mut Graph,
+    hooks: &FrameHooks,
+    slot: PassSlot,
+    color: ResourceId,
+    depth: ResourceId,
+) {
+    let count = hooks.pass_count(slot);
+    let pass = graph.add_pass(
+        &format!("{:?} hooks ({})", slot, count),
+        &[],
+        &[Attachment::load(color), Attachment::load(depth)],
+    );
+    graph.set_enabled(pass, count > 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(graph: &Graph) -> Vec<String> {
+        graph
+            .order()
+            .into_iter()
+            .map(|i| graph.pass(i).name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn post_chain_is_ordered_dumped_and_aliased() {
+        use wgpu::TextureFormat::{Bgra8UnormSrgb, Depth32Float, Rgba16Float};
+
+        let mut graph = Graph::new();
+        let size = (256, 256);
+        let surface = graph.add_resource("surface", Bgra8UnormSrgb, size, false);
+        let shadow = graph.add_resource("shadow", Depth32Float, (1024, 1024), true);
+        let depth = graph.add_resource("depth", Depth32Float, size, true);
+        let hdr = graph.add_resource("hdr", Rgba16Float, size, true);
+        let bright = graph.add_resource("bright", Rgba16Float, size, true);
+        let blur = graph.add_resource("blur", Rgba16Float, size, true);
+        let blur2 = graph.add_resource("blur2", Rgba16Float, size, true);
+        graph.add_pass("Shadows", &[], &[Attachment::clear(shadow)]);
+        graph.add_pass(
+            "Scene",
+            &[shadow],
+            &[Attachment::clear(hdr), Attachment::clear(depth)],
+        );
+        graph.add_pass("Bright", &[hdr], &[Attachment::clear(bright)]);
+        graph.add_pass("Blur", &[bright], &[Attachment::clear(blur)]);
+        graph.add_pass("Blur2", &[blur], &[Attachment::clear(blur2)]);
+        graph.add_pass("Tonemap", &[hdr, blur2], &[Attachment::clear(surface)]);
+        let ui = graph.add_pass("Ui", &[], &[Attachment::load(surface)]);
+        graph.set_enabled(ui, false);
+        assert_eq!(
+            names(&graph),
+            ["Shadows", "Scene", "Bright", "Blur", "Blur2", "Tonemap"]
+        );
+
+        let dot = graph.dump_dot();
+        for line in [
+            "pass0 -> res1 [label=\"Clear/Store\"];",
+            "res1 -> pass1 [label=\"read\"];",
+            "pass5 -> res0 [label=\"Clear/Store\"];",
+            "res0 -> pass6 [label=\"Load/Store\", style=dashed];",
+            "pass4 -> pass5 [style=dotted, arrowhead=none];",
+            "pass6 [shape=box, label=\"Ui (disabled)\", style=dashed",
+            "pass5 [shape=box, label=\"6. Tonemap\"];",
+            "Rgba16Float 256x256\\ntransient\\naliases bright",
+        ] {
+            assert!(dot.contains(line), "the dump lacks {:?}:\n{}", line, dot);
+        }
+        assert!(!dot.contains("pass5 -> pass6"), "{}", dot);
+
+        // Blur2 starts after Bright's last use, and nothing else of its
+        // format and size is free in time.
+        let expected = [None, None, None, None, None, None, Some(bright)];
+        assert_eq!(graph.aliases(), expected);
+        let explanation = graph.explain();
+        for line in [
+            "  6. Tonemap: reads hdr, reads blur2, Clear/Store surface",
+            "Disabled: Ui",
+            "  blur2 reuses the memory of bright",
+            "  shadow gets its own memory",
+        ] {
+            assert!(
+                explanation.contains(line),
+                "the explanation lacks {:?}:\n{}",
+                line,
+                explanation
+            );
+        }
+
+        // Toggled on, the UI pass runs last and depends on the tonemap.
+        graph.set_enabled_by_name("Ui", true);
+        assert_eq!(names(&graph).last().map(String::as_str), Some("Ui"));
+        let edge = Edge {
+            from: graph.order()[5],
+            to: ui,
+            resource: surface,
+        };
+        assert!(graph.edges().contains(&edge), "{:?}", graph.edges());
+        assert!(graph.undefined_reads().is_empty());
+    }
+
+    #[test]
+    fn hook_slots_without_callbacks_are_disabled() {
+        use wgpu::TextureFormat::{Bgra8UnormSrgb, Depth32Float};
+
+        let mut hooks = FrameHooks::default();
+        hooks.add_pass(PassSlot::AfterOpaque, |_| {});
+        let mut graph = Graph::new();
+        let color = graph.add_resource("color", Bgra8UnormSrgb, (256, 256), false);
+        let depth = graph.add_resource("depth", Depth32Float, (256, 256), false);
+        for slot in [PassSlot::BeforeScene, PassSlot::AfterOpaque] {
+            add_hooks(&mut graph, &hooks, slot, color, depth);
+        }
+        assert_eq!(names(&graph), ["AfterOpaque hooks (1)"]);
+    }
+}
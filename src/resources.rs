@@ -1,14 +1,28 @@
-use std::io::{BufReader, Cursor};
+use std::{
+    io::{BufRead, BufReader, Cursor},
+    sync::Arc,
+};
 
 use anyhow::Context;
 use cfg_if::cfg_if;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    model::{self, Mesh},
-    texture,
+    assets, axes,
+    bounds::Aabb,
+    builtin, gpu, mipmap, model,
+    model_data::{self, MaterialRecord, MeshRecord, ModelData},
+    obj, ply,
+    refraction::Refractive,
+    split, stats, texture,
+    triplanar::Triplanar,
+    units, uv, variant,
 };
 
+/// Where the `asset-pipeline` bin is expected to write its output, relative
+/// to the resource directory.
+pub const PROCESSED_DIR: &str = "processed";
+
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
     let window = web_sys::window().unwrap();
@@ -21,18 +35,68 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+static RESOURCE_DIR: std::sync::RwLock<Option<std::path::PathBuf>> = std::sync::RwLock::new(None);
+
+/// Reads resources from `dir` instead of the `res` folder copied by the
+/// build, e.g. for tools working on a source asset tree. `None` goes back to
+/// `res`. Builtin files are still found either way.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_resource_dir(dir: Option<std::path::PathBuf>) {
+    *RESOURCE_DIR.write().unwrap() = dir;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn resource_path(file_name: &str) -> std::path::PathBuf {
+    match &*RESOURCE_DIR.read().unwrap() {
+        Some(dir) => dir.join(file_name),
+        None => std::path::Path::new(env!("OUT_DIR"))
+            .join("res")
+            .join(file_name),
+    }
+}
+
+/// Resolves `name` relative to the directory containing `file_name`, the way
+/// OBJ files refer to their material libraries and textures.
+pub(crate) fn sibling_path(file_name: &str, name: &str) -> String {
+    match file_name.rfind(['/', '\\']) {
+        Some(i) => format!("{}/{}", &file_name[..i], name),
+        None => name.to_string(),
+    }
+}
+
+/// The builtin file named `file_name` if `result` failed to read the
+/// user's, so that user files take precedence.
+fn or_builtin<T>(
+    result: anyhow::Result<T>,
+    file_name: &str,
+    convert: impl FnOnce(&'static [u8]) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    match (result, builtin::BuiltinSource::get(file_name)) {
+        (Err(_), Some(bytes)) => convert(bytes),
+        (result, _) => result,
+    }
+}
+
+/// Reads `file_name` from `res`, or from the [`builtin`] files if it isn't
+/// there.
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+    or_builtin(read_string(file_name).await, file_name, |bytes| {
+        Ok(std::str::from_utf8(bytes)?.to_string())
+    })
+}
+
+async fn read_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             let url = format_url(file_name);
             let txt = reqwest::get(url)
                 .await?
+                .error_for_status()?
                 .text()
                 .await?;
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
+            let path = resource_path(file_name);
             let txt = std::fs::read_to_string(path)?;
         }
     }
@@ -40,19 +104,26 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     Ok(txt)
 }
 
+/// Reads `file_name` from `res`, or from the [`builtin`] files if it isn't
+/// there.
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    or_builtin(read_binary(file_name).await, file_name, |bytes| {
+        Ok(bytes.to_vec())
+    })
+}
+
+async fn read_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             let url = format_url(file_name);
             let data = reqwest::get(url)
                 .await?
+                .error_for_status()?
                 .bytes()
                 .await?
                 .to_vec();
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
+            let path = resource_path(file_name);
             let data = std::fs::read(path)?;
         }
     }
@@ -60,6 +131,86 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     Ok(data)
 }
 
+/// Files at least this large are memory mapped rather than read on native.
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// The contents of a resource, either read into memory or, for large files
+/// on native, memory mapped. The mapping is released on drop.
+pub enum ResourceBytes {
+    Owned(Vec<u8>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+}
+
+impl ResourceBytes {
+    /// A reader over the bytes, for parsers such as tobj that want one.
+    pub fn reader(&self) -> Cursor<&[u8]> {
+        Cursor::new(&self[..])
+    }
+
+    /// Reads `path`, or maps it if it is at least `mmap_threshold` bytes.
+    /// [`load_resource`] opens resources this way with [`MMAP_THRESHOLD`],
+    /// and its caveats about truncation apply.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: &std::path::Path, mmap_threshold: u64) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < mmap_threshold {
+            return Ok(ResourceBytes::Owned(std::fs::read(path)?));
+        }
+        // SAFETY: the mapping is read only and limited to the length at
+        // open. See the doc comment of `load_resource` for the truncation
+        // caveat.
+        let map = unsafe { memmap2::MmapOptions::new().len(len as usize).map(&file)? };
+        log::info!("Memory mapped {} ({} bytes)", path.display(), len);
+        Ok(ResourceBytes::Mapped(map))
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            ResourceBytes::Owned(data) => data,
+            #[cfg(not(target_arch = "wasm32"))]
+            ResourceBytes::Mapped(map) => map.to_vec(),
+        }
+    }
+}
+
+impl std::ops::Deref for ResourceBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ResourceBytes::Owned(data) => data,
+            #[cfg(not(target_arch = "wasm32"))]
+            ResourceBytes::Mapped(map) => map,
+        }
+    }
+}
+
+/// Loads a resource without copying it if it is at least [`MMAP_THRESHOLD`]
+/// bytes, which keeps peak memory down when parsing huge assets. Keep the
+/// result only until parsing is done.
+///
+/// The length is taken when the file is opened and only that much is mapped,
+/// so a file growing meanwhile is harmless. Truncating it while it is mapped
+/// can still crash the process on access, which is why only files in the
+/// resource directory, not ones other programs are writing, should be loaded.
+pub async fn load_resource(file_name: &str) -> anyhow::Result<ResourceBytes> {
+    or_builtin(read_resource(file_name).await, file_name, |bytes| {
+        Ok(ResourceBytes::Owned(bytes.to_vec()))
+    })
+}
+
+async fn read_resource(file_name: &str) -> anyhow::Result<ResourceBytes> {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            Ok(ResourceBytes::Owned(load_binary(file_name).await?))
+        } else {
+            Ok(ResourceBytes::open(&resource_path(file_name), MMAP_THRESHOLD)?)
+        }
+    }
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
@@ -69,33 +220,511 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name)
 }
 
+/// Decodes an image into a window icon.
+pub async fn load_icon(file_name: &str) -> anyhow::Result<winit::window::Icon> {
+    let data = load_binary(file_name).await?;
+    let rgba = image::load_from_memory(&data)?.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    Ok(winit::window::Icon::from_rgba(
+        rgba.into_raw(),
+        width,
+        height,
+    )?)
+}
+
+/// Options for how the loaders turn files into GPU resources.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub vertex_precision: model::VertexPrecision,
+    /// Split meshes whose buffers would exceed the device's `max_buffer_size`
+    /// into several meshes. When false, loading them fails with a
+    /// [`split::TooLarge`] error instead.
+    pub split_large_meshes: bool,
+    /// The axis convention the file was authored in. Geometry is converted
+    /// to [`axes::TARGET_AXES`] when loaded.
+    pub source_axes: axes::AxisConvention,
+    /// The unit the file's lengths are in, as OBJ doesn't say. Geometry is
+    /// scaled to [`units::TARGET_UNIT`] when loaded.
+    pub assumed_unit: units::Unit,
+    /// Let tobj duplicate OBJ vertices so positions, UVs and normals share
+    /// one index. Only affects files that need tobj rather than the
+    /// [`obj`] fast path. When false, each distinct combination of the three indices
+    /// becomes one vertex instead, which is usually far fewer for scans
+    /// where faces share positions but not UVs or normals.
+    pub single_index: bool,
+    /// How to make texture coordinates for meshes that have none, or `None`
+    /// to leave them all at (0, 0).
+    pub missing_uvs: Option<uv::UvProjection>,
+    /// The `scale` passed to [`model::MeshData::generate_uvs`] with
+    /// [`LoadOptions::missing_uvs`].
+    pub generated_uv_scale: f32,
+    /// Limits on the textures of materials.
+    pub texture_settings: texture::TextureSettings,
+    /// Load the `asset-pipeline` output for a model, at [`processed_path`],
+    /// when there is one, and the source file otherwise.
+    pub prefer_processed: bool,
+    /// Draw every material triplanar, e.g. for generated geometry whose
+    /// UVs stretch. `None` leaves it to each MTL material's
+    /// [`Triplanar::MTL_KEY`] statement.
+    pub triplanar: Option<Triplanar>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            vertex_precision: model::VertexPrecision::default(),
+            split_large_meshes: true,
+            source_axes: axes::AxisConvention::default(),
+            assumed_unit: units::Unit::default(),
+            single_index: true,
+            missing_uvs: None,
+            generated_uv_scale: 1.0,
+            texture_settings: texture::TextureSettings::default(),
+            prefer_processed: false,
+            triplanar: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// The factor lengths are multiplied by when loaded.
+    pub fn unit_scale(&self) -> units::UnitScale {
+        self.assumed_unit.scale_to(units::TARGET_UNIT)
+    }
+}
+
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+    load_model_with_options(file_name, device, queue, layout, &LoadOptions::default()).await
+}
+
+pub async fn load_model_with_options(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+) -> anyhow::Result<model::Model> {
+    load_obj(file_name, device, queue, layout, options, None).await
+}
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
+/// The handle of `file_name` in `models`, loading it first if it isn't
+/// there yet, so each path is only loaded and resolved once. If the GPU
+/// runs out of memory, the least recently used models are released until
+/// it fits, see [`assets::Assets::evict_for`].
+pub async fn load_model_handle(
+    models: &mut assets::Assets<model::Model>,
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<assets::ModelHandle> {
+    if let Some(handle) = models.lookup(file_name) {
+        return Ok(handle);
+    }
+    loop {
+        match load_model(file_name, device, queue, layout).await {
+            Ok(model) => return Ok(models.insert_with_path(file_name, model)),
+            Err(e) if models.evict_for(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Like [`load_model_with_options`], but the meshes are uploaded into `pool`
+/// instead of getting buffers of their own. The pool's vertex stride has to
+/// match `options.vertex_precision`.
+pub async fn load_model_pooled(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+    pool: &mut gpu::GeometryPool,
+) -> anyhow::Result<model::Model> {
+    load_obj(file_name, device, queue, layout, options, Some(pool)).await
+}
+
+/// The mesh's geometry, and the memory it holds if it has buffers of its
+/// own.
+fn create_geometry<V: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pool: Option<&mut gpu::GeometryPool>,
+    file_name: &str,
+    vertices: &[V],
+    indices: &[u32],
+) -> Result<(model::MeshGeometry, Vec<gpu::MemoryToken>), gpu::AllocError> {
+    match pool {
+        Some(pool) => Ok((
+            model::MeshGeometry::Pooled(pool.allocate(device, queue, vertices, indices)?),
+            Vec::new(),
+        )),
+        None => {
+            let allocator = gpu::Allocator::new(device);
+            let (vertex_buffer, vertex_memory) = allocator.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", file_name)),
+                    contents: bytemuck::cast_slice(vertices),
+                    // COPY_SRC so the export can read the geometry back.
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+                },
+                gpu::MemoryCategory::Vertex,
+            )?;
+            let (index_buffer, index_memory) = allocator.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", file_name)),
+                    contents: bytemuck::cast_slice(indices),
+                    usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
+                },
+                gpu::MemoryCategory::Index,
+            )?;
+            let geometry = model::MeshGeometry::Buffers {
+                vertex_buffer,
+                index_buffer,
+            };
+            Ok((geometry, vec![vertex_memory, index_memory]))
+        }
+    }
+}
+
+/// Builds one vertex for each distinct combination of position, UV and
+/// normal index that the mesh's triangles use, in order of first use.
+///
+/// With tobj's `single_index` the UV and normal index arrays are empty and
+/// the position index is used for all three. Missing data falls back as:
+/// - no UVs: every vertex gets (0, 0);
+/// - no normals: each position gets the area weighted average of the
+///   normals of the faces around it, so smooth surfaces stay smooth.
+fn obj_vertices(mesh: &tobj::Mesh) -> (Vec<model::ModelVertex>, Vec<u32>) {
+    let mut builder = VertexBuilder::new(mesh);
+    while !builder.step(mesh, usize::MAX) {}
+    builder.finish()
+}
+
+/// [`obj_vertices`] a slice at a time. Meshes without normals first sum
+/// the normals of their faces, a slice of triangles at a time, then build
+/// their corners.
+pub(crate) struct VertexBuilder {
+    /// The area weighted sums of the face normals around each position,
+    /// for meshes without normals.
+    fallback_normals: Vec<[f32; 3]>,
+    /// Corners of the triangles summed into `fallback_normals` so far.
+    summed: usize,
+    unique: std::collections::HashMap<(u32, Option<u32>, Option<u32>), u32>,
+    vertices: Vec<model::ModelVertex>,
+    indices: Vec<u32>,
+}
+
+impl VertexBuilder {
+    pub(crate) fn new(mesh: &tobj::Mesh) -> Self {
+        let (fallback_normals, summed) = if mesh.normals.is_empty() {
+            (vec![[0.0; 3]; mesh.positions.len() / 3], 0)
+        } else {
+            (Vec::new(), mesh.indices.len())
+        };
+        // There is a vertex for at least every position, UV and normal, so
+        // the map and vertices rarely grow, which would stall a step.
+        let vertices = (mesh.positions.len() / 3)
+            .max(mesh.texcoords.len() / 2)
+            .max(mesh.normals.len() / 3)
+            .min(mesh.indices.len());
+        Self {
+            fallback_normals,
+            summed,
+            unique: std::collections::HashMap::with_capacity(vertices),
+            vertices: Vec::with_capacity(vertices),
+            indices: Vec::with_capacity(mesh.indices.len()),
+        }
+    }
+
+    /// How much of `mesh` is done, from 0 to 1. Summing normals counts as
+    /// half of the work for meshes that need it.
+    pub(crate) fn progress(&self, mesh: &tobj::Mesh) -> f32 {
+        let total = mesh.indices.len().max(1) as f32;
+        if mesh.normals.is_empty() {
+            (self.summed + self.indices.len()) as f32 / (2.0 * total)
+        } else {
+            self.indices.len() as f32 / total
+        }
+    }
+
+    /// Sums the normals of or builds up to `count` more corners of `mesh`,
+    /// which must be the same for every call. Returns `true` once all are
+    /// done.
+    pub(crate) fn step(&mut self, mesh: &tobj::Mesh, count: usize) -> bool {
+        if self.summed < mesh.indices.len() {
+            self.sum_normals(mesh, count);
+            return false;
+        }
+        let start = self.indices.len();
+        let end = mesh.indices.len().min(start.saturating_add(count));
+        for k in start..end {
+            let p = mesh.indices[k];
+            let t = (!mesh.texcoords.is_empty())
+                .then(|| mesh.texcoord_indices.get(k).copied().unwrap_or(p));
+            let n = (!mesh.normals.is_empty())
+                .then(|| mesh.normal_indices.get(k).copied().unwrap_or(p));
+            let (fallback_normals, vertices) = (&self.fallback_normals, &mut self.vertices);
+            let index = *self.unique.entry((p, t, n)).or_insert_with(|| {
+                let i = p as usize * 3;
+                let position = [
+                    mesh.positions[i],
+                    mesh.positions[i + 1],
+                    mesh.positions[i + 2],
+                ];
+                let tex_coords = match t {
+                    Some(t) => {
+                        let t = t as usize * 2;
+                        [mesh.texcoords[t], mesh.texcoords[t + 1]]
+                    }
+                    None => [0.0, 0.0],
+                };
+                let normal = match n {
+                    Some(n) => {
+                        let n = n as usize * 3;
+                        [mesh.normals[n], mesh.normals[n + 1], mesh.normals[n + 2]]
+                    }
+                    None => normalize_or_up(fallback_normals[p as usize]),
+                };
+                vertices.push(model::ModelVertex {
+                    position,
+                    tex_coords,
+                    normal,
+                    occlusion: 1.0,
+                });
+                vertices.len() as u32 - 1
+            });
+            self.indices.push(index);
+        }
+        end == mesh.indices.len()
+    }
+
+    /// Adds the normals of the triangles in the next `count` corners, at
+    /// least one triangle's worth, to `fallback_normals`.
+    fn sum_normals(&mut self, mesh: &tobj::Mesh, count: usize) {
+        use cgmath::Vector3;
+
+        let positions = &mesh.positions;
+        let position = |i: u32| {
+            let i = i as usize * 3;
+            Vector3::new(positions[i], positions[i + 1], positions[i + 2])
+        };
+        let triangles = mesh.indices.len() / 3;
+        let start = self.summed / 3;
+        let end = triangles.min(start.saturating_add(count.max(3) / 3));
+        for triangle in mesh.indices[start * 3..end * 3].chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            // The cross product's length is twice the area, which weights it.
+            let normal = (position(b) - position(a)).cross(position(c) - position(a));
+            for i in [a, b, c] {
+                let sum = &mut self.fallback_normals[i as usize];
+                *sum = (Vector3::from(*sum) + normal).into();
+            }
+        }
+        // A partial triangle at the end is left out, as it has no normal.
+        self.summed = if end == triangles {
+            mesh.indices.len()
+        } else {
+            end * 3
+        };
+    }
+
+    pub(crate) fn finish(self) -> (Vec<model::ModelVertex>, Vec<u32>) {
+        (self.vertices, self.indices)
+    }
+}
+
+/// `normal` normalized, or straight up if it is zero, as it is for
+/// positions no triangle uses or whose triangles cancel out.
+fn normalize_or_up(normal: [f32; 3]) -> [f32; 3] {
+    use cgmath::{InnerSpace, Vector3};
+
+    let normal = Vector3::from(normal);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize().into()
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+/// The meshes of an OBJ with their material ids set, and the materials they
+/// refer to, before anything is on the GPU.
+pub(crate) struct ParsedObj {
+    pub(crate) models: Vec<tobj::Model>,
+    pub(crate) materials: Vec<tobj::Material>,
+}
+
+/// Loads the material libraries of a fast path parse and resolves the
+/// meshes' material names against them.
+async fn resolve_obj_materials(file_name: &str, parsed: obj::ObjData) -> anyhow::Result<ParsedObj> {
+    let (materials, material_ids) = load_material_libs(file_name, &parsed.material_libs).await?;
+    let models = parsed
+        .models
+        .into_iter()
+        .zip(parsed.material_names)
+        .map(|(mut model, name)| {
+            model.mesh.material_id = name.and_then(|name| material_ids.get(&name).copied());
+            model
+        })
+        .collect::<Vec<_>>();
+    Ok(ParsedObj { models, materials })
+}
+
+/// The materials of every library in `libs`, and the index of each by name.
+pub(crate) async fn load_material_libs(
+    file_name: &str,
+    libs: &[String],
+) -> anyhow::Result<(
+    Vec<tobj::Material>,
+    std::collections::HashMap<String, usize>,
+)> {
+    let mut materials = Vec::new();
+    let mut material_ids = std::collections::HashMap::new();
+    for lib in libs {
+        let mat_text = load_string(&sibling_path(file_name, lib)).await?;
+        add_material_lib(&mut materials, &mut material_ids, mat_text)?;
+    }
+    Ok((materials, material_ids))
+}
+
+/// Parses the material library `mat_text` onto the end of `materials`,
+/// indexing the new ones in `material_ids`.
+pub(crate) fn add_material_lib(
+    materials: &mut Vec<tobj::Material>,
+    material_ids: &mut std::collections::HashMap<String, usize>,
+    mat_text: String,
+) -> anyhow::Result<()> {
+    let (lib_materials, ids) = tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))?;
+    let offset = materials.len();
+    material_ids.extend(ids.into_iter().map(|(name, id)| (name, id + offset)));
+    materials.extend(lib_materials);
+    Ok(())
+}
+
+/// Parses with tobj, for files using something the fast path doesn't handle.
+async fn parse_obj_with_tobj(
+    file_name: &str,
+    obj_bytes: &[u8],
+    options: &LoadOptions,
+) -> anyhow::Result<ParsedObj> {
+    let (models, materials) = tobj::load_obj_buf_async(
+        &mut Cursor::new(obj_bytes),
         &tobj::LoadOptions {
             triangulate: true,
-            single_index: true,
+            single_index: options.single_index,
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
+            let mat_text = load_string(&sibling_path(file_name, &p)).await.unwrap();
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         },
     )
     .await?;
+    Ok(ParsedObj {
+        models,
+        materials: materials?,
+    })
+}
 
+/// Loads the textures of `obj_materials` and creates their bind groups.
+pub(crate) async fn load_obj_materials(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    obj_materials: Vec<tobj::Material>,
+    options: &LoadOptions,
+) -> anyhow::Result<Vec<model::Material>> {
+    let mut uploader = MaterialUploader::new(device);
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
+    for m in obj_materials {
+        let (diffuse_path, diffuse_bytes) = read_diffuse(file_name, &m.diffuse_texture).await?;
+        let decoded = DecodedMaterial {
+            diffuse: texture::SourceImage::decode(&diffuse_bytes)?,
+            diffuse_path,
+            material: m,
+        };
+        materials.push(uploader.upload(device, queue, layout, decoded, options)?);
+    }
+    uploader.finish(queue);
+    Ok(materials)
+}
+
+/// The path and bytes of the diffuse texture `diffuse_texture` of a
+/// material in `file_name`, or of a placeholder if it has none or it can't
+/// be read.
+pub(crate) async fn read_diffuse(
+    file_name: &str,
+    diffuse_texture: &str,
+) -> anyhow::Result<(String, Vec<u8>)> {
+    let diffuse_path = sibling_path(file_name, diffuse_texture);
+    let diffuse_bytes = if diffuse_texture.is_empty() {
+        None
+    } else {
+        load_binary(&diffuse_path)
+            .await
+            .map_err(|e| log::warn!("Couldn't load {}, using a placeholder: {}", diffuse_path, e))
+            .ok()
+    };
+    let diffuse_bytes = match diffuse_bytes {
+        Some(bytes) => bytes,
+        None => load_binary(builtin::MISSING_TEXTURE).await?,
+    };
+    Ok((diffuse_path, diffuse_bytes))
+}
+
+/// An OBJ material with its diffuse texture read and decoded.
+pub(crate) struct DecodedMaterial {
+    pub(crate) material: tobj::Material,
+    pub(crate) diffuse_path: String,
+    pub(crate) diffuse: texture::SourceImage,
+}
+
+/// Creates the textures and bind groups of [`DecodedMaterial`]s. All of a
+/// model's mip chains are generated in a single submission, in
+/// [`MaterialUploader::finish`].
+pub(crate) struct MaterialUploader {
+    mipmaps: mipmap::MipmapGenerator,
+    encoder: wgpu::CommandEncoder,
+}
+
+impl MaterialUploader {
+    pub(crate) fn new(device: &wgpu::Device) -> Self {
+        Self {
+            mipmaps: mipmap::MipmapGenerator::new(device),
+            encoder: device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mipmap Encoder"),
+            }),
+        }
+    }
+
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        decoded: DecodedMaterial,
+        options: &LoadOptions,
+    ) -> anyhow::Result<model::Material> {
+        let DecodedMaterial {
+            material: m,
+            diffuse_path,
+            diffuse: diffuse_image,
+        } = decoded;
+        let diffuse_texture = texture::Texture::from_source_mipmapped(
+            device,
+            queue,
+            &mut self.encoder,
+            &mut self.mipmaps,
+            &diffuse_image,
+            Some(&diffuse_path),
+            &options.texture_settings,
+        )?;
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -111,72 +740,815 @@ pub async fn load_model(
             label: None,
         });
 
-        materials.push(model::Material {
+        let mut features = variant::MaterialFeatures::DIFFUSE_MAP;
+        // Cutouts usually keep the mask in the diffuse map's alpha too, which
+        // is what the shader reads.
+        if !m.dissolve_texture.is_empty() {
+            features.insert(variant::MaterialFeatures::ALPHA_MASK);
+        }
+        let triplanar = options.triplanar.or_else(|| {
+            m.unknown_param
+                .get(Triplanar::MTL_KEY)
+                .map(String::as_str)
+                .and_then(Triplanar::from_mtl)
+        });
+        let mut material = model::Material {
+            refractive: Refractive::from_mtl(m.illumination_model, m.optical_density),
             name: m.name,
-            diffuse_texture,
+            diffuse_texture: Arc::new(diffuse_texture),
             bind_group,
-        })
+            features,
+            triplanar: None,
+        };
+        material.set_triplanar(device, triplanar)?;
+        Ok(material)
+    }
+
+    /// Submits the mip chains.
+    pub(crate) fn finish(self, queue: &wgpu::Queue) {
+        queue.submit(std::iter::once(self.encoder.finish()));
+    }
+}
+
+/// A mesh's vertices in the engine's axes, before splitting and upload.
+pub(crate) struct BuiltMesh {
+    pub(crate) name: String,
+    pub(crate) material: usize,
+    pub(crate) vertices: Vec<model::ModelVertex>,
+    pub(crate) indices: Vec<u32>,
+}
+
+impl BuiltMesh {
+    /// Converts `data` from the source axes of `options`, then generates
+    /// texture coordinates if it has none and `options` asks for them.
+    pub(crate) fn new(
+        name: String,
+        material: usize,
+        mut data: model::MeshData,
+        has_uvs: bool,
+        options: &LoadOptions,
+    ) -> Self {
+        let conversion = options.source_axes.conversion_to(axes::TARGET_AXES);
+        let scale = options.unit_scale();
+        for v in &mut data.vertices {
+            v.position = scale.position(conversion.vector(v.position));
+            v.normal = conversion.vector(v.normal);
+        }
+        conversion.fix_winding(&mut data.indices);
+        if let (false, Some(projection)) = (has_uvs, options.missing_uvs) {
+            data.generate_uvs(projection, options.generated_uv_scale);
+        }
+        Self {
+            name,
+            material,
+            vertices: data.vertices,
+            indices: data.indices,
+        }
+    }
+}
+
+/// Splits [`BuiltMesh`]es that are too large and uploads them, one at a
+/// time so only one mesh's CPU data has to be alive at once.
+pub(crate) struct MeshUploader<'a> {
+    file_name: &'a str,
+    options: &'a LoadOptions,
+    mesh_layout: wgpu::BindGroupLayout,
+    max_buffer_size: u64,
+    vertex_size: usize,
+    full_bytes: usize,
+    uploaded_bytes: usize,
+    meshes: Vec<model::Mesh>,
+}
+
+impl<'a> MeshUploader<'a> {
+    pub(crate) fn new(device: &wgpu::Device, file_name: &'a str, options: &'a LoadOptions) -> Self {
+        Self {
+            file_name,
+            options,
+            mesh_layout: model::create_mesh_bind_group_layout(device),
+            max_buffer_size: device.limits().max_buffer_size,
+            vertex_size: match options.vertex_precision {
+                model::VertexPrecision::Full => std::mem::size_of::<model::ModelVertex>(),
+                model::VertexPrecision::Compressed => {
+                    std::mem::size_of::<model::CompressedVertex>()
+                }
+            },
+            full_bytes: 0,
+            uploaded_bytes: 0,
+            meshes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut pool: Option<&mut gpu::GeometryPool>,
+        mesh: BuiltMesh,
+    ) -> anyhow::Result<()> {
+        let (file_name, options) = (self.file_name, self.options);
+        let BuiltMesh {
+            name,
+            material,
+            vertices,
+            indices,
+        } = mesh;
+        let size = split::check_size(
+            vertices.len(),
+            self.vertex_size,
+            indices.len(),
+            self.max_buffer_size,
+        );
+        let chunks = match size {
+            Ok(()) => vec![(vertices, indices)],
+            Err(e) if options.split_large_meshes => {
+                let (max_vertices, max_indices) =
+                    split::chunk_limits(self.vertex_size, self.max_buffer_size);
+                let chunks = split::split_triangles(&vertices, &indices, max_vertices, max_indices);
+                log::info!(
+                    "{}: {:?} is too large ({}), split into {} chunks",
+                    file_name,
+                    name,
+                    e,
+                    chunks.len()
+                );
+                chunks
+            }
+            Err(e) => return Err(e).with_context(|| format!("Couldn't load {:?}", name)),
+        };
+
+        for (chunk_vertices, chunk_indices) in chunks {
+            let aabb = Aabb::from_points(chunk_vertices.iter().map(|v| v.position.into()))
+                .unwrap_or(Aabb::new([0.0; 3].into(), [0.0; 3].into()));
+
+            self.full_bytes += std::mem::size_of_val(chunk_vertices.as_slice());
+            let ((geometry, memory), bind_group) = match options.vertex_precision {
+                model::VertexPrecision::Full => {
+                    let geometry = create_geometry(
+                        device,
+                        queue,
+                        pool.as_deref_mut(),
+                        file_name,
+                        &chunk_vertices,
+                        &chunk_indices,
+                    )?;
+                    (geometry, None)
+                }
+                model::VertexPrecision::Compressed => {
+                    let compressed = chunk_vertices
+                        .iter()
+                        .map(|v| model::CompressedVertex::encode(v, &aabb))
+                        .collect::<Vec<_>>();
+                    self.uploaded_bytes += std::mem::size_of_val(compressed.as_slice());
+                    let mut geometry = create_geometry(
+                        device,
+                        queue,
+                        pool.as_deref_mut(),
+                        file_name,
+                        &compressed,
+                        &chunk_indices,
+                    )?;
+
+                    let (uniform, uniform_memory) = gpu::Allocator::new(device)
+                        .create_buffer_init(
+                            &wgpu::util::BufferInitDescriptor {
+                                label: Some(&format!("{:?} Mesh Uniform", file_name)),
+                                contents: bytemuck::cast_slice(&[model::MeshUniform::dequantize(
+                                    &aabb,
+                                )]),
+                                usage: wgpu::BufferUsages::UNIFORM,
+                            },
+                            gpu::MemoryCategory::Uniform,
+                        )?;
+                    geometry.1.push(uniform_memory);
+                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        layout: &self.mesh_layout,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform.as_entire_binding(),
+                        }],
+                        label: Some("mesh_bind_group"),
+                    });
+                    (geometry, Some(bind_group))
+                }
+            };
+
+            self.meshes.push(model::Mesh {
+                name: file_name.to_string(),
+                geometry,
+                num_elements: chunk_indices.len() as u32,
+                material,
+                aabb,
+                skin_bounds: None,
+                morph_bounds: None,
+                bind_group,
+                memory,
+            });
+        }
+        Ok(())
     }
 
-    let meshes = models
+    pub(crate) fn finish(self) -> Vec<model::Mesh> {
+        if self.options.vertex_precision == model::VertexPrecision::Compressed {
+            log::info!(
+                "{}: compressed vertices take {} bytes instead of {} ({:.0}% saved)",
+                self.file_name,
+                self.uploaded_bytes,
+                self.full_bytes,
+                100.0 * (1.0 - self.uploaded_bytes as f64 / self.full_bytes.max(1) as f64)
+            );
+        }
+        let bounds = self
+            .meshes
+            .iter()
+            .map(|m| m.aabb)
+            .reduce(|a, b| a.union(&b));
+        if let Some(bounds) = bounds.filter(|b| !units::is_plausible_size(b)) {
+            let size = bounds.size();
+            log::warn!(
+                "{} is {:.3} m across, which is unlikely; is LoadOptions::assumed_unit {:?} right?",
+                self.file_name,
+                size.x.max(size.y).max(size.z),
+                self.options.assumed_unit
+            );
+        }
+        stats::note_event(stats::FrameEvent::AssetIntegrated(
+            self.file_name.to_string(),
+        ));
+        self.meshes
+    }
+}
+
+/// Parses an OBJ and its material libraries, without touching the GPU.
+async fn parse_obj(file_name: &str, options: &LoadOptions) -> anyhow::Result<ParsedObj> {
+    let obj_bytes = load_resource(file_name).await?;
+    let parsed = match obj::parse(&obj_bytes)? {
+        Some(parsed) => resolve_obj_materials(file_name, parsed).await?,
+        // Something the fast path doesn't handle, which tobj might.
+        None => parse_obj_with_tobj(file_name, &obj_bytes, options).await?,
+    };
+    Ok(parsed)
+}
+
+/// The `asset-pipeline` output for `file_name`: the same path under
+/// [`PROCESSED_DIR`], with the [`model_data::EXTENSION`].
+pub fn processed_path(file_name: &str) -> String {
+    let stem = match file_name.rfind('.') {
+        Some(i) if !file_name[i..].contains(['/', '\\']) => &file_name[..i],
+        _ => file_name,
+    };
+    format!("{}/{}.{}", PROCESSED_DIR, stem, model_data::EXTENSION)
+}
+
+/// Whether `file_name` is a glTF file, JSON or binary, by its extension.
+pub fn is_gltf(file_name: &str) -> bool {
+    let extension = file_name.rsplit_once('.').map_or("", |(_, e)| e);
+    extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+}
+
+/// Parses the OBJ or glTF `file_name` into [`ModelData`], as the
+/// `asset-pipeline` bin does. Texture paths are kept relative to the model.
+/// Images inside glTF files are left out, see [`parse_gltf`].
+pub async fn parse_model_data(file_name: &str, options: &LoadOptions) -> anyhow::Result<ModelData> {
+    if is_gltf(file_name) {
+        return Ok(parse_gltf(file_name).await?.0);
+    }
+    let parsed = parse_obj(file_name, options).await?;
+    let materials = parsed
+        .materials
+        .into_iter()
+        .map(|m| MaterialRecord {
+            alpha_mask: !m.dissolve_texture.is_empty(),
+            refractive: Refractive::from_mtl(m.illumination_model, m.optical_density),
+            triplanar: m
+                .unknown_param
+                .get(Triplanar::MTL_KEY)
+                .map(String::as_str)
+                .and_then(Triplanar::from_mtl),
+            name: m.name,
+            diffuse_texture: m.diffuse_texture,
+        })
+        .collect();
+    let meshes = parsed
+        .models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                })
-                .collect::<Vec<_>>();
+            let (vertices, indices) = obj_vertices(&m.mesh);
+            MeshRecord {
+                material: m.mesh.material_id.unwrap_or(0) as u32,
+                has_uvs: !m.mesh.texcoords.is_empty(),
+                name: m.name,
+                data: model::MeshData { vertices, indices },
+            }
+        })
+        .collect();
+    Ok(ModelData { meshes, materials })
+}
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+/// An image stored inside a glTF file rather than next to it.
+#[derive(Debug, Clone)]
+pub struct EmbeddedImage {
+    /// The path its materials name it by, relative to the glTF file.
+    pub name: String,
+    /// Still encoded, as a PNG or JPEG.
+    pub bytes: Vec<u8>,
+}
 
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+/// Parses the glTF `file_name` into [`ModelData`], along with the images
+/// stored inside it. See [`gltf_model_data`].
+pub async fn parse_gltf(file_name: &str) -> anyhow::Result<(ModelData, Vec<EmbeddedImage>)> {
+    let gltf = gltf::Gltf::from_slice(&load_binary(file_name).await?)
+        .with_context(|| format!("Couldn't parse {}", file_name))?;
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .with_context(|| format!("{} has no binary chunk", file_name))?,
+            gltf::buffer::Source::Uri(uri) => read_gltf_uri(file_name, uri).await?,
+        };
+        anyhow::ensure!(
+            data.len() >= buffer.length(),
+            "Buffer {} of {} is cut off",
+            buffer.index(),
+            file_name
+        );
+        buffers.push(data);
+    }
+    gltf_model_data(file_name, &gltf, &buffers)
+}
+
+/// The bytes a glTF buffer `uri` in `file_name` refers to, either inline
+/// or in a file next to it.
+async fn read_gltf_uri(file_name: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        decode_data_uri(file_name, uri)
+    } else {
+        load_binary(&sibling_path(file_name, uri)).await
+    }
+}
+
+/// The bytes of a base64 `data:` URI in `file_name`.
+fn decode_data_uri(file_name: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    let (_, encoded) = uri
+        .split_once(";base64,")
+        .with_context(|| format!("{} has a data URI that isn't base64", file_name))?;
+    base64::decode(encoded).with_context(|| format!("{} has a bad data URI", file_name))
+}
+
+/// What [`gltf_model_data`] names `image` of `file_name` if it is stored
+/// inside the file, `<stem>.<image index>.<png or jpg>`, or `None` if it is
+/// a file of its own.
+pub fn embedded_image_name(file_name: &str, image: &gltf::Image) -> Option<String> {
+    let mime_type = match image.source() {
+        gltf::image::Source::Uri { uri, mime_type } => {
+            let media_type = uri.strip_prefix("data:")?.split([';', ',']).next();
+            mime_type.or(media_type)
+        }
+        gltf::image::Source::View { mime_type, .. } => Some(mime_type),
+    };
+    let stem = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+    let stem = stem.rsplit_once('.').map_or(stem, |(stem, _)| stem);
+    let extension = if mime_type == Some("image/jpeg") {
+        "jpg"
+    } else {
+        "png"
+    };
+    Some(format!("{}.{}.{}", stem, image.index(), extension))
+}
+
+/// The triangles of `gltf`'s default scene, or of its first if it has no
+/// default, with each node's meshes moved by the node's transform, one
+/// [`MeshRecord`] per primitive. Only the base color texture of materials
+/// is kept, as the diffuse map. Images stored in the file, inline or in a
+/// buffer, have no path of their own, so their materials name them
+/// `<stem>.<image index>.<png or jpg>` next to the file, and they are
+/// returned for the caller to write there.
+pub fn gltf_model_data(
+    file_name: &str,
+    gltf: &gltf::Gltf,
+    buffers: &[Vec<u8>],
+) -> anyhow::Result<(ModelData, Vec<EmbeddedImage>)> {
+    use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+
+    let mut embedded = Vec::new();
+    let mut image_names = Vec::new();
+    for image in gltf.images() {
+        let Some(name) = embedded_image_name(file_name, &image) else {
+            // A file of its own, which is left where it is.
+            if let gltf::image::Source::Uri { uri, .. } = image.source() {
+                image_names.push(uri.to_string());
             }
+            continue;
+        };
+        let bytes = match image.source() {
+            gltf::image::Source::Uri { uri, .. } => decode_data_uri(file_name, uri)?,
+            gltf::image::Source::View { view, .. } => {
+                let start = view.offset();
+                buffers[view.buffer().index()]
+                    .get(start..start + view.length())
+                    .with_context(|| {
+                        format!("Image {} of {} is cut off", image.index(), file_name)
+                    })?
+                    .to_vec()
+            }
+        };
+        image_names.push(name.clone());
+        embedded.push(EmbeddedImage { name, bytes });
+    }
+
+    let mut materials = gltf
+        .materials()
+        .map(|m| MaterialRecord {
+            name: m.name().map_or_else(
+                || format!("material {}", m.index().unwrap_or(0)),
+                str::to_string,
+            ),
+            diffuse_texture: m
+                .pbr_metallic_roughness()
+                .base_color_texture()
+                .map_or_else(String::new, |info| {
+                    image_names[info.texture().source().index()].clone()
+                }),
+            alpha_mask: m.alpha_mode() == gltf::material::AlphaMode::Mask,
+            refractive: None,
+            triplanar: None,
         })
         .collect::<Vec<_>>();
+    // For primitives without a material, added the first time one is seen.
+    let mut default_material = None;
+
+    // The nodes left to visit, last first, so meshes come in the order the
+    // file lists them.
+    let mut nodes = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .map_or_else(Vec::new, |scene| {
+            scene
+                .nodes()
+                .map(|node| (node, Matrix4::identity()))
+                .collect()
+        });
+    nodes.reverse();
+    let mut meshes = Vec::new();
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        let first_child = nodes.len();
+        nodes.extend(node.children().map(|child| (child, transform)));
+        nodes[first_child..].reverse();
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        let linear = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = linear
+            .invert()
+            .map_or(Matrix3::identity(), |inverse| inverse.transpose());
+        // Mirroring turns the triangles inside out, so they are wound back.
+        let mirrored = linear.determinant() < 0.0;
 
-    Ok(model::Model { meshes, materials })
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "Skipping a {:?} primitive of {}, only triangles are supported",
+                    primitive.mode(),
+                    file_name
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions = reader
+                .read_positions()
+                .with_context(|| format!("A primitive of {} has no positions", file_name))?
+                .map(|p| {
+                    (transform * Vector4::new(p[0], p[1], p[2], 1.0))
+                        .truncate()
+                        .into()
+                })
+                .collect::<Vec<[f32; 3]>>();
+            let mut indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            indices.truncate(indices.len() / 3 * 3);
+            anyhow::ensure!(
+                indices.iter().all(|&i| (i as usize) < positions.len()),
+                "A primitive of {} has indices past its vertices",
+                file_name
+            );
+            if mirrored {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            let normals = match reader.read_normals() {
+                Some(normals) => normals
+                    .map(|n| normalize_or_up((normal_matrix * Vector3::from(n)).into()))
+                    .collect(),
+                None => {
+                    // The area weighted average of the faces around each
+                    // vertex, as for OBJ files without normals.
+                    let mut sums = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+                    for triangle in indices.chunks_exact(3) {
+                        let [a, b, c] =
+                            [0, 1, 2].map(|k| Vector3::from(positions[triangle[k] as usize]));
+                        let normal = (b - a).cross(c - a);
+                        for &i in triangle {
+                            sums[i as usize] += normal;
+                        }
+                    }
+                    sums.into_iter()
+                        .map(|sum| normalize_or_up(sum.into()))
+                        .collect::<Vec<_>>()
+                }
+            };
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|t| t.into_f32().collect::<Vec<_>>());
+            let vertices = positions
+                .iter()
+                .zip(&normals)
+                .enumerate()
+                .map(|(i, (&position, &normal))| model::ModelVertex {
+                    position,
+                    tex_coords: tex_coords
+                        .as_ref()
+                        .and_then(|t| t.get(i).copied())
+                        .unwrap_or([0.0, 0.0]),
+                    normal,
+                    occlusion: 1.0,
+                })
+                .collect();
+
+            let material = match primitive.material().index() {
+                Some(index) => index,
+                None => *default_material.get_or_insert_with(|| {
+                    materials.push(MaterialRecord {
+                        name: "default".to_string(),
+                        diffuse_texture: String::new(),
+                        alpha_mask: false,
+                        refractive: None,
+                        triplanar: None,
+                    });
+                    materials.len() - 1
+                }),
+            };
+            meshes.push(MeshRecord {
+                name: mesh
+                    .name()
+                    .map_or_else(|| format!("mesh {}", mesh.index()), str::to_string),
+                material: material as u32,
+                data: model::MeshData { vertices, indices },
+                has_uvs: tex_coords.is_some(),
+            });
+        }
+    }
+    Ok((ModelData { meshes, materials }, embedded))
 }
 
-pub async fn load_gltf(
+/// Uploads `data`, read from `file_name`, which its texture paths are
+/// relative to.
+pub async fn load_model_data(
     file_name: &str,
+    data: ModelData,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::GLTFModel> {
+    options: &LoadOptions,
+    mut pool: Option<&mut gpu::GeometryPool>,
+) -> anyhow::Result<model::Model> {
+    let refractive = data
+        .materials
+        .iter()
+        .map(|m| m.refractive)
+        .collect::<Vec<_>>();
+    // `load_obj_materials` only reads these fields, and the refraction is
+    // set after, as MTL has no thickness. Triplanar mapping goes back in as
+    // the MTL statement, so `LoadOptions::triplanar` overrides it the same.
+    let obj_materials = data
+        .materials
+        .into_iter()
+        .map(|m| tobj::Material {
+            dissolve_texture: if m.alpha_mask {
+                m.diffuse_texture.clone()
+            } else {
+                String::new()
+            },
+            unknown_param: m
+                .triplanar
+                .map(|t| (Triplanar::MTL_KEY.to_string(), t.to_mtl()))
+                .into_iter()
+                .collect(),
+            name: m.name,
+            diffuse_texture: m.diffuse_texture,
+            ..Default::default()
+        })
+        .collect();
+    let mut materials =
+        load_obj_materials(file_name, device, queue, layout, obj_materials, options).await?;
+    for (material, refractive) in materials.iter_mut().zip(refractive) {
+        material.refractive = refractive;
+    }
+    let mut uploader = MeshUploader::new(device, file_name, options);
+    for m in data.meshes {
+        let mesh = BuiltMesh::new(m.name, m.material as usize, m.data, m.has_uvs, options);
+        uploader.upload(device, queue, pool.as_deref_mut(), mesh)?;
+    }
+
+    Ok(model::Model {
+        meshes: uploader.finish(),
+        materials,
+        vertex_precision: options.vertex_precision,
+        unit_scale: options.unit_scale(),
+    })
+}
+
+/// The processed form of `file_name` if there is a readable one.
+async fn load_processed(file_name: &str) -> Option<(String, ModelData)> {
+    let path = processed_path(file_name);
+    let bytes = read_binary(&path).await.ok()?;
+    match ModelData::from_bytes(&bytes) {
+        Ok(data) => Some((path, data)),
+        Err(e) => {
+            log::warn!(
+                "Couldn't read {}, loading {} instead: {}",
+                path,
+                file_name,
+                e
+            );
+            None
+        }
+    }
+}
+
+async fn load_obj(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+    mut pool: Option<&mut gpu::GeometryPool>,
+) -> anyhow::Result<model::Model> {
+    if options.prefer_processed {
+        if let Some((path, data)) = load_processed(file_name).await {
+            return load_model_data(&path, data, device, queue, layout, options, pool).await;
+        }
+    }
+    // Only the parsed data is needed from here on; the file is released.
+    let parsed = parse_obj(file_name, options).await?;
+
+    let materials =
+        load_obj_materials(file_name, device, queue, layout, parsed.materials, options).await?;
+    let mut uploader = MeshUploader::new(device, file_name, options);
+    for m in parsed.models {
+        let (vertices, indices) = obj_vertices(&m.mesh);
+        let material = m.mesh.material_id.unwrap_or(0);
+        let data = model::MeshData { vertices, indices };
+        let has_uvs = !m.mesh.texcoords.is_empty();
+        let mesh = BuiltMesh::new(m.name, material, data, has_uvs, options);
+        uploader.upload(device, queue, pool.as_deref_mut(), mesh)?;
+    }
+
+    Ok(model::Model {
+        meshes: uploader.finish(),
+        materials,
+        vertex_precision: options.vertex_precision,
+        unit_scale: options.unit_scale(),
+    })
+}
+
+/// Points [`load_ply`] parses and uploads at a time.
+const PLY_CHUNK_POINTS: usize = 1 << 16;
+
+/// A PLY file opened by [`open_ply`], its points not read yet.
+pub type PlyFile = ply::PlyReader<Box<dyn BufRead>>;
+
+/// Loads the points of a PLY file, see [`ply`]. On native the file is read
+/// and uploaded a chunk at a time, so scans larger than memory load. Scans
+/// with more points than the device's largest buffer holds keep every nth
+/// point.
+pub async fn load_ply(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<model::PointCloud> {
+    let reader = open_ply(file_name).await?;
+    upload_ply(file_name, device, queue, reader)
+}
+
+/// Opens `file_name` and reads its header, the part of [`load_ply`] that
+/// doesn't need the device. On the web the whole file is fetched here.
+pub async fn open_ply(file_name: &str) -> anyhow::Result<PlyFile> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
-            unimplemented!()
+            let reader: Box<dyn BufRead> = Box::new(Cursor::new(load_binary(file_name).await?));
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
+            let file = std::fs::File::open(resource_path(file_name))
+                .with_context(|| format!("Couldn't open {}", file_name))?;
+            let reader: Box<dyn BufRead> = Box::new(BufReader::new(file));
         }
     }
-    let gltf = gltf::Gltf::open(path)?;
+    ply::PlyReader::new(reader).with_context(|| format!("Couldn't load {}", file_name))
+}
+
+/// Reads the points of `reader`, opened from `file_name` by [`open_ply`],
+/// and uploads them, the rest of [`load_ply`].
+pub fn upload_ply(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    reader: PlyFile,
+) -> anyhow::Result<model::PointCloud> {
+    upload_points(file_name, device, queue, reader)
+        .with_context(|| format!("Couldn't load {}", file_name))
+}
+
+fn upload_points<R: BufRead>(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mut reader: ply::PlyReader<R>,
+) -> anyhow::Result<model::PointCloud> {
+    let stride = std::mem::size_of::<model::PointVertex>() as u64;
+    let total = reader.remaining();
+    let max_points = (device.limits().max_buffer_size / stride).min(u32::MAX as u64);
+    let keep_every = total.div_ceil(max_points).max(1);
+    if keep_every > 1 {
+        log::warn!(
+            "{} has {} points, more than a buffer holds, so only every {}th is kept",
+            file_name,
+            total,
+            keep_every
+        );
+    }
+    let capacity = total.div_ceil(keep_every);
+    let (vertex_buffer, memory) = gpu::Allocator::new(device).create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some(&format!("{:?} Point Buffer", file_name)),
+            // An empty buffer can't be created on every backend.
+            size: capacity.max(1) * stride,
+            // COPY_SRC so picking can read the points back.
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        },
+        gpu::MemoryCategory::Vertex,
+    )?;
+
+    let mut aabb: Option<Aabb> = None;
+    let mut chunk = Vec::new();
+    let mut index = 0;
+    let mut written = 0;
+    loop {
+        chunk.clear();
+        let read = reader.read_points(PLY_CHUNK_POINTS, &mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        if keep_every > 1 {
+            let mut i = index;
+            chunk.retain(|_| {
+                let keep = i % keep_every == 0;
+                i += 1;
+                keep
+            });
+        }
+        index += read as u64;
+        for point in &chunk {
+            let p = cgmath::Point3::from(point.position);
+            aabb = Some(aabb.map_or(Aabb::new(p, p), |aabb| aabb.including(p)));
+        }
+        queue.write_buffer(
+            &vertex_buffer,
+            written * stride,
+            bytemuck::cast_slice(&chunk),
+        );
+        // Submitting hands the chunk to the GPU, so writes don't pile up in
+        // staging memory until the next frame.
+        queue.submit([]);
+        written += chunk.len() as u64;
+    }
+
+    Ok(model::PointCloud {
+        vertex_buffer,
+        num_points: written as u32,
+        aabb,
+        memory,
+    })
+}
+
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::GLTFModel> {
+    let gltf = gltf::Gltf::from_slice(&load_resource(file_name).await?)?;
 
     let accessors = gltf.accessors().collect::<Vec<_>>();
 
@@ -212,13 +1584,12 @@ pub async fn load_gltf(
             let index_buffer = match buffer.source() {
                 gltf::buffer::Source::Bin => unimplemented!(),
                 gltf::buffer::Source::Uri(uri) => {
-                    let binary_file = load_binary(uri)
+                    let binary_file = load_resource(uri)
                         .await
                         .context("binary file not found")
                         .unwrap();
                     // Get buffer slice from view spec and load it into a buffer
                     let buffer_slice = binary_file
-                        .as_slice()
                         .get(view.offset()..(view.offset() + view.length()))
                         .context("buffer slice not found")
                         .unwrap();
@@ -244,3 +1615,142 @@ pub async fn load_gltf(
 
     todo!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str, single_index: bool) -> Vec<tobj::Model> {
+        let (models, _) = tobj::load_obj_buf(
+            &mut Cursor::new(source.as_bytes()),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index,
+                ..Default::default()
+            },
+            |_| Ok(Default::default()),
+        )
+        .unwrap();
+        models
+    }
+
+    /// Every triangle corner's position, UV and normal, in order, which is
+    /// what gets drawn whichever way the vertices are shared.
+    fn corners(vertices: &[model::ModelVertex], indices: &[u32]) -> Vec<[f32; 8]> {
+        indices
+            .iter()
+            .map(|&i| {
+                let v = &vertices[i as usize];
+                let [x, y, z] = v.position;
+                let [u, w] = v.tex_coords;
+                let [nx, ny, nz] = v.normal;
+                [x, y, z, u, w, nx, ny, nz]
+            })
+            .collect()
+    }
+
+    /// Loads `source` both ways and returns the vertex counts with and
+    /// without `single_index`, checking the triangles are the same.
+    fn compare(source: &str) -> (usize, usize) {
+        let duplicated = parse(source, true);
+        let unified = parse(source, false);
+        assert_eq!(duplicated.len(), unified.len());
+        let (mut duplicated_count, mut unified_count) = (0, 0);
+        for (a, b) in duplicated.iter().zip(&unified) {
+            let (a_vertices, a_indices) = obj_vertices(&a.mesh);
+            let (b_vertices, b_indices) = obj_vertices(&b.mesh);
+            assert_eq!(
+                corners(&a_vertices, &a_indices),
+                corners(&b_vertices, &b_indices),
+                "{} draws differently",
+                a.name
+            );
+            duplicated_count += a_vertices.len();
+            unified_count += b_vertices.len();
+        }
+        (duplicated_count, unified_count)
+    }
+
+    #[test]
+    fn unifying_indices_draws_the_same_with_no_more_vertices() {
+        let (duplicated, unified) = compare(include_str!("../res/cube/cube.obj"));
+        assert!(
+            unified <= duplicated,
+            "{} vertices unified, {} duplicated",
+            unified,
+            duplicated
+        );
+    }
+
+    #[test]
+    fn meshes_without_uvs_or_normals_unify_too() {
+        let square = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+        for faces in [
+            "f 1 2 3\nf 1 3 4\n",
+            "vt 0 0\nvt 1 1\nf 1/1 2/1 3/2\nf 1/1 3/2 4/2\n",
+            "vn 0 0 1\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n",
+        ] {
+            let (duplicated, unified) = compare(&format!("{}{}", square, faces));
+            assert!(unified <= duplicated, "{}", faces);
+        }
+    }
+
+    #[test]
+    fn gltf_nodes_place_and_mirror_their_meshes() {
+        use crate::export::{self, ExportMaterial, ExportMesh, ExportNode, ExportScene};
+
+        let mut parent = ExportNode::new("parent");
+        parent.translation = [0.0, 0.0, 5.0];
+        parent.children = vec![1];
+        let mut mirrored = ExportNode::new("mirrored");
+        mirrored.mesh = Some(0);
+        mirrored.scale = [-1.0, 1.0, 1.0];
+        let scene = ExportScene {
+            meshes: vec![ExportMesh {
+                name: "triangle".to_string(),
+                positions: vec![[1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+                normals: vec![[0.0, 0.0, 1.0]; 3],
+                tex_coords: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+                indices: vec![0, 1, 2],
+                material: Some(0),
+            }],
+            materials: vec![ExportMaterial {
+                name: "painted".to_string(),
+                base_color: [1.0; 4],
+                base_color_texture: Some(image::RgbaImage::new(4, 2)),
+            }],
+            nodes: vec![parent, mirrored],
+            roots: vec![0],
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("gltf-data-{}.glb", std::process::id()));
+        export::to_gltf(&scene, &path).unwrap();
+        let parsed = pollster::block_on(parse_gltf(&path.to_string_lossy()));
+        std::fs::remove_file(&path).unwrap();
+        let (data, embedded) = parsed.unwrap();
+
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.name, "triangle");
+        assert!(mesh.has_uvs);
+        let positions = mesh
+            .data
+            .vertices
+            .iter()
+            .map(|v| v.position)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [[-1.0, 0.0, 5.0], [-2.0, 0.0, 5.0], [-1.0, 1.0, 5.0]]
+        );
+        // Mirrored, but still facing +Z, so wound the other way around.
+        assert_eq!(mesh.data.indices, [0, 2, 1]);
+        assert_eq!(mesh.data.vertices[0].normal, [0.0, 0.0, 1.0]);
+
+        let name = format!("gltf-data-{}.0.png", std::process::id());
+        assert_eq!(data.materials[0].diffuse_texture, name);
+        assert_eq!(embedded.len(), 1);
+        assert_eq!(embedded[0].name, name);
+        let image = image::load_from_memory(&embedded[0].bytes).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 2));
+    }
+}
@@ -0,0 +1,289 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Quaternion, Rad, Rotation3, Transform, Vector3};
+
+use crate::{bounds::Aabb, spatial::SpatialHash, Instance};
+
+/// How [`scatter_on_mesh`] places instances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterOptions {
+    pub seed: u64,
+    /// Candidate points per unit of surface area, after `transform`.
+    pub density: f32,
+    /// Candidates closer than this to an accepted instance are dropped.
+    pub min_spacing: f32,
+    /// Whether instances point up along the surface normal rather than +Y.
+    pub align_to_normal: bool,
+    /// Uniform scales are picked evenly from this range.
+    pub scale_range: (f32, f32),
+    /// Surfaces steeper than this from horizontal get no instances.
+    pub slope_limit: Rad<f32>,
+}
+
+impl Default for ScatterOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            density: 1.0,
+            min_spacing: 0.0,
+            align_to_normal: false,
+            scale_range: (1.0, 1.0),
+            slope_limit: Rad(std::f32::consts::PI),
+        }
+    }
+}
+
+/// PCG32, so a seed gives the same numbers on every platform and with every
+/// dependency version.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.state = state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        xorshifted.rotate_right((state >> 59) as u32)
+    }
+
+    /// In `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Places instances on the triangle list `positions`/`indices` after
+/// transforming it by `transform`, ready for [`crate::State::set_instances`].
+///
+/// `density * area` candidates are picked with area weighted sampling, then
+/// those on too steep a slope or within `min_spacing` of an earlier instance
+/// are dropped, so the count stops growing with density once the surface is
+/// full. The same seed and inputs always give the same instances.
+pub fn scatter_on_mesh(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    transform: Matrix4<f32>,
+    options: ScatterOptions,
+) -> Vec<Instance> {
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|t| {
+            [t[0], t[1], t[2]].map(|i| transform.transform_point(positions[i as usize].into()))
+        })
+        .collect::<Vec<_>>();
+
+    // Running totals of the triangle areas, to pick triangles by area.
+    let mut total_area = 0.0;
+    let cumulative_area = triangles
+        .iter()
+        .map(|&[a, b, c]| {
+            total_area += (b - a).cross(c - a).magnitude() / 2.0;
+            total_area
+        })
+        .collect::<Vec<_>>();
+    if total_area <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut rng = Rng::new(options.seed);
+    let mut accepted = SpatialHash::new(options.min_spacing.max(f32::EPSILON));
+    let spacing = Vector3::new(1.0, 1.0, 1.0) * options.min_spacing;
+    let mut instances = Vec::new();
+    let min_up = options.slope_limit.0.min(std::f32::consts::PI).cos();
+    let candidates = (total_area * options.density).round() as usize;
+    for _ in 0..candidates {
+        // Every random number is drawn whether or not the candidate is kept,
+        // so each candidate's values only depend on its position in the
+        // sequence.
+        let pick = rng.next_f32() * total_area;
+        let (u, v) = (rng.next_f32(), rng.next_f32());
+        let yaw = rng.next_f32() * std::f32::consts::TAU;
+        let scale = rng.next_f32();
+
+        let triangle = cumulative_area
+            .partition_point(|&area| area <= pick)
+            .min(triangles.len() - 1);
+        let [a, b, c] = triangles[triangle];
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+        let normal = normal.normalize();
+        if normal.y < min_up {
+            continue;
+        }
+
+        // Uniform over the triangle.
+        let r = u.sqrt();
+        let position = a + (b - a) * (r * (1.0 - v)) + (c - a) * (r * v);
+        if options.min_spacing > 0.0 {
+            let near = accepted.query_aabb(&Aabb::new(position, position).expanded(spacing));
+            let too_close = near.into_iter().any(|id| {
+                accepted.get(id).is_some_and(|(point, _)| {
+                    (point.min - position).magnitude2() < options.min_spacing.powi(2)
+                })
+            });
+            if too_close {
+                continue;
+            }
+            accepted.insert(Aabb::new(position, position), ());
+        }
+
+        let yaw = Quaternion::from_angle_y(Rad(yaw));
+        let rotation = if options.align_to_normal {
+            Quaternion::from_arc(Vector3::unit_y(), normal, None) * yaw
+        } else {
+            yaw
+        };
+        let (min_scale, max_scale) = options.scale_range;
+        let scale = min_scale + (max_scale - min_scale) * scale;
+        instances.push(Instance {
+            position: position.to_vec(),
+            rotation,
+            scale: Vector3::new(scale, scale, scale),
+        });
+    }
+    instances
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, SquareMatrix};
+
+    use super::*;
+
+    /// A `size` by `size` square on the XZ plane, facing up.
+    fn ground(size: f32) -> ([[f32; 3]; 4], [u32; 6]) {
+        let h = size / 2.0;
+        (
+            [[-h, 0.0, -h], [-h, 0.0, h], [h, 0.0, h], [h, 0.0, -h]],
+            [0, 1, 2, 0, 2, 3],
+        )
+    }
+
+    fn scatter(options: ScatterOptions) -> Vec<Instance> {
+        let (positions, indices) = ground(10.0);
+        scatter_on_mesh(&positions, &indices, Matrix4::identity(), options)
+    }
+
+    fn same(a: &[Instance], b: &[Instance]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| {
+                a.position == b.position && a.rotation == b.rotation && a.scale == b.scale
+            })
+    }
+
+    #[test]
+    fn same_seed_gives_same_instances() {
+        let options = ScatterOptions {
+            seed: 42,
+            density: 5.0,
+            min_spacing: 0.3,
+            scale_range: (0.5, 2.0),
+            ..Default::default()
+        };
+        let first = scatter(options);
+        assert!(!first.is_empty());
+        assert!(same(&first, &scatter(options)));
+        let other = scatter(ScatterOptions {
+            seed: 43,
+            ..options
+        });
+        assert!(!same(&first, &other));
+    }
+
+    #[test]
+    fn rng_sequence_is_fixed() {
+        // Pinned so a refactor can't silently change every scattered scene.
+        let mut rng = Rng::new(42);
+        let values: Vec<_> = (0..4).map(|_| rng.next_u32()).collect();
+        assert_eq!(values, [3270867926, 1795671209, 1924641435, 1143034755]);
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&rng.next_f32()));
+        }
+    }
+
+    #[test]
+    fn instances_keep_min_spacing() {
+        let min_spacing = 0.5;
+        let instances = scatter(ScatterOptions {
+            seed: 1,
+            density: 50.0,
+            min_spacing,
+            ..Default::default()
+        });
+        for (i, a) in instances.iter().enumerate() {
+            for b in &instances[i + 1..] {
+                let distance = (a.position - b.position).magnitude();
+                assert!(distance >= min_spacing, "{} apart", distance);
+            }
+        }
+        // A full surface stops growing with density.
+        let denser = scatter(ScatterOptions {
+            seed: 1,
+            density: 100.0,
+            min_spacing,
+            ..Default::default()
+        });
+        let full = 100.0 / (min_spacing * min_spacing);
+        assert!((instances.len() as f32) < full);
+        assert!((denser.len() as f32) < full);
+    }
+
+    #[test]
+    fn instances_stay_on_the_surface() {
+        let transform = Matrix4::from_translation(Vector3::new(3.0, 2.0, 0.0));
+        let (positions, indices) = ground(4.0);
+        let options = ScatterOptions {
+            density: 10.0,
+            scale_range: (0.5, 0.75),
+            ..Default::default()
+        };
+        let instances = scatter_on_mesh(&positions, &indices, transform, options);
+        // 16 square units at 10 per unit.
+        assert_eq!(instances.len(), 160);
+        for instance in &instances {
+            let p = instance.position;
+            assert!((1.0..=5.0).contains(&p.x) && (-2.0..=2.0).contains(&p.z));
+            assert_eq!(p.y, 2.0);
+            assert!((0.5..=0.75).contains(&instance.scale.x));
+            assert!(instance.is_uniformly_scaled());
+        }
+    }
+
+    #[test]
+    fn slope_limit_skips_steep_faces() {
+        // A wall facing +Z.
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let options = ScatterOptions {
+            density: 100.0,
+            slope_limit: Deg(45.0).into(),
+            align_to_normal: true,
+            ..Default::default()
+        };
+        let none = scatter_on_mesh(&positions, &[0, 1, 2], Matrix4::identity(), options);
+        assert!(none.is_empty());
+        let options = ScatterOptions {
+            slope_limit: Deg(90.0).into(),
+            ..options
+        };
+        let some = scatter_on_mesh(&positions, &[0, 1, 2], Matrix4::identity(), options);
+        assert_eq!(some.len(), 50);
+        // Aligned instances point their up axis along the normal.
+        let up = some[0].rotation * Vector3::unit_y();
+        assert!((up - Vector3::unit_z()).magnitude() < 1e-5);
+    }
+}
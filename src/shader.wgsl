@@ -9,17 +9,39 @@ var<uniform> camera: Camera;
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) occlusion: f32,
 }
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+//!ifdef FULL_INSTANCES
+    // NormalMatrixRaw, for instances that aren't uniformly scaled.
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+//!endif
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+//!ifdef SHADING_FAST
+    // Light reaching the vertex, interpolated across the triangle.
+    @location(1) light: vec3<f32>,
+//!else
+    @location(1) world_normal: vec3<f32>,
+    // Baked ambient occlusion, see model::MeshData::bake_vertex_ao.
+    @location(2) occlusion: f32,
+//!endif
+//!ifdef HAS_TRIPLANAR
+    // Where the diffuse map is projected from, already scaled, and the
+    // normal that weighs the projections, both in the material's space.
+    @location(3) triplanar_position: vec3<f32>,
+    @location(4) triplanar_normal: vec3<f32>,
+//!endif
 }
 
 @vertex
@@ -33,20 +55,186 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = instance_normal_matrix(instance, model_matrix);
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    set_lighting(&out, world_normal(normal_matrix, model.normal), model.occlusion);
+    set_triplanar(&out, model_matrix, normal_matrix, model.position, model.normal);
     out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
     return out;
 }
 
+// Instances packed compactly are uniformly scaled, so the upper 3x3 of the
+// model matrix turns normals too. Others come with their normal matrix.
+fn instance_normal_matrix(instance: InstanceInput, model_matrix: mat4x4<f32>) -> mat3x3<f32> {
+//!ifdef FULL_INSTANCES
+    return mat3x3<f32>(instance.normal_matrix_0, instance.normal_matrix_1, instance.normal_matrix_2);
+//!else
+    return mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+//!endif
+}
+
+fn world_normal(normal_matrix: mat3x3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    return normalize(normal_matrix * normal);
+}
+
+// The fast tier lights vertices here, and the full tier passes the normal
+// and occlusion on for fs_main to light each pixel.
+fn set_lighting(out: ptr<function, VertexOutput>, normal: vec3<f32>, occlusion: f32) {
+//!ifdef SHADING_FAST
+    (*out).light = ambient_light(normal) * occlusion;
+//!else
+    (*out).world_normal = normal;
+    (*out).occlusion = occlusion;
+//!endif
+}
+
+//!ifdef HAS_TRIPLANAR
+// triplanar::TriplanarUniform.
+struct Triplanar {
+    scale: f32,
+    blend_sharpness: f32,
+    object_space: u32,
+    _padding: u32,
+}
+@group(0) @binding(2)
+var<uniform> triplanar: Triplanar;
+//!endif
+
+// Object space projections use the position and normal as the mesh has
+// them, so the texture moves with the instance.
+fn set_triplanar(
+    out: ptr<function, VertexOutput>,
+    model_matrix: mat4x4<f32>,
+    normal_matrix: mat3x3<f32>,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+) {
+//!ifdef HAS_TRIPLANAR
+    if triplanar.object_space != 0u {
+        (*out).triplanar_position = position * triplanar.scale;
+        (*out).triplanar_normal = normal;
+    } else {
+        let world_position = model_matrix * vec4<f32>(position, 1.0);
+        (*out).triplanar_position = world_position.xyz * triplanar.scale;
+        (*out).triplanar_normal = world_normal(normal_matrix, normal);
+    }
+//!endif
+}
+
+// Vertex shader for model::CompressedVertex. Positions are quantized to the
+// mesh bounds, so they are scaled back with the per mesh uniform first. The
+// fourth component of the position is the occlusion.
+
+struct Mesh {
+    dequantize_offset: vec4<f32>,
+    dequantize_scale: vec4<f32>,
+}
+@group(2) @binding(0)
+var<uniform> mesh: Mesh;
+
+struct CompressedVertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec2<f32>,
+}
+
+// Same as compression::octahedral_decode.
+fn octahedral_decode(e: vec2<f32>) -> vec3<f32> {
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    if n.z < 0.0 {
+        let sign_not_zero = select(vec2<f32>(-1.0), vec2<f32>(1.0), e >= vec2<f32>(0.0));
+        n = vec3<f32>((1.0 - abs(e.yx)) * sign_not_zero, n.z);
+    }
+    return normalize(n);
+}
+
+@vertex
+fn vs_compressed(
+    model: CompressedVertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = instance_normal_matrix(instance, model_matrix);
+    let position = mesh.dequantize_offset.xyz + model.position.xyz * mesh.dequantize_scale.xyz;
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    let normal = octahedral_decode(model.normal);
+    set_lighting(&out, world_normal(normal_matrix, normal), model.position.w);
+    set_triplanar(&out, model_matrix, normal_matrix, position, normal);
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    return out;
+}
+
 // Fragment shader
 
+// light::Ambient, blended from ground to sky by the world normal's Y.
+struct Ambient {
+    sky_color: vec3<f32>,
+    intensity: f32,
+    ground_color: vec3<f32>,
+}
+@group(1) @binding(1)
+var<uniform> ambient: Ambient;
+
+fn ambient_light(normal: vec3<f32>) -> vec3<f32> {
+    let t = normal.y * 0.5 + 0.5;
+    return mix(ambient.ground_color, ambient.sky_color, t) * ambient.intensity;
+}
+
 @group(0) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
+// With HAS_TRIPLANAR, the diffuse map projected along each axis and blended
+// by how squarely the surface faces it. Each projection is mirrored on the
+// negative side of its axis, so the texture reads the same way round from
+// both sides, with up along +Y on the side projections.
+fn sample_diffuse(in: VertexOutput) -> vec4<f32> {
+//!ifdef HAS_TRIPLANAR
+    let n = normalize(in.triplanar_normal);
+    var weights = pow(abs(n), vec3<f32>(triplanar.blend_sharpness));
+    weights /= max(weights.x + weights.y + weights.z, 0.0001);
+    let side = select(vec3<f32>(-1.0), vec3<f32>(1.0), n >= vec3<f32>(0.0));
+    let p = in.triplanar_position;
+    // Sampled unconditionally: skipping faint projections would break the
+    // derivatives mip selection relies on.
+    let x = textureSample(t_diffuse, s_diffuse, vec2<f32>(-p.z * side.x, -p.y));
+    let y = textureSample(t_diffuse, s_diffuse, vec2<f32>(p.x * side.y, p.z));
+    let z = textureSample(t_diffuse, s_diffuse, vec2<f32>(p.x * side.z, -p.y));
+    return x * weights.x + y * weights.y + z * weights.z;
+//!else
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+//!endif
+}
+
+// Diffuse alpha under this is cut out of alpha masked materials.
+const ALPHA_CUTOFF: f32 = 0.5;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    var color = sample_diffuse(in);
+//!ifdef HAS_ALPHA_MASK
+//!ifdef ALPHA_TO_COVERAGE
+    // Sharpen alpha to a ramp about a pixel wide around the cutoff. Coverage
+    // then gives soft edges, and averaged down mips don't erode the shape.
+    color.a = clamp((color.a - ALPHA_CUTOFF) / max(fwidth(color.a), 0.0001) + 0.5, 0.0, 1.0);
+//!else
+    if color.a < ALPHA_CUTOFF {
+        discard;
+    }
+//!endif
+//!endif
+//!ifdef SHADING_FAST
+    color = vec4<f32>(color.rgb * in.light, color.a);
+//!else
+    color = vec4<f32>(color.rgb * ambient_light(normalize(in.world_normal)) * in.occlusion, color.a);
+//!endif
+    return color;
 }
\ No newline at end of file
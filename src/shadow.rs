@@ -0,0 +1,287 @@
+//! Shadow maps of many lights packed into one depth texture, so they are
+//! drawn in one pass and bound once. [`ShadowTiles`] sizes each light's tile
+//! by its importance, and [`ShadowAtlas`] renders casters into the tiles.
+//! A directional light's cascades each request a tile of their own.
+
+pub mod allocator;
+
+use std::ops::Range;
+
+use cgmath::Matrix4;
+
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken},
+    model::{self, Model, Vertex},
+    reflect, InstanceRaw,
+};
+
+pub use allocator::{screen_coverage, AtlasAllocator, AtlasTile, ShadowRequest, ShadowTiles};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// One depth texture holding every light's shadow map in a tile of its own.
+pub struct ShadowAtlas {
+    tiles: ShadowTiles,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    _memory: [MemoryToken; 2],
+    pipeline: wgpu::RenderPipeline,
+    view_buffer: wgpu::Buffer,
+    view_bind_group: wgpu::BindGroup,
+    view_stride: u32,
+    /// The tile of each view written by the last [`Self::prepare`].
+    views: Vec<AtlasTile>,
+}
+
+impl ShadowAtlas {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const DEFAULT_SIZE: u32 = 4096;
+    pub const MIN_TILE: u32 = 64;
+    /// Views past this many in a frame are left out.
+    pub const MAX_VIEWS: usize = 64;
+
+    pub(crate) const VIEW_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] =
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ViewUniform>() as u64),
+            },
+            count: None,
+        }];
+
+    /// An atlas `size` across, or as large as the device allows, rounded
+    /// down to a power of two. The largest tile is half the atlas across,
+    /// so on devices with a lower limit every tile shrinks alike.
+    pub fn new(device: &wgpu::Device, size: u32) -> Result<Self, AllocError> {
+        let limit = device.limits().max_texture_dimension_2d;
+        let atlas = AtlasAllocator::new(size.min(limit), Self::MIN_TILE);
+        if atlas.size() < size {
+            log::info!("Shadow atlas reduced to {0}x{0}", atlas.size());
+        }
+        let size = atlas.size();
+        let tiles = ShadowTiles::new(atlas, size / 2);
+
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("Shadow Atlas"),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                // COPY_SRC lets the tiles be read back to check them.
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let source = include_str!("shadow.wgsl");
+        reflect::debug_check("shadow.wgsl", source, &[&Self::VIEW_LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shadow.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let view_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::VIEW_LAYOUT_ENTRIES,
+            label: Some("shadow_view_bind_group_layout"),
+        });
+        let view_stride = wgpu::util::align_to(
+            std::mem::size_of::<ViewUniform>() as u32,
+            device.limits().min_uniform_buffer_offset_alignment,
+        );
+        let (view_buffer, view_buffer_memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Shadow View Buffer"),
+                size: view_stride as u64 * Self::MAX_VIEWS as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &view_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &view_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<ViewUniform>() as u64),
+                }),
+            }],
+            label: Some("shadow_view_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&view_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            tiles,
+            texture,
+            view,
+            _memory: [memory, view_buffer_memory],
+            pipeline,
+            view_buffer,
+            view_bind_group,
+            view_stride,
+            views: Vec::new(),
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.tiles.atlas_size()
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// For sampling the shadow maps.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn tiles(&self) -> &ShadowTiles {
+        &self.tiles
+    }
+
+    /// Update with this frame's requests before [`Self::prepare`].
+    pub fn tiles_mut(&mut self) -> &mut ShadowTiles {
+        &mut self.tiles
+    }
+
+    /// The tile of the light with `id` in atlas UVs, for
+    /// [`LightUniform::shadow_rect`](crate::light::LightUniform), or all
+    /// zero without one.
+    pub fn uv_rect(&self, id: u64) -> [f32; 4] {
+        self.tiles
+            .tile(id)
+            .map_or([0.0; 4], |tile| tile.uv_rect(self.size()))
+    }
+
+    /// Writes the view projection of each light that has a tile. The views
+    /// are drawn by index in this order, leaving out lights without a tile.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, views: &[(u64, Matrix4<f32>)]) {
+        self.views.clear();
+        let mut data = Vec::new();
+        for (id, view_proj) in views {
+            let Some(tile) = self.tiles.tile(*id) else {
+                continue;
+            };
+            if self.views.len() == Self::MAX_VIEWS {
+                log::warn!("Only the first {} shadow views are drawn", Self::MAX_VIEWS);
+                break;
+            }
+            data.resize(self.views.len() * self.view_stride as usize, 0);
+            let uniform = ViewUniform {
+                view_proj: (*view_proj).into(),
+            };
+            data.extend_from_slice(bytemuck::bytes_of(&uniform));
+            self.views.push(tile);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.view_buffer, 0, &data);
+        }
+    }
+
+    /// How many views the last [`Self::prepare`] kept.
+    pub fn view_count(&self) -> usize {
+        self.views.len()
+    }
+
+    /// Clears the whole atlas and begins the pass every tile is drawn in.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    /// Points `render_pass` at the `index`th view's tile, for the casters
+    /// drawn after it.
+    pub fn set_view<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        let tile = self.views[index];
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.view_bind_group, &[index as u32 * self.view_stride]);
+        render_pass.set_viewport(
+            tile.x as f32,
+            tile.y as f32,
+            tile.size as f32,
+            tile.size as f32,
+            0.0,
+            1.0,
+        );
+        render_pass.set_scissor_rect(tile.x, tile.y, tile.size, tile.size);
+    }
+
+    /// Draws `model` with `instances` of `instance_buffer` into the current
+    /// view. Only models with [`model::VertexPrecision::Full`] cast shadows.
+    pub fn draw_model<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        if model.vertex_precision != model::VertexPrecision::Full {
+            return;
+        }
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for mesh in &model.meshes {
+            model::draw_geometry(render_pass, mesh, instances.clone());
+        }
+    }
+}
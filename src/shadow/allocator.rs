@@ -0,0 +1,408 @@
+//! Packing of square power of two tiles into a shadow atlas, and the choice
+//! of each light's tile size.
+
+/// A square region of the atlas, in texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasTile {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl AtlasTile {
+    pub fn overlaps(&self, other: &AtlasTile) -> bool {
+        self.x < other.x + other.size
+            && other.x < self.x + self.size
+            && self.y < other.y + other.size
+            && other.y < self.y + self.size
+    }
+
+    /// `(x, y, width, height)` in the UVs of an atlas `atlas_size` across,
+    /// for looking the tile up in a shader.
+    pub fn uv_rect(&self, atlas_size: u32) -> [f32; 4] {
+        let scale = 1.0 / atlas_size as f32;
+        [
+            self.x as f32 * scale,
+            self.y as f32 * scale,
+            self.size as f32 * scale,
+            self.size as f32 * scale,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Free,
+    Used,
+    /// Split into the four quadrants at this index and the three after it.
+    Split(usize),
+}
+
+/// Hands out tiles of an atlas by splitting it into quadrants, and merges
+/// quadrants again once all four are free. Every tile is a power of two
+/// across and aligned to its size.
+#[derive(Debug, Clone)]
+pub struct AtlasAllocator {
+    size: u32,
+    min_tile: u32,
+    nodes: Vec<Node>,
+    /// The first of four unused nodes left by merging.
+    spare: Vec<usize>,
+}
+
+impl AtlasAllocator {
+    /// `size` and `min_tile` are rounded down to powers of two.
+    pub fn new(size: u32, min_tile: u32) -> Self {
+        let size = floor_power_of_two(size);
+        Self {
+            size,
+            min_tile: floor_power_of_two(min_tile).min(size),
+            nodes: vec![Node::Free],
+            spare: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn min_tile(&self) -> u32 {
+        self.min_tile
+    }
+
+    /// A free tile `size` across, rounded up to a power of two, or `None`
+    /// if there is no room or it's outside the allocator's tile sizes.
+    pub fn allocate(&mut self, size: u32) -> Option<AtlasTile> {
+        let size = size.max(1).next_power_of_two();
+        if size < self.min_tile || size > self.size {
+            return None;
+        }
+        self.allocate_in(0, 0, 0, self.size, size)
+    }
+
+    fn allocate_in(
+        &mut self,
+        node: usize,
+        x: u32,
+        y: u32,
+        size: u32,
+        want: u32,
+    ) -> Option<AtlasTile> {
+        match self.nodes[node] {
+            Node::Used => None,
+            Node::Free if size == want => {
+                self.nodes[node] = Node::Used;
+                Some(AtlasTile { x, y, size })
+            }
+            Node::Free => {
+                let children = self.split(node);
+                self.allocate_in_children(children, x, y, size, want)
+            }
+            Node::Split(_) if size == want => None,
+            Node::Split(children) => self.allocate_in_children(children, x, y, size, want),
+        }
+    }
+
+    fn allocate_in_children(
+        &mut self,
+        children: usize,
+        x: u32,
+        y: u32,
+        size: u32,
+        want: u32,
+    ) -> Option<AtlasTile> {
+        let half = size / 2;
+        (0..4).find_map(|i| {
+            let (cx, cy) = quadrant(x, y, half, i);
+            self.allocate_in(children + i, cx, cy, half, want)
+        })
+    }
+
+    fn split(&mut self, node: usize) -> usize {
+        let children = match self.spare.pop() {
+            Some(children) => {
+                self.nodes[children..children + 4].fill(Node::Free);
+                children
+            }
+            None => {
+                self.nodes.extend([Node::Free; 4]);
+                self.nodes.len() - 4
+            }
+        };
+        self.nodes[node] = Node::Split(children);
+        children
+    }
+
+    /// Returns `tile` to the allocator. Tiles it didn't hand out are ignored.
+    pub fn free(&mut self, tile: AtlasTile) {
+        self.free_in(0, 0, 0, self.size, tile);
+    }
+
+    fn free_in(&mut self, node: usize, x: u32, y: u32, size: u32, tile: AtlasTile) {
+        if size == tile.size {
+            if (x, y) == (tile.x, tile.y) && self.nodes[node] == Node::Used {
+                self.nodes[node] = Node::Free;
+            }
+            return;
+        }
+        let Node::Split(children) = self.nodes[node] else {
+            return;
+        };
+        let half = size / 2;
+        let i = (tile.x >= x + half) as usize + 2 * (tile.y >= y + half) as usize;
+        let (cx, cy) = quadrant(x, y, half, i);
+        self.free_in(children + i, cx, cy, half, tile);
+        if self.nodes[children..children + 4]
+            .iter()
+            .all(|n| *n == Node::Free)
+        {
+            self.nodes[node] = Node::Free;
+            self.spare.push(children);
+        }
+    }
+
+    /// Frees every tile.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.nodes.push(Node::Free);
+        self.spare.clear();
+    }
+}
+
+/// Quadrants in reading order: top left, top right, bottom left, bottom
+/// right.
+fn quadrant(x: u32, y: u32, half: u32, i: usize) -> (u32, u32) {
+    (x + half * (i as u32 & 1), y + half * (i as u32 >> 1))
+}
+
+fn floor_power_of_two(n: u32) -> u32 {
+    if n == 0 {
+        1
+    } else {
+        1 << (31 - n.leading_zeros())
+    }
+}
+
+/// A light, or one cascade of a directional light, that wants a tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowRequest {
+    /// Stays the same across frames for the same light, so it keeps its
+    /// tile.
+    pub id: u64,
+    /// From 0 to 1, the share of the largest tile size the light deserves
+    /// by area, such as from [`screen_coverage`].
+    pub importance: f32,
+}
+
+/// Roughly how much of a screen with vertical field of view `fovy` a
+/// light's sphere of influence, `radius` across at `distance` from the
+/// camera, covers, from 0 to 1.
+pub fn screen_coverage(radius: f32, distance: f32, fovy: cgmath::Rad<f32>) -> f32 {
+    let half_height = distance.max(f32::EPSILON) * (fovy.0 / 2.0).tan();
+    (radius / half_height).powi(2).clamp(0.0, 1.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Assignment {
+    id: u64,
+    /// The level asked for, which `tile` can be below when the atlas was
+    /// full.
+    level: u32,
+    tile: Option<AtlasTile>,
+}
+
+/// Gives each light a tile sized by its importance, keeping tiles across
+/// frames where it can. Level 0 is the largest tile, and each level is half
+/// as wide.
+#[derive(Debug, Clone)]
+pub struct ShadowTiles {
+    allocator: AtlasAllocator,
+    max_tile: u32,
+    assignments: Vec<Assignment>,
+}
+
+impl ShadowTiles {
+    /// How far past the halfway point between two levels importance has to
+    /// move before a light changes level, so one hovering near the boundary
+    /// doesn't reallocate every frame.
+    pub const HYSTERESIS: f32 = 0.25;
+
+    /// Tiles of `atlas`, at most `max_tile` across.
+    pub fn new(atlas: AtlasAllocator, max_tile: u32) -> Self {
+        let max_tile = floor_power_of_two(max_tile).clamp(atlas.min_tile(), atlas.size());
+        Self {
+            allocator: atlas,
+            max_tile,
+            assignments: Vec::new(),
+        }
+    }
+
+    pub fn atlas_size(&self) -> u32 {
+        self.allocator.size()
+    }
+
+    pub fn max_tile(&self) -> u32 {
+        self.max_tile
+    }
+
+    fn max_level(&self) -> u32 {
+        (self.max_tile / self.allocator.min_tile()).trailing_zeros()
+    }
+
+    /// The level for `importance`, staying at `current` unless it moved
+    /// [`Self::HYSTERESIS`] past the boundary.
+    fn level(&self, importance: f32, current: Option<u32>) -> u32 {
+        // Tiles are sized by area, so each level down is a quarter of it.
+        let exact = -0.5 * importance.max(f32::MIN_POSITIVE).log2();
+        let max_level = self.max_level();
+        if let Some(current) = current {
+            if (exact - current as f32).abs() <= 0.5 + Self::HYSTERESIS
+                || (current == max_level && exact > max_level as f32)
+            {
+                return current;
+            }
+        }
+        (exact.round().max(0.0) as u32).min(max_level)
+    }
+
+    /// Updates the tiles for this frame's `requests`. Lights that keep their
+    /// level keep their tile. The rest get one in order of importance, a
+    /// smaller one if the atlas is too full, or none.
+    pub fn update(&mut self, requests: &[ShadowRequest]) {
+        let mut order = requests.iter().collect::<Vec<_>>();
+        order.sort_by(|a, b| b.importance.total_cmp(&a.importance).then(a.id.cmp(&b.id)));
+
+        let mut previous = std::mem::take(&mut self.assignments);
+        let mut assignments = Vec::with_capacity(order.len());
+        let mut pending = Vec::new();
+        for request in order {
+            let kept = previous
+                .iter()
+                .position(|a| a.id == request.id)
+                .map(|i| previous.swap_remove(i));
+            let level = self.level(request.importance, kept.map(|a| a.level));
+            match kept {
+                Some(assignment) if assignment.level == level && assignment.tile.is_some() => {
+                    assignments.push(assignment)
+                }
+                _ => {
+                    if let Some(tile) = kept.and_then(|a| a.tile) {
+                        self.allocator.free(tile);
+                    }
+                    pending.push(assignments.len());
+                    assignments.push(Assignment {
+                        id: request.id,
+                        level,
+                        tile: None,
+                    });
+                }
+            }
+        }
+        for gone in previous {
+            if let Some(tile) = gone.tile {
+                self.allocator.free(tile);
+            }
+        }
+        for i in pending {
+            let level = assignments[i].level;
+            assignments[i].tile = (level..=self.max_level())
+                .find_map(|level| self.allocator.allocate(self.max_tile >> level));
+        }
+        self.assignments = assignments;
+    }
+
+    /// The tile of the light with `id`, if it has one.
+    pub fn tile(&self, id: u64) -> Option<AtlasTile> {
+        self.assignments.iter().find(|a| a.id == id)?.tile
+    }
+
+    /// Every light with a tile, most important first.
+    pub fn tiles(&self) -> impl Iterator<Item = (u64, AtlasTile)> + '_ {
+        self.assignments
+            .iter()
+            .filter_map(|a| Some((a.id, a.tile?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_fill_the_atlas_and_merge_when_freed() {
+        let mut atlas = AtlasAllocator::new(1024, 64);
+        let mut tiles = Vec::new();
+        for size in [512, 64, 256, 128].into_iter().cycle() {
+            match atlas.allocate(size) {
+                Some(tile) => tiles.push(tile),
+                None if size == 64 => break,
+                None => {}
+            }
+        }
+        for (i, tile) in tiles.iter().enumerate() {
+            assert!(
+                tile.size.is_power_of_two() && tile.x % tile.size == 0 && tile.y % tile.size == 0,
+                "{:?} isn't aligned",
+                tile
+            );
+            assert!(tile.x + tile.size <= 1024 && tile.y + tile.size <= 1024);
+            if let Some(other) = tiles[..i].iter().find(|other| other.overlaps(tile)) {
+                panic!("{:?} overlaps {:?}", tile, other);
+            }
+        }
+        let area = tiles.iter().map(|t| t.size * t.size).sum::<u32>();
+        assert_eq!(area, 1024 * 1024);
+        for tile in tiles {
+            atlas.free(tile);
+        }
+        let whole = AtlasTile {
+            x: 0,
+            y: 0,
+            size: 1024,
+        };
+        assert_eq!(
+            atlas.allocate(1024),
+            Some(whole),
+            "freed tiles didn't merge"
+        );
+        assert!(atlas.allocate(64).is_none());
+        assert!(AtlasAllocator::new(1024, 64).allocate(32).is_none());
+        assert_eq!(AtlasAllocator::new(3000, 64).size(), 2048);
+    }
+
+    #[test]
+    fn tiles_follow_importance_with_hysteresis() {
+        // Importance 0.25 is a 256 tile and 1.0 a 512 one, with the boundary
+        // at 0.5.
+        let mut tiles = ShadowTiles::new(AtlasAllocator::new(1024, 64), 512);
+        let request = |importance| ShadowRequest { id: 1, importance };
+        tiles.update(&[request(0.45)]);
+        let first = tiles.tile(1);
+        assert_eq!(first.map(|t| t.size), Some(256));
+        for importance in [0.55, 0.4, 0.6, 0.45] {
+            tiles.update(&[request(importance)]);
+            assert_eq!(
+                tiles.tile(1),
+                first,
+                "importance {} moved the tile",
+                importance
+            );
+        }
+        tiles.update(&[request(1.0)]);
+        assert_eq!(tiles.tile(1).map(|t| t.size), Some(512));
+
+        // Four of the largest tiles fill the atlas, so a fifth light goes
+        // without, and the least important one is it.
+        let requests = (0..5)
+            .map(|id| ShadowRequest {
+                id,
+                importance: 1.0 - id as f32 * 0.01,
+            })
+            .collect::<Vec<_>>();
+        tiles.update(&requests);
+        assert_eq!(tiles.tiles().count(), 4);
+        assert!(tiles.tile(4).is_none());
+        tiles.update(&[]);
+        assert_eq!(tiles.tiles().count(), 0);
+    }
+}
@@ -0,0 +1,273 @@
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, SquareMatrix, Vector3, Zero,
+};
+
+/// How a skinned mesh blends its joints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SkinningMethod {
+    /// Blends joint matrices. Cheap, but twisted joints such as forearms
+    /// lose volume ("candy wrapper").
+    #[default]
+    Linear,
+    /// Blends joints as dual quaternions, which keeps volume under twist.
+    /// Only rigid joints can be represented, see [`SkinningMethod::resolve`].
+    DualQuaternion,
+}
+
+impl SkinningMethod {
+    /// WGSL with `skin_position` and `skin_normal` for both methods, to be
+    /// prepended to shaders drawing skinned meshes. The variant is picked by
+    /// [`PipelineKey::skinning`](crate::variant::PipelineKey::skinning).
+    pub const WGSL: &'static str = include_str!("skinning.wgsl");
+
+    pub(crate) fn define(self) -> &'static str {
+        match self {
+            SkinningMethod::Linear => "SKINNING_LINEAR",
+            SkinningMethod::DualQuaternion => "SKINNING_DUAL_QUATERNION",
+        }
+    }
+
+    /// The method a skeleton posed with `palette` can actually use, with the
+    /// dual quaternion palette when that is it. Joints that scale or shear
+    /// can't be dual quaternions, so such skeletons fall back to linear
+    /// blending with a warning.
+    pub fn resolve(self, palette: &[Matrix4<f32>]) -> (Self, Option<Vec<DualQuat>>) {
+        match self {
+            SkinningMethod::Linear => (self, None),
+            SkinningMethod::DualQuaternion => match dual_quaternion_palette(palette) {
+                Ok(dual_quats) => (self, Some(dual_quats)),
+                Err(joint) => {
+                    log::warn!(
+                        "Joint {} scales or shears, using linear blend skinning instead",
+                        joint
+                    );
+                    (SkinningMethod::Linear, None)
+                }
+            },
+        }
+    }
+}
+
+/// A rigid transform as a unit dual quaternion, laid out as in
+/// `skinning.wgsl`: `real` is the rotation and `dual` encodes the
+/// translation, both as xyzw.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DualQuat {
+    pub real: [f32; 4],
+    pub dual: [f32; 4],
+}
+
+/// How far a joint matrix's axes may be from unit length and orthogonal for
+/// it to still count as rigid.
+const RIGID_TOLERANCE: f32 = 1e-3;
+
+fn to_xyzw(q: Quaternion<f32>) -> [f32; 4] {
+    [q.v.x, q.v.y, q.v.z, q.s]
+}
+
+fn from_xyzw(q: [f32; 4]) -> Quaternion<f32> {
+    Quaternion::new(q[3], q[0], q[1], q[2])
+}
+
+impl DualQuat {
+    pub fn from_rotation_translation(rotation: Quaternion<f32>, translation: Vector3<f32>) -> Self {
+        let rotation = rotation.normalize();
+        let dual = Quaternion::from_sv(0.0, translation) * rotation * 0.5;
+        Self {
+            real: to_xyzw(rotation),
+            dual: to_xyzw(dual),
+        }
+    }
+
+    /// `None` if `matrix` scales, shears or mirrors.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Option<Self> {
+        let basis = Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        let unit = |v: Vector3<f32>| (v.magnitude() - 1.0).abs() <= RIGID_TOLERANCE;
+        let rigid = unit(basis.x)
+            && unit(basis.y)
+            && unit(basis.z)
+            && basis.x.dot(basis.y).abs() <= RIGID_TOLERANCE
+            && basis.y.dot(basis.z).abs() <= RIGID_TOLERANCE
+            && basis.z.dot(basis.x).abs() <= RIGID_TOLERANCE
+            && basis.determinant() > 0.0;
+        rigid.then(|| Self::from_rotation_translation(Quaternion::from(basis), matrix.w.truncate()))
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        from_xyzw(self.real)
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        (from_xyzw(self.dual) * self.rotation().conjugate() * 2.0).v
+    }
+
+    pub fn transform_point(&self, point: Point3<f32>) -> Point3<f32> {
+        Point3::from_vec(self.rotation() * point.to_vec() + self.translation())
+    }
+
+    pub fn transform_vector(&self, vector: Vector3<f32>) -> Vector3<f32> {
+        self.rotation() * vector
+    }
+
+    /// Blends the joints a vertex is weighted to, as the shader does. Each
+    /// quaternion is flipped onto the hemisphere of the most weighted one,
+    /// since `q` and `-q` are the same rotation but don't blend the same.
+    pub fn blend(palette: &[DualQuat], joints: [u16; 4], weights: [f32; 4]) -> Self {
+        let dominant = (0..4)
+            .max_by(|&a, &b| weights[a].total_cmp(&weights[b]))
+            .map_or(0, |i| joints[i] as usize);
+        let pivot = from_xyzw(palette[dominant].real);
+        let (mut real, mut dual) = (Quaternion::zero(), Quaternion::zero());
+        for (&joint, &weight) in joints.iter().zip(&weights) {
+            let dq = &palette[joint as usize];
+            let (r, d) = (from_xyzw(dq.real), from_xyzw(dq.dual));
+            let weight = if r.dot(pivot) < 0.0 { -weight } else { weight };
+            real += r * weight;
+            dual += d * weight;
+        }
+        let length = real.magnitude();
+        Self {
+            real: to_xyzw(real / length),
+            dual: to_xyzw(dual / length),
+        }
+    }
+}
+
+/// Converts a skinning palette to dual quaternions, or returns the index of
+/// the first joint that isn't rigid.
+pub fn dual_quaternion_palette(palette: &[Matrix4<f32>]) -> Result<Vec<DualQuat>, usize> {
+    palette
+        .iter()
+        .enumerate()
+        .map(|(i, matrix)| DualQuat::from_matrix(matrix).ok_or(i))
+        .collect()
+}
+
+/// Blends joint matrices the way [`SkinningMethod::Linear`] does, for
+/// comparing with [`DualQuat::blend`] on the CPU.
+pub fn blend_linear(palette: &[Matrix4<f32>], joints: [u16; 4], weights: [f32; 4]) -> Matrix4<f32> {
+    joints
+        .iter()
+        .zip(&weights)
+        .fold(Matrix4::zero(), |sum, (&joint, &weight)| {
+            sum + palette[joint as usize] * weight
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rotation3, Transform};
+
+    use super::*;
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    /// A forearm along +X: the elbow at rest and the wrist twisted `twist`
+    /// about the bone and moved `offset` along it.
+    fn forearm(twist: Deg<f32>, offset: f32) -> Vec<Matrix4<f32>> {
+        vec![
+            Matrix4::identity(),
+            Matrix4::from_translation(Vector3::new(offset, 0.0, 0.0))
+                * Matrix4::from_angle_x(twist),
+        ]
+    }
+
+    #[test]
+    fn rigid_matrices_round_trip() {
+        let rotation =
+            Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Deg(70.0));
+        let translation = Vector3::new(0.5, -2.0, 3.0);
+        let matrix = Matrix4::from_translation(translation) * Matrix4::from(rotation);
+        let dq = DualQuat::from_matrix(&matrix).unwrap();
+        assert!((dq.translation() - translation).magnitude() < 1e-5);
+        for point in [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, -4.0, 2.5)] {
+            assert_close(dq.transform_point(point), matrix.transform_point(point));
+        }
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        assert!((dq.transform_vector(normal) - matrix.transform_vector(normal)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn non_rigid_joints_fall_back_to_linear() {
+        let scaled = Matrix4::from_scale(1.5);
+        let mirrored = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let mut sheared = Matrix4::identity();
+        sheared.y.x = 0.5;
+        for matrix in [scaled, mirrored, sheared] {
+            assert!(DualQuat::from_matrix(&matrix).is_none(), "{:?}", matrix);
+        }
+        let palette = [Matrix4::identity(), Matrix4::identity(), scaled];
+        assert_eq!(dual_quaternion_palette(&palette), Err(2));
+        assert_eq!(
+            SkinningMethod::DualQuaternion.resolve(&palette),
+            (SkinningMethod::Linear, None)
+        );
+        let (method, dual_quats) = SkinningMethod::DualQuaternion.resolve(&palette[..2]);
+        assert_eq!(method, SkinningMethod::DualQuaternion);
+        assert_eq!(dual_quats.map(|d| d.len()), Some(2));
+    }
+
+    #[test]
+    fn twist_keeps_volume_where_linear_collapses() {
+        let palette = forearm(Deg(160.0), 0.0);
+        let dual_quats = dual_quaternion_palette(&palette).unwrap();
+        let (joints, weights) = ([0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]);
+        // A point on the skin of the forearm, halfway between the joints.
+        let skin = Point3::new(0.5, 1.0, 0.0);
+        let radius = |p: Point3<f32>| (p.y * p.y + p.z * p.z).sqrt();
+
+        let linear = blend_linear(&palette, joints, weights).transform_point(skin);
+        let dual = DualQuat::blend(&dual_quats, joints, weights).transform_point(skin);
+        // Averaging the matrices pulls the skin in to cos(80°) of the radius,
+        // the candy wrapper. The dual quaternion turns it 80° instead.
+        assert!((radius(linear) - 80f32.to_radians().cos()).abs() < 1e-4);
+        assert!((radius(dual) - 1.0).abs() < 1e-4);
+        assert_close(dual, Matrix4::from_angle_x(Deg(80.0)).transform_point(skin));
+        // Neither moves the point along the bone.
+        assert!((linear.x - 0.5).abs() < 1e-5 && (dual.x - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn blends_agree_without_rotation() {
+        // Pure translations blend linearly either way.
+        let palette = forearm(Deg(0.0), 2.0);
+        let dual_quats = dual_quaternion_palette(&palette).unwrap();
+        let point = Point3::new(1.0, 0.5, -0.5);
+        for weight in [0.0, 0.25, 0.5, 1.0] {
+            let (joints, weights) = ([0, 1, 0, 0], [1.0 - weight, weight, 0.0, 0.0]);
+            let linear = blend_linear(&palette, joints, weights).transform_point(point);
+            let dual = DualQuat::blend(&dual_quats, joints, weights).transform_point(point);
+            assert_close(dual, linear);
+        }
+    }
+
+    #[test]
+    fn blend_ignores_quaternion_sign() {
+        let palette = forearm(Deg(120.0), 1.0);
+        let dual_quats = dual_quaternion_palette(&palette).unwrap();
+        let mut flipped = dual_quats.clone();
+        flipped[1] = DualQuat {
+            real: flipped[1].real.map(|c| -c),
+            dual: flipped[1].dual.map(|c| -c),
+        };
+        let (joints, weights) = ([0, 1, 0, 0], [0.3, 0.7, 0.0, 0.0]);
+        let point = Point3::new(0.5, 1.0, 0.0);
+        assert_close(
+            DualQuat::blend(&flipped, joints, weights).transform_point(point),
+            DualQuat::blend(&dual_quats, joints, weights).transform_point(point),
+        );
+        // A single joint at full weight is just that joint.
+        let single = DualQuat::blend(&dual_quats, [1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+        assert_close(
+            single.transform_point(point),
+            palette[1].transform_point(point),
+        );
+    }
+}
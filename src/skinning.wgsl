@@ -0,0 +1,76 @@
+// Skinning for both SkinningMethod variants behind the same two functions,
+// selected with SKINNING_LINEAR or SKINNING_DUAL_QUATERNION. Shaders using
+// it bind the matching palette at group 3.
+
+struct SkinInput {
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+}
+
+//!ifdef SKINNING_DUAL_QUATERNION
+struct DualQuat {
+    real: vec4<f32>,
+    dual: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<storage, read> joint_palette: array<DualQuat>;
+
+fn quat_rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Blends the vertex's joints, flipping each onto the hemisphere of the most
+// weighted one so opposite signs of the same rotation don't cancel out.
+fn blend_joints(skin: SkinInput) -> DualQuat {
+    var dominant = 0u;
+    for (var i = 1u; i < 4u; i++) {
+        if skin.weights[i] > skin.weights[dominant] {
+            dominant = i;
+        }
+    }
+    let pivot = joint_palette[skin.joints[dominant]].real;
+    var out = DualQuat(vec4<f32>(0.0), vec4<f32>(0.0));
+    for (var i = 0u; i < 4u; i++) {
+        let dq = joint_palette[skin.joints[i]];
+        let weight = select(skin.weights[i], -skin.weights[i], dot(dq.real, pivot) < 0.0);
+        out.real += dq.real * weight;
+        out.dual += dq.dual * weight;
+    }
+    let length = length(out.real);
+    return DualQuat(out.real / length, out.dual / length);
+}
+
+fn skin_position(skin: SkinInput, position: vec3<f32>) -> vec3<f32> {
+    let dq = blend_joints(skin);
+    let r = dq.real;
+    let d = dq.dual;
+    let translation = 2.0 * (r.w * d.xyz - d.w * r.xyz + cross(r.xyz, d.xyz));
+    return quat_rotate(r, position) + translation;
+}
+
+fn skin_normal(skin: SkinInput, normal: vec3<f32>) -> vec3<f32> {
+    return normalize(quat_rotate(blend_joints(skin).real, normal));
+}
+//!endif
+
+//!ifdef SKINNING_LINEAR
+@group(3) @binding(0)
+var<storage, read> joint_palette: array<mat4x4<f32>>;
+
+fn blend_joints(skin: SkinInput) -> mat4x4<f32> {
+    return joint_palette[skin.joints.x] * skin.weights.x
+        + joint_palette[skin.joints.y] * skin.weights.y
+        + joint_palette[skin.joints.z] * skin.weights.z
+        + joint_palette[skin.joints.w] * skin.weights.w;
+}
+
+fn skin_position(skin: SkinInput, position: vec3<f32>) -> vec3<f32> {
+    return (blend_joints(skin) * vec4<f32>(position, 1.0)).xyz;
+}
+
+fn skin_normal(skin: SkinInput, normal: vec3<f32>) -> vec3<f32> {
+    let m = blend_joints(skin);
+    return normalize(mat3x3<f32>(m[0].xyz, m[1].xyz, m[2].xyz) * normal);
+}
+//!endif
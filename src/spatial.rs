@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use cgmath::{Point3, Vector3};
+
+use crate::{accel::Ray, bounds::Aabb};
+
+/// Identifies an entry in a [`SpatialHash`]. Ids of removed entries are
+/// reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntryId(pub u32);
+
+type Cell = (i32, i32, i32);
+
+struct Entry<T> {
+    aabb: Aabb,
+    value: T,
+}
+
+/// Entries bucketed by the uniform grid cells their boxes overlap, for fast
+/// overlap and ray queries when entries are of similar size. Unlike
+/// [`SceneBvh`](crate::accel::SceneBvh) inserting and removing is cheap, so
+/// it suits placement tools that add entries one at a time.
+///
+/// Queries return each entry once, in id order, however many cells it
+/// covers, so results don't depend on the map's iteration order.
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<u32>>,
+    entries: Vec<Option<Entry<T>>>,
+    free: Vec<u32>,
+    /// Covers every entry ever inserted, to bound ray walks.
+    bounds: Option<Aabb>,
+}
+
+impl<T> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "cell size must be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            bounds: None,
+        }
+    }
+
+    /// A cell size for entries like `aabbs`: twice their average largest
+    /// extent, so a typical entry covers a few cells rather than many.
+    /// `None` if there are no entries or all are points.
+    pub fn suggested_cell_size<'a, I>(aabbs: I) -> Option<f32>
+    where
+        I: IntoIterator<Item = &'a Aabb>,
+    {
+        let (sum, count) = aabbs.into_iter().fold((0.0, 0), |(sum, count), aabb| {
+            let size = aabb.size();
+            (sum + size.x.max(size.y).max(size.z), count + 1)
+        });
+        (sum > 0.0).then(|| 2.0 * sum / count as f32)
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn cell(&self, p: Point3<f32>) -> Cell {
+        (
+            (p.x / self.cell_size).floor() as i32,
+            (p.y / self.cell_size).floor() as i32,
+            (p.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cells_overlapping(&self, aabb: &Aabb) -> impl Iterator<Item = Cell> {
+        let (min, max) = (self.cell(aabb.min), self.cell(aabb.max));
+        (min.0..=max.0).flat_map(move |x| {
+            (min.1..=max.1).flat_map(move |y| (min.2..=max.2).map(move |z| (x, y, z)))
+        })
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, value: T) -> EntryId {
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.entries[id as usize] = Some(Entry { aabb, value });
+                id
+            }
+            None => {
+                self.entries.push(Some(Entry { aabb, value }));
+                self.entries.len() as u32 - 1
+            }
+        };
+        let cells = self.cells_overlapping(&aabb).collect::<Vec<_>>();
+        for cell in cells {
+            self.cells.entry(cell).or_default().push(id);
+        }
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => bounds.union(&aabb),
+            None => aabb,
+        });
+        EntryId(id)
+    }
+
+    pub fn remove(&mut self, id: EntryId) -> Option<T> {
+        let entry = self.entries.get_mut(id.0 as usize)?.take()?;
+        let cells = self.cells_overlapping(&entry.aabb).collect::<Vec<_>>();
+        for cell in cells {
+            if let Some(ids) = self.cells.get_mut(&cell) {
+                ids.retain(|&i| i != id.0);
+                if ids.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+        self.free.push(id.0);
+        Some(entry.value)
+    }
+
+    pub fn get(&self, id: EntryId) -> Option<(&Aabb, &T)> {
+        let entry = self.entries.get(id.0 as usize)?.as_ref()?;
+        Some((&entry.aabb, &entry.value))
+    }
+
+    /// Every entry, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntryId, &Aabb, &T)> {
+        self.entries.iter().enumerate().filter_map(|(i, entry)| {
+            let entry = entry.as_ref()?;
+            Some((EntryId(i as u32), &entry.aabb, &entry.value))
+        })
+    }
+
+    /// Looks up the ids in `cells`, deduped and in id order.
+    fn collect(&self, cells: impl Iterator<Item = Cell>) -> Vec<EntryId> {
+        let mut ids = cells
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|&id| EntryId(id))
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Entries whose boxes overlap `aabb`, including just touching.
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<EntryId> {
+        let mut ids = self.collect(self.cells_overlapping(aabb));
+        ids.retain(|&id| self.get(id).is_some_and(|(a, _)| a.intersects(aabb)));
+        ids
+    }
+
+    /// Entries whose boxes `ray` passes through, walking only the cells
+    /// along it.
+    pub fn query_ray(&self, ray: &Ray) -> Vec<EntryId> {
+        let Some(bounds) = self.bounds else {
+            return Vec::new();
+        };
+        let Some(t_enter) = ray.intersect_aabb(&bounds) else {
+            return Vec::new();
+        };
+
+        // Amanatides and Woo's voxel walk, from where the ray enters the
+        // occupied space until it leaves it.
+        let end = self.cell(bounds.max);
+        let begin = self.cell(bounds.min);
+        // Rounding can put the entry point just outside the occupied cells.
+        let start = self.cell(ray.at(t_enter));
+        let mut cell = (
+            start.0.clamp(begin.0, end.0),
+            start.1.clamp(begin.1, end.1),
+            start.2.clamp(begin.2, end.2),
+        );
+        let step = ray.direction.map(|d| if d >= 0.0 { 1 } else { -1 });
+        // Distance along the ray to the first boundary crossed on an axis,
+        // and between crossings. Infinite along axes the ray doesn't move on,
+        // so they never step.
+        let first_crossing = |c: i32, s: i32, o: f32, d: f32| {
+            if d == 0.0 {
+                f32::INFINITY
+            } else {
+                ((c + (s > 0) as i32) as f32 * self.cell_size - o) / d
+            }
+        };
+        let mut t_max = Vector3::new(
+            first_crossing(cell.0, step.x, ray.origin.x, ray.direction.x),
+            first_crossing(cell.1, step.y, ray.origin.y, ray.direction.y),
+            first_crossing(cell.2, step.z, ray.origin.z, ray.direction.z),
+        );
+        let t_delta = ray.direction.map(|d| (self.cell_size / d).abs());
+
+        let mut cells = Vec::new();
+        let inside = |c: Cell| {
+            (begin.0..=end.0).contains(&c.0)
+                && (begin.1..=end.1).contains(&c.1)
+                && (begin.2..=end.2).contains(&c.2)
+        };
+        while inside(cell) {
+            cells.push(cell);
+            if t_max.x <= t_max.y && t_max.x <= t_max.z {
+                cell.0 += step.x;
+                t_max.x += t_delta.x;
+            } else if t_max.y <= t_max.z {
+                cell.1 += step.y;
+                t_max.y += t_delta.y;
+            } else {
+                cell.2 += step.z;
+                t_max.z += t_delta.z;
+            }
+        }
+
+        let mut ids = self.collect(cells.into_iter());
+        ids.retain(|&id| {
+            self.get(id)
+                .is_some_and(|(a, _)| ray.intersect_aabb(a).is_some())
+        });
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    /// A small deterministic generator, so failures reproduce.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+
+        fn point(&mut self, extent: f32) -> Point3<f32> {
+            Point3::new(
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+            )
+        }
+
+        /// Mostly smaller than a unit cell, some spanning many of them.
+        fn aabb(&mut self, extent: f32) -> Aabb {
+            let center = self.point(extent);
+            let largest = if self.next() < 0.2 { 6.0 } else { 0.8 };
+            let half = Vector3::new(
+                self.range(0.01, largest),
+                self.range(0.01, largest),
+                self.range(0.01, largest),
+            );
+            Aabb::new(center - half, center + half)
+        }
+
+        fn ray(&mut self) -> Ray {
+            let direction = Vector3::new(
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+            );
+            Ray::new(self.point(30.0), direction.normalize())
+        }
+    }
+
+    /// What the hash should hold, by id.
+    type Expected = Vec<Option<Aabb>>;
+
+    fn brute_force_aabb(expected: &Expected, region: &Aabb) -> Vec<EntryId> {
+        expected
+            .iter()
+            .enumerate()
+            .filter(|(_, aabb)| matches!(aabb, Some(a) if a.intersects(region)))
+            .map(|(i, _)| EntryId(i as u32))
+            .collect()
+    }
+
+    fn brute_force_ray(expected: &Expected, ray: &Ray) -> Vec<EntryId> {
+        expected
+            .iter()
+            .enumerate()
+            .filter(|(_, aabb)| matches!(aabb, Some(a) if ray.intersect_aabb(a).is_some()))
+            .map(|(i, _)| EntryId(i as u32))
+            .collect()
+    }
+
+    fn check_queries(hash: &SpatialHash<usize>, expected: &Expected, rng: &mut Lcg) {
+        let live = expected.iter().flatten().copied().collect::<Vec<_>>();
+        for i in 0..200 {
+            let mut ray = rng.ray();
+            // Aim every other ray at an entry, so most of them hit something.
+            if i % 2 == 0 && !live.is_empty() {
+                let target = live[i % live.len()].center();
+                ray.direction = (target - ray.origin).normalize();
+            }
+            assert_eq!(
+                hash.query_ray(&ray),
+                brute_force_ray(expected, &ray),
+                "{:?}",
+                ray
+            );
+        }
+        for _ in 0..100 {
+            let region = rng.aabb(20.0);
+            assert_eq!(
+                hash.query_aabb(&region),
+                brute_force_aabb(expected, &region),
+                "{:?}",
+                region
+            );
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Lcg(5);
+        for count in [0, 1, 3, 40, 400] {
+            let mut hash = SpatialHash::new(1.0);
+            let mut expected = Expected::new();
+            for i in 0..count {
+                let aabb = rng.aabb(20.0);
+                assert_eq!(hash.insert(aabb, i), EntryId(i as u32));
+                expected.push(Some(aabb));
+            }
+            assert_eq!(hash.len(), count);
+            check_queries(&hash, &expected, &mut rng);
+        }
+    }
+
+    #[test]
+    fn entries_straddling_many_cells_are_found_once() {
+        let mut hash = SpatialHash::new(0.5);
+        let large = Aabb::new(Point3::new(-7.3, -2.1, -9.9), Point3::new(-0.2, 4.4, -1.0));
+        let small = Aabb::new(Point3::new(-3.1, 0.0, -5.1), Point3::new(-3.0, 0.1, -5.0));
+        let large_id = hash.insert(large, 0);
+        let small_id = hash.insert(small, 1);
+
+        let region = Aabb::new(Point3::new(-4.0, -1.0, -6.0), Point3::new(-2.0, 1.0, -4.0));
+        assert_eq!(hash.query_aabb(&region), vec![large_id, small_id]);
+        let ray = Ray::new(Point3::new(-20.0, 0.05, -5.05), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(hash.query_ray(&ray), vec![large_id, small_id]);
+        let above = Ray::new(Point3::new(-20.0, 5.0, -5.05), Vector3::new(1.0, 0.0, 0.0));
+        assert!(hash.query_ray(&above).is_empty());
+    }
+
+    #[test]
+    fn removed_ids_are_reused() {
+        let mut rng = Lcg(13);
+        let mut hash = SpatialHash::new(1.5);
+        let mut expected = Expected::new();
+        for i in 0..100 {
+            let aabb = rng.aabb(15.0);
+            hash.insert(aabb, i);
+            expected.push(Some(aabb));
+        }
+
+        for id in (0..100).step_by(3) {
+            assert_eq!(hash.remove(EntryId(id)), Some(id as usize));
+            assert_eq!(hash.remove(EntryId(id)), None);
+            expected[id as usize] = None;
+        }
+        assert_eq!(hash.len(), 66);
+        check_queries(&hash, &expected, &mut rng);
+
+        // Reinserted entries take the freed ids, and nothing of what held
+        // the id before is found through them.
+        for i in 0..20 {
+            let aabb = rng.aabb(15.0);
+            let id = hash.insert(aabb, 1000 + i);
+            assert!(expected[id.0 as usize].is_none(), "{:?} was live", id);
+            assert_eq!(hash.get(id), Some((&aabb, &(1000 + i))));
+            expected[id.0 as usize] = Some(aabb);
+        }
+        assert_eq!(hash.len(), 86);
+        check_queries(&hash, &expected, &mut rng);
+
+        let ids = hash.iter().map(|(id, _, _)| id).collect::<Vec<_>>();
+        let live = (0..expected.len())
+            .filter(|&i| expected[i].is_some())
+            .map(|i| EntryId(i as u32))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, live);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+/// Returned by the loaders when a mesh needs a bigger buffer than the device
+/// allows and splitting is disabled. Sizes are in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLarge {
+    pub required: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mesh needs a {} byte buffer but the device allows at most {}",
+            self.required, self.limit
+        )
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// The largest vertex and index counts whose buffers stay within
+/// `max_buffer_size`. The index count is kept to whole triangles.
+pub fn chunk_limits(vertex_size: usize, max_buffer_size: u64) -> (usize, usize) {
+    let vertex_size = vertex_size.max(1) as u64;
+    let index_size = std::mem::size_of::<u32>() as u64;
+    let max_vertices = (max_buffer_size / vertex_size).min(u32::MAX as u64) as usize;
+    let max_indices = (max_buffer_size / index_size) as usize / 3 * 3;
+    (max_vertices, max_indices)
+}
+
+/// Checks the vertex and index buffers for a mesh against `max_buffer_size`.
+pub fn check_size(
+    vertex_count: usize,
+    vertex_size: usize,
+    index_count: usize,
+    max_buffer_size: u64,
+) -> Result<(), TooLarge> {
+    let vertex_bytes = (vertex_count * vertex_size) as u64;
+    let index_bytes = (index_count * std::mem::size_of::<u32>()) as u64;
+    let required = vertex_bytes.max(index_bytes);
+    if required > max_buffer_size {
+        Err(TooLarge {
+            required,
+            limit: max_buffer_size,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Splits an indexed triangle list into chunks of at most `max_vertices`
+/// vertices and `max_indices` indices. Triangles keep their order, and each
+/// chunk gets its own copy of the vertices it uses with indices rebased to
+/// them, so drawing every chunk gives the same geometry as the original.
+pub fn split_triangles<V: Copy>(
+    vertices: &[V],
+    indices: &[u32],
+    max_vertices: usize,
+    max_indices: usize,
+) -> Vec<(Vec<V>, Vec<u32>)> {
+    // A chunk must be able to hold at least one triangle.
+    let max_vertices = max_vertices.max(3);
+    let max_indices = max_indices.max(3);
+
+    let mut chunks = Vec::new();
+    let mut chunk_vertices = Vec::new();
+    let mut chunk_indices = Vec::new();
+    let mut remap = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let new_vertices = triangle.iter().filter(|i| !remap.contains_key(*i)).count();
+        if chunk_indices.len() + 3 > max_indices
+            || chunk_vertices.len() + new_vertices > max_vertices
+        {
+            chunks.push((
+                std::mem::take(&mut chunk_vertices),
+                std::mem::take(&mut chunk_indices),
+            ));
+            remap.clear();
+        }
+        for &index in triangle {
+            let local = *remap.entry(index).or_insert_with(|| {
+                chunk_vertices.push(vertices[index as usize]);
+                chunk_vertices.len() as u32 - 1
+            });
+            chunk_indices.push(local);
+        }
+    }
+    if !chunk_indices.is_empty() {
+        chunks.push((chunk_vertices, chunk_indices));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width` by `height` grid of quads, two triangles each. Vertices are
+    /// their own positions so chunks can be checked against the original.
+    fn grid(width: u32, height: u32) -> (Vec<[u32; 2]>, Vec<u32>) {
+        let vertices = (0..=height)
+            .flat_map(|y| (0..=width).map(move |x| [x, y]))
+            .collect();
+        let mut indices = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * (width + 1) + x;
+                let j = i + width + 1;
+                indices.extend_from_slice(&[i, j, i + 1, i + 1, j, j + 1]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    fn triangles<V: Copy>(vertices: &[V], indices: &[u32]) -> Vec<[V; 3]> {
+        indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]].map(|i| vertices[i as usize]))
+            .collect()
+    }
+
+    fn check_chunks(
+        vertices: &[[u32; 2]],
+        indices: &[u32],
+        max_vertices: usize,
+        max_indices: usize,
+    ) -> Vec<(Vec<[u32; 2]>, Vec<u32>)> {
+        let chunks = split_triangles(vertices, indices, max_vertices, max_indices);
+        let mut reassembled = Vec::new();
+        for (chunk_vertices, chunk_indices) in &chunks {
+            assert!(chunk_vertices.len() <= max_vertices);
+            assert!(chunk_indices.len() <= max_indices);
+            assert_eq!(chunk_indices.len() % 3, 0);
+            // Every vertex in a chunk is used by one of its triangles.
+            let mut used = vec![false; chunk_vertices.len()];
+            for &i in chunk_indices {
+                used[i as usize] = true;
+            }
+            assert!(used.iter().all(|&u| u));
+            reassembled.extend(triangles(chunk_vertices, chunk_indices));
+        }
+        assert_eq!(reassembled, triangles(vertices, indices));
+        chunks
+    }
+
+    #[test]
+    fn mesh_within_limits_stays_whole() {
+        let (vertices, indices) = grid(4, 4);
+        let chunks = check_chunks(&vertices, &indices, vertices.len(), indices.len());
+        // Vertices are reordered by first use, but none are duplicated.
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0.len(), vertices.len());
+    }
+
+    #[test]
+    fn index_limit_splits_on_triangle_boundaries() {
+        let (vertices, indices) = grid(4, 1);
+        // Room for exactly three triangles per chunk.
+        let chunks = check_chunks(&vertices, &indices, usize::MAX, 9);
+        let sizes: Vec<_> = chunks.iter().map(|(_, i)| i.len()).collect();
+        assert_eq!(sizes, [9, 9, 6]);
+        // Limits that aren't whole triangles round down.
+        let chunks = check_chunks(&vertices, &indices, usize::MAX, 11);
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn vertex_limit_counts_shared_vertices_once() {
+        let (vertices, indices) = grid(4, 1);
+        // One quad is four vertices, each further quad along the strip
+        // shares an edge and adds two.
+        let chunks = check_chunks(&vertices, &indices, 6, usize::MAX);
+        let sizes: Vec<_> = chunks.iter().map(|(v, _)| v.len()).collect();
+        assert_eq!(sizes, [6, 6]);
+        // One vertex short, and the second quad's last triangle no longer
+        // fits.
+        let chunks = check_chunks(&vertices, &indices, 5, usize::MAX);
+        assert_eq!(chunks[0].0.len(), 5);
+        assert_eq!(chunks[0].1.len(), 9);
+    }
+
+    #[test]
+    fn tiny_limits_still_hold_a_triangle() {
+        let (vertices, indices) = grid(2, 2);
+        let chunks = split_triangles(&vertices, &indices, 1, 1);
+        assert_eq!(chunks.len(), indices.len() / 3);
+        assert!(chunks.iter().all(|(v, i)| v.len() == 3 && i == &[0, 1, 2]));
+        assert!(split_triangles(&vertices, &[], 3, 3).is_empty());
+    }
+
+    #[test]
+    fn limits_come_from_the_buffer_size() {
+        assert_eq!(chunk_limits(32, 320), (10, 78));
+        assert_eq!(chunk_limits(0, 12), (12, 3));
+        assert_eq!(chunk_limits(4, u64::MAX).0, u32::MAX as usize);
+
+        assert_eq!(check_size(10, 32, 80, 320), Ok(()));
+        assert_eq!(
+            check_size(11, 32, 80, 320),
+            Err(TooLarge {
+                required: 352,
+                limit: 320
+            })
+        );
+        assert_eq!(
+            check_size(10, 32, 81, 320),
+            Err(TooLarge {
+                required: 324,
+                limit: 320
+            })
+        );
+    }
+
+    #[test]
+    fn too_large_survives_context_for_callers_to_downcast() {
+        let error = anyhow::Error::from(check_size(11, 32, 80, 320).unwrap_err())
+            .context("Loading big.obj");
+        assert_eq!(
+            error.downcast_ref::<TooLarge>(),
+            Some(&TooLarge {
+                required: 352,
+                limit: 320
+            })
+        );
+        assert_eq!(
+            error.root_cause().to_string(),
+            "mesh needs a 352 byte buffer but the device allows at most 320"
+        );
+    }
+}
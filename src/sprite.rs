@@ -0,0 +1,741 @@
+use std::ops::Range;
+
+use cgmath::{Matrix4, Point3};
+
+use crate::{
+    frame::{ArenaVec, FrameArena, Watermark},
+    gpu,
+    math::projection,
+    model::Vertex,
+    stats::FrameStats,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteVertex {
+    pub position: [f32; 2],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl Vertex for SpriteVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SpriteVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// A texture registered with [`SpriteBatch::add_texture`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpriteTexture(usize);
+
+/// Keeps the borders of a sprite at a fixed size while the middle stretches,
+/// for UI panels. Insets are left, top, right and bottom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NineSlice {
+    /// Border sizes in pixels on screen.
+    pub insets: [f32; 4],
+    /// Border sizes as fractions of the sprite's texture region.
+    pub uv_insets: [f32; 4],
+}
+
+/// Where a sprite is placed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpriteSpace {
+    /// In logical pixels with the origin at the top left of the window.
+    Screen,
+    /// Facing the camera from `anchor`, a point in the world. Sizes and
+    /// insets are in world units, and `position` moves the sprite away from
+    /// the anchor along the camera's right and down. World sprites still draw
+    /// over the scene, like markers, as the sprite pass has no depth.
+    World { anchor: [f32; 3] },
+}
+
+/// The 3D camera world sprites are projected through, set with
+/// [`SpriteBatch::set_camera`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteCamera {
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+/// A textured quad. Positions and sizes are in logical pixels with the
+/// origin at the top left of the window, or in world units for sprites in
+/// [`SpriteSpace::World`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub texture: SpriteTexture,
+    /// The part of the texture to show, e.g. from
+    /// [`AtlasEntry::remap_uv`](crate::atlas::AtlasEntry::remap_uv).
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    /// Where the sprite's `origin` ends up on screen.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    /// The point rotated and positioned around, as a fraction of the size.
+    pub origin: [f32; 2],
+    /// Clockwise rotation in radians.
+    pub rotation: f32,
+    /// A tint, sRGB encoded with straight alpha.
+    pub color: [f32; 4],
+    /// Higher layers are drawn on top.
+    pub layer: i32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub nine_slice: Option<NineSlice>,
+    pub space: SpriteSpace,
+}
+
+impl Sprite {
+    /// A sprite showing the whole texture with its top left at `position`.
+    pub fn new(texture: SpriteTexture, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            texture,
+            uv_min: [0.0, 0.0],
+            uv_max: [1.0, 1.0],
+            position,
+            size,
+            origin: [0.0, 0.0],
+            rotation: 0.0,
+            color: [1.0; 4],
+            layer: 0,
+            flip_x: false,
+            flip_y: false,
+            nine_slice: None,
+            space: SpriteSpace::Screen,
+        }
+    }
+
+    /// A sprite showing the whole texture centred on `anchor` in the world,
+    /// `size` world units across.
+    pub fn in_world(texture: SpriteTexture, anchor: [f32; 3], size: [f32; 2]) -> Self {
+        Self {
+            origin: [0.5, 0.5],
+            space: SpriteSpace::World { anchor },
+            ..Self::new(texture, [0.0, 0.0], size)
+        }
+    }
+
+    /// The screen sprite a world sprite is drawn as on a window `viewport`
+    /// logical pixels across, or `None` if it is behind `camera`. Screen
+    /// sprites come back as they are.
+    ///
+    /// A quad facing the camera stays a rectangle under perspective, only
+    /// scaled by its distance, so projecting the anchor and scaling the rest
+    /// is exact.
+    pub fn on_screen(&self, camera: &SpriteCamera, viewport: [f32; 2]) -> Option<Self> {
+        let SpriteSpace::World { anchor } = self.space else {
+            return Some(*self);
+        };
+        let clip = camera.projection * camera.view * Point3::from(anchor).to_homogeneous();
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+        // Pixels per world unit at the anchor's distance.
+        let scale = [
+            camera.projection.x.x / clip.w * viewport[0] / 2.0,
+            camera.projection.y.y / clip.w * viewport[1] / 2.0,
+        ];
+        let scaled = |[x, y]: [f32; 2]| [x * scale[0], y * scale[1]];
+        let center = [
+            (clip.x / clip.w + 1.0) / 2.0 * viewport[0],
+            (1.0 - clip.y / clip.w) / 2.0 * viewport[1],
+        ];
+        let offset = scaled(self.position);
+        Some(Self {
+            position: [center[0] + offset[0], center[1] + offset[1]],
+            size: scaled(self.size),
+            nine_slice: self.nine_slice.map(|slice| {
+                let [l, t, r, b] = slice.insets;
+                let ([l, t], [r, b]) = (scaled([l, t]), scaled([r, b]));
+                NineSlice {
+                    insets: [l, t, r, b],
+                    ..slice
+                }
+            }),
+            space: SpriteSpace::Screen,
+            ..*self
+        })
+    }
+
+    /// The sprite's quads, four vertices each in the order top left, top
+    /// right, bottom right, bottom left. Nine-sliced sprites have nine quads.
+    /// World sprites should be put on screen with [`Self::on_screen`] first.
+    pub fn vertices(&self) -> Vec<SpriteVertex> {
+        let quads = if self.nine_slice.is_some() { 9 } else { 1 };
+        let mut vertices = Vec::with_capacity(quads * 4);
+        self.extend_vertices(&mut vertices);
+        vertices
+    }
+
+    /// Appends [`Self::vertices`] to `out` without allocating a vector.
+    pub fn extend_vertices(&self, out: &mut impl Extend<SpriteVertex>) {
+        let [w, h] = self.size;
+        // Slice edges in pixels and as fractions of the texture region.
+        let nine_slice_edges: [[f32; 4]; 4];
+        let edges: [[f32; 2]; 4];
+        let (xs, us, ys, vs): (&[f32], &[f32], &[f32], &[f32]) = match &self.nine_slice {
+            Some(slice) => {
+                let [l, t, r, b] = slice.insets;
+                // Shrink the borders if the sprite is smaller than them.
+                let sx = if l + r > w { w / (l + r) } else { 1.0 };
+                let sy = if t + b > h { h / (t + b) } else { 1.0 };
+                let [ul, ut, ur, ub] = slice.uv_insets;
+                nine_slice_edges = [
+                    [0.0, l * sx, w - r * sx, w],
+                    [0.0, ul, 1.0 - ur, 1.0],
+                    [0.0, t * sy, h - b * sy, h],
+                    [0.0, ut, 1.0 - ub, 1.0],
+                ];
+                let [xs, us, ys, vs] = &nine_slice_edges;
+                (&xs[..], &us[..], &ys[..], &vs[..])
+            }
+            None => {
+                edges = [[0.0, w], [0.0, 1.0], [0.0, h], [0.0, 1.0]];
+                let [xs, us, ys, vs] = &edges;
+                (&xs[..], &us[..], &ys[..], &vs[..])
+            }
+        };
+
+        let (sin, cos) = self.rotation.sin_cos();
+        let pivot = [self.origin[0] * w, self.origin[1] * h];
+        let transform = |x: f32, y: f32| {
+            let (x, y) = (x - pivot[0], y - pivot[1]);
+            // Y points down on screen, so this turns clockwise.
+            [
+                self.position[0] + x * cos - y * sin,
+                self.position[1] + x * sin + y * cos,
+            ]
+        };
+        let uv = |u: f32, v: f32| {
+            let u = if self.flip_x { 1.0 - u } else { u };
+            let v = if self.flip_y { 1.0 - v } else { v };
+            [
+                self.uv_min[0] + u * (self.uv_max[0] - self.uv_min[0]),
+                self.uv_min[1] + v * (self.uv_max[1] - self.uv_min[1]),
+            ]
+        };
+
+        for row in 0..ys.len() - 1 {
+            for column in 0..xs.len() - 1 {
+                out.extend([(0, 0), (1, 0), (1, 1), (0, 1)].map(|(c, r)| SpriteVertex {
+                    position: transform(xs[column + c], ys[row + r]),
+                    tex_coords: uv(us[column + c], vs[row + r]),
+                    color: self.color,
+                }));
+            }
+        }
+    }
+}
+
+/// Collects sprites over a frame and draws them in as few draws as possible.
+/// Sprites are sorted by layer and then texture, so the order of sprites
+/// sharing a layer isn't preserved across textures.
+pub struct SpriteBatch {
+    pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    _camera_memory: gpu::MemoryToken,
+    camera_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    _buffer_memory: [gpu::MemoryToken; 2],
+    quad_capacity: usize,
+    textures: Vec<wgpu::BindGroup>,
+    sprites: Vec<Sprite>,
+    camera: Option<SpriteCamera>,
+    draws: Vec<(SpriteTexture, Range<u32>)>,
+    /// Sort keys and vertices, reset every [`Self::prepare`].
+    arena: FrameArena,
+}
+
+impl SpriteBatch {
+    /// `texture_bind_group_layout` is the same layout materials use, so
+    /// bind groups for [`Self::add_texture`] can be made with it.
+    /// `color_format` is [`UiCompositor::format`](crate::ui::UiCompositor::format)
+    /// for sprites drawn as UI.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self, gpu::AllocError> {
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("sprite_camera_bind_group_layout"),
+            });
+        let (camera_buffer, camera_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Sprite Camera Buffer"),
+                    contents: bytemuck::cast_slice(&[[[0.0f32; 4]; 4]]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("sprite_camera_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("sprite.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sprite.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[texture_bind_group_layout, &camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SpriteVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                // Flipped sprites turn their quads around.
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let quad_capacity = 64;
+        let (vertex_buffer, index_buffer, buffer_memory) =
+            Self::create_buffers(device, quad_capacity)?;
+        Ok(Self {
+            pipeline,
+            camera_buffer,
+            _camera_memory: camera_buffer_memory,
+            camera_bind_group,
+            vertex_buffer,
+            index_buffer,
+            _buffer_memory: buffer_memory,
+            quad_capacity,
+            textures: Vec::new(),
+            sprites: Vec::new(),
+            camera: None,
+            draws: Vec::new(),
+            arena: FrameArena::new(),
+        })
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        quads: usize,
+    ) -> Result<(wgpu::Buffer, wgpu::Buffer, [gpu::MemoryToken; 2]), gpu::AllocError> {
+        let (vertex_buffer, vertex_buffer_memory) = gpu::Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Sprite Vertex Buffer"),
+                size: (quads * 4 * std::mem::size_of::<SpriteVertex>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+            gpu::MemoryCategory::Vertex,
+        )?;
+        // Every quad uses the same pattern, so the indices only change when
+        // the buffers grow.
+        let indices = (0..quads as u32)
+            .flat_map(|q| {
+                let base = q * 4;
+                [base, base + 1, base + 2, base, base + 2, base + 3]
+            })
+            .collect::<Vec<_>>();
+        let (index_buffer, index_buffer_memory) = gpu::Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+            gpu::MemoryCategory::Index,
+        )?;
+        Ok((
+            vertex_buffer,
+            index_buffer,
+            [vertex_buffer_memory, index_buffer_memory],
+        ))
+    }
+
+    /// Registers a texture bind group, e.g. an atlas page's. The texture
+    /// is read as it's stored, so it should hold sRGB encoded values with
+    /// premultiplied alpha in a format that doesn't decode them, like those
+    /// from [`Texture::from_ui_image`](crate::texture::Texture::from_ui_image).
+    /// Material textures are decoded when read and come out too dark.
+    pub fn add_texture(&mut self, bind_group: wgpu::BindGroup) -> SpriteTexture {
+        self.textures.push(bind_group);
+        SpriteTexture(self.textures.len() - 1)
+    }
+
+    /// Queues a sprite for the next [`Self::prepare`].
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    /// Sets the camera world sprites are projected through from the next
+    /// [`Self::prepare`] on. Without one, world sprites aren't drawn.
+    pub fn set_camera(&mut self, camera: SpriteCamera) {
+        self.camera = Some(camera);
+    }
+
+    /// Builds this frame's vertices from the queued sprites and clears the
+    /// queue. `size` and `scale_factor` come from the window, so sprites keep
+    /// their logical size across resizes and DPI changes. If the buffers had
+    /// to grow and couldn't, nothing is drawn.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+    ) -> Result<(), gpu::AllocError> {
+        let logical = size.to_logical::<f32>(scale_factor);
+        let view_proj: [[f32; 4]; 4] = projection::orthographic(
+            0.0,
+            logical.width,
+            logical.height,
+            0.0,
+            -1.0,
+            1.0,
+            projection::DepthRange::Standard,
+        )
+        .into();
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+
+        self.arena.reset();
+        let vertices = batch(
+            &self.arena,
+            &self.sprites,
+            self.camera.as_ref(),
+            [logical.width, logical.height],
+            &mut self.draws,
+        );
+        self.sprites.clear();
+
+        let quads = vertices.len() / 4;
+        if quads > self.quad_capacity {
+            let capacity = quads.next_power_of_two();
+            let (vertex_buffer, index_buffer, buffer_memory) =
+                Self::create_buffers(device, capacity).inspect_err(|_| self.draws.clear())?;
+            self.vertex_buffer = vertex_buffer;
+            self.index_buffer = index_buffer;
+            self._buffer_memory = buffer_memory;
+            self.quad_capacity = capacity;
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        Ok(())
+    }
+
+    /// How much of the arena [`Self::prepare`] uses, for pre-sizing it with
+    /// [`Self::reserve_arena`].
+    pub fn arena_watermark(&self) -> Watermark {
+        self.arena.watermark()
+    }
+
+    pub fn reserve_arena(&mut self, bytes: usize) {
+        self.arena.reset();
+        self.arena.reserve(bytes);
+    }
+
+    /// Whether the last [`Self::prepare`] produced anything to draw.
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    /// Adds the commands [`Self::draw`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats) {
+        if self.draws.is_empty() {
+            return;
+        }
+        stats.pipeline_sets += 1;
+        stats.bind_group_sets += 1 + self.draws.len() as u32;
+        stats.draw_calls += self.draws.len() as u32;
+        for (_, range) in &self.draws {
+            stats.instances += 1;
+            stats.triangles += ((range.end - range.start) / 3) as u64;
+        }
+    }
+
+    /// Draws the prepared sprites. Call in a pass after the 3D scene, without
+    /// a depth attachment.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.draws.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        for (texture, range) in &self.draws {
+            render_pass.set_bind_group(0, &self.textures[texture.0], &[]);
+            render_pass.draw_indexed(range.clone(), 0, 0..1);
+        }
+    }
+}
+
+/// Sorts `sprites` by layer and then texture into `draws`, index ranges
+/// into the returned vertices, which are in draw order. World sprites are
+/// projected through `camera` onto a `viewport` logical pixels across, and
+/// left out if they can't be.
+fn batch<'a>(
+    arena: &'a FrameArena,
+    sprites: &[Sprite],
+    camera: Option<&SpriteCamera>,
+    viewport: [f32; 2],
+    draws: &mut Vec<(SpriteTexture, Range<u32>)>,
+) -> ArenaVec<'a, SpriteVertex> {
+    // The index keeps sprites sharing a layer and texture in push order.
+    let mut keys = arena.vec_from_iter(
+        sprites
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.layer, s.texture, i)),
+    );
+    keys.sort_unstable();
+
+    draws.clear();
+    let mut vertices = arena.vec();
+    for &(_, _, i) in keys.iter() {
+        let sprite = match sprites[i].space {
+            SpriteSpace::Screen => sprites[i],
+            SpriteSpace::World { .. } => {
+                match camera.and_then(|camera| sprites[i].on_screen(camera, viewport)) {
+                    Some(sprite) => sprite,
+                    None => continue,
+                }
+            }
+        };
+        let first = (vertices.len() / 4 * 6) as u32;
+        sprite.extend_vertices(&mut vertices);
+        let last = (vertices.len() / 4 * 6) as u32;
+        match draws.last_mut() {
+            Some((texture, range)) if *texture == sprite.texture => range.end = last,
+            _ => draws.push((sprite.texture, first..last)),
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!(
+            (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    fn positions(vertices: &[SpriteVertex]) -> Vec<[f32; 2]> {
+        vertices.iter().map(|v| v.position).collect()
+    }
+
+    fn tex_coords(vertices: &[SpriteVertex]) -> Vec<[f32; 2]> {
+        vertices.iter().map(|v| v.tex_coords).collect()
+    }
+
+    #[test]
+    fn plain_sprite_is_one_clockwise_quad() {
+        let mut sprite = Sprite::new(SpriteTexture(0), [10.0, 20.0], [30.0, 40.0]);
+        sprite.color = [0.5, 0.25, 1.0, 0.75];
+        let vertices = sprite.vertices();
+        assert_eq!(
+            positions(&vertices),
+            [[10.0, 20.0], [40.0, 20.0], [40.0, 60.0], [10.0, 60.0]]
+        );
+        assert_eq!(
+            tex_coords(&vertices),
+            [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]
+        );
+        assert!(vertices.iter().all(|v| v.color == sprite.color));
+    }
+
+    #[test]
+    fn rotation_turns_clockwise_around_the_origin() {
+        let mut sprite = Sprite::new(SpriteTexture(0), [100.0, 100.0], [20.0, 10.0]);
+        sprite.origin = [0.5, 0.5];
+        sprite.rotation = std::f32::consts::FRAC_PI_2;
+        let vertices = sprite.vertices();
+        // The top left corner swings round to the top right, as y is down.
+        let expected = [[105.0, 90.0], [105.0, 110.0], [95.0, 110.0], [95.0, 90.0]];
+        for (position, expected) in positions(&vertices).into_iter().zip(expected) {
+            assert_close(position, expected);
+        }
+    }
+
+    #[test]
+    fn batches_sort_by_layer_then_texture() {
+        let (a, b, c) = (SpriteTexture(0), SpriteTexture(1), SpriteTexture(2));
+        let pushed = [
+            (1, a, 0.0),
+            (0, c, 1.0),
+            (0, b, 2.0),
+            (1, a, 3.0),
+            (0, b, 4.0),
+            (1, b, 5.0),
+        ]
+        .map(|(layer, texture, x)| Sprite {
+            layer,
+            ..Sprite::new(texture, [x, 0.0], [1.0, 1.0])
+        });
+        let arena = FrameArena::new();
+        let mut draws = Vec::new();
+        let vertices = batch(&arena, &pushed, None, [100.0, 100.0], &mut draws);
+        assert_eq!(draws, [(b, 0..12), (c, 12..18), (a, 18..30), (b, 30..36)]);
+        // Sprites sharing a layer and texture keep their push order.
+        let order: Vec<_> = vertices.chunks(4).map(|q| q[0].position[0]).collect();
+        assert_eq!(order, [2.0, 4.0, 1.0, 0.0, 3.0, 5.0]);
+    }
+
+    #[test]
+    fn world_sprites_shrink_with_distance() {
+        let camera = SpriteCamera {
+            view: Matrix4::look_at_rh(
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, -1.0),
+                cgmath::Vector3::unit_y(),
+            ),
+            projection: projection::perspective(
+                cgmath::Deg(90.0).into(),
+                2.0,
+                0.1,
+                100.0,
+                projection::DepthRange::Standard,
+            ),
+        };
+        let viewport = [200.0, 100.0];
+        let near = Sprite::in_world(SpriteTexture(0), [0.0, 0.0, -1.0], [0.5, 0.5]);
+        let shown = near.on_screen(&camera, viewport).unwrap();
+        assert_eq!(shown.space, SpriteSpace::Screen);
+        assert_close(shown.position, [100.0, 50.0]);
+        assert_close(shown.size, [25.0, 25.0]);
+        // Twice as far away, it and its offset from the anchor come out at
+        // half the size.
+        let far = Sprite {
+            position: [0.0, 1.0],
+            ..Sprite::in_world(SpriteTexture(0), [2.0, 0.0, -2.0], [0.5, 0.5])
+        };
+        let shown = far.on_screen(&camera, viewport).unwrap();
+        assert_close(shown.position, [150.0, 75.0]);
+        assert_close(shown.size, [12.5, 12.5]);
+
+        let behind = Sprite::in_world(SpriteTexture(0), [0.0, 0.0, 1.0], [0.5, 0.5]);
+        assert_eq!(behind.on_screen(&camera, viewport), None);
+        let arena = FrameArena::new();
+        let mut draws = Vec::new();
+        let vertices = batch(&arena, &[behind, near], Some(&camera), viewport, &mut draws);
+        assert_eq!(vertices[0].position, [87.5, 37.5]);
+        assert_eq!(vertices.len(), 4);
+        let vertices = batch(&arena, &[behind, near], None, viewport, &mut draws);
+        assert_eq!((vertices.len(), draws.len()), (0, 0));
+    }
+
+    #[test]
+    fn flips_mirror_the_texture_region() {
+        let mut sprite = Sprite::new(SpriteTexture(0), [0.0, 0.0], [8.0, 8.0]);
+        sprite.uv_min = [0.25, 0.5];
+        sprite.uv_max = [0.5, 1.0];
+        assert_eq!(
+            tex_coords(&sprite.vertices()),
+            [[0.25, 0.5], [0.5, 0.5], [0.5, 1.0], [0.25, 1.0]]
+        );
+        sprite.flip_x = true;
+        sprite.flip_y = true;
+        assert_eq!(
+            tex_coords(&sprite.vertices()),
+            [[0.5, 1.0], [0.25, 1.0], [0.25, 0.5], [0.5, 0.5]]
+        );
+    }
+
+    #[test]
+    fn nine_slice_keeps_borders_fixed() {
+        let mut sprite = Sprite::new(SpriteTexture(0), [0.0, 0.0], [100.0, 50.0]);
+        sprite.nine_slice = Some(NineSlice {
+            insets: [4.0, 6.0, 8.0, 10.0],
+            uv_insets: [0.25, 0.25, 0.125, 0.5],
+        });
+        let vertices = sprite.vertices();
+        assert_eq!(vertices.len(), 9 * 4);
+        // The top left of each quad, row by row.
+        let corners: Vec<_> = vertices.chunks(4).map(|q| q[0]).collect();
+        let xs = [0.0, 4.0, 92.0];
+        let ys = [0.0, 6.0, 40.0];
+        let us = [0.0, 0.25, 0.875];
+        let vs = [0.0, 0.25, 0.5];
+        for (i, corner) in corners.iter().enumerate() {
+            let (row, column) = (i / 3, i % 3);
+            assert_eq!(corner.position, [xs[column], ys[row]]);
+            assert_eq!(corner.tex_coords, [us[column], vs[row]]);
+        }
+        // Quads share their edges, so there are no gaps.
+        assert_eq!(vertices[8 * 4 + 2].position, [100.0, 50.0]);
+        assert_eq!(vertices[4 * 4 + 1].position, vertices[5 * 4].position);
+    }
+
+    #[test]
+    fn nine_slice_shrinks_borders_on_small_sprites() {
+        let mut sprite = Sprite::new(SpriteTexture(0), [0.0, 0.0], [6.0, 40.0]);
+        sprite.nine_slice = Some(NineSlice {
+            insets: [4.0, 4.0, 8.0, 4.0],
+            uv_insets: [0.25; 4],
+        });
+        let vertices = sprite.vertices();
+        // The 12 pixels of horizontal border are squeezed into 6, leaving
+        // no middle column. The vertical borders fit and stay as they are.
+        let xs: Vec<_> = vertices[..12].chunks(4).map(|q| q[0].position[0]).collect();
+        assert_eq!(xs, [0.0, 2.0, 2.0]);
+        let ys: Vec<_> = vertices.chunks(12).map(|r| r[0].position[1]).collect();
+        assert_eq!(ys, [0.0, 4.0, 36.0]);
+        // The texture borders aren't squeezed.
+        assert_eq!(vertices[4].tex_coords, [0.25, 0.0]);
+    }
+}
@@ -0,0 +1,42 @@
+// Screen space sprites, tinted by their vertex color. Everything is in sRGB
+// encoded values, as UI is authored: textures have premultiplied alpha and
+// aren't decoded when read, the tint has straight alpha, and the output is
+// premultiplied for a target that stores encoded values. See `ui.rs`.
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 0.0, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let tint = vec4<f32>(in.color.rgb * in.color.a, in.color.a);
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords) * tint;
+}
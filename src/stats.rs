@@ -0,0 +1,105 @@
+pub mod timeline;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::model::Model;
+
+pub use timeline::{
+    note_event, take_events, FrameEvent, FrameRecord, FrameTimeline, FrameTiming, Jank,
+};
+
+/// Seconds since some fixed point, usable for measuring frame times and
+/// loading deadlines.
+pub fn now() -> f64 {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            web_sys::window()
+                .and_then(|w| w.performance())
+                .map(|p| p.now() / 1000.0)
+                .unwrap_or(0.0)
+        } else {
+            use std::sync::OnceLock;
+            use std::time::Instant;
+            static START: OnceLock<Instant> = OnceLock::new();
+            START.get_or_init(Instant::now).elapsed().as_secs_f64()
+        }
+    }
+}
+
+/// Counts of the GPU commands recorded for a frame, for spotting changes
+/// that add draws or state changes to the draw path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub passes: u32,
+    pub pipeline_sets: u32,
+    pub bind_group_sets: u32,
+    pub draw_calls: u32,
+    pub instances: u32,
+    pub triangles: u64,
+    /// Heap allocations while recording the frame. Only counted when
+    /// [`CountingAllocator`] is the global allocator, and 0 otherwise.
+    pub allocations: usize,
+}
+
+impl FrameStats {
+    /// Adds the commands `DrawModel::draw_model_instanced` records.
+    pub fn add_model(&mut self, model: &Model, instances: u32) {
+        for mesh in &model.meshes {
+            if model.materials[mesh.material].refractive.is_some() {
+                continue;
+            }
+            self.bind_group_sets += 2 + mesh.bind_group.is_some() as u32;
+            self.draw_calls += 1;
+            self.instances += instances;
+            self.triangles += (mesh.num_elements / 3) as u64 * instances as u64;
+        }
+    }
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations. Install it in a binary or
+/// test with
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: test2::stats::CountingAllocator = test2::stats::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    /// Allocations, including reallocations, since the program started.
+    pub fn allocations() -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    pub fn allocated_bytes() -> usize {
+        ALLOCATED_BYTES.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
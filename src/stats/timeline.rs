@@ -0,0 +1,332 @@
+//! A history of recent frame times, for spotting stutters rather than just
+//! a lower average frame rate.
+//!
+//! Systems that can cause a long frame, such as model loading, pipeline
+//! builds and target rebuilds, report it with [`note_event`]. Each frame
+//! pushed to a [`FrameTimeline`] takes the events noted since the previous
+//! one, so a frame that is much slower than usual comes with a likely
+//! cause.
+
+use std::{collections::VecDeque, fmt, sync::Mutex};
+
+/// Something done during a frame that may make it slow.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameEvent {
+    /// A model's meshes were uploaded, by file name.
+    AssetIntegrated(String),
+    /// Pipelines were built, by label.
+    PipelineBuilt(String),
+    /// Targets that follow the surface size were rebuilt at a new size.
+    TargetsRebuilt(u32, u32),
+}
+
+impl fmt::Display for FrameEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameEvent::AssetIntegrated(name) => write!(f, "loaded {}", name),
+            FrameEvent::PipelineBuilt(label) => write!(f, "built {}", label),
+            FrameEvent::TargetsRebuilt(width, height) => {
+                write!(f, "rebuilt targets at {}x{}", width, height)
+            }
+        }
+    }
+}
+
+/// Events noted before anything takes them are dropped past this, so
+/// noting is harmless without a timeline.
+const MAX_PENDING_EVENTS: usize = 256;
+
+static PENDING_EVENTS: Mutex<Vec<FrameEvent>> = Mutex::new(Vec::new());
+
+/// Notes `event` for the frame in progress.
+pub fn note_event(event: FrameEvent) {
+    let mut pending = PENDING_EVENTS.lock().unwrap();
+    if pending.len() < MAX_PENDING_EVENTS {
+        pending.push(event);
+    }
+}
+
+/// The events noted since last taken. [`FrameTimeline::push`] calls this.
+pub fn take_events() -> Vec<FrameEvent> {
+    std::mem::take(&mut *PENDING_EVENTS.lock().unwrap())
+}
+
+/// Like [`take_events`], leaving the empty `events` to note the next ones
+/// in, so its capacity is reused.
+fn swap_events(events: &mut Vec<FrameEvent>) {
+    events.clear();
+    std::mem::swap(&mut *PENDING_EVENTS.lock().unwrap(), events);
+}
+
+/// Where a frame's time went, in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTiming {
+    /// Since the previous frame finished, which covers this frame's update
+    /// and recording and any wait before them.
+    pub frame: f64,
+    /// Updating state on the CPU before recording.
+    pub update: f64,
+    /// Recording and submitting commands, including waiting for the
+    /// surface texture.
+    pub record: f64,
+    /// From submitting to presenting, where that can be measured.
+    pub present_latency: Option<f64>,
+    /// On the GPU, where a profiler measured it.
+    pub gpu: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameRecord {
+    /// Counts up from 0 for the timeline's first frame.
+    pub index: u64,
+    pub timing: FrameTiming,
+    pub events: Vec<FrameEvent>,
+}
+
+/// A frame that took much longer than the frames before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jank {
+    pub index: u64,
+    pub frame: f64,
+    /// The median frame time before it.
+    pub median: f64,
+    /// What was noted during the frame, most likely the cause.
+    pub causes: Vec<FrameEvent>,
+}
+
+impl fmt::Display for Jank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Frame {} took {:.1} ms, {:.1}x the median of {:.1} ms",
+            self.index,
+            1000.0 * self.frame,
+            self.frame / self.median,
+            1000.0 * self.median
+        )?;
+        if self.causes.is_empty() {
+            write!(f, ", with nothing noted")
+        } else {
+            let causes = self.causes.iter().map(ToString::to_string);
+            write!(f, " while it {}", causes.collect::<Vec<_>>().join(", "))
+        }
+    }
+}
+
+/// The timings of the last frames, and the janks among them.
+#[derive(Debug, Clone)]
+pub struct FrameTimeline {
+    capacity: usize,
+    frames: VecDeque<FrameRecord>,
+    janks: VecDeque<Jank>,
+    next_index: u64,
+    jank_factor: f64,
+    /// Frame times for the median, kept so pushing doesn't allocate.
+    scratch: Vec<f64>,
+}
+
+impl Default for FrameTimeline {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl FrameTimeline {
+    /// Four seconds at 60 Hz.
+    pub const DEFAULT_CAPACITY: usize = 240;
+    pub const DEFAULT_JANK_FACTOR: f64 = 2.0;
+    /// Frames needed before a median is trusted to find janks with, so the
+    /// slow first frames aren't all reported.
+    pub const MIN_FRAMES_FOR_JANK: usize = 10;
+
+    /// Keeps the last `capacity` frames, and as many janks.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+            janks: VecDeque::new(),
+            next_index: 0,
+            jank_factor: Self::DEFAULT_JANK_FACTOR,
+            scratch: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Frames taking more than `factor` times the median are janks.
+    pub fn set_jank_factor(&mut self, factor: f64) {
+        self.jank_factor = factor;
+    }
+
+    /// Adds a finished frame with the events noted since the last one,
+    /// returning its jank if it was one.
+    /// Once the timeline is full, this only allocates for janks, which keep
+    /// a copy of their frame's events.
+    pub fn push(&mut self, timing: FrameTiming) -> Option<&Jank> {
+        let median = if self.frames.len() >= Self::MIN_FRAMES_FOR_JANK {
+            self.scratch.clear();
+            self.scratch
+                .extend(self.frames.iter().map(|f| f.timing.frame));
+            nearest_rank(&mut self.scratch, 50.0)
+        } else {
+            None
+        };
+        let index = self.next_index;
+        self.next_index += 1;
+
+        // The oldest frame's events make room for the ones after this.
+        let mut events = if self.frames.len() == self.capacity {
+            self.frames.pop_front().unwrap().events
+        } else {
+            Vec::new()
+        };
+        swap_events(&mut events);
+        let jank = median
+            .filter(|&m| timing.frame > self.jank_factor * m)
+            .map(|median| Jank {
+                index,
+                frame: timing.frame,
+                median,
+                causes: events.clone(),
+            });
+        let is_jank = jank.is_some();
+        if let Some(jank) = jank {
+            if self.janks.len() == self.capacity {
+                self.janks.pop_front();
+            }
+            self.janks.push_back(jank);
+        }
+        self.frames.push_back(FrameRecord {
+            index,
+            timing,
+            events,
+        });
+        if is_jank {
+            self.janks.back()
+        } else {
+            None
+        }
+    }
+
+    /// Oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &FrameRecord> {
+        self.frames.iter()
+    }
+
+    /// Oldest first, including those older than the frames kept.
+    pub fn janks(&self) -> impl Iterator<Item = &Jank> {
+        self.janks.iter()
+    }
+
+    /// The frame time that `p` percent of the kept frames took at most, by
+    /// nearest rank, or `None` without frames.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.percentile_of(p, |t| Some(t.frame))
+    }
+
+    /// Like [`Self::percentile`] for another part of the timing, over the
+    /// frames where it was measured.
+    pub fn percentile_of(&self, p: f64, part: impl Fn(&FrameTiming) -> Option<f64>) -> Option<f64> {
+        let mut times = self
+            .frames
+            .iter()
+            .filter_map(|f| part(&f.timing))
+            .collect::<Vec<_>>();
+        nearest_rank(&mut times, p)
+    }
+
+    /// The latest frame, if any.
+    pub fn last(&self) -> Option<&FrameRecord> {
+        self.frames.back()
+    }
+}
+
+/// The time that `p` percent of `times` are at most, sorting them in place.
+fn nearest_rank(times: &mut [f64], p: f64) -> Option<f64> {
+    if times.is_empty() {
+        return None;
+    }
+    times.sort_unstable_by(f64::total_cmp);
+    let rank = (p.clamp(0.0, 100.0) / 100.0 * times.len() as f64).ceil() as usize;
+    Some(times[rank.max(1) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f64 = 0.016;
+
+    fn timing(frame: f64) -> FrameTiming {
+        FrameTiming {
+            frame,
+            update: 0.002,
+            record: frame - 0.004,
+            ..Default::default()
+        }
+    }
+
+    // One test, as the pending events are shared by every timeline.
+    #[test]
+    fn spikes_are_janks_with_their_causes() {
+        take_events();
+        let mut timeline = FrameTimeline::new(64);
+        for _ in 0..30 {
+            assert!(timeline.push(timing(FRAME)).is_none());
+        }
+
+        let cause = FrameEvent::PipelineBuilt("shader.wgsl (Fast)".to_string());
+        note_event(cause.clone());
+        let jank = timeline.push(timing(0.060)).cloned().expect("not a jank");
+        assert_eq!(jank.index, 30);
+        assert_eq!(jank.median, FRAME);
+        assert_eq!(jank.causes, [cause]);
+
+        // Events belong to the frame they were noted in, and a slower frame
+        // under the factor isn't a jank.
+        for _ in 0..5 {
+            assert!(timeline.push(timing(FRAME)).is_none());
+        }
+        note_event(FrameEvent::TargetsRebuilt(800, 600));
+        assert!(timeline.push(timing(0.020)).is_none());
+        let last = timeline.last().unwrap();
+        assert_eq!(last.events, [FrameEvent::TargetsRebuilt(800, 600)]);
+        assert_eq!(timeline.janks().count(), 1);
+
+        // 37 frames: 35 of 16 ms, then 20 and 60.
+        let percentiles = [50.0, 95.0, 99.0].map(|p| timeline.percentile(p));
+        assert_eq!(percentiles, [Some(FRAME), Some(0.020), Some(0.060)]);
+        assert_eq!(
+            timeline.percentile_of(50.0, |t| Some(t.update)),
+            Some(0.002)
+        );
+        assert!(timeline.percentile_of(50.0, |t| t.gpu).is_none());
+
+        // Once full, the dropped frames' event lists are reused without
+        // carrying their events along.
+        let mut timeline = FrameTimeline::new(FrameTimeline::MIN_FRAMES_FOR_JANK);
+        for i in 0..25 {
+            if i % 3 == 0 {
+                note_event(FrameEvent::TargetsRebuilt(i, i));
+            }
+            timeline.push(timing(FRAME));
+        }
+        assert_eq!(
+            timeline.frames().count(),
+            FrameTimeline::MIN_FRAMES_FOR_JANK
+        );
+        for frame in timeline.frames() {
+            let i = frame.index as u32;
+            let expected = match i % 3 {
+                0 => vec![FrameEvent::TargetsRebuilt(i, i)],
+                _ => Vec::new(),
+            };
+            assert_eq!(frame.events, expected);
+        }
+        note_event(FrameEvent::AssetIntegrated("cube.obj".to_string()));
+        let jank = timeline.push(timing(0.050)).unwrap();
+        assert_eq!(
+            jank.causes,
+            [FrameEvent::AssetIntegrated("cube.obj".to_string())]
+        );
+    }
+}
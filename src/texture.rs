@@ -1,20 +1,205 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
 use anyhow::*;
 use image::GenericImageView;
 
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken},
+    ktx2, mipmap,
+};
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// The size of the source image, if it was downscaled to fit
+    /// [`TextureSettings::max_dimension`] or the GPU memory left.
+    pub downscaled_from: Option<(u32, u32)>,
+    memory: MemoryToken,
+}
+
+/// What to do with images larger than [`TextureSettings::max_dimension`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversized {
+    /// Downscale them on the CPU, keeping the aspect ratio.
+    #[default]
+    Downscale,
+    /// Fail, e.g. when authoring assets meant to fit a target as they are.
+    Error,
+}
+
+/// Limits on the textures made from images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextureSettings {
+    /// The largest width or height to upload. The device's
+    /// `max_texture_dimension_2d` always applies, and is the default.
+    pub max_dimension: Option<u32>,
+    pub oversized: Oversized,
+}
+
+impl TextureSettings {
+    /// The largest width or height `device` gets with these settings.
+    pub fn limit(&self, device: &wgpu::Device) -> u32 {
+        self.limit_for(&device.limits())
+    }
+
+    /// Like [`Self::limit`], for a device with `limits`.
+    pub fn limit_for(&self, limits: &wgpu::Limits) -> u32 {
+        let device_limit = limits.max_texture_dimension_2d;
+        self.max_dimension
+            .map_or(device_limit, |max| max.min(device_limit))
+    }
+
+    /// `img` as RGBA, downscaled to fit `limit` if needed, along with its
+    /// original size if it was. `label` names the image in errors and logs.
+    pub fn fit(
+        &self,
+        img: &image::DynamicImage,
+        limit: u32,
+        label: Option<&str>,
+    ) -> Result<(image::RgbaImage, Option<(u32, u32)>)> {
+        let (width, height) = img.dimensions();
+        let fitted = fit_size(width, height, limit);
+        if fitted == (width, height) {
+            return Ok((img.to_rgba8(), None));
+        }
+        let label = label.unwrap_or("texture");
+        if self.oversized == Oversized::Error {
+            bail!(
+                "{} is {}x{}, larger than the maximum of {}",
+                label,
+                width,
+                height,
+                limit
+            );
+        }
+        log::info!(
+            "Downscaling {} from {}x{} to {}x{} to fit {}",
+            label,
+            width,
+            height,
+            fitted.0,
+            fitted.1,
+            limit
+        );
+        let rgba = image::imageops::resize(
+            &img.to_rgba8(),
+            fitted.0,
+            fitted.1,
+            image::imageops::FilterType::Lanczos3,
+        );
+        Ok((rgba, Some((width, height))))
+    }
+}
+
+/// Creates what `create` gives for `rgba`'s size, halving `rgba` for as
+/// long as there isn't enough GPU memory for it. Returns the image that fit,
+/// and its size before any downscaling, which `downscaled_from` has if it
+/// was already downscaled before. Running out at 1x1 is returned as is, for
+/// the caller to free memory and retry, see [`crate::assets::Assets::evicting`].
+#[allow(clippy::type_complexity)]
+fn create_shrinking<R>(
+    mut rgba: image::RgbaImage,
+    mut downscaled_from: Option<(u32, u32)>,
+    label: Option<&str>,
+    mut create: impl FnMut(u32, u32) -> std::result::Result<R, AllocError>,
+) -> std::result::Result<(R, image::RgbaImage, Option<(u32, u32)>), AllocError> {
+    loop {
+        let (width, height) = rgba.dimensions();
+        match create(width, height) {
+            std::result::Result::Ok(created) => {
+                return std::result::Result::Ok((created, rgba, downscaled_from))
+            }
+            Err(e) if e.is_out_of_memory() && (width, height) != (1, 1) => {
+                let half = ((width / 2).max(1), (height / 2).max(1));
+                log::warn!(
+                    "{}: {}, retrying at {}x{}",
+                    label.unwrap_or("texture"),
+                    e,
+                    half.0,
+                    half.1
+                );
+                downscaled_from.get_or_insert((width, height));
+                rgba = image::imageops::resize(
+                    &rgba,
+                    half.0,
+                    half.1,
+                    image::imageops::FilterType::Triangle,
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A material's image as read, before it is uploaded.
+pub enum SourceImage {
+    /// Decoded from a PNG, JPEG or the like, its mips generated on upload.
+    Image(image::DynamicImage),
+    /// A whole mip chain, largest level first, as read from a
+    /// [`ktx2`](crate::ktx2) file.
+    MipChain(Vec<image::RgbaImage>),
+}
+
+impl SourceImage {
+    /// Reads KTX2 files as their mip chain and decodes anything else.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if ktx2::is_ktx2(bytes) {
+            Ok(Self::MipChain(ktx2::decode(bytes)?))
+        } else {
+            Ok(Self::Image(image::load_from_memory(bytes)?))
+        }
+    }
+}
+
+/// The first of `levels`, each half the size of the one before, that fits
+/// `limit` on both sides. The last one is taken if none does.
+fn first_fitting_level(levels: &[image::RgbaImage], limit: u32) -> usize {
+    levels
+        .iter()
+        .position(|level| level.width() <= limit && level.height() <= limit)
+        .unwrap_or(levels.len() - 1)
+}
+
+/// Multiplies each pixel's color by its alpha, in place. UI colors are
+/// premultiplied as they're stored, sRGB encoded.
+pub fn premultiply_alpha(rgba: &mut image::RgbaImage) {
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let premultiply = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+        pixel.0 = [premultiply(r), premultiply(g), premultiply(b), a];
+    }
+}
+
+/// The largest size with the aspect ratio of `width` by `height` that fits
+/// `limit` on both sides, or the size itself if it already fits. Neither
+/// side goes below 1, so long strips stay usable.
+pub fn fit_size(width: u32, height: u32, limit: u32) -> (u32, u32) {
+    let limit = limit.max(1);
+    if width <= limit && height <= limit {
+        return (width, height);
+    }
+    let scale = limit as f64 / width.max(height) as f64;
+    let fit = |n: u32| ((n as f64 * scale).round() as u32).clamp(1, limit);
+    (fit(width), fit(height))
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    /// The GPU memory the texture is accounted for, over every mip.
+    pub fn bytes(&self) -> u64 {
+        self.memory.bytes()
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
-    ) -> Self {
+    ) -> std::result::Result<Self, AllocError> {
         let size = wgpu::Extent3d {
             width: config.width,
             height: config.height,
@@ -30,7 +215,8 @@ impl Texture {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[Self::DEPTH_FORMAT],
         };
-        let texture = device.create_texture(&desc);
+        let (texture, memory) =
+            Allocator::new(device).create_texture(&desc, MemoryCategory::Target)?;
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -45,11 +231,13 @@ impl Texture {
             ..Default::default()
         });
 
-        Self {
+        std::result::Result::Ok(Self {
             texture,
             view,
             sampler,
-        }
+            downscaled_from: None,
+            memory,
+        })
     }
 
     #[allow(dead_code)]
@@ -60,34 +248,48 @@ impl Texture {
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            Some(label),
+            &TextureSettings::default(),
+        )
     }
 
+    /// Images larger than `settings` allow are downscaled or rejected, see
+    /// [`TextureSettings::fit`].
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        settings: &TextureSettings,
     ) -> Result<Self> {
-        let dimensions = img.dimensions();
-        let rgba = img.to_rgba8();
-
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        let (rgba, downscaled_from) = settings.fit(img, settings.limit(device), label)?;
+        let allocator = Allocator::new(device);
+        let ((texture, memory), rgba, downscaled_from) =
+            create_shrinking(rgba, downscaled_from, label, |width, height| {
+                let desc = wgpu::TextureDescriptor {
+                    label,
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_DST
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                };
+                allocator.create_texture(&desc, MemoryCategory::Texture)
+            })?;
+        let dimensions = rgba.dimensions();
+        let size = texture.size();
 
         queue.write_texture(
             wgpu::ImageCopyTexture {
@@ -120,6 +322,1231 @@ impl Texture {
             texture,
             view,
             sampler,
+            downscaled_from,
+            memory,
+        })
+    }
+
+    /// A texture for UI, see [`crate::ui`]. `rgba` should already have
+    /// premultiplied alpha, e.g. from [`premultiply_alpha`]. The values are
+    /// stored as they are, in a format that doesn't decode them when read.
+    pub fn from_ui_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let (width, height) = rgba.dimensions();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            MemoryCategory::Texture,
+        )?;
+        queue.write_texture(
+            texture.as_image_copy(),
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Filtering premultiplied values doesn't bleed color out of
+        // transparent texels, so UI can be drawn at any scale.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            downscaled_from: None,
+            memory,
+        })
+    }
+
+    /// Like [`Texture::from_image`], but with a full mip chain. Generation is
+    /// recorded into `encoder` so the caller can batch many textures into one
+    /// submission; formats that can't be rendered to fall back to the CPU.
+    /// The chain starts from the downscaled size if the image was too large.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_image_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut mipmap::MipmapGenerator,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        settings: &TextureSettings,
+    ) -> Result<Self> {
+        let (rgba, downscaled_from) = settings.fit(img, settings.limit(device), label)?;
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let gpu_mips = mipmap::supports_gpu_generation(device, format);
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC;
+        if gpu_mips {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+        let allocator = Allocator::new(device);
+        let ((texture, memory), rgba, downscaled_from) =
+            create_shrinking(rgba, downscaled_from, label, |width, height| {
+                let desc = wgpu::TextureDescriptor {
+                    label,
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: mipmap::mip_level_count(width, height),
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                };
+                allocator.create_texture(&desc, MemoryCategory::Texture)
+            })?;
+        let mip_level_count = texture.mip_level_count();
+
+        let write_level = |mip_level: u32, data: &image::RgbaImage| {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * data.width()),
+                    rows_per_image: Some(data.height()),
+                },
+                wgpu::Extent3d {
+                    width: data.width(),
+                    height: data.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        };
+
+        write_level(0, &rgba);
+        if gpu_mips {
+            mipmaps.generate(device, encoder, &texture, format, mip_level_count)?;
+        } else {
+            for (i, level) in mipmap::generate_cpu(&rgba, format.is_srgb())
+                .iter()
+                .enumerate()
+            {
+                write_level(i as u32 + 1, level);
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            downscaled_from,
+            memory,
+        })
+    }
+
+    /// Like [`Texture::from_image_mipmapped`] for `source` of either kind.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_source_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut mipmap::MipmapGenerator,
+        source: &SourceImage,
+        label: Option<&str>,
+        settings: &TextureSettings,
+    ) -> Result<Self> {
+        match source {
+            SourceImage::Image(img) => {
+                Self::from_image_mipmapped(device, queue, encoder, mipmaps, img, label, settings)
+            }
+            SourceImage::MipChain(levels) => {
+                Self::from_mip_chain(device, queue, levels, label, settings)
+            }
+        }
+    }
+
+    /// A texture with `levels` as its mips, largest first, each half the
+    /// size of the one before. Levels too large for `settings` are left
+    /// out rather than downscaled, as are more of them for as long as
+    /// there isn't enough GPU memory.
+    pub fn from_mip_chain(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        levels: &[image::RgbaImage],
+        label: Option<&str>,
+        settings: &TextureSettings,
+    ) -> Result<Self> {
+        ensure!(
+            !levels.is_empty(),
+            "{} has no mip levels",
+            label.unwrap_or("texture")
+        );
+        let full_size = levels[0].dimensions();
+        let limit = settings.limit(device);
+        let mut first = first_fitting_level(levels, limit);
+        if first > 0 && settings.oversized == Oversized::Error {
+            bail!(
+                "{} is {}x{}, larger than the maximum of {}",
+                label.unwrap_or("texture"),
+                full_size.0,
+                full_size.1,
+                limit
+            );
+        }
+        let allocator = Allocator::new(device);
+        let (texture, memory) = loop {
+            let (width, height) = levels[first].dimensions();
+            let desc = wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: (levels.len() - first) as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            };
+            match allocator.create_texture(&desc, MemoryCategory::Texture) {
+                std::result::Result::Ok(created) => break created,
+                Err(e) if e.is_out_of_memory() && first + 1 < levels.len() => {
+                    log::warn!(
+                        "{}: {}, retrying without level {}",
+                        label.unwrap_or("texture"),
+                        e,
+                        first
+                    );
+                    first += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if first > 0 {
+            log::info!(
+                "Loading {} from {}x{} down, to fit",
+                label.unwrap_or("texture"),
+                levels[first].width(),
+                levels[first].height()
+            );
+        }
+
+        for (mip_level, level) in levels[first..].iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * level.width()),
+                    rows_per_image: Some(level.height()),
+                },
+                wgpu::Extent3d {
+                    width: level.width(),
+                    height: level.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            downscaled_from: (first > 0).then_some(full_size),
+            memory,
+        })
+    }
+
+    /// A blank texture meant to be updated in place with
+    /// [`Texture::write_region`]. With `mipmapped` it gets a full mip chain,
+    /// kept current with [`Texture::regenerate_mips`] after writes.
+    pub fn writable(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        mipmapped: bool,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        if format.block_dimensions() != (1, 1) {
+            bail!(
+                "{:?} is block compressed and can't be written in regions",
+                format
+            );
+        }
+        let mip_level_count = if mipmapped {
+            if !mipmap::supports_gpu_generation(device, format) {
+                bail!("mips of {:?} can't be generated on the GPU", format);
+            }
+            mipmap::mip_level_count(width, height)
+        } else {
+            1
+        };
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC;
+        if mipmapped {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            },
+            MemoryCategory::Texture,
+        )?;
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            downscaled_from: None,
+            memory,
+        })
+    }
+
+    /// Overwrites the `size` texels at `origin` of the top mip level with
+    /// tightly packed rows from `data`. Queue writes take any row pitch, so
+    /// `data` is uploaded as it is, without padding rows to the copy
+    /// alignment. Lower mips are left as they were until
+    /// [`Texture::regenerate_mips`].
+    pub fn write_region(
+        &self,
+        queue: &wgpu::Queue,
+        origin: (u32, u32),
+        size: (u32, u32),
+        data: &[u8],
+    ) -> Result<()> {
+        let Some(bytes_per_row) = region_row_bytes(
+            self.texture.format(),
+            self.texture.size(),
+            origin,
+            size,
+            data,
+        )?
+        else {
+            return Ok(());
+        };
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin.0,
+                    y: origin.1,
+                    z: 0,
+                },
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size.1),
+            },
+            wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+        );
+        Ok(())
+    }
+
+    /// Rebuilds the mip chain from the top level, after writes to it. Does
+    /// nothing for textures without mips.
+    pub fn regenerate_mips(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut mipmap::MipmapGenerator,
+    ) -> std::result::Result<(), AllocError> {
+        let mip_level_count = self.texture.mip_level_count();
+        if mip_level_count > 1 {
+            mipmaps.generate(
+                device,
+                encoder,
+                &self.texture,
+                self.texture.format(),
+                mip_level_count,
+            )?;
+        }
+        std::result::Result::Ok(())
+    }
+
+    /// A copy of the texture at most `max_size` texels on its longer side,
+    /// keeping the aspect ratio, e.g. for UI previews. Textures already that
+    /// small come back at their own size. The downscale starts from the
+    /// closest larger mip and halves from there, so textures without a mip
+    /// chain don't load thousands of texels per thumbnail texel. Filters in
+    /// linear space for sRGB textures. Blocks until the GPU is done, so it
+    /// only works on native; see [`ThumbnailCache`] to avoid reading the
+    /// same texture back repeatedly.
+    pub fn thumbnail(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut mipmap::MipmapGenerator,
+        max_size: u32,
+    ) -> Result<image::RgbaImage> {
+        let format = self.texture.format();
+        if !matches!(
+            format,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb
+        ) {
+            bail!("thumbnails of {:?} textures aren't supported", format);
+        }
+        let size = self.texture.size();
+        let (width, height) = thumbnail_size(size.width, size.height, max_size);
+
+        let source_mip = (0..self.texture.mip_level_count())
+            .take_while(|&mip| (size.width >> mip) >= width && (size.height >> mip) >= height)
+            .last()
+            .unwrap_or(0);
+        let mut source = self.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("thumbnail_source"),
+            base_mip_level: source_mip,
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Thumbnail Encoder"),
+        });
+
+        // Halve through scratch textures until one blit covers at most 2x2
+        // texels, like the levels of a mip chain the texture doesn't have.
+        let mut source_size = (
+            (size.width >> source_mip).max(1),
+            (size.height >> source_mip).max(1),
+        );
+        while source_size.0 > width * 2 || source_size.1 > height * 2 {
+            source_size = (
+                (source_size.0 / 2).max(width),
+                (source_size.1 / 2).max(height),
+            );
+            let (level, _level_memory) = Allocator::new(device).create_texture(
+                &wgpu::TextureDescriptor {
+                    label: Some("thumbnail_level"),
+                    size: wgpu::Extent3d {
+                        width: source_size.0,
+                        height: source_size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                MemoryCategory::Target,
+            )?;
+            let view = level.create_view(&wgpu::TextureViewDescriptor::default());
+            mipmaps.blit(device, &mut encoder, &source, &view, format, source_size)?;
+            source = view;
+        }
+
+        let (target, _target_memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("Thumbnail"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        mipmaps.blit(
+            device,
+            &mut encoder,
+            &source,
+            &target.create_view(&wgpu::TextureViewDescriptor::default()),
+            format,
+            (width, height),
+        )?;
+        queue.submit(std::iter::once(encoder.finish()));
+
+        crate::export::read_texture(device, queue, &target)?
+            .context("the thumbnail couldn't be read back")
+    }
+}
+
+/// The size of a [`Texture::thumbnail`] of a `width` by `height` texture:
+/// the longer side scaled down to `max_size`, the other one in proportion
+/// and at least 1.
+pub fn thumbnail_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_size {
+        return (width, height);
+    }
+    let scale = |side: u32| {
+        ((side as u64 * max_size as u64 + longest as u64 / 2) / longest as u64).max(1) as u32
+    };
+    (scale(width), scale(height))
+}
+
+/// Thumbnails of textures made once and kept until the texture is dropped,
+/// so UI panels can ask for them every frame.
+pub struct ThumbnailCache {
+    max_size: u32,
+    /// Keyed by the texture's address, which the `Weak` keeps from being
+    /// reused while the entry exists.
+    thumbnails: HashMap<usize, (Weak<Texture>, image::RgbaImage)>,
+}
+
+impl ThumbnailCache {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            thumbnails: HashMap::new(),
+        }
+    }
+
+    /// The thumbnail of `texture`, made with [`Texture::thumbnail`] on the
+    /// first call.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut mipmap::MipmapGenerator,
+        texture: &Arc<Texture>,
+    ) -> Result<&image::RgbaImage> {
+        let key = Arc::as_ptr(texture) as usize;
+        if !self.thumbnails.contains_key(&key) {
+            let thumbnail = texture.thumbnail(device, queue, mipmaps, self.max_size)?;
+            self.thumbnails
+                .insert(key, (Arc::downgrade(texture), thumbnail));
+        }
+        Ok(&self.thumbnails[&key].1)
+    }
+
+    /// Forgets the thumbnail of `texture`, e.g. after it was written to.
+    pub fn invalidate(&mut self, texture: &Arc<Texture>) {
+        self.thumbnails.remove(&(Arc::as_ptr(texture) as usize));
+    }
+
+    /// Drops the thumbnails of textures that no longer exist.
+    pub fn prune(&mut self) {
+        self.thumbnails
+            .retain(|_, (texture, _)| texture.strong_count() > 0);
+    }
+}
+
+/// The row pitch of `size` texels of tightly packed `data` at `origin`, in
+/// a `format` texture of `extent`, checking that they can be written with
+/// [`Texture::write_region`]. `None` for an empty region.
+fn region_row_bytes(
+    format: wgpu::TextureFormat,
+    extent: wgpu::Extent3d,
+    origin: (u32, u32),
+    size: (u32, u32),
+    data: &[u8],
+) -> Result<Option<u32>> {
+    if format.block_dimensions() != (1, 1) {
+        bail!(
+            "partial updates of block compressed {:?} textures aren't supported",
+            format
+        );
+    }
+    let Some(texel_size) = format.block_size(None) else {
+        bail!("{:?} has no single texel size to write with", format);
+    };
+    if size.0 == 0 || size.1 == 0 {
+        return Ok(None);
+    }
+    let fits = |o: u32, s: u32, max: u32| o.checked_add(s).is_some_and(|end| end <= max);
+    if !fits(origin.0, size.0, extent.width) || !fits(origin.1, size.1, extent.height) {
+        bail!(
+            "region {}x{} at {:?} is outside the {}x{} texture",
+            size.0,
+            size.1,
+            origin,
+            extent.width,
+            extent.height
+        );
+    }
+    let row = size.0 as usize * texel_size as usize;
+    if data.len() != row * size.1 as usize {
+        bail!(
+            "expected {} bytes for a {}x{} region of {:?}, got {}",
+            row * size.1 as usize,
+            size.0,
+            size.1,
+            format,
+            data.len()
+        );
+    }
+    Ok(Some(row as u32))
+}
+
+/// A paletted texture: one `R8Uint` index per texel and a 256x1 palette,
+/// looked up in shaders with `sample_indexed` from [`IndexedTexture::WGSL`].
+/// Swapping the palette recolors everything using the texture.
+pub struct IndexedTexture {
+    pub indices: wgpu::Texture,
+    pub index_view: wgpu::TextureView,
+    pub palette: wgpu::Texture,
+    pub palette_view: wgpu::TextureView,
+    _memory: [MemoryToken; 2],
+}
+
+impl IndexedTexture {
+    /// WGSL for the two step lookup, to be prepended to shaders using it.
+    pub const WGSL: &'static str = include_str!("indexed.wgsl");
+
+    pub const PALETTE_SIZE: usize = 256;
+
+    /// Replaces the palette. Entries are sRGB colors with linear alpha.
+    pub fn set_palette(&self, queue: &wgpu::Queue, palette: &[[u8; 4]; 256]) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.palette,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(palette),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * Self::PALETTE_SIZE as u32),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: Self::PALETTE_SIZE as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Bindings 0 and 1 are the index and palette textures. Both are read
+    /// with `textureLoad`, so there is no sampler and filtering is always
+    /// nearest.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("indexed_texture_bind_group_layout"),
+        })
+    }
+
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.index_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.palette_view),
+                },
+            ],
+            label: Some("indexed_texture_bind_group"),
         })
     }
 }
+
+/// An indexed PNG's indices, one byte per pixel, and palette.
+struct IndexedImage {
+    width: u32,
+    height: u32,
+    indices: Vec<u8>,
+    palette: [[u8; 4]; IndexedTexture::PALETTE_SIZE],
+}
+
+impl IndexedImage {
+    fn from_png(bytes: &[u8], label: &str) -> Result<Self> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+        let info = reader.info();
+        if info.color_type != png::ColorType::Indexed {
+            bail!("{} isn't an indexed PNG ({:?})", label, info.color_type);
+        }
+        let rgb = info
+            .palette
+            .as_ref()
+            .context("indexed PNG has no palette")?
+            .to_vec();
+        let alpha = info.trns.as_ref().map(|t| t.to_vec()).unwrap_or_default();
+
+        let mut palette = [[0u8; 4]; IndexedTexture::PALETTE_SIZE];
+        for (i, entry) in rgb.chunks_exact(3).take(palette.len()).enumerate() {
+            // Entries without a tRNS value are opaque.
+            let a = alpha.get(i).copied().unwrap_or(255);
+            palette[i] = [entry[0], entry[1], entry[2], a];
+        }
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer)?;
+        let (width, height) = (frame.width, frame.height);
+        // Rows pack 1, 2 or 4 bit indices from the most significant bit.
+        let depth = frame.bit_depth as usize;
+        let mut indices = Vec::with_capacity((width * height) as usize);
+        for row in buffer.chunks(frame.line_size).take(height as usize) {
+            for x in 0..width as usize {
+                let index = match depth {
+                    8 => row[x],
+                    _ => {
+                        let bit = x * depth;
+                        let shift = 8 - depth - bit % 8;
+                        (row[bit / 8] >> shift) & ((1 << depth) - 1) as u8
+                    }
+                };
+                indices.push(index);
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            indices,
+            palette,
+        })
+    }
+}
+
+impl Texture {
+    /// Loads an indexed PNG keeping its palette, rather than expanding it to
+    /// RGBA as [`Texture::from_bytes`] does. Palette entries past those in
+    /// the file are transparent black.
+    pub fn indexed_from_png(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<IndexedTexture> {
+        let IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        } = IndexedImage::from_png(bytes, label)?;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let (index_texture, index_texture_memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Uint,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            MemoryCategory::Texture,
+        )?;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &index_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &indices,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        let (palette_texture, palette_texture_memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(&format!("{} Palette", label)),
+                size: wgpu::Extent3d {
+                    width: IndexedTexture::PALETTE_SIZE as u32,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            MemoryCategory::Texture,
+        )?;
+
+        let texture = IndexedTexture {
+            index_view: index_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            indices: index_texture,
+            palette_view: palette_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            palette: palette_texture,
+            _memory: [index_texture_memory, palette_texture_memory],
+        };
+        texture.set_palette(queue, &palette);
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use super::*;
+
+    /// A device that can't make textures over 256 texels across.
+    fn small_device() -> wgpu::Limits {
+        wgpu::Limits {
+            max_texture_dimension_2d: 256,
+            ..wgpu::Limits::default()
+        }
+    }
+
+    fn gradient(width: u32, height: u32) -> image::DynamicImage {
+        image::RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([(x % 256) as u8, (y % 256) as u8, 0, 255])
+        })
+        .into()
+    }
+
+    #[test]
+    fn oversized_images_are_downscaled_to_the_device_limit() {
+        let settings = TextureSettings::default();
+        let limit = settings.limit_for(&small_device());
+        assert_eq!(limit, 256);
+
+        let (rgba, downscaled_from) = settings.fit(&gradient(1000, 500), limit, None).unwrap();
+        assert_eq!(rgba.dimensions(), (256, 128));
+        assert_eq!(downscaled_from, Some((1000, 500)));
+
+        // Images that fit are left alone.
+        let (rgba, downscaled_from) = settings.fit(&gradient(256, 40), limit, None).unwrap();
+        assert_eq!(rgba.dimensions(), (256, 40));
+        assert_eq!(downscaled_from, None);
+    }
+
+    #[test]
+    fn the_smaller_of_the_setting_and_the_device_applies() {
+        let limits = small_device();
+        let with_max = |max_dimension| TextureSettings {
+            max_dimension: Some(max_dimension),
+            ..Default::default()
+        };
+        assert_eq!(with_max(100).limit_for(&limits), 100);
+        assert_eq!(with_max(4096).limit_for(&limits), 256);
+
+        let settings = with_max(100);
+        let (rgba, downscaled_from) = settings
+            .fit(&gradient(300, 600), settings.limit_for(&limits), None)
+            .unwrap();
+        assert_eq!(rgba.dimensions(), (50, 100));
+        assert_eq!(downscaled_from, Some((300, 600)));
+    }
+
+    #[test]
+    fn oversized_images_can_be_refused() {
+        let settings = TextureSettings {
+            oversized: Oversized::Error,
+            ..Default::default()
+        };
+        let limit = settings.limit_for(&small_device());
+        let error = settings
+            .fit(&gradient(512, 512), limit, Some("wall.png"))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "wall.png is 512x512, larger than the maximum of 256"
+        );
+        assert!(settings.fit(&gradient(256, 256), limit, None).is_ok());
+    }
+
+    #[test]
+    fn fitting_keeps_the_aspect_ratio_and_at_least_a_texel() {
+        assert_eq!(fit_size(4096, 1024, 1024), (1024, 256));
+        assert_eq!(fit_size(100, 100, 200), (100, 100));
+        assert_eq!(fit_size(10000, 2, 1000), (1000, 1));
+        assert_eq!(fit_size(7, 3, 0), (1, 1));
+    }
+
+    #[test]
+    fn mip_chains_start_at_the_first_level_that_fits() {
+        let levels = [(8, 2), (4, 1), (2, 1), (1, 1)]
+            .map(|(width, height)| image::RgbaImage::new(width, height));
+        assert_eq!(first_fitting_level(&levels, 8), 0);
+        assert_eq!(first_fitting_level(&levels, 5), 1);
+        assert_eq!(first_fitting_level(&levels, 2), 2);
+        assert_eq!(first_fitting_level(&levels, 0), 3);
+        assert!(matches!(
+            SourceImage::decode(&ktx2::encode(&levels[0])).unwrap(),
+            SourceImage::MipChain(chain) if chain.len() == 4
+        ));
+    }
+
+    /// An indexed PNG of `indices`, `width` to a row, with `palette` as its
+    /// PLTE and tRNS chunks.
+    fn indexed_png(
+        width: u32,
+        depth: png::BitDepth,
+        palette: &[[u8; 4]],
+        indices: &[u8],
+    ) -> Vec<u8> {
+        let height = indices.len() as u32 / width;
+        let bits = depth as usize;
+        let mut packed = Vec::new();
+        for row in indices.chunks(width as usize) {
+            let mut line = vec![0u8; (width as usize * bits).div_ceil(8)];
+            for (x, &index) in row.iter().enumerate() {
+                let bit = x * bits;
+                line[bit / 8] |= index << (8 - bits - bit % 8);
+            }
+            packed.extend(line);
+        }
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(
+            palette
+                .iter()
+                .flat_map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<_>>(),
+        );
+        encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&packed).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn indexed_pngs_keep_their_indices_and_palette() {
+        for (depth, colors) in [
+            (png::BitDepth::One, 2),
+            (png::BitDepth::Two, 4),
+            (png::BitDepth::Four, 16),
+            (png::BitDepth::Eight, 200),
+        ] {
+            let palette: Vec<[u8; 4]> = (0..colors)
+                .map(|i| {
+                    [
+                        i as u8,
+                        255 - i as u8,
+                        (i * 7) as u8,
+                        255 - (i % 3) as u8 * 100,
+                    ]
+                })
+                .collect();
+            // An odd width, so rows of packed indices end mid byte.
+            let (width, height) = (7, 5);
+            let indices: Vec<u8> = (0..width * height)
+                .map(|i| ((i * 5 + i / width) % colors) as u8)
+                .collect();
+            let bytes = indexed_png(width as u32, depth, &palette, &indices);
+
+            let image = IndexedImage::from_png(&bytes, "indexed.png").unwrap();
+            assert_eq!((image.width, image.height), (width as u32, height as u32));
+            assert_eq!(image.indices, indices, "{:?}", depth);
+            assert_eq!(&image.palette[..colors], &palette[..], "{:?}", depth);
+            assert!(image.palette[colors..].iter().all(|c| *c == [0; 4]));
+
+            // Looking the indices up gives what decoding to RGBA does.
+            let expanded: Vec<u8> = image
+                .indices
+                .iter()
+                .flat_map(|&i| image.palette[i as usize])
+                .collect();
+            let rgba = image::load_from_memory(&bytes).unwrap().to_rgba8();
+            assert_eq!(expanded, rgba.into_raw(), "{:?}", depth);
+        }
+    }
+
+    #[test]
+    fn only_indexed_pngs_load_as_indexed() {
+        let mut bytes = Vec::new();
+        gradient(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let error = IndexedImage::from_png(&bytes, "gradient.png")
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("gradient.png isn't an indexed PNG"));
+    }
+
+    fn extent(width: u32, height: u32) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    #[test]
+    fn regions_are_written_with_their_own_row_pitch() {
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let pitch = |origin, size, data: &[u8]| {
+            region_row_bytes(format, extent(70, 40), origin, size, data).unwrap()
+        };
+        // Rows that aren't a multiple of the copy alignment aren't padded.
+        assert_eq!(pitch((60, 2), (5, 3), &[0; 3 * 5 * 4]), Some(20));
+        assert_eq!(pitch((0, 0), (64, 2), &[7; 64 * 4 * 2]), Some(256));
+        assert_eq!(pitch((1, 1), (3, 1), &[7; 12]), Some(12));
+        assert_eq!(pitch((70, 40), (0, 5), &[]), None);
+    }
+
+    #[test]
+    fn bad_regions_are_refused() {
+        let rgba = wgpu::TextureFormat::Rgba8Unorm;
+        let refused = |format, origin, size, data: &[u8]| {
+            region_row_bytes(format, extent(70, 40), origin, size, data)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            refused(rgba, (68, 0), (3, 1), &[0; 12]),
+            "region 3x1 at (68, 0) is outside the 70x40 texture"
+        );
+        assert_eq!(
+            refused(rgba, (0, u32::MAX), (1, 2), &[0; 8]),
+            "region 1x2 at (0, 4294967295) is outside the 70x40 texture"
+        );
+        assert_eq!(
+            refused(rgba, (0, 0), (2, 2), &[0; 15]),
+            "expected 16 bytes for a 2x2 region of Rgba8Unorm, got 15"
+        );
+        assert!(
+            refused(wgpu::TextureFormat::Bc1RgbaUnorm, (0, 0), (4, 4), &[0; 8])
+                .contains("block compressed")
+        );
+    }
+
+    /// Stands in for a texture, logging when its asset is released.
+    struct MockTexture {
+        name: &'static str,
+        bytes: u64,
+        live: Rc<Cell<u64>>,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Drop for MockTexture {
+        fn drop(&mut self) {
+            self.live.set(self.live.get() - self.bytes);
+            self.log.borrow_mut().push(format!("evict {}", self.name));
+        }
+    }
+
+    #[test]
+    fn running_out_shrinks_then_evicts_the_least_recently_used() {
+        let live = Rc::new(Cell::new(0));
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut textures = crate::assets::Assets::new();
+        let [_old, recent] = ["old", "recent"].map(|name| {
+            live.set(live.get() + 3000);
+            textures.insert(MockTexture {
+                name,
+                bytes: 3000,
+                live: live.clone(),
+                log: log.clone(),
+            })
+        });
+        textures.resolve(recent);
+
+        // Allocates against a fake budget, like the allocator's error scopes.
+        let budget = Cell::new(6000);
+        let allocate = |width: u32, height: u32| {
+            let bytes = 4 * (width * height) as u64;
+            if live.get() + bytes > budget.get() {
+                log.borrow_mut().push(format!("fail {}x{}", width, height));
+                return Err(AllocError::OutOfMemory {
+                    label: Some("mock".into()),
+                    category: MemoryCategory::Texture,
+                    bytes,
+                });
+            }
+            live.set(live.get() + bytes);
+            log.borrow_mut().push(format!("fit {}x{}", width, height));
+            std::result::Result::Ok(bytes)
+        };
+        let image = gradient(8, 8).to_rgba8();
+        let (bytes, rgba, downscaled_from) = textures
+            .evicting(|| {
+                Ok(create_shrinking(
+                    image.clone(),
+                    None,
+                    Some("mock"),
+                    allocate,
+                )?)
+            })
+            .unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [
+                "fail 8x8",
+                "fail 4x4",
+                "fail 2x2",
+                "fail 1x1",
+                "evict old",
+                "fit 8x8"
+            ]
+        );
+        assert_eq!(
+            (bytes, rgba.dimensions(), downscaled_from),
+            (256, (8, 8), None)
+        );
+        assert!(textures.resolve(recent).is_some());
+
+        // With nothing left to release, the error reaches the caller.
+        log.borrow_mut().clear();
+        budget.set(0);
+        let error = textures
+            .evicting(|| {
+                Ok(create_shrinking(
+                    image.clone(),
+                    None,
+                    Some("mock"),
+                    allocate,
+                )?)
+            })
+            .unwrap_err();
+        assert!(error
+            .downcast_ref::<AllocError>()
+            .is_some_and(AllocError::is_out_of_memory));
+        assert_eq!(
+            *log.borrow(),
+            [
+                "fail 8x8",
+                "fail 4x4",
+                "fail 2x2",
+                "fail 1x1",
+                "evict recent",
+                "fail 8x8",
+                "fail 4x4",
+                "fail 2x2",
+                "fail 1x1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! The output pass. With auto exposure on, everything before the UI draws
+//! into an HDR target instead of the surface, and [`Tonemap::draw`] scales
+//! it by the exposure onto the surface. SDR surfaces also get a filmic
+//! tone curve, as a scene exposed for its average would otherwise clip
+//! everything brighter than that.
+
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken, OutputMode},
+    reflect,
+    stats::FrameStats,
+};
+
+/// The format of the target the scene draws into with auto exposure on.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Krzysztof Narkowicz's fit of the ACES filmic curve, as `tonemap.wgsl`
+/// applies it for SDR surfaces.
+pub fn aces_filmic(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    curve: u32,
+    _padding: [f32; 2],
+}
+
+struct HdrTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    _memory: MemoryToken,
+}
+
+pub struct Tonemap {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    _memory: MemoryToken,
+    curve: bool,
+    target: HdrTarget,
+}
+
+impl Tonemap {
+    /// The HDR target and the exposure, group 0 of `tonemap.wgsl`.
+    pub(crate) const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// Whether `adapter` can draw into and then read [`HDR_FORMAT`]
+    /// targets, which WebGL2 only does with `EXT_color_buffer_float`.
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        adapter
+            .get_texture_format_features(HDR_FORMAT)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+    }
+
+    /// For a surface of `surface_format` in `output_mode`, `size` across.
+    /// The exposure starts at 1.
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        output_mode: OutputMode,
+        size: (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let source = include_str!("tonemap.wgsl");
+        reflect::debug_check("tonemap.wgsl", source, &[&Self::LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("tonemap.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::LAYOUT_ENTRIES,
+            label: Some("tonemap_bind_group_layout"),
+        });
+        let curve = output_mode == OutputMode::Sdr;
+        let (uniform_buffer, memory) = Allocator::new(device).create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Tonemap Buffer"),
+                contents: bytemuck::bytes_of(&TonemapUniform {
+                    exposure: 1.0,
+                    curve: curve as u32,
+                    _padding: [0.0; 2],
+                }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+            MemoryCategory::Uniform,
+        )?;
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(surface_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let target = HdrTarget::new(device, &layout, &uniform_buffer, size)?;
+
+        Ok(Self {
+            pipeline,
+            layout,
+            uniform_buffer,
+            _memory: memory,
+            curve,
+            target,
+        })
+    }
+
+    /// Recreates the HDR target for a surface `size` across.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) -> Result<(), AllocError> {
+        self.target = HdrTarget::new(device, &self.layout, &self.uniform_buffer, size)?;
+        Ok(())
+    }
+
+    /// The HDR target the scene draws into.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.target.texture.width(), self.target.texture.height())
+    }
+
+    /// Scales the next frames by `exposure`.
+    pub fn set_exposure(&self, queue: &wgpu::Queue, exposure: f32) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&TonemapUniform {
+                exposure,
+                curve: self.curve as u32,
+                _padding: [0.0; 2],
+            }),
+        );
+    }
+
+    /// Adds the commands [`Self::draw`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats) {
+        stats.passes += 1;
+        stats.pipeline_sets += 1;
+        stats.bind_group_sets += 1;
+        stats.draw_calls += 1;
+        stats.instances += 1;
+        stats.triangles += 1;
+    }
+
+    /// Draws the HDR target over the whole of `surface`, a view of the
+    /// surface in its own format.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, surface: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.target.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl HdrTarget {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        (width, height): (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("HDR Scene"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+        Ok(Self {
+            texture,
+            view,
+            bind_group,
+            _memory: memory,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exposure;
+
+    #[test]
+    fn the_curve_rolls_highlights_off_instead_of_clipping() {
+        assert_eq!(aces_filmic(0.0), 0.0);
+        let samples = (0..=200)
+            .map(|i| aces_filmic(i as f32 * 0.1))
+            .collect::<Vec<_>>();
+        assert!(samples.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Twice and four times as bright still come out apart.
+        assert!(aces_filmic(2.0) < aces_filmic(4.0));
+        assert!(aces_filmic(4.0) < 1.0);
+        assert_eq!(aces_filmic(1000.0), 1.0);
+    }
+
+    #[test]
+    fn a_metered_average_comes_out_darker_than_mid_tones() {
+        // Exposed for its average, a scene's average lands at 1 / 9.6, and
+        // the curve keeps it in the lower mid tones.
+        let mut auto = exposure::AutoExposure::new(Default::default());
+        let exposed = 40.0 * auto.update_log_luminance(40.0f32.log2(), 0.0);
+        let shown = aces_filmic(exposed);
+        assert!((0.05..0.2).contains(&shown), "{}", shown);
+    }
+}
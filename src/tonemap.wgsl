@@ -0,0 +1,42 @@
+// Exposes the HDR scene and draws it onto the surface. SDR surfaces get a
+// tone curve, so highlights roll off instead of clipping; HDR surfaces take
+// the exposed values as they are.
+
+struct Tonemap {
+    exposure: f32,
+    curve: u32,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle covering the whole target.
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve. See
+// `tonemap::aces_filmic`.
+fn aces_filmic(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<i32>(position.xy), 0);
+    var color = hdr.rgb * tonemap.exposure;
+    if tonemap.curve != 0u {
+        color = aces_filmic(color);
+    }
+    return vec4<f32>(color, hdr.a);
+}
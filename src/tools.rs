@@ -0,0 +1,550 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, Transform, Vector3};
+
+use crate::{
+    accel::Ray,
+    bounds::Aabb,
+    export::MeshReadback,
+    gpu::{ReadbackBatch, ReadbackError, ReadbackPolicy, ReadbackQueue},
+    model::{Model, PointCloud, PointVertex},
+};
+
+/// A CPU copy of a model's triangles, for picking points on its surface.
+pub struct PickMesh {
+    pub positions: Vec<Point3<f32>>,
+    /// Empty, or one per position.
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    pub aabb: Option<Aabb>,
+}
+
+impl PickMesh {
+    /// Records readbacks of the model's geometry into `encoder`, for
+    /// `on_read` once `readbacks` has delivered all of them. An `Err` means
+    /// not all were recorded, and `on_read` won't be called.
+    pub fn read_model(
+        readbacks: &mut ReadbackQueue,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        on_read: impl FnOnce(Result<Self, ReadbackError>) + Send + 'static,
+    ) -> Result<(), ReadbackError> {
+        let batch = ReadbackBatch::default();
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                MeshReadback::record(
+                    readbacks,
+                    &batch,
+                    device,
+                    encoder,
+                    mesh,
+                    model.vertex_precision,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        batch.finish(move |results| {
+            let mut results = results.into_iter();
+            let read = || {
+                let mut positions = Vec::new();
+                let mut tex_coords = Vec::new();
+                let mut indices = Vec::new();
+                for readback in meshes {
+                    let (vertices, mesh_indices) = readback.decode(&mut results)?;
+                    let base = positions.len() as u32;
+                    positions.extend(vertices.iter().map(|v| Point3::from(v.position)));
+                    tex_coords.extend(vertices.iter().map(|v| v.tex_coords));
+                    indices.extend(mesh_indices.iter().map(|i| base + i));
+                }
+                let mut mesh = Self::new(positions, indices);
+                mesh.tex_coords = tex_coords;
+                Ok(mesh)
+            };
+            on_read(read());
+        });
+        Ok(())
+    }
+
+    pub fn new(positions: Vec<Point3<f32>>, indices: Vec<u32>) -> Self {
+        let aabb = Aabb::from_points(positions.iter().copied());
+        Self {
+            positions,
+            tex_coords: Vec::new(),
+            indices,
+            aabb,
+        }
+    }
+
+    /// The closest point where `ray` hits the surface.
+    pub fn pick(&self, ray: &Ray) -> Option<Point3<f32>> {
+        // Most clicks miss small models entirely.
+        ray.intersect_aabb(self.aabb.as_ref()?)?;
+        self.indices
+            .chunks_exact(3)
+            .filter_map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| self.positions[i as usize]);
+                ray.intersect_triangle(a, b, c)
+            })
+            .min_by(f32::total_cmp)
+            .map(|t| ray.at(t))
+    }
+
+    /// The closest point where `ray` hits the surface, with the texture
+    /// coordinates there. `None` without texture coordinates.
+    pub fn pick_uv(&self, ray: &Ray) -> Option<(Point3<f32>, [f32; 2])> {
+        if self.tex_coords.len() != self.positions.len() {
+            return None;
+        }
+        ray.intersect_aabb(self.aabb.as_ref()?)?;
+        let (t, triangle, u, v) = self
+            .indices
+            .chunks_exact(3)
+            .filter_map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| self.positions[i as usize]);
+                let (distance, u, v) = ray.intersect_triangle_barycentric(a, b, c)?;
+                Some((distance, t, u, v))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))?;
+        let [a, b, c] =
+            [triangle[0], triangle[1], triangle[2]].map(|i| self.tex_coords[i as usize]);
+        let w = 1.0 - u - v;
+        let uv = [
+            a[0] * w + b[0] * u + c[0] * v,
+            a[1] * w + b[1] * u + c[1] * v,
+        ];
+        Some((ray.at(t), uv))
+    }
+
+    /// The vertex nearest `hit` among those within `radius` pixels of the
+    /// cursor on screen, or `hit` itself if there are none.
+    pub fn snap(
+        &self,
+        hit: Point3<f32>,
+        view_proj: &Matrix4<f32>,
+        cursor: (f32, f32),
+        size: (u32, u32),
+        radius: f32,
+    ) -> Point3<f32> {
+        self.positions
+            .iter()
+            .filter(|p| {
+                let clip = view_proj.transform_point(**p);
+                let x = (clip.x + 1.0) / 2.0 * size.0 as f32;
+                let y = (1.0 - clip.y) / 2.0 * size.1 as f32;
+                (0.0..=1.0).contains(&clip.z)
+                    && (x - cursor.0).powi(2) + (y - cursor.1).powi(2) <= radius * radius
+            })
+            // Nearest in the world rather than on screen, so vertices behind
+            // the surface that project close to the cursor aren't chosen.
+            .min_by(|a, b| {
+                (**a - hit)
+                    .magnitude2()
+                    .total_cmp(&(**b - hit).magnitude2())
+            })
+            .copied()
+            .unwrap_or(hit)
+    }
+}
+
+/// A CPU copy of a point cloud's positions, for picking points on screen.
+pub struct PickPoints {
+    pub positions: Vec<Point3<f32>>,
+    pub aabb: Option<Aabb>,
+}
+
+impl PickPoints {
+    /// Records a readback of the cloud's points into `encoder`, for
+    /// `on_read` once `readbacks` delivers it.
+    pub fn read_cloud(
+        readbacks: &mut ReadbackQueue,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        cloud: &PointCloud,
+        on_read: impl FnOnce(Result<Self, ReadbackError>) + Send + 'static,
+    ) -> Result<(), ReadbackError> {
+        if cloud.num_points == 0 {
+            on_read(Ok(Self::new(Vec::new())));
+            return Ok(());
+        }
+        readbacks.read_buffer(
+            device,
+            encoder,
+            &cloud.vertex_buffer,
+            0..Self::cloud_size(cloud),
+            ReadbackPolicy::Guaranteed,
+            |result| on_read(result.map(|data| Self::from_vertex_bytes(&data.bytes))),
+        )
+    }
+
+    fn cloud_size(cloud: &PointCloud) -> wgpu::BufferAddress {
+        cloud.num_points as u64 * std::mem::size_of::<PointVertex>() as u64
+    }
+
+    fn from_vertex_bytes(bytes: &[u8]) -> Self {
+        let points = bytemuck::pod_collect_to_vec::<u8, PointVertex>(bytes);
+        Self::new(points.iter().map(|p| Point3::from(p.position)).collect())
+    }
+
+    pub fn new(positions: Vec<Point3<f32>>) -> Self {
+        let aabb = Aabb::from_points(positions.iter().copied());
+        Self { positions, aabb }
+    }
+
+    /// The point nearest the camera among those within `radius` pixels of
+    /// the cursor on screen.
+    pub fn pick(
+        &self,
+        view_proj: &Matrix4<f32>,
+        cursor: (f32, f32),
+        size: (u32, u32),
+        radius: f32,
+    ) -> Option<Point3<f32>> {
+        self.positions
+            .iter()
+            .filter_map(|p| {
+                let clip = view_proj.transform_point(*p);
+                let x = (clip.x + 1.0) / 2.0 * size.0 as f32;
+                let y = (1.0 - clip.y) / 2.0 * size.1 as f32;
+                ((0.0..=1.0).contains(&clip.z)
+                    && (x - cursor.0).powi(2) + (y - cursor.1).powi(2) <= radius * radius)
+                    .then_some((clip.z, *p))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, p)| p)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    #[default]
+    Meters,
+    Centimeters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeasureSettings {
+    pub units: Units,
+    /// Meters per scene unit, for models not authored in meters.
+    pub scene_scale: f32,
+    /// Picked points snap to the nearest vertex within this many pixels.
+    /// `None` turns snapping off.
+    pub snap_radius: Option<f32>,
+}
+
+impl Default for MeasureSettings {
+    fn default() -> Self {
+        Self {
+            units: Units::default(),
+            scene_scale: 1.0,
+            snap_radius: Some(8.0),
+        }
+    }
+}
+
+impl MeasureSettings {
+    /// Formats a length in scene units.
+    pub fn format_length(&self, length: f32) -> String {
+        let meters = length * self.scene_scale;
+        match self.units {
+            Units::Meters => format!("{:.3} m", meters),
+            Units::Centimeters => format!("{:.1} cm", meters * 100.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeasureMode {
+    #[default]
+    Off,
+    /// Between two picked points.
+    Distance,
+    /// At the second of three picked points.
+    Angle,
+    /// Of the model's bounding box.
+    Dimensions,
+}
+
+impl MeasureMode {
+    /// The mode after this one, for cycling through them with one key.
+    pub fn next(self) -> Self {
+        match self {
+            MeasureMode::Off => MeasureMode::Distance,
+            MeasureMode::Distance => MeasureMode::Angle,
+            MeasureMode::Angle => MeasureMode::Dimensions,
+            MeasureMode::Dimensions => MeasureMode::Off,
+        }
+    }
+
+    fn points_needed(self) -> usize {
+        match self {
+            MeasureMode::Off | MeasureMode::Dimensions => 0,
+            MeasureMode::Distance => 2,
+            MeasureMode::Angle => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measurement {
+    Distance(f32),
+    Angle(Rad<f32>),
+    Dimensions(Vector3<f32>),
+}
+
+impl Measurement {
+    pub fn label(&self, settings: &MeasureSettings) -> String {
+        match self {
+            Measurement::Distance(d) => settings.format_length(*d),
+            Measurement::Angle(a) => format!("{:.1}°", cgmath::Deg::from(*a).0),
+            Measurement::Dimensions(size) => format!(
+                "{} x {} x {}",
+                settings.format_length(size.x),
+                settings.format_length(size.y),
+                settings.format_length(size.z)
+            ),
+        }
+    }
+}
+
+pub fn distance(a: Point3<f32>, b: Point3<f32>) -> f32 {
+    (b - a).magnitude()
+}
+
+/// The angle at `vertex` between the lines to `a` and `b`, or `None` if
+/// either has no length.
+pub fn angle(a: Point3<f32>, vertex: Point3<f32>, b: Point3<f32>) -> Option<Rad<f32>> {
+    let (u, v) = (a - vertex, b - vertex);
+    if u.magnitude2() == 0.0 || v.magnitude2() == 0.0 {
+        return None;
+    }
+    Some(u.angle(v))
+}
+
+/// Click driven measurement. Each click adds a picked point; once the mode
+/// has all it needs the result is kept until the next click starts over.
+#[derive(Debug, Clone, Default)]
+pub struct Measure {
+    mode: MeasureMode,
+    points: Vec<Point3<f32>>,
+    result: Option<Measurement>,
+    pub settings: MeasureSettings,
+}
+
+impl Measure {
+    pub fn new(settings: MeasureSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    pub fn mode(&self) -> MeasureMode {
+        self.mode
+    }
+
+    /// Switches mode, dropping any points picked so far.
+    pub fn set_mode(&mut self, mode: MeasureMode) {
+        self.mode = mode;
+        self.cancel();
+    }
+
+    pub fn cancel(&mut self) {
+        self.points.clear();
+        self.result = None;
+    }
+
+    /// The points of the measurement in progress or last finished, e.g. to
+    /// draw markers and lines between them.
+    pub fn points(&self) -> &[Point3<f32>] {
+        &self.points
+    }
+
+    pub fn result(&self) -> Option<Measurement> {
+        self.result
+    }
+
+    /// Adds a picked point. Returns the measurement if this point
+    /// completed one.
+    pub fn click(&mut self, point: Point3<f32>) -> Option<Measurement> {
+        let needed = self.mode.points_needed();
+        if needed == 0 {
+            return None;
+        }
+        if self.points.len() >= needed {
+            self.cancel();
+        }
+        self.points.push(point);
+        if self.points.len() < needed {
+            return None;
+        }
+        self.result = match self.mode {
+            MeasureMode::Distance => Some(Measurement::Distance(distance(
+                self.points[0],
+                self.points[1],
+            ))),
+            MeasureMode::Angle => {
+                angle(self.points[0], self.points[1], self.points[2]).map(Measurement::Angle)
+            }
+            MeasureMode::Off | MeasureMode::Dimensions => None,
+        };
+        self.result
+    }
+
+    /// Shows the size of `aabb` when in [`MeasureMode::Dimensions`].
+    pub fn measure_bounds(&mut self, aabb: &Aabb) -> Option<Measurement> {
+        if self.mode == MeasureMode::Dimensions {
+            self.result = Some(Measurement::Dimensions(aabb.size()));
+        }
+        self.result
+    }
+
+    /// A line describing the current state, e.g. for a status bar.
+    pub fn status(&self) -> String {
+        match (self.mode, self.result) {
+            (MeasureMode::Off, _) => String::new(),
+            (_, Some(result)) => result.label(&self.settings),
+            (MeasureMode::Dimensions, None) => "Dimensions: nothing to measure".to_string(),
+            // Points that measured nothing, such as an angle with two of
+            // them in one place, are replaced by the next click.
+            (mode, None) => format!(
+                "{:?}: pick point {} of {}",
+                mode,
+                self.points.len() % mode.points_needed() + 1,
+                mode.points_needed()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+
+    fn p(x: f32, y: f32, z: f32) -> Point3<f32> {
+        Point3::new(x, y, z)
+    }
+
+    #[test]
+    fn distance_takes_two_clicks_and_then_starts_over() {
+        let mut measure = Measure::default();
+        assert_eq!(measure.click(p(0.0, 0.0, 0.0)), None, "off picks nothing");
+        assert!(measure.points().is_empty());
+
+        measure.set_mode(MeasureMode::Distance);
+        assert_eq!(measure.status(), "Distance: pick point 1 of 2");
+        assert_eq!(measure.click(p(1.0, 2.0, 3.0)), None);
+        assert_eq!(measure.status(), "Distance: pick point 2 of 2");
+        let result = measure.click(p(4.0, 6.0, 3.0));
+        assert_eq!(result, Some(Measurement::Distance(5.0)));
+        assert_eq!(measure.result(), result);
+        assert_eq!(measure.points(), &[p(1.0, 2.0, 3.0), p(4.0, 6.0, 3.0)]);
+        assert_eq!(measure.status(), "5.000 m");
+
+        // The next click begins a new measurement.
+        assert_eq!(measure.click(p(0.0, 0.0, 0.0)), None);
+        assert_eq!(measure.points(), &[p(0.0, 0.0, 0.0)]);
+        assert_eq!(measure.result(), None);
+
+        // As does switching modes or cancelling.
+        measure.set_mode(MeasureMode::Angle);
+        assert!(measure.points().is_empty());
+        measure.click(p(1.0, 0.0, 0.0));
+        measure.cancel();
+        assert!(measure.points().is_empty());
+    }
+
+    #[test]
+    fn angle_is_at_the_second_point() {
+        let mut measure = Measure::default();
+        measure.set_mode(MeasureMode::Angle);
+        measure.click(p(2.0, 0.0, 0.0));
+        assert_eq!(measure.click(p(0.0, 0.0, 0.0)), None);
+        let Some(Measurement::Angle(angle)) = measure.click(p(0.0, 0.0, -3.0)) else {
+            panic!("no angle after three points");
+        };
+        assert!((Deg::from(angle).0 - 90.0).abs() < 1e-4, "{:?}", angle);
+        assert_eq!(measure.result().unwrap().label(&measure.settings), "90.0°");
+
+        // Two points in the same place leave no angle to measure.
+        measure.click(p(1.0, 0.0, 0.0));
+        measure.click(p(1.0, 0.0, 0.0));
+        assert_eq!(measure.click(p(0.0, 1.0, 0.0)), None);
+        assert_eq!(measure.status(), "Angle: pick point 1 of 3");
+    }
+
+    #[test]
+    fn distance_and_angle_math() {
+        assert_eq!(distance(p(1.0, 1.0, 1.0), p(1.0, 1.0, 1.0)), 0.0);
+        assert_eq!(distance(p(-1.0, 0.0, 0.0), p(2.0, 0.0, -4.0)), 5.0);
+
+        let straight = angle(p(-1.0, 0.0, 0.0), p(0.0, 0.0, 0.0), p(3.0, 0.0, 0.0)).unwrap();
+        assert!((Deg::from(straight).0 - 180.0).abs() < 1e-4);
+        let acute = angle(p(1.0, 0.0, 0.0), p(0.0, 0.0, 0.0), p(1.0, 1.0, 0.0)).unwrap();
+        assert!((Deg::from(acute).0 - 45.0).abs() < 1e-4);
+        assert_eq!(
+            angle(p(0.0, 0.0, 0.0), p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn dimensions_and_units() {
+        let mut measure = Measure::new(MeasureSettings {
+            units: Units::Centimeters,
+            scene_scale: 0.5,
+            snap_radius: None,
+        });
+        let aabb = Aabb::new(p(-1.0, 0.0, 2.0), p(1.0, 0.5, 3.0));
+        assert_eq!(
+            measure.measure_bounds(&aabb),
+            None,
+            "only in dimensions mode"
+        );
+
+        measure.set_mode(MeasureMode::Dimensions);
+        assert_eq!(measure.status(), "Dimensions: nothing to measure");
+        assert_eq!(measure.click(p(0.0, 0.0, 0.0)), None);
+        let result = measure.measure_bounds(&aabb);
+        assert_eq!(
+            result,
+            Some(Measurement::Dimensions(Vector3::new(2.0, 0.5, 1.0)))
+        );
+        assert_eq!(measure.status(), "100.0 cm x 25.0 cm x 50.0 cm");
+    }
+
+    #[test]
+    fn picked_uvs_interpolate_the_nearest_triangle() {
+        // Two unit quads facing +z, one in front of the other, with their
+        // UVs running across them.
+        let positions = [0.0, -1.0]
+            .iter()
+            .flat_map(|&z| {
+                [
+                    p(0.0, 0.0, z),
+                    p(1.0, 0.0, z),
+                    p(1.0, 1.0, z),
+                    p(0.0, 1.0, z),
+                ]
+            })
+            .collect();
+        let mut mesh = PickMesh::new(positions, vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+        let ray = Ray::new(p(0.25, 0.75, 5.0), -Vector3::unit_z());
+        assert_eq!(mesh.pick_uv(&ray), None, "no UVs to pick");
+
+        let uvs = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+        mesh.tex_coords = uvs.iter().chain(&[[0.5; 2]; 4]).copied().collect();
+        let (hit, uv) = mesh.pick_uv(&ray).unwrap();
+        assert_eq!(hit, p(0.25, 0.75, 0.0));
+        assert!(
+            (uv[0] - 0.25).abs() < 1e-6 && (uv[1] - 0.25).abs() < 1e-6,
+            "{:?}",
+            uv
+        );
+        assert_eq!(mesh.pick(&ray), Some(hit));
+
+        let miss = Ray::new(p(2.0, 0.5, 5.0), -Vector3::unit_z());
+        assert_eq!(mesh.pick_uv(&miss), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Triplanar mapping: materials that project their diffuse map along the
+//! three axes instead of reading the mesh's texture coordinates, and blend
+//! the projections by how squarely the surface faces each axis. Meshes
+//! without usable UVs, or with UVs that pinch at poles or tear along seams,
+//! look even all over.
+//!
+//! A triplanar material keeps its plain bind group, so every pipeline can
+//! still draw it with its UVs, and gets a second one for the `HAS_TRIPLANAR`
+//! variant of the model shader, with its parameters and a repeating sampler.
+
+use crate::{gpu, texture};
+
+/// Which axes the textures are projected along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TriplanarSpace {
+    /// The world's. Instances that move slide under the texture, which
+    /// suits static geometry such as terrain, where neighbouring meshes then
+    /// line up.
+    #[default]
+    World,
+    /// The mesh's own, before the instance transform, so the texture moves
+    /// with the object.
+    Object,
+}
+
+/// The parameters of a triplanar material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Triplanar {
+    /// Texture repeats per unit of distance.
+    pub scale: f32,
+    /// The power the normal's absolute components are raised to for the
+    /// blend weights. Higher values narrow the blend between projections.
+    pub blend_sharpness: f32,
+    pub space: TriplanarSpace,
+}
+
+impl Triplanar {
+    pub const DEFAULT: Triplanar = Triplanar {
+        scale: 1.0,
+        blend_sharpness: 4.0,
+        space: TriplanarSpace::World,
+    };
+
+    /// The MTL statement for a triplanar material:
+    /// `triplanar <scale> [<blend sharpness> [world|object]]`. tobj keeps
+    /// statements it doesn't know in `unknown_param`.
+    pub const MTL_KEY: &'static str = "triplanar";
+
+    /// Parses the arguments of a [`Self::MTL_KEY`] statement. Missing
+    /// arguments take their [`Self::DEFAULT`], and malformed ones are
+    /// logged and leave the material UV mapped.
+    pub fn from_mtl(arguments: &str) -> Option<Self> {
+        let triplanar = Self::parse_mtl(arguments);
+        if triplanar.is_none() {
+            log::warn!("Ignoring `{} {}`", Self::MTL_KEY, arguments);
+        }
+        triplanar
+    }
+
+    fn parse_mtl(arguments: &str) -> Option<Self> {
+        let mut tokens = arguments.split_whitespace();
+        let mut triplanar = Self::DEFAULT;
+        if let Some(scale) = tokens.next() {
+            triplanar.scale = scale.parse().ok()?;
+        }
+        if let Some(sharpness) = tokens.next() {
+            triplanar.blend_sharpness = sharpness.parse().ok()?;
+        }
+        triplanar.space = match tokens.next() {
+            None | Some("world") => TriplanarSpace::World,
+            Some("object") => TriplanarSpace::Object,
+            Some(_) => return None,
+        };
+        tokens.next().is_none().then_some(triplanar)
+    }
+
+    /// The arguments [`Self::from_mtl`] reads back.
+    pub fn to_mtl(&self) -> String {
+        let space = match self.space {
+            TriplanarSpace::World => "world",
+            TriplanarSpace::Object => "object",
+        };
+        format!("{} {} {}", self.scale, self.blend_sharpness, space)
+    }
+}
+
+impl Default for Triplanar {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TriplanarUniform {
+    scale: f32,
+    blend_sharpness: f32,
+    object_space: u32,
+    _padding: u32,
+}
+
+impl From<Triplanar> for TriplanarUniform {
+    fn from(triplanar: Triplanar) -> Self {
+        Self {
+            scale: triplanar.scale,
+            blend_sharpness: triplanar.blend_sharpness,
+            object_space: (triplanar.space == TriplanarSpace::Object) as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// What a material needs to be drawn triplanar: group 0 of the
+/// `HAS_TRIPLANAR` model pipelines.
+#[derive(Debug)]
+pub struct TriplanarBinding {
+    triplanar: Triplanar,
+    uniform_buffer: wgpu::Buffer,
+    _memory: gpu::MemoryToken,
+    _sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl TriplanarBinding {
+    /// Binds `diffuse_texture` for the projections. Its own sampler clamps
+    /// to the edge, which would smear the texture past the first repeat, so
+    /// the binding has a repeating one.
+    pub fn new(
+        device: &wgpu::Device,
+        diffuse_texture: &texture::Texture,
+        triplanar: Triplanar,
+    ) -> Result<Self, gpu::AllocError> {
+        // wgpu shares layouts with the same entries, so this one works with
+        // the pipelines built from `crate::create_triplanar_bind_group_layout`.
+        let layout = crate::create_triplanar_bind_group_layout(device);
+        let (uniform_buffer, uniform_buffer_memory) = gpu::Allocator::new(device)
+            .create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Triplanar Buffer"),
+                    contents: bytemuck::cast_slice(&[TriplanarUniform::from(triplanar)]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                },
+                gpu::MemoryCategory::Uniform,
+            )?;
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("triplanar_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("triplanar_bind_group"),
+        });
+        Ok(Self {
+            triplanar,
+            uniform_buffer,
+            _memory: uniform_buffer_memory,
+            _sampler: sampler,
+            bind_group,
+        })
+    }
+
+    pub fn triplanar(&self) -> Triplanar {
+        self.triplanar
+    }
+
+    /// Changes the parameters in place, e.g. from an editor.
+    pub fn set_triplanar(&mut self, queue: &wgpu::Queue, triplanar: Triplanar) {
+        self.triplanar = triplanar;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TriplanarUniform::from(triplanar)]),
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! How UI is composited over the scene. UI draws last, after everything
+//! the scene does, at the surface's native size, in sRGB encoded values
+//! with premultiplied alpha, and blends as if the target stored encoded
+//! values, the way image editors and browsers blend. That keeps edges and
+//! gradients looking as authored whatever the scene renders into.
+//!
+//! Where the surface stores encoded values, or has a view that does, UI
+//! draws straight into it. Otherwise, for sRGB surfaces without view
+//! formats and linear HDR surfaces, it draws into a layer of its own that
+//! is decoded and blended over the scene afterwards. Opaque UI comes out
+//! the same either way, but translucent UI over the scene blends in linear
+//! on the layer path.
+
+use crate::{
+    gpu::{AllocError, Allocator, MemoryCategory, MemoryToken, OutputMode},
+    reflect,
+    stats::FrameStats,
+};
+
+/// Where UI is drawn for a surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiPath {
+    /// Straight into the surface, through a view of this format.
+    Direct(wgpu::TextureFormat),
+    /// Into a [`UiCompositor::LAYER_FORMAT`] layer, then over the surface
+    /// with linear colors scaled by `paper_white_scale`, then PQ encoded
+    /// if `pq`.
+    Layer { paper_white_scale: f32, pq: bool },
+}
+
+impl UiPath {
+    /// The path for a surface of `surface_format` in `output_mode`.
+    /// `view_formats` is whether the surface can have views of another
+    /// format, [`wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS`].
+    pub fn select(
+        surface_format: wgpu::TextureFormat,
+        output_mode: OutputMode,
+        view_formats: bool,
+        paper_white_nits: f32,
+    ) -> Self {
+        if surface_format.is_srgb() {
+            if view_formats {
+                UiPath::Direct(surface_format.remove_srgb_suffix())
+            } else {
+                UiPath::Layer {
+                    paper_white_scale: 1.0,
+                    pq: false,
+                }
+            }
+        } else if output_mode == OutputMode::Sdr {
+            // SDR surfaces without an sRGB format store what's written.
+            UiPath::Direct(surface_format)
+        } else {
+            UiPath::Layer {
+                paper_white_scale: output_mode.paper_white_scale(paper_white_nits),
+                pq: output_mode == OutputMode::Hdr10,
+            }
+        }
+    }
+
+    /// The view formats the surface must be configured with for this path.
+    pub fn surface_view_formats(
+        &self,
+        surface_format: wgpu::TextureFormat,
+    ) -> Vec<wgpu::TextureFormat> {
+        match *self {
+            UiPath::Direct(format) if format != surface_format => vec![format],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CompositeUniform {
+    paper_white_scale: f32,
+    pq: u32,
+    _padding: [f32; 2],
+}
+
+/// The target UI draws into, and for [`UiPath::Layer`] the pass that
+/// blends it over the surface.
+pub struct UiCompositor {
+    path: UiPath,
+    composite: Option<Composite>,
+}
+
+struct Composite {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    _memory: MemoryToken,
+    layer: Layer,
+}
+
+/// The UI layer, the size of the surface.
+struct Layer {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    _memory: MemoryToken,
+}
+
+impl UiCompositor {
+    /// Encoded values, so UI blends in the layer as it would in the surface.
+    pub const LAYER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    pub(crate) const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// For a surface of `surface_format`, `size` across. Only
+    /// [`UiPath::Layer`] allocates anything.
+    pub fn new(
+        device: &wgpu::Device,
+        path: UiPath,
+        surface_format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let UiPath::Layer {
+            paper_white_scale,
+            pq,
+        } = path
+        else {
+            return Ok(Self {
+                path,
+                composite: None,
+            });
+        };
+
+        let source = include_str!("ui.wgsl");
+        reflect::debug_check("ui.wgsl", source, &[&Self::LAYOUT_ENTRIES]);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ui.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::LAYOUT_ENTRIES,
+            label: Some("ui_composite_bind_group_layout"),
+        });
+        let (uniform_buffer, uniform_buffer_memory) = Allocator::new(device).create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("UI Composite Buffer"),
+                size: std::mem::size_of::<CompositeUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            },
+            MemoryCategory::Uniform,
+        )?;
+        uniform_buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .copy_from_slice(bytemuck::bytes_of(&CompositeUniform {
+                paper_white_scale,
+                pq: pq as u32,
+                _padding: [0.0; 2],
+            }));
+        uniform_buffer.unmap();
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("UI Composite Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("UI Composite Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let layer = Layer::new(device, &layout, &uniform_buffer, size)?;
+
+        Ok(Self {
+            path,
+            composite: Some(Composite {
+                pipeline,
+                layout,
+                uniform_buffer,
+                _memory: uniform_buffer_memory,
+                layer,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> UiPath {
+        self.path
+    }
+
+    /// The format UI pipelines draw in, for
+    /// [`SpriteBatch::new`](crate::sprite::SpriteBatch::new).
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self.path {
+            UiPath::Direct(format) => format,
+            UiPath::Layer { .. } => Self::LAYER_FORMAT,
+        }
+    }
+
+    /// Recreates the layer for a surface `size` across.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) -> Result<(), AllocError> {
+        if let Some(composite) = &mut self.composite {
+            composite.layer =
+                Layer::new(device, &composite.layout, &composite.uniform_buffer, size)?;
+        }
+        Ok(())
+    }
+
+    /// The view UI passes draw into this frame, of `surface` or the layer.
+    pub fn target(&self, surface: &wgpu::Texture) -> wgpu::TextureView {
+        let texture = match &self.composite {
+            Some(composite) => &composite.layer.texture,
+            None => surface,
+        };
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.format()),
+            ..Default::default()
+        })
+    }
+
+    /// How the first UI pass of a frame loads [`Self::target`]. The layer
+    /// starts out transparent.
+    pub fn load_op(&self) -> wgpu::LoadOp<wgpu::Color> {
+        match self.path {
+            UiPath::Direct(_) => wgpu::LoadOp::Load,
+            UiPath::Layer { .. } => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+        }
+    }
+
+    /// Clears the layer, for frames whose first UI pass loads
+    /// [`Self::target`] instead of starting it off with [`Self::load_op`].
+    /// Returns whether a pass was recorded, which it isn't on
+    /// [`UiPath::Direct`].
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) -> bool {
+        if !self.has_layer() {
+            return false;
+        }
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("UI Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: self.load_op(),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        true
+    }
+
+    /// Whether [`Self::composite`] records a pass.
+    pub fn has_layer(&self) -> bool {
+        self.composite.is_some()
+    }
+
+    /// Adds the commands [`Self::composite`] records.
+    pub fn add_stats(&self, stats: &mut FrameStats) {
+        if self.has_layer() {
+            stats.pipeline_sets += 1;
+            stats.bind_group_sets += 1;
+            stats.draw_calls += 1;
+            stats.instances += 1;
+            stats.triangles += 1;
+        }
+    }
+
+    /// Blends the layer over `surface`, a view of the surface in its own
+    /// format. Does nothing on [`UiPath::Direct`]. Record it after the last
+    /// UI pass.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, surface: &wgpu::TextureView) {
+        let Some(composite) = &self.composite else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("UI Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&composite.pipeline);
+        render_pass.set_bind_group(0, &composite.layer.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl Layer {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        (width, height): (u32, u32),
+    ) -> Result<Self, AllocError> {
+        let (texture, memory) = Allocator::new(device).create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("UI Layer"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: UiCompositor::LAYER_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            MemoryCategory::Target,
+        )?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ui_composite_bind_group"),
+        });
+        Ok(Self {
+            texture,
+            bind_group,
+            _memory: memory,
+        })
+    }
+}
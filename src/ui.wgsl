@@ -0,0 +1,55 @@
+// Blends the UI layer over the scene. The layer holds sRGB encoded colors
+// with premultiplied alpha; they're decoded to linear, scaled to paper white
+// and premultiplied again, for a target that stores linear values, or PQ
+// encoded for an HDR10 target.
+
+struct Composite {
+    paper_white_scale: f32,
+    pq: u32,
+    _padding2: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_layer: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> composite: Composite;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle covering the whole target.
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+fn decode(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+// SMPTE ST 2084, for linear values where 1.0 is 10000 nits. See
+// `gpu::pq_encode`.
+fn pq_encode(c: vec3<f32>) -> vec3<f32> {
+    let m1 = 2610.0 / 16384.0;
+    let m2 = 2523.0 / 4096.0 * 128.0;
+    let c1 = 3424.0 / 4096.0;
+    let c2 = 2413.0 / 4096.0 * 32.0;
+    let c3 = 2392.0 / 4096.0 * 32.0;
+    let y = pow(clamp(c, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3<f32>(m2));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // The layer is the size of the target, so every pixel reads its own.
+    let ui = textureLoad(t_layer, vec2<i32>(position.xy), 0);
+    if ui.a <= 0.0 {
+        discard;
+    }
+    let straight = min(ui.rgb / ui.a, vec3<f32>(1.0));
+    var color = decode(straight) * composite.paper_white_scale;
+    if composite.pq != 0u {
+        color = pq_encode(color);
+    }
+    return vec4<f32>(color * ui.a, ui.a);
+}
@@ -0,0 +1,128 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    animation::{Animation, ChannelValues, Skeleton},
+    bounds::Aabb,
+    model::Node,
+};
+
+/// The unit of length a source file was authored in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Unit {
+    /// What the crate uses, and what glTF specifies.
+    #[default]
+    Meters,
+    /// Common for assets that went through FBX.
+    Centimeters,
+    /// This many meters per unit.
+    Custom(f32),
+}
+
+/// The unit loaded assets are converted to.
+pub const TARGET_UNIT: Unit = Unit::Meters;
+
+/// Loaded models larger or smaller than this across, in meters, were most
+/// likely authored in another unit than assumed.
+pub const PLAUSIBLE_SIZE: RangeInclusive<f32> = 0.001..=1000.0;
+
+impl Unit {
+    pub fn meters(self) -> f32 {
+        match self {
+            Unit::Meters => 1.0,
+            Unit::Centimeters => 0.01,
+            Unit::Custom(meters) => meters,
+        }
+    }
+
+    pub fn scale_to(self, target: Unit) -> UnitScale {
+        UnitScale(self.meters() / target.meters())
+    }
+
+    /// The unit named by `name`, such as "m" or "centimeters".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "m" | "meter" | "meters" | "metre" | "metres" => Some(Unit::Meters),
+            "cm" | "centimeter" | "centimeters" | "centimetre" | "centimetres" => {
+                Some(Unit::Centimeters)
+            }
+            "mm" | "millimeter" | "millimeters" | "millimetre" | "millimetres" => {
+                Some(Unit::Custom(0.001))
+            }
+            "in" | "inch" | "inches" => Some(Unit::Custom(0.0254)),
+            _ => None,
+        }
+    }
+
+    /// The unit hinted at by the raw JSON of a glTF `asset.extras`: a
+    /// `unit`, `units` or `lengthUnit` name, or a number of `metersPerUnit`
+    /// or `unitScale`. glTF itself is always in meters, but pipelines that
+    /// bake FBX into it sometimes leave centimeters and say so here.
+    /// Generator names don't reliably say which, so they aren't read.
+    pub fn from_gltf_extras(extras: &str) -> Option<Self> {
+        let extras: serde_json::Value = serde_json::from_str(extras).ok()?;
+        let named = ["unit", "units", "lengthUnit"]
+            .iter()
+            .find_map(|key| Self::from_name(extras.get(key)?.as_str()?));
+        let scaled = ["metersPerUnit", "unitScale"]
+            .iter()
+            .find_map(|key| extras.get(key)?.as_f64())
+            .filter(|&meters| meters > 0.0)
+            .map(|meters| Unit::Custom(meters as f32));
+        named.or(scaled)
+    }
+}
+
+/// Scales lengths from one unit to another. Only lengths change: rotations,
+/// scales and normals are the same in any unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitScale(pub f32);
+
+impl UnitScale {
+    pub fn is_identity(&self) -> bool {
+        self.0 == 1.0
+    }
+
+    /// Converts a length, such as a light's range.
+    pub fn length(&self, length: f32) -> f32 {
+        length * self.0
+    }
+
+    pub fn position(&self, p: [f32; 3]) -> [f32; 3] {
+        p.map(|x| x * self.0)
+    }
+
+    /// Converts a camera's near and far planes.
+    pub fn clip_planes(&self, near: f32, far: f32) -> (f32, f32) {
+        (near * self.0, far * self.0)
+    }
+
+    pub fn convert_node(&self, node: &mut Node) {
+        node.position *= self.0;
+        node.update_transform();
+    }
+
+    pub fn convert_skeleton(&self, skeleton: &mut Skeleton) {
+        for joint in &mut skeleton.joints {
+            joint.translation *= self.0;
+        }
+    }
+
+    /// Scales the translation channels.
+    pub fn convert_animation(&self, animation: &mut Animation) {
+        for channel in &mut animation.channels {
+            if let ChannelValues::Translations(translations) = &mut channel.values {
+                for t in translations {
+                    *t *= self.0;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `aabb`, in [`TARGET_UNIT`], is within [`PLAUSIBLE_SIZE`] on its
+/// longest side.
+pub fn is_plausible_size(aabb: &Aabb) -> bool {
+    let size = aabb.size();
+    let longest = size.x.max(size.y).max(size.z) * TARGET_UNIT.meters();
+    PLAUSIBLE_SIZE.contains(&longest)
+}
@@ -0,0 +1,281 @@
+//! Texture coordinates projected from positions, for meshes that come
+//! without any, such as most scans. See [`MeshData::generate_uvs`].
+//!
+//! Coordinates past 1, from a `scale` above 1 or along the wrap-around seam
+//! of the cylindrical and spherical projections, need a repeating sampler to
+//! show the texture rather than its edge.
+
+use std::{collections::HashMap, f32::consts::PI};
+
+use crate::model::MeshData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+/// How [`MeshData::generate_uvs`] maps positions to texture coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UvProjection {
+    /// Straight along `axis`, as seen from its positive end. Sides parallel
+    /// to the axis get stretched. Coordinates are distances in the mesh's
+    /// units, so the texture repeats once per unit at a scale of 1.
+    Planar { axis: Axis },
+    /// Planar along whichever of X, Y and Z each triangle faces most, so
+    /// every side of a box gets an undistorted, upright copy of the texture.
+    /// Also in the mesh's units: a box fits the texture to its faces at a
+    /// scale of one over its size.
+    Box,
+    /// Wrapped around `axis` through the middle of the mesh: u goes once
+    /// around, and v from the top of the mesh to the bottom.
+    Cylindrical { axis: Axis },
+    /// Wrapped around the middle of the mesh: u goes once around Y, and v
+    /// from the top pole to the bottom one.
+    Spherical,
+}
+
+/// Which way u and v run on a plane, as an axis and whether it points the
+/// same way as that axis. v runs down, so the plane's up is the negated v.
+struct Frame {
+    u: (usize, bool),
+    up: (usize, bool),
+}
+
+impl Frame {
+    /// The plane across `axis`, seen from its positive or negative end with
+    /// Y up, or -Z up when looking along Y.
+    fn facing(axis: usize, positive: bool) -> Self {
+        let (u, up) = match (axis, positive) {
+            (0, true) => ((2, false), (1, true)),
+            (0, false) => ((2, true), (1, true)),
+            (1, true) => ((0, true), (2, false)),
+            (1, false) => ((0, true), (2, true)),
+            (2, true) => ((0, true), (1, true)),
+            _ => ((0, false), (1, true)),
+        };
+        Self { u, up }
+    }
+
+    /// Distances from the bounds' edges, so a box's faces start at 0.
+    fn project(&self, p: [f32; 3], min: [f32; 3], max: [f32; 3]) -> [f32; 2] {
+        let (u_axis, u_positive) = self.u;
+        let (up_axis, up_positive) = self.up;
+        let u = if u_positive {
+            p[u_axis] - min[u_axis]
+        } else {
+            max[u_axis] - p[u_axis]
+        };
+        let v = if up_positive {
+            max[up_axis] - p[up_axis]
+        } else {
+            p[up_axis] - min[up_axis]
+        };
+        [u, v]
+    }
+}
+
+pub(crate) fn generate(data: &mut MeshData, projection: UvProjection, scale: f32) {
+    let Some((min, max)) = bounds(data) else {
+        return;
+    };
+    let center = [0, 1, 2].map(|k| (min[k] + max[k]) / 2.0);
+    let extent = [0, 1, 2].map(|k| max[k] - min[k]);
+
+    let mut remap = HashMap::new();
+    let mut vertices = Vec::with_capacity(data.vertices.len());
+    let mut indices = Vec::with_capacity(data.indices.len());
+    for triangle in data.indices.chunks_exact(3) {
+        let positions = [0, 1, 2].map(|c| data.vertices[triangle[c] as usize].position);
+        let uvs = match projection {
+            UvProjection::Planar { axis } => {
+                let frame = Frame::facing(axis.index(), true);
+                positions.map(|p| frame.project(p, min, max))
+            }
+            UvProjection::Box => {
+                let normal = face_normal(positions);
+                let axis = (0..3)
+                    .max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs()))
+                    .unwrap();
+                let frame = Frame::facing(axis, normal[axis] >= 0.0);
+                positions.map(|p| frame.project(p, min, max))
+            }
+            UvProjection::Cylindrical { axis } => {
+                let k = axis.index();
+                let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+                let around = positions.map(|p| {
+                    let (di, dj) = (p[i] - center[i], p[j] - center[j]);
+                    (di != 0.0 || dj != 0.0).then(|| dj.atan2(di) / (2.0 * PI) + 0.5)
+                });
+                let along = positions.map(|p| {
+                    if extent[k] > 0.0 {
+                        (max[k] - p[k]) / extent[k]
+                    } else {
+                        0.0
+                    }
+                });
+                let u = wrap(around);
+                [0, 1, 2].map(|c| [u[c], along[c]])
+            }
+            UvProjection::Spherical => {
+                let around = positions.map(|p| {
+                    let (dx, dz) = (p[0] - center[0], p[2] - center[2]);
+                    (dx != 0.0 || dz != 0.0).then(|| dx.atan2(dz) / (2.0 * PI) + 0.5)
+                });
+                let down = positions.map(|p| {
+                    let d = [0, 1, 2].map(|k| p[k] - center[k]);
+                    let length = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                    if length > 0.0 {
+                        (d[1] / length).clamp(-1.0, 1.0).acos() / PI
+                    } else {
+                        0.5
+                    }
+                });
+                let u = wrap(around);
+                [0, 1, 2].map(|c| [u[c], down[c]])
+            }
+        };
+
+        for (&index, uv) in triangle.iter().zip(uvs) {
+            let uv = [uv[0] * scale, uv[1] * scale];
+            // Corners sharing a vertex but not its coordinates, such as on
+            // box edges and the seam, get a copy of it each.
+            let new_index = *remap
+                .entry((index, uv.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    let mut vertex = data.vertices[index as usize];
+                    vertex.tex_coords = uv;
+                    vertices.push(vertex);
+                    vertices.len() as u32 - 1
+                });
+            indices.push(new_index);
+        }
+    }
+    data.vertices = vertices;
+    data.indices = indices;
+}
+
+fn bounds(data: &MeshData) -> Option<([f32; 3], [f32; 3])> {
+    data.indices
+        .iter()
+        .map(|&i| data.vertices[i as usize].position)
+        .fold(None, |bounds, p| {
+            let (min, max) = bounds.unwrap_or((p, p));
+            Some((
+                [0, 1, 2].map(|k| min[k].min(p[k])),
+                [0, 1, 2].map(|k| max[k].max(p[k])),
+            ))
+        })
+}
+
+fn face_normal([a, b, c]: [[f32; 3]; 3]) -> [f32; 3] {
+    let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ]
+}
+
+/// The u of a triangle's corners going around an axis, kept on one side of
+/// the seam. A triangle crossing it would otherwise run back across the
+/// whole texture, so its corners just past 0 are moved past 1 instead.
+/// Corners on the axis have no direction, and take the others' average.
+fn wrap(around: [Option<f32>; 3]) -> [f32; 3] {
+    let known = around.iter().flatten().copied();
+    let (lowest, highest) = known.fold((f32::MAX, f32::MIN), |(lo, hi), u| (lo.min(u), hi.max(u)));
+    let crosses = highest - lowest > 0.5;
+    let around = around.map(|u| u.map(|u| if crosses && u < 0.5 { u + 1.0 } else { u }));
+    let (sum, count) = around
+        .iter()
+        .flatten()
+        .fold((0.0, 0), |(sum, count), u| (sum + u, count + 1));
+    let average = if count > 0 { sum / count as f32 } else { 0.5 };
+    around.map(|u| u.unwrap_or(average))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::ModelVertex, primitives};
+
+    /// [`primitives::cube`] with its coordinates cleared, and its corners
+    /// shared between faces when `welded`.
+    fn cube(size: f32, welded: bool) -> (MeshData, Vec<[f32; 2]>) {
+        let (primitive, indices) = primitives::cube(size);
+        let mut data = MeshData::default();
+        let mut remap = HashMap::new();
+        for &i in &indices {
+            let p = &primitive[i as usize];
+            let key = (p.position.map(f32::to_bits), (!welded).then_some(i));
+            let index = *remap.entry(key).or_insert_with(|| {
+                data.vertices.push(ModelVertex {
+                    position: p.position,
+                    tex_coords: [0.0, 0.0],
+                    normal: p.normal,
+                    occlusion: 1.0,
+                });
+                data.vertices.len() as u32 - 1
+            });
+            data.indices.push(index);
+        }
+        let uvs = indices
+            .iter()
+            .map(|&i| primitive[i as usize].tex_coords)
+            .collect();
+        (data, uvs)
+    }
+
+    fn corner_uvs(data: &MeshData) -> Vec<[f32; 2]> {
+        data.indices
+            .iter()
+            .map(|&i| data.vertices[i as usize].tex_coords)
+            .collect()
+    }
+
+    #[test]
+    fn box_mapping_a_unit_cube_gives_every_face_the_whole_texture() {
+        for welded in [false, true] {
+            let (mut data, face_uvs) = cube(1.0, welded);
+            data.generate_uvs(UvProjection::Box, 1.0);
+            // Upright on every face, as the cube's own coordinates are.
+            assert_eq!(corner_uvs(&data), face_uvs);
+            // Shared corners are copied for each face needing other
+            // coordinates for them. Four get the same from two faces.
+            assert_eq!(data.vertices.len(), if welded { 20 } else { 24 });
+            assert_eq!(data.indices.len(), 36);
+        }
+    }
+
+    #[test]
+    fn planar_and_box_coordinates_are_in_mesh_units() {
+        let (mut data, face_uvs) = cube(2.0, false);
+        data.generate_uvs(UvProjection::Box, 1.0);
+        let doubled = face_uvs.iter().map(|uv| uv.map(|c| c * 2.0));
+        assert!(corner_uvs(&data).into_iter().eq(doubled));
+
+        // Scaling by the inverse size fits the texture to the faces again.
+        data.generate_uvs(UvProjection::Box, 0.5);
+        assert_eq!(corner_uvs(&data), face_uvs);
+
+        let (mut data, _) = cube(4.0, true);
+        data.generate_uvs(UvProjection::Planar { axis: Axis::Z }, 1.0);
+        let extent = |k: usize| {
+            let values = data.vertices.iter().map(|v| v.tex_coords[k]);
+            values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min)
+        };
+        assert_eq!((extent(0), extent(1)), (4.0, 4.0));
+    }
+}
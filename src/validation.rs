@@ -0,0 +1,3739 @@
+//! Smoke tests every backend the host has, so a change that only breaks
+//! Metal or GL shows up while working on Vulkan. [`run`] creates a headless
+//! device per backend and draws a fixed set of [`Scene`]s on each. Backends
+//! without an adapter are skipped, not failed, and every scene runs in its
+//! own error scope so one broken scene doesn't hide the others.
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+use cgmath::{InnerSpace, Matrix4, Point3, Rotation3, Transform};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    assets::Assets,
+    compose, compression, export, exposure,
+    frame::{self, DrawFlags, LayerNames, Layers, MaterialOverrides, ModelHandle, RenderFrame},
+    gpu,
+    math::projection::{self, DepthRange},
+    mipmap,
+    model::{self, Model, ModelVertex, PointVertex, Vertex},
+    model_data::{MaterialRecord, MeshRecord, ModelData},
+    particle::{Particle, ParticleBatch},
+    pipeline::{self, FileStatus, PipelineOptions},
+    ply,
+    points::{PointPipeline, PointSize},
+    reflect::{self, LayoutMismatch, ReflectError, ShaderReflection},
+    refraction::{RefractionDraw, RefractionPass, Refractive},
+    region::Viewport,
+    resources,
+    shadow::{AtlasTile, ShadowAtlas, ShadowRequest},
+    sprite::{Sprite, SpriteBatch, SpriteTexture},
+    stats, texture, tonemap,
+    tools::PickPoints,
+    triplanar::{Triplanar, TriplanarSpace},
+    ui::{UiCompositor, UiPath},
+    units::{self, Unit, UnitScale},
+    variant::{
+        self, InstanceLayout, MaterialFeatures, PassType, PipelineCache, PipelineKey,
+        PipelineState, ShadingTier,
+    },
+    window::ResizeDebounce,
+    Camera, CameraUniform, Instance, State,
+};
+
+/// The backends [`run`] tries, one at a time.
+pub const BACKENDS: [wgpu::Backends; 6] = [
+    wgpu::Backends::VULKAN,
+    wgpu::Backends::METAL,
+    wgpu::Backends::DX12,
+    wgpu::Backends::DX11,
+    wgpu::Backends::GL,
+    wgpu::Backends::BROWSER_WEBGPU,
+];
+
+const TARGET_SIZE: (u32, u32) = (256, 256);
+
+/// How far [`ShadingTier::Fast`] may drift from [`ShadingTier::Full`], as
+/// the mean difference per channel, out of 255. Only the interpolation of
+/// the lighting differs, so anything above a rounding error is a bug.
+const MAX_SHADING_DELTA: f64 = 2.0;
+
+/// How far UI drawn through a [`UiPath`] may differ from UI drawn straight
+/// into an `Rgba8Unorm` target, per channel out of 255. Compositing the
+/// layer decodes and encodes once more, which rounds by one at most, and
+/// float targets lose a little more to half precision.
+const MAX_UI_DELTA: u8 = 2;
+
+/// How far GPU generated mips may differ from [`mipmap::generate_cpu`], per
+/// channel out of 255. Both filter the same texels with the same weights,
+/// but rounding, and sRGB encoding on the GPU, can differ by one step.
+const MAX_MIP_DELTA: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Scene {
+    /// The default OBJ model through [`frame::Renderer`].
+    ObjModel,
+    /// The default model exported to glTF and loaded back.
+    GltfModel,
+    /// Many draws of one model, batched into an instanced draw without
+    /// allocating once the frame arena has grown.
+    Instancing,
+    /// The default cube from three spot lights, packed into one shadow
+    /// atlas in one pass. Each tile must match its light drawn alone, and
+    /// the rest of the atlas must stay clear.
+    ShadowPass,
+    /// The scene into the HDR target, metered from a readback and, where
+    /// the device can, with compute, then exposed for what was metered
+    /// onto an SDR target.
+    PostChain,
+    /// The default model drawn with [`ShadingTier::Full`] and
+    /// [`ShadingTier::Fast`], which must look nearly the same.
+    FastShading,
+    /// A model on a gizmo layer, in one viewport that draws the layer and
+    /// one that doesn't.
+    Layers,
+    /// Textures loaded under a [`gpu::memory::set_budget`] limit, which
+    /// must shrink until they fit, or fail with an [`gpu::AllocError`].
+    OutOfMemory,
+    /// The default model run through [`pipeline::run`] in a temp dir, then
+    /// loaded from the outputs alone.
+    AssetPipeline,
+    /// Distinct values for three frames in flight through a
+    /// [`gpu::UniformRing`], then a frame that overflows it, then frame
+    /// hooks allocating from the renderer's ring.
+    UniformRing,
+    /// The crate's shaders reflected, which must give exactly the layouts
+    /// written by hand.
+    Reflection,
+    /// A burst of resizes as from dragging a window's corner, which must
+    /// rebuild the depth target once, then a frame at the final size.
+    ResizeStorm,
+    /// A quad standing on a floor, drawn with and without a fade distance.
+    /// Where it meets the floor, the soft one must fade over several rows
+    /// and the hard one must cut off.
+    SoftParticles,
+    /// The default cube's [`model::ModelReport`], which must have its known
+    /// counts and sizes, and totals that are the sums of its parts.
+    ModelReport,
+    /// The same cube written in meters and in centimeters, which must load
+    /// to the same bounds when each is assumed to be in its unit.
+    Units,
+    /// A model pipeline variant built slowly in the background. Frames must
+    /// draw with the closest ready variant until it is done, with no jank
+    /// from waiting on it. State's triplanar variants must be picked up
+    /// within the fallback frame limit.
+    AsyncPipelines,
+    /// The glass sphere over a textured floor, once with an index of
+    /// refraction of 1 and once as glass. The first must barely differ
+    /// from the floor alone, and the second must bend it, but only where
+    /// the sphere covers.
+    Refraction,
+    /// A UI test pattern of gradients, 1px lines and scaled text over an
+    /// opaque panel, drawn through the [`UiPath`] of every kind of surface.
+    /// All must match drawing straight into an `Rgba8Unorm` target, and
+    /// translucent lines must blend in sRGB encoded values.
+    UiCompositing,
+    /// The same scan as ASCII and binary PLY, with normals and a face
+    /// element to skip. Both must parse, whole and in chunks, and upload to
+    /// the points written. Drawn as pixels and as quads, each point must
+    /// show its color, and picking near one must find it.
+    PointCloud,
+    /// A brick sphere from above its pole, UV mapped and with triplanar
+    /// materials in world and object space. Circles around the pole must
+    /// cross bricks more densely the smaller they are on the UV mapped one
+    /// only, and turning the sphere, which moves its seam, must leave the
+    /// world space one alone.
+    Triplanar,
+    /// Mip chains of odd sized textures generated on the GPU, in linear and
+    /// sRGB formats. Every level must match [`mipmap::generate_cpu`], which
+    /// weights the partly covered texels at the edges by their coverage.
+    /// Also thumbnails a texture without mips through halved levels.
+    Mipmaps,
+    /// Buffer and texture copies through a [`gpu::ReadbackQueue`], which
+    /// must arrive in the order they were recorded and only once mapping
+    /// has started, and a full queue dropping its oldest droppable copy.
+    Readback,
+    /// Two disjoint regions written into a [`texture::Texture::writable`]
+    /// texture, one with rows that need padding. Only their texels may
+    /// change, and a region past the edge must be refused.
+    RegionUpdates,
+    /// [`compose::FrameHooks`] callbacks at every slot around an empty
+    /// frame. They must run in frame order. A scene pass that loads must
+    /// keep what the `BeforeScene` pass drew, with the `AfterUi` pass's
+    /// draw on top of it. After that pass is removed, a scene pass that
+    /// clears must leave nothing but the clear color.
+    PassHooks,
+    /// A unit sphere stretched along one axis by its instance, which packs
+    /// it in the full [`variant::InstanceLayout`]. It must look like the
+    /// sphere stretched in the mesh with correctly turned normals, and not
+    /// like one whose normals were stretched with it, as the compact
+    /// layout would turn them.
+    InstanceLayouts,
+}
+
+impl Scene {
+    pub const ALL: [Scene; 25] = [
+        Scene::ObjModel,
+        Scene::GltfModel,
+        Scene::Instancing,
+        Scene::ShadowPass,
+        Scene::PostChain,
+        Scene::FastShading,
+        Scene::Layers,
+        Scene::OutOfMemory,
+        Scene::AssetPipeline,
+        Scene::UniformRing,
+        Scene::Reflection,
+        Scene::ResizeStorm,
+        Scene::SoftParticles,
+        Scene::ModelReport,
+        Scene::Units,
+        Scene::AsyncPipelines,
+        Scene::Refraction,
+        Scene::UiCompositing,
+        Scene::PointCloud,
+        Scene::Triplanar,
+        Scene::Mipmaps,
+        Scene::Readback,
+        Scene::RegionUpdates,
+        Scene::PassHooks,
+        Scene::InstanceLayouts,
+    ];
+
+    /// Why the scene can't run in `context`, if it can't. Checks what the
+    /// device was created with, which on downlevel adapters is less than
+    /// the adapter reports.
+    fn skip_reason(self, context: &HeadlessContext) -> Option<String> {
+        match self {
+            // Reading glTF back in is still to be written.
+            Scene::GltfModel => Some("resources::load_gltf isn't implemented".to_string()),
+            // The tiles are compared by reading the atlas back.
+            Scene::ShadowPass
+                if !context
+                    .adapter
+                    .get_downlevel_capabilities()
+                    .flags
+                    .contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES) =>
+            {
+                Some("no depth texture copies".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Skipped(String),
+    /// Validation errors, errors returned by the scene and panics, in the
+    /// order they happened.
+    Failed(Vec<String>),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Skipped(reason) => write!(f, "skipped ({})", reason),
+            Outcome::Failed(messages) => write!(f, "failed: {}", messages.join("; ")),
+        }
+    }
+}
+
+pub struct BackendReport {
+    pub backend: wgpu::Backends,
+    /// `None` when the host has no adapter for the backend.
+    pub adapter: Option<wgpu::AdapterInfo>,
+    /// What the adapter can do, which the scenes and device limits follow.
+    pub downlevel: Option<wgpu::DownlevelCapabilities>,
+    pub scenes: Vec<(Scene, Outcome)>,
+    /// GPU memory after the scenes ran, with the high water marks of this
+    /// backend's scenes.
+    pub memory: gpu::MemoryStats,
+}
+
+impl BackendReport {
+    pub fn outcome(&self, scene: Scene) -> Option<&Outcome> {
+        self.scenes
+            .iter()
+            .find(|(s, _)| *s == scene)
+            .map(|(_, outcome)| outcome)
+    }
+}
+
+/// A scene whose outcome differs between two [`Report`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportChange {
+    pub backend: wgpu::Backends,
+    pub scene: Scene,
+    /// `None` if the backend wasn't in that report.
+    pub before: Option<Outcome>,
+    pub after: Option<Outcome>,
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub backends: Vec<BackendReport>,
+}
+
+impl Report {
+    pub fn backend(&self, backend: wgpu::Backends) -> Option<&BackendReport> {
+        self.backends.iter().find(|b| b.backend == backend)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.backends
+            .iter()
+            .flat_map(|b| &b.scenes)
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    /// The scenes whose outcome changed since `earlier`, e.g. a report
+    /// saved from another machine or before a change.
+    pub fn diff(&self, earlier: &Report) -> Vec<ReportChange> {
+        let mut changes = Vec::new();
+        for backend in BACKENDS {
+            let (before, after) = (earlier.backend(backend), self.backend(backend));
+            if before.is_none() && after.is_none() {
+                continue;
+            }
+            for scene in Scene::ALL {
+                let before = before.and_then(|b| b.outcome(scene)).cloned();
+                let after = after.and_then(|b| b.outcome(scene)).cloned();
+                if before != after {
+                    changes.push(ReportChange {
+                        backend,
+                        scene,
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+        changes
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for backend in &self.backends {
+            match &backend.adapter {
+                Some(info) => writeln!(
+                    f,
+                    "{:?}: {} ({:?})",
+                    backend.backend, info.name, info.device_type
+                )?,
+                None => writeln!(f, "{:?}: no adapter", backend.backend)?,
+            }
+            for (scene, outcome) in &backend.scenes {
+                writeln!(f, "  {:?}: {}", scene, outcome)?;
+            }
+            if backend.adapter.is_some() {
+                writeln!(f, "  GPU memory: {}", backend.memory)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A device without a surface, with errors collected instead of panicking.
+pub struct HeadlessContext {
+    pub adapter: wgpu::Adapter,
+    /// Shared with pipelines built on other threads.
+    pub device: Arc<wgpu::Device>,
+    pub queue: wgpu::Queue,
+    uncaptured: Arc<Mutex<Vec<String>>>,
+}
+
+impl HeadlessContext {
+    /// `None` if the host has no adapter for `backend`, or it has one but
+    /// won't create a device with the limits it reports.
+    pub async fn new(backend: wgpu::Backends) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: backend,
+            dx12_shader_compiler: Default::default(),
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Validation Device"),
+                    features: wgpu::Features::empty(),
+                    limits: gpu::device_limits(&adapter),
+                },
+                None,
+            )
+            .await
+            .map_err(|e| log::warn!("{:?} has an adapter but no device: {}", backend, e))
+            .ok()?;
+
+        let uncaptured = Arc::new(Mutex::new(Vec::new()));
+        let errors = uncaptured.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            errors.lock().unwrap().push(error.to_string());
+        }));
+        Some(Self {
+            adapter,
+            device: Arc::new(device),
+            queue,
+            uncaptured,
+        })
+    }
+
+    /// Runs `scene`, catching validation errors and panics.
+    pub fn run(&self, scene: Scene) -> Outcome {
+        if let Some(reason) = scene.skip_reason(self) {
+            return Outcome::Skipped(reason);
+        }
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pollster::block_on(run_scene(self, scene))
+        }));
+        self.device.poll(wgpu::Maintain::Wait);
+        let scoped = pollster::block_on(self.device.pop_error_scope());
+
+        let mut messages = Vec::new();
+        messages.extend(scoped.map(|error| error.to_string()));
+        messages.append(&mut self.uncaptured.lock().unwrap());
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => messages.push(format!("{:?}", e)),
+            Err(payload) => messages.push(panic_message(payload.as_ref())),
+        }
+        if messages.is_empty() {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(messages)
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    format!("panicked: {}", message)
+}
+
+/// Runs every scene on every backend of [`BACKENDS`].
+pub async fn run() -> Report {
+    let mut report = Report::default();
+    for backend in BACKENDS {
+        gpu::MemoryStats::reset_peaks();
+        let context = HeadlessContext::new(backend).await;
+        let scenes = Scene::ALL
+            .into_iter()
+            .map(|scene| {
+                let outcome = match &context {
+                    Some(context) => context.run(scene),
+                    None => Outcome::Skipped("no adapter".to_string()),
+                };
+                (scene, outcome)
+            })
+            .collect();
+        report.backends.push(BackendReport {
+            backend,
+            adapter: context.as_ref().map(|c| c.adapter.get_info()),
+            downlevel: context
+                .as_ref()
+                .map(|c| c.adapter.get_downlevel_capabilities()),
+            scenes,
+            memory: gpu::MemoryStats::snapshot(),
+        });
+    }
+    report
+}
+
+/// What every scene draws with, built the way [`State`] builds its own.
+struct Fixture {
+    texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    pipelines: crate::ModelPipelines,
+    renderer: frame::Renderer,
+    uniform_ring: gpu::UniformRing,
+    color: wgpu::Texture,
+    depth: texture::Texture,
+}
+
+impl Fixture {
+    fn new(device: &Arc<wgpu::Device>, format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        let texture_bind_group_layout = Arc::new(crate::create_texture_bind_group_layout(device));
+        let camera_bind_group_layout = Arc::new(crate::create_camera_bind_group_layout(device));
+        let mut pipelines = crate::ModelPipelines::new(
+            crate::ModelPipelineBuilder {
+                device: device.clone(),
+                color_format: format,
+                texture_layout: texture_bind_group_layout.clone(),
+                triplanar_layout: Arc::new(crate::create_triplanar_bind_group_layout(device)),
+                camera_layout: camera_bind_group_layout.clone(),
+            },
+            ShadingTier::Full,
+        );
+        pipelines.build(MaterialFeatures::TRIPLANAR);
+        let renderer = frame::Renderer::new(device, &camera_bind_group_layout, 2)?;
+        let color = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Validation Target"),
+            size: wgpu::Extent3d {
+                width: TARGET_SIZE.0,
+                height: TARGET_SIZE.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: TARGET_SIZE.0,
+            height: TARGET_SIZE.1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        let depth = texture::Texture::create_depth_texture(device, &config, "validation_depth")?;
+        Ok(Self {
+            texture_bind_group_layout,
+            pipelines,
+            renderer,
+            uniform_ring: gpu::UniformRing::new(device, 1024, 3)?,
+            color,
+            depth,
+        })
+    }
+
+    fn set_shading(&mut self, shading: ShadingTier) {
+        self.pipelines.set_shading(shading);
+        self.pipelines.build(MaterialFeatures::TRIPLANAR);
+    }
+
+    async fn load_obj(&self, context: &HeadlessContext) -> anyhow::Result<Model> {
+        resources::load_model(
+            State::DEFAULT_MODEL,
+            &context.device,
+            &context.queue,
+            &self.texture_bind_group_layout,
+        )
+        .await
+    }
+
+    /// Draws `frame` into the target and submits it.
+    fn draw(
+        &mut self,
+        context: &HeadlessContext,
+        frame: &RenderFrame,
+        models: &Assets<Model>,
+    ) -> anyhow::Result<()> {
+        let view = self
+            .color
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.draw_into(context, frame, models, &view)
+    }
+
+    /// Draws into `color` instead of the fixture's own color target, which
+    /// it must match in size and format.
+    fn draw_into(
+        &mut self,
+        context: &HeadlessContext,
+        frame: &RenderFrame,
+        models: &Assets<Model>,
+        color: &wgpu::TextureView,
+    ) -> anyhow::Result<()> {
+        self.draw_with_hooks(context, frame, models, color, &mut Default::default())?;
+        Ok(())
+    }
+
+    /// Like [`Self::draw_into`], running `hooks` with a frame of the
+    /// fixture's uniform ring. Returns what the renderer recorded, counting
+    /// allocations the way [`State`] does.
+    fn draw_with_hooks(
+        &mut self,
+        context: &HeadlessContext,
+        frame: &RenderFrame,
+        models: &Assets<Model>,
+        color: &wgpu::TextureView,
+        hooks: &mut compose::FrameHooks,
+    ) -> anyhow::Result<stats::FrameStats> {
+        let mut uniforms = self
+            .uniform_ring
+            .begin_frame(&context.device, &context.queue);
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Validation Encoder"),
+            });
+        let instance_layout = frame.instance_layout();
+        self.pipelines.use_layout(instance_layout);
+        self.pipelines.build(MaterialFeatures::TRIPLANAR);
+        let allocations = stats::CountingAllocator::allocations();
+        let mut frame_stats = self.renderer.render(
+            &context.device,
+            &context.queue,
+            &mut encoder,
+            &frame::RenderTarget {
+                color,
+                depth: &self.depth.view,
+                size: TARGET_SIZE,
+            },
+            &frame::FrameResources {
+                models,
+                materials: &Assets::new(),
+                pipeline: self
+                    .pipelines
+                    .plain(model::VertexPrecision::Full, instance_layout),
+                compressed_pipeline: self
+                    .pipelines
+                    .plain(model::VertexPrecision::Compressed, instance_layout),
+                triplanar_pipelines: self.pipelines.triplanar(instance_layout),
+            },
+            frame,
+            hooks,
+            &mut uniforms,
+        )?;
+        frame_stats.allocations = stats::CountingAllocator::allocations() - allocations;
+        context.queue.submit(Some(encoder.finish()));
+        Ok(frame_stats)
+    }
+}
+
+/// The view projection of a camera looking at the origin, and its eye.
+fn origin_camera(aspect: f32) -> (cgmath::Matrix4<f32>, cgmath::Point3<f32>) {
+    let camera = Camera {
+        eye: (0.0, 5.0, -10.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let mut uniform = CameraUniform::new();
+    uniform.update_view_proj(&camera);
+    (uniform.view_proj.into(), camera.eye)
+}
+
+/// A frame looking at the origin with `draws` instances of `model`.
+fn frame_with(model: ModelHandle, draws: Vec<Instance>) -> RenderFrame {
+    let (view_proj, eye) = origin_camera(TARGET_SIZE.0 as f32 / TARGET_SIZE.1 as f32);
+    let mut frame = RenderFrame::new();
+    frame.push_camera(
+        view_proj,
+        eye,
+        Viewport::new(0.0, 0.0, TARGET_SIZE.0 as f32, TARGET_SIZE.1 as f32),
+    );
+    for draw in draws {
+        frame.push_model(
+            model,
+            draw,
+            MaterialOverrides::default(),
+            DrawFlags::empty(),
+        );
+    }
+    frame
+}
+
+/// Draws a single instance of `model` at the origin.
+fn draw_one(context: &HeadlessContext, fixture: &mut Fixture, model: Model) -> anyhow::Result<()> {
+    let mut models = Assets::new();
+    let frame = frame_with(models.insert(model), vec![at(0.0, 0.0)]);
+    fixture.draw(context, &frame, &models)
+}
+
+fn at(x: f32, z: f32) -> Instance {
+    Instance {
+        position: cgmath::Vector3::new(x, 0.0, z),
+        rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+    }
+}
+
+/// Culling and batching a frame mustn't allocate once the arena has grown
+/// to fit it, and the arena must stop growing after the first frame.
+/// Allocations are only counted when [`stats::CountingAllocator`] is the
+/// global allocator, as it is in the `validate` binary.
+fn check_frame_arena(frame: &RenderFrame, models: &Assets<Model>) -> anyhow::Result<()> {
+    let mut arena = frame::FrameArena::new();
+    let mut capacities = [0; 3];
+    for (i, capacity) in capacities.iter_mut().enumerate() {
+        arena.reset();
+        let allocations = stats::CountingAllocator::allocations();
+        let batches = frame.batches(&frame.cameras[0], models, &arena);
+        let allocations = stats::CountingAllocator::allocations() - allocations;
+        anyhow::ensure!(!batches.is_empty(), "Nothing was visible");
+        anyhow::ensure!(
+            i == 0 || allocations == 0,
+            "Batching frame {} made {} allocations",
+            i,
+            allocations
+        );
+        *capacity = arena.watermark().capacity;
+    }
+    anyhow::ensure!(
+        capacities[1] == capacities[2],
+        "The frame arena kept growing: {:?} bytes",
+        capacities
+    );
+    Ok(())
+}
+
+/// Meshes in [`perf_guard`]'s scene, spread over [`PERF_GUARD_MODELS`]
+/// models.
+pub const PERF_GUARD_MESHES: usize = 1000;
+pub const PERF_GUARD_MODELS: usize = 10;
+/// Materials each of [`perf_guard`]'s models shares between its meshes.
+pub const PERF_GUARD_MATERIALS: usize = 20;
+/// Draws of [`perf_guard`]'s models, spread evenly over them.
+pub const PERF_GUARD_DRAWS: usize = 10_000;
+
+/// What [`perf_guard`] counted while recording its scene.
+#[derive(Debug, Clone, Copy)]
+pub struct PerfGuardReport {
+    /// The whole scene, recorded after a first frame sized the instance
+    /// buffer and the frame arena.
+    pub frame: stats::FrameStats,
+    /// Only the draws of the first model, a tenth of the draw calls. wgpu
+    /// allocates while recording too, but not per draw, so this should
+    /// allocate about as much as [`Self::frame`].
+    pub small_frame: stats::FrameStats,
+    /// Allocations culling and batching the whole scene made.
+    pub batching_allocations: usize,
+    /// Seconds recording the whole scene took.
+    pub record_seconds: f64,
+}
+
+/// A model of `meshes` one triangle meshes, cycling through `materials`
+/// untextured materials.
+fn perf_guard_obj(meshes: usize, materials: usize) -> (String, String) {
+    let mut obj = String::from("mtllib perf-guard.mtl\n");
+    for i in 0..meshes {
+        let x = (i % 10) as f32 * 0.1;
+        let z = (i / 10) as f32 * 0.1;
+        obj.push_str(&format!("o mesh{}\nusemtl material{}\n", i, i % materials));
+        obj.push_str(&format!(
+            "v {} 0 {}\nv {} 0 {}\nv {} 0.1 {}\nf -3 -2 -1\n",
+            x,
+            z,
+            x + 0.1,
+            z,
+            x,
+            z
+        ));
+    }
+    let mtl = (0..materials)
+        .map(|i| format!("newmtl material{}\nKd 1 1 1\n", i))
+        .collect();
+    (obj, mtl)
+}
+
+/// Records a synthetic scene of [`PERF_GUARD_MESHES`] meshes drawn
+/// [`PERF_GUARD_DRAWS`] times, for the `perf_guard` test to hold to its
+/// ceilings. Allocations are only counted when
+/// [`stats::CountingAllocator`] is the global allocator.
+pub async fn perf_guard(context: &HeadlessContext) -> anyhow::Result<PerfGuardReport> {
+    let mut fixture = Fixture::new(&context.device, wgpu::TextureFormat::Rgba8UnormSrgb)?;
+    let dir = std::env::temp_dir().join(format!(
+        "validation-perf-guard-{:?}",
+        context.adapter.get_info().backend
+    ));
+    std::fs::create_dir_all(&dir)?;
+    let (obj, mtl) = perf_guard_obj(PERF_GUARD_MESHES / PERF_GUARD_MODELS, PERF_GUARD_MATERIALS);
+    let path = dir.join("perf-guard.obj");
+    std::fs::write(&path, obj)?;
+    std::fs::write(dir.join("perf-guard.mtl"), mtl)?;
+    let mut loaded = Vec::new();
+    for _ in 0..PERF_GUARD_MODELS {
+        loaded.push(
+            resources::load_model(
+                &path.to_string_lossy(),
+                &context.device,
+                &context.queue,
+                &fixture.texture_bind_group_layout,
+            )
+            .await,
+        );
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut models = Assets::new();
+    let mut handles = Vec::new();
+    for model in loaded {
+        let model = model?;
+        anyhow::ensure!(
+            model.meshes.len() == PERF_GUARD_MESHES / PERF_GUARD_MODELS
+                && model.materials.len() == PERF_GUARD_MATERIALS,
+            "The perf guard model has {} meshes and {} materials",
+            model.meshes.len(),
+            model.materials.len()
+        );
+        handles.push(models.insert(model));
+    }
+
+    // Culling isn't what's measured, so every draw is kept.
+    let frame_of = |handles: &[ModelHandle], draws: usize| {
+        let mut frame = frame_with(handles[0], Vec::new());
+        let side = (draws as f32).sqrt().ceil() as usize;
+        for i in 0..draws {
+            frame.push_model(
+                handles[i % handles.len()],
+                at((i % side) as f32 * 0.2, (i / side) as f32 * 0.2),
+                MaterialOverrides::default(),
+                DrawFlags::NO_CULL,
+            );
+        }
+        frame
+    };
+    let frame = frame_of(&handles, PERF_GUARD_DRAWS);
+    let small = frame_of(&handles[..1], PERF_GUARD_DRAWS / PERF_GUARD_MODELS);
+
+    let view = fixture
+        .color
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    let mut hooks = compose::FrameHooks::default();
+    fixture.draw_with_hooks(context, &frame, &models, &view, &mut hooks)?;
+    let small_frame = fixture.draw_with_hooks(context, &small, &models, &view, &mut hooks)?;
+    let started = stats::now();
+    let full_frame = fixture.draw_with_hooks(context, &frame, &models, &view, &mut hooks)?;
+    let record_seconds = stats::now() - started;
+
+    let mut arena = frame::FrameArena::new();
+    frame.batches(&frame.cameras[0], &models, &arena);
+    arena.reset();
+    let allocations = stats::CountingAllocator::allocations();
+    frame.batches(&frame.cameras[0], &models, &arena);
+    let batching_allocations = stats::CountingAllocator::allocations() - allocations;
+
+    Ok(PerfGuardReport {
+        frame: full_frame,
+        small_frame,
+        batching_allocations,
+        record_seconds,
+    })
+}
+
+/// Loads a 256x256 texture with room for 128x128, then a 1x1 one with no
+/// room at all.
+fn check_out_of_memory(context: &HeadlessContext) -> anyhow::Result<()> {
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(256, 256));
+    let load = |image: &image::DynamicImage| {
+        texture::Texture::from_image(
+            &context.device,
+            &context.queue,
+            image,
+            Some("Budget Test"),
+            &texture::TextureSettings::default(),
+        )
+    };
+
+    let live = gpu::MemoryStats::snapshot().total_live();
+    gpu::memory::set_budget(Some(live + 128 * 128 * 4));
+    let texture = load(&image)?;
+    let size = texture.texture.size();
+    anyhow::ensure!(
+        (size.width, size.height) == (128, 128),
+        "Expected the texture to shrink to 128x128, got {}x{}",
+        size.width,
+        size.height
+    );
+    anyhow::ensure!(
+        texture.downscaled_from == Some((256, 256)),
+        "The texture doesn't say it was downscaled from 256x256: {:?}",
+        texture.downscaled_from
+    );
+    drop(texture);
+
+    let live = gpu::MemoryStats::snapshot().total_live();
+    gpu::memory::set_budget(Some(live));
+    let error = match load(&image::DynamicImage::ImageRgba8(image::RgbaImage::new(
+        1, 1,
+    ))) {
+        Ok(_) => anyhow::bail!("A texture was created with no memory left"),
+        Err(e) => e,
+    };
+    anyhow::ensure!(
+        error
+            .downcast_ref::<gpu::AllocError>()
+            .is_some_and(gpu::AllocError::is_out_of_memory),
+        "Expected an out of memory error, got {:?}",
+        error
+    );
+    Ok(())
+}
+
+/// Copies the default model's OBJ, material library and diffuse map into
+/// a temp dir and runs the pipeline over it twice, the second time with
+/// nothing to do. Then deletes the OBJ and loads the model, which only
+/// works from the processed output.
+async fn check_asset_pipeline(
+    context: &HeadlessContext,
+    fixture: &Fixture,
+) -> anyhow::Result<Model> {
+    const MAX_DIMENSION: u32 = 64;
+    let root = std::env::temp_dir().join(format!(
+        "validation-pipeline-{:?}",
+        context.adapter.get_info().backend
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+    let model_dir = root.join("cube");
+    std::fs::create_dir_all(&model_dir)?;
+    for file in ["cube.obj", "cube.mtl", "cube-diffuse.jpg"] {
+        let bytes = resources::load_binary(&format!("cube/{}", file)).await?;
+        std::fs::write(model_dir.join(file), bytes)?;
+    }
+
+    let output = root.join(resources::PROCESSED_DIR);
+    let options = PipelineOptions {
+        force: false,
+        max_dimension: Some(MAX_DIMENSION),
+        bake_ao: None,
+    };
+    let report = pipeline::run(&root, &output, &options)?;
+    anyhow::ensure!(
+        report.failures() == 0 && report.count(&FileStatus::Converted) == 2,
+        "Expected the model and its texture to convert:\n{}",
+        report
+    );
+    let report = pipeline::run(&root, &output, &options)?;
+    anyhow::ensure!(
+        report.count(&FileStatus::UpToDate) == 2,
+        "Expected nothing to convert the second time:\n{}",
+        report
+    );
+    let manifest = std::fs::read_to_string(output.join(pipeline::MANIFEST))?;
+    let entries = pipeline::read_manifest(&manifest)?;
+    anyhow::ensure!(entries.len() == 2, "Unexpected manifest:\n{}", manifest);
+    for entry in &entries {
+        let bytes = std::fs::read(output.join(&entry.path))?;
+        anyhow::ensure!(
+            pipeline::fnv1a(&bytes) == entry.hash && bytes.len() as u64 == entry.bytes,
+            "{} doesn't match its manifest entry",
+            entry.path
+        );
+    }
+
+    std::fs::remove_file(model_dir.join("cube.obj"))?;
+    resources::set_resource_dir(Some(root.clone()));
+    let model = resources::load_model_with_options(
+        State::DEFAULT_MODEL,
+        &context.device,
+        &context.queue,
+        &fixture.texture_bind_group_layout,
+        &resources::LoadOptions {
+            prefer_processed: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    resources::set_resource_dir(None);
+    let _ = std::fs::remove_dir_all(&root);
+    let model = model?;
+
+    let size = model.materials[0].diffuse_texture.texture.size();
+    anyhow::ensure!(
+        size.width <= MAX_DIMENSION && size.height <= MAX_DIMENSION,
+        "The processed texture is {}x{}, over {}",
+        size.width,
+        size.height,
+        MAX_DIMENSION
+    );
+    Ok(model)
+}
+
+/// Frames [`check_readback`] polls for before giving up on a copy.
+const MAX_READBACK_FRAMES: usize = 1000;
+
+/// Reads back three buffers and an odd sized texture, the texture through a
+/// [`gpu::ReadbackFuture`], then overfills a queue of droppable copies.
+fn check_readback(context: &HeadlessContext) -> anyhow::Result<()> {
+    let device = &context.device;
+    let buffers: Vec<_> = (0..3u32)
+        .map(|i| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Validation Readback Source"),
+                contents: bytemuck::cast_slice(&[i * 10, i * 10 + 1, i * 10 + 2, i * 10 + 3]),
+                usage: wgpu::BufferUsages::COPY_SRC,
+            })
+        })
+        .collect();
+    let image = image::RgbaImage::from_fn(5, 3, |x, y| {
+        image::Rgba([x as u8 * 40, y as u8 * 80, 7, 255])
+    });
+    let texture = device.create_texture_with_data(
+        &context.queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Validation Readback Texture"),
+            size: wgpu::Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        },
+        image.as_raw(),
+    );
+
+    let mut readbacks = gpu::ReadbackQueue::new(4);
+    let arrived = Arc::new(Mutex::new(Vec::new()));
+    let mut encoder = device.create_command_encoder(&Default::default());
+    for (i, buffer) in buffers.iter().enumerate() {
+        let arrived = arrived.clone();
+        readbacks.read_buffer(
+            device,
+            &mut encoder,
+            buffer,
+            4..16,
+            gpu::ReadbackPolicy::Guaranteed,
+            move |result| arrived.lock().unwrap().push((i, result)),
+        )?;
+    }
+    let screenshot = gpu::ReadbackFuture::default();
+    readbacks.read_texture(
+        device,
+        &mut encoder,
+        texture.as_image_copy(),
+        image.dimensions(),
+        gpu::ReadbackPolicy::Guaranteed,
+        screenshot.callback(),
+    )?;
+    context.queue.submit(Some(encoder.finish()));
+    device.poll(wgpu::Maintain::Wait);
+    anyhow::ensure!(
+        readbacks.poll(device) == 0,
+        "Copies arrived before they were mapped"
+    );
+    readbacks.after_submit();
+    let mut frames = 0;
+    while readbacks.in_flight() > 0 && frames < MAX_READBACK_FRAMES {
+        readbacks.poll(device);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        frames += 1;
+    }
+    log::info!("Readbacks arrived after {} frames", frames);
+
+    let arrived = std::mem::take(&mut *arrived.lock().unwrap());
+    let order: Vec<_> = arrived.iter().map(|(i, _)| *i).collect();
+    anyhow::ensure!(order == [0, 1, 2], "Buffer copies arrived as {:?}", order);
+    for (i, result) in arrived {
+        let values: Vec<u32> = bytemuck::pod_collect_to_vec(&result?.bytes);
+        let i = i as u32 * 10;
+        let expected = [i + 1, i + 2, i + 3];
+        anyhow::ensure!(
+            values == expected,
+            "Read {:?}, wanted {:?}",
+            values,
+            expected
+        );
+    }
+    let read = screenshot
+        .try_take()
+        .ok_or_else(|| anyhow::anyhow!("The texture copy didn't arrive"))??
+        .into_rgba_image();
+    anyhow::ensure!(
+        read.as_ref() == Some(&image),
+        "The texture read back differently"
+    );
+
+    // With two in flight, each copy after them drops the oldest droppable
+    // one as it is recorded, and guaranteed ones are kept.
+    let mut readbacks = gpu::ReadbackQueue::new(2);
+    let arrived = Arc::new(Mutex::new(Vec::new()));
+    let mut encoder = device.create_command_encoder(&Default::default());
+    for (i, policy) in [
+        gpu::ReadbackPolicy::DropOldest,
+        gpu::ReadbackPolicy::Guaranteed,
+        gpu::ReadbackPolicy::DropOldest,
+        gpu::ReadbackPolicy::Guaranteed,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let arrived = arrived.clone();
+        readbacks.read_buffer(
+            device,
+            &mut encoder,
+            &buffers[0],
+            0..16,
+            policy,
+            move |result| arrived.lock().unwrap().push((i, result.err())),
+        )?;
+    }
+    context.queue.submit(Some(encoder.finish()));
+    readbacks.after_submit();
+    readbacks.flush(device);
+    let arrived = std::mem::take(&mut *arrived.lock().unwrap());
+    let expected = [
+        (0, Some(gpu::ReadbackError::Dropped)),
+        (2, Some(gpu::ReadbackError::Dropped)),
+        (1, None),
+        (3, None),
+    ];
+    anyhow::ensure!(arrived == expected, "A full queue delivered {:?}", arrived);
+    Ok(())
+}
+
+/// Writes a distinct value per slot for three frames in flight, and checks
+/// the offsets are aligned and apart and the values read back where they
+/// were written. Then overflows a frame, which the next must grow for.
+fn check_uniform_ring(context: &HeadlessContext, fixture: &mut Fixture) -> anyhow::Result<()> {
+    const FRAMES: u32 = 3;
+    const PER_FRAME: u32 = 4;
+    let (device, queue) = (&context.device, &context.queue);
+    let alignment = device.limits().min_uniform_buffer_offset_alignment;
+    let mut ring = gpu::UniformRing::new(device, (PER_FRAME * alignment) as u64, 3)?;
+
+    let mut written = Vec::new();
+    for frame in 0..FRAMES {
+        let mut uniforms = ring.begin_frame(device, queue);
+        for i in 0..PER_FRAME {
+            let value = [frame, i, frame * PER_FRAME + i, 0xA11C];
+            written.push((uniforms.allocate(&value).get(), value));
+        }
+        queue.submit(std::iter::empty());
+    }
+
+    for (i, (offset, _)) in written.iter().enumerate() {
+        anyhow::ensure!(
+            offset % alignment == 0,
+            "Offset {} isn't aligned to {}",
+            offset,
+            alignment
+        );
+        if let Some((clash, _)) = written[..i]
+            .iter()
+            .find(|(o, _)| o.abs_diff(*offset) < alignment)
+        {
+            anyhow::bail!("Offsets {} and {} overlap", clash, offset);
+        }
+    }
+    let contents = export::read_buffer(device, queue, ring.buffer(), 0, ring.buffer().size())?;
+    for (offset, value) in &written {
+        let start = *offset as usize;
+        let read: [u32; 4] = bytemuck::pod_read_unaligned(&contents[start..start + 16]);
+        anyhow::ensure!(
+            read == *value,
+            "Read {:?} at {}, wrote {:?}",
+            read,
+            offset,
+            value
+        );
+    }
+
+    let (segment_size, generation) = (ring.segment_size(), ring.generation());
+    let wanted = {
+        let mut uniforms = ring.begin_frame(device, queue);
+        for i in 0..=PER_FRAME {
+            uniforms.allocate(&[i; 4]);
+        }
+        uniforms.used()
+    };
+    queue.submit(std::iter::empty());
+    anyhow::ensure!(
+        ring.segment_size() == segment_size,
+        "The ring grew mid frame"
+    );
+    let uniforms = ring.begin_frame(device, queue);
+    anyhow::ensure!(
+        uniforms.ring().segment_size() >= wanted && uniforms.ring().generation() == generation + 1,
+        "The ring didn't grow to {} bytes after overflowing",
+        wanted
+    );
+
+    // Hooks allocate from the renderer's frame of the ring, so each frame
+    // in flight writes its own segment.
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let mut hooks = compose::FrameHooks::default();
+    let recorded = written.clone();
+    hooks.add_pass(compose::PassSlot::AfterOpaque, move |context| {
+        let mut recorded = recorded.lock().unwrap();
+        let value = [recorded.len() as u32, 0xF00D, 0, 0];
+        recorded.push((context.uniforms.allocate(&value).get(), value));
+    });
+    let view = fixture
+        .color
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    for _ in 0..=FRAMES {
+        fixture.draw_with_hooks(
+            context,
+            &RenderFrame::new(),
+            &Assets::new(),
+            &view,
+            &mut hooks,
+        )?;
+    }
+    let ring = &fixture.uniform_ring;
+    let written = written.lock().unwrap();
+    let segments = written
+        .iter()
+        .map(|(offset, _)| *offset as u64 / ring.segment_size())
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        segments == [0, 1, 2, 0],
+        "Hook uniforms went to segments {:?}",
+        segments
+    );
+    // The first frame's segment was reused by the last.
+    let contents = export::read_buffer(device, queue, ring.buffer(), 0, ring.buffer().size())?;
+    for (offset, value) in &written[1..] {
+        let start = *offset as usize;
+        let read: [u32; 4] = bytemuck::pod_read_unaligned(&contents[start..start + 16]);
+        anyhow::ensure!(
+            read == *value,
+            "A hook read {:?} at {}, wrote {:?}",
+            read,
+            offset,
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Reflects `shader.wgsl` in both shading tiers, with and without
+/// triplanar mapping, `blit.wgsl`, `metering.wgsl`, `ui.wgsl`,
+/// `points.wgsl` and `tonemap.wgsl`, and compares the layouts with the
+/// hand-written ones. The derived layouts are created too, except
+/// `metering.wgsl`'s where the device can't meter, as its storage buffers
+/// can't be bound there. Then checks that binding arrays and override
+/// constants are refused.
+fn check_reflection(context: &HeadlessContext) -> anyhow::Result<()> {
+    let mut main_shader = ShaderReflection::default();
+    let mut triplanar_shader = ShaderReflection::default();
+    for shading in [ShadingTier::Full, ShadingTier::Fast] {
+        let defines = shading.define().into_iter().collect::<Vec<_>>();
+        let source = variant::preprocess(include_str!("shader.wgsl"), &defines)?;
+        main_shader.merge(&ShaderReflection::from_wgsl(&source)?)?;
+        let defines = defines
+            .into_iter()
+            .chain(MaterialFeatures::TRIPLANAR.defines())
+            .collect::<Vec<_>>();
+        let source = variant::preprocess(include_str!("shader.wgsl"), &defines)?;
+        triplanar_shader.merge(&ShaderReflection::from_wgsl(&source)?)?;
+    }
+    let mut points_shader = ShaderReflection::default();
+    for defines in [&[][..], &["QUADS"][..]] {
+        let source = variant::preprocess(include_str!("points.wgsl"), defines)?;
+        points_shader.merge(&ShaderReflection::from_wgsl(&source)?)?;
+    }
+    let shaders: [(&str, ShaderReflection, &[&[wgpu::BindGroupLayoutEntry]]); 7] = [
+        (
+            "shader.wgsl",
+            main_shader,
+            &[
+                &crate::TEXTURE_LAYOUT_ENTRIES,
+                &crate::CAMERA_LAYOUT_ENTRIES,
+                &model::MESH_LAYOUT_ENTRIES,
+            ],
+        ),
+        (
+            "shader.wgsl (HAS_TRIPLANAR)",
+            triplanar_shader,
+            &[
+                &crate::TRIPLANAR_LAYOUT_ENTRIES,
+                &crate::CAMERA_LAYOUT_ENTRIES,
+                &model::MESH_LAYOUT_ENTRIES,
+            ],
+        ),
+        (
+            "blit.wgsl",
+            ShaderReflection::from_wgsl(include_str!("blit.wgsl"))?,
+            &[&mipmap::MipmapGenerator::LAYOUT_ENTRIES],
+        ),
+        (
+            "metering.wgsl",
+            ShaderReflection::from_wgsl(include_str!("metering.wgsl"))?,
+            &[&exposure::ExposureMeter::LAYOUT_ENTRIES],
+        ),
+        (
+            "ui.wgsl",
+            ShaderReflection::from_wgsl(include_str!("ui.wgsl"))?,
+            &[&UiCompositor::LAYOUT_ENTRIES],
+        ),
+        (
+            "points.wgsl",
+            points_shader,
+            &[&PointPipeline::LAYOUT_ENTRIES],
+        ),
+        (
+            "tonemap.wgsl",
+            ShaderReflection::from_wgsl(include_str!("tonemap.wgsl"))?,
+            &[&tonemap::Tonemap::LAYOUT_ENTRIES],
+        ),
+    ];
+    let metering = exposure::ExposureMeter::is_supported(&context.adapter, &context.device);
+    for (name, reflection, groups) in &shaders {
+        anyhow::ensure!(
+            reflection.group_count() as usize == groups.len(),
+            "{} has {} groups, the layouts {}",
+            name,
+            reflection.group_count(),
+            groups.len()
+        );
+        for (group, manual) in groups.iter().enumerate() {
+            let derived = reflection.layout_entries(group as u32);
+            anyhow::ensure!(
+                derived == *manual,
+                "{} group {} reflects as {:?}, but is written as {:?}",
+                name,
+                group,
+                derived,
+                manual
+            );
+        }
+        let mismatches = reflection.check_groups(groups);
+        anyhow::ensure!(
+            mismatches.is_empty(),
+            "{}: {}",
+            name,
+            mismatches
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if *name != "metering.wgsl" || metering {
+            reflection.create_layouts(&context.device, name);
+        }
+    }
+
+    let wrong = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            ..crate::CAMERA_LAYOUT_ENTRIES[0]
+        },
+        mipmap::MipmapGenerator::LAYOUT_ENTRIES[1],
+    ];
+    let mismatches = shaders[2].1.check(0, &wrong);
+    anyhow::ensure!(
+        mismatches.len() == 2,
+        "Expected a wrong type and wrong visibility, got {:?}",
+        mismatches
+    );
+    let mismatches = shaders[4].1.check(0, &UiCompositor::LAYOUT_ENTRIES[..1]);
+    anyhow::ensure!(
+        matches!(mismatches[..], [LayoutMismatch::Missing { .. }]),
+        "Expected a missing uniform, got {:?}",
+        mismatches
+    );
+
+    let array = "@group(0) @binding(0) var textures: binding_array<texture_2d<f32>, 4>;";
+    anyhow::ensure!(
+        matches!(
+            ShaderReflection::from_wgsl(array),
+            Err(ReflectError::BindingArray { .. }
+                | ReflectError::Parse(_)
+                | ReflectError::Invalid(_))
+        ),
+        "A binding array was reflected"
+    );
+    let overridden = "override scale: f32 = 1.0;";
+    anyhow::ensure!(
+        ShaderReflection::from_wgsl(overridden)
+            == Err(ReflectError::OverrideConstant("scale".to_string())),
+        "An override constant wasn't refused"
+    );
+    let module = naga::front::wgsl::parse_str(include_str!("blit.wgsl"))?;
+    anyhow::ensure!(
+        reflect::layouts_from_shader(&context.device, &module)?.len() == 1,
+        "blit.wgsl should need one layout"
+    );
+    Ok(())
+}
+
+/// Drives a [`ResizeDebounce`] as [`State`] does, with `State::update`
+/// between events and `State::render` once the storm is over.
+fn check_resize_storm(context: &HeadlessContext) -> anyhow::Result<()> {
+    let mut config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        width: TARGET_SIZE.0,
+        height: TARGET_SIZE.1,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+        view_formats: Vec::new(),
+    };
+    let settle = ResizeDebounce::DEFAULT_SETTLE;
+    let mut debounce = ResizeDebounce::new(TARGET_SIZE, settle);
+    let mut depth =
+        texture::Texture::create_depth_texture(&context.device, &config, "Storm Depth")?;
+    let mut rebuild = |debounce: &mut ResizeDebounce, config: &wgpu::SurfaceConfiguration| {
+        depth = texture::Texture::create_depth_texture(&context.device, config, "Storm Depth")?;
+        debounce.rebuilt((config.width, config.height));
+        anyhow::Ok(())
+    };
+
+    // Forty events 8 ms apart, far quicker than the size settles.
+    let mut now = 10.0;
+    for i in 0..40 {
+        let size = (TARGET_SIZE.0 + 7 * i, TARGET_SIZE.1 + 3 * i);
+        anyhow::ensure!(debounce.resize(size, now));
+        (config.width, config.height) = size;
+        if debounce.due(now, false).is_some() {
+            rebuild(&mut debounce, &config)?;
+        }
+        now += 0.008;
+    }
+    anyhow::ensure!(
+        debounce.rebuilds() == 0,
+        "rebuilt {} times mid-storm",
+        debounce.rebuilds()
+    );
+
+    now += settle;
+    let due = debounce.due(now, false);
+    anyhow::ensure!(
+        due == Some((config.width, config.height)),
+        "due at {:?} once settled",
+        due
+    );
+    rebuild(&mut debounce, &config)?;
+    anyhow::ensure!(debounce.due(now, true).is_none());
+    anyhow::ensure!(
+        debounce.rebuilds() == 1,
+        "rebuilt {} times",
+        debounce.rebuilds()
+    );
+
+    // Minimizing pauses without a rebuild, and restoring to the same size
+    // needs none.
+    anyhow::ensure!(!debounce.resize((0, 0), now));
+    anyhow::ensure!(debounce.is_paused() && debounce.due(now + settle, true).is_none());
+    debounce.resize((config.width, config.height), now);
+    anyhow::ensure!(!debounce.is_paused() && debounce.due(now + settle, true).is_none());
+
+    // The frame at the final size, which the validation error scope checks.
+    let size = (config.width, config.height);
+    anyhow::ensure!(debounce.matches(size), "targets don't match {:?}", size);
+    let color = context.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Storm Color"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let view = color.create_view(&wgpu::TextureViewDescriptor::default());
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Storm Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: &depth.view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    });
+    context.queue.submit(std::iter::once(encoder.finish()));
+    Ok(())
+}
+
+/// Draws a floor with its top at y = 0, then a quad standing on it with each
+/// fade distance, and counts the rows in the middle column where the quad
+/// is only partly blended in.
+async fn check_soft_particles(
+    context: &HeadlessContext,
+    fixture: &mut Fixture,
+) -> anyhow::Result<()> {
+    let model = fixture.load_obj(context).await?;
+    let mut models = Assets::new();
+    let floor = Instance {
+        position: cgmath::Vector3::new(0.0, -20.0, 0.0),
+        rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: cgmath::Vector3::new(20.0, 20.0, 20.0),
+    };
+    let frame = frame_with(models.insert(model), vec![floor]);
+    let camera = Camera {
+        eye: (0.0, 5.0, -10.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: TARGET_SIZE.0 as f32 / TARGET_SIZE.1 as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    }
+    .particle_camera();
+    let mut particles = ParticleBatch::new(&context.device, fixture.color.format(), 1)?;
+    particles.set_depth(&context.device, &fixture.depth.view);
+
+    let mut renders = Vec::new();
+    for fade_distance in [None, Some(0.0), Some(1.0)] {
+        fixture.draw(context, &frame, &models)?;
+        if let Some(fade_distance) = fade_distance {
+            particles.push(Particle {
+                fade_distance,
+                axes: Some([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]),
+                ..Particle::new([0.0, 0.5, 0.0], [4.0, 3.0], [1.0, 0.2, 0.2, 0.8])
+            });
+            particles.prepare(&context.device, &context.queue, &camera)?;
+            let view = fixture
+                .color
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            let mut encoder =
+                context
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Validation Particle Encoder"),
+                    });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Validation Particle Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                particles.draw(&mut render_pass);
+            }
+            context.queue.submit(Some(encoder.finish()));
+        }
+        let render = export::read_texture(&context.device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))?;
+        renders.push(render);
+    }
+
+    // How much the particle changes each row down the middle. The floor's
+    // texture shows through, so rows differ even where the particle doesn't
+    // fade, and the soft particle is measured against the hard one.
+    let x = TARGET_SIZE.0 / 2;
+    let row_deltas = |render: &image::RgbaImage| {
+        (0..TARGET_SIZE.1)
+            .map(|y| {
+                let (a, b) = (renders[0].get_pixel(x, y), render.get_pixel(x, y));
+                a.0.iter()
+                    .zip(b.0)
+                    .map(|(a, b)| a.abs_diff(b) as u32)
+                    .sum::<u32>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let (hard, soft) = (row_deltas(&renders[1]), row_deltas(&renders[2]));
+    let partial = hard
+        .iter()
+        .zip(&soft)
+        .filter(|(&h, &s)| s * 10 > h && s * 10 < h * 9)
+        .count();
+    log::info!("Soft particles fade over {} rows", partial);
+    let drawn = hard.iter().rposition(|&d| d > 0);
+    let Some(last) = drawn.filter(|_| soft.iter().any(|&d| d > 0)) else {
+        anyhow::bail!("The particle wasn't drawn");
+    };
+    // Where it cuts off, the hard particle is as strong as just above.
+    let above = hard[last.saturating_sub(4)..last].iter().sum::<u32>() / 4;
+    anyhow::ensure!(
+        hard[last] * 2 >= above,
+        "A particle without a fade distance fades out towards the floor ({} after {})",
+        hard[last],
+        above
+    );
+    anyhow::ensure!(
+        partial >= 3,
+        "A particle with a fade distance fades over only {} rows",
+        partial
+    );
+    Ok(())
+}
+
+/// Checks the report of [`State::DEFAULT_MODEL`]: one mesh of 277 vertices
+/// and 428 triangles within a 2 unit cube, and one material with a 256 by
+/// 256 diffuse map and its mips.
+fn check_model_report(report: &model::ModelReport) -> anyhow::Result<()> {
+    let [mesh] = report.meshes.as_slice() else {
+        anyhow::bail!("Expected one mesh, got {}", report.meshes.len());
+    };
+    anyhow::ensure!(
+        (mesh.vertices, mesh.indices, mesh.material) == (277, 1284, 0),
+        "wrong mesh counts {:?}",
+        mesh
+    );
+    anyhow::ensure!(
+        (mesh.vertex_bytes, mesh.index_bytes) == (277 * 36, 1284 * 4),
+        "wrong buffer sizes {:?}",
+        mesh
+    );
+    anyhow::ensure!(
+        (mesh.aabb_volume - 8.0).abs() < 1e-3,
+        "wrong bounds volume {}",
+        mesh.aabb_volume
+    );
+    let [material] = report.materials.as_slice() else {
+        anyhow::bail!("Expected one material, got {}", report.materials.len());
+    };
+    let [texture] = material.textures.as_slice() else {
+        anyhow::bail!("Expected one texture, got {:?}", material.textures);
+    };
+    // 256x256 RGBA down to 1x1.
+    let mip_bytes = (0..9)
+        .map(|level| 4 * (256u64 >> level).pow(2))
+        .sum::<u64>();
+    anyhow::ensure!(
+        (texture.role, texture.width, texture.height, texture.bytes)
+            == (model::TextureRole::Diffuse, 256, 256, mip_bytes),
+        "wrong texture {:?}",
+        texture
+    );
+    anyhow::ensure!(material.bytes == mip_bytes, "wrong material {:?}", material);
+
+    let parts = model::ModelReport::new(report.meshes.clone(), report.materials.clone());
+    anyhow::ensure!(
+        report.totals == parts.totals,
+        "totals {:?} aren't the sums {:?}",
+        report.totals,
+        parts.totals
+    );
+    let expected = model::ReportTotals {
+        vertices: 277,
+        indices: 1284,
+        buffer_bytes: 277 * 36 + 1284 * 4,
+        texture_bytes: mip_bytes,
+    };
+    anyhow::ensure!(
+        report.totals == expected,
+        "wrong totals {:?}",
+        report.totals
+    );
+    anyhow::ensure!(report.nodes.is_none() && report.warnings.is_empty());
+    log::info!("{}", report);
+    Ok(())
+}
+
+/// A cube `size` across as an OBJ, with every corner in a triangle.
+fn cube_obj(size: f32) -> String {
+    let half = size / 2.0;
+    let mut obj = String::new();
+    for i in 0..8 {
+        let corner = |bit| if i & bit == 0 { -half } else { half };
+        obj.push_str(&format!("v {} {} {}\n", corner(1), corner(2), corner(4)));
+    }
+    obj.push_str("f 1 2 4\nf 5 6 8\nf 3 7 8\n");
+    obj
+}
+
+async fn check_units(context: &HeadlessContext, fixture: &Fixture) -> anyhow::Result<()> {
+    let mut bounds = Vec::new();
+    for (unit, size) in [(Unit::Meters, 2.0), (Unit::Centimeters, 200.0)] {
+        let path = std::env::temp_dir().join(format!(
+            "validation-{:?}-{:?}.obj",
+            context.adapter.get_info().backend,
+            unit
+        ));
+        std::fs::write(&path, cube_obj(size))?;
+        let options = resources::LoadOptions {
+            assumed_unit: unit,
+            ..Default::default()
+        };
+        let model = resources::load_model_with_options(
+            &path.to_string_lossy(),
+            &context.device,
+            &context.queue,
+            &fixture.texture_bind_group_layout,
+            &options,
+        )
+        .await;
+        let _ = std::fs::remove_file(&path);
+        let model = model?;
+        anyhow::ensure!(
+            model.unit_scale == unit.scale_to(units::TARGET_UNIT),
+            "{:?} recorded a scale of {:?}",
+            unit,
+            model.unit_scale
+        );
+        let aabb = model
+            .meshes
+            .iter()
+            .map(|m| m.aabb)
+            .reduce(|a, b| a.union(&b))
+            .ok_or_else(|| anyhow::anyhow!("The {:?} cube has no meshes", unit))?;
+        bounds.push(aabb);
+    }
+    let close = |a: cgmath::Point3<f32>, b: cgmath::Point3<f32>| {
+        let d = a - b;
+        d.x.abs() + d.y.abs() + d.z.abs() < 1e-4
+    };
+    anyhow::ensure!(
+        close(bounds[0].min, bounds[1].min) && close(bounds[0].max, bounds[1].max),
+        "meters load to {:?} but centimeters to {:?}",
+        bounds[0],
+        bounds[1]
+    );
+    anyhow::ensure!(units::is_plausible_size(&bounds[0]));
+
+    // Centimeters left as meters make a 200 m cube, which is still
+    // plausible; kilometers aren't.
+    let far_too_large = bounds[0].transformed(&cgmath::Matrix4::from_scale(1000.0));
+    anyhow::ensure!(!units::is_plausible_size(&far_too_large));
+
+    let hints = [
+        (r#"{"unit": "cm"}"#, Some(Unit::Centimeters)),
+        (r#"{"units": "Meters"}"#, Some(Unit::Meters)),
+        (r#"{"metersPerUnit": 0.01}"#, Some(Unit::Custom(0.01))),
+        (r#"{"author": "someone"}"#, None),
+        ("not json", None),
+    ];
+    for (extras, expected) in hints {
+        let unit = Unit::from_gltf_extras(extras);
+        anyhow::ensure!(unit == expected, "{} hints at {:?}", extras, unit);
+    }
+    Ok(())
+}
+
+/// Draws three spot lights' views of the default cube into one atlas, then
+/// each light alone, and compares the tiles texel for texel.
+async fn check_shadow_pass(context: &HeadlessContext, fixture: &Fixture) -> anyhow::Result<()> {
+    let model = fixture.load_obj(context).await?;
+    let instances = context
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Validation Shadow Instances"),
+            contents: bytemuck::cast_slice(&[at(0.0, 0.0).to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+    let mut atlas = ShadowAtlas::new(&context.device, 512)?;
+    let requests =
+        [(1, 1.0), (2, 0.25), (3, 0.25)].map(|(id, importance)| ShadowRequest { id, importance });
+    atlas.tiles_mut().update(&requests);
+    let projection = projection::perspective(
+        cgmath::Deg(60.0).into(),
+        1.0,
+        0.5,
+        30.0,
+        DepthRange::Standard,
+    );
+    let views = [
+        (1, (4.0, 5.0, 0.0)),
+        (2, (-4.0, 5.0, 0.0)),
+        (3, (0.0, 5.0, 4.0)),
+    ]
+    .map(|(id, eye): (u64, (f32, f32, f32))| {
+        let view = Matrix4::look_at_rh(
+            Point3::from(eye),
+            Point3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::unit_y(),
+        );
+        (id, projection * view)
+    });
+
+    let together = draw_shadows(context, &mut atlas, &views, &model, &instances)?;
+    let tiles = atlas
+        .tiles()
+        .tiles()
+        .map(|(_, tile)| tile)
+        .collect::<Vec<_>>();
+    let sizes = tiles.iter().map(|t| t.size).collect::<Vec<_>>();
+    anyhow::ensure!(sizes == [256, 128, 128], "tiles of {:?}", sizes);
+    let size = atlas.size();
+    let contains = |t: &AtlasTile, i: usize| {
+        let (x, y) = (i as u32 % size, i as u32 / size);
+        x >= t.x && x < t.x + t.size && y >= t.y && y < t.y + t.size
+    };
+    let drawn = |depth: &[f32]| {
+        (0..depth.len())
+            .filter(|&i| depth[i] != 1.0)
+            .collect::<Vec<_>>()
+    };
+    anyhow::ensure!(
+        drawn(&together)
+            .into_iter()
+            .all(|i| tiles.iter().any(|t| contains(t, i))),
+        "casters were drawn outside their tiles"
+    );
+
+    for view in &views {
+        let tile = atlas
+            .tiles()
+            .tile(view.0)
+            .ok_or_else(|| anyhow::anyhow!("light {} has no tile", view.0))?;
+        let alone = draw_shadows(
+            context,
+            &mut atlas,
+            std::slice::from_ref(view),
+            &model,
+            &instances,
+        )?;
+        let drawn_alone = drawn(&alone);
+        anyhow::ensure!(!drawn_alone.is_empty(), "light {} sees no caster", view.0);
+        anyhow::ensure!(
+            drawn_alone.iter().all(|&i| contains(&tile, i)),
+            "light {} was drawn outside its tile",
+            view.0
+        );
+        let mismatched = (0..alone.len())
+            .filter(|&i| contains(&tile, i) && alone[i] != together[i])
+            .count();
+        anyhow::ensure!(
+            mismatched == 0,
+            "light {} has {} texels that differ from drawing it alone",
+            view.0,
+            mismatched
+        );
+    }
+    Ok(())
+}
+
+/// Draws `model` into each of `views`' tiles in one pass, and reads back
+/// the atlas's depth.
+fn draw_shadows(
+    context: &HeadlessContext,
+    atlas: &mut ShadowAtlas,
+    views: &[(u64, Matrix4<f32>)],
+    model: &Model,
+    instances: &wgpu::Buffer,
+) -> anyhow::Result<Vec<f32>> {
+    atlas.prepare(&context.queue, views);
+    let size = atlas.size();
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Validation Shadow Encoder"),
+        });
+    {
+        let mut render_pass = atlas.begin_pass(&mut encoder);
+        for i in 0..atlas.view_count() {
+            atlas.set_view(&mut render_pass, i);
+            atlas.draw_model(&mut render_pass, model, instances, 0..1);
+        }
+    }
+    context.queue.submit(Some(encoder.finish()));
+    let source = wgpu::ImageCopyTexture {
+        texture: atlas.texture(),
+        mip_level: 0,
+        origin: wgpu::Origin3d::ZERO,
+        aspect: wgpu::TextureAspect::DepthOnly,
+    };
+    let depth = export::read_texture_region(&context.device, &context.queue, source, (size, size))?;
+    Ok(depth
+        .bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// [`State`]'s model pipelines with layouts of their own, with the plain
+/// variants for `shading` built.
+fn model_pipelines(context: &HeadlessContext, shading: ShadingTier) -> crate::ModelPipelines {
+    let device = &context.device;
+    crate::ModelPipelines::new(
+        crate::ModelPipelineBuilder {
+            device: device.clone(),
+            color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            texture_layout: Arc::new(crate::create_texture_bind_group_layout(device)),
+            triplanar_layout: Arc::new(crate::create_triplanar_bind_group_layout(device)),
+            camera_layout: Arc::new(crate::create_camera_bind_group_layout(device)),
+        },
+        shading,
+    )
+}
+
+/// Builds model pipeline variants as [`crate::ModelPipelineBuilder`]
+/// does, after sleeping for `delay` to stand in for a slow driver.
+fn variant_builder(
+    context: &HeadlessContext,
+    delay: std::time::Duration,
+) -> impl Fn(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline + Send + Clone + 'static {
+    let texture_layout = crate::create_texture_bind_group_layout(&context.device);
+    let camera_layout = crate::create_camera_bind_group_layout(&context.device);
+    let layout = Arc::new(
+        context
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Validation Variant Layout"),
+                bind_group_layouts: &[&texture_layout, &camera_layout],
+                push_constant_ranges: &[],
+            }),
+    );
+    let device = context.device.clone();
+    move |_key, shader| {
+        std::thread::sleep(delay);
+        crate::create_render_pipeline(
+            &device,
+            &layout,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &[ModelVertex::desc(), crate::InstanceRaw::desc()],
+            shader,
+            "vs_main",
+        )
+    }
+}
+
+fn check_async_pipelines(context: &HeadlessContext) -> anyhow::Result<()> {
+    const FRAME: std::time::Duration = std::time::Duration::from_millis(16);
+    let mut cache = PipelineCache::new(include_str!("shader.wgsl"));
+    // How long the build takes depends on the device, so the fallback is
+    // never cut short here; the limit is checked on its own below.
+    cache.set_max_fallback_frames(u32::MAX);
+    let plain = PipelineKey::new(
+        model::VertexPrecision::Full,
+        MaterialFeatures::empty(),
+        PassType::Forward,
+        1,
+    );
+    cache.get_or_create(
+        &context.device,
+        plain,
+        variant_builder(context, Default::default()),
+    )?;
+    let plain_pipeline = cache
+        .fallback(&plain)
+        .ok_or_else(|| anyhow::anyhow!("a ready variant isn't its own fallback"))?;
+
+    stats::take_events();
+    let mut timeline = stats::FrameTimeline::new(256);
+    for _ in 0..30 {
+        timeline.push(stats::FrameTiming {
+            frame: FRAME.as_secs_f64(),
+            ..Default::default()
+        });
+    }
+    let masked = PipelineKey {
+        features: MaterialFeatures::ALPHA_MASK,
+        ..plain
+    };
+    let slow = variant_builder(context, std::time::Duration::from_millis(100));
+    let mut fallback_frames = 0;
+    let ready = loop {
+        anyhow::ensure!(fallback_frames < 200, "the masked variant never finished");
+        let start = std::time::Instant::now();
+        cache.poll();
+        let state = cache.get_or_spawn(&context.device, masked, slow.clone())?;
+        let drawn = match state {
+            PipelineState::Ready(pipeline) => Some(pipeline),
+            PipelineState::Building => {
+                let fallback = cache
+                    .fallback(&masked)
+                    .ok_or_else(|| anyhow::anyhow!("no fallback while building"))?;
+                anyhow::ensure!(
+                    Arc::ptr_eq(&fallback, &plain_pipeline),
+                    "the wrong fallback"
+                );
+                fallback_frames += 1;
+                None
+            }
+        };
+        let work = start.elapsed();
+        if let Some(jank) = timeline.push(stats::FrameTiming {
+            frame: (FRAME + work).as_secs_f64(),
+            ..Default::default()
+        }) {
+            anyhow::bail!("waiting on the build was a jank: {}", jank);
+        }
+        if let Some(pipeline) = drawn {
+            break pipeline;
+        }
+        std::thread::sleep(FRAME.saturating_sub(work));
+    };
+    anyhow::ensure!(
+        fallback_frames > 0 && !Arc::ptr_eq(&ready, &plain_pipeline),
+        "the fallback was drawn for {} frames",
+        fallback_frames
+    );
+    anyhow::ensure!(cache
+        .fallback(&masked)
+        .is_some_and(|f| Arc::ptr_eq(&f, &ready)));
+    anyhow::ensure!(cache.building() == 0 && cache.len() == 2);
+
+    // Past the limit, a poll waits for the build rather than draw a
+    // fallback any longer.
+    cache.set_max_fallback_frames(1);
+    let fast = plain.with_shading(ShadingTier::Fast);
+    anyhow::ensure!(matches!(
+        cache.get_or_spawn(&context.device, fast, slow.clone())?,
+        PipelineState::Building
+    ));
+    anyhow::ensure!(
+        cache.fallback(&fast).is_none(),
+        "a fallback from another tier"
+    );
+    cache.poll();
+    anyhow::ensure!(
+        cache.poll() == [fast],
+        "the overdue build wasn't waited for"
+    );
+    let events = stats::take_events();
+    anyhow::ensure!(
+        events == [stats::FrameEvent::PipelineBuilt(format!("{:?}", fast))],
+        "waiting wasn't noted: {:?}",
+        events
+    );
+
+    // Prewarming hands every variant to the background at once.
+    let keys = [
+        PipelineKey {
+            features: MaterialFeatures::DIFFUSE_MAP,
+            ..plain
+        },
+        PipelineKey {
+            features: MaterialFeatures::DIFFUSE_MAP | MaterialFeatures::ALPHA_MASK,
+            ..plain
+        },
+    ];
+    cache.set_max_fallback_frames(u32::MAX);
+    cache.prewarm_in_background(
+        &context.device,
+        &keys,
+        variant_builder(context, Default::default()),
+    )?;
+    anyhow::ensure!(cache.building() == 2);
+    let start = std::time::Instant::now();
+    while cache.building() > 0 {
+        anyhow::ensure!(start.elapsed().as_secs() < 30, "prewarming never finished");
+        cache.poll();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    anyhow::ensure!(keys.iter().all(|key| cache.get(key).is_some()));
+
+    // State's triplanar variants build the same way, with the plain ones
+    // drawn until a poll picks them up, a few frames at most.
+    let mut pipelines = model_pipelines(context, ShadingTier::Full);
+    pipelines.spawn(MaterialFeatures::TRIPLANAR);
+    let mut frames = 0;
+    while pipelines.triplanar(InstanceLayout::Compact).is_none() {
+        anyhow::ensure!(
+            frames <= PipelineCache::DEFAULT_MAX_FALLBACK_FRAMES,
+            "the triplanar variants weren't ready after {} frames",
+            frames
+        );
+        pipelines.poll();
+        frames += 1;
+    }
+    anyhow::ensure!(
+        frames > 0,
+        "the triplanar variants were built in the draw path"
+    );
+    Ok(())
+}
+
+async fn check_refraction(context: &HeadlessContext, fixture: &mut Fixture) -> anyhow::Result<()> {
+    let floor_model = fixture.load_obj(context).await?;
+    let mut models = Assets::new();
+    let floor = Instance {
+        position: cgmath::Vector3::new(0.0, -20.0, 0.0),
+        rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: cgmath::Vector3::new(20.0, 20.0, 20.0),
+    };
+    let frame = frame_with(models.insert(floor_model), vec![floor]);
+
+    let mut sphere = resources::load_model(
+        "glass/sphere.obj",
+        &context.device,
+        &context.queue,
+        &fixture.texture_bind_group_layout,
+    )
+    .await?;
+    let loaded = sphere.materials.first().and_then(|m| m.refractive);
+    anyhow::ensure!(
+        loaded.map(|r| r.ior) == Some(1.5),
+        "the sphere's MTL loaded as {:?}",
+        loaded
+    );
+    // Its only material is glass, so the opaque pass leaves it out.
+    let mut opaque_stats = stats::FrameStats::default();
+    opaque_stats.add_model(&sphere, 1);
+    anyhow::ensure!(opaque_stats.draw_calls == 0, "the opaque pass draws glass");
+    let position = cgmath::Vector3::new(0.0, 1.0, 0.0);
+    let instances = context
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Validation Glass Instances"),
+            contents: bytemuck::cast_slice(&[Instance {
+                position,
+                ..at(0.0, 0.0)
+            }
+            .to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+    let camera = Camera {
+        eye: (0.0, 5.0, -10.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: TARGET_SIZE.0 as f32 / TARGET_SIZE.1 as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let mut pass = RefractionPass::new(&context.device, fixture.color.format(), TARGET_SIZE)?;
+
+    let read = |fixture: &Fixture| {
+        export::read_texture(&context.device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))
+    };
+    fixture.draw(context, &frame, &models)?;
+    let opaque = read(fixture)?;
+    let mut renders = Vec::new();
+    for ior in [1.0, Refractive::GLASS.ior] {
+        sphere.materials[0].refractive = Some(Refractive {
+            ior,
+            ..Refractive::GLASS
+        });
+        // The opaque scene goes where the pass samples it, and the pass
+        // draws it back into the fixture's target under the glass.
+        fixture.draw_into(context, &frame, &models, pass.scene_view())?;
+        let draws = [RefractionDraw {
+            model: &sphere,
+            instance_buffer: &instances,
+            instances: 0..1,
+        }];
+        pass.prepare(
+            &context.queue,
+            &camera.refraction_camera(),
+            &crate::light::Ambient::default(),
+            &draws,
+        );
+        anyhow::ensure!(!pass.is_empty(), "the sphere has no refractive meshes");
+        let view = fixture
+            .color
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Validation Refraction Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Validation Refraction Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &fixture.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.draw(&mut render_pass, &draws);
+        }
+        context.queue.submit(Some(encoder.finish()));
+        renders.push(read(fixture)?);
+    }
+
+    // Without bending, only the Fresnel rim shows.
+    let flat = mean_delta(&opaque, &renders[0]);
+    anyhow::ensure!(
+        flat < 2.0,
+        "glass with an IOR of 1 differs from the floor alone by {:.2}",
+        flat
+    );
+
+    // The sphere's bounds on screen.
+    let view_proj = camera.build_view_projection_matrix();
+    let corners = (0..8).map(|i| {
+        let corner = |bit| if i & bit == 0 { -1.0 } else { 1.0 };
+        let p = view_proj
+            * (position + cgmath::Vector3::new(corner(1), corner(2), corner(4))).extend(1.0);
+        (
+            (p.x / p.w * 0.5 + 0.5) * TARGET_SIZE.0 as f32,
+            (0.5 - p.y / p.w * 0.5) * TARGET_SIZE.1 as f32,
+        )
+    });
+    let (min, max) = corners.fold(
+        ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+        |(min, max), (x, y)| ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y))),
+    );
+    let bent = renders[0]
+        .enumerate_pixels()
+        .filter(|(x, y, pixel)| {
+            let glass = renders[1].get_pixel(*x, *y);
+            pixel
+                .0
+                .iter()
+                .zip(glass.0)
+                .map(|(a, b)| a.abs_diff(b) as u32)
+                .sum::<u32>()
+                > 12
+        })
+        .map(|(x, y, _)| (x as f32 + 0.5, y as f32 + 0.5))
+        .collect::<Vec<_>>();
+    anyhow::ensure!(bent.len() > 100, "glass only bent {} pixels", bent.len());
+    let outside = bent
+        .iter()
+        .filter(|(x, y)| *x < min.0 || *x > max.0 || *y < min.1 || *y > max.1)
+        .count();
+    anyhow::ensure!(
+        outside == 0,
+        "{} of {} changed pixels are outside the sphere",
+        outside,
+        bent.len()
+    );
+    Ok(())
+}
+
+/// The UI test pattern's panel color, and the tints of its lines and text,
+/// sRGB encoded.
+const UI_PANEL: [f32; 4] = [0.2, 0.2, 0.25, 1.0];
+const UI_LINE: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+const UI_TEXT: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+
+fn check_ui_compositing(context: &HeadlessContext, fixture: &Fixture) -> anyhow::Result<()> {
+    let texture_view_formats = context
+        .adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::VIEW_FORMATS);
+    let mut surfaces = vec![
+        (wgpu::TextureFormat::Rgba8Unorm, gpu::OutputMode::Sdr, false),
+        (
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            gpu::OutputMode::Sdr,
+            false,
+        ),
+        (
+            wgpu::TextureFormat::Rgba16Float,
+            gpu::OutputMode::ScRgbLinear,
+            false,
+        ),
+    ];
+    if texture_view_formats {
+        surfaces.push((
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            gpu::OutputMode::Sdr,
+            true,
+        ));
+    }
+
+    let textures = ui_pattern_textures(context)?;
+    let mut renders = Vec::new();
+    for (format, output_mode, view_formats) in surfaces {
+        let path = UiPath::select(
+            format,
+            output_mode,
+            view_formats,
+            gpu::DEFAULT_PAPER_WHITE_NITS,
+        );
+        let render = draw_ui_pattern(context, fixture, &textures, format, path)?;
+        renders.push((format, path, render));
+    }
+
+    let (_, path, reference) = &renders[0];
+    anyhow::ensure!(
+        *path == UiPath::Direct(wgpu::TextureFormat::Rgba8Unorm),
+        "an Rgba8Unorm surface draws UI {:?}",
+        path
+    );
+    // Outside the panel the scene shows through untouched.
+    let scene = reference.get_pixel(4, 4).0;
+    anyhow::ensure!(
+        scene[..3].iter().all(|c| c.abs_diff(128) <= MAX_UI_DELTA),
+        "the scene around the panel came out as {:?}",
+        scene
+    );
+    // A half transparent white line over the panel, blended as encoded
+    // values. Blending in linear comes out far brighter.
+    let line = reference.get_pixel(24, 120).0;
+    let expected = UI_PANEL.map(|panel| {
+        ((UI_LINE[0] * UI_LINE[3] + panel * (1.0 - UI_LINE[3])) * 255.0).round() as u8
+    });
+    anyhow::ensure!(
+        line[..3]
+            .iter()
+            .zip(expected)
+            .all(|(c, e)| c.abs_diff(e) <= MAX_UI_DELTA),
+        "a line over the panel came out as {:?}, not {:?}",
+        line,
+        &expected[..3]
+    );
+
+    for (format, path, render) in &renders[1..] {
+        let (delta, x, y) = reference
+            .enumerate_pixels()
+            .flat_map(|(x, y, pixel)| {
+                let other = render.get_pixel(x, y).0;
+                pixel
+                    .0
+                    .into_iter()
+                    .zip(other)
+                    .map(move |(a, b)| (a.abs_diff(b), x, y))
+            })
+            .max_by_key(|(delta, _, _)| *delta)
+            .unwrap_or_default();
+        anyhow::ensure!(
+            delta <= MAX_UI_DELTA,
+            "UI on {:?} drawn {:?} differs by {} at {}, {}",
+            format,
+            path,
+            delta,
+            x,
+            y
+        );
+    }
+    Ok(())
+}
+
+/// The UI test pattern's textures, premultiplied: a white texel, an opaque
+/// black to white ramp, red fading in, and the debug font if it's built
+/// in. The font is white on transparent black, which premultiplying leaves
+/// as it is.
+fn ui_pattern_textures(context: &HeadlessContext) -> anyhow::Result<Vec<texture::Texture>> {
+    let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]));
+    let ramp =
+        image::RgbaImage::from_fn(256, 1, |x, _| image::Rgba([x as u8, x as u8, x as u8, 255]));
+    let mut fade = image::RgbaImage::from_fn(256, 1, |x, _| image::Rgba([255, 0, 0, x as u8]));
+    texture::premultiply_alpha(&mut fade);
+    #[allow(unused_mut)]
+    let mut images = vec![white, ramp, fade];
+    #[cfg(feature = "builtin-font")]
+    images.push(crate::builtin::font::atlas());
+    images
+        .iter()
+        .map(|image| {
+            texture::Texture::from_ui_image(
+                &context.device,
+                &context.queue,
+                image,
+                Some("Validation UI Texture"),
+            )
+        })
+        .collect()
+}
+
+/// The UI test pattern over `textures` from [`ui_pattern_textures`]: an
+/// opaque panel, both ramps stretched, 1px lines at half alpha, and text
+/// at a fractional scale and offset so its edges are filtered.
+fn ui_pattern(textures: &[SpriteTexture]) -> Vec<Sprite> {
+    let mut sprites = vec![
+        Sprite {
+            color: UI_PANEL,
+            ..Sprite::new(textures[0], [16.0, 16.0], [224.0, 224.0])
+        },
+        Sprite {
+            layer: 1,
+            ..Sprite::new(textures[1], [24.0, 24.0], [208.0, 24.0])
+        },
+        Sprite {
+            layer: 1,
+            ..Sprite::new(textures[2], [24.0, 56.0], [208.0, 24.0])
+        },
+    ];
+    for i in 0..8 {
+        let offset = i as f32;
+        sprites.push(Sprite {
+            color: UI_LINE,
+            layer: 1,
+            ..Sprite::new(textures[0], [24.0 + offset * 4.0, 96.0], [1.0, 48.0])
+        });
+        sprites.push(Sprite {
+            color: UI_LINE,
+            layer: 1,
+            ..Sprite::new(textures[0], [64.0, 150.0 + offset * 3.0], [100.0, 1.0])
+        });
+    }
+    #[cfg(feature = "builtin-font")]
+    {
+        use crate::builtin::font;
+        let atlas = font::atlas();
+        let (width, height) = (atlas.width() as f32, atlas.height() as f32);
+        let glyph = [font::GLYPH_WIDTH as f32, font::GLYPH_HEIGHT as f32];
+        for (i, c) in "UI 0123 ABC".chars().enumerate() {
+            let Some((x, y)) = font::atlas_position(c) else {
+                continue;
+            };
+            let (x, y) = (x as f32, y as f32);
+            sprites.push(Sprite {
+                uv_min: [x / width, y / height],
+                uv_max: [(x + glyph[0]) / width, (y + glyph[1]) / height],
+                color: UI_TEXT,
+                layer: 2,
+                ..Sprite::new(
+                    textures[3],
+                    [24.3 + i as f32 * 10.0, 200.3],
+                    [glyph[0] * 2.5, glyph[1] * 2.5],
+                )
+            });
+        }
+    }
+    sprites
+}
+
+/// Draws the UI test pattern through `path` over a mid gray scene, into a
+/// target of `format` standing in for the surface, and reads it back sRGB
+/// encoded.
+fn draw_ui_pattern(
+    context: &HeadlessContext,
+    fixture: &Fixture,
+    textures: &[texture::Texture],
+    format: wgpu::TextureFormat,
+    path: UiPath,
+) -> anyhow::Result<image::RgbaImage> {
+    let device = &context.device;
+    let view_formats = path.surface_view_formats(format);
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Validation UI Target"),
+        size: wgpu::Extent3d {
+            width: TARGET_SIZE.0,
+            height: TARGET_SIZE.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &view_formats,
+    });
+    let compositor = UiCompositor::new(device, path, format, TARGET_SIZE)?;
+    let mut sprites = SpriteBatch::new(
+        device,
+        compositor.format(),
+        &fixture.texture_bind_group_layout,
+    )?;
+    let handles = textures
+        .iter()
+        .map(|texture| {
+            sprites.add_texture(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &fixture.texture_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+                label: Some("validation_ui_bind_group"),
+            }))
+        })
+        .collect::<Vec<_>>();
+    for sprite in ui_pattern(&handles) {
+        sprites.push(sprite);
+    }
+    sprites.prepare(
+        device,
+        &context.queue,
+        winit::dpi::PhysicalSize::new(TARGET_SIZE.0, TARGET_SIZE.1),
+        1.0,
+    )?;
+
+    // The gray is stored the way the scene would store it on each surface.
+    let scale = match path {
+        UiPath::Layer {
+            paper_white_scale, ..
+        } => paper_white_scale,
+        UiPath::Direct(_) => 1.0,
+    };
+    let gray = match format {
+        wgpu::TextureFormat::Rgba8Unorm => 128.0 / 255.0,
+        _ => (mipmap::srgb_to_linear(128) * scale) as f64,
+    };
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let ui_view = compositor.target(&target);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Validation UI Encoder"),
+    });
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Validation Scene Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a: 1.0,
+                }),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Validation Sprite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &ui_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: compositor.load_op(),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        sprites.draw(&mut render_pass);
+    }
+    compositor.composite(&mut encoder, &view);
+    context.queue.submit(Some(encoder.finish()));
+
+    match format {
+        wgpu::TextureFormat::Rgba16Float => read_float_encoded(context, &target, scale),
+        _ => export::read_texture(device, &context.queue, &target)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the UI target")),
+    }
+}
+
+/// Reads back an `Rgba16Float` target sRGB encoded, with its colors divided
+/// by `scale` first.
+fn read_float_encoded(
+    context: &HeadlessContext,
+    texture: &wgpu::Texture,
+    scale: f32,
+) -> anyhow::Result<image::RgbaImage> {
+    let size = texture.size();
+    let data = export::read_texture_region(
+        &context.device,
+        &context.queue,
+        texture.as_image_copy(),
+        (size.width, size.height),
+    )?;
+    let pixels = data
+        .bytes
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, half)| {
+            let value = compression::f16_to_f32(u16::from_le_bytes([half[0], half[1]]));
+            if i % 4 == 3 {
+                (value * 255.0).round().clamp(0.0, 255.0) as u8
+            } else {
+                mipmap::linear_to_srgb(value / scale)
+            }
+        })
+        .collect();
+    image::RgbaImage::from_raw(size.width, size.height, pixels)
+        .ok_or_else(|| anyhow::anyhow!("Couldn't read back the UI target"))
+}
+
+/// The points of the PLY fixtures, one for each primary color.
+const PLY_POINTS: [PointVertex; 3] = [
+    PointVertex {
+        position: [-1.0, 0.0, 0.0],
+        color: [255, 0, 0, 255],
+    },
+    PointVertex {
+        position: [0.0, 1.0, 0.5],
+        color: [0, 255, 0, 255],
+    },
+    PointVertex {
+        position: [1.0, -0.5, 0.0],
+        color: [0, 0, 255, 255],
+    },
+];
+
+/// [`PLY_POINTS`] as a PLY file, with normals and, first, a face element
+/// that must be skipped.
+fn ply_fixture(binary: bool) -> Vec<u8> {
+    let format = if binary {
+        "binary_little_endian"
+    } else {
+        "ascii"
+    };
+    let mut bytes = format!(
+        "ply\nformat {} 1.0\ncomment validation fixture\n\
+         element face 1\nproperty list uchar int vertex_indices\n\
+         element vertex {}\n\
+         property float x\nproperty float y\nproperty float z\n\
+         property float nx\nproperty float ny\nproperty float nz\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\n\
+         end_header\n",
+        format,
+        PLY_POINTS.len()
+    )
+    .into_bytes();
+    let normal = [0.0f32, 0.0, 1.0];
+    if binary {
+        bytes.push(3);
+        for i in 0..3i32 {
+            bytes.extend(i.to_le_bytes());
+        }
+        for point in &PLY_POINTS {
+            for v in point.position.iter().chain(&normal) {
+                bytes.extend(v.to_le_bytes());
+            }
+            bytes.extend(&point.color[..3]);
+        }
+    } else {
+        bytes.extend(b"3 0 1 2\n");
+        for point in &PLY_POINTS {
+            let [x, y, z] = point.position;
+            let [r, g, b, _] = point.color;
+            let line = format!("{} {} {} 0 0 1 {} {} {}\n", x, y, z, r, g, b);
+            bytes.extend(line.into_bytes());
+        }
+    }
+    bytes
+}
+
+async fn check_point_cloud(context: &HeadlessContext, fixture: &Fixture) -> anyhow::Result<()> {
+    let fixtures = [ply_fixture(false), ply_fixture(true)];
+    for (bytes, name) in fixtures.iter().zip(["ASCII", "binary"]) {
+        let points = ply::parse(bytes)?;
+        anyhow::ensure!(
+            points == PLY_POINTS,
+            "The {} fixture parses as {:?}",
+            name,
+            points
+        );
+        let mut reader = ply::PlyReader::new(&bytes[..])?;
+        let mut chunked = Vec::new();
+        while reader.read_points(2, &mut chunked)? > 0 {}
+        anyhow::ensure!(
+            chunked == PLY_POINTS,
+            "The {} fixture parses as {:?} in chunks",
+            name,
+            chunked
+        );
+    }
+    let gray = ply::parse(
+        b"ply\nformat ascii 1.0\nelement vertex 1\n\
+          property float x\nproperty float y\nproperty float z\nproperty float intensity\n\
+          end_header\n0 0 0 0.5\n",
+    )?;
+    anyhow::ensure!(
+        gray[0].color == [128, 128, 128, 255],
+        "An intensity of 0.5 parses as {:?}",
+        gray[0].color
+    );
+
+    let mut clouds = Vec::new();
+    for (bytes, name) in fixtures.iter().zip(["ascii", "binary"]) {
+        let path = std::env::temp_dir().join(format!(
+            "validation-{:?}-{}.ply",
+            context.adapter.get_info().backend,
+            name
+        ));
+        std::fs::write(&path, bytes)?;
+        let cloud =
+            resources::load_ply(&path.to_string_lossy(), &context.device, &context.queue).await;
+        let _ = std::fs::remove_file(&path);
+        let cloud = cloud?;
+        let uploaded = export::read_buffer(
+            &context.device,
+            &context.queue,
+            &cloud.vertex_buffer,
+            0,
+            std::mem::size_of_val(&PLY_POINTS) as u64,
+        )?;
+        anyhow::ensure!(
+            cloud.num_points == PLY_POINTS.len() as u32
+                && uploaded == bytemuck::cast_slice::<_, u8>(&PLY_POINTS),
+            "The {} fixture uploads {} points as {:?}",
+            name,
+            cloud.num_points,
+            uploaded
+        );
+        clouds.push(cloud);
+    }
+    let aabb = clouds[0]
+        .aabb
+        .ok_or_else(|| anyhow::anyhow!("The cloud has no bounds"))?;
+    anyhow::ensure!(
+        aabb.min == Point3::new(-1.0, -0.5, 0.0) && aabb.max == Point3::new(1.0, 1.0, 0.5),
+        "The cloud's bounds are {:?}",
+        aabb
+    );
+
+    let (view_proj, _) = origin_camera(TARGET_SIZE.0 as f32 / TARGET_SIZE.1 as f32);
+    let on_screen = |p: [f32; 3]| {
+        let clip = view_proj.transform_point(Point3::from(p));
+        (
+            (clip.x + 1.0) / 2.0 * TARGET_SIZE.0 as f32,
+            (1.0 - clip.y) / 2.0 * TARGET_SIZE.1 as f32,
+        )
+    };
+    let mut drawn = Vec::new();
+    for size in [PointSize::Pixel, PointSize::Screen(6.0)] {
+        let pipeline = PointPipeline::new(&context.device, fixture.color.format(), size)?;
+        pipeline.prepare(&context.queue, view_proj, TARGET_SIZE);
+        let view = fixture
+            .color
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Validation Point Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Validation Point Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &fixture.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pipeline.draw(&mut render_pass, &clouds[1]);
+        }
+        context.queue.submit(Some(encoder.finish()));
+        let render = export::read_texture(&context.device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))?;
+
+        // Quads are centered on the point, so its own pixel has its color
+        // whichever way the corners round.
+        if let PointSize::Screen(_) = size {
+            for point in &PLY_POINTS {
+                let (x, y) = on_screen(point.position);
+                let pixel = render.get_pixel(x as u32, y as u32);
+                anyhow::ensure!(
+                    pixel.0[..3] == point.color[..3],
+                    "The point at {:?} is drawn as {:?}",
+                    point.position,
+                    pixel
+                );
+            }
+        }
+        drawn.push(drawn_pixels(&render, 0..TARGET_SIZE.0));
+    }
+    log::info!(
+        "Points cover {} pixels, and {} as quads",
+        drawn[0],
+        drawn[1]
+    );
+    anyhow::ensure!(
+        (1..=PLY_POINTS.len()).contains(&drawn[0]),
+        "{} points are drawn as {} pixels",
+        PLY_POINTS.len(),
+        drawn[0]
+    );
+    // 6 pixels across, less any overlap.
+    anyhow::ensure!(
+        drawn[1] >= PLY_POINTS.len() * 25,
+        "{} points are drawn as quads of {} pixels in all",
+        PLY_POINTS.len(),
+        drawn[1]
+    );
+
+    let picking = read_back(context, |readbacks, encoder, on_read| {
+        PickPoints::read_cloud(readbacks, &context.device, encoder, &clouds[1], on_read)
+    })??;
+    let (x, y) = on_screen(PLY_POINTS[1].position);
+    let picked = picking.pick(&view_proj, (x + 2.0, y), TARGET_SIZE, 4.0);
+    anyhow::ensure!(
+        picked == Some(Point3::from(PLY_POINTS[1].position)),
+        "Picking next to {:?} finds {:?}",
+        PLY_POINTS[1].position,
+        picked
+    );
+    let missed = picking.pick(&view_proj, (0.0, 0.0), TARGET_SIZE, 4.0);
+    anyhow::ensure!(missed.is_none(), "Picking a corner finds {:?}", missed);
+    Ok(())
+}
+
+/// Rows of bricks 24 by 16 texels, mortar included, with 4 texel joints
+/// along the bottom and left of each, every other row offset by half a
+/// brick. 64 isn't a multiple of 24, so the pattern breaks where the
+/// texture wraps, as it does at a UV mapped sphere's seam.
+fn brick_texture() -> image::RgbaImage {
+    image::RgbaImage::from_fn(64, 64, |x, y| {
+        let row = y / 16;
+        let x = x + row % 2 * 12;
+        if y % 16 >= 12 || x % 24 < 4 {
+            image::Rgba([220, 220, 210, 255])
+        } else {
+            image::Rgba([170, 60, 40, 255])
+        }
+    })
+}
+
+/// Mortar is about as green as it is red, brick much less, whatever the
+/// light.
+fn is_mortar(pixel: &image::Rgba<u8>) -> bool {
+    pixel[1] as u32 * 3 > pixel[0] as u32 * 2
+}
+
+/// Changes between brick and mortar along a circle of `radius` pixels
+/// around `center`.
+fn circle_transitions(image: &image::RgbaImage, center: (f32, f32), radius: f32) -> usize {
+    const SAMPLES: usize = 720;
+    let mortar = (0..SAMPLES)
+        .map(|i| {
+            let angle = i as f32 / SAMPLES as f32 * std::f32::consts::TAU;
+            let x = center.0 + radius * angle.cos();
+            let y = center.1 + radius * angle.sin();
+            is_mortar(image.get_pixel(x as u32, y as u32))
+        })
+        .collect::<Vec<_>>();
+    (0..SAMPLES)
+        .filter(|&i| mortar[i] != mortar[(i + 1) % SAMPLES])
+        .count()
+}
+
+/// A UV sphere with a brick material for each of `spaces`, `None` being UV
+/// mapped, loaded through model data files from `triplanar/` in the
+/// resource dir.
+async fn load_brick_spheres(
+    context: &HeadlessContext,
+    fixture: &Fixture,
+    spaces: &[Option<Triplanar>],
+) -> anyhow::Result<Vec<Model>> {
+    let (vertices, indices) = crate::primitives::uv_sphere(1.0, 32, 16);
+    let sphere = model::MeshData {
+        vertices: vertices
+            .into_iter()
+            .map(|v| ModelVertex {
+                position: v.position,
+                tex_coords: v.tex_coords,
+                normal: v.normal,
+                occlusion: 1.0,
+            })
+            .collect(),
+        indices,
+    };
+    let mut models = Vec::new();
+    for &space in spaces {
+        let data = ModelData {
+            meshes: vec![MeshRecord {
+                name: "sphere".to_string(),
+                material: 0,
+                data: sphere.clone(),
+                has_uvs: true,
+            }],
+            materials: vec![MaterialRecord {
+                name: "bricks".to_string(),
+                diffuse_texture: "bricks.png".to_string(),
+                alpha_mask: false,
+                refractive: None,
+                triplanar: space,
+            }],
+        };
+        let data = ModelData::from_bytes(&data.to_bytes())?;
+        let saved = data.materials[0].triplanar;
+        anyhow::ensure!(saved == space, "{:?} was saved as {:?}", space, saved);
+        let model = resources::load_model_data(
+            "triplanar/sphere.modeldata",
+            data,
+            &context.device,
+            &context.queue,
+            &fixture.texture_bind_group_layout,
+            &resources::LoadOptions::default(),
+            None,
+        )
+        .await?;
+        let material = &model.materials[0];
+        anyhow::ensure!(
+            material.triplanar.as_ref().map(|t| t.triplanar()) == space
+                && material.features.contains(MaterialFeatures::TRIPLANAR) == space.is_some(),
+            "{:?} loaded as {:?}",
+            space,
+            material.features
+        );
+        models.push(model);
+    }
+    Ok(models)
+}
+
+/// A brick sphere seen from straight above its pole, UV mapped and
+/// triplanar in each space, turned by `turns` quarter turns about Y. The
+/// sphere has 32 sectors, so a quarter turn leaves its shape the same and
+/// only moves its seam and UVs.
+async fn check_triplanar(context: &HeadlessContext, fixture: &mut Fixture) -> anyhow::Result<()> {
+    let triplanar = Triplanar {
+        scale: 3.0,
+        blend_sharpness: 8.0,
+        space: TriplanarSpace::World,
+    };
+    let mtl = triplanar.to_mtl();
+    anyhow::ensure!(
+        Triplanar::from_mtl(&mtl) == Some(triplanar),
+        "`{} {}` reads back as {:?}",
+        Triplanar::MTL_KEY,
+        mtl,
+        Triplanar::from_mtl(&mtl)
+    );
+    anyhow::ensure!(
+        Triplanar::from_mtl("0.5")
+            == Some(Triplanar {
+                scale: 0.5,
+                ..Triplanar::DEFAULT
+            })
+            && Triplanar::from_mtl("1 4 sideways").is_none(),
+        "optional and malformed triplanar arguments aren't handled"
+    );
+
+    let spaces = [
+        None,
+        Some(triplanar),
+        Some(Triplanar {
+            space: TriplanarSpace::Object,
+            ..triplanar
+        }),
+    ];
+    let root = std::env::temp_dir().join(format!(
+        "validation-triplanar-{:?}",
+        context.adapter.get_info().backend
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("triplanar"))?;
+    brick_texture().save(root.join("triplanar/bricks.png"))?;
+    resources::set_resource_dir(Some(root.clone()));
+    let spheres = load_brick_spheres(context, fixture, &spaces).await;
+    resources::set_resource_dir(None);
+    let _ = std::fs::remove_dir_all(&root);
+    let mut models = Assets::new();
+    let handles = spheres?
+        .into_iter()
+        .map(|model| models.insert(model))
+        .collect::<Vec<_>>();
+
+    let camera = Camera {
+        eye: (0.0, 4.0, 0.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_z(),
+        aspect: TARGET_SIZE.0 as f32 / TARGET_SIZE.1 as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let mut uniform = CameraUniform::new();
+    uniform.update_view_proj(&camera);
+    let view_proj: Matrix4<f32> = uniform.view_proj.into();
+    let pole = view_proj.transform_point(Point3::new(0.0, 1.0, 0.0));
+    let pole = (
+        (pole.x * 0.5 + 0.5) * TARGET_SIZE.0 as f32,
+        (0.5 - pole.y * 0.5) * TARGET_SIZE.1 as f32,
+    );
+    let mut draw = |model: ModelHandle, turns: u32| {
+        let mut frame = RenderFrame::new();
+        frame.push_camera(
+            view_proj,
+            camera.eye,
+            Viewport::new(0.0, 0.0, TARGET_SIZE.0 as f32, TARGET_SIZE.1 as f32),
+        );
+        frame.push_model(
+            model,
+            Instance {
+                rotation: cgmath::Quaternion::from_axis_angle(
+                    cgmath::Vector3::unit_y(),
+                    cgmath::Deg(90.0 * turns as f32),
+                ),
+                ..at(0.0, 0.0)
+            },
+            MaterialOverrides::default(),
+            DrawFlags::empty(),
+        );
+        fixture.draw(context, &frame, &models)?;
+        export::read_texture(&context.device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))
+    };
+    let uv = draw(handles[0], 0)?;
+    let world = draw(handles[1], 0)?;
+    let object = draw(handles[2], 0)?;
+
+    // Pinching packs the same number of bricks into ever smaller circles
+    // around the pole, so the density of transitions grows toward it.
+    let pinching = |image: &image::RgbaImage| {
+        let density = |radius: f32| circle_transitions(image, pole, radius) as f32 / radius;
+        density(4.0) / density(40.0).max(f32::EPSILON)
+    };
+    let (uv_pinching, world_pinching, object_pinching) =
+        (pinching(&uv), pinching(&world), pinching(&object));
+    anyhow::ensure!(
+        uv_pinching > 5.0,
+        "the UV mapped reference only pinches by {:.2} at the pole",
+        uv_pinching
+    );
+    anyhow::ensure!(
+        world_pinching < 3.0 && object_pinching < 3.0,
+        "triplanar bricks pinch by {:.2} in world space and {:.2} in object space",
+        world_pinching,
+        object_pinching
+    );
+
+    // Turning the sphere moves its seam and UVs. World space projections
+    // don't move with it, object space ones do.
+    let (uv_turned, world_turned, object_turned) = (
+        draw(handles[0], 1)?,
+        draw(handles[1], 1)?,
+        draw(handles[2], 1)?,
+    );
+    let (uv_moved, world_moved, object_moved) = (
+        mean_delta(&uv, &uv_turned),
+        mean_delta(&world, &world_turned),
+        mean_delta(&object, &object_turned),
+    );
+    anyhow::ensure!(
+        world_moved < 1.0 && uv_moved > 4.0 * world_moved.max(0.25),
+        "a quarter turn changes world space triplanar by {:.2} and UV mapping by {:.2}",
+        world_moved,
+        uv_moved
+    );
+    anyhow::ensure!(
+        object_moved > 4.0 * world_moved.max(0.25),
+        "object space triplanar only changed by {:.2} with a quarter turn",
+        object_moved
+    );
+    Ok(())
+}
+
+/// Noise with hard edges, so a filter weighting any texel wrongly shows.
+fn mip_test_image(width: u32, height: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let hash = (x * 73 + y * 151) ^ (x * y * 17);
+        let channel = |shift: u32| ((hash >> shift) * 97 % 256) as u8;
+        image::Rgba([channel(0), channel(2), channel(4), 128 + channel(1) / 2])
+    })
+}
+
+fn check_mipmaps(context: &HeadlessContext) -> anyhow::Result<()> {
+    let device = &context.device;
+    let mut generator = mipmap::MipmapGenerator::new(device);
+    for format in [
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+    ] {
+        for (width, height) in [(13, 7), (5, 5), (16, 9)] {
+            let base = mip_test_image(width, height);
+            let mip_level_count = mipmap::mip_level_count(width, height);
+            let texture = device.create_texture_with_data(
+                &context.queue,
+                &wgpu::TextureDescriptor {
+                    label: Some("Mip Test Texture"),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                },
+                // Only the top level is filled; the rest start as zeros.
+                &[
+                    base.as_raw().clone(),
+                    vec![0; 4 * (width * height) as usize],
+                ]
+                .concat(),
+            );
+            let mut encoder = device.create_command_encoder(&Default::default());
+            generator.generate(device, &mut encoder, &texture, format, mip_level_count)?;
+            context.queue.submit(Some(encoder.finish()));
+
+            let expected = mipmap::generate_cpu(&base, format.is_srgb());
+            for (level, expected) in (1..).zip(&expected) {
+                let level_image =
+                    export::read_texture_level(device, &context.queue, &texture, level)?
+                        .ok_or_else(|| anyhow::anyhow!("Couldn't read back mip {}", level))?;
+                let delta = level_image
+                    .pixels()
+                    .zip(expected.pixels())
+                    .flat_map(|(a, b)| a.0.iter().zip(b.0).map(|(a, b)| a.abs_diff(b)))
+                    .max()
+                    .unwrap_or(0);
+                anyhow::ensure!(
+                    delta <= MAX_MIP_DELTA,
+                    "{:?} {}x{} mip {} is off by up to {}",
+                    format,
+                    width,
+                    height,
+                    level,
+                    delta
+                );
+            }
+        }
+    }
+
+    // A texture without mips still thumbnails through halved levels; with
+    // the edge on a level boundary every thumbnail texel stays one color.
+    let red = image::Rgba([255, 0, 0, 255]);
+    let blue = image::Rgba([0, 0, 255, 255]);
+    let halves = image::RgbaImage::from_fn(192, 120, |x, _| if x < 96 { red } else { blue });
+    let texture = texture::Texture::from_image(
+        device,
+        &context.queue,
+        &image::DynamicImage::ImageRgba8(halves),
+        Some("Thumbnail Test Texture"),
+        &texture::TextureSettings::default(),
+    )?;
+    let thumbnail = texture.thumbnail(device, &context.queue, &mut generator, 12)?;
+    anyhow::ensure!(
+        thumbnail.dimensions() == (12, 8),
+        "a 192x120 thumbnail at 12 is {:?}",
+        thumbnail.dimensions()
+    );
+    for (x, y, pixel) in thumbnail.enumerate_pixels() {
+        let expected = if x < 6 { red } else { blue };
+        let delta = pixel
+            .0
+            .iter()
+            .zip(expected.0)
+            .map(|(a, b)| a.abs_diff(b))
+            .max()
+            .unwrap_or(0);
+        anyhow::ensure!(
+            delta <= MAX_MIP_DELTA,
+            "thumbnail texel ({}, {}) is {:?}",
+            x,
+            y,
+            pixel
+        );
+    }
+    Ok(())
+}
+
+fn check_region_updates(context: &HeadlessContext) -> anyhow::Result<()> {
+    let (width, height) = (70, 40);
+    let texture = texture::Texture::writable(
+        &context.device,
+        width,
+        height,
+        wgpu::TextureFormat::Rgba8Unorm,
+        false,
+        Some("Region Test Texture"),
+    )?;
+    let base = image::Rgba([10, 20, 30, 255]);
+    let filled = image::RgbaImage::from_pixel(width, height, base);
+    texture.write_region(&context.queue, (0, 0), (width, height), filled.as_raw())?;
+    // 64 texels are exactly one copy alignment across; 5 need padding.
+    let regions = [
+        ((0, 0), (64, 3), image::Rgba([200, 0, 0, 255])),
+        ((61, 30), (5, 7), image::Rgba([0, 200, 0, 128])),
+    ];
+    for (origin, size, color) in regions {
+        let region = image::RgbaImage::from_pixel(size.0, size.1, color);
+        texture.write_region(&context.queue, origin, size, region.as_raw())?;
+    }
+    anyhow::ensure!(
+        texture
+            .write_region(&context.queue, (68, 0), (3, 1), &[0; 12])
+            .is_err(),
+        "a region past the edge was written"
+    );
+
+    let written = export::read_texture(&context.device, &context.queue, &texture.texture)?
+        .ok_or_else(|| anyhow::anyhow!("Couldn't read back the texture"))?;
+    for (x, y, pixel) in written.enumerate_pixels() {
+        let expected = regions
+            .iter()
+            .find(|((ox, oy), (w, h), _)| (*ox..ox + w).contains(&x) && (*oy..oy + h).contains(&y))
+            .map_or(base, |region| region.2);
+        anyhow::ensure!(
+            *pixel == expected,
+            "texel ({}, {}) is {:?}, expected {:?}",
+            x,
+            y,
+            pixel,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// A triangle covering the target in one flat color.
+const FLAT_SHADER: &str = "
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
+";
+
+fn check_pass_hooks(context: &HeadlessContext, fixture: &mut Fixture) -> anyhow::Result<()> {
+    let device = &context.device;
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Validation Flat Shader"),
+        source: wgpu::ShaderSource::Wgsl(FLAT_SHADER.into()),
+    });
+    let flat = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Validation Flat Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(fixture.color.format().into())],
+        }),
+        primitive: Default::default(),
+        depth_stencil: None,
+        multisample: Default::default(),
+        multiview: None,
+    });
+
+    let slots = [
+        compose::PassSlot::BeforeScene,
+        compose::PassSlot::AfterOpaque,
+        compose::PassSlot::AfterTransparent,
+        compose::PassSlot::BeforeUi,
+        compose::PassSlot::AfterUi,
+    ];
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let mut hooks = compose::FrameHooks::default();
+    // Added out of order, to run in frame order.
+    for &slot in slots.iter().rev() {
+        let ran = ran.clone();
+        hooks.add_pass(slot, move |_| ran.lock().unwrap().push(slot));
+    }
+    hooks.add_pass(compose::PassSlot::BeforeScene, |context| {
+        context
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Validation Background Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: context.color,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+    });
+    let overlay = hooks.add_pass(compose::PassSlot::AfterUi, move |context| {
+        let mut pass = context
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Validation Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: context.color,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+        pass.set_pipeline(&flat);
+        pass.set_scissor_rect(0, 0, context.size.0 / 2, context.size.1);
+        pass.draw(0..3, 0..1);
+    });
+    hooks.set_loads(compose::TargetLoads {
+        color: wgpu::LoadOp::Load,
+        ..Default::default()
+    });
+
+    let view = fixture
+        .color
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    let mut draw = |hooks: &mut compose::FrameHooks| {
+        let stats =
+            fixture.draw_with_hooks(context, &RenderFrame::new(), &Assets::new(), &view, hooks)?;
+        let image = export::read_texture(device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))?;
+        anyhow::Ok((stats, image))
+    };
+    let expect = |image: &image::RgbaImage, color: &dyn Fn(u32) -> [u8; 4]| {
+        for (x, y, pixel) in image.enumerate_pixels() {
+            anyhow::ensure!(
+                pixel.0 == color(x),
+                "({}, {}) is {:?}, expected {:?}",
+                x,
+                y,
+                pixel.0,
+                color(x)
+            );
+        }
+        Ok(())
+    };
+
+    let (stats, loaded) = draw(&mut hooks)?;
+    let order = std::mem::take(&mut *ran.lock().unwrap());
+    anyhow::ensure!(order == slots, "the hooks ran as {:?}", order);
+    anyhow::ensure!(
+        stats.passes >= 8,
+        "{} passes counted with the scene and 7 hooks",
+        stats.passes
+    );
+    expect(&loaded, &|x| {
+        if x < TARGET_SIZE.0 / 2 {
+            [255, 0, 255, 255]
+        } else {
+            [0, 255, 0, 255]
+        }
+    })?;
+
+    anyhow::ensure!(hooks.remove_pass(overlay), "the overlay wasn't there");
+    hooks.set_clear_color(wgpu::Color::BLUE);
+    let (_, cleared) = draw(&mut hooks)?;
+    expect(&cleared, &|_| [0, 0, 255, 255])?;
+    anyhow::ensure!(
+        ran.lock().unwrap().len() == slots.len(),
+        "the hooks didn't run again"
+    );
+    Ok(())
+}
+
+/// A UV sphere with a white material for each of `meshes`, loaded through
+/// model data files from `layouts/` in the resource dir.
+async fn load_white_meshes(
+    context: &HeadlessContext,
+    fixture: &Fixture,
+    meshes: Vec<model::MeshData>,
+) -> anyhow::Result<Vec<Model>> {
+    let mut models = Vec::new();
+    for mesh in meshes {
+        let data = ModelData {
+            meshes: vec![MeshRecord {
+                name: "sphere".to_string(),
+                material: 0,
+                data: mesh,
+                has_uvs: true,
+            }],
+            materials: vec![MaterialRecord {
+                name: "white".to_string(),
+                diffuse_texture: "white.png".to_string(),
+                alpha_mask: false,
+                refractive: None,
+                triplanar: None,
+            }],
+        };
+        models.push(
+            resources::load_model_data(
+                "layouts/sphere.modeldata",
+                data,
+                &context.device,
+                &context.queue,
+                &fixture.texture_bind_group_layout,
+                &resources::LoadOptions::default(),
+                None,
+            )
+            .await?,
+        );
+    }
+    Ok(models)
+}
+
+/// A unit sphere stretched to 2.5 along Y by its instance, which goes
+/// through the full instance layout, against the sphere stretched in the
+/// mesh, drawn compactly. With an ambient that darkens toward the ground
+/// they have to match, and differ from a stretched mesh whose normals were
+/// stretched too, which is what the compact layout would have drawn.
+async fn check_instance_layouts(
+    context: &HeadlessContext,
+    fixture: &mut Fixture,
+) -> anyhow::Result<()> {
+    let stretch = cgmath::Vector3::new(1.0, 2.5, 1.0);
+    let (vertices, indices) = crate::primitives::uv_sphere(1.0, 32, 16);
+    let mesh = |position: &dyn Fn([f32; 3]) -> [f32; 3], normal: &dyn Fn([f32; 3]) -> [f32; 3]| {
+        model::MeshData {
+            vertices: vertices
+                .iter()
+                .map(|v| ModelVertex {
+                    position: position(v.position),
+                    tex_coords: v.tex_coords,
+                    normal: normal(v.normal),
+                    occlusion: 1.0,
+                })
+                .collect(),
+            indices: indices.clone(),
+        }
+    };
+    let stretched = |p: [f32; 3]| [p[0] * stretch.x, p[1] * stretch.y, p[2] * stretch.z];
+    let meshes = vec![
+        mesh(&|p| p, &|n| n),
+        mesh(&stretched, &|n| {
+            let n = cgmath::Vector3::new(n[0] / stretch.x, n[1] / stretch.y, n[2] / stretch.z);
+            n.normalize().into()
+        }),
+        mesh(&stretched, &|n| {
+            cgmath::Vector3::from(stretched(n)).normalize().into()
+        }),
+    ];
+
+    let root = std::env::temp_dir().join(format!(
+        "validation-instance-layouts-{:?}",
+        context.adapter.get_info().backend
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("layouts"))?;
+    image::RgbaImage::from_pixel(4, 4, image::Rgba([255; 4]))
+        .save(root.join("layouts/white.png"))?;
+    resources::set_resource_dir(Some(root.clone()));
+    let spheres = load_white_meshes(context, fixture, meshes).await;
+    resources::set_resource_dir(None);
+    let _ = std::fs::remove_dir_all(&root);
+    let mut models = Assets::new();
+    let handles = spheres?
+        .into_iter()
+        .map(|model| models.insert(model))
+        .collect::<Vec<_>>();
+
+    let mut draw = |model: ModelHandle, scale: cgmath::Vector3<f32>, layout: InstanceLayout| {
+        let mut frame = frame_with(
+            model,
+            vec![Instance {
+                scale,
+                ..at(0.0, 0.0)
+            }],
+        );
+        frame.ambient = crate::light::Ambient::new([1.0; 3], [0.05; 3], 1.0);
+        anyhow::ensure!(
+            frame.instance_layout() == layout,
+            "scaling by {:?} packs {:?} instances",
+            scale,
+            frame.instance_layout()
+        );
+        fixture.draw(context, &frame, &models)?;
+        export::read_texture(&context.device, &context.queue, &fixture.color)?
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))
+    };
+    let unit = cgmath::Vector3::new(1.0, 1.0, 1.0);
+    let instanced = draw(handles[0], stretch, InstanceLayout::Full)?;
+    let reference = draw(handles[1], unit, InstanceLayout::Compact)?;
+    let skewed = draw(handles[2], unit, InstanceLayout::Compact)?;
+    anyhow::ensure!(
+        drawn_pixels(&reference, 0..TARGET_SIZE.0) > 0,
+        "the reference sphere isn't in view"
+    );
+
+    let (full, compact) = (
+        mean_delta(&instanced, &reference),
+        mean_delta(&skewed, &reference),
+    );
+    anyhow::ensure!(
+        full < 0.5,
+        "the stretched instance is off the stretched mesh by {:.3}",
+        full
+    );
+    anyhow::ensure!(
+        compact > (4.0 * full).max(0.5),
+        "skewed normals are only off by {:.3}, against {:.3} for the full layout",
+        compact,
+        full
+    );
+    Ok(())
+}
+
+/// How many pixels in the `x` range of `image` differ from its pixel at
+/// (`x.start`, 0), which the scenes leave as the clear color.
+fn drawn_pixels(image: &image::RgbaImage, x: std::ops::Range<u32>) -> usize {
+    let clear = *image.get_pixel(x.start, 0);
+    x.flat_map(|x| (0..image.height()).map(move |y| (x, y)))
+        .filter(|&(x, y)| *image.get_pixel(x, y) != clear)
+        .count()
+}
+
+/// The mean difference per channel between two renders, out of 255.
+fn mean_delta(a: &image::RgbaImage, b: &image::RgbaImage) -> f64 {
+    let total = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(a, b)| a.abs_diff(*b) as u64)
+        .sum::<u64>();
+    total as f64 / a.as_raw().len().max(1) as f64
+}
+
+/// Records readbacks with `record`, submits them and waits for what they
+/// hand the callback `record` is given.
+fn read_back<T: Send + 'static>(
+    context: &HeadlessContext,
+    record: impl FnOnce(
+        &mut gpu::ReadbackQueue,
+        &mut wgpu::CommandEncoder,
+        Box<dyn FnOnce(T) + Send>,
+    ) -> Result<(), gpu::ReadbackError>,
+) -> anyhow::Result<T> {
+    let mut readbacks = gpu::ReadbackQueue::new(1);
+    let mut encoder = context.device.create_command_encoder(&Default::default());
+    let slot = Arc::new(Mutex::new(None));
+    let on_read = slot.clone();
+    record(
+        &mut readbacks,
+        &mut encoder,
+        Box::new(move |value: T| *on_read.lock().unwrap() = Some(value)),
+    )?;
+    context.queue.submit(Some(encoder.finish()));
+    readbacks.after_submit();
+    readbacks.flush(&context.device);
+    let value = slot.lock().unwrap().take();
+    value.ok_or_else(|| anyhow::anyhow!("The readbacks didn't arrive"))
+}
+
+async fn run_scene(context: &HeadlessContext, scene: Scene) -> anyhow::Result<()> {
+    let format = match scene {
+        Scene::PostChain => wgpu::TextureFormat::Rgba16Float,
+        _ => wgpu::TextureFormat::Rgba8UnormSrgb,
+    };
+    let mut fixture = Fixture::new(&context.device, format)?;
+    match scene {
+        Scene::ObjModel => {
+            let model = fixture.load_obj(context).await?;
+            draw_one(context, &mut fixture, model)?;
+        }
+        Scene::GltfModel => {
+            let obj = fixture.load_obj(context).await?;
+            let scene = read_back(context, |readbacks, encoder, on_read| {
+                export::ExportScene::read_model(readbacks, &context.device, encoder, &obj, on_read)
+            })??;
+            let path = std::env::temp_dir().join(format!(
+                "validation-{:?}.glb",
+                context.adapter.get_info().backend
+            ));
+            export::to_gltf(&scene, &path)?;
+            let gltf = resources::load_gltf(
+                &path.to_string_lossy(),
+                &context.device,
+                &context.queue,
+                &fixture.texture_bind_group_layout,
+            )
+            .await;
+            let _ = std::fs::remove_file(&path);
+            let gltf = gltf?;
+            let model = Model {
+                meshes: gltf.meshes,
+                materials: gltf.materials,
+                vertex_precision: model::VertexPrecision::Full,
+                unit_scale: UnitScale(1.0),
+            };
+            draw_one(context, &mut fixture, model)?;
+        }
+        Scene::Instancing => {
+            let model = fixture.load_obj(context).await?;
+            let draws = (0..100)
+                .map(|i| at((i % 10) as f32 * 3.0 - 15.0, (i / 10) as f32 * 3.0 - 15.0))
+                .collect::<Vec<_>>();
+            let mut models = Assets::new();
+            let frame = frame_with(models.insert(model), draws);
+            fixture.draw(context, &frame, &models)?;
+            check_frame_arena(&frame, &models)?;
+        }
+        Scene::ShadowPass => check_shadow_pass(context, &fixture).await?,
+        Scene::UniformRing => check_uniform_ring(context, &mut fixture)?,
+        Scene::Readback => check_readback(context)?,
+        Scene::Reflection => check_reflection(context)?,
+        Scene::ResizeStorm => check_resize_storm(context)?,
+        Scene::SoftParticles => check_soft_particles(context, &mut fixture).await?,
+        Scene::Units => check_units(context, &fixture).await?,
+        Scene::AsyncPipelines => check_async_pipelines(context)?,
+        Scene::Refraction => check_refraction(context, &mut fixture).await?,
+        Scene::UiCompositing => check_ui_compositing(context, &fixture)?,
+        Scene::PointCloud => check_point_cloud(context, &fixture).await?,
+        Scene::Triplanar => check_triplanar(context, &mut fixture).await?,
+        Scene::Mipmaps => check_mipmaps(context)?,
+        Scene::InstanceLayouts => check_instance_layouts(context, &mut fixture).await?,
+        Scene::ModelReport => {
+            let model = fixture.load_obj(context).await?;
+            check_model_report(&model.report())?;
+        }
+        Scene::AssetPipeline => {
+            let model = check_asset_pipeline(context, &fixture).await?;
+            draw_one(context, &mut fixture, model)?;
+        }
+        Scene::OutOfMemory => {
+            let result = check_out_of_memory(context);
+            gpu::memory::set_budget(None);
+            result?;
+        }
+        Scene::Layers => {
+            let mut names = LayerNames::new();
+            names.set(1, "gizmos");
+            let gizmos = names.get("gizmos").unwrap();
+            let game = Layers::ALL.without(gizmos);
+
+            let model = fixture.load_obj(context).await?;
+            let mut models = Assets::new();
+            let half = TARGET_SIZE.0 / 2;
+            let (view_proj, eye) = origin_camera(half as f32 / TARGET_SIZE.1 as f32);
+            let mut frame = RenderFrame::new();
+            for (x, mask) in [(0, Layers::ALL), (half, game)] {
+                let viewport = Viewport::new(x as f32, 0.0, half as f32, TARGET_SIZE.1 as f32);
+                frame.push_masked_camera(view_proj, eye, viewport, mask);
+            }
+            frame.push_model_on(
+                models.insert(model),
+                at(0.0, 0.0),
+                MaterialOverrides::default(),
+                DrawFlags::empty(),
+                gizmos,
+            );
+            fixture.draw(context, &frame, &models)?;
+
+            let render = export::read_texture(&context.device, &context.queue, &fixture.color)?
+                .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))?;
+            let (shown, hidden) = (
+                drawn_pixels(&render, 0..half),
+                drawn_pixels(&render, half..TARGET_SIZE.0),
+            );
+            anyhow::ensure!(shown > 0, "The gizmo layer isn't drawn where it should be");
+            anyhow::ensure!(
+                hidden == 0,
+                "{} pixels of the gizmo layer were drawn in the game viewport",
+                hidden
+            );
+        }
+        Scene::FastShading => {
+            let model = fixture.load_obj(context).await?;
+            let mut models = Assets::new();
+            let frame = frame_with(models.insert(model), vec![at(0.0, 0.0)]);
+            let mut renders = Vec::new();
+            for shading in [ShadingTier::Full, ShadingTier::Fast] {
+                fixture.set_shading(shading);
+                fixture.draw(context, &frame, &models)?;
+                let render = export::read_texture(&context.device, &context.queue, &fixture.color)?
+                    .ok_or_else(|| anyhow::anyhow!("Couldn't read back the target"))?;
+                renders.push(render);
+            }
+            let delta = mean_delta(&renders[0], &renders[1]);
+            log::info!("Fast shading differs by {:.3} per channel", delta);
+            anyhow::ensure!(
+                delta <= MAX_SHADING_DELTA,
+                "Fast shading differs by {:.3} per channel, over {}",
+                delta,
+                MAX_SHADING_DELTA
+            );
+        }
+        Scene::PostChain => {
+            let device = &context.device;
+            let model = fixture.load_obj(context).await?;
+            let mut models = Assets::new();
+            let frame = frame_with(models.insert(model), vec![at(0.0, 0.0)]);
+            let surface_format = wgpu::TextureFormat::Rgba8UnormSrgb;
+            let tonemap =
+                tonemap::Tonemap::new(device, surface_format, gpu::OutputMode::Sdr, TARGET_SIZE)?;
+            fixture.draw_into(context, &frame, &models, tonemap.view())?;
+
+            // The readback is what WebGL2 meters with, and works anywhere.
+            let settings = exposure::ExposureSettings::default();
+            let mut meters = vec![exposure::Meter::Readback(exposure::ReadbackMeter::new(
+                device, &settings,
+            )?)];
+            if let Some(meter) =
+                exposure::ExposureMeter::try_new(&context.adapter, device, &settings)?
+            {
+                meters.push(exposure::Meter::Compute(meter));
+            }
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Validation Post Encoder"),
+            });
+            for meter in &mut meters {
+                meter.set_source(device, tonemap.view(), TARGET_SIZE)?;
+                meter.meter(device, &mut encoder);
+            }
+            context.queue.submit(Some(encoder.finish()));
+            for meter in &mut meters {
+                meter.after_submit();
+            }
+            device.poll(wgpu::Maintain::Wait);
+            let mut histograms = Vec::new();
+            for meter in &mut meters {
+                let histogram = meter
+                    .poll(device)
+                    .ok_or_else(|| anyhow::anyhow!("A histogram didn't arrive"))?;
+                histograms.push(histogram);
+            }
+            let averages = histograms
+                .iter()
+                .map(|histogram| {
+                    histogram
+                        .average_log_luminance(settings.ignore_darkest, settings.ignore_brightest)
+                        .ok_or_else(|| anyhow::anyhow!("Nothing was metered"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            log::info!("Metered log2 luminances {:?}", averages);
+            // Each texel read back averages a tile, so they only roughly agree.
+            if let [readback, compute] = averages[..] {
+                anyhow::ensure!(
+                    (readback - compute).abs() < 1.0,
+                    "The readback metered {}, compute {}",
+                    readback,
+                    compute
+                );
+            }
+
+            let mut auto = exposure::AutoExposure::new(settings);
+            tonemap.set_exposure(&context.queue, auto.update(&histograms[0], 0.0));
+            let (target, _memory) = gpu::Allocator::new(device).create_texture(
+                &wgpu::TextureDescriptor {
+                    label: Some("Validation Tonemapped"),
+                    size: wgpu::Extent3d {
+                        width: TARGET_SIZE.0,
+                        height: TARGET_SIZE.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: surface_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                },
+                gpu::MemoryCategory::Target,
+            )?;
+            let mut encoder = device.create_command_encoder(&Default::default());
+            tonemap.draw(
+                &mut encoder,
+                &target.create_view(&wgpu::TextureViewDescriptor::default()),
+            );
+            context.queue.submit(Some(encoder.finish()));
+            let render = export::read_texture(device, &context.queue, &target)?
+                .ok_or_else(|| anyhow::anyhow!("Couldn't read back the tonemapped target"))?;
+            let mean = render
+                .pixels()
+                .map(|pixel| pixel.0[..3].iter().map(|&c| c as u64).sum::<u64>())
+                .sum::<u64>() as f64
+                / (3 * render.width() * render.height()) as f64;
+            anyhow::ensure!(
+                (20.0..200.0).contains(&mean),
+                "The exposed scene averages {:.1} of 255",
+                mean
+            );
+        }
+        Scene::RegionUpdates => check_region_updates(context)?,
+        Scene::PassHooks => check_pass_hooks(context, &mut fixture)?,
+    }
+    Ok(())
+}
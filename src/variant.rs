@@ -0,0 +1,895 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::bail;
+
+use crate::{model::VertexPrecision, skinning::SkinningMethod, stats, Instance};
+
+/// The optional inputs a material has, which pick the shader variant it is
+/// drawn with.
+///
+/// All variants share one bind group layout: maps a material doesn't have
+/// are bound to a 1x1 default texture, so switching variants never needs a
+/// different pipeline layout, and the shader simply doesn't sample them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MaterialFeatures(u32);
+
+impl MaterialFeatures {
+    pub const DIFFUSE_MAP: Self = Self(1 << 0);
+    pub const NORMAL_MAP: Self = Self(1 << 1);
+    pub const EMISSIVE_MAP: Self = Self(1 << 2);
+    pub const OCCLUSION_MAP: Self = Self(1 << 3);
+    pub const SECOND_UV: Self = Self(1 << 4);
+    /// The diffuse map is an [`IndexedTexture`](crate::texture::IndexedTexture).
+    pub const INDEXED_COLOR: Self = Self(1 << 5);
+    /// Alpha masked, as glTF's `MASK` alpha mode: fragments whose diffuse
+    /// alpha is under the shader's `ALPHA_CUTOFF` are cut out.
+    pub const ALPHA_MASK: Self = Self(1 << 6);
+    /// The diffuse map is projected along the three axes rather than read
+    /// at the texture coordinates, see [`crate::triplanar`]. Unlike the
+    /// other features, this one changes group 0's layout: it adds the
+    /// projection's parameters.
+    pub const TRIPLANAR: Self = Self(1 << 7);
+
+    /// Each feature with the name the shaders test for.
+    const DEFINES: [(Self, &'static str); 8] = [
+        (Self::DIFFUSE_MAP, "HAS_DIFFUSE_MAP"),
+        (Self::NORMAL_MAP, "HAS_NORMAL_MAP"),
+        (Self::EMISSIVE_MAP, "HAS_EMISSIVE_MAP"),
+        (Self::OCCLUSION_MAP, "HAS_OCCLUSION_MAP"),
+        (Self::SECOND_UV, "HAS_SECOND_UV"),
+        (Self::INDEXED_COLOR, "HAS_INDEXED_COLOR"),
+        (Self::ALPHA_MASK, "HAS_ALPHA_MASK"),
+        (Self::TRIPLANAR, "HAS_TRIPLANAR"),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// The preprocessor defines for the features that are set.
+    pub fn defines(self) -> impl Iterator<Item = &'static str> {
+        Self::DEFINES
+            .into_iter()
+            .filter(move |(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name)
+    }
+}
+
+impl std::ops::BitOr for MaterialFeatures {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Where the model shader works out lighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShadingTier {
+    /// Per pixel, from the interpolated normal.
+    #[default]
+    Full,
+    /// Per vertex (Gouraud), for devices bound by fill rate such as old
+    /// phones on WebGL2. The fragment stage only samples the diffuse map and
+    /// multiplies it by the interpolated light.
+    ///
+    /// Maps this tier doesn't sample are dropped from its [`PipelineKey`],
+    /// so such materials share the plain variant: normal maps fall back to
+    /// the vertex normals, emissive maps are left out, so those parts are
+    /// only as bright as the light makes them, and occlusion maps are left
+    /// out too. Alpha masking and triplanar mapping work as in the full
+    /// tier.
+    Fast,
+}
+
+impl ShadingTier {
+    /// The features [`ShadingTier::Fast`] ignores, see there.
+    const FAST_UNSUPPORTED: MaterialFeatures = MaterialFeatures(
+        MaterialFeatures::NORMAL_MAP.0
+            | MaterialFeatures::EMISSIVE_MAP.0
+            | MaterialFeatures::OCCLUSION_MAP.0
+            | MaterialFeatures::SECOND_UV.0,
+    );
+
+    /// The tier for a device with `downlevel` capabilities. Devices without
+    /// compute shaders, i.e. WebGL2 and GLES 3.0 class hardware, get the
+    /// fast tier.
+    pub fn for_downlevel(downlevel: &wgpu::DownlevelCapabilities) -> Self {
+        if downlevel
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            ShadingTier::Full
+        } else {
+            ShadingTier::Fast
+        }
+    }
+
+    /// `features` less those the tier doesn't draw.
+    pub fn supported_features(self, mut features: MaterialFeatures) -> MaterialFeatures {
+        if self == ShadingTier::Fast {
+            features.remove(Self::FAST_UNSUPPORTED);
+        }
+        features
+    }
+
+    pub(crate) fn define(self) -> Option<&'static str> {
+        match self {
+            ShadingTier::Full => None,
+            ShadingTier::Fast => Some("SHADING_FAST"),
+        }
+    }
+}
+
+/// Renderer choices that depend on the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderSettings {
+    /// `None` picks the tier by the adapter's capabilities, see
+    /// [`ShadingTier::for_downlevel`].
+    pub shading_tier: Option<ShadingTier>,
+}
+
+impl RenderSettings {
+    /// The tier to draw with on `adapter`.
+    pub fn shading_tier(&self, adapter: &wgpu::Adapter) -> ShadingTier {
+        self.shading_tier
+            .unwrap_or_else(|| ShadingTier::for_downlevel(&adapter.get_downlevel_capabilities()))
+    }
+}
+
+/// How instance transforms reach the vertex shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum InstanceLayout {
+    /// The model matrix alone, whose upper 3x3 also turns normals. That is
+    /// only right when every instance is scaled the same along each axis,
+    /// and saves fetching 36 bytes per instance.
+    #[default]
+    Compact,
+    /// The model matrix and a normal matrix, for sets with any instance
+    /// scaled non-uniformly.
+    Full,
+}
+
+impl InstanceLayout {
+    /// The layout `instances` need: compact unless one of them is scaled
+    /// non-uniformly.
+    pub fn for_instances<'a>(instances: impl IntoIterator<Item = &'a Instance>) -> Self {
+        if instances
+            .into_iter()
+            .all(|instance| instance.is_uniformly_scaled())
+        {
+            InstanceLayout::Compact
+        } else {
+            InstanceLayout::Full
+        }
+    }
+
+    pub(crate) fn define(self) -> Option<&'static str> {
+        match self {
+            InstanceLayout::Compact => None,
+            InstanceLayout::Full => Some("FULL_INSTANCES"),
+        }
+    }
+}
+
+/// Which pass a pipeline renders in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PassType {
+    Forward,
+    DepthOnly,
+}
+
+impl PassType {
+    fn define(self) -> &'static str {
+        match self {
+            PassType::Forward => "PASS_FORWARD",
+            PassType::DepthOnly => "PASS_DEPTH_ONLY",
+        }
+    }
+}
+
+/// Everything a pipeline variant depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub vertex_precision: VertexPrecision,
+    pub features: MaterialFeatures,
+    pub pass: PassType,
+    /// Masked edges become MSAA coverage instead of being discarded.
+    pub alpha_to_coverage: bool,
+    /// Set for skinned meshes, to the method from
+    /// [`SkinningMethod::resolve`] for their skeleton.
+    pub skinning: Option<SkinningMethod>,
+    pub shading: ShadingTier,
+    pub instance_layout: InstanceLayout,
+}
+
+impl PipelineKey {
+    /// The key for drawing a material with `features` into a target with
+    /// `sample_count` samples. Alpha to coverage is used for masked
+    /// materials whenever there is more than one sample, since it only
+    /// softens edges with MSAA.
+    pub fn new(
+        vertex_precision: VertexPrecision,
+        features: MaterialFeatures,
+        pass: PassType,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            vertex_precision,
+            features,
+            pass,
+            alpha_to_coverage: features.contains(MaterialFeatures::ALPHA_MASK)
+                && pass == PassType::Forward
+                && sample_count > 1,
+            skinning: None,
+            shading: ShadingTier::Full,
+            instance_layout: InstanceLayout::Compact,
+        }
+    }
+
+    pub fn with_skinning(self, skinning: SkinningMethod) -> Self {
+        Self {
+            skinning: Some(skinning),
+            ..self
+        }
+    }
+
+    /// The key for `shading`, with the features it doesn't draw removed.
+    pub fn with_shading(self, shading: ShadingTier) -> Self {
+        Self {
+            features: shading.supported_features(self.features),
+            shading,
+            ..self
+        }
+    }
+
+    pub fn with_instance_layout(self, instance_layout: InstanceLayout) -> Self {
+        Self {
+            instance_layout,
+            ..self
+        }
+    }
+
+    pub fn multisample_state(&self, sample_count: u32) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: self.alpha_to_coverage,
+        }
+    }
+
+    /// The `//!define` lines for this variant.
+    pub fn defines(&self) -> Vec<&'static str> {
+        let mut defines = self.features.defines().collect::<Vec<_>>();
+        if self.vertex_precision == VertexPrecision::Compressed {
+            defines.push("COMPRESSED_VERTICES");
+        }
+        if self.alpha_to_coverage {
+            defines.push("ALPHA_TO_COVERAGE");
+        }
+        if let Some(skinning) = self.skinning {
+            defines.push(skinning.define());
+        }
+        defines.extend(self.instance_layout.define());
+        defines.extend(self.shading.define());
+        defines.push(self.pass.define());
+        defines
+    }
+}
+
+/// Expands the conditional directives in `source` for the given defines.
+///
+/// Directives are written as comments so the unprocessed source still reads
+/// as WGSL: `//!define NAME`, `//!ifdef NAME`, `//!ifndef NAME`, `//!else`
+/// and `//!endif`, and may be nested. The defines are emitted as
+/// `//!define` lines at the top of the output so the variant is visible
+/// when debugging shader errors.
+pub fn preprocess(source: &str, defines: &[&str]) -> anyhow::Result<String> {
+    let mut defined = defines.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+    let mut output = String::with_capacity(source.len());
+    for define in defines {
+        output.push_str(&format!("//!define {}\n", define));
+    }
+
+    // For each open block, whether it is active and whether its parent is.
+    let mut stack: Vec<(bool, bool)> = Vec::new();
+    let active = |stack: &[(bool, bool)]| stack.last().is_none_or(|(active, _)| *active);
+    for (number, line) in source.lines().enumerate() {
+        let directive = line.trim_start().strip_prefix("//!");
+        let Some(directive) = directive else {
+            if active(&stack) {
+                output.push_str(line);
+                output.push('\n');
+            }
+            continue;
+        };
+        let mut words = directive.split_whitespace();
+        let (keyword, name) = (words.next().unwrap_or(""), words.next());
+        let parent = active(&stack);
+        match (keyword, name) {
+            ("define", Some(name)) => {
+                if parent {
+                    defined.push(name.to_string());
+                }
+            }
+            ("ifdef", Some(name)) => {
+                let set = defined.iter().any(|d| d == name);
+                stack.push((parent && set, parent));
+            }
+            ("ifndef", Some(name)) => {
+                let set = defined.iter().any(|d| d == name);
+                stack.push((parent && !set, parent));
+            }
+            ("else", None) => match stack.last_mut() {
+                Some((active, parent)) => *active = *parent && !*active,
+                None => bail!("line {}: //!else without //!ifdef", number + 1),
+            },
+            ("endif", None) => {
+                if stack.pop().is_none() {
+                    bail!("line {}: //!endif without //!ifdef", number + 1);
+                }
+            }
+            _ => bail!("line {}: unknown directive {:?}", number + 1, line.trim()),
+        }
+    }
+    if !stack.is_empty() {
+        bail!("{} unterminated //!ifdef blocks", stack.len());
+    }
+    Ok(output)
+}
+
+/// A variant from [`PipelineCache::get_or_spawn`].
+#[derive(Debug, Clone)]
+pub enum PipelineState {
+    Ready(Arc<wgpu::RenderPipeline>),
+    /// Being built in the background. Draw with
+    /// [`PipelineCache::fallback`] meanwhile.
+    Building,
+}
+
+/// What builds a variant's pipeline from its preprocessed shader module. On
+/// native, builds run on their own thread, so the closure must be `Send`.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait BuildPipeline:
+    FnOnce(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline + Send + 'static
+{
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl<F> BuildPipeline for F where
+    F: FnOnce(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline + Send + 'static
+{
+}
+
+/// What builds a variant's pipeline from its preprocessed shader module.
+#[cfg(target_arch = "wasm32")]
+pub trait BuildPipeline:
+    FnOnce(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline + 'static
+{
+}
+#[cfg(target_arch = "wasm32")]
+impl<F> BuildPipeline for F where
+    F: FnOnce(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline + 'static
+{
+}
+
+/// A variant being built in the background.
+struct Build {
+    /// Polls since the build started.
+    frames: u32,
+    #[cfg(not(target_arch = "wasm32"))]
+    result: mpsc::Receiver<wgpu::RenderPipeline>,
+    /// Run by [`PipelineCache::poll`], as the web has no threads.
+    #[cfg(target_arch = "wasm32")]
+    job: Option<Box<dyn FnOnce() -> wgpu::RenderPipeline>>,
+}
+
+impl Build {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn(
+        key: PipelineKey,
+        job: impl FnOnce() -> wgpu::RenderPipeline + Send + 'static,
+    ) -> Self {
+        let (sender, result) = mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name(format!("pipeline {:?}", key))
+            .spawn(move || {
+                sender.send(job()).ok();
+            });
+        if let Err(e) = spawned {
+            log::error!("Couldn't start building {:?}: {}", key, e);
+        }
+        Self { frames: 0, result }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn(_key: PipelineKey, job: impl FnOnce() -> wgpu::RenderPipeline + 'static) -> Self {
+        Self {
+            frames: 0,
+            job: Some(Box::new(job)),
+        }
+    }
+
+    /// The pipeline if the build is done, or after waiting for it if
+    /// `wait`. `Some(None)` if the build panicked.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn take(&mut self, wait: bool) -> Option<Option<wgpu::RenderPipeline>> {
+        if wait {
+            return Some(self.result.recv().ok());
+        }
+        match self.result.try_recv() {
+            Ok(pipeline) => Some(Some(pipeline)),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(None),
+        }
+    }
+
+    /// Runs the build if `wait`.
+    #[cfg(target_arch = "wasm32")]
+    fn take(&mut self, wait: bool) -> Option<Option<wgpu::RenderPipeline>> {
+        wait.then(|| self.job.take().map(|job| job()))
+    }
+}
+
+/// Render pipelines by variant, so only variants that are drawn get
+/// compiled.
+///
+/// Compiling a pipeline can stall a frame for tens of milliseconds on some
+/// drivers, so [`Self::get_or_spawn`] builds variants in the background
+/// while the draw path uses [`Self::fallback`]. Call [`Self::poll`] once a
+/// frame to pick up finished builds.
+pub struct PipelineCache {
+    source: String,
+    pipelines: HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>,
+    building: HashMap<PipelineKey, Build>,
+    /// Variants whose build panicked, which aren't tried again.
+    failed: HashSet<PipelineKey>,
+    max_fallback_frames: u32,
+}
+
+impl PipelineCache {
+    /// How many polls a variant may take to build before [`Self::poll`]
+    /// waits for it, so a fallback is only drawn for a few frames.
+    pub const DEFAULT_MAX_FALLBACK_FRAMES: u32 = 4;
+
+    /// `source` is the shader with the directives [`preprocess`] expands.
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            pipelines: HashMap::new(),
+            building: HashMap::new(),
+            failed: HashSet::new(),
+            max_fallback_frames: Self::DEFAULT_MAX_FALLBACK_FRAMES,
+        }
+    }
+
+    pub fn set_max_fallback_frames(&mut self, frames: u32) {
+        self.max_fallback_frames = frames;
+    }
+
+    /// The number of ready variants.
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// The number of variants still building, e.g. for a loading screen to
+    /// wait on after [`Self::prewarm_in_background`].
+    pub fn building(&self) -> usize {
+        self.building.len()
+    }
+
+    pub fn get(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(key).map(|p| &**p)
+    }
+
+    /// Returns the pipeline for `key`, calling `create` with the
+    /// preprocessed shader module to build it the first time. Waits for the
+    /// variant if it is building in the background.
+    pub fn get_or_create<F>(
+        &mut self,
+        device: &wgpu::Device,
+        key: PipelineKey,
+        create: F,
+    ) -> anyhow::Result<&wgpu::RenderPipeline>
+    where
+        F: FnOnce(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline,
+    {
+        if let Some(mut build) = self.building.remove(&key) {
+            let pipeline = build.take(true).flatten();
+            self.finish(key, pipeline, true);
+        }
+        if !self.pipelines.contains_key(&key) {
+            let shader = self.shader_module(device, &key)?;
+            log::info!("Compiled pipeline variant {:?}", key);
+            self.pipelines.insert(key, Arc::new(create(&key, &shader)));
+        }
+        Ok(&self.pipelines[&key])
+    }
+
+    /// Returns the pipeline for `key` if it is ready, or starts building it
+    /// in the background with `create` and returns
+    /// [`PipelineState::Building`]. Fails if the shader doesn't preprocess
+    /// or an earlier build of the variant panicked.
+    pub fn get_or_spawn<F>(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        key: PipelineKey,
+        create: F,
+    ) -> anyhow::Result<PipelineState>
+    where
+        F: BuildPipeline,
+    {
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(PipelineState::Ready(pipeline.clone()));
+        }
+        if self.failed.contains(&key) {
+            bail!("building pipeline variant {:?} failed", key);
+        }
+        if !self.building.contains_key(&key) {
+            let source = preprocess(&self.source, &key.defines())?;
+            let device = device.clone();
+            let build = Build::spawn(key, move || {
+                let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(&format!("{:?}", key)),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                create(&key, &shader)
+            });
+            log::info!("Building pipeline variant {:?} in the background", key);
+            self.building.insert(key, build);
+        }
+        Ok(PipelineState::Building)
+    }
+
+    /// The ready variant closest to `key`, to draw with while it builds: one
+    /// with the same vertex layout, pass, skinning, tier and group 0 layout,
+    /// and as many of its features as possible without any it lacks. `None` if there is
+    /// none, for the caller to draw with its plain pipeline or skip the
+    /// draw.
+    pub fn fallback(&self, key: &PipelineKey) -> Option<Arc<wgpu::RenderPipeline>> {
+        self.pipelines
+            .iter()
+            .filter(|(ready, _)| {
+                ready.vertex_precision == key.vertex_precision
+                    && ready.pass == key.pass
+                    && ready.skinning == key.skinning
+                    && ready.shading == key.shading
+                    && ready.features.contains(MaterialFeatures::TRIPLANAR)
+                        == key.features.contains(MaterialFeatures::TRIPLANAR)
+                    && key.features.contains(ready.features)
+            })
+            .max_by_key(|(ready, _)| {
+                (
+                    ready.features.bits().count_ones(),
+                    ready.alpha_to_coverage == key.alpha_to_coverage,
+                    // Ties go to the same variant every time.
+                    std::cmp::Reverse(ready.features.bits()),
+                )
+            })
+            .map(|(_, pipeline)| pipeline.clone())
+    }
+
+    /// Picks up finished background builds, to call once a frame. Builds
+    /// that have taken more polls than allowed by
+    /// [`Self::set_max_fallback_frames`] are waited for.
+    ///
+    /// On the web, where there are no threads, this builds one waiting
+    /// variant per call instead, so call it when a frame has time to spare.
+    /// Returns the variants that became ready.
+    pub fn poll(&mut self) -> Vec<PipelineKey> {
+        let mut finished = Vec::new();
+        #[cfg(target_arch = "wasm32")]
+        let mut idle = true;
+        for (key, build) in &mut self.building {
+            build.frames += 1;
+            let overdue = build.frames > self.max_fallback_frames;
+            #[cfg(target_arch = "wasm32")]
+            let overdue = overdue || std::mem::take(&mut idle);
+            if let Some(pipeline) = build.take(overdue) {
+                finished.push((*key, pipeline, overdue));
+            }
+        }
+        finished
+            .into_iter()
+            .filter_map(|(key, pipeline, stalled)| {
+                self.building.remove(&key);
+                self.finish(key, pipeline, stalled).then_some(key)
+            })
+            .collect()
+    }
+
+    /// Stores a finished build's pipeline, or marks the variant failed
+    /// without one. `stalled` notes the build as a [`stats::FrameEvent`],
+    /// as the frame waited on it.
+    fn finish(
+        &mut self,
+        key: PipelineKey,
+        pipeline: Option<wgpu::RenderPipeline>,
+        stalled: bool,
+    ) -> bool {
+        let Some(pipeline) = pipeline else {
+            log::error!("Building pipeline variant {:?} panicked", key);
+            self.failed.insert(key);
+            return false;
+        };
+        if stalled {
+            stats::note_event(stats::FrameEvent::PipelineBuilt(format!("{:?}", key)));
+        }
+        log::info!("Compiled pipeline variant {:?}", key);
+        self.pipelines.insert(key, Arc::new(pipeline));
+        true
+    }
+
+    fn shader_module(
+        &self,
+        device: &wgpu::Device,
+        key: &PipelineKey,
+    ) -> anyhow::Result<wgpu::ShaderModule> {
+        let source = preprocess(&self.source, &key.defines())?;
+        Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&format!("{:?}", key)),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }))
+    }
+
+    /// Builds the given variants up front, e.g. at startup, so drawing them
+    /// for the first time doesn't stall.
+    pub fn prewarm<F>(
+        &mut self,
+        device: &wgpu::Device,
+        keys: &[PipelineKey],
+        mut create: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&PipelineKey, &wgpu::ShaderModule) -> wgpu::RenderPipeline,
+    {
+        for key in keys {
+            self.get_or_create(device, *key, &mut create)?;
+        }
+        Ok(())
+    }
+
+    /// Starts building the given variants in the background, e.g. while a
+    /// loading screen shows, which can wait for [`Self::building`] to reach
+    /// zero while calling [`Self::poll`].
+    pub fn prewarm_in_background<F>(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        keys: &[PipelineKey],
+        create: F,
+    ) -> anyhow::Result<()>
+    where
+        F: BuildPipeline + Clone,
+    {
+        for key in keys {
+            self.get_or_spawn(device, *key, create.clone())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+a
+//!ifdef X
+x
+//!ifndef Y
+x and not y
+//!define Z
+//!else
+x and y
+//!endif
+//!else
+not x
+//!endif
+//!ifdef Z
+z
+//!endif
+";
+
+    #[test]
+    fn directives_keep_the_active_branches() {
+        assert_eq!(
+            preprocess(SOURCE, &[]).unwrap(),
+            "a\nnot x\n",
+            "nothing defined"
+        );
+        assert_eq!(
+            preprocess(SOURCE, &["X"]).unwrap(),
+            "//!define X\na\nx\nx and not y\nz\n"
+        );
+        assert_eq!(
+            preprocess(SOURCE, &["X", "Y"]).unwrap(),
+            "//!define X\n//!define Y\na\nx\nx and y\n"
+        );
+        // Defines in inactive blocks don't count.
+        assert_eq!(
+            preprocess(
+                "//!ifdef X\n//!define Y\n//!endif\n//!ifdef Y\ny\n//!endif\n",
+                &[]
+            )
+            .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn unbalanced_or_unknown_directives_are_errors() {
+        for (source, error) in [
+            ("//!else\n", "line 1: //!else without //!ifdef"),
+            ("a\n//!endif\n", "line 2: //!endif without //!ifdef"),
+            (
+                "//!ifdef X\n//!ifdef Y\n//!endif\n",
+                "1 unterminated //!ifdef blocks",
+            ),
+            ("//!if X\n", "line 1: unknown directive \"//!if X\""),
+            ("//!ifdef\n", "line 1: unknown directive \"//!ifdef\""),
+        ] {
+            assert_eq!(preprocess(source, &[]).unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn keys_name_their_variant() {
+        let masked = MaterialFeatures::DIFFUSE_MAP | MaterialFeatures::ALPHA_MASK;
+        let key = PipelineKey::new(VertexPrecision::Compressed, masked, PassType::Forward, 4);
+        assert!(key.alpha_to_coverage);
+        assert!(key.multisample_state(4).alpha_to_coverage_enabled);
+        assert_eq!(
+            key.defines(),
+            [
+                "HAS_DIFFUSE_MAP",
+                "HAS_ALPHA_MASK",
+                "COMPRESSED_VERTICES",
+                "ALPHA_TO_COVERAGE",
+                "PASS_FORWARD"
+            ]
+        );
+
+        // Coverage needs samples, and the depth pass has no color to cover.
+        let single = PipelineKey::new(VertexPrecision::Full, masked, PassType::Forward, 1);
+        let depth = PipelineKey::new(VertexPrecision::Full, masked, PassType::DepthOnly, 4);
+        assert!(!single.alpha_to_coverage && !depth.alpha_to_coverage);
+
+        let skinned = single
+            .with_skinning(SkinningMethod::DualQuaternion)
+            .with_shading(ShadingTier::Fast)
+            .with_instance_layout(InstanceLayout::Full);
+        assert_eq!(
+            skinned.defines(),
+            [
+                "HAS_DIFFUSE_MAP",
+                "HAS_ALPHA_MASK",
+                "SKINNING_DUAL_QUATERNION",
+                "FULL_INSTANCES",
+                "SHADING_FAST",
+                "PASS_FORWARD"
+            ]
+        );
+    }
+
+    #[test]
+    fn alpha_to_coverage_replaces_the_discard() {
+        let masked = MaterialFeatures::DIFFUSE_MAP | MaterialFeatures::ALPHA_MASK;
+        let plain = PipelineKey::new(VertexPrecision::Full, masked, PassType::Forward, 1);
+        let a2c = PipelineKey::new(VertexPrecision::Full, masked, PassType::Forward, 4);
+        assert_ne!(plain, a2c, "coverage must be part of the cache key");
+        assert!(!plain.multisample_state(1).alpha_to_coverage_enabled);
+        let state = a2c.multisample_state(4);
+        assert_eq!((state.count, state.mask), (4, !0));
+        assert!(state.alpha_to_coverage_enabled);
+
+        // Only the fragment shader's own statements, so comments don't count.
+        let body = |key: &PipelineKey| {
+            let source = preprocess(include_str!("shader.wgsl"), &key.defines()).unwrap();
+            let start = source.find("fn fs_main").unwrap();
+            source[start..]
+                .lines()
+                .map(|line| line.split("//").next().unwrap())
+                .collect::<String>()
+        };
+        let (discarding, covering) = (body(&plain), body(&a2c));
+        assert!(discarding.contains("discard") && !discarding.contains("fwidth"));
+        assert!(covering.contains("fwidth") && !covering.contains("discard"));
+
+        // Unmasked materials don't cut anything out either way.
+        let opaque = PipelineKey::new(
+            VertexPrecision::Full,
+            MaterialFeatures::DIFFUSE_MAP,
+            PassType::Forward,
+            4,
+        );
+        assert!(!opaque.alpha_to_coverage);
+        assert!(!body(&opaque).contains("discard") && !body(&opaque).contains("fwidth"));
+    }
+
+    #[test]
+    fn the_fast_tier_drops_the_maps_it_doesnt_sample() {
+        let all = MaterialFeatures::DEFINES
+            .iter()
+            .fold(MaterialFeatures::empty(), |all, (feature, _)| {
+                all | *feature
+            });
+        let fast = ShadingTier::Fast.supported_features(all);
+        assert_eq!(
+            fast.defines().collect::<Vec<_>>(),
+            [
+                "HAS_DIFFUSE_MAP",
+                "HAS_INDEXED_COLOR",
+                "HAS_ALPHA_MASK",
+                "HAS_TRIPLANAR"
+            ]
+        );
+        assert_eq!(ShadingTier::Full.supported_features(all), all);
+    }
+
+    #[test]
+    fn every_model_shader_variant_validates() {
+        let source = include_str!("shader.wgsl");
+        let features = [
+            MaterialFeatures::DIFFUSE_MAP,
+            MaterialFeatures::ALPHA_MASK,
+            MaterialFeatures::TRIPLANAR,
+        ];
+        for bits in 0..1 << features.len() {
+            let features = features
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| bits & (1 << i) != 0)
+                .fold(MaterialFeatures::empty(), |all, (_, feature)| {
+                    all | *feature
+                });
+            for precision in [VertexPrecision::Full, VertexPrecision::Compressed] {
+                for samples in [1, 4] {
+                    for (shading, layout) in [ShadingTier::Full, ShadingTier::Fast]
+                        .into_iter()
+                        .flat_map(|shading| {
+                            [InstanceLayout::Compact, InstanceLayout::Full]
+                                .map(|layout| (shading, layout))
+                        })
+                    {
+                        let key = PipelineKey::new(precision, features, PassType::Forward, samples)
+                            .with_shading(shading)
+                            .with_instance_layout(layout);
+                        let wgsl = preprocess(source, &key.defines()).unwrap();
+                        let module = naga::front::wgsl::parse_str(&wgsl)
+                            .unwrap_or_else(|e| panic!("{:?}: {}", key, e.emit_to_string(&wgsl)));
+                        naga::valid::Validator::new(
+                            naga::valid::ValidationFlags::all(),
+                            naga::valid::Capabilities::empty(),
+                        )
+                        .validate(&module)
+                        .unwrap_or_else(|e| panic!("{:?}: {:?}", key, e));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::*,
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Window},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed,
+    /// A borderless window covering the monitor. Switching in and out is
+    /// quick and doesn't change the monitor's video mode.
+    Borderless,
+    /// Exclusive fullscreen at the monitor's largest video mode. Falls back to
+    /// [`DisplayMode::Borderless`] where that isn't available, e.g. on web.
+    Exclusive,
+}
+
+/// Where the window was before it went fullscreen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WindowedGeometry {
+    position: Option<PhysicalPosition<i32>>,
+    size: PhysicalSize<u32>,
+}
+
+/// What [`WindowController`] needs of a window to change its display mode,
+/// so mode changes can be exercised without one.
+pub trait DisplayWindow {
+    type Monitor;
+    type VideoMode;
+
+    fn outer_position(&self) -> Option<PhysicalPosition<i32>>;
+    fn inner_size(&self) -> PhysicalSize<u32>;
+    fn set_inner_size(&self, size: PhysicalSize<u32>);
+    fn set_outer_position(&self, position: PhysicalPosition<i32>);
+    fn current_monitor(&self) -> Option<Self::Monitor>;
+    fn primary_monitor(&self) -> Option<Self::Monitor>;
+    fn first_available_monitor(&self) -> Option<Self::Monitor>;
+    /// The largest exclusive video mode of `monitor`, if it has any that
+    /// can be used.
+    fn largest_video_mode(&self, monitor: &Self::Monitor) -> Option<Self::VideoMode>;
+    fn set_windowed(&self);
+    /// On `monitor`, or the one the window is on for `None`.
+    fn set_borderless(&self, monitor: Option<Self::Monitor>);
+    fn set_exclusive(&self, video_mode: Self::VideoMode);
+}
+
+impl DisplayWindow for Window {
+    type Monitor = MonitorHandle;
+    type VideoMode = VideoMode;
+
+    fn outer_position(&self) -> Option<PhysicalPosition<i32>> {
+        Window::outer_position(self).ok()
+    }
+
+    fn inner_size(&self) -> PhysicalSize<u32> {
+        Window::inner_size(self)
+    }
+
+    fn set_inner_size(&self, size: PhysicalSize<u32>) {
+        Window::set_inner_size(self, size);
+    }
+
+    fn set_outer_position(&self, position: PhysicalPosition<i32>) {
+        Window::set_outer_position(self, position);
+    }
+
+    fn current_monitor(&self) -> Option<MonitorHandle> {
+        Window::current_monitor(self)
+    }
+
+    fn primary_monitor(&self) -> Option<MonitorHandle> {
+        Window::primary_monitor(self)
+    }
+
+    fn first_available_monitor(&self) -> Option<MonitorHandle> {
+        self.available_monitors().next()
+    }
+
+    fn largest_video_mode(&self, monitor: &MonitorHandle) -> Option<VideoMode> {
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        monitor.video_modes().max_by_key(|mode| {
+            let size = mode.size();
+            (
+                size.width * size.height,
+                mode.bit_depth(),
+                mode.refresh_rate_millihertz(),
+            )
+        })
+    }
+
+    fn set_windowed(&self) {
+        self.set_fullscreen(None);
+    }
+
+    fn set_borderless(&self, monitor: Option<MonitorHandle>) {
+        self.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    }
+
+    fn set_exclusive(&self, video_mode: VideoMode) {
+        self.set_fullscreen(Some(Fullscreen::Exclusive(video_mode)));
+    }
+}
+
+/// Handles display mode changes and title updates for the application
+/// window. F11 toggles borderless fullscreen and Alt+Enter toggles exclusive
+/// fullscreen. Mode changes resize the window, so the surface is reconfigured
+/// by the usual `Resized` handling.
+pub struct WindowController {
+    title: String,
+    mode: DisplayMode,
+    windowed: Option<WindowedGeometry>,
+    modifiers: ModifiersState,
+}
+
+impl WindowController {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            mode: DisplayMode::Windowed,
+            windowed: None,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    /// Returns true if the event was a display mode shortcut.
+    pub fn process_events(&mut self, window: &impl DisplayWindow, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                let toggled = match keycode {
+                    VirtualKeyCode::F11 => DisplayMode::Borderless,
+                    VirtualKeyCode::Return if self.modifiers.alt() => DisplayMode::Exclusive,
+                    _ => return false,
+                };
+                let mode = if self.mode == DisplayMode::Windowed {
+                    toggled
+                } else {
+                    DisplayMode::Windowed
+                };
+                self.set_mode(window, mode);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Switches to `mode`, returning the mode actually used.
+    pub fn set_mode(&mut self, window: &impl DisplayWindow, mode: DisplayMode) -> DisplayMode {
+        if mode == self.mode {
+            return mode;
+        }
+        if self.mode == DisplayMode::Windowed {
+            self.windowed = Some(WindowedGeometry {
+                position: window.outer_position(),
+                size: window.inner_size(),
+            });
+        }
+
+        let mode = match mode {
+            DisplayMode::Windowed => {
+                window.set_windowed();
+                if let Some(windowed) = self.windowed.take() {
+                    window.set_inner_size(windowed.size);
+                    if let Some(position) = windowed.position {
+                        window.set_outer_position(position);
+                    }
+                }
+                DisplayMode::Windowed
+            }
+            DisplayMode::Borderless => {
+                window.set_borderless(window.current_monitor());
+                DisplayMode::Borderless
+            }
+            DisplayMode::Exclusive => {
+                let video_mode = fullscreen_monitor(window)
+                    .and_then(|monitor| window.largest_video_mode(&monitor));
+                match video_mode {
+                    Some(video_mode) => {
+                        window.set_exclusive(video_mode);
+                        DisplayMode::Exclusive
+                    }
+                    None => {
+                        log::warn!("Exclusive fullscreen isn't available, using borderless");
+                        window.set_borderless(window.current_monitor());
+                        DisplayMode::Borderless
+                    }
+                }
+            }
+        };
+        self.mode = mode;
+        mode
+    }
+
+    /// Sets the title to the application name followed by `info`, e.g. the
+    /// frame rate and the loaded file.
+    pub fn set_title_info(&self, window: &Window, info: &str) {
+        if info.is_empty() {
+            window.set_title(&self.title);
+        } else {
+            window.set_title(&format!("{} - {}", self.title, info));
+        }
+    }
+}
+
+/// The monitor to go fullscreen on. Monitors can be unplugged at any time, so
+/// this falls back from the window's monitor to the primary one to any.
+fn fullscreen_monitor<W: DisplayWindow>(window: &W) -> Option<W::Monitor> {
+    window
+        .current_monitor()
+        .or_else(|| window.primary_monitor())
+        .or_else(|| window.first_available_monitor())
+}
+
+/// Coalesces the resizes of a window being dragged, so targets that follow
+/// the surface size are rebuilt once rather than for every event.
+///
+/// The surface itself has to be reconfigured at every resize. Other targets
+/// are rebuilt once the size has stayed the same for the settle time, or as
+/// soon as a frame needs them at the new size. Zero sizes, e.g. when
+/// minimized, pause rendering instead.
+#[derive(Debug, Clone)]
+pub struct ResizeDebounce {
+    settle: f64,
+    /// The size targets were last built at.
+    built: (u32, u32),
+    /// The latest size, if targets haven't been built at it.
+    pending: Option<(u32, u32)>,
+    changed_at: f64,
+    paused: bool,
+    rebuilds: u64,
+}
+
+impl ResizeDebounce {
+    /// In seconds.
+    pub const DEFAULT_SETTLE: f64 = 0.15;
+
+    /// Starts with targets built at `size`, waiting `settle` seconds after
+    /// the last resize.
+    pub fn new(size: (u32, u32), settle: f64) -> Self {
+        Self {
+            settle,
+            built: size,
+            pending: None,
+            changed_at: 0.0,
+            paused: false,
+            rebuilds: 0,
+        }
+    }
+
+    /// Notes a resize at `now`, in seconds. Returns false for a zero size,
+    /// which pauses rendering until the next resize.
+    pub fn resize(&mut self, size: (u32, u32), now: f64) -> bool {
+        if size.0 == 0 || size.1 == 0 {
+            self.paused = true;
+            return false;
+        }
+        self.paused = false;
+        // Back at the built size, e.g. after restoring a minimized window,
+        // nothing needs rebuilding.
+        self.pending = Some(size).filter(|&s| s != self.built);
+        self.changed_at = now;
+        true
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The size to rebuild targets at, if it's time to. `needed` is whether
+    /// a frame is about to draw, which can't wait for the size to settle.
+    pub fn due(&self, now: f64, needed: bool) -> Option<(u32, u32)> {
+        if self.paused {
+            return None;
+        }
+        let size = self.pending?;
+        (needed || now - self.changed_at >= self.settle).then_some(size)
+    }
+
+    /// Notes that targets were rebuilt at `size`.
+    pub fn rebuilt(&mut self, size: (u32, u32)) {
+        self.built = size;
+        if self.pending == Some(size) {
+            self.pending = None;
+        }
+        self.rebuilds += 1;
+    }
+
+    /// Whether targets were built at `size`, so passes can draw into a
+    /// target of that size with them.
+    pub fn matches(&self, size: (u32, u32)) -> bool {
+        self.built == size
+    }
+
+    /// How many times targets were rebuilt.
+    pub fn rebuilds(&self) -> u64 {
+        self.rebuilds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Shown {
+        Windowed,
+        Borderless(Option<u32>),
+        /// On the monitor, at the size.
+        Exclusive(u32, u32),
+    }
+
+    /// A window on monitors identified by number, each with exclusive
+    /// video modes of the given widths.
+    struct MockWindow {
+        current: Option<u32>,
+        primary: Option<u32>,
+        monitors: Vec<Vec<u32>>,
+        shown: RefCell<Shown>,
+        size: RefCell<PhysicalSize<u32>>,
+        position: RefCell<PhysicalPosition<i32>>,
+    }
+
+    impl MockWindow {
+        fn new(monitors: Vec<Vec<u32>>) -> Self {
+            Self {
+                current: Some(0).filter(|_| !monitors.is_empty()),
+                primary: Some(0).filter(|_| !monitors.is_empty()),
+                monitors,
+                shown: RefCell::new(Shown::Windowed),
+                size: RefCell::new(PhysicalSize::new(800, 600)),
+                position: RefCell::new(PhysicalPosition::new(40, 30)),
+            }
+        }
+
+        fn shown(&self) -> Shown {
+            *self.shown.borrow()
+        }
+    }
+
+    impl DisplayWindow for MockWindow {
+        type Monitor = u32;
+        type VideoMode = (u32, u32);
+
+        fn outer_position(&self) -> Option<PhysicalPosition<i32>> {
+            Some(*self.position.borrow())
+        }
+
+        fn inner_size(&self) -> PhysicalSize<u32> {
+            *self.size.borrow()
+        }
+
+        fn set_inner_size(&self, size: PhysicalSize<u32>) {
+            *self.size.borrow_mut() = size;
+        }
+
+        fn set_outer_position(&self, position: PhysicalPosition<i32>) {
+            *self.position.borrow_mut() = position;
+        }
+
+        fn current_monitor(&self) -> Option<u32> {
+            self.current
+        }
+
+        fn primary_monitor(&self) -> Option<u32> {
+            self.primary
+        }
+
+        fn first_available_monitor(&self) -> Option<u32> {
+            (!self.monitors.is_empty()).then_some(0)
+        }
+
+        fn largest_video_mode(&self, monitor: &u32) -> Option<(u32, u32)> {
+            let widest = self.monitors[*monitor as usize].iter().max()?;
+            Some((*monitor, *widest))
+        }
+
+        fn set_windowed(&self) {
+            *self.shown.borrow_mut() = Shown::Windowed;
+        }
+
+        fn set_borderless(&self, monitor: Option<u32>) {
+            *self.shown.borrow_mut() = Shown::Borderless(monitor);
+            *self.size.borrow_mut() = PhysicalSize::new(1920, 1080);
+            *self.position.borrow_mut() = PhysicalPosition::new(0, 0);
+        }
+
+        fn set_exclusive(&self, (monitor, width): (u32, u32)) {
+            *self.shown.borrow_mut() = Shown::Exclusive(monitor, width);
+            *self.size.borrow_mut() = PhysicalSize::new(width, width * 9 / 16);
+            *self.position.borrow_mut() = PhysicalPosition::new(0, 0);
+        }
+    }
+
+    fn key(keycode: VirtualKeyCode) -> WindowEvent<'static> {
+        #[allow(deprecated)]
+        WindowEvent::KeyboardInput {
+            // Only compared against other ids, which the controller doesn't.
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state: ElementState::Pressed,
+                virtual_keycode: Some(keycode),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn shortcuts_toggle_and_restore_the_window() {
+        let window = MockWindow::new(vec![vec![1280, 2560, 1920]]);
+        let mut controller = WindowController::new("test");
+
+        assert!(controller.process_events(&window, &key(VirtualKeyCode::F11)));
+        assert_eq!(controller.mode(), DisplayMode::Borderless);
+        assert_eq!(window.shown(), Shown::Borderless(Some(0)));
+        assert!(controller.process_events(&window, &key(VirtualKeyCode::F11)));
+        assert_eq!(controller.mode(), DisplayMode::Windowed);
+        assert_eq!(window.shown(), Shown::Windowed);
+        assert_eq!(window.inner_size(), PhysicalSize::new(800, 600));
+        assert_eq!(window.outer_position(), Some(PhysicalPosition::new(40, 30)));
+
+        // Enter alone isn't a shortcut; with Alt it's exclusive fullscreen
+        // at the largest mode.
+        assert!(!controller.process_events(&window, &key(VirtualKeyCode::Return)));
+        controller.process_events(&window, &WindowEvent::ModifiersChanged(ModifiersState::ALT));
+        assert!(controller.process_events(&window, &key(VirtualKeyCode::Return)));
+        assert_eq!(controller.mode(), DisplayMode::Exclusive);
+        assert_eq!(window.shown(), Shown::Exclusive(0, 2560));
+
+        // Switching between fullscreen modes keeps the windowed geometry.
+        assert_eq!(
+            controller.set_mode(&window, DisplayMode::Borderless),
+            DisplayMode::Borderless
+        );
+        controller.set_mode(&window, DisplayMode::Windowed);
+        assert_eq!(window.shown(), Shown::Windowed);
+        assert_eq!(window.inner_size(), PhysicalSize::new(800, 600));
+        assert_eq!(window.outer_position(), Some(PhysicalPosition::new(40, 30)));
+
+        assert!(!controller.process_events(&window, &key(VirtualKeyCode::A)));
+    }
+
+    #[test]
+    fn exclusive_falls_back_through_monitors() {
+        // Off every monitor, exclusive goes to the primary one.
+        let mut window = MockWindow::new(vec![vec![1280], vec![3840]]);
+        window.current = None;
+        window.primary = Some(1);
+        let mut controller = WindowController::new("test");
+        assert_eq!(
+            controller.set_mode(&window, DisplayMode::Exclusive),
+            DisplayMode::Exclusive
+        );
+        assert_eq!(window.shown(), Shown::Exclusive(1, 3840));
+
+        // Without a primary one either, any monitor will do.
+        window.primary = None;
+        let mut controller = WindowController::new("test");
+        controller.set_mode(&window, DisplayMode::Exclusive);
+        assert_eq!(window.shown(), Shown::Exclusive(0, 1280));
+
+        // A monitor without exclusive modes, or none at all, gets a
+        // borderless window instead.
+        for monitors in [vec![vec![]], vec![]] {
+            let window = MockWindow::new(monitors);
+            let mut controller = WindowController::new("test");
+            assert_eq!(
+                controller.set_mode(&window, DisplayMode::Exclusive),
+                DisplayMode::Borderless
+            );
+            assert_eq!(controller.mode(), DisplayMode::Borderless);
+            assert_eq!(window.shown(), Shown::Borderless(window.current));
+            controller.set_mode(&window, DisplayMode::Windowed);
+            assert_eq!(window.shown(), Shown::Windowed);
+            assert_eq!(window.inner_size(), PhysicalSize::new(800, 600));
+        }
+    }
+}
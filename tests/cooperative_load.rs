@@ -0,0 +1,87 @@
+//! A scripted load of a 30 MB OBJ, ticked the way a frame loop would tick
+//! it. Frame times are measured on the wall clock, so this is the binary's
+//! only test and nothing runs alongside it.
+
+use std::fmt::Write;
+
+use test2::{
+    cooperative::{self, CooperativeLoad},
+    resources::{LoadOptions, ResourceBytes},
+    stats,
+};
+
+/// What the rest of a frame takes, rendering and all.
+const RENDER_SECONDS: f64 = 0.008;
+
+/// 30 fps.
+const MAX_FRAME_SECONDS: f64 = 1.0 / 30.0;
+
+/// The odd frame can be preempted for a while on a busy machine, but a
+/// step stalling on work that isn't sliced takes far longer than this.
+const MAX_STALL_SECONDS: f64 = 1.0 / 15.0;
+
+/// A grid of quads without normals, so the loader generates them, as OBJ
+/// text of at least `bytes`.
+fn grid_obj(bytes: usize) -> Vec<u8> {
+    // About 60 bytes of positions and faces per grid point.
+    let side = ((bytes / 60) as f64).sqrt().ceil() as usize + 1;
+    let mut source = String::with_capacity(bytes + bytes / 8);
+    for y in 0..side {
+        for x in 0..side {
+            let height = ((x * 7 + y * 13) % 100) as f32 * 0.01;
+            writeln!(source, "v {:.6} {:.6} {:.6}", x as f32, height, y as f32).unwrap();
+        }
+    }
+    for y in 0..side - 1 {
+        for x in 0..side - 1 {
+            let i = y * side + x + 1;
+            let j = i + side;
+            writeln!(source, "f {} {} {} {}", i, i + 1, j + 1, j).unwrap();
+        }
+    }
+    assert!(source.len() >= bytes);
+    source.into_bytes()
+}
+
+#[test]
+fn a_30mb_load_keeps_frames_above_30_fps() {
+    let bytes = ResourceBytes::Owned(grid_obj(30 << 20));
+    let mut load = CooperativeLoad::new("grid.obj", bytes, LoadOptions::default());
+    let mut timeline = stats::FrameTimeline::new(1 << 16);
+    // Debug builds parse several times slower than release ones, so the
+    // slices are made that much smaller to take about as long.
+    if cfg!(debug_assertions) {
+        load.slice = cooperative::DEFAULT_SLICE / 8;
+    }
+    let mut frames = 0;
+    while !load.is_ready() {
+        let start = stats::now();
+        load.tick(start + cooperative::DEFAULT_BUDGET).unwrap();
+        let update = stats::now() - start;
+        timeline.push(stats::FrameTiming {
+            frame: RENDER_SECONDS + update,
+            update,
+            ..Default::default()
+        });
+        frames += 1;
+    }
+    assert!(frames < 1 << 16, "every frame should be kept");
+    // Spread over enough frames that no one of them did it all.
+    assert!(frames > 30, "loaded in {} frames", frames);
+    assert_eq!(load.progress(), 1.0);
+
+    let typical = timeline.percentile(99.0).unwrap();
+    assert!(
+        typical < MAX_FRAME_SECONDS,
+        "1% of {} frames took over {:.1} ms",
+        frames,
+        1000.0 * typical
+    );
+    let slowest = timeline.percentile(100.0).unwrap();
+    assert!(
+        slowest < MAX_STALL_SECONDS,
+        "the slowest of {} frames took {:.1} ms",
+        frames,
+        1000.0 * slowest
+    );
+}
@@ -0,0 +1,61 @@
+//! Ceilings on what recording a large scene costs, to catch changes that add
+//! draw calls, bind group changes or allocations per draw. It needs a GPU, so
+//! it is only built with the `perf-guard` feature and ignored by default:
+//!
+//! `cargo test --features perf-guard --test perf_guard -- --ignored`
+
+use test2::{
+    stats::CountingAllocator,
+    validation::{self, HeadlessContext},
+};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// More than wgpu's own buffers growing a few times, less than one
+/// allocation for every ten extra draw calls.
+const MAX_EXTRA_ALLOCATIONS: usize = 64;
+
+/// Far above what recording takes even in debug builds, so only something
+/// like a draw doing work per instance trips it.
+const MAX_RECORD_SECONDS: f64 = 2.0;
+
+#[test]
+#[ignore]
+fn recording_a_large_scene_stays_under_its_ceilings() {
+    let Some(context) = pollster::block_on(HeadlessContext::new(wgpu::Backends::PRIMARY)) else {
+        eprintln!("No adapter, so the perf guard can't run");
+        return;
+    };
+    let report = pollster::block_on(validation::perf_guard(&context)).unwrap();
+    let frame = report.frame;
+    let meshes = validation::PERF_GUARD_MESHES as u32;
+
+    // Every draw of a model is one batch, so one draw call per mesh.
+    assert!(frame.draw_calls <= meshes, "{:?}", frame);
+    // The material and the camera for each draw call.
+    assert!(frame.bind_group_sets <= 2 * meshes, "{:?}", frame);
+    assert!(frame.pipeline_sets <= 2, "{:?}", frame);
+    assert_eq!(
+        frame.instances as usize,
+        validation::PERF_GUARD_DRAWS * validation::PERF_GUARD_MESHES
+            / validation::PERF_GUARD_MODELS,
+        "{:?}",
+        frame
+    );
+
+    assert_eq!(report.batching_allocations, 0);
+    assert!(
+        frame.allocations <= report.small_frame.allocations + MAX_EXTRA_ALLOCATIONS,
+        "{} draw calls made {} allocations, but {} made {}",
+        frame.draw_calls,
+        frame.allocations,
+        report.small_frame.draw_calls,
+        report.small_frame.allocations
+    );
+    assert!(
+        report.record_seconds < MAX_RECORD_SECONDS,
+        "Recording took {} s",
+        report.record_seconds
+    );
+}
@@ -0,0 +1,77 @@
+//! Checks that memory mapping a resource parses the same as reading it,
+//! without the copy. Allocations are counted for the whole test binary, so
+//! this is its only test.
+
+use std::fmt::Write;
+
+use test2::{obj, resources::ResourceBytes, stats::CountingAllocator};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A `side` by `side` grid of quads with UVs and normals.
+fn grid_obj(side: u32) -> String {
+    let mut source = String::new();
+    for y in 0..=side {
+        for x in 0..=side {
+            writeln!(source, "v {} 0 {}\nvt {} {}", x, y, x, y).unwrap();
+        }
+    }
+    source.push_str("vn 0 1 0\n");
+    for y in 0..side {
+        for x in 0..side {
+            let i = y * (side + 1) + x + 1;
+            let j = i + side + 1;
+            writeln!(
+                source,
+                "f {a}/{a}/1 {b}/{b}/1 {c}/{c}/1 {d}/{d}/1",
+                a = i,
+                b = i + 1,
+                c = j + 1,
+                d = j
+            )
+            .unwrap();
+        }
+    }
+    source
+}
+
+/// Opens and parses `path`, returning the parsed vertex and index counts
+/// and the bytes allocated meanwhile.
+fn open_and_parse(path: &std::path::Path, mmap_threshold: u64) -> ((usize, usize), usize, bool) {
+    let before = CountingAllocator::allocated_bytes();
+    let bytes = ResourceBytes::open(path, mmap_threshold).unwrap();
+    let mapped = !matches!(bytes, ResourceBytes::Owned(_));
+    let data = obj::parse(&bytes).unwrap().expect("on the fast path");
+    let allocated = CountingAllocator::allocated_bytes() - before;
+    let mesh = &data.models[0].mesh;
+    (
+        (mesh.positions.len(), mesh.indices.len()),
+        allocated,
+        mapped,
+    )
+}
+
+#[test]
+fn mapping_parses_the_same_without_copying_the_file() {
+    let path = std::env::temp_dir().join("resource-bytes-grid.obj");
+    std::fs::write(&path, grid_obj(200)).unwrap();
+    let len = std::fs::metadata(&path).unwrap().len() as usize;
+
+    let (read, read_allocated, read_mapped) = open_and_parse(&path, u64::MAX);
+    let (mapped, mapped_allocated, mapped_mapped) = open_and_parse(&path, 0);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(!read_mapped && mapped_mapped);
+    assert_eq!(read, mapped);
+    assert_eq!(read.1, 200 * 200 * 6);
+    // Parsing allocates the same either way; only the copy of the file
+    // differs.
+    assert!(
+        read_allocated >= mapped_allocated + len,
+        "reading allocated {} bytes and mapping {}, for a {} byte file",
+        read_allocated,
+        mapped_allocated,
+        len
+    );
+}